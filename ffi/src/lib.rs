@@ -0,0 +1,312 @@
+//! UniFFI bindings to the node, for Swift/Kotlin apps & Python scripts.
+//!
+//! This is a thin layer over [`squiggle_node::sdk`]. JSON values cross the boundary as
+//! strings, ids & hashes as their string forms. Generate bindings from a release build:
+//!
+//! ```sh
+//! cargo build --release
+//! cargo run --bin uniffi-bindgen generate --library target/release/libsquiggle_ffi.so \
+//!     --language swift --out-dir out
+//! ```
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use squiggle_node::sdk;
+use uuid::Uuid;
+
+uniffi::setup_scaffolding!();
+
+#[derive(Debug, thiserror::Error, uniffi::Error)]
+#[uniffi(flat_error)]
+pub enum SquiggleError {
+    #[error("{0}")]
+    Node(String),
+    #[error("invalid argument: {0}")]
+    InvalidArgument(String),
+}
+
+impl From<anyhow::Error> for SquiggleError {
+    fn from(err: anyhow::Error) -> Self {
+        SquiggleError::Node(format!("{err:#}"))
+    }
+}
+
+type Result<T> = std::result::Result<T, SquiggleError>;
+
+fn parse_id(id: &str) -> Result<Uuid> {
+    Uuid::parse_str(id).map_err(|e| SquiggleError::InvalidArgument(format!("{id}: {e}")))
+}
+
+fn parse_json(json: &str) -> Result<serde_json::Value> {
+    serde_json::from_str(json).map_err(|e| SquiggleError::InvalidArgument(e.to_string()))
+}
+
+#[derive(Debug, Clone, uniffi::Record)]
+pub struct SpaceInfo {
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, uniffi::Record)]
+pub struct Table {
+    pub id: String,
+    pub title: String,
+    pub hash: String,
+    pub author: String,
+    pub created_at: i64,
+    pub schema_json: String,
+}
+
+impl From<sdk::Table> for Table {
+    fn from(table: sdk::Table) -> Self {
+        Table {
+            id: table.id.to_string(),
+            title: table.title,
+            hash: table.hash,
+            author: table.author,
+            created_at: table.created_at,
+            schema_json: table.schema.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, uniffi::Record)]
+pub struct Row {
+    pub id: String,
+    pub table: String,
+    pub author: String,
+    pub created_at: i64,
+    pub data_json: String,
+}
+
+impl From<sdk::Row> for Row {
+    fn from(row: sdk::Row) -> Self {
+        Row {
+            id: row.id.to_string(),
+            table: row.table,
+            author: row.author,
+            created_at: row.created_at,
+            data_json: row.data.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, uniffi::Record)]
+pub struct Program {
+    pub id: String,
+    pub name: String,
+    pub version: String,
+    pub description: Option<String>,
+    pub created_at: i64,
+}
+
+impl From<sdk::Program> for Program {
+    fn from(program: sdk::Program) -> Self {
+        Program {
+            id: program.id.to_string(),
+            name: program.name,
+            version: program.version,
+            description: program.description,
+            created_at: program.created_at,
+        }
+    }
+}
+
+#[derive(Debug, Clone, uniffi::Record)]
+pub struct RunResult {
+    pub id: String,
+    pub succeeded: bool,
+    pub output: String,
+    pub error: Option<String>,
+    pub wall_time_ms: Option<u64>,
+}
+
+impl From<sdk::RunResult> for RunResult {
+    fn from(result: sdk::RunResult) -> Self {
+        RunResult {
+            id: result.id.to_string(),
+            succeeded: result.succeeded,
+            output: result.output,
+            error: result.error,
+            wall_time_ms: result.wall_time_ms,
+        }
+    }
+}
+
+#[derive(Debug, Clone, uniffi::Enum)]
+pub enum RunEvent {
+    Started { program_id: String },
+    Finished { result: RunResult },
+    Failed { error: String },
+}
+
+/// Implemented by the host app to follow a program run.
+#[uniffi::export(with_foreign)]
+pub trait RunProgress: Send + Sync {
+    fn on_event(&self, event: RunEvent);
+}
+
+#[derive(uniffi::Object)]
+pub struct Node {
+    client: sdk::Client,
+}
+
+#[uniffi::export(async_runtime = "tokio")]
+impl Node {
+    /// Open the node stored at `path`.
+    #[uniffi::constructor]
+    pub async fn open(path: String) -> Result<Arc<Self>> {
+        let client = sdk::Client::open(path).await?;
+        Ok(Arc::new(Node { client }))
+    }
+
+    pub async fn spaces(&self) -> Result<Vec<SpaceInfo>> {
+        let spaces = self.client.spaces().await?;
+        Ok(spaces
+            .into_iter()
+            .map(|space| SpaceInfo {
+                id: space.id.to_string(),
+                name: space.name,
+            })
+            .collect())
+    }
+
+    pub async fn space(&self, name: String) -> Result<Arc<Space>> {
+        let inner = self.client.space(&name).await?;
+        Ok(Arc::new(Space { inner }))
+    }
+
+    pub async fn create_space(&self, name: String, description: String) -> Result<Arc<Space>> {
+        let inner = self.client.create_space(&name, &description).await?;
+        Ok(Arc::new(Space { inner }))
+    }
+}
+
+#[derive(uniffi::Object)]
+pub struct Space {
+    inner: sdk::Space,
+}
+
+#[uniffi::export(async_runtime = "tokio")]
+impl Space {
+    pub fn id(&self) -> String {
+        self.inner.id().to_string()
+    }
+
+    pub fn name(&self) -> String {
+        self.inner.name().to_string()
+    }
+
+    pub async fn tables(&self) -> Result<Vec<Table>> {
+        let tables = self.inner.tables().await?;
+        Ok(tables.into_iter().map(Table::from).collect())
+    }
+
+    pub async fn create_table(&self, schema_json: String) -> Result<Table> {
+        let schema = parse_json(&schema_json)?;
+        Ok(self.inner.create_table(&schema).await?.into())
+    }
+
+    /// The latest version of every row in the table titled `table`.
+    pub async fn rows(&self, table: String) -> Result<Vec<Row>> {
+        let table = self.inner.table(&table).await?;
+        let rows = self.inner.rows(&table).await?;
+        Ok(rows.into_iter().map(Row::from).collect())
+    }
+
+    pub async fn insert_row(&self, table: String, data_json: String) -> Result<Row> {
+        let table = self.inner.table(&table).await?;
+        let data = parse_json(&data_json)?;
+        Ok(self.inner.insert_row(&table, data).await?.into())
+    }
+
+    pub async fn update_row(&self, table: String, id: String, data_json: String) -> Result<Row> {
+        let table = self.inner.table(&table).await?;
+        let data = parse_json(&data_json)?;
+        let row = self.inner.update_row(&table, parse_id(&id)?, data).await?;
+        Ok(row.into())
+    }
+
+    pub async fn programs(&self) -> Result<Vec<Program>> {
+        let programs = self.inner.programs().await?;
+        Ok(programs.into_iter().map(Program::from).collect())
+    }
+
+    pub async fn import_program(&self, path: String) -> Result<Program> {
+        Ok(self.inner.import_program(path).await?.into())
+    }
+
+    /// Run a program to completion, reporting to `progress` along the way.
+    pub async fn run_program(
+        &self,
+        program_id: String,
+        environment: HashMap<String, String>,
+        progress: Option<Arc<dyn RunProgress>>,
+    ) -> Result<RunResult> {
+        let id = parse_id(&program_id)?;
+        let report = |event| {
+            if let Some(progress) = &progress {
+                progress.on_event(event);
+            }
+        };
+        report(RunEvent::Started { program_id });
+        match self.inner.run_program(id, environment).await {
+            Ok(result) => {
+                let result = RunResult::from(result);
+                report(RunEvent::Finished {
+                    result: result.clone(),
+                });
+                Ok(result)
+            }
+            Err(err) => {
+                let err = SquiggleError::from(err);
+                report(RunEvent::Failed {
+                    error: err.to_string(),
+                });
+                Err(err)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bad_arguments_are_invalid() {
+        assert!(matches!(
+            parse_id("not-a-uuid"),
+            Err(SquiggleError::InvalidArgument(msg)) if msg.starts_with("not-a-uuid")
+        ));
+        assert!(matches!(
+            parse_json("{"),
+            Err(SquiggleError::InvalidArgument(_))
+        ));
+        let id = Uuid::new_v4();
+        assert_eq!(parse_id(&id.to_string()).unwrap(), id);
+    }
+
+    #[test]
+    fn node_errors_keep_their_context() {
+        let err = anyhow::anyhow!("table not found").context("insert row");
+        let err = SquiggleError::from(err);
+        assert_eq!(err.to_string(), "insert row: table not found");
+    }
+
+    #[test]
+    fn rows_cross_as_json_strings() {
+        let row = Row::from(sdk::Row {
+            id: Uuid::nil(),
+            table: "hash".to_string(),
+            author: "author".to_string(),
+            created_at: 1,
+            data: serde_json::json!({ "n": 1 }),
+        });
+        assert_eq!(row.id, Uuid::nil().to_string());
+        assert_eq!(
+            parse_json(&row.data_json).unwrap(),
+            serde_json::json!({ "n": 1 })
+        );
+    }
+}
@@ -0,0 +1,377 @@
+//! Operational endpoints: Prometheus `/metrics` & `/admin` routes to inspect & reload config.
+//!
+//! They're served apart from the gateway, on the metrics port of the address in
+//! [`AdminConfig::bind`], loopback unless configured otherwise, so exposing a node's gateway
+//! doesn't expose its internals. Binding anywhere else needs a bearer [`AdminConfig::token`] or
+//! client certificates, see [`AdminTls`].
+use std::fs::File;
+use std::io::BufReader;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{anyhow, Context, Result};
+use axum::extract::{ConnectInfo, Request};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Extension, Json, Router};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::service::TowerToHyperService;
+use rustls::server::AllowAnyAuthenticatedClient;
+use rustls::{Certificate, PrivateKey, RootCertStore, ServerConfig};
+use serde::{Deserialize, Serialize};
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+use tokio_rustls::TlsAcceptor;
+use tracing::{debug, info, warn};
+
+use crate::vm::config::{ConfigReload, LiveConfig, RuntimeConfig};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AdminConfig {
+    /// address `/metrics` & `/admin` listen on, at `metrics_port`
+    pub bind: IpAddr,
+    /// bearer token every request must carry
+    pub token: Option<String>,
+    pub tls: Option<AdminTls>,
+}
+
+impl Default for AdminConfig {
+    fn default() -> Self {
+        Self {
+            bind: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            token: None,
+            tls: None,
+        }
+    }
+}
+
+/// Serve over TLS, requiring client certificates signed by `client_ca` when set.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AdminTls {
+    /// PEM certificate chain
+    pub cert: PathBuf,
+    /// PEM private key
+    pub key: PathBuf,
+    /// PEM certificates of the authorities client certificates must chain to
+    pub client_ca: Option<PathBuf>,
+}
+
+impl AdminConfig {
+    /// Refuse to expose the endpoints beyond this machine without authentication.
+    pub fn validate(&self) -> Result<()> {
+        let mtls = self.tls.as_ref().is_some_and(|tls| tls.client_ca.is_some());
+        anyhow::ensure!(
+            self.bind.is_loopback() || self.token.is_some() || mtls,
+            "admin endpoints bind to {}, which needs admin.token or admin.tls.client_ca",
+            self.bind
+        );
+        anyhow::ensure!(
+            self.token.as_ref().map_or(true, |token| !token.is_empty()),
+            "admin.token is empty"
+        );
+        Ok(())
+    }
+}
+
+/// Serve `/metrics` & `/admin` on `port` until the returned task is aborted.
+pub async fn serve(config: &AdminConfig, port: u16, live: LiveConfig) -> Result<JoinHandle<()>> {
+    config.validate()?;
+    if let Err(err) = crate::vm::metrics::try_init_metrics_collection() {
+        debug!("metrics collection already initialized: {:?}", err);
+    }
+    let tls = config.tls.as_ref().map(tls_acceptor).transpose()?;
+    let addr = SocketAddr::new(config.bind, port);
+    let listener = TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("binding admin endpoints to {addr}"))?;
+    info!(
+        "admin endpoints listening on {}{}",
+        listener.local_addr()?,
+        if tls.is_some() { ", tls" } else { "" }
+    );
+    let app = router(config.token.clone(), live);
+    Ok(tokio::spawn(async move {
+        let res = match tls {
+            Some(tls) => serve_tls(listener, tls, app).await,
+            None => axum::serve(listener, app).await.map_err(Into::into),
+        };
+        if let Err(err) = res {
+            tracing::error!("admin endpoints stopped: {:?}", err);
+        }
+    }))
+}
+
+/// Serve `/metrics` & `/admin` on the runtime `metrics_port`, moving them when the port changes
+/// & stopping them while it's unset, until the returned task is aborted.
+pub async fn follow(config: AdminConfig, live: LiveConfig) -> Result<JoinHandle<()>> {
+    let mut changes = live.subscribe();
+    let mut port = changes.borrow_and_update().metrics_port;
+    let mut server = match port {
+        Some(port) => Some(Server(serve(&config, port, live.clone()).await?)),
+        None => None,
+    };
+    Ok(tokio::spawn(async move {
+        while changes.changed().await.is_ok() {
+            let next = changes.borrow_and_update().metrics_port;
+            if next == port {
+                continue;
+            }
+            port = next;
+            // stop listening before binding again
+            drop(server.take());
+            let Some(port) = port else {
+                info!("admin endpoints stopped, metrics port unset");
+                continue;
+            };
+            match serve(&config, port, live.clone()).await {
+                Ok(handle) => server = Some(Server(handle)),
+                Err(err) => warn!("moving admin endpoints to port {}: {:?}", port, err),
+            }
+        }
+    }))
+}
+
+/// A running [`serve`], stopped when dropped.
+struct Server(JoinHandle<()>);
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Required bearer token, if any.
+#[derive(Debug, Clone)]
+struct AdminToken(Option<Arc<str>>);
+
+fn router(token: Option<String>, live: LiveConfig) -> Router {
+    Router::new()
+        .route("/metrics", get(handle_metrics))
+        .route("/admin/config", get(handle_config))
+        .route("/admin/config/reload", post(handle_config_reload))
+        .layer(middleware::from_fn(require_token))
+        .layer(Extension(AdminToken(token.map(Arc::from))))
+        .layer(Extension(live))
+}
+
+async fn require_token(
+    Extension(AdminToken(token)): Extension<AdminToken>,
+    headers: HeaderMap,
+    req: Request,
+    next: Next,
+) -> Response {
+    let Some(token) = token else {
+        return next.run(req).await;
+    };
+    let given = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match given {
+        Some(given) if tokens_match(given.as_bytes(), token.as_bytes()) => next.run(req).await,
+        _ => (
+            StatusCode::UNAUTHORIZED,
+            [(header::WWW_AUTHENTICATE, "Bearer")],
+            "unauthorized",
+        )
+            .into_response(),
+    }
+}
+
+/// Compare in time that only depends on the length, not where the tokens differ.
+pub(crate) fn tokens_match(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+async fn handle_metrics() -> Response {
+    let Some(core) = iroh_metrics::core::Core::get() else {
+        return (StatusCode::SERVICE_UNAVAILABLE, "metrics aren't collected").into_response();
+    };
+    let mut body = String::new();
+    match prometheus_client::encoding::text::encode(&mut body, core.registry()) {
+        Ok(()) => ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], body).into_response(),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+async fn handle_config(Extension(live): Extension<LiveConfig>) -> Json<RuntimeConfig> {
+    Json(live.runtime())
+}
+
+async fn handle_config_reload(
+    Extension(live): Extension<LiveConfig>,
+) -> Result<Json<ConfigReload>, (StatusCode, String)> {
+    live.reload()
+        .await
+        .map(Json)
+        .map_err(|err| (StatusCode::BAD_REQUEST, format!("{err:#}")))
+}
+
+pub(crate) fn tls_acceptor(tls: &AdminTls) -> Result<TlsAcceptor> {
+    let certs = read_certs(&tls.cert)?;
+    let key = read_key(&tls.key)?;
+    let builder = ServerConfig::builder().with_safe_defaults();
+    let builder = match &tls.client_ca {
+        Some(path) => {
+            let mut roots = RootCertStore::empty();
+            for cert in read_certs(path)? {
+                roots
+                    .add(&cert)
+                    .with_context(|| format!("reading client ca {}", path.display()))?;
+            }
+            builder.with_client_cert_verifier(AllowAnyAuthenticatedClient::new(roots).boxed())
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let config = builder
+        .with_single_cert(certs, key)
+        .context("admin tls certificate")?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+fn read_certs(path: &Path) -> Result<Vec<Certificate>> {
+    let mut reader =
+        BufReader::new(File::open(path).with_context(|| format!("opening {}", path.display()))?);
+    let certs = rustls_pemfile::certs(&mut reader)
+        .with_context(|| format!("reading certificates from {}", path.display()))?;
+    anyhow::ensure!(!certs.is_empty(), "no certificates in {}", path.display());
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+fn read_key(path: &Path) -> Result<PrivateKey> {
+    let mut reader =
+        BufReader::new(File::open(path).with_context(|| format!("opening {}", path.display()))?);
+    for item in rustls_pemfile::read_all(&mut reader)? {
+        match item {
+            rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::ECKey(key) => return Ok(PrivateKey(key)),
+            _ => {}
+        }
+    }
+    Err(anyhow!("no private key in {}", path.display()))
+}
+
+/// Serve `app` over TLS, with the [`ConnectInfo`] of each client.
+pub(crate) async fn serve_tls(listener: TcpListener, tls: TlsAcceptor, app: Router) -> Result<()> {
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let tls = tls.clone();
+        let app = app.clone().layer(Extension(ConnectInfo(peer)));
+        tokio::spawn(async move {
+            // failed handshakes include clients without an accepted certificate
+            let stream = match tls.accept(stream).await {
+                Ok(stream) => stream,
+                Err(err) => {
+                    debug!("tls handshake with {} failed: {:?}", peer, err);
+                    return;
+                }
+            };
+            let service = TowerToHyperService::new(app);
+            if let Err(err) = hyper_util::server::conn::auto::Builder::new(TokioExecutor::new())
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+            {
+                debug!("tls connection from {} ended: {:?}", peer, err);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn token_guards_every_route() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let live = LiveConfig::open(dir.path()).await?;
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let url = format!("http://{}", listener.local_addr()?);
+        let app = router(Some("secret".to_string()), live);
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let client = reqwest::Client::new();
+        for path in ["/metrics", "/admin/config"] {
+            let res = client.get(format!("{url}{path}")).send().await?;
+            assert_eq!(res.status(), StatusCode::UNAUTHORIZED, "{path}");
+            let res = client
+                .get(format!("{url}{path}"))
+                .bearer_auth("wrong!")
+                .send()
+                .await?;
+            assert_eq!(res.status(), StatusCode::UNAUTHORIZED, "{path}");
+        }
+        let res = client
+            .get(format!("{url}/admin/config"))
+            .bearer_auth("secret")
+            .send()
+            .await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        let runtime: RuntimeConfig = res.json().await?;
+        assert!(runtime.worker_enabled);
+        Ok(())
+    }
+
+    async fn free_port() -> Result<u16> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        Ok(listener.local_addr()?.port())
+    }
+
+    /// Wait for `/metrics` on `port` to answer, or to stop answering.
+    async fn wait_for_metrics(port: u16, up: bool) -> Result<()> {
+        let url = format!("http://127.0.0.1:{port}/metrics");
+        for _ in 0..50 {
+            if reqwest::get(&url).await.is_ok() == up {
+                return Ok(());
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        Err(anyhow!(
+            "metrics on port {port} never went {}",
+            if up { "up" } else { "down" }
+        ))
+    }
+
+    #[tokio::test]
+    async fn follows_metrics_port() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let live = LiveConfig::open(dir.path()).await?;
+        let (first, second) = (free_port().await?, free_port().await?);
+        live.set_metrics_port(Some(first));
+        let follow = follow(AdminConfig::default(), live.clone()).await?;
+        wait_for_metrics(first, true).await?;
+
+        live.set_metrics_port(Some(second));
+        wait_for_metrics(second, true).await?;
+        wait_for_metrics(first, false).await?;
+
+        live.set_metrics_port(None);
+        wait_for_metrics(second, false).await?;
+        follow.abort();
+        Ok(())
+    }
+
+    #[test]
+    fn public_bind_needs_auth() {
+        let mut config = AdminConfig::default();
+        assert!(config.validate().is_ok());
+        config.bind = IpAddr::V4(Ipv4Addr::UNSPECIFIED);
+        assert!(config.validate().is_err());
+        config.tls = Some(AdminTls {
+            cert: "cert.pem".into(),
+            key: "key.pem".into(),
+            client_ca: None,
+        });
+        assert!(config.validate().is_err(), "tls alone doesn't authenticate");
+        config.token = Some("secret".to_string());
+        assert!(config.validate().is_ok());
+        config.token = None;
+        config.tls.as_mut().unwrap().client_ca = Some("ca.pem".into());
+        assert!(config.validate().is_ok());
+    }
+}
@@ -0,0 +1,248 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::{ensure, Result};
+use iroh::net::key::PublicKey;
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+use tracing::warn;
+
+use crate::space::users::get_blankname;
+use crate::space::Spaces;
+
+const ALIASES_FILENAME: &str = "aliases.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+#[serde(rename_all = "camelCase")]
+pub enum AliasSource {
+    /// set by the user on this node, always wins
+    Manual,
+    /// taken from a user profile in one of our spaces
+    Profile,
+    /// generated from the key when nothing better is known
+    Generated,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+#[serde(rename_all = "camelCase")]
+pub struct Alias {
+    #[cfg_attr(feature = "specta", specta(type = String))]
+    pub pubkey: PublicKey,
+    pub name: String,
+    pub source: AliasSource,
+}
+
+/// A value paired with a display name for its author.
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+#[serde(rename_all = "camelCase")]
+pub struct Aliased<T> {
+    #[serde(flatten)]
+    pub value: T,
+    pub author_name: String,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct AliasesFile {
+    /// manual aliases by public key
+    manual: HashMap<String, String>,
+}
+
+/// Node-wide display names for public keys, shared by every space. Manual aliases are persisted,
+/// profile names are collected from the users of each space.
+#[derive(Debug, Clone)]
+pub struct Aliases {
+    path: PathBuf,
+    file: Arc<RwLock<AliasesFile>>,
+    profiles: Arc<RwLock<HashMap<PublicKey, String>>>,
+}
+
+impl Aliases {
+    pub async fn load(base_path: impl Into<PathBuf>) -> Result<Self> {
+        let path = base_path.into().join(ALIASES_FILENAME);
+        let file = if path.exists() {
+            let data = tokio::fs::read(&path).await?;
+            serde_json::from_slice(&data)?
+        } else {
+            AliasesFile::default()
+        };
+        Ok(Self {
+            path,
+            file: Arc::new(RwLock::new(file)),
+            profiles: Default::default(),
+        })
+    }
+
+    /// Re-read profile names from every space.
+    pub async fn refresh(&self, spaces: &Spaces) -> Result<()> {
+        let mut profiles = HashMap::new();
+        for details in spaces.list(0, -1).await? {
+            let Some(space) = spaces.get(&details.id).await else {
+                continue;
+            };
+            let users = match space.users().list(0, -1).await {
+                Ok(users) => users,
+                Err(err) => {
+                    warn!("reading users for aliases in {}: {:?}", space.name, err);
+                    continue;
+                }
+            };
+            for user in users {
+                if let Some(name) = user.profile.as_ref().map(|p| p.name().trim()) {
+                    if !name.is_empty() {
+                        profiles.insert(user.pubkey, name.to_string());
+                    }
+                }
+            }
+        }
+        *self.profiles.write().await = profiles;
+        Ok(())
+    }
+
+    pub async fn set(&self, pubkey: PublicKey, name: &str) -> Result<Alias> {
+        let name = name.trim();
+        ensure!(!name.is_empty(), "alias can't be empty");
+        let mut file = self.file.write().await;
+        file.manual.insert(pubkey.to_string(), name.to_string());
+        self.write_to_file(&file).await?;
+        Ok(Alias {
+            pubkey,
+            name: name.to_string(),
+            source: AliasSource::Manual,
+        })
+    }
+
+    /// Remove a manual alias, falling back to profile or generated names.
+    pub async fn remove(&self, pubkey: &PublicKey) -> Result<()> {
+        let mut file = self.file.write().await;
+        if file.manual.remove(&pubkey.to_string()).is_some() {
+            self.write_to_file(&file).await?;
+        }
+        Ok(())
+    }
+
+    /// Every manual & profile alias. Generated names aren't listed.
+    pub async fn list(&self) -> Vec<Alias> {
+        let file = self.file.read().await;
+        let profiles = self.profiles.read().await;
+        let mut aliases = file
+            .manual
+            .iter()
+            .filter_map(|(pubkey, name)| {
+                Some(Alias {
+                    pubkey: pubkey.parse().ok()?,
+                    name: name.clone(),
+                    source: AliasSource::Manual,
+                })
+            })
+            .collect::<Vec<_>>();
+        for (pubkey, name) in profiles.iter() {
+            if !file.manual.contains_key(&pubkey.to_string()) {
+                aliases.push(Alias {
+                    pubkey: *pubkey,
+                    name: name.clone(),
+                    source: AliasSource::Profile,
+                });
+            }
+        }
+        aliases.sort_by(|a, b| a.name.cmp(&b.name));
+        aliases
+    }
+
+    /// The best display name for a key. Never fails, unknown keys get a generated name.
+    pub async fn resolve(&self, pubkey: &PublicKey) -> Alias {
+        if let Some(name) = self.file.read().await.manual.get(&pubkey.to_string()) {
+            return Alias {
+                pubkey: *pubkey,
+                name: name.clone(),
+                source: AliasSource::Manual,
+            };
+        }
+        if let Some(name) = self.profiles.read().await.get(pubkey) {
+            return Alias {
+                pubkey: *pubkey,
+                name: name.clone(),
+                source: AliasSource::Profile,
+            };
+        }
+        Alias {
+            pubkey: *pubkey,
+            name: get_blankname(*pubkey),
+            source: AliasSource::Generated,
+        }
+    }
+
+    /// Display names for many keys at once, keyed by the key's string form.
+    pub async fn resolve_all(
+        &self,
+        pubkeys: impl IntoIterator<Item = PublicKey>,
+    ) -> HashMap<String, String> {
+        let mut names = HashMap::new();
+        for pubkey in pubkeys {
+            if !names.contains_key(&pubkey.to_string()) {
+                names.insert(pubkey.to_string(), self.resolve(&pubkey).await.name);
+            }
+        }
+        names
+    }
+
+    /// Pair values with their author's display name, for events, rows & anything else that
+    /// carries an author key.
+    pub async fn hydrate<T>(
+        &self,
+        values: Vec<T>,
+        author: impl Fn(&T) -> PublicKey,
+    ) -> Vec<Aliased<T>> {
+        let mut hydrated = Vec::with_capacity(values.len());
+        for value in values {
+            let author_name = self.resolve(&author(&value)).await.name;
+            hydrated.push(Aliased { value, author_name });
+        }
+        hydrated
+    }
+
+    async fn write_to_file(&self, file: &AliasesFile) -> Result<()> {
+        let file = serde_json::to_vec(file)?;
+        tokio::fs::write(&self.path, file).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use iroh::net::key::SecretKey;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn manual_aliases_persist() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let key = SecretKey::generate().public();
+
+        let aliases = Aliases::load(dir.path()).await?;
+        assert_eq!(aliases.resolve(&key).await.source, AliasSource::Generated);
+        aliases
+            .profiles
+            .write()
+            .await
+            .insert(key, "from profile".into());
+        assert_eq!(aliases.resolve(&key).await.name, "from profile");
+
+        aliases.set(key, " bob ").await?;
+        let aliases = Aliases::load(dir.path()).await?;
+        let alias = aliases.resolve(&key).await;
+        assert_eq!(
+            (alias.name.as_str(), alias.source),
+            ("bob", AliasSource::Manual)
+        );
+
+        aliases.remove(&key).await?;
+        assert_eq!(aliases.resolve(&key).await.source, AliasSource::Generated);
+        assert!(aliases.set(key, "  ").await.is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,55 @@
+//! Headless worker, runs jobs for one workspace without any spaces. See
+//! [`squiggle_node::vm::headless`].
+
+use std::path::PathBuf;
+
+use anyhow::Result;
+use clap::Parser;
+
+use squiggle_node::join_codes::RendezvousClient;
+use squiggle_node::worker_node::WorkerNode;
+use squiggle_node::DocTicket;
+
+#[derive(Parser)]
+struct Cli {
+    /// write ticket of the workspace to run jobs for
+    #[arg(required_unless_present = "code")]
+    ticket: Option<DocTicket>,
+    /// join code from `squiggle share-code`, instead of a ticket
+    #[arg(long, conflicts_with = "ticket", requires = "rendezvous")]
+    code: Option<String>,
+    /// url of the rendezvous service the code was left with
+    #[arg(long)]
+    rendezvous: Option<String>,
+    /// where to keep the node key, blobs & job files. Defaults to the node's data dir
+    #[arg(long)]
+    data_dir: Option<PathBuf>,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    tracing_subscriber::fmt::init();
+    let cli = Cli::parse();
+    let path = match cli.data_dir {
+        Some(path) => path,
+        None => squiggle_node::node::data_root()?,
+    };
+    let ticket = match (cli.ticket, cli.code, cli.rendezvous) {
+        (Some(ticket), _, _) => ticket,
+        (None, Some(code), Some(rendezvous)) => {
+            RendezvousClient::new(&rendezvous)?
+                .resolve_ticket(&code)
+                .await?
+        }
+        _ => unreachable!("clap requires a ticket or a code & rendezvous"),
+    };
+    let node = WorkerNode::open(path, ticket).await?;
+    println!(
+        "worker {} running {:?}",
+        node.router().node_id(),
+        node.worker().worker().capabilities().job_types
+    );
+
+    tokio::signal::ctrl_c().await?;
+    Ok(())
+}
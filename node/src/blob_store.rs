@@ -0,0 +1,164 @@
+//! Where blob data is kept for good.
+//!
+//! iroh's local store always holds the blobs a node is working with. A [`BlobStore`] is the
+//! durable tier behind it: with the default local backend that's iroh itself, with S3 every
+//! object written to the workspace is also uploaded to a bucket, and fetches that no peer can
+//! serve fall back to the bucket. That lets a headless node keep bulk data in object storage
+//! while local disk only has to hold what's in use.
+use anyhow::{ensure, Result};
+use bytes::Bytes;
+use iroh::blobs::Hash;
+use iroh::client::blobs::BlobStatus;
+use serde::{Deserialize, Serialize};
+
+use crate::router::RouterClient;
+
+pub use self::s3::{S3Config, S3Store};
+
+mod s3;
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum BlobStoreConfig {
+    /// keep blobs in the local iroh store only
+    #[default]
+    Local,
+    S3(S3Config),
+}
+
+/// Persistence for content-addressed blobs.
+pub(crate) trait BlobBackend {
+    /// Store `data` under `hash`. Storing a blob that's already there is a no-op.
+    async fn put(&self, hash: Hash, data: Bytes) -> Result<()>;
+    async fn get(&self, hash: Hash) -> Result<Option<Bytes>>;
+    async fn delete(&self, hash: Hash) -> Result<()>;
+    /// Is this backend separate from the local iroh store?
+    fn is_remote(&self) -> bool;
+}
+
+/// The local iroh store.
+#[derive(Debug, Clone)]
+pub struct LocalStore(RouterClient);
+
+impl LocalStore {
+    pub fn new(router: RouterClient) -> Self {
+        Self(router)
+    }
+
+    async fn has(&self, hash: Hash) -> Result<bool> {
+        let status = self.0.blobs().status(hash).await?;
+        Ok(matches!(status, BlobStatus::Complete { .. }))
+    }
+}
+
+impl BlobBackend for LocalStore {
+    async fn put(&self, hash: Hash, data: Bytes) -> Result<()> {
+        if self.has(hash).await? {
+            return Ok(());
+        }
+        let outcome = self.0.blobs().add_bytes(data).await?;
+        ensure!(outcome.hash == hash, "blob content doesn't match {hash}");
+        Ok(())
+    }
+
+    async fn get(&self, hash: Hash) -> Result<Option<Bytes>> {
+        if !self.has(hash).await? {
+            return Ok(None);
+        }
+        Ok(Some(self.0.blobs().read_to_bytes(hash).await?))
+    }
+
+    async fn delete(&self, hash: Hash) -> Result<()> {
+        self.0.blobs().delete_blob(hash).await
+    }
+
+    fn is_remote(&self) -> bool {
+        false
+    }
+}
+
+/// The configured blob backend.
+#[derive(Debug, Clone)]
+pub enum BlobStore {
+    Local(LocalStore),
+    S3(S3Store),
+}
+
+impl BlobStore {
+    pub fn open(config: &BlobStoreConfig, router: RouterClient) -> Result<Self> {
+        match config {
+            BlobStoreConfig::Local => Ok(BlobStore::Local(LocalStore::new(router))),
+            BlobStoreConfig::S3(config) => Ok(BlobStore::S3(S3Store::new(config.clone())?)),
+        }
+    }
+}
+
+impl BlobBackend for BlobStore {
+    async fn put(&self, hash: Hash, data: Bytes) -> Result<()> {
+        match self {
+            BlobStore::Local(store) => store.put(hash, data).await,
+            BlobStore::S3(store) => store.put(hash, data).await,
+        }
+    }
+
+    async fn get(&self, hash: Hash) -> Result<Option<Bytes>> {
+        match self {
+            BlobStore::Local(store) => store.get(hash).await,
+            BlobStore::S3(store) => store.get(hash).await,
+        }
+    }
+
+    async fn delete(&self, hash: Hash) -> Result<()> {
+        match self {
+            BlobStore::Local(store) => store.delete(hash).await,
+            BlobStore::S3(store) => store.delete(hash).await,
+        }
+    }
+
+    fn is_remote(&self) -> bool {
+        match self {
+            BlobStore::Local(store) => store.is_remote(),
+            BlobStore::S3(store) => store.is_remote(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn local_store_round_trip() -> Result<()> {
+        let node = iroh::node::Node::memory().spawn().await?;
+        let store = BlobStore::open(&BlobStoreConfig::Local, node.client().clone())?;
+        let data = Bytes::from_static(b"bulk data");
+        let hash = Hash::new(&data);
+
+        assert!(store.get(hash).await?.is_none());
+        store.put(hash, data.clone()).await?;
+        store.put(hash, data.clone()).await?;
+        assert_eq!(store.get(hash).await?, Some(data));
+        assert!(store
+            .put(Hash::new(b"other"), Bytes::from_static(b"x"))
+            .await
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn config_from_toml() -> Result<()> {
+        let config: BlobStoreConfig = toml::from_str(
+            r#"
+            type = "s3"
+            endpoint = "https://s3.example.com"
+            bucket = "squiggle"
+            "#,
+        )?;
+        let BlobStoreConfig::S3(s3) = config else {
+            panic!("expected s3 config");
+        };
+        assert_eq!(s3.region, "us-east-1");
+        assert_eq!(s3.prefix, "blobs/");
+        Ok(())
+    }
+}
@@ -0,0 +1,268 @@
+use anyhow::{anyhow, bail, Context, Result};
+use bytes::Bytes;
+use iroh::blobs::Hash;
+use reqwest::{Method, StatusCode};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use url::Url;
+
+use super::BlobBackend;
+
+const ACCESS_KEY_ENV: &str = "AWS_ACCESS_KEY_ID";
+const SECRET_KEY_ENV: &str = "AWS_SECRET_ACCESS_KEY";
+
+/// An S3-compatible bucket. Credentials fall back to the usual AWS environment variables so
+/// they don't have to live in the config file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct S3Config {
+    /// eg: https://s3.us-east-1.amazonaws.com, or a minio/R2 endpoint
+    pub endpoint: Url,
+    pub bucket: String,
+    #[serde(default = "default_region")]
+    pub region: String,
+    /// prepended to the hash of every object
+    #[serde(default = "default_prefix")]
+    pub prefix: String,
+    pub access_key_id: Option<String>,
+    pub secret_access_key: Option<String>,
+}
+
+fn default_region() -> String {
+    "us-east-1".to_string()
+}
+
+fn default_prefix() -> String {
+    "blobs/".to_string()
+}
+
+#[derive(Debug, Clone)]
+struct Credentials {
+    access_key_id: String,
+    secret_access_key: String,
+}
+
+/// Blobs in an S3 bucket, addressed by path-style URLs & signed with AWS signature v4.
+#[derive(Debug, Clone)]
+pub struct S3Store {
+    config: S3Config,
+    credentials: Credentials,
+    client: reqwest::Client,
+}
+
+impl S3Store {
+    pub fn new(config: S3Config) -> Result<Self> {
+        let credential = |value: &Option<String>, env: &str| {
+            value
+                .clone()
+                .or_else(|| std::env::var(env).ok())
+                .with_context(|| format!("missing s3 credential, set it in config or {env}"))
+        };
+        let credentials = Credentials {
+            access_key_id: credential(&config.access_key_id, ACCESS_KEY_ENV)?,
+            secret_access_key: credential(&config.secret_access_key, SECRET_KEY_ENV)?,
+        };
+        Ok(Self {
+            config,
+            credentials,
+            client: reqwest::Client::new(),
+        })
+    }
+
+    fn object_url(&self, hash: Hash) -> Result<Url> {
+        let path = format!(
+            "{}/{}{}",
+            self.config.bucket,
+            self.config.prefix,
+            hash.to_hex()
+        );
+        Ok(self.config.endpoint.join(&path)?)
+    }
+
+    async fn send(&self, method: Method, hash: Hash, body: Bytes) -> Result<reqwest::Response> {
+        let url = self.object_url(hash)?;
+        let now = chrono::Utc::now();
+        let signed = sign(
+            &self.credentials,
+            &self.config.region,
+            &method,
+            &url,
+            &body,
+            &now.format("%Y%m%dT%H%M%SZ").to_string(),
+        )?;
+        let res = self
+            .client
+            .request(method, url)
+            .header("x-amz-date", signed.amz_date)
+            .header("x-amz-content-sha256", signed.payload_hash)
+            .header("authorization", signed.authorization)
+            .body(body)
+            .send()
+            .await?;
+        Ok(res)
+    }
+}
+
+impl BlobBackend for S3Store {
+    async fn put(&self, hash: Hash, data: Bytes) -> Result<()> {
+        anyhow::ensure!(
+            Hash::new(&data) == hash,
+            "blob content doesn't match {hash}"
+        );
+        if self.send(Method::HEAD, hash, Bytes::new()).await?.status() == StatusCode::OK {
+            return Ok(());
+        }
+        let res = self.send(Method::PUT, hash, data).await?;
+        if !res.status().is_success() {
+            bail!("s3 put {hash}: {}", res.status());
+        }
+        Ok(())
+    }
+
+    async fn get(&self, hash: Hash) -> Result<Option<Bytes>> {
+        let res = self.send(Method::GET, hash, Bytes::new()).await?;
+        match res.status() {
+            StatusCode::NOT_FOUND => Ok(None),
+            status if status.is_success() => {
+                let data = res.bytes().await?;
+                // never trust the bucket
+                anyhow::ensure!(Hash::new(&data) == hash, "s3 returned corrupt blob {hash}");
+                Ok(Some(data))
+            }
+            status => Err(anyhow!("s3 get {hash}: {status}")),
+        }
+    }
+
+    async fn delete(&self, hash: Hash) -> Result<()> {
+        let res = self.send(Method::DELETE, hash, Bytes::new()).await?;
+        if !res.status().is_success() && res.status() != StatusCode::NOT_FOUND {
+            bail!("s3 delete {hash}: {}", res.status());
+        }
+        Ok(())
+    }
+
+    fn is_remote(&self) -> bool {
+        true
+    }
+}
+
+#[derive(Debug)]
+struct Signed {
+    amz_date: String,
+    payload_hash: String,
+    authorization: String,
+}
+
+/// AWS signature v4 for a request without query parameters.
+fn sign(
+    credentials: &Credentials,
+    region: &str,
+    method: &Method,
+    url: &Url,
+    body: &[u8],
+    amz_date: &str,
+) -> Result<Signed> {
+    let host = match url.port() {
+        Some(port) => format!("{}:{port}", url.host_str().context("s3 url has no host")?),
+        None => url.host_str().context("s3 url has no host")?.to_string(),
+    };
+    let date = &amz_date[..8];
+    let payload_hash = hex::encode(Sha256::digest(body));
+    let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+    let canonical_request = format!(
+        "{method}\n{}\n\nhost:{host}\nx-amz-content-sha256:{payload_hash}\nx-amz-date:{amz_date}\n\n{signed_headers}\n{payload_hash}",
+        url.path()
+    );
+    let scope = format!("{date}/{region}/s3/aws4_request");
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}",
+        hex::encode(Sha256::digest(canonical_request.as_bytes()))
+    );
+    let key = signing_key(&credentials.secret_access_key, date, region, "s3");
+    let signature = hex::encode(hmac_sha256(&key, string_to_sign.as_bytes()));
+    Ok(Signed {
+        amz_date: amz_date.to_string(),
+        payload_hash,
+        authorization: format!(
+            "AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={signed_headers}, Signature={signature}",
+            credentials.access_key_id
+        ),
+    })
+}
+
+fn signing_key(secret: &str, date: &str, region: &str, service: &str) -> [u8; 32] {
+    let key = hmac_sha256(format!("AWS4{secret}").as_bytes(), date.as_bytes());
+    let key = hmac_sha256(&key, region.as_bytes());
+    let key = hmac_sha256(&key, service.as_bytes());
+    hmac_sha256(&key, b"aws4_request")
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> [u8; 32] {
+    const BLOCK: usize = 64;
+    let mut block = [0u8; BLOCK];
+    if key.len() > BLOCK {
+        block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+    let mut inner = Sha256::new();
+    inner.update(block.map(|b| b ^ 0x36));
+    inner.update(data);
+    let mut outer = Sha256::new();
+    outer.update(block.map(|b| b ^ 0x5c));
+    outer.update(inner.finalize());
+    outer.finalize().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hmac_rfc4231() {
+        let mac = hmac_sha256(b"Jefe", b"what do ya want for nothing?");
+        assert_eq!(
+            hex::encode(mac),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn aws_signing_key() {
+        // from the AWS signature v4 documentation
+        let key = signing_key(
+            "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+            "20150830",
+            "us-east-1",
+            "iam",
+        );
+        assert_eq!(
+            hex::encode(key),
+            "c4afb1cc5771d871763a393e44b703571b55cc28424d1a5e86da6ed3c154a4b9"
+        );
+    }
+
+    #[test]
+    fn signs_path_style_urls() -> Result<()> {
+        let credentials = Credentials {
+            access_key_id: "AKID".to_string(),
+            secret_access_key: "secret".to_string(),
+        };
+        let url = Url::parse("http://localhost:9000/bucket/blobs/abc")?;
+        let signed = sign(
+            &credentials,
+            "us-east-1",
+            &Method::GET,
+            &url,
+            b"",
+            "20240101T000000Z",
+        )?;
+        assert_eq!(
+            signed.payload_hash,
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert!(signed
+            .authorization
+            .starts_with("AWS4-HMAC-SHA256 Credential=AKID/20240101/us-east-1/s3/aws4_request"));
+        Ok(())
+    }
+}
@@ -0,0 +1,250 @@
+//! First-run setup: an account, a space to put things in & a little sample data to poke at.
+//! See [`crate::node::Node::bootstrap`].
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use iroh::docs::{Author, AuthorId};
+use iroh::net::key::PublicKey;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::router::RouterClient;
+use crate::space::users::Profile;
+use crate::space::{Space, Spaces};
+
+const BOOTSTRAP_FILENAME: &str = "bootstrap.json";
+const DEFAULT_SPACE_NAME: &str = "personal";
+const DEFAULT_SPACE_DESCRIPTION: &str = "my first space";
+
+const SAMPLE_TABLE: &str = r#"{
+  "title": "bookmarks",
+  "type": "object",
+  "properties": {
+    "title": { "type": "string" },
+    "url": { "type": "string", "format": "uri" },
+    "tags": { "type": "array", "items": { "type": "string" } }
+  },
+  "required": ["title", "url"]
+}"#;
+
+const SAMPLE_PROGRAM_MANIFEST: &str = r#"{
+  "name": "welcome",
+  "version": "0.1.0",
+  "description": "Say hello & list the sample bookmarks",
+  "permissions": {
+    "tables": ["bookmarks"]
+  }
+}"#;
+
+const SAMPLE_PROGRAM_INDEX: &str = r#"<!DOCTYPE html>
+<html>
+  <head>
+    <title>Welcome</title>
+  </head>
+  <body>
+    <h1>Welcome to squiggle</h1>
+    <p>This space has a bookmarks table with a few rows to get you started.</p>
+  </body>
+</html>
+"#;
+
+/// Everything [`crate::node::Node::bootstrap`] created.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+#[serde(rename_all = "camelCase")]
+pub struct Bootstrap {
+    /// key of the new account
+    #[cfg_attr(feature = "specta", specta(type = String))]
+    pub account: PublicKey,
+    pub user_id: Uuid,
+    pub space_id: Uuid,
+    pub table_id: Uuid,
+    pub row_ids: Vec<Uuid>,
+    pub program_id: Uuid,
+}
+
+fn sample_rows() -> Vec<serde_json::Value> {
+    vec![
+        json!({ "title": "iroh", "url": "https://iroh.computer", "tags": ["networking"] }),
+        json!({ "title": "nostr", "url": "https://nostr.com", "tags": ["protocols"] }),
+        json!({ "title": "JSON Schema", "url": "https://json-schema.org", "tags": ["data"] }),
+    ]
+}
+
+/// Set up a repo on first launch. Once it has run, later calls return what the first one
+/// created. Setup either finishes or leaves nothing behind: a failure removes the account &
+/// the default space it created, so the next call starts over. If the default space already
+/// existed it's kept, and what was written to it stays.
+pub(crate) async fn bootstrap(
+    repo_path: &Path,
+    router: &RouterClient,
+    spaces: &Spaces,
+    node_author: Author,
+    profile: Profile,
+) -> Result<Bootstrap> {
+    let path = repo_path.join(BOOTSTRAP_FILENAME);
+    if path.exists() {
+        let data = tokio::fs::read(&path).await?;
+        return serde_json::from_slice(&data).context("reading bootstrap record");
+    }
+
+    let existing = spaces.get_by_name(DEFAULT_SPACE_NAME).await.is_some();
+    let space = spaces
+        .clone()
+        .get_or_create(
+            router,
+            node_author,
+            DEFAULT_SPACE_NAME,
+            DEFAULT_SPACE_DESCRIPTION,
+        )
+        .await?;
+
+    let mut account = None;
+    let res = populate(&space, profile, &mut account).await;
+    let res = match res {
+        Ok(bootstrap) => write_record(&path, &bootstrap).await.map(|_| bootstrap),
+        Err(err) => Err(err),
+    };
+    if res.is_err() {
+        if let Some(account) = account {
+            if let Err(err) = router.authors().delete(account).await {
+                warn!("failed to remove partial account {}: {:?}", account, err);
+            }
+        }
+        if !existing {
+            if let Err(err) = spaces.remove(&space.id).await {
+                warn!("failed to remove partial space {}: {:?}", space.name, err);
+            }
+        }
+    }
+    res
+}
+
+/// Fill `space` with the account & sample data, setting `account` as soon as it exists so a
+/// failure after that can remove it.
+async fn populate(
+    space: &Space,
+    profile: Profile,
+    account: &mut Option<AuthorId>,
+) -> Result<Bootstrap> {
+    let user = space.users().create(profile).await?;
+    let author = user.author.clone().context("new account has no author")?;
+    *account = Some(author.id());
+
+    let mut table = space
+        .tables()
+        .load_or_create(author.clone(), SAMPLE_TABLE.into())
+        .await
+        .context("creating sample table")?;
+    let mut row_ids = Vec::new();
+    for row in sample_rows() {
+        let row = table
+            .create_row(space, author.clone(), row)
+            .await
+            .context("adding sample rows")?;
+        row_ids.push(row.id);
+    }
+
+    let dir = tempfile::tempdir()?;
+    tokio::fs::write(dir.path().join("program.json"), SAMPLE_PROGRAM_MANIFEST).await?;
+    tokio::fs::write(dir.path().join("index.html"), SAMPLE_PROGRAM_INDEX).await?;
+    let program = space
+        .programs()
+        .create(author, dir.path())
+        .await
+        .context("installing sample program")?;
+
+    Ok(Bootstrap {
+        account: user.pubkey,
+        user_id: user.id,
+        space_id: space.id,
+        table_id: table.id,
+        row_ids,
+        program_id: program.id,
+    })
+}
+
+/// Write the record next to `path` & move it into place, so it's never half written.
+async fn write_record(path: &Path, bootstrap: &Bootstrap) -> Result<()> {
+    let tmp = path.with_extension("json.tmp");
+    tokio::fs::write(&tmp, serde_json::to_vec(bootstrap)?).await?;
+    tokio::fs::rename(&tmp, path)
+        .await
+        .context("writing bootstrap record")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn failed_setup_leaves_nothing() -> Result<()> {
+        let node = iroh::node::Node::memory().enable_docs().spawn().await?;
+        let dir = tempfile::tempdir()?;
+        let router = node.client().clone();
+        let spaces = Spaces::open_all(router.clone(), dir.path()).await?;
+        let author = Author::new(&mut rand::thread_rng());
+        let profile = || Profile::new("ada", "", "");
+        let authors = || async {
+            use futures::StreamExt;
+            anyhow::Ok(router.authors().list().await?.count().await)
+        };
+        let before = authors().await?;
+
+        // the record can't be moved into place, so setup fails at the very end
+        let tmp = dir
+            .path()
+            .join(BOOTSTRAP_FILENAME)
+            .with_extension("json.tmp");
+        std::fs::create_dir(&tmp)?;
+        let res = bootstrap(dir.path(), &router, &spaces, author.clone(), profile()).await;
+        assert!(res.is_err());
+        assert!(spaces.get_by_name(DEFAULT_SPACE_NAME).await.is_none());
+        assert_eq!(authors().await?, before);
+
+        std::fs::remove_dir(&tmp)?;
+        let first = bootstrap(dir.path(), &router, &spaces, author.clone(), profile()).await?;
+        let again = bootstrap(dir.path(), &router, &spaces, author, profile()).await?;
+        assert_eq!(first.space_id, again.space_id);
+        assert_eq!(first.row_ids, again.row_ids);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn populates_the_default_space() -> Result<()> {
+        let node = iroh::node::Node::memory().enable_docs().spawn().await?;
+        let dir = tempfile::tempdir()?;
+        let router = node.client().clone();
+        let spaces = Spaces::open_all(router.clone(), dir.path()).await?;
+        let author = Author::new(&mut rand::thread_rng());
+        // a default space from before keeps its data
+        let existing = spaces
+            .clone()
+            .get_or_create(&router, author.clone(), DEFAULT_SPACE_NAME, "mine")
+            .await?;
+
+        let created = bootstrap(
+            dir.path(),
+            &router,
+            &spaces,
+            author,
+            Profile::new("ada", "", ""),
+        )
+        .await?;
+        assert_eq!(created.space_id, existing.id);
+        let account = AuthorId::from(created.account.as_bytes());
+        assert!(router.authors().export(account).await?.is_some());
+
+        let table = existing.tables().get(created.table_id).await?;
+        let rows = existing
+            .rows()
+            .query(table.content.hash, String::new(), 0, -1)
+            .await?;
+        assert_eq!(rows.len(), sample_rows().len());
+        let program = existing.programs().get_by_id(created.program_id).await?;
+        assert_eq!(program.manifest.name, "welcome");
+        Ok(())
+    }
+}
@@ -0,0 +1,178 @@
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{ensure, Result};
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Free space below either threshold pauses blob fetching.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+#[serde(rename_all = "camelCase")]
+pub struct DiskThresholds {
+    pub min_free_bytes: u64,
+    /// fraction of the disk, 0.0 - 1.0
+    pub min_free_ratio: f64,
+}
+
+impl Default for DiskThresholds {
+    fn default() -> Self {
+        Self {
+            min_free_bytes: 1024 * 1024 * 1024,
+            min_free_ratio: 0.05,
+        }
+    }
+}
+
+impl DiskThresholds {
+    fn is_low(&self, available: u64, total: u64) -> bool {
+        available < self.min_free_bytes
+            || (total > 0 && (available as f64 / total as f64) < self.min_free_ratio)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+#[serde(rename_all = "camelCase")]
+pub struct DiskStatus {
+    /// true while blob autofetch & sync are paused
+    pub paused: bool,
+    pub low: bool,
+    pub available_bytes: u64,
+    pub total_bytes: u64,
+    pub thresholds: DiskThresholds,
+}
+
+/// Watches free space on the disk holding the node's data. When space runs low blob fetching
+/// is paused, and stays paused until explicitly resumed.
+#[derive(Debug, Clone)]
+pub struct DiskMonitor {
+    path: PathBuf,
+    thresholds: Arc<RwLock<DiskThresholds>>,
+    paused: Arc<AtomicBool>,
+}
+
+impl DiskMonitor {
+    pub fn new(path: impl Into<PathBuf>, thresholds: DiskThresholds) -> Self {
+        Self {
+            path: path.into(),
+            thresholds: Arc::new(RwLock::new(thresholds)),
+            paused: Default::default(),
+        }
+    }
+
+    /// Check free space periodically in the background.
+    pub fn spawn(&self) -> JoinHandle<()> {
+        let this = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(CHECK_INTERVAL);
+            loop {
+                interval.tick().await;
+                if let Err(err) = this.check().await {
+                    warn!("checking disk space: {:?}", err);
+                }
+            }
+        })
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    pub async fn status(&self) -> Result<DiskStatus> {
+        let (available_bytes, total_bytes) = self.space()?;
+        let thresholds = *self.thresholds.read().await;
+        Ok(DiskStatus {
+            paused: self.is_paused(),
+            low: thresholds.is_low(available_bytes, total_bytes),
+            available_bytes,
+            total_bytes,
+            thresholds,
+        })
+    }
+
+    pub async fn set_thresholds(&self, thresholds: DiskThresholds) -> Result<DiskStatus> {
+        *self.thresholds.write().await = thresholds;
+        self.check().await
+    }
+
+    /// Resume fetching after space has been freed. Fails if the disk is still low.
+    pub async fn resume(&self) -> Result<DiskStatus> {
+        let mut status = self.status().await?;
+        ensure!(
+            !status.low,
+            "disk space is still low: {} bytes available",
+            status.available_bytes
+        );
+        if self.paused.swap(false, Ordering::Relaxed) {
+            info!("resuming blob sync");
+        }
+        status.paused = false;
+        Ok(status)
+    }
+
+    async fn check(&self) -> Result<DiskStatus> {
+        let mut status = self.status().await?;
+        if status.low && !self.paused.swap(true, Ordering::Relaxed) {
+            warn!(
+                available = status.available_bytes,
+                total = status.total_bytes,
+                "disk space low, pausing blob sync"
+            );
+        }
+        status.paused = self.is_paused();
+        Ok(status)
+    }
+
+    fn space(&self) -> Result<(u64, u64)> {
+        let available = fs2::available_space(&self.path)?;
+        let total = fs2::total_space(&self.path)?;
+        Ok((available, total))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn either_threshold_counts_as_low() {
+        let thresholds = DiskThresholds {
+            min_free_bytes: 100,
+            min_free_ratio: 0.1,
+        };
+        assert!(thresholds.is_low(99, 10_000));
+        assert!(thresholds.is_low(500, 10_000));
+        assert!(!thresholds.is_low(1_000, 10_000));
+        // an unknown total only checks bytes
+        assert!(!thresholds.is_low(1_000, 0));
+    }
+
+    #[tokio::test]
+    async fn low_disk_pauses_until_resumed() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let full = DiskThresholds {
+            min_free_bytes: u64::MAX,
+            min_free_ratio: 0.0,
+        };
+        let monitor = DiskMonitor::new(dir.path(), full);
+        assert!(monitor.check().await?.paused);
+        assert!(monitor.resume().await.is_err(), "still low");
+
+        // freeing space doesn't resume on its own
+        let roomy = DiskThresholds {
+            min_free_bytes: 0,
+            min_free_ratio: 0.0,
+        };
+        assert!(monitor.set_thresholds(roomy).await?.paused);
+        assert!(!monitor.resume().await?.paused);
+        assert!(!monitor.is_paused());
+        Ok(())
+    }
+}
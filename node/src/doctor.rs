@@ -0,0 +1,368 @@
+//! Diagnostics for the environment a node runs in: the container & wasm runtimes, the network
+//! & the data on disk. Every check reports what it found and, when something's off, how to
+//! fix it.
+use std::net::{SocketAddr, TcpListener, UdpSocket};
+use std::path::Path;
+use std::time::Duration;
+
+use iroh::net::defaults::prod::{default_eu_relay_node, default_na_relay_node};
+use iroh::net::relay::{RelayNode, RelayUrl};
+use serde::{Deserialize, Serialize};
+
+use crate::router::{Relays, Router};
+use crate::space::Spaces;
+use crate::vm::config::NodeConfig;
+
+const RELAY_TIMEOUT: Duration = Duration::from_secs(5);
+/// relays answer plain HTTP requests on this path
+const RELAY_PING_PATH: &str = "ping";
+const PROBE_FILENAME: &str = ".doctor-probe";
+/// the smallest valid wasm module
+const EMPTY_WASM_MODULE: &[u8] = b"\0asm\x01\0\0\0";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CheckStatus {
+    Ok,
+    /// works, but not as well as it could
+    Warning,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Check {
+    pub name: String,
+    pub status: CheckStatus,
+    pub detail: String,
+    /// what to do about a warning or failure
+    pub fix: Option<String>,
+}
+
+impl Check {
+    fn ok(name: &str, detail: impl Into<String>) -> Self {
+        Self {
+            name: name.to_string(),
+            status: CheckStatus::Ok,
+            detail: detail.into(),
+            fix: None,
+        }
+    }
+
+    fn warning(name: &str, detail: impl Into<String>, fix: impl Into<String>) -> Self {
+        Self {
+            name: name.to_string(),
+            status: CheckStatus::Warning,
+            detail: detail.into(),
+            fix: Some(fix.into()),
+        }
+    }
+
+    fn failed(name: &str, detail: impl Into<String>, fix: impl Into<String>) -> Self {
+        Self {
+            name: name.to_string(),
+            status: CheckStatus::Failed,
+            detail: detail.into(),
+            fix: Some(fix.into()),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DoctorReport {
+    pub checks: Vec<Check>,
+}
+
+impl DoctorReport {
+    /// True when no check failed. Warnings don't count.
+    pub fn healthy(&self) -> bool {
+        self.checks
+            .iter()
+            .all(|check| check.status != CheckStatus::Failed)
+    }
+}
+
+/// Run every check against a node's repo.
+pub(crate) async fn diagnose(
+    path: &Path,
+    router: &Router,
+    relays: &Relays,
+    spaces: &Spaces,
+) -> DoctorReport {
+    let mut checks = vec![docker().await, wasm().await, data_dir(path).await];
+    checks.extend(ports(path, router).await);
+    checks.push(home_relay(router));
+    checks.extend(relay_reachability(relays.list().await).await);
+    checks.extend(sqlite(spaces).await);
+    DoctorReport { checks }
+}
+
+async fn docker() -> Check {
+    const NAME: &str = "docker";
+    let docker = match crate::vm::docker::get_docker().await {
+        Ok(docker) => docker,
+        Err(err) => {
+            return Check::warning(
+                NAME,
+                format!("{err:#}"),
+                "install & start Docker, or make sure this user can reach its socket (eg: add \
+                 it to the docker group). Without Docker only wasm jobs can run",
+            )
+        }
+    };
+    match docker.version().await {
+        Ok(version) => Check::ok(
+            NAME,
+            format!(
+                "Docker {} (API {})",
+                version.version.unwrap_or_default(),
+                version.api_version.unwrap_or_default()
+            ),
+        ),
+        Err(err) => Check::warning(NAME, err.to_string(), "restart the Docker daemon"),
+    }
+}
+
+async fn wasm() -> Check {
+    const NAME: &str = "wasm";
+    let loaded = tokio::task::spawn_blocking(|| {
+        let manifest = extism::Manifest::new([extism::Wasm::data(EMPTY_WASM_MODULE)]);
+        extism::Plugin::new(manifest, [], true).map(|_| ())
+    })
+    .await;
+    match loaded {
+        Ok(Ok(())) => Check::ok(NAME, "wasm runtime loads modules"),
+        Ok(Err(err)) => Check::failed(
+            NAME,
+            format!("loading a module: {err:#}"),
+            "the wasm runtime can't compile code on this machine, check that executable \
+             memory isn't blocked (eg: by SELinux or a hardened kernel)",
+        ),
+        Err(err) => Check::failed(
+            NAME,
+            format!("wasm runtime crashed: {err}"),
+            "report this as a bug, with the output of `doctor --json`",
+        ),
+    }
+}
+
+async fn data_dir(path: &Path) -> Check {
+    const NAME: &str = "data dir";
+    let probe = path.join(PROBE_FILENAME);
+    let written = async {
+        tokio::fs::write(&probe, b"ok").await?;
+        tokio::fs::read(&probe).await?;
+        tokio::fs::remove_file(&probe).await
+    }
+    .await;
+    match written {
+        Ok(()) => Check::ok(NAME, format!("{} is writable", path.display())),
+        Err(err) => Check::failed(
+            NAME,
+            format!("writing to {}: {err}", path.display()),
+            format!(
+                "make {} readable & writable by this user, or point SQUIGGLE_DATA_DIR elsewhere",
+                path.display()
+            ),
+        ),
+    }
+}
+
+/// Configured ports that something else already listens on.
+async fn ports(path: &Path, router: &Router) -> Vec<Check> {
+    const NAME: &str = "ports";
+    let config = match NodeConfig::load(path).await {
+        Ok(Some(config)) => config,
+        Ok(None) => return vec![Check::ok(NAME, "no config.toml, nothing to check")],
+        Err(err) => {
+            return vec![Check::failed(
+                "config",
+                format!("{err:#}"),
+                "fix or remove config.toml",
+            )]
+        }
+    };
+
+    let mut checks = Vec::new();
+    let tcp = [
+        ("api", Some(config.api_port)),
+        ("metrics", config.metrics_port),
+    ];
+    for (name, port) in tcp {
+        let Some(port) = port.filter(|port| *port != 0) else {
+            continue;
+        };
+        let check = match TcpListener::bind(SocketAddr::from(([0, 0, 0, 0], port))) {
+            Ok(_) => Check::ok(NAME, format!("{name} port {port} is free")),
+            Err(err) => Check::failed(
+                NAME,
+                format!("{name} port {port}: {err}"),
+                format!("stop whatever listens on {port}, or change {name}_port in config.toml"),
+            ),
+        };
+        checks.push(check);
+    }
+
+    // iroh is already running, the port should be ours
+    if config.iroh_port != 0 {
+        let (bound, _) = router.endpoint().bound_sockets();
+        let check = if bound.port() == config.iroh_port {
+            Check::ok(NAME, format!("iroh listens on {}", config.iroh_port))
+        } else if UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], config.iroh_port))).is_err() {
+            Check::failed(
+                NAME,
+                format!(
+                    "iroh port {} is taken, iroh listens on {} instead",
+                    config.iroh_port,
+                    bound.port()
+                ),
+                "stop whatever listens on the port, or change iroh_port in config.toml",
+            )
+        } else {
+            Check::warning(
+                NAME,
+                format!(
+                    "iroh listens on {}, not the configured {}",
+                    bound.port(),
+                    config.iroh_port
+                ),
+                "restart the node to pick up config.toml",
+            )
+        };
+        checks.push(check);
+    }
+    checks
+}
+
+fn home_relay(router: &Router) -> Check {
+    const NAME: &str = "relay";
+    match router.endpoint().home_relay() {
+        Some(url) => Check::ok(NAME, format!("connected to home relay {url}")),
+        None => Check::warning(
+            NAME,
+            "no home relay, peers behind NATs can't reach this node",
+            "check the network allows outbound HTTPS & UDP, or add a relay you can reach",
+        ),
+    }
+}
+
+/// Relays the node is configured with, or iroh's defaults when none are.
+async fn relay_reachability(mut relays: Vec<RelayNode>) -> Vec<Check> {
+    if relays.is_empty() {
+        relays = vec![default_na_relay_node(), default_eu_relay_node()];
+    }
+    let client = match reqwest::Client::builder().timeout(RELAY_TIMEOUT).build() {
+        Ok(client) => client,
+        Err(err) => {
+            return vec![Check::failed(
+                "relay",
+                format!("building HTTP client: {err}"),
+                "check the system's TLS configuration",
+            )]
+        }
+    };
+    let mut checks = Vec::new();
+    for relay in relays {
+        checks.push(ping_relay(&client, &relay.url).await);
+    }
+    checks
+}
+
+async fn ping_relay(client: &reqwest::Client, url: &RelayUrl) -> Check {
+    const NAME: &str = "relay";
+    let ping = match url.join(RELAY_PING_PATH) {
+        Ok(ping) => ping,
+        Err(err) => return Check::failed(NAME, format!("{url}: {err}"), "fix the relay URL"),
+    };
+    match client.get(ping).send().await {
+        Ok(response) if response.status().is_success() => {
+            Check::ok(NAME, format!("{url} is reachable"))
+        }
+        Ok(response) => Check::warning(
+            NAME,
+            format!("{url} answered {}", response.status()),
+            "check the relay is running an iroh relay server",
+        ),
+        Err(err) => Check::warning(
+            NAME,
+            format!("{url} is unreachable: {err}"),
+            "check the network allows outbound HTTPS, or remove the relay",
+        ),
+    }
+}
+
+async fn sqlite(spaces: &Spaces) -> Vec<Check> {
+    const NAME: &str = "sqlite";
+    let details = match spaces.list(0, -1).await {
+        Ok(details) => details,
+        Err(err) => {
+            return vec![Check::failed(
+                NAME,
+                format!("reading spaces.json: {err:#}"),
+                "restore spaces.json from a backup",
+            )]
+        }
+    };
+    let mut checks = Vec::new();
+    for unreadable in spaces.unreadable().await {
+        checks.push(Check::failed(
+            NAME,
+            format!(
+                "space {} wasn't opened: {}",
+                unreadable.name, unreadable.error
+            ),
+            format!(
+                "run `recover {}` to salvage its events into a new space",
+                unreadable.name
+            ),
+        ));
+    }
+    for details in details {
+        let Some(space) = spaces.get(&details.id).await else {
+            continue;
+        };
+        let conn = space.db().lock().await;
+        let problems = conn.prepare("PRAGMA integrity_check").and_then(|mut stmt| {
+            stmt.query_map([], |row| row.get::<_, String>(0))?
+                .collect::<rusqlite::Result<Vec<_>>>()
+        });
+        let check = match problems {
+            Ok(problems) if problems == ["ok"] => {
+                Check::ok(NAME, format!("space {} database is intact", space.name))
+            }
+            Ok(problems) => Check::failed(
+                NAME,
+                format!("space {} database: {}", space.name, problems.join("; ")),
+                format!(
+                    "restore {}.db from a backup, or fork the space to rebuild it",
+                    space.name
+                ),
+            ),
+            Err(err) => Check::failed(
+                NAME,
+                format!("space {} database: {err}", space.name),
+                format!("check {}.db isn't locked or truncated", space.name),
+            ),
+        };
+        checks.push(check);
+    }
+    checks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn checks_data_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let check = data_dir(dir.path()).await;
+        assert_eq!(check.status, CheckStatus::Ok);
+        assert!(!dir.path().join(PROBE_FILENAME).exists());
+
+        let check = data_dir(&dir.path().join("missing")).await;
+        assert_eq!(check.status, CheckStatus::Failed);
+        assert!(check.fix.is_some());
+    }
+}
@@ -0,0 +1,95 @@
+//! Errors the app & gateway tell apart.
+//!
+//! Most of the node returns [`anyhow::Error`]. Where a caller outside the node needs to know
+//! what went wrong, not just read about it, the node returns one of these inside the
+//! `anyhow::Error` & [`Error::from`] digs it back out. Anything else becomes
+//! [`Error::Internal`]. Errors serialize tagged with their kind, eg:
+//! `{"kind": "spaceNotFound", "id": "…"}`.
+use serde::Serialize;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error, Serialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum Error {
+    #[error("space {id} not found")]
+    SpaceNotFound { id: Uuid },
+    /// `program` is the id or name it was looked up by
+    #[error("program {program} not found")]
+    ProgramNotFound { program: String },
+    #[error("no author")]
+    AuthorMissing,
+    /// input that doesn't parse or doesn't make sense
+    #[error("{message}")]
+    Invalid { message: String },
+    #[error("{message}")]
+    Io { message: String },
+    /// couldn't get data from peers
+    #[error("sync failed: {message}")]
+    Sync { message: String },
+    #[error("{message}")]
+    Internal { message: String },
+}
+
+impl Error {
+    pub fn invalid(err: impl std::fmt::Display) -> Self {
+        Error::Invalid {
+            message: err.to_string(),
+        }
+    }
+
+    pub fn sync(err: impl std::fmt::Display) -> Self {
+        Error::Sync {
+            message: err.to_string(),
+        }
+    }
+}
+
+impl From<anyhow::Error> for Error {
+    fn from(err: anyhow::Error) -> Self {
+        for cause in err.chain() {
+            if let Some(err) = cause.downcast_ref::<Error>() {
+                return err.clone();
+            }
+        }
+        let message = format!("{err:#}");
+        if err.chain().any(|cause| cause.is::<std::io::Error>()) {
+            return Error::Io { message };
+        }
+        Error::Internal { message }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Error::Io {
+            message: err.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Context;
+
+    use super::*;
+
+    #[test]
+    fn finds_kind_in_context() {
+        let id = Uuid::new_v4();
+        let err = Err::<(), _>(Error::SpaceNotFound { id })
+            .context("opening space")
+            .unwrap_err();
+        assert_eq!(Error::from(err), Error::SpaceNotFound { id });
+
+        let io = std::io::Error::new(std::io::ErrorKind::NotFound, "gone");
+        let err = Err::<(), _>(io).context("reading").unwrap_err();
+        assert!(matches!(Error::from(err), Error::Io { .. }));
+
+        let err = Error::from(anyhow::anyhow!("oops"));
+        assert_eq!(
+            serde_json::to_value(&err).unwrap(),
+            serde_json::json!({"kind": "internal", "message": "oops"})
+        );
+    }
+}
@@ -1,2 +1,6 @@
+pub(crate) mod local;
 mod ranges;
+pub mod replica;
+mod sandbox;
 pub mod server;
+pub(crate) mod token;
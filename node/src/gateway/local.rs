@@ -0,0 +1,137 @@
+//! Serving blobs the gateway's own node already has, without going through an iroh connection.
+//!
+//! Complete blobs are read straight from the data files of the node's store when they're
+//! there: iroh's file store keeps larger blobs as plain `<hash>.data` files, which can be
+//! streamed from any offset without decoding verified chunks. Small blobs live inline in the
+//! store's database & blobs in any other layout are read through the node's client.
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use axum::body::Body;
+use bytes::Bytes;
+use iroh::blobs::format::collection::Collection;
+use iroh::blobs::Hash;
+use iroh::client::blobs::{BlobStatus, ReadAtLen};
+use iroh::util::path::IrohPaths;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+use tracing::debug;
+
+use crate::router::RouterClient;
+
+/// read size when streaming data files
+const READ_BUFFER_SIZE: usize = 256 * 1024;
+
+#[derive(Debug, Clone)]
+pub(crate) struct LocalBlobs {
+    client: RouterClient,
+    /// where the store keeps data files, none for stores that aren't on disk
+    data_dir: Option<PathBuf>,
+}
+
+impl LocalBlobs {
+    /// Blobs of the node at `repo`, none for an in-memory node.
+    pub(crate) fn new(client: RouterClient, repo: Option<&Path>) -> Self {
+        let data_dir = repo.map(|repo| IrohPaths::BaoStoreDir.with_root(repo).join("data"));
+        LocalBlobs { client, data_dir }
+    }
+
+    pub(crate) fn client(&self) -> &RouterClient {
+        &self.client
+    }
+
+    /// Size of `hash`, if the store has all of it.
+    pub(crate) async fn size(&self, hash: &Hash) -> Option<u64> {
+        match self.client.blobs().status(*hash).await {
+            Ok(BlobStatus::Complete { size }) => Some(size),
+            Ok(_) => None,
+            Err(err) => {
+                debug!("checking local status of {}: {:?}", hash, err);
+                None
+            }
+        }
+    }
+
+    /// The first `len` bytes of a blob, for sniffing its type.
+    pub(crate) async fn head(&self, hash: &Hash, len: usize) -> Result<Bytes> {
+        let data = self
+            .client
+            .blobs()
+            .read_at_to_bytes(*hash, 0, ReadAtLen::AtMost(len as u64))
+            .await?;
+        Ok(data)
+    }
+
+    /// A collection, if the store has it.
+    pub(crate) async fn collection(&self, hash: &Hash) -> Option<Collection> {
+        self.size(hash).await?;
+        match self.client.blobs().get_collection(*hash).await {
+            Ok(collection) => Some(collection),
+            Err(err) => {
+                debug!("{} isn't a local collection: {:?}", hash, err);
+                None
+            }
+        }
+    }
+
+    /// Stream `len` bytes of a complete blob of `size` bytes, from `offset`.
+    pub(crate) async fn body(&self, hash: &Hash, size: u64, offset: u64, len: u64) -> Result<Body> {
+        if let Some(mut file) = self.data_file(hash, size).await {
+            file.seek(SeekFrom::Start(offset)).await?;
+            let reader = file.take(len);
+            return Ok(Body::from_stream(ReaderStream::with_capacity(
+                reader,
+                READ_BUFFER_SIZE,
+            )));
+        }
+        let reader = self
+            .client
+            .blobs()
+            .read_at(*hash, offset, ReadAtLen::Exact(len))
+            .await?;
+        Ok(Body::from_stream(reader))
+    }
+
+    /// The data file of a blob, if it's kept in one & has the size the store reports.
+    async fn data_file(&self, hash: &Hash, size: u64) -> Option<tokio::fs::File> {
+        let path = self
+            .data_dir
+            .as_ref()?
+            .join(format!("{}.data", hash.to_hex()));
+        let file = tokio::fs::File::open(&path).await.ok()?;
+        let len = file.metadata().await.ok()?.len();
+        (len == size).then_some(file)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn stream_ranges_from_data_files() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let node = iroh::node::Node::persistent(dir.path())
+            .await?
+            .spawn()
+            .await?;
+        let local = LocalBlobs::new(node.client().clone(), Some(dir.path()));
+        // large enough to get a data file rather than being inlined
+        let data: Vec<u8> = (0..1024 * 1024).map(|i| (i % 251) as u8).collect();
+        let hash = node.blobs().add_bytes(data.clone()).await?.hash;
+
+        let size = local.size(&hash).await.expect("complete");
+        assert_eq!(size, data.len() as u64);
+        assert!(local.data_file(&hash, size).await.is_some());
+        let body = local.body(&hash, size, 1000, 5000).await?;
+        let read = axum::body::to_bytes(body, usize::MAX).await?;
+        assert_eq!(&read[..], &data[1000..6000]);
+
+        let small = node.blobs().add_bytes(&b"small"[..]).await?.hash;
+        let body = local.body(&small, 5, 1, 3).await?;
+        assert_eq!(&axum::body::to_bytes(body, usize::MAX).await?[..], b"mal");
+        assert_eq!(local.size(&Hash::new("missing")).await, None);
+        Ok(())
+    }
+}
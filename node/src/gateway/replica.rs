@@ -0,0 +1,479 @@
+//! Read replicas of shared spaces.
+//!
+//! A replica follows the feed doc of each space it's given a ticket for (see
+//! [`crate::space::feed`]), mirroring events into a local sqlite db & keeping the content of
+//! the most recent events in the local blob store. Reads are served from the mirror, so they
+//! keep working while the origin is offline. iroh docs pick up where they left off once the
+//! origin is reachable again, the replica just has to keep asking.
+use std::collections::HashMap;
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use futures::StreamExt;
+use iroh::blobs::Hash;
+use iroh::client::blobs::BlobStatus;
+use iroh::client::docs::{Doc, Entry, LiveEvent};
+use iroh::docs::store::Query;
+use iroh::docs::{ContentStatus, DocTicket, NamespaceId};
+use iroh::net::NodeAddr;
+use rusqlite::params;
+use serde::Serialize;
+use tracing::{debug, warn};
+
+use crate::router::RouterClient;
+use crate::space::db::{open_db, setup_db, DB};
+use crate::space::events::{Event, EventKind, EVENT_SQL_READ_FIELDS};
+use crate::space::feed::EVENTS_PREFIX;
+use crate::space::validation;
+
+/// How many rows waiting for their table a replica holds on to per space. The oldest are
+/// dropped past that, they're written when the feed is next replayed.
+const MAX_DEFERRED: usize = 1024;
+
+#[derive(Debug, Clone)]
+pub struct ReplicaConfig {
+    /// directory mirror dbs are kept in
+    pub root: PathBuf,
+    /// feed tickets of the spaces to mirror
+    pub spaces: Vec<DocTicket>,
+    /// how many of the most recent events per space get their content mirrored
+    pub hot_events: usize,
+    /// reads are flagged stale when the last sync with the origin is older than this
+    pub stale_after: Duration,
+    /// how often to retry syncing while the origin is unreachable
+    pub retry_interval: Duration,
+}
+
+impl ReplicaConfig {
+    pub fn new(root: impl Into<PathBuf>, spaces: Vec<DocTicket>) -> Self {
+        Self {
+            root: root.into(),
+            spaces,
+            hot_events: 1000,
+            stale_after: Duration::from_secs(5 * 60),
+            retry_interval: Duration::from_secs(30),
+        }
+    }
+}
+
+/// How up to date a mirrored space is.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncStatus {
+    pub space: String,
+    /// unix time of the last successful sync with the origin
+    pub synced_at: Option<i64>,
+    pub origin_online: bool,
+    pub stale: bool,
+}
+
+/// Where a page of [`Replica::events`] ended: the clock & id of its last event.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EventCursor {
+    clock: i64,
+    id: String,
+}
+
+impl EventCursor {
+    pub fn after(event: &Event) -> Result<Self> {
+        Ok(EventCursor {
+            clock: event.hlc()?.to_sql(),
+            id: event.id.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for EventCursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.clock, self.id)
+    }
+}
+
+impl FromStr for EventCursor {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (clock, id) = s
+            .split_once('.')
+            .ok_or_else(|| anyhow!("invalid event cursor {s}"))?;
+        Ok(EventCursor {
+            clock: clock.parse()?,
+            id: id.to_string(),
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Mirror {
+    db: DB,
+    origin: Vec<NodeAddr>,
+    synced_at: Arc<Mutex<Option<i64>>>,
+    origin_online: Arc<Mutex<bool>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Replica {
+    router: RouterClient,
+    config: Arc<ReplicaConfig>,
+    mirrors: Arc<HashMap<NamespaceId, Mirror>>,
+}
+
+impl Replica {
+    /// Import every configured feed & start following them.
+    pub async fn open(router: RouterClient, config: ReplicaConfig) -> Result<Self> {
+        tokio::fs::create_dir_all(&config.root).await?;
+        let mut mirrors = HashMap::new();
+        let mut docs = Vec::new();
+        for ticket in config.spaces.iter() {
+            let doc = router.docs().import(ticket.clone()).await?;
+            let db = open_db(config.root.join(format!("{}.db", doc.id()))).await?;
+            setup_db(&db).await?;
+            let mirror = Mirror {
+                db,
+                origin: ticket.nodes.clone(),
+                synced_at: Arc::new(Mutex::new(None)),
+                origin_online: Arc::new(Mutex::new(false)),
+            };
+            mirrors.insert(doc.id(), mirror);
+            docs.push(doc);
+        }
+
+        let replica = Replica {
+            router,
+            config: Arc::new(config),
+            mirrors: Arc::new(mirrors),
+        };
+        for doc in docs {
+            replica.watch(doc).await?;
+        }
+        Ok(replica)
+    }
+
+    pub fn status(&self) -> Vec<SyncStatus> {
+        self.mirrors
+            .iter()
+            .map(|(id, mirror)| self.mirror_status(id, mirror))
+            .collect()
+    }
+
+    /// Status of one mirrored space, None if it isn't replicated here.
+    pub fn space_status(&self, space: &str) -> Option<SyncStatus> {
+        let id = NamespaceId::from_str(space).ok()?;
+        let mirror = self.mirrors.get(&id)?;
+        Some(self.mirror_status(&id, mirror))
+    }
+
+    fn mirror_status(&self, id: &NamespaceId, mirror: &Mirror) -> SyncStatus {
+        let synced_at = *mirror.synced_at.lock().unwrap();
+        let now = chrono::Utc::now().timestamp();
+        let stale = match synced_at {
+            Some(synced_at) => now - synced_at > self.config.stale_after.as_secs() as i64,
+            None => true,
+        };
+        SyncStatus {
+            space: id.to_string(),
+            synced_at,
+            origin_online: *mirror.origin_online.lock().unwrap(),
+            stale,
+        }
+    }
+
+    /// Mirrored events of a space, newest first, starting after `before`. Events that sync in
+    /// while paging land before the first page & don't shift the pages after it.
+    pub async fn events(
+        &self,
+        space: &str,
+        before: Option<&EventCursor>,
+        limit: i64,
+    ) -> Result<Vec<Event>> {
+        let mirror = self.mirror(space)?;
+        let (clock, id) = match before {
+            Some(cursor) => (Some(cursor.clock), Some(cursor.id.as_str())),
+            None => (None, None),
+        };
+        let conn = mirror.db.lock().await;
+        let mut stmt = conn.prepare(
+            format!(
+                "SELECT {EVENT_SQL_READ_FIELDS} FROM events
+                WHERE ?1 IS NULL OR clock < ?1 OR (clock = ?1 AND id < ?2)
+                ORDER BY clock DESC, id DESC LIMIT ?3"
+            )
+            .as_str(),
+        )?;
+        let mut rows = stmt.query(params![clock, id, limit])?;
+        let mut events = Vec::new();
+        while let Some(row) = rows.next()? {
+            events.push(Event::from_sql_row(row)?);
+        }
+        Ok(events)
+    }
+
+    /// Content of a mirrored blob, None if it isn't held locally.
+    pub async fn blob(&self, hash: Hash) -> Result<Option<bytes::Bytes>> {
+        if !self.has_blob(hash).await? {
+            return Ok(None);
+        }
+        Ok(Some(self.router.blobs().read_to_bytes(hash).await?))
+    }
+
+    fn mirror(&self, space: &str) -> Result<&Mirror> {
+        let id = NamespaceId::from_str(space)?;
+        self.mirrors
+            .get(&id)
+            .ok_or_else(|| anyhow!("space {space} is not replicated"))
+    }
+
+    async fn has_blob(&self, hash: Hash) -> Result<bool> {
+        let status = self.router.blobs().status(hash).await?;
+        Ok(matches!(status, BlobStatus::Complete { .. }))
+    }
+
+    /// Apply the current state of the doc & spawn a task that follows it.
+    async fn watch(&self, doc: Doc) -> Result<()> {
+        let mirror = self.mirrors[&doc.id()].clone();
+        let mut events = doc.subscribe().await?;
+        let query = Query::single_latest_per_key().key_prefix(EVENTS_PREFIX);
+        let mut entries = doc.get_many(query).await?;
+        // rows that arrived before the table they belong to
+        let mut deferred = Vec::new();
+        while let Some(entry) = entries.next().await {
+            self.apply_or_defer(&mirror, entry?, &mut deferred).await;
+        }
+
+        let this = self.clone();
+        tokio::spawn(async move {
+            let mut retry = tokio::time::interval(this.config.retry_interval);
+            // entries whose content arrived after the entry itself
+            let mut pending: HashMap<Hash, Entry> = HashMap::new();
+            loop {
+                let event = tokio::select! {
+                    event = events.next() => match event {
+                        Some(event) => event,
+                        None => break,
+                    },
+                    _ = retry.tick() => {
+                        if !*mirror.origin_online.lock().unwrap() {
+                            if let Err(err) = doc.start_sync(mirror.origin.clone()).await {
+                                debug!("replica {} sync retry failed: {:?}", doc.id(), err);
+                            }
+                        }
+                        continue;
+                    }
+                };
+                let entry = match event {
+                    Ok(LiveEvent::InsertRemote {
+                        entry,
+                        content_status,
+                        ..
+                    }) => {
+                        if content_status != ContentStatus::Complete {
+                            pending.insert(entry.content_hash(), entry);
+                            continue;
+                        }
+                        entry
+                    }
+                    Ok(LiveEvent::ContentReady { hash }) => match pending.remove(&hash) {
+                        Some(entry) => entry,
+                        None => continue,
+                    },
+                    Ok(LiveEvent::NeighborUp(_)) => {
+                        *mirror.origin_online.lock().unwrap() = true;
+                        continue;
+                    }
+                    Ok(LiveEvent::NeighborDown(_)) => {
+                        *mirror.origin_online.lock().unwrap() = false;
+                        continue;
+                    }
+                    Ok(LiveEvent::SyncFinished(sync)) => {
+                        if sync.result.is_ok() {
+                            let now = chrono::Utc::now().timestamp();
+                            *mirror.synced_at.lock().unwrap() = Some(now);
+                            *mirror.origin_online.lock().unwrap() = true;
+                            if let Err(err) = this.fetch_hot(&mirror).await {
+                                warn!("replica {} failed to fetch blobs: {:?}", doc.id(), err);
+                            }
+                        }
+                        continue;
+                    }
+                    Ok(_) => continue,
+                    Err(err) => {
+                        warn!("replica {} event error: {:?}", doc.id(), err);
+                        continue;
+                    }
+                };
+                this.apply_or_defer(&mirror, entry, &mut deferred).await;
+            }
+            debug!("stopped following replica {}", doc.id());
+        });
+        Ok(())
+    }
+
+    /// Apply `entry`. Rows that arrived before their table are kept in `deferred`, at most
+    /// [`MAX_DEFERRED`] of them, and get another go whenever a table is applied. Entries
+    /// rejected for anything else are dropped, they won't apply later either.
+    async fn apply_or_defer(&self, mirror: &Mirror, entry: Entry, deferred: &mut Vec<Entry>) {
+        match self.apply(mirror, &entry).await {
+            Ok(Some(EventKind::MutateTable)) => {}
+            Ok(_) => return,
+            Err(err) if validation::awaits_table(&err) => {
+                debug!("replica deferring entry {:?}: {:?}", entry.key(), err);
+                if deferred.len() >= MAX_DEFERRED {
+                    let dropped = deferred.remove(0);
+                    warn!("replica dropping deferred entry {:?}", dropped.key());
+                }
+                deferred.push(entry);
+                return;
+            }
+            Err(err) => {
+                debug!("replica dropping entry {:?}: {:?}", entry.key(), err);
+                return;
+            }
+        }
+        // rows don't unblock other rows, so one pass is enough
+        for entry in std::mem::take(deferred) {
+            match self.apply(mirror, &entry).await {
+                Err(err) if validation::awaits_table(&err) => deferred.push(entry),
+                Err(err) => debug!("replica dropping entry {:?}: {:?}", entry.key(), err),
+                Ok(_) => {}
+            }
+        }
+    }
+
+    /// Write the event of `entry` to the mirror, returning its kind if it wasn't held yet.
+    async fn apply(&self, mirror: &Mirror, entry: &Entry) -> Result<Option<EventKind>> {
+        if !entry.key().starts_with(EVENTS_PREFIX.as_bytes()) || entry.content_len() == 0 {
+            return Ok(None);
+        }
+        let data = self
+            .router
+            .blobs()
+            .read_to_bytes(entry.content_hash())
+            .await?;
+        let event = Event::decode(&data)?;
+        validation::validate(&mirror.db, &self.router, &event).await?;
+
+        let conn = mirror.db.lock().await;
+        let count: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM events WHERE id = ?1",
+            params![event.id.to_string()],
+            |row| row.get(0),
+        )?;
+        drop(conn);
+        if count > 0 {
+            return Ok(None);
+        }
+        event.write(&mirror.db).await?;
+        Ok(Some(event.kind))
+    }
+
+    /// Download the content of the most recent events that isn't held locally yet.
+    async fn fetch_hot(&self, mirror: &Mirror) -> Result<()> {
+        let hashes = {
+            let conn = mirror.db.lock().await;
+            let mut stmt =
+                conn.prepare("SELECT content_hash FROM events ORDER BY clock DESC LIMIT ?1")?;
+            let mut rows = stmt.query(params![self.config.hot_events as i64])?;
+            let mut hashes = Vec::new();
+            while let Some(row) = rows.next()? {
+                let hash: String = row.get(0)?;
+                hashes.push(Hash::from_str(&hash)?);
+            }
+            hashes
+        };
+
+        for hash in hashes {
+            if self.has_blob(hash).await? {
+                continue;
+            }
+            for addr in mirror.origin.iter() {
+                match self.router.blobs().download(hash, addr.clone()).await {
+                    Ok(progress) => match progress.await {
+                        Ok(_) => break,
+                        Err(err) => debug!("fetching {} from {}: {:?}", hash, addr.node_id, err),
+                    },
+                    Err(err) => debug!("fetching {} from {}: {:?}", hash, addr.node_id, err),
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use iroh::docs::Author;
+    use serde_json::json;
+
+    use super::*;
+    use crate::space::Spaces;
+
+    #[tokio::test]
+    async fn mirrors_shared_space() -> Result<()> {
+        let origin = iroh::node::Node::memory().enable_docs().spawn().await?;
+        let replica_node = iroh::node::Node::memory().enable_docs().spawn().await?;
+        let origin_dir = tempfile::tempdir()?;
+        let dir = tempfile::tempdir()?;
+        let router = origin.client().clone();
+        let mut spaces = Spaces::open_all(router.clone(), origin_dir.path()).await?;
+        let author = Author::new(&mut rand::thread_rng());
+
+        let space = spaces
+            .create(&router, author.clone(), "shared", "a shared space")
+            .await?;
+        let schema = json!({
+            "title": "things",
+            "type": "object",
+            "properties": { "n": { "type": "number" } },
+        });
+        let table = space
+            .tables()
+            .create(author.clone(), Bytes::from(schema.to_string()))
+            .await?;
+        let ticket = space.feed().share().await?;
+        assert!(space.feed().is_shared().await?);
+        // written after sharing, published as it's written
+        space
+            .rows()
+            .create(author.clone(), table.content.hash, json!({ "n": 1 }))
+            .await?;
+
+        let config = ReplicaConfig::new(dir.path().join("replica"), vec![ticket]);
+        let replica = Replica::open(replica_node.client().clone(), config).await?;
+        let id = space.details().secret.id().to_string();
+        let status = replica.space_status(&id).expect("replicated");
+        assert!(status.stale);
+
+        let mut events = Vec::new();
+        for _ in 0..100 {
+            events = replica.events(&id, None, -1).await?;
+            if events.len() >= 3 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        // space details, table & row
+        assert_eq!(events.len(), 3);
+        let page = replica.events(&id, None, 2).await?;
+        let cursor = EventCursor::after(page.last().expect("page"))?;
+        assert_eq!(cursor.to_string().parse::<EventCursor>()?, cursor);
+        let rest = replica.events(&id, Some(&cursor), 2).await?;
+        let ids = |events: &[Event]| events.iter().map(|e| e.id.to_string()).collect::<Vec<_>>();
+        assert_eq!([ids(&page), ids(&rest)].concat(), ids(&events));
+        assert!(replica
+            .space_status(&id)
+            .expect("replicated")
+            .synced_at
+            .is_some());
+
+        // reads keep working with the origin gone
+        origin.shutdown().await?;
+        assert_eq!(replica.events(&id, None, -1).await?.len(), 3);
+        assert!(replica.events("not a space", None, -1).await.is_err());
+        Ok(())
+    }
+}
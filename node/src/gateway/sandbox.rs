@@ -0,0 +1,205 @@
+//! Sandboxed rendering of program UIs.
+//!
+//! Program `index.html` files come from whoever shared the program, so they're treated as
+//! hostile. `/sandbox/:hash` serves a small wrapper page that loads the program UI in an
+//! iframe with an opaque origin. The program can't make network requests beyond the gateway,
+//! submit forms or navigate the embedding app. It can reach the node two ways: `postMessage`
+//! to the wrapper, which relays requests for approved, read-only node APIs to the embedding
+//! app & passes answers back, or the gateway's `/api` routes with the token the wrapper hands
+//! it, see [`super::token`].
+use axum::http::{header, HeaderMap, HeaderValue};
+use iroh::blobs::Hash;
+use rand::RngCore;
+use url::Url;
+
+/// Node APIs a sandboxed program may ask the embedding app for. All read-only.
+pub(crate) const APPROVED_METHODS: &[&str] = &[
+    "tables_list",
+    "table_get",
+    "rows_query",
+    "rows_aggregate",
+    "rows_query_range",
+    "rows_changes",
+];
+
+/// Program file the wrapper loads.
+pub(crate) const ENTRY: &str = "index.html";
+
+/// Origins allowed to embed the wrapper: the gateway itself & the desktop app.
+const FRAME_ANCESTORS: &str =
+    "'self' tauri: http://tauri.localhost http://localhost:* http://127.0.0.1:*";
+
+/// Policy for program content. Subresources may only come from the program's own collection
+/// & there's no way to send data anywhere but the gateway.
+const CONTENT_CSP: &str = "sandbox allow-scripts; \
+    default-src 'none'; \
+    script-src 'self' 'unsafe-inline'; \
+    style-src 'self' 'unsafe-inline'; \
+    img-src 'self' data: blob:; \
+    font-src 'self' data:; \
+    media-src 'self' blob:; \
+    connect-src 'self'; \
+    form-action 'none'; \
+    base-uri 'none'; \
+    frame-ancestors 'self'";
+
+/// Check that `parent` is a bare origin, like `http://localhost:1420` or `tauri://localhost`.
+pub(crate) fn parse_parent_origin(parent: &str) -> anyhow::Result<String> {
+    let url = Url::parse(parent)?;
+    anyhow::ensure!(
+        matches!(url.scheme(), "http" | "https" | "tauri"),
+        "unsupported parent scheme {}",
+        url.scheme()
+    );
+    let origin = url.origin().ascii_serialization();
+    // tauri:// origins are opaque to the url crate
+    let origin = match origin.as_str() {
+        "null" => format!("{}://{}", url.scheme(), url.host_str().unwrap_or_default()),
+        _ => origin,
+    };
+    anyhow::ensure!(
+        parent.trim_end_matches('/') == origin,
+        "parent must be an origin, got {parent}"
+    );
+    Ok(origin)
+}
+
+/// Headers for a file served from inside the sandbox.
+pub(crate) fn apply_content_headers(headers: &mut HeaderMap) {
+    headers.insert(
+        header::CONTENT_SECURITY_POLICY,
+        HeaderValue::from_static(CONTENT_CSP),
+    );
+    harden(headers);
+}
+
+fn harden(headers: &mut HeaderMap) {
+    headers.insert(
+        header::X_CONTENT_TYPE_OPTIONS,
+        HeaderValue::from_static("nosniff"),
+    );
+    headers.insert(
+        header::REFERRER_POLICY,
+        HeaderValue::from_static("no-referrer"),
+    );
+    headers.insert(
+        "cross-origin-resource-policy",
+        HeaderValue::from_static("same-origin"),
+    );
+}
+
+/// The wrapper page for a program collection & its headers. Without a parent origin the
+/// program still renders, but every bridge request is refused. The API token is passed to the
+/// program in the fragment of its url, which is never sent to the gateway.
+pub(crate) fn wrapper(
+    hash: &Hash,
+    parent: Option<&str>,
+    token: Option<&str>,
+) -> anyhow::Result<(HeaderMap, String)> {
+    let mut nonce = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    let nonce = hex::encode(nonce);
+
+    let csp = format!(
+        "default-src 'none'; script-src 'nonce-{nonce}'; style-src 'nonce-{nonce}'; \
+        frame-src 'self'; base-uri 'none'; form-action 'none'; frame-ancestors {FRAME_ANCESTORS}"
+    );
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::CONTENT_SECURITY_POLICY,
+        HeaderValue::from_str(&csp)?,
+    );
+    headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("text/html"));
+    headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    harden(&mut headers);
+
+    let parent = serde_json::to_string(&parent)?;
+    let methods = serde_json::to_string(APPROVED_METHODS)?;
+    // tokens are url-safe base64, nothing to escape
+    let fragment = token
+        .map(|token| format!("#token={token}"))
+        .unwrap_or_default();
+    let body = format!(
+        r#"<!doctype html>
+<html>
+<head>
+<meta charset="utf-8">
+<style nonce="{nonce}">html, body, iframe {{ margin: 0; border: 0; width: 100%; height: 100%; }}</style>
+</head>
+<body>
+<iframe id="program" sandbox="allow-scripts" referrerpolicy="no-referrer" src="/sandbox/{hash}/app/{ENTRY}{fragment}"></iframe>
+<script nonce="{nonce}">
+(() => {{
+  const PROGRAM = "{hash}";
+  const PARENT = {parent};
+  const APPROVED = new Set({methods});
+  const frame = document.getElementById("program");
+  const reply = (id, body) => frame.contentWindow.postMessage({{ id, ...body }}, "*");
+
+  window.addEventListener("message", (event) => {{
+    const msg = event.data;
+    if (!msg || typeof msg !== "object") return;
+    if (event.source === frame.contentWindow) {{
+      if (!APPROVED.has(msg.method)) return reply(msg.id, {{ error: "method not allowed" }});
+      if (!PARENT) return reply(msg.id, {{ error: "bridge disabled" }});
+      window.parent.postMessage({{
+        squiggle: "sandbox-request",
+        program: PROGRAM,
+        id: msg.id,
+        method: msg.method,
+        params: msg.params ?? {{}},
+      }}, PARENT);
+    }} else if (PARENT && event.source === window.parent && event.origin === PARENT) {{
+      if (msg.squiggle !== "sandbox-response") return;
+      reply(msg.id, {{ result: msg.result, error: msg.error }});
+    }}
+  }});
+}})();
+</script>
+</body>
+</html>
+"#
+    );
+    Ok((headers, body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parent_origins() {
+        assert_eq!(
+            parse_parent_origin("http://localhost:1420").unwrap(),
+            "http://localhost:1420"
+        );
+        assert_eq!(
+            parse_parent_origin("tauri://localhost").unwrap(),
+            "tauri://localhost"
+        );
+        assert!(parse_parent_origin("http://localhost:1420/evil").is_err());
+        assert!(parse_parent_origin("javascript:alert(1)").is_err());
+        assert!(parse_parent_origin("http://a.com\"></script>").is_err());
+    }
+
+    #[test]
+    fn wrapper_escapes_parent() -> anyhow::Result<()> {
+        let hash = Hash::new(b"program");
+        let (headers, body) = wrapper(&hash, Some("http://localhost:1420"), None)?;
+        assert!(body.contains(r#"const PARENT = "http://localhost:1420";"#));
+        let csp = headers[header::CONTENT_SECURITY_POLICY].to_str()?;
+        assert!(csp.contains("script-src 'nonce-"));
+
+        let (_, body) = wrapper(&hash, None, Some("abc.def"))?;
+        assert!(body.contains("const PARENT = null;"));
+        assert!(body.contains(&format!("/sandbox/{hash}/app/{ENTRY}#token=abc.def")));
+        Ok(())
+    }
+
+    #[test]
+    fn wrapper_relays_the_change_feed() -> anyhow::Result<()> {
+        let (_, body) = wrapper(&Hash::new(b"program"), None, None)?;
+        assert_eq!(body.matches(r#""rows_changes""#).count(), 1);
+        Ok(())
+    }
+}
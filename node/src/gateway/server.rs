@@ -1,16 +1,21 @@
 use std::{
+    collections::HashMap,
     result,
+    str::FromStr,
     sync::{Arc, Mutex},
 };
 
 use anyhow::Context;
 use axum::{
     body::Body,
-    extract::Path,
-    http::{header, Method, Request, StatusCode},
-    response::{IntoResponse, Response},
-    routing::get,
-    Extension, Router,
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        DefaultBodyLimit, FromRequest, FromRequestParts, Multipart, Path, Query,
+    },
+    http::{header, request::Parts, HeaderMap, Method, Request, StatusCode},
+    response::{IntoResponse, Redirect, Response},
+    routing::{get, post},
+    Extension, Json, Router,
 };
 use bytes::Bytes;
 use derive_more::Deref;
@@ -20,8 +25,10 @@ use iroh::{
         get::fsm::{BlobContentNext, ConnectedNext, DecodeError, EndBlobNext},
         protocol::{RangeSpecSeq, ALPN},
         store::bao_tree::{io::fsm::BaoContentItem, ChunkNum},
+        util::SetTagOption,
         Hash,
     },
+    docs::{Author, AuthorId},
     net::{discovery::dns::DnsDiscovery, Endpoint, NodeAddr},
 };
 use lru::LruCache;
@@ -30,20 +37,37 @@ use mime_classifier::MimeClassifier;
 use range_collections::RangeSet2;
 use tower_http::cors::{AllowHeaders, AllowOrigin, CorsLayer};
 use url::Url;
+use uuid::Uuid;
 
+use super::local::LocalBlobs;
 use super::ranges::{parse_byte_range, slice, to_byte_range, to_chunk_range};
+use super::replica::{EventCursor, Replica, SyncStatus};
+use super::sandbox;
+use super::token::{ApiTokens, Claims, Role, TokenKey};
+use crate::router::RouterClient;
+use crate::space::grants::SpaceHandle;
+use crate::space::rows::Rows;
+use crate::space::{Space, SpaceDetails, Spaces};
+use crate::vm::{JobPriority, RunOptions, ThumbnailConfig, VM};
+use crate::Error;
 
 // Make our own error that wraps `anyhow::Error`.
 struct AppError(anyhow::Error);
 
-// Tell axum how to convert `AppError` into a response.
+// Tell axum how to convert `AppError` into a response: the typed error it holds as JSON, with
+// a status to match.
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Something went wrong: {}", self.0),
-        )
-            .into_response()
+        let err = Error::from(self.0);
+        let status = match err {
+            Error::SpaceNotFound { .. } | Error::ProgramNotFound { .. } => StatusCode::NOT_FOUND,
+            Error::Invalid { .. } => StatusCode::BAD_REQUEST,
+            Error::Sync { .. } => StatusCode::BAD_GATEWAY,
+            Error::AuthorMissing | Error::Io { .. } | Error::Internal { .. } => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        };
+        (status, Json(err)).into_response()
     }
 }
 
@@ -84,6 +108,20 @@ struct Inner {
     mime_cache: Mutex<MimeCache>,
     /// Cache of hashes to collections
     collection_cache: Mutex<LruCache<Hash, Collection>>,
+    /// The default node's store, when the gateway runs next to it. Blobs it has are served
+    /// from there rather than over a connection.
+    local: Option<LocalBlobs>,
+    /// Mirrored spaces, in read replica mode
+    replica: Option<Replica>,
+    /// Spaces of the default node, for looking up thumbnails & serving the API
+    spaces: Option<Spaces>,
+    /// Signs the API tokens handed to program UIs
+    tokens: TokenKey,
+    /// Tokens scripts call the API with, as the node
+    #[debug("ApiTokens")]
+    api_tokens: ApiTokens,
+    /// Runs programs for the API, when the gateway runs next to a node
+    vm: Option<Arc<VM>>,
 }
 
 impl Inner {
@@ -96,6 +134,18 @@ impl Inner {
         Ok(node_addr)
     }
 
+    fn replica(&self) -> anyhow::Result<&Replica> {
+        self.replica.as_ref().context("gateway is not a replica")
+    }
+
+    fn spaces(&self) -> anyhow::Result<&Spaces> {
+        self.spaces.as_ref().context("gateway has no spaces")
+    }
+
+    fn vm(&self) -> anyhow::Result<&VM> {
+        self.vm.as_deref().context("gateway can't run programs")
+    }
+
     /// Get the mime type for a hash from the remote node.
     async fn get_default_connection(&self) -> anyhow::Result<iroh_quinn::Connection> {
         let connection = self.endpoint.connect(self.default_node()?, ALPN).await?;
@@ -254,10 +304,9 @@ async fn handle_local_collection_index(
     gateway: Extension<Gateway>,
     Path(hash): Path<Hash>,
 ) -> std::result::Result<impl IntoResponse, AppError> {
-    let connection = gateway.get_default_connection().await?;
     // let link_prefix = format!("/collection/{}", hash);
     let link_prefix = format!("{}", hash);
-    let res = collection_index(&gateway, connection, &hash, &link_prefix).await?;
+    let res = collection_index(&gateway, &hash, &link_prefix).await?;
     Ok(res)
 }
 
@@ -267,12 +316,737 @@ async fn handle_local_collection_request(
     Path((hash, suffix)): Path<(Hash, String)>,
     req: Request<Body>,
 ) -> std::result::Result<impl IntoResponse, AppError> {
-    let connection = gateway.get_default_connection().await?;
     let byte_range = parse_byte_range(req).await?;
-    let res = forward_collection_range(&gateway, connection, &hash, &suffix, byte_range).await?;
+    let res = serve_collection_range(&gateway, &hash, &suffix, byte_range).await?;
+    Ok(res)
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct SandboxParams {
+    /// origin of the app embedding the sandbox, bridge requests are relayed there
+    parent: Option<String>,
+    /// space the program is shown in, scripts with a node API token get a program token for it
+    space: Option<Uuid>,
+    /// a program token the app got from its node, see [`crate::node::Node::sandbox_token`]
+    token: Option<String>,
+}
+
+/// Serve the sandbox wrapper for a program collection. The program only gets an API token if
+/// the request proves who's viewing it: with a program token the node issued to the app, or
+/// one of the node's API tokens, as the node's default author. Anyone else gets the program
+/// without one.
+async fn handle_sandbox_index(
+    gateway: Extension<Gateway>,
+    Path(hash): Path<Hash>,
+    Query(params): Query<SandboxParams>,
+    headers: HeaderMap,
+) -> std::result::Result<impl IntoResponse, AppError> {
+    let parent = match params.parent {
+        Some(parent) => match sandbox::parse_parent_origin(&parent) {
+            Ok(origin) => Some(origin),
+            Err(err) => return Err(Error::invalid(err).into()),
+        },
+        None => None,
+    };
+    let token = match (params.token, params.space) {
+        (Some(token), _) => {
+            let claims = match gateway
+                .tokens
+                .verify(&token, chrono::Utc::now().timestamp())
+            {
+                Ok(claims) => claims,
+                Err(err) => return Ok((StatusCode::UNAUTHORIZED, err.to_string()).into_response()),
+            };
+            if claims.program != hash {
+                return Ok((StatusCode::FORBIDDEN, "token is for another program").into_response());
+            }
+            Some(token)
+        }
+        (None, Some(space)) if bearer(&headers).is_some_and(|t| gateway.api_tokens.allows(t)) => {
+            match gateway.spaces()?.get(&space).await {
+                Some(space) => {
+                    let author = space.router().authors().default().await?;
+                    gateway.tokens.issue_for(&space, hash, author).await?
+                }
+                None => None,
+            }
+        }
+        _ => None,
+    };
+    let (headers, body) = sandbox::wrapper(&hash, parent.as_deref(), token.as_deref())?;
+    Ok((StatusCode::OK, headers, body).into_response())
+}
+
+/// The token of an `Authorization: Bearer <token>` header.
+fn bearer(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+}
+
+/// Who an `/api` request is from, by the bearer token it carries.
+enum ApiCaller {
+    /// a program UI, with a token the sandbox issued, see [`super::token`]
+    Program(Claims),
+    /// a script with one of the node's configured API tokens, which may use every space as
+    /// the node's default author
+    Node,
+}
+
+impl ApiCaller {
+    fn authorize(&self, space: Uuid, role: Role) -> anyhow::Result<()> {
+        match self {
+            ApiCaller::Program(claims) => claims.authorize(space, role),
+            ApiCaller::Node => Ok(()),
+        }
+    }
+
+    /// The author the caller writes as, none if this node doesn't hold its key.
+    async fn author(&self, space: &Space) -> anyhow::Result<Option<Author>> {
+        let author = match self {
+            ApiCaller::Program(claims) => AuthorId::from(claims.viewer.as_bytes()),
+            ApiCaller::Node => space.router().authors().default().await?,
+        };
+        space.router().authors().export(author).await
+    }
+}
+
+#[axum::async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for ApiCaller {
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let Some(gateway) = parts.extensions.get::<Gateway>() else {
+            return Err(StatusCode::INTERNAL_SERVER_ERROR.into_response());
+        };
+        ApiCaller::from_token(gateway, bearer(&parts.headers))
+    }
+}
+
+impl ApiCaller {
+    fn from_token(gateway: &Gateway, token: Option<&str>) -> Result<Self, Response> {
+        let Some(token) = token else {
+            return Err((StatusCode::UNAUTHORIZED, "missing token").into_response());
+        };
+        if gateway.api_tokens.allows(token) {
+            return Ok(ApiCaller::Node);
+        }
+        match gateway.tokens.verify(token, chrono::Utc::now().timestamp()) {
+            Ok(claims) => Ok(ApiCaller::Program(claims)),
+            Err(err) => Err((StatusCode::UNAUTHORIZED, err.to_string()).into_response()),
+        }
+    }
+}
+
+/// The space an API request is for, if its caller may take `role` there.
+async fn api_space(
+    gateway: &Gateway,
+    caller: &ApiCaller,
+    space: Uuid,
+    role: Role,
+) -> anyhow::Result<Result<Space, Response>> {
+    if let Err(err) = caller.authorize(space, role) {
+        return Ok(Err((StatusCode::FORBIDDEN, err.to_string()).into_response()));
+    }
+    match gateway.spaces()?.get(&space).await {
+        Some(space) => Ok(Ok(space)),
+        None => Ok(Err(
+            AppError::from(Error::SpaceNotFound { id: space }).into_response()
+        )),
+    }
+}
+
+/// Spaces the caller may read.
+async fn handle_api_spaces(
+    gateway: Extension<Gateway>,
+    caller: ApiCaller,
+) -> std::result::Result<impl IntoResponse, AppError> {
+    let spaces: Vec<SpaceDetails> = gateway
+        .spaces()?
+        .list(0, -1)
+        .await?
+        .into_iter()
+        .filter(|details| caller.authorize(details.id, Role::Reader).is_ok())
+        .collect();
+    Ok(Json(spaces).into_response())
+}
+
+async fn handle_api_tables(
+    gateway: Extension<Gateway>,
+    caller: ApiCaller,
+    Path(space): Path<Uuid>,
+    Query(params): Query<PageParams>,
+) -> std::result::Result<impl IntoResponse, AppError> {
+    let space = match api_space(&gateway, &caller, space, Role::Reader).await? {
+        Ok(space) => space,
+        Err(res) => return Ok(res),
+    };
+    let tables = space.tables().list(params.offset, params.limit).await?;
+    Ok(Json(tables).into_response())
+}
+
+async fn handle_api_rows(
+    gateway: Extension<Gateway>,
+    caller: ApiCaller,
+    Path((space, hash)): Path<(Uuid, Hash)>,
+    Query(params): Query<PageParams>,
+) -> std::result::Result<impl IntoResponse, AppError> {
+    let space = match api_space(&gateway, &caller, space, Role::Reader).await? {
+        Ok(space) => space,
+        Err(res) => return Ok(res),
+    };
+    let rows = space
+        .rows()
+        .query(hash, String::new(), params.offset, params.limit)
+        .await?;
+    Ok(Json(rows).into_response())
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct RowsParams {
+    table: Hash,
+    /// a [`crate::space::rows::query::RowQuery`], every row when empty
+    #[serde(default)]
+    query: String,
+    #[serde(default)]
+    offset: i64,
+    #[serde(default = "default_page_limit")]
+    limit: i64,
+}
+
+/// Query the rows of a table, eg: `?table=<hash>&query=where status = "open"`.
+async fn handle_api_query_rows(
+    gateway: Extension<Gateway>,
+    caller: ApiCaller,
+    Path(space): Path<Uuid>,
+    Query(params): Query<RowsParams>,
+) -> std::result::Result<impl IntoResponse, AppError> {
+    let space = match api_space(&gateway, &caller, space, Role::Reader).await? {
+        Ok(space) => space,
+        Err(res) => return Ok(res),
+    };
+    let rows = space
+        .rows()
+        .query(params.table, params.query, params.offset, params.limit)
+        .await?;
+    Ok(Json(rows).into_response())
+}
+
+async fn handle_api_programs(
+    gateway: Extension<Gateway>,
+    caller: ApiCaller,
+    Path(space): Path<Uuid>,
+    Query(params): Query<PageParams>,
+) -> std::result::Result<impl IntoResponse, AppError> {
+    let space = match api_space(&gateway, &caller, space, Role::Reader).await? {
+        Ok(space) => space,
+        Err(res) => return Ok(res),
+    };
+    let programs = space.programs().list(params.offset, params.limit).await?;
+    Ok(Json(programs).into_response())
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct RunRequest {
+    environment: HashMap<String, String>,
+    /// run at most once per key, see [`VM::run_program_once`]
+    idempotency_key: Option<String>,
+    profile: bool,
+    /// other spaces the run uses, each must have granted the program access
+    spaces: Vec<SpaceHandle>,
+}
+
+/// Run a program as the caller & respond with its output once it finishes.
+async fn handle_api_program_run(
+    gateway: Extension<Gateway>,
+    caller: ApiCaller,
+    Path((space, program)): Path<(Uuid, Uuid)>,
+    body: Option<Json<RunRequest>>,
+) -> std::result::Result<impl IntoResponse, AppError> {
+    let space = match api_space(&gateway, &caller, space, Role::Writer).await? {
+        Ok(space) => space,
+        Err(res) => return Ok(res),
+    };
+    let Some(author) = caller.author(&space).await? else {
+        return Ok((StatusCode::FORBIDDEN, "caller key is gone").into_response());
+    };
+    let Json(run) = body.unwrap_or_default();
+    let vm = gateway.vm()?;
+    let output = match run.idempotency_key {
+        Some(_) if !run.spaces.is_empty() => {
+            return Err(
+                Error::invalid("runs with an idempotency key can't use other spaces").into(),
+            );
+        }
+        Some(key) => {
+            vm.run_program_once(&space, author, program, run.environment, &key)
+                .await?
+        }
+        None => {
+            let options = RunOptions {
+                priority: JobPriority::Interactive,
+                profile: run.profile,
+                spaces: run.spaces,
+            };
+            vm.run_program_with(&space, author, program, run.environment, options)
+                .await?
+        }
+    };
+    Ok(Json(output).into_response())
+}
+
+/// Add a row to a table, written by the viewer.
+async fn handle_api_row_create(
+    gateway: Extension<Gateway>,
+    caller: ApiCaller,
+    Path((space, hash)): Path<(Uuid, Hash)>,
+    Json(data): Json<serde_json::Value>,
+) -> std::result::Result<impl IntoResponse, AppError> {
+    let space = match api_space(&gateway, &caller, space, Role::Writer).await? {
+        Ok(space) => space,
+        Err(res) => return Ok(res),
+    };
+    let Some(author) = caller.author(&space).await? else {
+        return Ok((StatusCode::FORBIDDEN, "caller key is gone").into_response());
+    };
+    let row = space.rows().create(author, hash, data).await?;
+    Ok((StatusCode::CREATED, Json(row)).into_response())
+}
+
+/// Largest body `/upload` & `/collection` take, in bytes.
+const MAX_UPLOAD_SIZE: usize = 256 * 1024 * 1024;
+
+#[derive(Debug, serde::Serialize)]
+struct Uploaded {
+    hash: Hash,
+    size: u64,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct UploadedFile {
+    name: String,
+    hash: Hash,
+    size: u64,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct UploadedCollection {
+    hash: Hash,
+    files: Vec<UploadedFile>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct CollectionParams {
+    /// name of a raw body within the collection
+    #[serde(default = "default_upload_name")]
+    name: String,
+}
+
+fn default_upload_name() -> String {
+    "data".to_string()
+}
+
+/// The files of a request: the parts of a multipart form, named by file name or field name,
+/// or the whole body named `raw_name`, which can't be empty.
+async fn read_upload(req: Request<Body>, raw_name: &str) -> Result<Vec<(String, Bytes)>, Response> {
+    let multipart = req
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("multipart/form-data"));
+    if !multipart {
+        let data = axum::body::to_bytes(req.into_body(), MAX_UPLOAD_SIZE)
+            .await
+            .map_err(|err| (StatusCode::PAYLOAD_TOO_LARGE, err.to_string()).into_response())?;
+        if data.is_empty() {
+            return Err(AppError::from(Error::invalid("empty body")).into_response());
+        }
+        return Ok(vec![(raw_name.to_string(), data)]);
+    }
+    let mut form = Multipart::from_request(req, &())
+        .await
+        .map_err(IntoResponse::into_response)?;
+    let mut files = Vec::new();
+    while let Some(field) = form
+        .next_field()
+        .await
+        .map_err(IntoResponse::into_response)?
+    {
+        let name = field
+            .file_name()
+            .or(field.name())
+            .unwrap_or_default()
+            .trim_start_matches('/')
+            .to_string();
+        if name.is_empty() {
+            return Err(AppError::from(Error::invalid("form part without a name")).into_response());
+        }
+        let data = field.bytes().await.map_err(IntoResponse::into_response)?;
+        files.push((name, data));
+    }
+    Ok(files)
+}
+
+/// Where uploads from `caller` go: the node's store, through the space of a program token,
+/// which must allow writing there.
+async fn upload_store(
+    gateway: &Gateway,
+    caller: &ApiCaller,
+) -> anyhow::Result<Result<RouterClient, Response>> {
+    match caller {
+        ApiCaller::Program(claims) => {
+            let space = api_space(gateway, caller, claims.space, Role::Writer).await?;
+            Ok(space.map(|space| space.router().clone()))
+        }
+        ApiCaller::Node => {
+            let local = gateway
+                .local
+                .as_ref()
+                .context("gateway has no node store")?;
+            Ok(Ok(local.client().clone()))
+        }
+    }
+}
+
+/// Add a blob to the node's store, for programs & tools that push content over HTTP instead
+/// of running an iroh client. Takes a raw body, or a multipart form holding one file. Needs a
+/// node API token, or a program token that can write to its space.
+async fn handle_upload(
+    gateway: Extension<Gateway>,
+    caller: ApiCaller,
+    req: Request<Body>,
+) -> std::result::Result<impl IntoResponse, AppError> {
+    let router = match upload_store(&gateway, &caller).await? {
+        Ok(router) => router,
+        Err(res) => return Ok(res),
+    };
+    let mut files = match read_upload(req, "").await {
+        Ok(files) => files,
+        Err(res) => return Ok(res),
+    };
+    if files.len() != 1 {
+        return Err(Error::invalid("expected one file").into());
+    }
+    let (_, data) = files.remove(0);
+    let res = router.blobs().add_bytes(data).await?;
+    let uploaded = Uploaded {
+        hash: res.hash,
+        size: res.size,
+    };
+    Ok((StatusCode::CREATED, Json(uploaded)).into_response())
+}
+
+/// Add files to the node's store wrapped in a collection, which the gateway serves like any
+/// other, see [`handle_local_collection_request`]. Takes a multipart form, or a raw body that
+/// becomes the one file of the collection, named by `?name=`.
+async fn handle_collection_upload(
+    gateway: Extension<Gateway>,
+    caller: ApiCaller,
+    Query(params): Query<CollectionParams>,
+    req: Request<Body>,
+) -> std::result::Result<impl IntoResponse, AppError> {
+    let router = match upload_store(&gateway, &caller).await? {
+        Ok(router) => router,
+        Err(res) => return Ok(res),
+    };
+    let files = match read_upload(req, &params.name).await {
+        Ok(files) => files,
+        Err(res) => return Ok(res),
+    };
+    if files.is_empty() {
+        return Err(Error::invalid("no files").into());
+    }
+    let mut names = std::collections::HashSet::new();
+    if let Some((name, _)) = files.iter().find(|(name, _)| !names.insert(name.as_str())) {
+        return Err(Error::invalid(format!("duplicate file {name}")).into());
+    }
+
+    let blobs = router.blobs();
+    let mut uploaded = Vec::with_capacity(files.len());
+    let mut tags = Vec::with_capacity(files.len());
+    for (name, data) in files {
+        let res = blobs.add_bytes(data).await?;
+        tags.push(res.tag);
+        uploaded.push(UploadedFile {
+            name,
+            hash: res.hash,
+            size: res.size,
+        });
+    }
+    let collection = uploaded
+        .iter()
+        .map(|file| (file.name.clone(), file.hash))
+        .collect::<Collection>();
+    // the collection's tag keeps the files around, theirs can go
+    let (hash, _) = blobs
+        .create_collection(collection, SetTagOption::Auto, tags)
+        .await?;
+    let uploaded = UploadedCollection {
+        hash,
+        files: uploaded,
+    };
+    Ok((StatusCode::CREATED, Json(uploaded)).into_response())
+}
+
+/// Serve a file from a program collection with the sandbox policy applied.
+async fn handle_sandbox_request(
+    gateway: Extension<Gateway>,
+    Path((hash, suffix)): Path<(Hash, String)>,
+    req: Request<Body>,
+) -> std::result::Result<impl IntoResponse, AppError> {
+    let byte_range = parse_byte_range(req).await?;
+    let mut res = serve_collection_range(&gateway, &hash, &suffix, byte_range)
+        .await?
+        .into_response();
+    sandbox::apply_content_headers(res.headers_mut());
     Ok(res)
 }
 
+/// Send the root of a published program to its index.
+async fn handle_published_index(Path((space, name)): Path<(Uuid, String)>) -> impl IntoResponse {
+    Redirect::permanent(&format!("/published/{space}/{name}/index.html"))
+}
+
+/// Serve a file from the published version of a program, see
+/// [`crate::space::programs::Programs::publish`].
+async fn handle_published_request(
+    gateway: Extension<Gateway>,
+    Path((space, name, suffix)): Path<(Uuid, String, String)>,
+    req: Request<Body>,
+) -> std::result::Result<impl IntoResponse, AppError> {
+    let Some(space) = gateway.spaces()?.get(&space).await else {
+        return Err(Error::SpaceNotFound { id: space }.into());
+    };
+    let Some(publication) = space.programs().publication_by_name(&name).await? else {
+        return Ok((StatusCode::NOT_FOUND, "nothing is published here").into_response());
+    };
+    let byte_range = parse_byte_range(req).await?;
+    let mut res = serve_collection_range(&gateway, &publication.hash, &suffix, byte_range)
+        .await?
+        .into_response();
+    // the name moves to new versions as they're published
+    res.headers_mut().insert(
+        header::CACHE_CONTROL,
+        header::HeaderValue::from_static("no-cache"),
+    );
+    sandbox::apply_content_headers(res.headers_mut());
+    Ok(res)
+}
+
+async fn handle_replica_status(
+    gateway: Extension<Gateway>,
+) -> std::result::Result<impl IntoResponse, AppError> {
+    let replica = gateway.replica()?;
+    Ok(axum::Json(replica.status()))
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct PageParams {
+    #[serde(default)]
+    offset: i64,
+    #[serde(default = "default_page_limit")]
+    limit: i64,
+}
+
+fn default_page_limit() -> i64 {
+    100
+}
+
+/// Response header of the replica events endpoint, where the next page starts.
+const REPLICA_CURSOR: &str = "x-replica-cursor";
+
+#[derive(Debug, serde::Deserialize)]
+struct ReplicaEventsParams {
+    /// `x-replica-cursor` of the previous page, the first page when unset
+    before: Option<String>,
+    #[serde(default = "default_page_limit")]
+    limit: i64,
+}
+
+/// Serve mirrored events of a space, newest first. The `x-replica-cursor` header points
+/// past the last event of the page, pass it as `before` to get the next one.
+async fn handle_replica_events(
+    gateway: Extension<Gateway>,
+    Path(space): Path<String>,
+    Query(params): Query<ReplicaEventsParams>,
+) -> std::result::Result<impl IntoResponse, AppError> {
+    let replica = gateway.replica()?;
+    let Some(status) = replica.space_status(&space) else {
+        return Ok((StatusCode::NOT_FOUND, "space is not replicated").into_response());
+    };
+    let before = match params.before.as_deref().map(EventCursor::from_str) {
+        Some(Ok(cursor)) => Some(cursor),
+        Some(Err(err)) => return Ok((StatusCode::BAD_REQUEST, err.to_string()).into_response()),
+        None => None,
+    };
+    let events = replica
+        .events(&space, before.as_ref(), params.limit)
+        .await?;
+    let cursor = events.last().map(EventCursor::after).transpose()?;
+    let mut res = axum::Json(events).into_response();
+    apply_staleness_headers(res.headers_mut(), &status);
+    if let Some(cursor) = cursor {
+        res.headers_mut().insert(
+            REPLICA_CURSOR,
+            header::HeaderValue::from_str(&cursor.to_string())?,
+        );
+    }
+    Ok(res)
+}
+
+/// Serve a blob held by the replica. Only the content of recent events is guaranteed to be
+/// mirrored.
+async fn handle_replica_blob(
+    gateway: Extension<Gateway>,
+    Path((space, hash)): Path<(String, Hash)>,
+) -> std::result::Result<impl IntoResponse, AppError> {
+    let replica = gateway.replica()?;
+    let Some(status) = replica.space_status(&space) else {
+        return Ok((StatusCode::NOT_FOUND, "space is not replicated").into_response());
+    };
+    let Some(data) = replica.blob(hash).await? else {
+        return Ok((StatusCode::NOT_FOUND, "blob is not mirrored").into_response());
+    };
+    let mime = get_mime_from_ext_and_data(None, &data, &gateway.mime_classifier);
+    let mut res = (
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, mime.to_string())],
+        Body::from(data),
+    )
+        .into_response();
+    apply_staleness_headers(res.headers_mut(), &status);
+    Ok(res)
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct ThumbnailParams {
+    /// longest side of the thumbnail, in pixels
+    size: Option<u32>,
+}
+
+/// Serve the thumbnail of an attachment to a row in `space`. 404s until the thumbnail is
+/// generated, clients should show the original, or nothing, in the meantime.
+async fn handle_thumbnail(
+    gateway: Extension<Gateway>,
+    caller: ApiCaller,
+    Path((space, hash)): Path<(Uuid, Hash)>,
+    Query(params): Query<ThumbnailParams>,
+    req: Request<Body>,
+) -> std::result::Result<impl IntoResponse, AppError> {
+    let space = match api_space(&gateway, &caller, space, Role::Reader).await? {
+        Ok(space) => space,
+        Err(res) => return Ok(res),
+    };
+    let size = params.size.unwrap_or(ThumbnailConfig::default().size);
+    let Some(thumbnail) = space.attachments().thumbnail(hash, size).await? else {
+        return Ok((StatusCode::NOT_FOUND, "no thumbnail").into_response());
+    };
+    let byte_range = parse_byte_range(req).await?;
+    let mut res = serve_range(&gateway, &thumbnail.hash, Some("thumbnail.jpg"), byte_range).await?;
+    res.headers_mut().insert(
+        header::CACHE_CONTROL,
+        header::HeaderValue::from_static("private, max-age=86400"),
+    );
+    Ok(res)
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct ChangesParams {
+    /// cursor to start after, 0 sends the whole table first
+    #[serde(default)]
+    since: i64,
+    /// API token, for browsers that can't set headers on a WebSocket
+    token: Option<String>,
+}
+
+/// Follow a table over a WebSocket. The server sends a [`crate::space::rows::RowChanges`]
+/// batch whenever the table is written to, clients keep the last cursor to resume from after
+/// a reconnect. Callers need a token that can read the space, as a bearer header or the
+/// `token` query param.
+async fn handle_row_changes(
+    gateway: Extension<Gateway>,
+    Path((space, hash)): Path<(Uuid, Hash)>,
+    Query(params): Query<ChangesParams>,
+    headers: HeaderMap,
+    ws: WebSocketUpgrade,
+) -> std::result::Result<impl IntoResponse, AppError> {
+    let token = bearer(&headers).or(params.token.as_deref());
+    let caller = match ApiCaller::from_token(&gateway, token) {
+        Ok(caller) => caller,
+        Err(res) => return Ok(res),
+    };
+    let space = match api_space(&gateway, &caller, space, Role::Reader).await? {
+        Ok(space) => space,
+        Err(res) => return Ok(res),
+    };
+    Ok(ws
+        .on_upgrade(move |socket| async move {
+            if let Err(err) = stream_row_changes(socket, space.rows(), hash, params.since).await {
+                tracing::debug!("row changes for {hash} stopped: {err:#}");
+            }
+        })
+        .into_response())
+}
+
+async fn stream_row_changes(
+    mut socket: WebSocket,
+    rows: Rows,
+    schema: Hash,
+    mut cursor: i64,
+) -> anyhow::Result<()> {
+    let mut writes = rows.subscribe();
+    loop {
+        writes.borrow_and_update();
+        loop {
+            let batch = rows.changes(schema, cursor).await?;
+            if batch.changes.is_empty() {
+                break;
+            }
+            cursor = batch.cursor;
+            socket
+                .send(Message::Text(serde_json::to_string(&batch)?))
+                .await?;
+        }
+        tokio::select! {
+            changed = writes.changed() => changed?,
+            msg = socket.recv() => match msg {
+                None | Some(Ok(Message::Close(_))) => return Ok(()),
+                // nothing to answer, axum replies to pings itself
+                Some(Ok(_)) => {}
+                Some(Err(err)) => return Err(err.into()),
+            },
+        }
+    }
+}
+
+/// Tell clients how old the mirror they're reading is. `Age` & `Warning` are the standard
+/// cache headers, the `x-replica-*` headers carry the details.
+fn apply_staleness_headers(headers: &mut header::HeaderMap, status: &SyncStatus) {
+    let origin = if status.origin_online {
+        "online"
+    } else {
+        "offline"
+    };
+    headers.insert("x-replica-origin", header::HeaderValue::from_static(origin));
+    headers.insert(
+        "x-replica-stale",
+        header::HeaderValue::from_static(if status.stale { "true" } else { "false" }),
+    );
+    if let Some(synced_at) = status.synced_at {
+        let age = (chrono::Utc::now().timestamp() - synced_at).max(0);
+        headers.insert("x-replica-synced-at", synced_at.into());
+        headers.insert(header::AGE, age.into());
+    }
+    if status.stale {
+        headers.insert(
+            header::WARNING,
+            header::HeaderValue::from_static("110 - \"Response is Stale\""),
+        );
+    }
+}
+
 // async fn handle_ticket_index(
 //     gateway: Extension<Gateway>,
 //     Path(ticket): Path<BlobTicket>,
@@ -315,7 +1089,6 @@ async fn handle_local_collection_request(
 
 async fn collection_index(
     gateway: &Gateway,
-    connection: iroh_quinn::Connection,
     hash: &Hash,
     link_prefix: &str,
 ) -> anyhow::Result<impl IntoResponse> {
@@ -326,7 +1099,7 @@ async fn collection_index(
         Ok(joined_url[url::Position::BeforePath..].to_string())
     }
 
-    let collection = get_collection(gateway, hash, &connection).await?;
+    let collection = find_collection(gateway, hash).await?;
     let mut res = String::new();
     res.push_str("<html>\n<head></head>\n");
 
@@ -350,19 +1123,37 @@ async fn collection_index(
     Ok((StatusCode::OK, response).into_response())
 }
 
-async fn forward_collection_range(
+/// Look up a collection, in the local store if it's there, otherwise on the default node.
+async fn find_collection(gateway: &Gateway, hash: &Hash) -> anyhow::Result<Collection> {
+    if let Some(res) = gateway.collection_cache.lock().unwrap().get(hash) {
+        return Ok(res.clone());
+    }
+    if let Some(local) = &gateway.local {
+        if let Some(collection) = local.collection(hash).await {
+            gateway
+                .collection_cache
+                .lock()
+                .unwrap()
+                .put(*hash, collection.clone());
+            return Ok(collection);
+        }
+    }
+    let connection = gateway.get_default_connection().await?;
+    get_collection(gateway, hash, &connection).await
+}
+
+async fn serve_collection_range(
     gateway: &Gateway,
-    connection: iroh_quinn::Connection,
     hash: &Hash,
     suffix: &str,
     range: (Option<u64>, Option<u64>),
 ) -> anyhow::Result<impl IntoResponse> {
     let suffix = suffix.strip_prefix('/').unwrap_or(suffix);
     tracing::trace!("suffix {}", suffix);
-    let collection = get_collection(gateway, hash, &connection).await?;
+    let collection = find_collection(gateway, hash).await?;
     for (name, hash) in collection.iter() {
         if name == suffix {
-            let res = forward_range(gateway, connection, hash, Some(suffix), range).await?;
+            let res = serve_range(gateway, hash, Some(suffix), range).await?;
             return Ok(res.into_response());
         } else {
             tracing::trace!("'{}' != '{}'", name, suffix);
@@ -385,6 +1176,90 @@ fn format_content_range(start: Option<u64>, end: Option<u64>, size: u64) -> Stri
     )
 }
 
+/// Serve a range of a blob from the local store if it has all of it, otherwise from the
+/// default node.
+async fn serve_range(
+    gateway: &Gateway,
+    hash: &Hash,
+    name: Option<&str>,
+    range: (Option<u64>, Option<u64>),
+) -> anyhow::Result<Response<Body>> {
+    if let Some(local) = &gateway.local {
+        if let Some(size) = local.size(hash).await {
+            return local_range(gateway, local, hash, size, name, range).await;
+        }
+    }
+    let connection = gateway.get_default_connection().await?;
+    forward_range(gateway, connection, hash, name, range).await
+}
+
+/// Get the mime type of a blob in the local store, from the cache or its first bytes.
+async fn get_local_mime_type(
+    gateway: &Gateway,
+    local: &LocalBlobs,
+    hash: &Hash,
+    size: u64,
+    name: Option<&str>,
+) -> anyhow::Result<Mime> {
+    let ext = name.and_then(get_extension);
+    let key = (*hash, ext.clone());
+    if let Some((_, mime)) = gateway.mime_cache.lock().unwrap().get(&key) {
+        return Ok(mime.clone());
+    }
+    let head = local.head(hash, 2048).await?;
+    let mime = get_mime_from_ext_and_data(ext.as_deref(), &head, &gateway.mime_classifier);
+    gateway
+        .mime_cache
+        .lock()
+        .unwrap()
+        .put(key, (size, mime.clone()));
+    Ok(mime)
+}
+
+async fn local_range(
+    gateway: &Gateway,
+    local: &LocalBlobs,
+    hash: &Hash,
+    size: u64,
+    name: Option<&str>,
+    (start, end): (Option<u64>, Option<u64>),
+) -> anyhow::Result<Response<Body>> {
+    tracing::debug!("local_range {:?} {:?} (name {name:?})", start, end);
+    let mime = get_local_mime_type(gateway, local, hash, size, name).await?;
+    let from = start.unwrap_or(0).min(size);
+    let to = end.unwrap_or(size).clamp(from, size);
+    let body = local.body(hash, size, from, to - from).await?;
+    Ok(range_response(&mime, size, (start, end), to - from, body))
+}
+
+/// A response with the headers every blob range gets.
+fn range_response(
+    mime: &Mime,
+    size: u64,
+    (start, end): (Option<u64>, Option<u64>),
+    transfer_size: u64,
+    body: Body,
+) -> Response<Body> {
+    let builder = Response::builder()
+        .status(StatusCode::OK)
+        .header(header::ACCEPT_RANGES, "bytes")
+        .header(header::CACHE_CONTROL, "public,max-age=31536000,immutable")
+        .header(header::CONTENT_TYPE, mime.to_string())
+        // content-length needs to be the actual repsonse size
+        .header(header::CONTENT_LENGTH, transfer_size);
+    let builder = if start.is_some() || end.is_some() {
+        builder
+            .header(
+                header::CONTENT_RANGE,
+                format_content_range(start, end, size),
+            )
+            .status(StatusCode::PARTIAL_CONTENT)
+    } else {
+        builder
+    };
+    builder.body(body).unwrap()
+}
+
 async fn forward_range(
     gateway: &Gateway,
     connection: iroh_quinn::Connection,
@@ -403,12 +1278,6 @@ async fn forward_range(
     tracing::debug!("mime: {}", mime);
     let chunk_ranges = RangeSpecSeq::from_ranges(vec![chunk_ranges]);
     let request = iroh::blobs::protocol::GetRequest::new(*hash, chunk_ranges.clone());
-    let status_code = if byte_ranges.is_all() {
-        StatusCode::OK
-    } else {
-        StatusCode::PARTIAL_CONTENT
-    };
-    tracing::debug!("status_code {}", status_code);
     let (send, recv) = flume::bounded::<result::Result<Bytes, DecodeError>>(2);
 
     tracing::trace!("requesting {:?}", request);
@@ -450,35 +1319,34 @@ async fn forward_range(
         Ok(())
     });
     let body = Body::from_stream(recv.into_stream());
-    let builder = Response::builder()
-        .status(status_code)
-        .header(header::ACCEPT_RANGES, "bytes")
-        .header(header::CACHE_CONTROL, "public,max-age=31536000,immutable")
-        .header(header::CONTENT_TYPE, mime.to_string());
-    // content-length needs to be the actual repsonse size
     let transfer_size = match (start, end) {
         (Some(start), Some(end)) => end - start,
         (Some(start), None) => size - start,
         (None, Some(end)) => end,
         (None, None) => size,
     };
-    let builder = builder.header(header::CONTENT_LENGTH, transfer_size);
-
-    let builder = if start.is_some() || end.is_some() {
-        builder
-            .header(
-                header::CONTENT_RANGE,
-                format_content_range(start, end, size),
-            )
-            .status(StatusCode::PARTIAL_CONTENT)
-    } else {
-        builder
-    };
-    let response = builder.body(body).unwrap();
-    Ok(response)
+    Ok(range_response(
+        &mime,
+        size,
+        (start, end),
+        transfer_size,
+        body,
+    ))
 }
 
-pub async fn run(default_node: NodeAddr, serve_addr: String) -> anyhow::Result<()> {
+/// Serve the gateway. Program tokens are signed with `tokens`, shared with the node so the
+/// app can get them from it.
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn run(
+    default_node: NodeAddr,
+    local: Option<LocalBlobs>,
+    replica: Option<Replica>,
+    spaces: Option<Spaces>,
+    vm: Option<Arc<VM>>,
+    tokens: TokenKey,
+    api_tokens: Vec<String>,
+    serve_addr: String,
+) -> anyhow::Result<()> {
     let endpoint = Endpoint::builder()
         .discovery(Box::new(DnsDiscovery::n0_dns()))
         .bind()
@@ -486,31 +1354,287 @@ pub async fn run(default_node: NodeAddr, serve_addr: String) -> anyhow::Result<(
     let gateway = Gateway(Arc::new(Inner {
         endpoint,
         default_node: Some(default_node),
+        local,
         mime_classifier: MimeClassifier::new(),
         mime_cache: Mutex::new(LruCache::new(100000.try_into().unwrap())),
         collection_cache: Mutex::new(LruCache::new(1000.try_into().unwrap())),
+        replica,
+        spaces,
+        tokens,
+        api_tokens: ApiTokens::new(api_tokens),
+        vm,
     }));
 
+    // Run our application as just http
+    println!("listening on {}, http", serve_addr);
+
+    let listener = tokio::net::TcpListener::bind(serve_addr).await?;
+    axum::serve(listener, app(gateway)).await?;
+    Ok(())
+}
+
+/// Routes of the gateway, see [`run`].
+#[rustfmt::skip]
+fn app(gateway: Gateway) -> Router {
     let cors = CorsLayer::new()
         .allow_headers(AllowHeaders::mirror_request())
-        .allow_methods([Method::GET, Method::HEAD, Method::OPTIONS])
-        .allow_origin(AllowOrigin::mirror_request());
+        .allow_methods([
+            Method::GET,
+            Method::HEAD,
+            Method::POST,
+            Method::PUT,
+            Method::OPTIONS,
+        ])
+        .allow_origin(AllowOrigin::mirror_request())
+        .expose_headers([
+            header::AGE,
+            header::WARNING,
+            header::HeaderName::from_static(REPLICA_CURSOR),
+            header::HeaderName::from_static("x-replica-origin"),
+            header::HeaderName::from_static("x-replica-stale"),
+            header::HeaderName::from_static("x-replica-synced-at"),
+        ]);
 
-    #[rustfmt::skip]
-    let app = Router::new()
+    let upload_limit = DefaultBodyLimit::max(MAX_UPLOAD_SIZE);
+    Router::new()
+        .route("/upload", post(handle_upload).put(handle_upload).layer(upload_limit))
+        .route("/collection", post(handle_collection_upload).put(handle_collection_upload).layer(upload_limit))
         .route("/:blake3_hash", get(handle_local_collection_index))
         .route("/:blake3_hash/*path", get(handle_local_collection_request))
+        .route("/sandbox/:blake3_hash", get(handle_sandbox_index))
+        .route("/sandbox/:blake3_hash/app/*path", get(handle_sandbox_request))
+        .route("/published/:space/:name", get(handle_published_index))
+        .route("/published/:space/:name/*path", get(handle_published_request))
+        .route("/replica/spaces", get(handle_replica_status))
+        .route("/replica/spaces/:space/events", get(handle_replica_events))
+        .route("/replica/spaces/:space/blobs/:blake3_hash", get(handle_replica_blob))
+        .route("/spaces/:space/tables/:blake3_hash/changes", get(handle_row_changes))
+        .route("/api/spaces", get(handle_api_spaces))
+        .route("/api/spaces/:space/tables", get(handle_api_tables))
+        .route("/api/spaces/:space/tables/:blake3_hash/rows", get(handle_api_rows).post(handle_api_row_create))
+        .route("/api/spaces/:space/rows", get(handle_api_query_rows))
+        .route("/api/spaces/:space/programs", get(handle_api_programs))
+        .route("/api/spaces/:space/programs/:program/run", post(handle_api_program_run))
+        .route("/api/spaces/:space/thumbnails/:blake3_hash", get(handle_thumbnail))
         // .route("/blob/:blake3_hash", get(handle_local_blob_request))
         // .route("/collection/:blake3_hash", get(handle_local_collection_index))
         // .route("/collection/:blake3_hash/*path",get(handle_local_collection_request))
         // .route("/ticket/:ticket", get(handle_ticket_index))
         // .route("/ticket/:ticket/*path", get(handle_ticket_request))
         .layer(cors)
-        .layer(Extension(gateway));
-    // Run our application as just http
-    println!("listening on {}, http", serve_addr);
+        .layer(Extension(gateway))
+}
 
-    let listener = tokio::net::TcpListener::bind(serve_addr).await?;
-    axum::serve(listener, app).await?;
-    Ok(())
+#[cfg(test)]
+mod tests {
+    use axum::body::to_bytes;
+    use iroh::net::key::PublicKey;
+    use tower_service::Service;
+
+    use super::*;
+
+    const API_TOKEN: &str = "ci-secret";
+
+    /// A gateway next to an in-memory node with one space, created by an author the node holds.
+    struct Harness {
+        app: Router,
+        tokens: TokenKey,
+        space: Space,
+        author: Author,
+        _node: iroh::node::Node<iroh::blobs::store::mem::Store>,
+        _dir: tempfile::TempDir,
+    }
+
+    impl Harness {
+        async fn new() -> anyhow::Result<Self> {
+            let node = iroh::node::Node::memory().enable_docs().spawn().await?;
+            let dir = tempfile::tempdir()?;
+            let router = node.client().clone();
+            let mut spaces = Spaces::open_all(router.clone(), dir.path()).await?;
+            let author = Author::new(&mut rand::thread_rng());
+            router.authors().import(author.clone()).await?;
+            let space = spaces
+                .create(&router, author.clone(), "gateway", "a space")
+                .await?;
+            let tokens = TokenKey::generate();
+            let gateway = Gateway(Arc::new(Inner {
+                endpoint: node.endpoint().clone(),
+                default_node: None,
+                mime_classifier: MimeClassifier::new(),
+                mime_cache: Mutex::new(LruCache::new(16.try_into().unwrap())),
+                collection_cache: Mutex::new(LruCache::new(16.try_into().unwrap())),
+                local: Some(LocalBlobs::new(router.clone(), None)),
+                replica: None,
+                spaces: Some(spaces),
+                tokens: tokens.clone(),
+                api_tokens: ApiTokens::new(vec![API_TOKEN.to_string()]),
+                vm: None,
+            }));
+            Ok(Harness {
+                app: app(gateway),
+                tokens,
+                space,
+                author,
+                _node: node,
+                _dir: dir,
+            })
+        }
+
+        async fn send(&self, req: Request<Body>) -> anyhow::Result<(StatusCode, String)> {
+            let res = self.app.clone().call(req).await?;
+            let status = res.status();
+            let body = to_bytes(res.into_body(), usize::MAX).await?;
+            Ok((status, String::from_utf8_lossy(&body).into_owned()))
+        }
+    }
+
+    fn get(uri: String) -> Request<Body> {
+        Request::get(uri).body(Body::empty()).unwrap()
+    }
+
+    /// The token the sandbox wrapper hands its program, if any.
+    fn wrapper_token(body: &str) -> Option<String> {
+        let start = body.find("#token=")? + "#token=".len();
+        let end = start + body[start..].find('"')?;
+        Some(body[start..end].to_string())
+    }
+
+    #[tokio::test]
+    async fn sandbox_tokens_need_a_proven_viewer() -> anyhow::Result<()> {
+        let h = Harness::new().await?;
+        let program = Hash::new(b"program");
+        let space = h.space.id;
+
+        // anyone can load the program, but gets no token for it
+        let (status, body) = h
+            .send(get(format!("/sandbox/{program}?space={space}")))
+            .await?;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(wrapper_token(&body), None);
+
+        // naming an author the node holds as the viewer proves nothing
+        let viewer = h.author.id();
+        let uri = format!("/sandbox/{program}?space={space}&viewer={viewer}");
+        let (status, body) = h.send(get(uri)).await?;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(wrapper_token(&body), None);
+
+        // neither does a token signed by anyone but the node
+        let forged = TokenKey::generate().issue(&Claims {
+            space,
+            program,
+            viewer: PublicKey::from_bytes(viewer.as_bytes())?,
+            role: Role::Writer,
+            expires_at: i64::MAX,
+        })?;
+        let (status, _) = h
+            .send(get(format!("/sandbox/{program}?token={forged}")))
+            .await?;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        // or an API token the node doesn't know
+        let req = Request::get(format!("/sandbox/{program}?space={space}"))
+            .header(header::AUTHORIZATION, "Bearer not-the-secret")
+            .body(Body::empty())?;
+        let (_, body) = h.send(req).await?;
+        assert_eq!(wrapper_token(&body), None);
+
+        // the app passes on what its node issued, for that program only
+        let token = h
+            .tokens
+            .issue_for(&h.space, program, viewer)
+            .await?
+            .expect("the author has a role");
+        let other = Hash::new(b"other program");
+        let (status, _) = h
+            .send(get(format!("/sandbox/{other}?token={token}")))
+            .await?;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, body) = h
+            .send(get(format!("/sandbox/{program}?token={token}")))
+            .await?;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(wrapper_token(&body), Some(token));
+
+        // scripts with a node API token view as the node
+        let req = Request::get(format!("/sandbox/{program}?space={space}"))
+            .header(header::AUTHORIZATION, format!("Bearer {API_TOKEN}"))
+            .body(Body::empty())?;
+        let (_, body) = h.send(req).await?;
+        let token = wrapper_token(&body).expect("a token for the node");
+        let claims = h.tokens.verify(&token, chrono::Utc::now().timestamp())?;
+        assert_eq!(claims.space, space);
+        assert_eq!(claims.role, Role::Writer);
+        Ok(())
+    }
+
+    fn upload(uri: &str, token: &str, body: &'static str) -> Request<Body> {
+        Request::post(uri)
+            .header(header::AUTHORIZATION, format!("Bearer {token}"))
+            .body(Body::from(body))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn uploads_take_program_and_node_tokens() -> anyhow::Result<()> {
+        let h = Harness::new().await?;
+        let program = Hash::new(b"program");
+        let writer = h
+            .tokens
+            .issue_for(&h.space, program, h.author.id())
+            .await?
+            .expect("the author has a role");
+
+        for token in [writer.as_str(), API_TOKEN] {
+            let (status, body) = h.send(upload("/upload", token, "hello")).await?;
+            assert_eq!(status, StatusCode::CREATED, "{body}");
+            let uploaded: serde_json::Value = serde_json::from_str(&body)?;
+            assert_eq!(uploaded["hash"], Hash::new(b"hello").to_string());
+
+            let (status, body) = h
+                .send(upload("/collection?name=a.txt", token, "hello"))
+                .await?;
+            assert_eq!(status, StatusCode::CREATED, "{body}");
+            let uploaded: serde_json::Value = serde_json::from_str(&body)?;
+            assert_eq!(uploaded["files"][0]["name"], "a.txt");
+        }
+        let data = h.space.router().blobs().read_to_bytes(Hash::new(b"hello"));
+        assert_eq!(data.await?, "hello".as_bytes());
+
+        // a program that can only read its space can't upload
+        let reader = h.tokens.issue(&Claims {
+            space: h.space.id,
+            program,
+            viewer: PublicKey::from_bytes(h.author.id().as_bytes())?,
+            role: Role::Reader,
+            expires_at: i64::MAX,
+        })?;
+        let (status, _) = h.send(upload("/upload", &reader, "hello")).await?;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn uploads_refuse_empty_oversized_and_unauthorized() -> anyhow::Result<()> {
+        let h = Harness::new().await?;
+
+        for uri in ["/upload", "/collection"] {
+            let (status, _) = h.send(upload(uri, API_TOKEN, "")).await?;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{uri}");
+
+            let big = Bytes::from(vec![0u8; MAX_UPLOAD_SIZE + 1]);
+            let req = Request::post(uri)
+                .header(header::AUTHORIZATION, format!("Bearer {API_TOKEN}"))
+                .body(Body::from(big))?;
+            let (status, _) = h.send(req).await?;
+            assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE, "{uri}");
+
+            let req = Request::post(uri).body(Body::from("hello"))?;
+            let (status, _) = h.send(req).await?;
+            assert_eq!(status, StatusCode::UNAUTHORIZED, "{uri}");
+            let (status, _) = h.send(upload(uri, "not-the-secret", "hello")).await?;
+            assert_eq!(status, StatusCode::UNAUTHORIZED, "{uri}");
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,197 @@
+//! Tokens program UIs call gateway API routes with, as the person viewing them.
+//!
+//! Only callers that proved who they are get one: the app asks its node with
+//! [`crate::node::Node::sandbox_token`], scripts fetch the sandbox wrapper with one of the
+//! node's [`ApiTokens`]. The wrapper hands the token to the program in the fragment of the
+//! iframe url. A token is scoped to one space & the viewer's role in it. Tokens are signed with
+//! a key the node generates on start, so they die with the process, and expire after
+//! [`TOKEN_TTL`] seconds regardless.
+//!
+//! Scripts driving a headless node use one of the [`ApiTokens`] set in the node's
+//! `api_tokens` config instead. Those don't expire & act as the node in every space.
+use anyhow::{anyhow, Context, Result};
+use base64::Engine;
+use ed25519_dalek::Signature;
+use iroh::blobs::Hash;
+use iroh::docs::AuthorId;
+use iroh::net::key::{PublicKey, SecretKey};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::admin::tokens_match;
+use crate::space::Space;
+
+/// How long a token is good for, in seconds. Programs reload the wrapper for a new one.
+pub(crate) const TOKEN_TTL: i64 = 15 * 60;
+
+const BASE64: base64::engine::GeneralPurpose = base64::engine::general_purpose::URL_SAFE_NO_PAD;
+
+/// What a viewer may do through the API. Writers can do everything readers can.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Role {
+    /// a member of the space
+    Reader,
+    /// someone this node holds the key of, so it can write as them
+    Writer,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Claims {
+    pub space: Uuid,
+    /// the program the token was issued to
+    pub program: Hash,
+    pub viewer: PublicKey,
+    pub role: Role,
+    /// unix timestamp, in seconds
+    pub expires_at: i64,
+}
+
+impl Claims {
+    /// Check the token is good for `space` & allows `role`.
+    pub fn authorize(&self, space: Uuid, role: Role) -> Result<()> {
+        anyhow::ensure!(self.space == space, "token is for another space");
+        anyhow::ensure!(self.role >= role, "token doesn't allow {:?}", role);
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct TokenKey(SecretKey);
+
+impl TokenKey {
+    pub fn generate() -> Self {
+        TokenKey(SecretKey::generate())
+    }
+
+    /// A token for `claims`, as `<claims>.<signature>` in unpadded url-safe base64.
+    pub fn issue(&self, claims: &Claims) -> Result<String> {
+        let claims = BASE64.encode(serde_json::to_vec(claims)?);
+        let sig = self.0.sign(claims.as_bytes());
+        Ok(format!("{}.{}", claims, BASE64.encode(sig.to_bytes())))
+    }
+
+    /// The claims of a token this key issued that hasn't expired by `now`.
+    pub fn verify(&self, token: &str, now: i64) -> Result<Claims> {
+        let (claims, sig) = token.split_once('.').context("malformed token")?;
+        let sig = BASE64.decode(sig).context("malformed token signature")?;
+        let sig = Signature::from_slice(&sig).map_err(|e| anyhow!(e))?;
+        self.0
+            .public()
+            .verify(claims.as_bytes(), &sig)
+            .map_err(|_| anyhow!("bad token signature"))?;
+
+        let claims: Claims = serde_json::from_slice(&BASE64.decode(claims)?)?;
+        anyhow::ensure!(claims.expires_at > now, "token expired");
+        Ok(claims)
+    }
+
+    /// A token for `program` shown to `viewer` in `space`, none if the viewer has no role
+    /// there. The caller must have proven it is `viewer`.
+    pub async fn issue_for(
+        &self,
+        space: &Space,
+        program: Hash,
+        viewer: AuthorId,
+    ) -> Result<Option<String>> {
+        let Some(role) = viewer_role(space, viewer).await? else {
+            return Ok(None);
+        };
+        let claims = Claims {
+            space: space.id,
+            program,
+            viewer: PublicKey::from_bytes(viewer.as_bytes())?,
+            role,
+            expires_at: chrono::Utc::now().timestamp() + TOKEN_TTL,
+        };
+        self.issue(&claims).map(Some)
+    }
+}
+
+/// Viewers this node holds the key of can write as themselves, other members can read.
+async fn viewer_role(space: &Space, viewer: AuthorId) -> Result<Option<Role>> {
+    if space.router().authors().export(viewer).await?.is_some() {
+        return Ok(Some(Role::Writer));
+    }
+    let members = space.users().list(0, -1).await?;
+    let role = members
+        .iter()
+        .any(|user| user.pubkey.as_bytes() == viewer.as_bytes())
+        .then_some(Role::Reader);
+    Ok(role)
+}
+
+/// Long-lived tokens from the node's config.
+#[derive(Default)]
+pub(crate) struct ApiTokens(Vec<String>);
+
+impl ApiTokens {
+    /// Empty tokens are dropped, they'd let in requests with an empty bearer.
+    pub fn new(tokens: Vec<String>) -> Self {
+        ApiTokens(tokens.into_iter().filter(|t| !t.is_empty()).collect())
+    }
+
+    pub fn allows(&self, token: &str) -> bool {
+        // no early return, so timing doesn't tell which token came close
+        self.0.iter().fold(false, |found, known| {
+            tokens_match(token.as_bytes(), known.as_bytes()) | found
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn claims(role: Role) -> Claims {
+        Claims {
+            space: Uuid::new_v4(),
+            program: Hash::new(b"program"),
+            viewer: SecretKey::generate().public(),
+            role,
+            expires_at: 1_000,
+        }
+    }
+
+    #[test]
+    fn tokens_round_trip() -> Result<()> {
+        let key = TokenKey::generate();
+        let claims = claims(Role::Reader);
+        let token = key.issue(&claims)?;
+        assert_eq!(key.verify(&token, 999)?, claims);
+        assert!(key.verify(&token, 1_000).is_err(), "expired");
+
+        // issued by another gateway
+        assert!(TokenKey::generate().verify(&token, 999).is_err());
+
+        // claims swapped out from under the signature
+        let (_, sig) = token.split_once('.').unwrap();
+        let mut writer = claims.clone();
+        writer.role = Role::Writer;
+        let forged = format!("{}.{}", BASE64.encode(serde_json::to_vec(&writer)?), sig);
+        assert!(key.verify(&forged, 999).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn tokens_are_scoped() {
+        let reader = claims(Role::Reader);
+        assert!(reader.authorize(reader.space, Role::Reader).is_ok());
+        assert!(reader.authorize(reader.space, Role::Writer).is_err());
+        assert!(reader.authorize(Uuid::new_v4(), Role::Reader).is_err());
+
+        let writer = claims(Role::Writer);
+        assert!(writer.authorize(writer.space, Role::Reader).is_ok());
+        assert!(writer.authorize(writer.space, Role::Writer).is_ok());
+    }
+
+    #[test]
+    fn api_tokens_match_exactly() {
+        let tokens = ApiTokens::new(vec!["ci-secret".to_string(), String::new()]);
+        assert!(tokens.allows("ci-secret"));
+        assert!(!tokens.allows("ci-secre"));
+        assert!(!tokens.allows(""));
+        assert!(!ApiTokens::default().allows(""));
+    }
+}
@@ -0,0 +1,680 @@
+//! GraphQL API over spaces, behind the `graphql` feature.
+//!
+//! Each space gets a schema generated from its tables: an object type per table with a field
+//! per top-level schema property, queries that page through the latest version of each row,
+//! & mutations that write row events as the node's account. Row metadata is exposed as
+//! `_id`, `_author` & `_createdAt` so it can't collide with row fields. For a table titled
+//! `github stargazers`:
+//!
+//! ```graphql
+//! query {
+//!   githubStargazers(where: { login: "b5" }, offset: 0, limit: 10) { _id login stars }
+//!   githubStargazersById(id: "...") { login }
+//! }
+//! mutation {
+//!   insertGithubStargazers(data: { login: "b5", stars: 3 }) { _id }
+//!   updateGithubStargazers(id: "...", data: { stars: 4 }) { stars }
+//! }
+//! ```
+//!
+//! Schemas are regenerated whenever a space's tables change. [`serve`] hosts every space at
+//! `/graphql/<space name>`, with GraphiQL on GET. Queries need one of the node's API tokens
+//! as a bearer token, see [`crate::gateway::token`].
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use async_graphql::dynamic::{
+    Field, FieldFuture, FieldValue, InputObject, InputValue, Object, ResolverContext, Scalar,
+    Schema, TypeRef,
+};
+use async_graphql::http::GraphiQLSource;
+use async_graphql_axum::{GraphQLRequest, GraphQLResponse};
+use axum::extract::Path;
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::Html;
+use axum::routing::get;
+use axum::{Extension, Router};
+use iroh::blobs::Hash;
+use iroh::docs::Author;
+use serde_json::{Map, Value};
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use crate::gateway::token::ApiTokens;
+use crate::space::rows::query::{Filter, Op, Order, RowQuery};
+use crate::space::rows::UPDATED_AT_FIELD;
+use crate::space::{Space, Spaces};
+
+const JSON_SCALAR: &str = "JSON";
+const DEFAULT_LIMIT: i64 = 100;
+/// type names that can't be used for tables
+const RESERVED_TYPES: &[&str] = &[
+    "Query",
+    "Mutation",
+    JSON_SCALAR,
+    "String",
+    "Int",
+    "Float",
+    "Boolean",
+    "ID",
+];
+
+/// A table, as it appears in the schema.
+#[derive(Debug, Clone, PartialEq)]
+struct TableType {
+    hash: Hash,
+    /// object type name, eg: `GithubStargazers`
+    name: String,
+    /// query field name, eg: `githubStargazers`
+    field: String,
+    columns: Vec<Column>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Column {
+    /// graphql field name
+    field: String,
+    /// property name in row data
+    property: String,
+    /// graphql type of the field
+    ty: &'static str,
+}
+
+/// The latest version of a row, the parent value of table object fields.
+struct RowValue {
+    id: Uuid,
+    author: String,
+    created_at: i64,
+    data: Value,
+}
+
+/// Generate the schema for a space, mutations are authored by `author`.
+pub async fn schema(space: &Space, author: Author) -> Result<Schema> {
+    let tables = table_types(space).await?;
+    build_schema(space.clone(), author, &tables)
+}
+
+/// Serve GraphQL for every space at `/graphql/<space name>`, to callers with one of
+/// `api_tokens`.
+pub async fn serve(
+    spaces: Spaces,
+    author: Author,
+    api_tokens: Vec<String>,
+    serve_addr: String,
+) -> Result<()> {
+    let graphql = Graphql {
+        spaces,
+        author,
+        api_tokens: Arc::new(ApiTokens::new(api_tokens)),
+        schemas: Default::default(),
+    };
+    let app = Router::new()
+        .route("/graphql/:space", get(handle_graphiql).post(handle_query))
+        .layer(Extension(graphql));
+
+    let listener = tokio::net::TcpListener::bind(serve_addr).await?;
+    tracing::info!("graphql listening on {}", listener.local_addr()?);
+    axum::serve(listener, app).await?;
+    Ok(())
+}
+
+#[derive(Clone)]
+struct Graphql {
+    spaces: Spaces,
+    author: Author,
+    api_tokens: Arc<ApiTokens>,
+    /// generated schemas by space id, with the tables they were generated from
+    schemas: Arc<Mutex<HashMap<Uuid, (Vec<TableType>, Schema)>>>,
+}
+
+impl Graphql {
+    async fn schema(&self, name: &str) -> Result<Schema> {
+        let space = self
+            .spaces
+            .get_by_name(name)
+            .await
+            .with_context(|| format!("space {name} not found"))?;
+        let tables = table_types(&space).await?;
+        let mut schemas = self.schemas.lock().await;
+        if let Some((generated_from, schema)) = schemas.get(&space.id) {
+            if *generated_from == tables {
+                return Ok(schema.clone());
+            }
+        }
+        let schema = build_schema(space.clone(), self.author.clone(), &tables)?;
+        schemas.insert(space.id, (tables, schema.clone()));
+        Ok(schema)
+    }
+}
+
+async fn handle_query(
+    graphql: Extension<Graphql>,
+    Path(space): Path<String>,
+    headers: HeaderMap,
+    req: GraphQLRequest,
+) -> std::result::Result<GraphQLResponse, (StatusCode, String)> {
+    // mutations write as the node, only its own scripts get in
+    let token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    if !token.is_some_and(|token| graphql.api_tokens.allows(token)) {
+        return Err((
+            StatusCode::UNAUTHORIZED,
+            "missing or unknown token".to_string(),
+        ));
+    }
+    let schema = graphql
+        .schema(&space)
+        .await
+        .map_err(|err| (StatusCode::NOT_FOUND, format!("{err:#}")))?;
+    Ok(schema.execute(req.into_inner()).await.into())
+}
+
+async fn handle_graphiql(Path(space): Path<String>) -> Html<String> {
+    let endpoint = format!("/graphql/{space}");
+    Html(GraphiQLSource::build().endpoint(&endpoint).finish())
+}
+
+/// The latest version of every table with at least one property, sorted by title.
+async fn table_types(space: &Space) -> Result<Vec<TableType>> {
+    let mut latest: HashMap<Uuid, crate::space::tables::Table> = HashMap::new();
+    for table in space.tables().list(0, -1).await? {
+        // listed oldest first, later versions win
+        latest.insert(table.id, table);
+    }
+
+    let mut tables = Vec::new();
+    let mut names: HashSet<String> = RESERVED_TYPES.iter().map(|s| s.to_string()).collect();
+    let mut latest: Vec<_> = latest.into_values().collect();
+    latest.sort_by(|a, b| a.title.cmp(&b.title));
+    for mut table in latest {
+        let schema = table.content.resolve(space.router()).await?;
+        let name = type_name(&table.title);
+        if !names.insert(name.clone()) {
+            tracing::warn!("graphql: skipping table {:?}, {name} is taken", table.title);
+            continue;
+        }
+        let columns = columns(&schema);
+        if columns.is_empty() {
+            tracing::warn!(
+                "graphql: skipping table {:?}, it has no properties",
+                table.title
+            );
+            continue;
+        }
+        tables.push(TableType {
+            hash: table.content.hash,
+            field: lower_first(&name),
+            name,
+            columns,
+        });
+    }
+    Ok(tables)
+}
+
+/// Fields for the top-level properties of a JSON schema.
+fn columns(schema: &Value) -> Vec<Column> {
+    let Some(properties) = schema.get("properties").and_then(Value::as_object) else {
+        return Vec::new();
+    };
+    let mut fields: HashSet<String> = ["_id", "_author", "_createdAt"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    let mut columns = Vec::new();
+    for (property, definition) in properties {
+        let field = field_name(property);
+        if !fields.insert(field.clone()) {
+            tracing::warn!("graphql: skipping property {property:?}, {field} is taken");
+            continue;
+        }
+        columns.push(Column {
+            field,
+            property: property.clone(),
+            ty: scalar_type(definition),
+        });
+    }
+    columns
+}
+
+/// The graphql type for a JSON schema property. Nullable unions like `["string", "null"]`
+/// map to their non-null type, anything that isn't a scalar is passed through as JSON.
+fn scalar_type(definition: &Value) -> &'static str {
+    let ty = match definition.get("type") {
+        Some(Value::String(ty)) => Some(ty.as_str()),
+        Some(Value::Array(types)) => types
+            .iter()
+            .filter_map(Value::as_str)
+            .find(|ty| *ty != "null"),
+        _ => None,
+    };
+    match ty {
+        Some("string") => TypeRef::STRING,
+        Some("integer") => TypeRef::INT,
+        Some("number") => TypeRef::FLOAT,
+        Some("boolean") => TypeRef::BOOLEAN,
+        _ => JSON_SCALAR,
+    }
+}
+
+/// `github stargazers` -> `GithubStargazers`
+fn type_name(title: &str) -> String {
+    let name: String = title
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| {
+            let mut chars = word.chars();
+            let first = chars.next().map(|c| c.to_ascii_uppercase());
+            first.into_iter().chain(chars).collect::<String>()
+        })
+        .collect();
+    match name.chars().next() {
+        None => "Table".to_string(),
+        Some(c) if c.is_ascii_digit() => format!("T{name}"),
+        Some(_) => name,
+    }
+}
+
+/// Property names with characters graphql doesn't allow replaced by `_`.
+fn field_name(property: &str) -> String {
+    let name: String = property
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    match name.chars().next() {
+        None => "_".to_string(),
+        Some(c) if c.is_ascii_digit() => format!("_{name}"),
+        Some(_) => name,
+    }
+}
+
+fn lower_first(name: &str) -> String {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) => c.to_ascii_lowercase().to_string() + chars.as_str(),
+        None => String::new(),
+    }
+}
+
+fn build_schema(space: Space, author: Author, tables: &[TableType]) -> Result<Schema> {
+    // an object type needs at least one field, always list the tables
+    let titles: Vec<async_graphql::Value> = tables
+        .iter()
+        .map(|table| async_graphql::Value::from(table.name.clone()))
+        .collect();
+    let mut query = Object::new("Query").field(Field::new(
+        "_tables",
+        TypeRef::named_nn_list_nn(TypeRef::STRING),
+        move |_| {
+            let titles = titles.clone();
+            FieldFuture::new(async move { Ok(Some(async_graphql::Value::List(titles))) })
+        },
+    ));
+    let mut mutation = Object::new("Mutation");
+    let mutation_type = (!tables.is_empty()).then_some("Mutation");
+    let mut builder =
+        Schema::build("Query", mutation_type, None::<&str>).register(Scalar::new(JSON_SCALAR));
+    for table in tables {
+        builder = builder
+            .register(object_type(table))
+            .register(input_type(table, &format!("{}Filter", table.name)))
+            .register(input_type(table, &format!("{}Input", table.name)));
+        query = query.field(list_field(table)).field(get_field(table));
+        mutation = mutation
+            .field(insert_field(table))
+            .field(update_field(table));
+    }
+    builder = builder.register(query);
+    if !tables.is_empty() {
+        builder = builder.register(mutation);
+    }
+    let schema = builder.data(space).data(author).finish()?;
+    Ok(schema)
+}
+
+fn object_type(table: &TableType) -> Object {
+    let mut object = Object::new(&table.name)
+        .field(meta_field("_id", TypeRef::ID, |row| {
+            row.id.to_string().into()
+        }))
+        .field(meta_field("_author", TypeRef::STRING, |row| {
+            row.author.clone().into()
+        }))
+        .field(meta_field("_createdAt", TypeRef::INT, |row| {
+            row.created_at.into()
+        }));
+    for column in table.columns.iter() {
+        let property = column.property.clone();
+        object = object.field(Field::new(
+            &column.field,
+            TypeRef::named(column.ty),
+            move |ctx| {
+                let property = property.clone();
+                FieldFuture::new(async move {
+                    let row = ctx.parent_value.try_downcast_ref::<RowValue>()?;
+                    match row.data.get(&property) {
+                        None | Some(Value::Null) => Ok(None),
+                        Some(value) => {
+                            let value = async_graphql::Value::from_json(value.clone())?;
+                            Ok(Some(FieldValue::value(value)))
+                        }
+                    }
+                })
+            },
+        ));
+    }
+    object
+}
+
+fn meta_field(name: &str, ty: &str, get: fn(&RowValue) -> async_graphql::Value) -> Field {
+    Field::new(name, TypeRef::named_nn(ty), move |ctx| {
+        FieldFuture::new(async move {
+            let row = ctx.parent_value.try_downcast_ref::<RowValue>()?;
+            Ok(Some(FieldValue::value(get(row))))
+        })
+    })
+}
+
+/// An input with an optional field per column, used for both filters & row data.
+fn input_type(table: &TableType, name: &str) -> InputObject {
+    table
+        .columns
+        .iter()
+        .fold(InputObject::new(name), |input, column| {
+            input.field(InputValue::new(&column.field, TypeRef::named(column.ty)))
+        })
+}
+
+fn list_field(table: &TableType) -> Field {
+    let hash = table.hash;
+    let columns = table.columns.clone();
+    Field::new(
+        &table.field,
+        TypeRef::named_nn_list_nn(&table.name),
+        move |ctx| {
+            let columns = columns.clone();
+            FieldFuture::new(async move {
+                let space = ctx.data::<Space>()?;
+                let filter = match ctx.args.get("where") {
+                    Some(filter) => properties(&columns, filter.as_value().clone().into_json()?),
+                    None => Map::new(),
+                };
+                let offset = int_arg(&ctx, "offset")?.unwrap_or(0).max(0);
+                let limit = int_arg(&ctx, "limit")?.unwrap_or(DEFAULT_LIMIT).max(0);
+                let query = RowQuery {
+                    select: Vec::new(),
+                    filters: filter
+                        .into_iter()
+                        .map(|(field, value)| Filter {
+                            field,
+                            op: Op::Eq,
+                            value,
+                        })
+                        .collect(),
+                    // newest first
+                    order: vec![Order {
+                        field: UPDATED_AT_FIELD.to_string(),
+                        descending: true,
+                    }],
+                };
+                let mut rows = Vec::new();
+                for row in space.rows().query_with(hash, &query, offset, limit).await? {
+                    rows.push(FieldValue::owned_any(row_value(space, row).await?));
+                }
+                Ok(Some(FieldValue::list(rows)))
+            })
+        },
+    )
+    .argument(InputValue::new(
+        "where",
+        TypeRef::named(format!("{}Filter", table.name)),
+    ))
+    .argument(InputValue::new("offset", TypeRef::named(TypeRef::INT)))
+    .argument(InputValue::new("limit", TypeRef::named(TypeRef::INT)))
+}
+
+fn get_field(table: &TableType) -> Field {
+    let hash = table.hash;
+    Field::new(
+        format!("{}ById", table.field),
+        TypeRef::named(&table.name),
+        move |ctx| {
+            FieldFuture::new(async move {
+                let space = ctx.data::<Space>()?;
+                let id = id_arg(&ctx)?;
+                match space.rows().get(hash, id).await? {
+                    Some(row) => Ok(Some(FieldValue::owned_any(row_value(space, row).await?))),
+                    None => Ok(None),
+                }
+            })
+        },
+    )
+    .argument(InputValue::new("id", TypeRef::named_nn(TypeRef::ID)))
+}
+
+fn insert_field(table: &TableType) -> Field {
+    let hash = table.hash;
+    let columns = table.columns.clone();
+    Field::new(
+        format!("insert{}", table.name),
+        TypeRef::named_nn(&table.name),
+        move |ctx| {
+            let columns = columns.clone();
+            FieldFuture::new(async move {
+                let space = ctx.data::<Space>()?;
+                let author = ctx.data::<Author>()?;
+                let mut data = properties(&columns, data_arg(&ctx)?);
+                data.retain(|_, value| !value.is_null());
+                let row = space
+                    .rows()
+                    .create(author.clone(), hash, Value::Object(data))
+                    .await?;
+                Ok(Some(FieldValue::owned_any(row_value(space, row).await?)))
+            })
+        },
+    )
+    .argument(InputValue::new(
+        "data",
+        TypeRef::named_nn(format!("{}Input", table.name)),
+    ))
+}
+
+/// Updates merge into the latest version of the row, null removes a field.
+fn update_field(table: &TableType) -> Field {
+    let hash = table.hash;
+    let columns = table.columns.clone();
+    Field::new(
+        format!("update{}", table.name),
+        TypeRef::named_nn(&table.name),
+        move |ctx| {
+            let columns = columns.clone();
+            FieldFuture::new(async move {
+                let space = ctx.data::<Space>()?;
+                let author = ctx.data::<Author>()?;
+                let id = id_arg(&ctx)?;
+                let current = space
+                    .rows()
+                    .get(hash, id)
+                    .await?
+                    .with_context(|| format!("row {id} not found"))?;
+                let mut data = match row_value(space, current).await?.data {
+                    Value::Object(data) => data,
+                    _ => Map::new(),
+                };
+                for (property, value) in properties(&columns, data_arg(&ctx)?) {
+                    if value.is_null() {
+                        data.remove(&property);
+                    } else {
+                        data.insert(property, value);
+                    }
+                }
+                let row = space
+                    .rows()
+                    .mutate(author.clone(), hash, id, Value::Object(data))
+                    .await?;
+                Ok(Some(FieldValue::owned_any(row_value(space, row).await?)))
+            })
+        },
+    )
+    .argument(InputValue::new("id", TypeRef::named_nn(TypeRef::ID)))
+    .argument(InputValue::new(
+        "data",
+        TypeRef::named_nn(format!("{}Input", table.name)),
+    ))
+}
+
+fn int_arg(ctx: &ResolverContext, name: &str) -> async_graphql::Result<Option<i64>> {
+    ctx.args.get(name).map(|value| value.i64()).transpose()
+}
+
+fn id_arg(ctx: &ResolverContext) -> async_graphql::Result<Uuid> {
+    Ok(ctx.args.try_get("id")?.string()?.parse()?)
+}
+
+fn data_arg(ctx: &ResolverContext) -> async_graphql::Result<Value> {
+    Ok(ctx.args.try_get("data")?.as_value().clone().into_json()?)
+}
+
+/// Translate an input object keyed by graphql field names to row properties.
+fn properties(columns: &[Column], input: Value) -> Map<String, Value> {
+    let Value::Object(input) = input else {
+        return Map::new();
+    };
+    input
+        .into_iter()
+        .filter_map(|(field, value)| {
+            let column = columns.iter().find(|column| column.field == field)?;
+            Some((column.property.clone(), value))
+        })
+        .collect()
+}
+
+async fn row_value(space: &Space, mut row: crate::space::rows::Row) -> Result<RowValue> {
+    let data = row.content.resolve(space.router()).await?;
+    Ok(RowValue {
+        id: row.id,
+        author: row.author.to_string(),
+        created_at: row.created_at,
+        data,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn names() {
+        assert_eq!(type_name("github stargazers"), "GithubStargazers");
+        assert_eq!(type_name("2024-sales"), "T2024Sales");
+        assert_eq!(type_name("!!"), "Table");
+        assert_eq!(lower_first("GithubStargazers"), "githubStargazers");
+        assert_eq!(field_name("first-name"), "first_name");
+        assert_eq!(field_name("1st"), "_1st");
+    }
+
+    #[test]
+    fn column_types() {
+        let schema = json!({
+            "properties": {
+                "login": { "type": "string" },
+                "stars": { "type": ["integer", "null"] },
+                "score": { "type": "number" },
+                "admin": { "type": "boolean" },
+                "meta": { "type": "object" },
+                "_id": { "type": "string" },
+            }
+        });
+        let columns = columns(&schema);
+        let ty = |field: &str| columns.iter().find(|c| c.field == field).map(|c| c.ty);
+        assert_eq!(ty("login"), Some(TypeRef::STRING));
+        assert_eq!(ty("stars"), Some(TypeRef::INT));
+        assert_eq!(ty("score"), Some(TypeRef::FLOAT));
+        assert_eq!(ty("admin"), Some(TypeRef::BOOLEAN));
+        assert_eq!(ty("meta"), Some(JSON_SCALAR));
+        // collides with row metadata
+        assert_eq!(columns.len(), 5);
+    }
+
+    #[tokio::test]
+    async fn queries_and_mutates_rows() -> Result<()> {
+        let node = iroh::node::Node::memory().enable_docs().spawn().await?;
+        let dir = tempfile::tempdir()?;
+        let router = node.client().clone();
+        let mut spaces = Spaces::open_all(router.clone(), dir.path()).await?;
+        let author = Author::new(&mut rand::thread_rng());
+        router.authors().import(author.clone()).await?;
+        let space = spaces
+            .create(&router, author.clone(), "graphql", "a space")
+            .await?;
+        let schema = json!({
+            "title": "github stargazers",
+            "type": "object",
+            "properties": {
+                "login": { "type": "string" },
+                "stars": { "type": "integer" },
+            },
+        });
+        space
+            .tables()
+            .create(author.clone(), Bytes::from(schema.to_string()))
+            .await?;
+
+        let schema = super::schema(&space, author).await?;
+        let execute = |query: &'static str| {
+            let schema = schema.clone();
+            async move {
+                let res = schema.execute(query).await;
+                assert!(res.errors.is_empty(), "{:?}", res.errors);
+                res.data.into_json()
+            }
+        };
+
+        let inserted = execute(
+            r#"mutation {
+                insertGithubStargazers(data: { login: "b5", stars: 3 }) { _id login stars }
+            }"#,
+        )
+        .await?;
+        assert_eq!(inserted["insertGithubStargazers"]["stars"], json!(3));
+        execute(r#"mutation { insertGithubStargazers(data: { login: "dig" }) { _id } }"#).await?;
+
+        let found =
+            execute(r#"{ githubStargazers(where: { login: "b5" }) { _id stars } }"#).await?;
+        let rows = found["githubStargazers"].as_array().unwrap();
+        assert_eq!(rows.len(), 1);
+        let id = rows[0]["_id"].as_str().unwrap().to_string();
+
+        let update = format!(
+            r#"mutation {{
+                updateGithubStargazers(id: "{id}", data: {{ stars: 4 }}) {{ login stars }}
+            }}"#
+        );
+        let res = schema.execute(update.as_str()).await;
+        assert!(res.errors.is_empty(), "{:?}", res.errors);
+        let updated = res.data.into_json()?;
+        assert_eq!(
+            updated["updateGithubStargazers"],
+            json!({ "login": "b5", "stars": 4 })
+        );
+
+        let all = execute("{ githubStargazers { login } _tables }").await?;
+        assert_eq!(all["githubStargazers"].as_array().unwrap().len(), 2);
+        assert_eq!(all["_tables"], json!(["GithubStargazers"]));
+        // newest first, b5 was updated last
+        let paged = execute("{ githubStargazers(offset: 1, limit: 1) { login } }").await?;
+        assert_eq!(paged["githubStargazers"], json!([{ "login": "dig" }]));
+        let by_id = format!(r#"{{ githubStargazersById(id: "{id}") {{ stars }} }}"#);
+        let res = schema.execute(by_id.as_str()).await;
+        assert_eq!(
+            res.data.into_json()?["githubStargazersById"]["stars"],
+            json!(4)
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,266 @@
+//! Minimal CIDv1 support for moving blobs between squiggle & IPFS.
+//!
+//! Only the `raw` codec is supported: a CID of a single block of bytes. Every blob can be named
+//! with a blake3 CID for free, since iroh blob hashes are blake3. Most IPFS tooling expects
+//! sha2-256, which requires reading the blob.
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, ensure, Result};
+use iroh::blobs::Hash;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+const CID_VERSION: u8 = 0x01;
+const CODEC_RAW: u8 = 0x55;
+const MULTIBASE_BASE32: char = 'b';
+const DIGEST_LEN: u8 = 32;
+
+/// Gateways aren't required to serve raw blocks larger than this.
+pub const MAX_RAW_BLOCK_SIZE: usize = 1024 * 1024;
+
+pub const DEFAULT_GATEWAY: &str = "https://trustless-gateway.link";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+#[serde(rename_all = "camelCase")]
+pub enum Multihash {
+    Sha256,
+    Blake3,
+}
+
+impl Multihash {
+    fn code(&self) -> u8 {
+        match self {
+            Multihash::Sha256 => 0x12,
+            Multihash::Blake3 => 0x1e,
+        }
+    }
+
+    fn from_code(code: u8) -> Result<Self> {
+        match code {
+            0x12 => Ok(Multihash::Sha256),
+            0x1e => Ok(Multihash::Blake3),
+            _ => bail!("unsupported multihash code {code:#x}"),
+        }
+    }
+
+    fn digest(&self, data: &[u8]) -> [u8; 32] {
+        match self {
+            Multihash::Sha256 => Sha256::digest(data).into(),
+            Multihash::Blake3 => *Hash::new(data).as_bytes(),
+        }
+    }
+}
+
+/// A CIDv1 with the raw codec.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cid {
+    pub multihash: Multihash,
+    pub digest: [u8; 32],
+}
+
+impl Cid {
+    pub fn for_data(multihash: Multihash, data: &[u8]) -> Self {
+        Cid {
+            multihash,
+            digest: multihash.digest(data),
+        }
+    }
+
+    /// The blake3 CID of a blob, no data required.
+    pub fn from_hash(hash: Hash) -> Self {
+        Cid {
+            multihash: Multihash::Blake3,
+            digest: *hash.as_bytes(),
+        }
+    }
+
+    /// Check that `data` is the content this CID names.
+    pub fn verify(&self, data: &[u8]) -> Result<()> {
+        ensure!(
+            self.multihash.digest(data) == self.digest,
+            "content does not match {self}"
+        );
+        Ok(())
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![CID_VERSION, CODEC_RAW, self.multihash.code(), DIGEST_LEN];
+        bytes.extend_from_slice(&self.digest);
+        bytes
+    }
+}
+
+impl fmt::Display for Cid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{MULTIBASE_BASE32}{}", base32_encode(&self.to_bytes()))
+    }
+}
+
+impl FromStr for Cid {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let encoded = s
+            .strip_prefix(MULTIBASE_BASE32)
+            .ok_or_else(|| anyhow!("only base32 CIDv1 strings are supported"))?;
+        let bytes = base32_decode(encoded)?;
+        match bytes.as_slice() {
+            [CID_VERSION, CODEC_RAW, code, DIGEST_LEN, digest @ ..] if digest.len() == 32 => {
+                Ok(Cid {
+                    multihash: Multihash::from_code(*code)?,
+                    digest: digest.try_into()?,
+                })
+            }
+            [CID_VERSION, CODEC_RAW, ..] => bail!("invalid multihash in {s}"),
+            [CID_VERSION, codec, ..] => {
+                bail!("unsupported codec {codec:#x}, only raw CIDs are supported")
+            }
+            _ => bail!("{s} is not a CIDv1"),
+        }
+    }
+}
+
+impl Serialize for Cid {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+#[cfg(feature = "specta")]
+impl specta::Type for Cid {
+    fn inline(type_map: &mut specta::TypeMap, generics: specta::Generics) -> specta::DataType {
+        String::inline(type_map, generics)
+    }
+}
+
+impl<'de> Deserialize<'de> for Cid {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        Cid::from_str(&s).map_err(serde::de::Error::custom)
+    }
+}
+
+/// Fetch a raw block from a trustless HTTP gateway, verifying it against the CID.
+pub async fn fetch_from_gateway(gateway: &str, cid: &Cid) -> Result<Vec<u8>> {
+    let url = format!("{}/ipfs/{cid}?format=raw", gateway.trim_end_matches('/'));
+    let mut res = reqwest::Client::new()
+        .get(&url)
+        .header(reqwest::header::ACCEPT, "application/vnd.ipld.raw")
+        .send()
+        .await?
+        .error_for_status()?;
+    // stop reading as soon as the block is too large, whatever the gateway sends
+    let mut data = Vec::new();
+    while let Some(chunk) = res.chunk().await? {
+        data.extend_from_slice(&chunk);
+        ensure!(
+            data.len() <= MAX_RAW_BLOCK_SIZE,
+            "gateway returned an oversized block for {cid}"
+        );
+    }
+    cid.verify(&data)?;
+    Ok(data)
+}
+
+const BASE32_ALPHABET: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+
+/// RFC4648 base32, lowercase & unpadded as multibase expects.
+fn base32_encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity((data.len() * 8 + 4) / 5);
+    let mut buffer: u16 = 0;
+    let mut bits = 0;
+    for byte in data {
+        buffer = (buffer << 8) | *byte as u16;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(BASE32_ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        out.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    out
+}
+
+fn base32_decode(s: &str) -> Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(s.len() * 5 / 8);
+    let mut buffer: u16 = 0;
+    let mut bits = 0;
+    for c in s.bytes() {
+        let value = BASE32_ALPHABET
+            .iter()
+            .position(|a| *a == c.to_ascii_lowercase())
+            .ok_or_else(|| anyhow!("invalid base32 character {:?}", c as char))?;
+        buffer = (buffer << 5) | value as u16;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_block_cid() {
+        let cid = Cid::for_data(Multihash::Sha256, b"");
+        assert_eq!(
+            cid.to_string(),
+            "bafkreihdwdcefgh4dqkjv67uzcmw7ojee6xedzdetojuzjevtenxquvyku"
+        );
+    }
+
+    #[test]
+    fn roundtrip() -> Result<()> {
+        let data = b"hello squiggle";
+        for multihash in [Multihash::Sha256, Multihash::Blake3] {
+            let cid = Cid::for_data(multihash, data);
+            let parsed = Cid::from_str(&cid.to_string())?;
+            assert_eq!(cid, parsed);
+            parsed.verify(data)?;
+            assert!(parsed.verify(b"something else").is_err());
+        }
+
+        let hash = Hash::new(data);
+        assert_eq!(Cid::from_hash(hash), Cid::for_data(Multihash::Blake3, data));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn gateway_blocks_are_bounded() -> Result<()> {
+        use axum::extract::Path;
+
+        let block = b"a small block";
+        let cid = Cid::for_data(Multihash::Sha256, block);
+        let expected = cid.to_string();
+        let app = axum::Router::new().route(
+            "/ipfs/:cid",
+            axum::routing::get(move |Path(requested): Path<String>| async move {
+                if requested == expected {
+                    return axum::body::Body::from(&block[..]);
+                }
+                // a gateway that never stops sending
+                let chunk = bytes::Bytes::from_static(&[0u8; 8192]);
+                axum::body::Body::from_stream(futures::stream::repeat(Ok::<_, std::io::Error>(
+                    chunk,
+                )))
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let gateway = format!("http://{}", listener.local_addr()?);
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        assert_eq!(fetch_from_gateway(&gateway, &cid).await?, block);
+        let endless = Cid::for_data(Multihash::Sha256, b"anything else");
+        let err = fetch_from_gateway(&gateway, &endless).await.unwrap_err();
+        assert!(err.to_string().contains("oversized block"));
+        Ok(())
+    }
+}
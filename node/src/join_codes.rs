@@ -0,0 +1,539 @@
+//! Short, speakable codes that stand in for workspace tickets.
+//!
+//! Tickets are far too long to read out loud. A rendezvous service holds a ticket for a few
+//! minutes under a code like `7-amber-falcon-otter-lemon-quill-bison-harbor`: one machine
+//! publishes its ticket & reads the code to the other, which trades the code back for the
+//! ticket. Codes work once and expire, so a code that's overheard later is worthless. Tickets
+//! grant write access, so codes carry 66 bits, too many to guess, & failed lookups are limited
+//! per client network & overall on top. The service only speaks TLS unless it's bound to
+//! loopback, where a TLS proxy is expected in front of it.
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Result};
+use axum::extract::{ConnectInfo, Path};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Extension, Json, Router};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+use crate::admin::{serve_tls, tls_acceptor, AdminTls};
+use crate::DocTicket;
+
+/// how long a code lasts when the publisher doesn't say
+pub const DEFAULT_CODE_TTL: Duration = Duration::from_secs(10 * 60);
+const MAX_CODE_TTL: Duration = Duration::from_secs(60 * 60);
+/// codes waiting to be claimed, across all publishers
+const MAX_PENDING_CODES: usize = 10_000;
+const MAX_TICKET_BYTES: usize = 8 * 1024;
+/// lookups of unknown codes a client network may make per [`MISS_WINDOW`]
+const MAX_MISSES: u32 = 20;
+/// lookups of unknown codes all clients together may make per [`MISS_WINDOW`]
+const MAX_TOTAL_MISSES: u32 = 1_000;
+const MISS_WINDOW: Duration = Duration::from_secs(60);
+const MAX_CODE_NUMBER: u16 = 999;
+/// words after the number, 8 bits each
+const CODE_WORDS: usize = 7;
+
+const WORDS: [&str; 256] = [
+    "acid", "acorn", "actor", "agent", "alarm", "album", "alpha", "amber", "anchor", "angle",
+    "apple", "apron", "arena", "arrow", "aspen", "atlas", "attic", "audio", "autumn", "avocado",
+    "badge", "bagel", "baker", "bamboo", "banjo", "barn", "basil", "basket", "bayou", "beacon",
+    "beaver", "bench", "berry", "bison", "blade", "blanket", "blossom", "bongo", "border",
+    "bottle", "bounce", "bramble", "brave", "breeze", "brick", "bridge", "bronze", "brook",
+    "bubble", "bucket", "buffalo", "bugle", "cabin", "cactus", "camel", "candle", "canoe",
+    "canyon", "carbon", "cargo", "carpet", "castle", "cedar", "cello", "chalk", "cherry", "chess",
+    "cider", "circus", "citrus", "clover", "cobalt", "cobra", "cocoa", "comet", "coral", "cotton",
+    "cougar", "crane", "crayon", "cricket", "crystal", "cupcake", "dahlia", "daisy", "delta",
+    "desert", "diesel", "dingo", "dolphin", "dragon", "drum", "dune", "eagle", "echo", "eclipse",
+    "elbow", "ember", "engine", "falcon", "fennel", "ferry", "fiddle", "fig", "fjord", "flannel",
+    "fossil", "fox", "galaxy", "garden", "garlic", "gecko", "ginger", "glacier", "goblet",
+    "gopher", "granite", "gravel", "guitar", "hammer", "harbor", "harvest", "hatch", "hazel",
+    "helmet", "heron", "hickory", "honey", "hornet", "igloo", "indigo", "island", "ivory",
+    "jacket", "jaguar", "jasmine", "jelly", "jewel", "jigsaw", "jungle", "kayak", "kernel",
+    "kettle", "kite", "kiwi", "koala", "ladder", "lagoon", "lantern", "laser", "lemon", "lentil",
+    "lilac", "linen", "lizard", "lobster", "locket", "lotus", "magnet", "mango", "maple", "marble",
+    "meadow", "melon", "meteor", "mint", "mirror", "mocha", "moose", "mosaic", "muffin", "nectar",
+    "needle", "nickel", "noodle", "nutmeg", "oasis", "ocean", "olive", "onion", "opal", "orbit",
+    "orchid", "otter", "owl", "paddle", "panda", "paper", "parrot", "peanut", "pebble", "pepper",
+    "piano", "pickle", "pilot", "pine", "planet", "plum", "pollen", "pony", "poppy", "puzzle",
+    "quartz", "quill", "rabbit", "radar", "radish", "raven", "reef", "ribbon", "river", "robin",
+    "rocket", "saddle", "saffron", "salmon", "sandal", "scarf", "shadow", "shell", "sierra",
+    "silver", "sketch", "sparrow", "spruce", "squash", "stone", "sugar", "summit", "sunset",
+    "swan", "tango", "teapot", "thistle", "thunder", "tiger", "timber", "tomato", "topaz", "tulip",
+    "tundra", "turtle", "umber", "valley", "velvet", "violet", "walnut", "walrus", "willow",
+    "window", "winter", "wizard", "yogurt", "zebra", "zephyr", "zinc",
+];
+
+/// A code of the form `<number>-<word>-…`, with [`CODE_WORDS`] words.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct JoinCode(String);
+
+impl JoinCode {
+    pub fn generate() -> Self {
+        let mut rng = rand::thread_rng();
+        let mut parts = vec![rng.gen_range(1..=MAX_CODE_NUMBER).to_string()];
+        parts.extend((0..CODE_WORDS).map(|_| WORDS[rng.gen_range(0..WORDS.len())].to_string()));
+        JoinCode(parts.join("-"))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for JoinCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Codes are forgiving of how they were typed: case, spaces instead of dashes & stray
+/// whitespace don't matter.
+impl FromStr for JoinCode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let normalized = s.trim().to_lowercase();
+        let parts: Vec<&str> = normalized
+            .split(|c: char| c == '-' || c.is_whitespace())
+            .filter(|part| !part.is_empty())
+            .collect();
+        let Some((number, words)) = parts.split_first() else {
+            bail!("join codes look like 7-amber-falcon-…");
+        };
+        if words.len() != CODE_WORDS {
+            bail!("join codes have a number & {CODE_WORDS} words");
+        }
+        let number: u16 = number
+            .parse()
+            .ok()
+            .filter(|n| (1..=MAX_CODE_NUMBER).contains(n))
+            .ok_or_else(|| anyhow!("join codes start with a number from 1 to {MAX_CODE_NUMBER}"))?;
+        for word in words {
+            if WORDS.binary_search(word).is_err() {
+                bail!("{word:?} isn't a join code word");
+            }
+        }
+        Ok(JoinCode(format!("{number}-{}", words.join("-"))))
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PublishRequest {
+    ticket: String,
+    ttl_secs: Option<u64>,
+}
+
+/// A ticket waiting under a code.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PublishedCode {
+    pub code: String,
+    /// unix seconds
+    pub expires_at: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ResolvedCode {
+    ticket: String,
+}
+
+#[derive(Debug)]
+struct Pending {
+    ticket: String,
+    expires_at: i64,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    codes: HashMap<JoinCode, Pending>,
+    /// failed lookups per client network: when the window started & misses in it
+    misses: HashMap<IpAddr, (i64, u32)>,
+    /// failed lookups of all clients
+    total_misses: (i64, u32),
+}
+
+impl State {
+    fn expire(&mut self, now: i64) {
+        self.codes.retain(|_, pending| pending.expires_at > now);
+        let window = MISS_WINDOW.as_secs() as i64;
+        self.misses.retain(|_, (start, _)| now - *start < window);
+        if now - self.total_misses.0 >= window {
+            self.total_misses = (now, 0);
+        }
+    }
+}
+
+/// The network a client's misses count against. IPv6 clients usually get a whole /64 to pick
+/// addresses from.
+fn client_network(client: IpAddr) -> IpAddr {
+    match client {
+        IpAddr::V4(_) => client,
+        IpAddr::V6(addr) => {
+            let segments = addr.segments();
+            IpAddr::V6(std::net::Ipv6Addr::new(
+                segments[0],
+                segments[1],
+                segments[2],
+                segments[3],
+                0,
+                0,
+                0,
+                0,
+            ))
+        }
+    }
+}
+
+/// The rendezvous service. It only ever holds tickets in memory.
+#[derive(Debug, Clone, Default)]
+pub struct Rendezvous {
+    state: Arc<Mutex<State>>,
+}
+
+impl Rendezvous {
+    pub fn router(&self) -> Router {
+        Router::new()
+            .route("/codes", post(publish))
+            .route("/codes/:code", get(resolve))
+            .layer(Extension(self.clone()))
+    }
+
+    /// Serve on `addr`, over TLS with `tls`. Without TLS `addr` has to be loopback.
+    pub async fn serve(self, addr: &str, tls: Option<RendezvousTls>) -> Result<JoinHandle<()>> {
+        let listener = tokio::net::TcpListener::bind(addr)
+            .await
+            .with_context(|| format!("binding rendezvous to {addr}"))?;
+        let local = listener.local_addr()?;
+        let tls = tls
+            .map(|tls| {
+                tls_acceptor(&AdminTls {
+                    cert: tls.cert,
+                    key: tls.key,
+                    client_ca: None,
+                })
+            })
+            .transpose()?;
+        if tls.is_none() && !local.ip().is_loopback() {
+            bail!("serving rendezvous on {local} needs a TLS certificate & key");
+        }
+        info!("rendezvous listening on {}", local);
+        let router = self.router();
+        Ok(tokio::spawn(async move {
+            let res = match tls {
+                Some(tls) => serve_tls(listener, tls, router).await,
+                None => {
+                    let app = router.into_make_service_with_connect_info::<SocketAddr>();
+                    axum::serve(listener, app).await.map_err(Into::into)
+                }
+            };
+            if let Err(err) = res {
+                tracing::error!("rendezvous stopped: {:?}", err);
+            }
+        }))
+    }
+
+    fn publish(&self, ticket: String, ttl: Duration) -> Result<PublishedCode, Rejection> {
+        if ticket.is_empty() || ticket.len() > MAX_TICKET_BYTES {
+            return Err(Rejection::BadRequest("tickets must be 1-8KiB"));
+        }
+        let now = chrono::Utc::now().timestamp();
+        let expires_at = now + ttl.min(MAX_CODE_TTL).as_secs() as i64;
+        let mut state = self.state.lock().unwrap();
+        state.expire(now);
+        if state.codes.len() >= MAX_PENDING_CODES {
+            return Err(Rejection::Busy);
+        }
+        let code = loop {
+            let code = JoinCode::generate();
+            if !state.codes.contains_key(&code) {
+                break code;
+            }
+        };
+        state
+            .codes
+            .insert(code.clone(), Pending { ticket, expires_at });
+        debug!("published code {}", code);
+        Ok(PublishedCode {
+            code: code.to_string(),
+            expires_at,
+        })
+    }
+
+    /// Claim the ticket behind a code, the code is gone afterwards.
+    fn resolve(&self, client: IpAddr, code: &str) -> Result<String, Rejection> {
+        let now = chrono::Utc::now().timestamp();
+        let network = client_network(client);
+        let mut state = self.state.lock().unwrap();
+        state.expire(now);
+        if matches!(state.misses.get(&network), Some((_, misses)) if *misses >= MAX_MISSES)
+            || state.total_misses.1 >= MAX_TOTAL_MISSES
+        {
+            return Err(Rejection::TooManyMisses);
+        }
+        let pending = JoinCode::from_str(code)
+            .ok()
+            .and_then(|code| state.codes.remove(&code));
+        match pending {
+            Some(pending) => Ok(pending.ticket),
+            None => {
+                state.misses.entry(network).or_insert((now, 0)).1 += 1;
+                state.total_misses.1 += 1;
+                if state.total_misses.1 == MAX_TOTAL_MISSES {
+                    warn!("too many unknown codes looked up, refusing lookups for a while");
+                }
+                Err(Rejection::NotFound)
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Rejection {
+    BadRequest(&'static str),
+    NotFound,
+    TooManyMisses,
+    Busy,
+}
+
+impl IntoResponse for Rejection {
+    fn into_response(self) -> Response {
+        match self {
+            Rejection::BadRequest(msg) => (StatusCode::BAD_REQUEST, msg).into_response(),
+            Rejection::NotFound => {
+                (StatusCode::NOT_FOUND, "unknown or expired code").into_response()
+            }
+            Rejection::TooManyMisses => {
+                (StatusCode::TOO_MANY_REQUESTS, "too many unknown codes").into_response()
+            }
+            Rejection::Busy => {
+                (StatusCode::SERVICE_UNAVAILABLE, "too many pending codes").into_response()
+            }
+        }
+    }
+}
+
+async fn publish(
+    Extension(rendezvous): Extension<Rendezvous>,
+    Json(req): Json<PublishRequest>,
+) -> Result<Json<PublishedCode>, Rejection> {
+    let ttl = req
+        .ttl_secs
+        .map(Duration::from_secs)
+        .unwrap_or(DEFAULT_CODE_TTL);
+    rendezvous.publish(req.ticket, ttl).map(Json)
+}
+
+async fn resolve(
+    Extension(rendezvous): Extension<Rendezvous>,
+    ConnectInfo(client): ConnectInfo<SocketAddr>,
+    Path(code): Path<String>,
+) -> Result<Json<ResolvedCode>, Rejection> {
+    let ticket = rendezvous.resolve(client.ip(), &code)?;
+    Ok(Json(ResolvedCode { ticket }))
+}
+
+/// Certificate & key a rendezvous service serves TLS with.
+#[derive(Debug, Clone)]
+pub struct RendezvousTls {
+    /// PEM certificate chain
+    pub cert: PathBuf,
+    /// PEM private key
+    pub key: PathBuf,
+}
+
+/// Talks to a rendezvous service.
+#[derive(Debug, Clone)]
+pub struct RendezvousClient {
+    url: url::Url,
+    client: reqwest::Client,
+}
+
+impl RendezvousClient {
+    /// Tickets only travel over https, or plain http to a service on this machine.
+    pub fn new(url: &str) -> Result<Self> {
+        let url = url::Url::parse(url).with_context(|| format!("invalid rendezvous url {url}"))?;
+        let loopback = match url.host() {
+            Some(url::Host::Domain(domain)) => domain == "localhost",
+            Some(url::Host::Ipv4(ip)) => ip.is_loopback(),
+            Some(url::Host::Ipv6(ip)) => ip.is_loopback(),
+            None => false,
+        };
+        if url.scheme() != "https" && !(url.scheme() == "http" && loopback) {
+            bail!("rendezvous urls have to be https, got {url}");
+        }
+        Ok(RendezvousClient {
+            url,
+            client: reqwest::Client::new(),
+        })
+    }
+
+    /// Leave a ticket with the service, returning the code to read out.
+    pub async fn publish(&self, ticket: &str, ttl: Duration) -> Result<PublishedCode> {
+        let res = self
+            .client
+            .post(self.url.join("codes")?)
+            .json(&PublishRequest {
+                ticket: ticket.to_string(),
+                ttl_secs: Some(ttl.as_secs()),
+            })
+            .send()
+            .await?;
+        if !res.status().is_success() {
+            bail!("publishing code: {} {}", res.status(), res.text().await?);
+        }
+        Ok(res.json().await?)
+    }
+
+    /// Trade a code for the ticket behind it.
+    pub async fn resolve(&self, code: &JoinCode) -> Result<String> {
+        let res = self
+            .client
+            .get(self.url.join(&format!("codes/{code}"))?)
+            .send()
+            .await?;
+        if !res.status().is_success() {
+            bail!(
+                "resolving code {}: {} {}",
+                code,
+                res.status(),
+                res.text().await?
+            );
+        }
+        let resolved: ResolvedCode = res.json().await?;
+        Ok(resolved.ticket)
+    }
+
+    /// Trade a code someone read out for the workspace ticket behind it.
+    pub async fn resolve_ticket(&self, code: &str) -> Result<DocTicket> {
+        let code = JoinCode::from_str(code)?;
+        let ticket = self.resolve(&code).await?;
+        DocTicket::from_str(&ticket).context("rendezvous returned a bad ticket")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_spoken_codes() -> Result<()> {
+        let code = JoinCode::from_str(" 7 Amber  falcon otter lemon quill-bison harbor ")?;
+        assert_eq!(
+            code.as_str(),
+            "7-amber-falcon-otter-lemon-quill-bison-harbor"
+        );
+        assert_eq!(
+            JoinCode::from_str("7-amber-falcon-otter-lemon-quill-bison-harbor")?,
+            code
+        );
+        assert!(JoinCode::from_str("0-amber-falcon-otter-lemon-quill-bison-harbor").is_err());
+        assert!(JoinCode::from_str("7-amber-falcon-otter-lemon-quill-bison-spaceship").is_err());
+        assert!(JoinCode::from_str("7-amber-falcon").is_err());
+
+        let generated = JoinCode::generate();
+        assert_eq!(JoinCode::from_str(generated.as_str())?, generated);
+        assert!(WORDS.windows(2).all(|pair| pair[0] < pair[1]));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn codes_work_once() -> Result<()> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let url = format!("http://{}/", listener.local_addr()?);
+        let app = Rendezvous::default()
+            .router()
+            .into_make_service_with_connect_info::<SocketAddr>();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let client = RendezvousClient::new(&url)?;
+        let published = client.publish("docaaa", DEFAULT_CODE_TTL).await?;
+        // read out loud & typed back in
+        let spoken = JoinCode::from_str(&published.code.replace('-', " ").to_uppercase())?;
+        assert_eq!(client.resolve(&spoken).await?, "docaaa");
+        assert!(client.resolve(&spoken).await.is_err());
+
+        // guessing gets cut off
+        for _ in 0..MAX_MISSES {
+            let _ = client.resolve(&JoinCode::generate()).await;
+        }
+        let published = client.publish("docbbb", DEFAULT_CODE_TTL).await?;
+        let err = client
+            .resolve(&JoinCode::from_str(&published.code)?)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("429"), "{err}");
+        Ok(())
+    }
+
+    #[test]
+    fn misses_count_per_network_and_overall() {
+        let rendezvous = Rendezvous::default();
+        // rotating through a /64 doesn't get around the limit
+        for host in 0..MAX_MISSES {
+            let client = IpAddr::V6(std::net::Ipv6Addr::new(
+                0x2001,
+                0xdb8,
+                0,
+                1,
+                0,
+                0,
+                0,
+                host as u16,
+            ));
+            assert!(matches!(
+                rendezvous.resolve(client, "1-acid-acid-acid-acid-acid-acid-acid"),
+                Err(Rejection::NotFound)
+            ));
+        }
+        let neighbour = IpAddr::V6(std::net::Ipv6Addr::new(
+            0x2001, 0xdb8, 0, 1, 0, 0, 0, 0xffff,
+        ));
+        assert!(matches!(
+            rendezvous.resolve(neighbour, "1-acid-acid-acid-acid-acid-acid-acid"),
+            Err(Rejection::TooManyMisses)
+        ));
+
+        // nor does spreading guesses over many clients
+        for client in 0..MAX_TOTAL_MISSES {
+            let client = IpAddr::V4(std::net::Ipv4Addr::from(0x0a00_0000 + client));
+            let _ = rendezvous.resolve(client, "1-acid-acid-acid-acid-acid-acid-acid");
+        }
+        let published = rendezvous
+            .publish("docaaa".to_string(), DEFAULT_CODE_TTL)
+            .unwrap();
+        let fresh = IpAddr::V4(std::net::Ipv4Addr::new(192, 0, 2, 1));
+        assert!(matches!(
+            rendezvous.resolve(fresh, &published.code),
+            Err(Rejection::TooManyMisses)
+        ));
+    }
+
+    #[test]
+    fn requires_tls_beyond_loopback() {
+        assert!(RendezvousClient::new("https://rendezvous.example.com/").is_ok());
+        assert!(RendezvousClient::new("http://127.0.0.1:8082/").is_ok());
+        assert!(RendezvousClient::new("http://localhost:8082/").is_ok());
+        assert!(RendezvousClient::new("http://rendezvous.example.com/").is_err());
+    }
+
+    #[tokio::test]
+    async fn serving_beyond_loopback_needs_tls() {
+        let err = Rendezvous::default()
+            .serve("0.0.0.0:0", None)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("TLS"), "{err}");
+    }
+}
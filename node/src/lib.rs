@@ -1,7 +1,28 @@
+pub mod admin;
+pub mod aliases;
+pub mod blob_store;
+pub mod bootstrap;
+pub mod disk;
+pub mod doctor;
+mod error;
 mod gateway;
+#[cfg(feature = "graphql")]
+pub mod graphql;
+pub mod ipfs;
+pub mod join_codes;
 pub mod node;
+pub mod peers;
 pub(crate) mod router;
+pub mod sdk;
+pub mod serve;
 pub mod space;
+pub mod storage;
 pub mod vm;
+pub mod worker_node;
 
+pub use error::Error;
 pub use iroh::blobs::Hash;
+pub use iroh::docs::DocTicket;
+pub use iroh::net::key::PublicKey;
+pub use iroh::net::relay::RelayUrl;
+pub use iroh::net::{NodeAddr, NodeId};
@@ -1,15 +1,380 @@
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use anyhow::Result;
+use anyhow::{bail, Result};
+use clap::{Parser, Subcommand};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, reload, EnvFilter};
 
+use squiggle_node::doctor::{CheckStatus, DoctorReport};
+use squiggle_node::join_codes::{Rendezvous, RendezvousClient, RendezvousTls};
 use squiggle_node::node::Node;
+use squiggle_node::serve::{ServeConfig, DEFAULT_SHUTDOWN_TIMEOUT};
 use squiggle_node::space::programs::Manifest;
+use squiggle_node::storage::{StorageReport, Usage};
+use squiggle_node::vm::flow::Flow;
+use squiggle_node::DocTicket;
+
+#[derive(Parser)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Import & run the github stargazers example program (the default)
+    Demo,
+    /// Show how blob storage is used across spaces, tables & programs
+    StorageReport {
+        /// print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Diagnose problems with Docker, wasm, ports, relays & the data dir
+    Doctor {
+        /// print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Read what's left of a space database the node won't open
+    Recover {
+        /// name of the space
+        space: String,
+        /// copy the salvageable events into a new space with this name
+        #[arg(long)]
+        export: Option<String>,
+        /// print the salvage as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Check a flow TOML file for problems without running it
+    Lint { path: PathBuf },
+    /// Run the tests in a program's tests dir
+    Test { path: PathBuf },
+    /// Serve the HTTP gateway
+    Gateway {
+        #[arg(long, default_value = "0.0.0.0:8080")]
+        addr: String,
+        /// mirror the space behind this feed ticket, serving it while its origin is offline
+        #[arg(long = "replica")]
+        replicas: Vec<DocTicket>,
+    },
+    /// Run a rendezvous service, trading short join codes for workspace tickets
+    Rendezvous {
+        /// anything but loopback needs `--tls-cert` & `--tls-key`
+        #[arg(long, default_value = "127.0.0.1:8082")]
+        addr: String,
+        /// PEM certificate chain to serve TLS with
+        #[arg(long, requires = "tls_key")]
+        tls_cert: Option<PathBuf>,
+        /// PEM private key of the certificate
+        #[arg(long, requires = "tls_cert")]
+        tls_key: Option<PathBuf>,
+    },
+    /// Print a short code another machine can join this node's workspace with
+    ShareCode {
+        /// url of the rendezvous service to leave the ticket with
+        #[arg(long)]
+        rendezvous: String,
+        /// minutes until the code expires
+        #[arg(long, default_value_t = 10)]
+        minutes: u64,
+    },
+    /// Run headless until stopped: gateway, sync & worker, with health checks & a pidfile
+    Serve {
+        /// address to serve the gateway on
+        #[arg(long, default_value = "0.0.0.0:8080")]
+        gateway: String,
+        /// don't serve the gateway
+        #[arg(long)]
+        no_gateway: bool,
+        /// address to serve the `/healthz` & `/readyz` endpoints on
+        #[arg(long, default_value = "127.0.0.1:8017")]
+        health: String,
+        /// where to write the process id. Defaults to `squiggle.pid` in the data dir
+        #[arg(long)]
+        pidfile: Option<PathBuf>,
+        /// seconds running jobs get to finish on shutdown
+        #[arg(long, default_value_t = DEFAULT_SHUTDOWN_TIMEOUT.as_secs())]
+        shutdown_timeout: u64,
+    },
+    /// Serve a GraphQL API over every space
+    #[cfg(feature = "graphql")]
+    Graphql {
+        /// requests need one of the node's `api_tokens` as a bearer token
+        #[arg(long, default_value = "127.0.0.1:8081")]
+        addr: String,
+    },
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    let cli = Cli::parse();
+    if let Some(Command::Lint { path }) = &cli.command {
+        return lint(path).await;
+    }
+    if let Some(Command::Rendezvous {
+        addr,
+        tls_cert,
+        tls_key,
+    }) = &cli.command
+    {
+        tracing_subscriber::fmt::init();
+        let tls = match (tls_cert, tls_key) {
+            (Some(cert), Some(key)) => Some(RendezvousTls {
+                cert: cert.clone(),
+                key: key.clone(),
+            }),
+            _ => None,
+        };
+        Rendezvous::default().serve(addr, tls).await?.await?;
+        return Ok(());
+    }
+
     let path = squiggle_node::node::data_root()?;
     let node = Node::open(path).await?;
+    init_logging(&node)?;
+
+    match cli.command.unwrap_or(Command::Demo) {
+        Command::Demo => demo(&node).await,
+        Command::StorageReport { json } => {
+            let report = node.storage_report().await?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            } else {
+                print_storage_report(&report);
+            }
+            Ok(())
+        }
+        Command::Doctor { json } => {
+            let report = node.doctor().await;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            } else {
+                print_doctor_report(&report);
+            }
+            if !report.healthy() {
+                bail!("some checks failed");
+            }
+            Ok(())
+        }
+        Command::Gateway { addr, replicas } => {
+            let handle = if replicas.is_empty() {
+                node.gateway(&addr).await?
+            } else {
+                node.gateway_replica(&addr, replicas).await?
+            };
+            handle.await?;
+            Ok(())
+        }
+        Command::Serve {
+            gateway,
+            no_gateway,
+            health,
+            pidfile,
+            shutdown_timeout,
+        } => {
+            let config = ServeConfig {
+                gateway: (!no_gateway).then_some(gateway),
+                health,
+                pidfile,
+                shutdown_timeout: Duration::from_secs(shutdown_timeout),
+            };
+            squiggle_node::serve::serve(&node, config).await
+        }
+        #[cfg(feature = "graphql")]
+        Command::Graphql { addr } => {
+            node.graphql(&addr).await?.await?;
+            Ok(())
+        }
+        Command::ShareCode {
+            rendezvous,
+            minutes,
+        } => {
+            let rendezvous = RendezvousClient::new(&rendezvous)?;
+            let ttl = Duration::from_secs(minutes * 60);
+            let published = node.vm().share_code(&rendezvous, ttl).await?;
+            println!("{}", published.code);
+            Ok(())
+        }
+        Command::Recover {
+            space,
+            export,
+            json,
+        } => recover(&node, &space, export.as_deref(), json).await,
+        Command::Test { path } => test(&node, &path).await,
+        Command::Lint { .. } | Command::Rendezvous { .. } => {
+            unreachable!("handled before opening the node")
+        }
+    }
+}
+
+/// Log at the node's configured level, following changes to it.
+fn init_logging(node: &Node) -> Result<()> {
+    let level = node.config().runtime().log_level;
+    let (filter, handle) = reload::Layer::new(log_filter(level.as_deref()));
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt::layer())
+        .try_init()?;
+
+    let mut changes = node.config().subscribe();
+    tokio::spawn(async move {
+        while changes.changed().await.is_ok() {
+            let level = changes.borrow_and_update().log_level.clone();
+            if let Err(err) = handle.reload(log_filter(level.as_deref())) {
+                eprintln!("changing log level: {err}");
+            }
+        }
+    });
+    Ok(())
+}
+
+fn log_filter(level: Option<&str>) -> EnvFilter {
+    match level {
+        Some(level) => EnvFilter::new(level),
+        None => EnvFilter::from_default_env(),
+    }
+}
+
+async fn lint(path: &Path) -> Result<()> {
+    let src = tokio::fs::read_to_string(path).await?;
+    let diagnostics = Flow::lint(&src);
+    for diagnostic in diagnostics.iter() {
+        match diagnostic.line {
+            Some(_) => println!("{}:{}", path.display(), diagnostic),
+            None => println!("{}: {}", path.display(), diagnostic),
+        }
+    }
+    if !diagnostics.is_empty() {
+        bail!("{} problem(s) found", diagnostics.len());
+    }
+    Ok(())
+}
+
+async fn recover(node: &Node, space: &str, export: Option<&str>, json: bool) -> Result<()> {
+    let recovery = node.spaces().recovery(space)?;
+    let salvage = recovery.salvage().await?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&salvage)?);
+    } else {
+        for problem in salvage.problems.iter() {
+            println!("problem: {}", problem);
+        }
+        for event in salvage.events.iter() {
+            println!(
+                "ok   {:?} {} {}",
+                event.kind, event.data_id, event.created_at
+            );
+        }
+        for invalid in salvage.invalid.iter() {
+            println!("BAD  {}: {}", invalid.id, invalid.error);
+        }
+        println!(
+            "{} salvageable, {} invalid, {} unreadable rows",
+            salvage.events.len(),
+            salvage.invalid.len(),
+            salvage.skipped
+        );
+    }
+
+    let Some(name) = export else {
+        return Ok(());
+    };
+    let author_id = node.accounts().await?[0];
+    let author = node
+        .router()
+        .authors()
+        .export(author_id)
+        .await?
+        .expect("author to exist");
+    let (space, report) = recovery
+        .export(
+            &mut node.spaces().clone(),
+            node.router().client(),
+            author,
+            name,
+        )
+        .await?;
+    println!(
+        "exported {} events into space {} ({})",
+        report.exported, space.name, space.id
+    );
+    Ok(())
+}
+
+async fn test(node: &Node, path: &Path) -> Result<()> {
+    let report = node.vm().test_program(path).await?;
+    for case in report.cases.iter() {
+        if case.passed() {
+            println!("ok   {}", case.name);
+            continue;
+        }
+        println!("FAIL {}", case.name);
+        for failure in case.failures.iter() {
+            println!("       {}", failure);
+        }
+    }
+    let failed = report.cases.iter().filter(|case| !case.passed()).count();
+    if failed > 0 {
+        bail!(
+            "{}: {} of {} test(s) failed",
+            report.program,
+            failed,
+            report.cases.len()
+        );
+    }
+    println!("{}: {} test(s) passed", report.program, report.cases.len());
+    Ok(())
+}
+
+fn print_doctor_report(report: &DoctorReport) {
+    for check in report.checks.iter() {
+        let status = match check.status {
+            CheckStatus::Ok => "ok  ",
+            CheckStatus::Warning => "warn",
+            CheckStatus::Failed => "FAIL",
+        };
+        println!("{} {}: {}", status, check.name, check.detail);
+        if let Some(fix) = &check.fix {
+            println!("       fix: {}", fix);
+        }
+    }
+}
+
+fn print_storage_report(report: &StorageReport) {
+    fn usage(u: &Usage) -> String {
+        format!("{} objects, {} bytes", u.objects, u.bytes)
+    }
+
+    println!("total: {}", usage(&report.total));
+    println!("unreferenced: {}", usage(&report.unreferenced));
+    println!("saved by deduplication: {} bytes", report.dedup_savings);
+    for space in report.spaces.iter() {
+        println!(
+            "\nspace {} ({}): {}",
+            space.name,
+            space.id,
+            usage(&space.total)
+        );
+        for (table, u) in space.tables.iter() {
+            println!("  table {}: {}", table, usage(u));
+        }
+        for (program, u) in space.programs.iter() {
+            println!("  program {}: {}", program, usage(u));
+        }
+        println!("  other: {}", usage(&space.other));
+    }
+    println!("\nlargest objects:");
+    for object in report.largest.iter() {
+        println!("  {} {} bytes", object.hash, object.size);
+    }
+}
 
+async fn demo(node: &Node) -> Result<()> {
     let authors = node.accounts().await?;
     let author = node
         .router()
@@ -50,7 +415,7 @@ async fn main() -> Result<()> {
 
     let res = node
         .vm()
-        .run_program(&space, author, program.id, HashMap::new())
+        .run_program(&space, author, program.id, HashMap::new(), false)
         .await?;
     println!("Flow output: {:?}", res);
     Ok(())
@@ -1,26 +1,63 @@
 use std::env;
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
 use futures::StreamExt;
-use iroh::docs::AuthorId;
+use iroh::blobs::util::SetTagOption;
+use iroh::blobs::Hash;
+use iroh::client::blobs::{BlobStatus, WrapOption};
+use iroh::docs::{AuthorId, DocTicket};
+use iroh::net::relay::{RelayNode, RelayUrl};
+use iroh::net::{NodeAddr, NodeId};
 use iroh::util::path::IrohPaths;
 use tokio::task::JoinHandle;
+use tracing::{info, warn};
+use uuid::Uuid;
 
-use crate::router::Router;
-use crate::space::Spaces;
-use crate::vm::{VMConfig, VM};
+use crate::aliases::Aliases;
+use crate::blob_store::BlobStore;
+use crate::bootstrap::Bootstrap;
+use crate::disk::{DiskMonitor, DiskStatus, DiskThresholds};
+use crate::doctor::DoctorReport;
+use crate::gateway::local::LocalBlobs;
+use crate::gateway::replica::{Replica, ReplicaConfig};
+use crate::gateway::token::TokenKey;
+use crate::ipfs::{Cid, Multihash};
+use crate::peers::{PeerBook, PeerInfo};
+use crate::router::{Relays, Router};
+use crate::space::hooks::Hooks;
+use crate::space::users::Profile;
+use crate::space::{SearchResult, Spaces};
+use crate::storage::StorageReport;
+use crate::vm::config::LiveConfig;
+use crate::vm::{RestartCheckpoint, VMConfig, VM};
 
 pub struct Node {
+    path: PathBuf,
     spaces: Spaces,
     router: Router,
-    vm: VM,
+    vm: Arc<VM>,
+    aliases: Aliases,
+    peers: PeerBook,
+    relays: Relays,
+    disk: DiskMonitor,
+    config: LiveConfig,
+    /// signs the API tokens of program UIs, shared with the gateway
+    tokens: TokenKey,
+    _disk_monitor_handle: JoinHandle<()>,
+    _config_handle: JoinHandle<()>,
+    _outbox_handle: JoinHandle<()>,
+    _triggers_handle: JoinHandle<()>,
+    _cron_handle: JoinHandle<()>,
 }
 
 impl Node {
     pub async fn open(path: impl Into<PathBuf>) -> Result<Self> {
         let repo_path = path.into();
-        let router = crate::router::router(&repo_path).await?;
+        let peers = PeerBook::load(&repo_path).await?;
+        let router = crate::router::router(&repo_path, peers.local_discovery().await).await?;
 
         // add the node key as an author:
         // TODO(b5): this is an anti-pattern, remove.
@@ -30,23 +67,97 @@ impl Node {
         router.authors().import(author.clone()).await?;
 
         let spaces = Spaces::open_all(router.client().clone(), repo_path.clone()).await?;
+        let aliases = Aliases::load(&repo_path).await?;
+        if let Err(err) = aliases.refresh(&spaces).await {
+            warn!("loading profile aliases: {:?}", err);
+        }
+        let relays = Relays::load(&repo_path).await?;
+        let disk = DiskMonitor::new(&repo_path, DiskThresholds::default());
+        let disk_monitor_handle = disk.spawn();
+        let config = LiveConfig::open(&repo_path).await?;
+        let runtime = config.runtime();
+        if let Some(file) = config.file() {
+            let max_skew = Duration::from_secs(file.max_clock_skew_secs);
+            spaces.clock_skew().set(max_skew);
+        }
+        let (disk_quota, blob_store, egress, slots, pickup, thumbnails, labels, admission_scanner) =
+            match config.file() {
+                Some(config) => (
+                    config.worker_disk_quota(),
+                    config.blob_store,
+                    config.egress,
+                    config.slots,
+                    config.worker_pickup,
+                    config.thumbnails,
+                    config.worker_labels,
+                    config.admission_scanner,
+                ),
+                None => Default::default(),
+            };
+        let blob_store = BlobStore::open(&blob_store, router.client().clone())?;
         let vm = VM::create(
             spaces.clone(),
             router.client(),
             VMConfig {
-                autofetch: crate::vm::content_routing::AutofetchPolicy::Disabled,
-                worker_root: repo_path,
+                autofetch: runtime.autofetch,
+                worker_root: repo_path.clone(),
+                peers: peers.clone(),
+                disk: disk.clone(),
+                blob_store,
+                egress,
+                slots,
+                pickup,
+                disk_quota,
+                thumbnails,
+                labels,
+                admission_scanner,
+                chaos: Default::default(),
             },
         )
         .await?;
 
-        Ok(Node { router, spaces, vm })
+        if !runtime.worker_enabled {
+            vm.worker().disable();
+        }
+        let config_handle = follow_config(&config, &vm);
+        let outbox_handle = crate::space::outbox::spawn(spaces.clone(), peers.clone());
+        let vm = Arc::new(vm);
+        let triggers_handle = crate::vm::triggers::spawn(vm.clone(), spaces.hooks());
+        let cron_handle = crate::vm::cron::spawn(vm.clone(), spaces.clone());
+
+        Ok(Node {
+            path: repo_path,
+            router,
+            spaces,
+            vm,
+            aliases,
+            peers,
+            relays,
+            disk,
+            config,
+            tokens: TokenKey::generate(),
+            _disk_monitor_handle: disk_monitor_handle,
+            _config_handle: config_handle,
+            _outbox_handle: outbox_handle,
+            _triggers_handle: triggers_handle,
+            _cron_handle: cron_handle,
+        })
+    }
+
+    /// The repo the node keeps its data in.
+    pub fn path(&self) -> &PathBuf {
+        &self.path
     }
 
     pub fn spaces(&self) -> &Spaces {
         &self.spaces
     }
 
+    /// Register callbacks for changes to any space, see [`crate::space::hooks`].
+    pub fn hooks(&self) -> &Hooks {
+        self.spaces.hooks()
+    }
+
     pub fn router(&self) -> &Router {
         &self.router
     }
@@ -55,6 +166,41 @@ impl Node {
         &self.vm
     }
 
+    /// The node's config, with runtime overrides & reloading.
+    pub fn config(&self) -> &LiveConfig {
+        &self.config
+    }
+
+    /// Display names for author keys across all spaces.
+    pub fn aliases(&self) -> &Aliases {
+        &self.aliases
+    }
+
+    /// First-run onboarding in one call: create an account with `profile`, a default space,
+    /// a sample table with a few rows & a sample program. Runs once, later calls return the ids
+    /// created the first time.
+    pub async fn bootstrap(&self, profile: Profile) -> Result<Bootstrap> {
+        let author_id = self
+            .accounts()
+            .await?
+            .pop()
+            .context("node has no account")?;
+        let author = self
+            .router
+            .authors()
+            .export(author_id)
+            .await?
+            .context("node account not found")?;
+        crate::bootstrap::bootstrap(
+            &self.path,
+            self.router.client(),
+            &self.spaces,
+            author,
+            profile,
+        )
+        .await
+    }
+
     pub async fn accounts(&self) -> Result<Vec<AuthorId>> {
         let mut author_ids = self.router.authors().list().await?;
         let mut authors = Vec::new();
@@ -65,21 +211,300 @@ impl Node {
         Ok(authors)
     }
 
+    /// List peers the networking layer knows about, excluding any we've been asked to forget.
+    pub async fn peers(&self) -> Result<Vec<PeerInfo>> {
+        let mut infos = self.router.net().remote_info_iter().await?;
+        let mut peers = Vec::new();
+        while let Some(info) = infos.next().await {
+            let info = info?;
+            if self.peers.is_forgotten(&info.node_id).await {
+                continue;
+            }
+            let traffic = self.peers.traffic(&info.node_id).await;
+            peers.push(PeerInfo::new(info, traffic));
+        }
+        Ok(peers)
+    }
+
+    /// Peers found on the local network.
+    pub async fn local_peers(&self) -> Result<Vec<PeerInfo>> {
+        let peers = self.peers().await?;
+        Ok(peers.into_iter().filter(|peer| peer.local).collect())
+    }
+
+    pub async fn local_discovery(&self) -> bool {
+        self.peers.local_discovery().await
+    }
+
+    /// Toggle mDNS discovery on the local network. Takes effect the next time the node is opened.
+    pub async fn set_local_discovery(&self, enabled: bool) -> Result<()> {
+        self.peers.set_local_discovery(enabled).await
+    }
+
+    pub async fn add_peer(&self, addr: NodeAddr) -> Result<()> {
+        self.peers.allow(addr.node_id).await?;
+        self.router.net().add_node_addr(addr).await?;
+        Ok(())
+    }
+
+    /// Stop fetching content from a peer, stop syncing space feeds with it and hide it from
+    /// `peers`. Persists across restarts.
+    pub async fn forget_peer(&self, node_id: NodeId) -> Result<()> {
+        self.peers.forget(node_id).await?;
+        for details in self.spaces.list(0, -1).await? {
+            let Some(space) = self.spaces.get(&details.id).await else {
+                continue;
+            };
+            if let Err(err) = space.feed().stop_sync_with(node_id).await {
+                warn!(
+                    "space {} stopping sync with {}: {:?}",
+                    space.id, node_id, err
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Configured relay nodes. Empty when the node is using the default relays.
+    pub async fn relays(&self) -> Vec<RelayNode> {
+        self.relays.list().await
+    }
+
+    /// Add a relay node to the persisted set & rebind the running endpoint to it, see
+    /// [`Node::rebind_relays`]. Returns whether the set changed.
+    pub async fn add_relay(&self, url: RelayUrl) -> Result<bool> {
+        let changed = self.relays.add(url).await?;
+        if changed {
+            self.rebind_relays().await;
+        }
+        Ok(changed)
+    }
+
+    /// Remove a relay node from the persisted set, see [`Node::add_relay`].
+    pub async fn remove_relay(&self, url: &RelayUrl) -> Result<bool> {
+        let changed = self.relays.remove(url).await?;
+        if changed {
+            self.rebind_relays().await;
+        }
+        Ok(changed)
+    }
+
+    /// Rebind the running endpoint after the relay set changed, without restarting anything:
+    /// it re-probes its network & reconnects to its home relay. Endpoints keep the relay map
+    /// they were built with, so the home relay is picked from the new set when the node next
+    /// opens. Relays outside the map are still dialed for peers that are homed on them.
+    async fn rebind_relays(&self) {
+        info!("rebinding to relays {:?}", self.relays.list().await);
+        self.router.endpoint().network_change().await;
+    }
+
+    /// Free space on the data disk, and whether blob sync is paused because of it.
+    pub async fn disk_status(&self) -> Result<DiskStatus> {
+        self.disk.status().await
+    }
+
+    pub async fn set_disk_thresholds(&self, thresholds: DiskThresholds) -> Result<DiskStatus> {
+        self.disk.set_thresholds(thresholds).await
+    }
+
+    /// Resume blob sync after it was paused for low disk space.
+    pub async fn resume_sync(&self) -> Result<DiskStatus> {
+        self.disk.resume().await
+    }
+
+    /// Search events across every space, tagged with the space each came from.
+    pub async fn search_all(&self, query: &str, limit: i64) -> Result<Vec<SearchResult>> {
+        self.spaces.search_all(query, limit).await
+    }
+
+    /// Break down blob store usage by space, table & program, flagging shared & large objects.
+    pub async fn storage_report(&self) -> Result<StorageReport> {
+        crate::storage::report(self.router.client(), &self.spaces).await
+    }
+
+    /// Get ready for the process to exit, eg: to apply an update. Waits up to `timeout` for
+    /// running jobs, then writes what was still in flight to `restart.json` in the repo, see
+    /// [`VM::prepare_restart`]. Once this returns the node doesn't take new jobs.
+    pub async fn prepare_restart(&self, timeout: Duration) -> Result<RestartCheckpoint> {
+        let checkpoint = self.vm.prepare_restart(timeout).await?;
+        let data = serde_json::to_vec_pretty(&checkpoint)?;
+        tokio::fs::write(self.path.join(RESTART_FILENAME), data).await?;
+        Ok(checkpoint)
+    }
+
+    /// The checkpoint the last [`Node::prepare_restart`] left, if any.
+    pub async fn restart_checkpoint(&self) -> Result<Option<RestartCheckpoint>> {
+        let path = self.path.join(RESTART_FILENAME);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let data = tokio::fs::read(path).await?;
+        Ok(Some(serde_json::from_slice(&data)?))
+    }
+
+    /// Check the environment the node runs in: Docker, the wasm runtime, configured ports,
+    /// relays, the data dir & space databases. Problems come with suggested fixes.
+    pub async fn doctor(&self) -> DoctorReport {
+        crate::doctor::diagnose(&self.path, &self.router, &self.relays, &self.spaces).await
+    }
+
+    /// Import a file from the local filesystem into the blob store, returning the hash.
+    pub async fn blob_add_path(&self, path: impl Into<PathBuf>) -> Result<Hash> {
+        add_path(self.router.blobs(), path.into()).await
+    }
+
+    /// Add raw bytes to the blob store, returning the hash.
+    pub async fn blob_add_bytes(&self, data: Vec<u8>) -> Result<Hash> {
+        let outcome = self.router.blobs().add_bytes(data).await?;
+        Ok(outcome.hash)
+    }
+
+    /// Name a blob with a CIDv1. sha2-256 CIDs require reading the blob, and are limited to
+    /// blobs that fit in a single IPFS block.
+    pub async fn blob_cid(&self, hash: Hash, multihash: Multihash) -> Result<Cid> {
+        match multihash {
+            Multihash::Blake3 => Ok(Cid::from_hash(hash)),
+            Multihash::Sha256 => {
+                let size = match self.router.blobs().status(hash).await? {
+                    BlobStatus::Complete { size } => size,
+                    _ => anyhow::bail!("blob {hash} isn't complete on this node"),
+                };
+                anyhow::ensure!(
+                    size <= crate::ipfs::MAX_RAW_BLOCK_SIZE as u64,
+                    "blob {hash} is too large for a raw IPFS block"
+                );
+                let data = self.router.blobs().read_to_bytes(hash).await?;
+                Ok(Cid::for_data(multihash, &data))
+            }
+        }
+    }
+
+    /// Fetch a raw block by CID from an IPFS HTTP gateway into the blob store.
+    pub async fn blob_import_cid(&self, cid: &Cid, gateway: Option<&str>) -> Result<Hash> {
+        let gateway = gateway.unwrap_or(crate::ipfs::DEFAULT_GATEWAY);
+        let data = crate::ipfs::fetch_from_gateway(gateway, cid).await?;
+        self.blob_add_bytes(data).await
+    }
+
     pub async fn gateway(&self, serve_addr: &str) -> Result<JoinHandle<()>> {
+        self.spawn_gateway(serve_addr, None).await
+    }
+
+    /// Run the gateway as a read replica of the spaces behind `feeds`, tickets from
+    /// [`crate::space::feed::Feed::share`]. Mirrored spaces stay readable while their origin
+    /// is offline.
+    pub async fn gateway_replica(
+        &self,
+        serve_addr: &str,
+        feeds: Vec<DocTicket>,
+    ) -> Result<JoinHandle<()>> {
+        let config = ReplicaConfig::new(self.path.join(REPLICAS_DIR), feeds);
+        let replica = Replica::open(self.router.client().clone(), config).await?;
+        self.spawn_gateway(serve_addr, Some(replica)).await
+    }
+
+    async fn spawn_gateway(
+        &self,
+        serve_addr: &str,
+        replica: Option<Replica>,
+    ) -> Result<JoinHandle<()>> {
         let addr = self.router.net().node_addr().await?;
+        let spaces = self.spaces.clone();
+        let vm = self.vm.clone();
+        let tokens = self.tokens.clone();
+        let api_tokens = self.config.file().unwrap_or_default().api_tokens;
+        let local = LocalBlobs::new(self.router.client().clone(), Some(&self.path));
         let serve_addr = serve_addr.to_string();
         let handle = tokio::spawn(async move {
-            crate::gateway::server::run(addr, serve_addr)
+            crate::gateway::server::run(
+                addr,
+                Some(local),
+                replica,
+                Some(spaces),
+                Some(vm),
+                tokens,
+                api_tokens,
+                serve_addr,
+            )
+            .await
+            .expect("gateway failed");
+        });
+
+        Ok(handle)
+    }
+
+    /// An API token for `program` to use while the app shows it in `space`, as the node's
+    /// account. Passed to the gateway's sandbox wrapper, which hands it to the program. None
+    /// if the account has no role in the space.
+    pub async fn sandbox_token(&self, space: Uuid, program: Hash) -> Result<Option<String>> {
+        let Some(space) = self.spaces.get(&space).await else {
+            return Err(crate::Error::SpaceNotFound { id: space }.into());
+        };
+        let author = self
+            .accounts()
+            .await?
+            .pop()
+            .context("node has no account")?;
+        self.tokens.issue_for(&space, program, author).await
+    }
+
+    /// Serve a GraphQL API over every space, mutations are authored by the node's account.
+    /// Requests need one of the configured `api_tokens`.
+    #[cfg(feature = "graphql")]
+    pub async fn graphql(&self, serve_addr: &str) -> Result<JoinHandle<()>> {
+        let api_tokens = self.config.file().unwrap_or_default().api_tokens;
+        anyhow::ensure!(
+            api_tokens.iter().any(|token| !token.is_empty()),
+            "graphql needs an api token, add one to api_tokens in the config"
+        );
+        let author_id = self
+            .accounts()
+            .await?
+            .pop()
+            .context("node has no account")?;
+        let author = self
+            .router
+            .authors()
+            .export(author_id)
+            .await?
+            .context("node account not found")?;
+        let spaces = self.spaces.clone();
+        let serve_addr = serve_addr.to_string();
+        let handle = tokio::spawn(async move {
+            crate::graphql::serve(spaces, author, api_tokens, serve_addr)
                 .await
-                .expect("gateway failed");
+                .expect("graphql server failed");
         });
 
         Ok(handle)
     }
 }
 
+/// Apply runtime config changes to the subsystems they affect. Log level & metrics port
+/// changes are left to whoever set up tracing & metrics, see [`LiveConfig::subscribe`] &
+/// [`crate::admin::follow`].
+fn follow_config(config: &LiveConfig, vm: &VM) -> JoinHandle<()> {
+    let mut changes = config.subscribe();
+    let blobs = vm.blobs().clone();
+    let worker = vm.worker().clone();
+    tokio::spawn(async move {
+        while changes.changed().await.is_ok() {
+            let runtime = changes.borrow_and_update().clone();
+            blobs.set_autofetch(runtime.autofetch);
+            match runtime.worker_enabled {
+                true => worker.enable(),
+                false => worker.disable(),
+            }
+        }
+    })
+}
+
 /// Name of directory that wraps all datalayer files in a given application directory
 const SQUIGGLE_DATA_DIR: &str = "squiggle";
+/// Directory under the repo that replica mirrors are kept in
+const REPLICAS_DIR: &str = "replicas";
+/// What was in flight the last time the node got ready to restart
+const RESTART_FILENAME: &str = "restart.json";
 
 /// Returns the path to the user's iroh data directory.
 ///
@@ -108,3 +533,43 @@ pub fn data_root() -> Result<PathBuf> {
     };
     Ok(path)
 }
+
+/// Import the file at `path`, which may be relative to the working directory: the store
+/// needs an absolute one.
+async fn add_path(blobs: &iroh::client::blobs::Client, path: PathBuf) -> Result<Hash> {
+    let path = path
+        .canonicalize()
+        .with_context(|| format!("reading {}", path.display()))?;
+    let outcome = blobs
+        .add_from_path(path, false, SetTagOption::Auto, WrapOption::NoWrap)
+        .await?
+        .finish()
+        .await?;
+    Ok(outcome.hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn add_blobs_from_paths() -> Result<()> {
+        let node = iroh::node::Node::memory().spawn().await?;
+        let blobs = node.client().blobs();
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("notes.txt");
+        std::fs::write(&path, b"some notes")?;
+
+        let hash = add_path(blobs, path.clone()).await?;
+        assert_eq!(hash, Hash::new(b"some notes"));
+        assert_eq!(blobs.read_to_bytes(hash).await?, "some notes".as_bytes());
+
+        // not wrapped in a collection, and readable through a path with `..` in it
+        let roundabout = dir.path().join("..").join(dir.path().file_name().unwrap());
+        assert_eq!(add_path(blobs, roundabout.join("notes.txt")).await?, hash);
+
+        let err = add_path(blobs, dir.path().join("missing.txt")).await;
+        assert!(format!("{:#}", err.unwrap_err()).contains("missing.txt"));
+        Ok(())
+    }
+}
@@ -0,0 +1,205 @@
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::Result;
+use iroh::net::discovery::local_swarm_discovery;
+use iroh::net::endpoint::{RemoteInfo, Source};
+use iroh::net::NodeId;
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+
+const PEERS_FILENAME: &str = "peers.json";
+
+/// Traffic we've exchanged with a single peer while fetching content from it.
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+#[serde(rename_all = "camelCase")]
+pub struct PeerTraffic {
+    pub requests: u64,
+    pub failures: u64,
+    pub bytes_received: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+#[serde(rename_all = "camelCase")]
+pub struct PeerInfo {
+    #[cfg_attr(feature = "specta", specta(type = String))]
+    pub node_id: NodeId,
+    pub relay_url: Option<String>,
+    pub addrs: Vec<String>,
+    /// one of "direct", "relay", "mixed", or "none"
+    pub conn_type: String,
+    pub latency_ms: Option<u64>,
+    /// seconds since we last sent or received data with this peer
+    pub last_used_secs: Option<u64>,
+    /// true if any of the peer's addresses were found via LAN discovery
+    pub local: bool,
+    pub traffic: PeerTraffic,
+}
+
+impl PeerInfo {
+    pub(crate) fn new(info: RemoteInfo, traffic: PeerTraffic) -> Self {
+        let local = info.addrs.iter().any(|addr| {
+            addr.sources.keys().any(|source| {
+                matches!(source, Source::Discovery { name } if *name == local_swarm_discovery::NAME)
+            })
+        });
+        PeerInfo {
+            node_id: info.node_id,
+            relay_url: info.relay_url.map(|r| r.relay_url.to_string()),
+            addrs: info.addrs.iter().map(|a| a.addr.to_string()).collect(),
+            conn_type: info.conn_type.to_string(),
+            latency_ms: info.latency.map(|l| l.as_millis() as u64),
+            last_used_secs: info.last_used.map(|l| l.as_secs()),
+            local,
+            traffic,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+struct PeersFile {
+    forgotten: HashSet<NodeId>,
+    local_discovery: bool,
+}
+
+impl Default for PeersFile {
+    fn default() -> Self {
+        Self {
+            forgotten: HashSet::new(),
+            local_discovery: true,
+        }
+    }
+}
+
+/// Node-wide bookkeeping for peers: which ones the user has asked us to stop talking to, and
+/// how much traffic we've exchanged with the rest.
+#[derive(Debug, Clone)]
+pub struct PeerBook {
+    path: PathBuf,
+    file: Arc<RwLock<PeersFile>>,
+    traffic: Arc<RwLock<HashMap<NodeId, PeerTraffic>>>,
+}
+
+impl PeerBook {
+    pub async fn load(base_path: impl Into<PathBuf>) -> Result<Self> {
+        let path = base_path.into().join(PEERS_FILENAME);
+        let file = if path.exists() {
+            let data = tokio::fs::read(&path).await?;
+            serde_json::from_slice(&data)?
+        } else {
+            PeersFile::default()
+        };
+        Ok(Self {
+            path,
+            file: Arc::new(RwLock::new(file)),
+            traffic: Default::default(),
+        })
+    }
+
+    pub async fn is_forgotten(&self, node_id: &NodeId) -> bool {
+        self.file.read().await.forgotten.contains(node_id)
+    }
+
+    pub async fn forget(&self, node_id: NodeId) -> Result<()> {
+        let mut file = self.file.write().await;
+        if file.forgotten.insert(node_id) {
+            self.traffic.write().await.remove(&node_id);
+            self.write_to_file(&file).await?;
+        }
+        Ok(())
+    }
+
+    /// Undo a previous `forget`, if any.
+    pub async fn allow(&self, node_id: NodeId) -> Result<()> {
+        let mut file = self.file.write().await;
+        if file.forgotten.remove(&node_id) {
+            self.write_to_file(&file).await?;
+        }
+        Ok(())
+    }
+
+    /// Whether to discover peers on the local network via mDNS. On by default.
+    pub async fn local_discovery(&self) -> bool {
+        self.file.read().await.local_discovery
+    }
+
+    pub async fn set_local_discovery(&self, enabled: bool) -> Result<()> {
+        let mut file = self.file.write().await;
+        if file.local_discovery != enabled {
+            file.local_discovery = enabled;
+            self.write_to_file(&file).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn traffic(&self, node_id: &NodeId) -> PeerTraffic {
+        self.traffic
+            .read()
+            .await
+            .get(node_id)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Record a fetch from `node_id`, `None` if it failed.
+    pub(crate) async fn record_fetch(&self, node_id: NodeId, bytes: Option<u64>) {
+        let mut traffic = self.traffic.write().await;
+        let entry = traffic.entry(node_id).or_default();
+        entry.requests += 1;
+        match bytes {
+            Some(bytes) => entry.bytes_received += bytes,
+            None => entry.failures += 1,
+        }
+    }
+
+    /// Write next to the file & move it into place, so a crash never leaves it half written.
+    async fn write_to_file(&self, file: &PeersFile) -> Result<()> {
+        let file = serde_json::to_vec(file)?;
+        let tmp = self.path.with_extension("json.tmp");
+        tokio::fs::write(&tmp, file).await?;
+        tokio::fs::rename(&tmp, &self.path).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use iroh::net::key::SecretKey;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn forgotten_peers_persist() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let book = PeerBook::load(dir.path()).await?;
+        let peer = SecretKey::generate().public();
+        book.record_fetch(peer, Some(10)).await;
+        book.forget(peer).await?;
+        assert!(book.is_forgotten(&peer).await);
+        assert_eq!(book.traffic(&peer).await.requests, 0);
+        assert!(!dir.path().join("peers.json.tmp").exists());
+
+        let reloaded = PeerBook::load(dir.path()).await?;
+        assert!(reloaded.is_forgotten(&peer).await);
+        reloaded.allow(peer).await?;
+        assert!(!PeerBook::load(dir.path()).await?.is_forgotten(&peer).await);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn local_discovery_defaults_on_and_persists() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        // written before discovery could be turned off
+        std::fs::write(dir.path().join(PEERS_FILENAME), r#"{"forgotten":[]}"#)?;
+        let book = PeerBook::load(dir.path()).await?;
+        assert!(book.local_discovery().await);
+
+        book.set_local_discovery(false).await?;
+        assert!(!PeerBook::load(dir.path()).await?.local_discovery().await);
+        Ok(())
+    }
+}
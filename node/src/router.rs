@@ -1,15 +1,115 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use std::path::PathBuf;
+use std::sync::Arc;
+
+use iroh::net::defaults::DEFAULT_STUN_PORT;
+use iroh::net::discovery::dns::DnsDiscovery;
+use iroh::net::discovery::local_swarm_discovery::LocalSwarmDiscovery;
+use iroh::net::discovery::pkarr::PkarrPublisher;
+use iroh::net::discovery::{ConcurrentDiscovery, Discovery};
+use iroh::net::relay::{RelayMap, RelayMode, RelayNode, RelayUrl};
+use iroh::node::DiscoveryConfig;
+use iroh::util::path::IrohPaths;
+use tokio::sync::RwLock;
 
 pub type Router = iroh::node::FsNode;
 pub type RouterClient = iroh::client::Iroh;
 
-pub async fn router(path: impl Into<PathBuf>) -> Result<Router> {
+const RELAYS_FILENAME: &str = "relays.json";
+
+pub async fn router(path: impl Into<PathBuf>, local_discovery: bool) -> Result<Router> {
     let path = path.into();
+    let relays = Relays::load(&path).await?;
+    let secret_key = iroh::util::fs::load_secret_key(IrohPaths::SecretKey.with_root(&path)).await?;
+
+    // the iroh defaults, plus mDNS on the local network if enabled
+    let mut discovery: Vec<Box<dyn Discovery>> = vec![
+        Box::new(DnsDiscovery::n0_dns()),
+        Box::new(PkarrPublisher::n0_dns(secret_key.clone())),
+    ];
+    if local_discovery {
+        discovery.push(Box::new(LocalSwarmDiscovery::new(secret_key.public())?));
+    }
+
     let router = iroh::node::Node::persistent(path)
         .await?
+        .secret_key(secret_key)
+        .relay_mode(relays.relay_mode().await?)
+        .node_discovery(DiscoveryConfig::Custom(Box::new(
+            ConcurrentDiscovery::from_services(discovery),
+        )))
         .enable_docs()
         .spawn()
         .await?;
     Ok(router)
 }
+
+/// The user-configured set of relay nodes, persisted alongside the repo. An empty set means
+/// "use the iroh defaults".
+#[derive(Debug, Clone)]
+pub struct Relays {
+    path: PathBuf,
+    nodes: Arc<RwLock<Vec<RelayNode>>>,
+}
+
+impl Relays {
+    pub async fn load(base_path: impl Into<PathBuf>) -> Result<Self> {
+        let path = base_path.into().join(RELAYS_FILENAME);
+        let nodes = if path.exists() {
+            let data = tokio::fs::read(&path).await?;
+            serde_json::from_slice(&data).context("reading relays file")?
+        } else {
+            Vec::new()
+        };
+        Ok(Self {
+            path,
+            nodes: Arc::new(RwLock::new(nodes)),
+        })
+    }
+
+    pub async fn list(&self) -> Vec<RelayNode> {
+        self.nodes.read().await.clone()
+    }
+
+    /// Add a relay, returning false if it was already configured.
+    pub async fn add(&self, url: RelayUrl) -> Result<bool> {
+        let mut nodes = self.nodes.write().await;
+        if nodes.iter().any(|node| node.url == url) {
+            return Ok(false);
+        }
+        nodes.push(RelayNode {
+            url,
+            stun_only: false,
+            stun_port: DEFAULT_STUN_PORT,
+        });
+        self.write_to_file(&nodes).await?;
+        Ok(true)
+    }
+
+    /// Remove a relay, returning false if it wasn't configured.
+    pub async fn remove(&self, url: &RelayUrl) -> Result<bool> {
+        let mut nodes = self.nodes.write().await;
+        let len = nodes.len();
+        nodes.retain(|node| &node.url != url);
+        if nodes.len() == len {
+            return Ok(false);
+        }
+        self.write_to_file(&nodes).await?;
+        Ok(true)
+    }
+
+    pub async fn relay_mode(&self) -> Result<RelayMode> {
+        let nodes = self.nodes.read().await;
+        if nodes.is_empty() {
+            return Ok(RelayMode::Default);
+        }
+        let map = RelayMap::from_nodes(nodes.iter().cloned())?;
+        Ok(RelayMode::Custom(map))
+    }
+
+    async fn write_to_file(&self, nodes: &[RelayNode]) -> Result<()> {
+        let file = serde_json::to_vec(nodes)?;
+        tokio::fs::write(&self.path, file).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,383 @@
+//! The stable public API.
+//!
+//! Everything else in this crate is free to change between releases. Types in this module
+//! follow semver: fields & methods are only added in minor releases, & structs are
+//! `#[non_exhaustive]` so adding a field isn't a breaking change. Hashes, keys & ids from
+//! iroh are exposed as strings, so depending on the sdk doesn't tie consumers to our iroh
+//! or rusqlite versions.
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use iroh::docs::Author;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::node::Node;
+use crate::space;
+use crate::vm::flow::TaskOutput;
+use crate::vm::job::{JobOutput, JobResultStatus};
+
+/// A handle to a node, acting as the node's default account.
+#[derive(Clone)]
+pub struct Client {
+    node: Arc<Node>,
+    author: Author,
+}
+
+impl Client {
+    /// Open the node in the default data directory.
+    pub async fn open_default() -> Result<Self> {
+        Self::open(crate::node::data_root()?).await
+    }
+
+    pub async fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let node = Node::open(path).await?;
+        let author_id = node
+            .accounts()
+            .await?
+            .pop()
+            .context("node has no account")?;
+        let author = node
+            .router()
+            .authors()
+            .export(author_id)
+            .await?
+            .context("node account not found")?;
+        Ok(Client {
+            node: Arc::new(node),
+            author,
+        })
+    }
+
+    pub async fn spaces(&self) -> Result<Vec<SpaceInfo>> {
+        let details = self.node.spaces().list(0, -1).await?;
+        Ok(details
+            .into_iter()
+            .map(|details| SpaceInfo {
+                id: details.id,
+                name: details.name,
+            })
+            .collect())
+    }
+
+    pub async fn space(&self, name: &str) -> Result<Space> {
+        let inner = self
+            .node
+            .spaces()
+            .get_by_name(name)
+            .await
+            .with_context(|| format!("space {name} not found"))?;
+        Ok(self.wrap(inner))
+    }
+
+    pub async fn create_space(&self, name: &str, description: &str) -> Result<Space> {
+        let inner = self
+            .node
+            .spaces()
+            .clone()
+            .create(self.node.router(), self.author.clone(), name, description)
+            .await?;
+        Ok(self.wrap(inner))
+    }
+
+    fn wrap(&self, inner: space::Space) -> Space {
+        Space {
+            client: self.clone(),
+            inner,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[non_exhaustive]
+pub struct SpaceInfo {
+    pub id: Uuid,
+    pub name: String,
+}
+
+/// A space, written to as the client's account.
+#[derive(Clone)]
+pub struct Space {
+    client: Client,
+    inner: space::Space,
+}
+
+impl Space {
+    pub fn id(&self) -> Uuid {
+        self.inner.id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.inner.name
+    }
+
+    /// The latest version of every table.
+    pub async fn tables(&self) -> Result<Vec<Table>> {
+        let mut latest: HashMap<Uuid, space::tables::Table> = HashMap::new();
+        for table in self.inner.tables().list(0, -1).await? {
+            // listed oldest first, later versions win
+            latest.insert(table.id, table);
+        }
+        let mut tables = Vec::new();
+        for (_, table) in latest {
+            tables.push(self.table_from(table).await?);
+        }
+        tables.sort_by(|a, b| a.title.cmp(&b.title));
+        Ok(tables)
+    }
+
+    pub async fn table(&self, title: &str) -> Result<Table> {
+        let table = self.inner.tables().get_by_title(title).await?;
+        self.table_from(table).await
+    }
+
+    /// Create a table from a JSON schema. The schema's `title` names the table.
+    pub async fn create_table(&self, schema: &Value) -> Result<Table> {
+        let data = Bytes::from(serde_json::to_vec(schema)?);
+        let table = self
+            .inner
+            .tables()
+            .create(self.client.author.clone(), data)
+            .await?;
+        self.table_from(table).await
+    }
+
+    /// The latest version of every row in a table, in no particular order.
+    pub async fn rows(&self, table: &Table) -> Result<Vec<Row>> {
+        let hash = table.hash.parse()?;
+        let rows = self.inner.rows().query(hash, String::new(), 0, -1).await?;
+        let mut latest: HashMap<Uuid, space::rows::Row> = HashMap::new();
+        for row in rows {
+            match latest.get(&row.id) {
+                Some(prev) if prev.created_at > row.created_at => {}
+                _ => {
+                    latest.insert(row.id, row);
+                }
+            }
+        }
+        let mut rows = Vec::new();
+        for (_, row) in latest {
+            rows.push(self.row_from(row).await?);
+        }
+        Ok(rows)
+    }
+
+    pub async fn insert_row(&self, table: &Table, data: Value) -> Result<Row> {
+        self.update_row(table, Uuid::new_v4(), data).await
+    }
+
+    /// Write a new version of a row, creating it if `id` is new.
+    pub async fn update_row(&self, table: &Table, id: Uuid, data: Value) -> Result<Row> {
+        let row = self
+            .inner
+            .rows()
+            .mutate(self.client.author.clone(), table.hash.parse()?, id, data)
+            .await?;
+        self.row_from(row).await
+    }
+
+    pub async fn programs(&self) -> Result<Vec<Program>> {
+        let programs = self.inner.programs().list(0, -1).await?;
+        Ok(programs.into_iter().map(Program::from).collect())
+    }
+
+    /// Import a built program from a directory holding its `program.json`.
+    pub async fn import_program(&self, path: impl AsRef<Path>) -> Result<Program> {
+        let program = self
+            .inner
+            .programs()
+            .create(self.client.author.clone(), path.as_ref())
+            .await?;
+        Ok(Program::from(program))
+    }
+
+    pub async fn run_program(
+        &self,
+        program_id: Uuid,
+        environment: HashMap<String, String>,
+    ) -> Result<RunResult> {
+        let output = self
+            .client
+            .node
+            .vm()
+            .run_program(
+                &self.inner,
+                self.client.author.clone(),
+                program_id,
+                environment,
+                false,
+            )
+            .await?;
+        Ok(RunResult::from(output))
+    }
+
+    /// Like [`Space::run_program`], but runs the program at most once per `key` within
+    /// [`crate::vm::RUN_KEY_TTL`]. Repeated calls get the first run's result.
+    pub async fn run_program_once(
+        &self,
+        program_id: Uuid,
+        environment: HashMap<String, String>,
+        key: &str,
+    ) -> Result<RunResult> {
+        let output = self
+            .client
+            .node
+            .vm()
+            .run_program_once(
+                &self.inner,
+                self.client.author.clone(),
+                program_id,
+                environment,
+                key,
+            )
+            .await?;
+        Ok(RunResult::from(output))
+    }
+
+    async fn table_from(&self, mut table: space::tables::Table) -> Result<Table> {
+        let schema = table.content.resolve(self.inner.router()).await?;
+        Ok(Table {
+            id: table.id,
+            title: table.title,
+            hash: table.content.hash.to_string(),
+            author: table.author.to_string(),
+            created_at: table.created_at,
+            schema,
+        })
+    }
+
+    async fn row_from(&self, mut row: space::rows::Row) -> Result<Row> {
+        let data = row.content.resolve(self.inner.router()).await?;
+        Ok(Row {
+            id: row.id,
+            table: row.schema.to_string(),
+            author: row.author.to_string(),
+            created_at: row.created_at,
+            data,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[non_exhaustive]
+pub struct Table {
+    pub id: Uuid,
+    pub title: String,
+    /// hash of the current schema, rows refer to their table by it
+    pub hash: String,
+    /// public key of the author of this version
+    pub author: String,
+    pub created_at: i64,
+    pub schema: Value,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[non_exhaustive]
+pub struct Row {
+    pub id: Uuid,
+    /// schema hash of the table this row belongs to
+    pub table: String,
+    pub author: String,
+    pub created_at: i64,
+    pub data: Value,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[non_exhaustive]
+pub struct Program {
+    pub id: Uuid,
+    pub name: String,
+    pub version: String,
+    pub description: Option<String>,
+    pub created_at: i64,
+}
+
+impl From<space::programs::Program> for Program {
+    fn from(program: space::programs::Program) -> Self {
+        Program {
+            id: program.id,
+            name: program.manifest.name,
+            version: program.manifest.version,
+            description: program.manifest.description,
+            created_at: program.created_at,
+        }
+    }
+}
+
+/// The outcome of a program run.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[non_exhaustive]
+pub struct RunResult {
+    pub id: Uuid,
+    pub succeeded: bool,
+    /// what the program printed, stdout for containers
+    pub output: String,
+    pub error: Option<String>,
+    pub wall_time_ms: Option<u64>,
+}
+
+impl From<TaskOutput> for RunResult {
+    fn from(output: TaskOutput) -> Self {
+        let wall_time_ms = output.result.usage.map(|usage| usage.wall_time_ms);
+        let (output_text, error) = match output.result.status {
+            JobResultStatus::Ok(JobOutput::Wasm { output }) => (output, None),
+            JobResultStatus::Ok(JobOutput::Docker { stdout, .. }) => (stdout, None),
+            JobResultStatus::Err(err) => (String::new(), Some(err)),
+            JobResultStatus::ErrTimeout => (String::new(), Some("timed out".to_string())),
+            JobResultStatus::ErrDeadlineExceeded => {
+                (String::new(), Some("missed its deadline".to_string()))
+            }
+            JobResultStatus::Unknown => (String::new(), Some("no result".to_string())),
+        };
+        RunResult {
+            id: output.id,
+            succeeded: error.is_none(),
+            output: output_text,
+            error,
+            wall_time_ms,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::job::{JobResult, JobUsage};
+
+    #[test]
+    fn run_result_from_task_output() {
+        let output = |status| TaskOutput {
+            name: "program".to_string(),
+            id: Uuid::nil(),
+            result: JobResult {
+                worker: None,
+                status,
+                usage: Some(JobUsage {
+                    wall_time_ms: 12,
+                    ..Default::default()
+                }),
+            },
+            ..Default::default()
+        };
+
+        let ok = RunResult::from(output(JobResultStatus::Ok(JobOutput::Wasm {
+            output: "hello".to_string(),
+        })));
+        assert!(ok.succeeded);
+        assert_eq!(ok.output, "hello");
+        assert_eq!(ok.wall_time_ms, Some(12));
+
+        let err = RunResult::from(output(JobResultStatus::ErrTimeout));
+        assert!(!err.succeeded);
+        assert_eq!(err.error.as_deref(), Some("timed out"));
+    }
+}
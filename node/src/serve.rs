@@ -0,0 +1,241 @@
+//! Long-running headless mode for running a node under a service manager like systemd.
+//!
+//! [`serve`] starts the gateway next to the sync & worker tasks the node already runs, writes
+//! a pidfile & answers health checks over HTTP: `/healthz` is ok for as long as the process
+//! is up, `/readyz` only once everything started & until shutdown begins. `/metrics` & `/admin`
+//! are served on the metrics port when it's set, see [`crate::admin`]. SIGTERM or ctrl-c
+//! shut down gracefully: the node stops taking jobs & waits for running ones, see
+//! [`Node::prepare_restart`], before the pidfile is removed & the process exits.
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::{Extension, Router};
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+use crate::admin;
+use crate::node::Node;
+
+/// How long running jobs get to finish on shutdown by default.
+pub const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+/// Name of the pidfile written to the repo when no other path is given
+const PIDFILE_NAME: &str = "squiggle.pid";
+
+#[derive(Debug, Clone)]
+pub struct ServeConfig {
+    /// address to serve the gateway on, no gateway when unset
+    pub gateway: Option<String>,
+    /// address to serve `/healthz` & `/readyz` on
+    pub health: String,
+    /// defaults to `squiggle.pid` in the repo
+    pub pidfile: Option<PathBuf>,
+    /// how long running jobs get to finish on shutdown
+    pub shutdown_timeout: Duration,
+}
+
+impl Default for ServeConfig {
+    fn default() -> Self {
+        ServeConfig {
+            gateway: Some("0.0.0.0:8080".to_string()),
+            health: "127.0.0.1:8017".to_string(),
+            pidfile: None,
+            shutdown_timeout: DEFAULT_SHUTDOWN_TIMEOUT,
+        }
+    }
+}
+
+/// Where a serving node is in its life.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Phase {
+    Starting = 0,
+    Ready = 1,
+    Stopping = 2,
+}
+
+/// The phase health checks report, shared with the health server.
+#[derive(Debug, Clone)]
+pub struct Health(Arc<AtomicU8>);
+
+impl Default for Health {
+    fn default() -> Self {
+        Health(Arc::new(AtomicU8::new(Phase::Starting as u8)))
+    }
+}
+
+impl Health {
+    pub fn phase(&self) -> Phase {
+        match self.0.load(Ordering::SeqCst) {
+            0 => Phase::Starting,
+            1 => Phase::Ready,
+            _ => Phase::Stopping,
+        }
+    }
+
+    pub fn set(&self, phase: Phase) {
+        self.0.store(phase as u8, Ordering::SeqCst);
+    }
+
+    pub fn router(&self) -> Router {
+        Router::new()
+            .route("/healthz", get(liveness))
+            .route("/readyz", get(readiness))
+            .layer(Extension(self.clone()))
+    }
+
+    pub async fn serve(self, addr: &str) -> Result<JoinHandle<()>> {
+        let listener = tokio::net::TcpListener::bind(addr)
+            .await
+            .with_context(|| format!("binding health checks to {addr}"))?;
+        info!("health checks listening on {}", listener.local_addr()?);
+        let app = self.router();
+        Ok(tokio::spawn(async move {
+            if let Err(err) = axum::serve(listener, app).await {
+                tracing::error!("health checks stopped: {:?}", err);
+            }
+        }))
+    }
+}
+
+async fn liveness() -> &'static str {
+    "ok"
+}
+
+async fn readiness(Extension(health): Extension<Health>) -> (StatusCode, &'static str) {
+    match health.phase() {
+        Phase::Ready => (StatusCode::OK, "ready"),
+        Phase::Starting => (StatusCode::SERVICE_UNAVAILABLE, "starting"),
+        Phase::Stopping => (StatusCode::SERVICE_UNAVAILABLE, "stopping"),
+    }
+}
+
+/// Holds the process id in a file until dropped.
+#[derive(Debug)]
+pub struct Pidfile(PathBuf);
+
+impl Pidfile {
+    pub fn create(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        if let Ok(existing) = std::fs::read_to_string(&path) {
+            warn!(
+                "replacing pidfile {} left by process {}",
+                path.display(),
+                existing.trim()
+            );
+        }
+        std::fs::write(&path, format!("{}\n", std::process::id()))
+            .with_context(|| format!("writing pidfile {}", path.display()))?;
+        Ok(Pidfile(path))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for Pidfile {
+    fn drop(&mut self) {
+        if let Err(err) = std::fs::remove_file(&self.0) {
+            warn!("removing pidfile {}: {:?}", self.0.display(), err);
+        }
+    }
+}
+
+/// Run `node` until the process is asked to stop.
+pub async fn serve(node: &Node, config: ServeConfig) -> Result<()> {
+    let health = Health::default();
+    let _health_handle = health.clone().serve(&config.health).await?;
+    let pidfile = config
+        .pidfile
+        .clone()
+        .unwrap_or_else(|| node.path().join(PIDFILE_NAME));
+    let pidfile = Pidfile::create(pidfile)?;
+
+    let gateway = match &config.gateway {
+        Some(addr) => Some(node.gateway(addr).await?),
+        None => None,
+    };
+    let admin_config = node.config().file().unwrap_or_default().admin;
+    let admin = admin::follow(admin_config, node.config().clone()).await?;
+    health.set(Phase::Ready);
+    info!(
+        "serving, pid {} written to {}",
+        std::process::id(),
+        pidfile.path().display()
+    );
+
+    shutdown_signal().await?;
+    info!("shutting down");
+    health.set(Phase::Stopping);
+    let checkpoint = node.prepare_restart(config.shutdown_timeout).await?;
+    if !checkpoint.interrupted_jobs.is_empty() {
+        warn!(
+            "{} jobs were interrupted by shutdown",
+            checkpoint.interrupted_jobs.len()
+        );
+    }
+    if let Some(gateway) = gateway {
+        gateway.abort();
+    }
+    admin.abort();
+    Ok(())
+}
+
+/// Resolves on SIGTERM or ctrl-c.
+async fn shutdown_signal() -> Result<()> {
+    #[cfg(unix)]
+    {
+        let mut term = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+        tokio::select! {
+            res = tokio::signal::ctrl_c() => res?,
+            _ = term.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn health_checks_follow_phase() -> Result<()> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let url = format!("http://{}", listener.local_addr()?);
+        let health = Health::default();
+        let app = health.router();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let status = |path: &'static str| {
+            let url = format!("{url}{path}");
+            async move { Ok::<_, anyhow::Error>(reqwest::get(url).await?.status()) }
+        };
+        assert_eq!(status("/healthz").await?, StatusCode::OK);
+        assert_eq!(status("/readyz").await?, StatusCode::SERVICE_UNAVAILABLE);
+        health.set(Phase::Ready);
+        assert_eq!(status("/readyz").await?, StatusCode::OK);
+        health.set(Phase::Stopping);
+        assert_eq!(status("/readyz").await?, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(status("/healthz").await?, StatusCode::OK);
+        Ok(())
+    }
+
+    #[test]
+    fn pidfile_is_removed_on_drop() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join(PIDFILE_NAME);
+        let pidfile = Pidfile::create(&path)?;
+        let pid: u32 = std::fs::read_to_string(&path)?.trim().parse()?;
+        assert_eq!(pid, std::process::id());
+        drop(pidfile);
+        assert!(!path.exists());
+        Ok(())
+    }
+}
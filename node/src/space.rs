@@ -1,37 +1,83 @@
 use std::collections::HashMap;
-use std::path::PathBuf;
-use std::sync::Arc;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock as SyncRwLock};
 
-use anyhow::Result;
-use events::{Event, EVENT_SQL_READ_FIELDS};
-use iroh::docs::{Author, NamespaceId, NamespaceSecret};
-use rusqlite::params;
+use anyhow::{Context, Result};
+use events::{Event, EventKind, EVENT_SQL_READ_FIELDS};
+use iroh::base::ticket::BlobTicket;
+use iroh::docs::{Author, DocTicket, NamespaceId, NamespaceSecret};
+use iroh::net::key::PublicKey;
 use serde::{Deserialize, Serialize};
-use tokio::sync::RwLock;
+use tokio::sync::{watch, Mutex, RwLock};
 use uuid::Uuid;
 
 use crate::router::RouterClient;
 
-use self::db::{open_db, setup_db, DB};
+use self::clock::{Clock, SkewLimit};
+use self::db::{check_db, open_db, open_memory_db, setup_db, DB};
+use self::event_rates::{AuthorRate, EventRates};
+use self::fork::ForkOptions;
+use self::hooks::Hooks;
+use self::recovery::{Recovery, UnreadableSpace};
+use self::sensitive::FieldKey;
+use self::templates::{ExportedTemplate, TemplateOptions};
 
+pub mod archive;
+pub mod attachments;
+pub mod audit;
 pub mod capabilities;
-mod db;
+pub mod clock;
+pub(crate) mod db;
+pub mod event_rates;
 pub mod events;
+pub mod external_sources;
+pub mod feed;
+pub mod fork;
+pub mod grants;
+pub mod hooks;
+pub mod imports;
+pub mod inbox;
+pub mod join;
+pub mod mirrors;
+pub mod outbox;
 pub mod programs;
+pub mod recovery;
+pub mod render;
+pub mod restore;
+pub mod retention;
+pub mod rotation;
 pub mod rows;
+pub mod runs;
+pub mod schedules;
+pub mod search;
 pub mod secrets;
+pub mod sensitive;
 pub mod space_events;
 pub mod tables;
+pub mod templates;
 pub mod tickets;
+pub mod triggers;
 pub mod users;
+pub(crate) mod validation;
 
 #[derive(Debug, Clone)]
 pub struct Space {
     pub id: Uuid,
     pub name: String,
-    secret: SpaceSecret,
+    /// shared by every handle to the space, so a rotation reaches all of them
+    secret: Arc<SyncRwLock<SpaceSecret>>,
+    /// keys of secrets the space rotated away from, for opening fields they sealed
+    retired_keys: Arc<SyncRwLock<Vec<FieldKey>>>,
     router: RouterClient,
     db: DB,
+    rates: EventRates,
+    hooks: Hooks,
+    /// orders the space's events, see [`clock`]
+    clock: Clock,
+    /// serializes audit log appends
+    audit_lock: Arc<Mutex<()>>,
+    /// bumped on every row write, see [`rows::Rows::subscribe`]
+    row_writes: Arc<watch::Sender<u64>>,
 }
 
 impl Space {
@@ -40,17 +86,58 @@ impl Space {
         name: String,
         secret: SpaceSecret,
         router: RouterClient,
+        rates: EventRates,
+        hooks: Hooks,
+        skew: SkewLimit,
         repo_base: impl Into<PathBuf>,
     ) -> Result<Self> {
         let path = repo_base.into().join(format!("{}.db", name));
         let db = open_db(&path).await?;
+        check_db(&*db.lock().await).with_context(|| format!("opening space {name}"))?;
         setup_db(&db).await?;
+        // spaces.json holds the secret the space was created with
+        let secret = rotation::current_secret(&db).await?.unwrap_or(secret);
+        let retired_keys = rotation::retired_field_keys(&db).await?;
+        let clock = Clock::new(clock::latest(&db).await?, skew);
         Ok(Space {
             id,
             name,
-            secret,
+            secret: Arc::new(SyncRwLock::new(secret)),
+            retired_keys: Arc::new(SyncRwLock::new(retired_keys)),
+            router,
+            db,
+            rates,
+            hooks,
+            clock,
+            audit_lock: Default::default(),
+            row_writes: Arc::new(watch::Sender::new(0)),
+        })
+    }
+
+    /// Open a space backed by an in-memory database.
+    pub(crate) async fn open_in_memory(
+        id: Uuid,
+        name: String,
+        secret: SpaceSecret,
+        router: RouterClient,
+        rates: EventRates,
+        hooks: Hooks,
+        skew: SkewLimit,
+    ) -> Result<Self> {
+        let db = open_memory_db().await?;
+        setup_db(&db).await?;
+        Ok(Space {
+            id,
+            name,
+            secret: Arc::new(SyncRwLock::new(secret)),
+            retired_keys: Default::default(),
             router,
             db,
+            rates,
+            hooks,
+            clock: Clock::new(Default::default(), skew),
+            audit_lock: Default::default(),
+            row_writes: Arc::new(watch::Sender::new(0)),
         })
     }
 
@@ -62,15 +149,60 @@ impl Space {
         &self.router
     }
 
+    /// Hooks called as the space changes, shared with every other space. See [`hooks`].
+    pub fn hooks(&self) -> &Hooks {
+        &self.hooks
+    }
+
+    /// The clock new events are written at, see [`clock`].
+    pub(crate) fn clock(&self) -> &Clock {
+        &self.clock
+    }
+
     pub fn details(&self) -> SpaceDetails {
         SpaceDetails {
             id: self.id,
             name: self.name.clone(),
             // TODO: nooooooo
-            secret: self.secret.clone(),
+            secret: self.secret(),
         }
     }
 
+    pub(crate) fn secret(&self) -> SpaceSecret {
+        self.secret.read().expect("poisoned").clone()
+    }
+
+    fn set_secret(&self, secret: SpaceSecret) {
+        *self.secret.write().expect("poisoned") = secret;
+    }
+
+    /// Keys that open sealed row fields: the current secret's first, then those of every
+    /// secret the space rotated away from. See [`sensitive`].
+    pub(crate) fn field_keys(&self) -> Vec<FieldKey> {
+        let mut keys = vec![FieldKey::new(&self.secret())];
+        keys.extend(self.retired_keys.read().expect("poisoned").iter().cloned());
+        keys
+    }
+
+    fn retire_key(&self, key: FieldKey) {
+        self.retired_keys.write().expect("poisoned").push(key);
+    }
+
+    /// Replace the space's secret with a new one, sealed to every member except `exclude`.
+    /// See [`rotation`].
+    pub async fn rotate_secret(
+        &self,
+        author: Author,
+        exclude: &[PublicKey],
+    ) -> Result<rotation::SecretRotation> {
+        rotation::rotate(self, author, exclude).await
+    }
+
+    /// Every rotation of the space's secret, oldest first.
+    pub async fn secret_rotations(&self) -> Result<Vec<rotation::Rotation>> {
+        rotation::list(self).await
+    }
+
     pub fn users(&self) -> users::Users {
         users::Users::new(self.clone())
     }
@@ -91,27 +223,207 @@ impl Space {
         rows::Rows::new(self.clone())
     }
 
-    pub async fn search(&self, query: &str, offset: i64, limit: i64) -> Result<Vec<Event>> {
-        let conn = self.db.lock().await;
-        let mut stmt = conn.prepare(
-            format!("SELECT {EVENT_SQL_READ_FIELDS} FROM events WHERE content LIKE '%' || ?1 || '%' COLLATE NOCASE ORDER BY created_at DESC LIMIT ?2 OFFSET ?3").as_str()
-        )?;
-        let mut rows = stmt.query(params![query, limit, offset])?;
-        let mut events = Vec::new();
-        while let Some(row) = rows.next()? {
-            events.push(Event::from_sql_row(row)?);
+    /// The history of program runs.
+    pub fn runs(&self) -> runs::Runs {
+        runs::Runs::new(self.clone())
+    }
+
+    /// Who may write what in each table, see [`capabilities`].
+    pub fn capabilities(&self) -> capabilities::Capabilities {
+        capabilities::Capabilities::new(self.clone())
+    }
+
+    /// Programs run when matching rows are written to a table.
+    pub fn triggers(&self) -> triggers::Triggers {
+        triggers::Triggers::new(self.clone())
+    }
+
+    /// Programs of other spaces allowed to use this one.
+    pub fn grants(&self) -> grants::Grants {
+        grants::Grants::new(self.clone())
+    }
+
+    /// Programs run on a timer.
+    pub fn schedules(&self) -> schedules::Schedules {
+        schedules::Schedules::new(self.clone())
+    }
+
+    pub fn external_sources(&self) -> external_sources::ExternalSources {
+        external_sources::ExternalSources::new(self.clone())
+    }
+
+    /// Images & videos attached to rows, and their thumbnails.
+    pub fn attachments(&self) -> attachments::Attachments {
+        attachments::Attachments::new(self.clone())
+    }
+
+    /// Tables kept in sync with Google Sheets & Airtable.
+    pub fn imports(&self) -> imports::Imports {
+        imports::Imports::new(self.clone())
+    }
+
+    /// Rules that expire old rows of a table.
+    pub fn retention(&self) -> retention::Retention {
+        retention::Retention::new(self.clone())
+    }
+
+    /// Tables replicated to external SQL databases.
+    pub fn mirrors(&self) -> mirrors::Mirrors {
+        mirrors::Mirrors::new(self.clone())
+    }
+
+    /// Events published for read replicas.
+    pub fn feed(&self) -> feed::Feed {
+        feed::Feed::new(self.clone())
+    }
+
+    /// Previews & restores of the space from a backup of its db.
+    pub fn restore(&self) -> restore::Restore {
+        restore::Restore::new(self.clone())
+    }
+
+    /// Events waiting for a peer to acknowledge them.
+    pub fn outbox(&self) -> outbox::Outbox {
+        outbox::Outbox::new(self.clone())
+    }
+
+    /// Events other members published to the space's feed.
+    pub fn inbox(&self) -> inbox::Inbox {
+        inbox::Inbox::new(self.clone())
+    }
+
+    /// The log of administrative actions taken in the space.
+    pub fn audit(&self) -> audit::Audit {
+        audit::Audit::new(self.clone())
+    }
+
+    /// Administrative actions in the order they were taken.
+    pub async fn audit_log(&self, offset: i64, limit: i64) -> Result<Vec<audit::AuditEntry>> {
+        self.audit().list(offset, limit).await
+    }
+
+    /// Write the space's events & every blob they link to into a single file at `path`, to
+    /// back it up or move it to another node, see [`archive`](mod@archive).
+    pub async fn export_archive(&self, path: impl AsRef<Path>) -> Result<archive::ArchiveSummary> {
+        archive::export(self, path.as_ref()).await
+    }
+
+    /// Package the tables, programs, triggers & retention policies of this space, without
+    /// any rows, as a template others can start spaces from.
+    pub async fn export_template(&self, options: TemplateOptions) -> Result<ExportedTemplate> {
+        templates::export(self, &options).await
+    }
+
+    /// Event write rates by author.
+    pub fn event_rates(&self) -> Vec<AuthorRate> {
+        self.rates.rates(self.id)
+    }
+
+    /// Write an event, counting it toward the author's write rate. Throttled authors are
+    /// refused.
+    pub(crate) async fn write_event(&self, event: &Event) -> Result<()> {
+        self.capabilities().check(event).await?;
+        self.rates.record(self.id, &event.pubkey.to_string())?;
+        event.write(&self.db).await?;
+        // the outbox retries events it couldn't publish, no reason to fail the write
+        if let Err(err) = self.outbox().send(event).await {
+            tracing::warn!("queueing event {} for the feed: {:?}", event.id, err);
+        }
+        self.event_written(event).await;
+        Ok(())
+    }
+
+    /// Write an event another member published, see [`inbox`]. It's theirs to publish, so
+    /// it doesn't go through the outbox or count against local rate limits. Events whose clock
+    /// is too far ahead of this node's are refused.
+    pub(crate) async fn ingest_event(&self, event: &Event) -> Result<()> {
+        validation::validate(&self.db, &self.router, event).await?;
+        self.clock.observe(event.hlc()?)?;
+        self.capabilities().check(event).await?;
+        event.write(&self.db).await?;
+        self.event_written(event).await;
+        Ok(())
+    }
+
+    async fn event_written(&self, event: &Event) {
+        if matches!(event.kind, EventKind::MutateRow | EventKind::DeleteRow) {
+            self.row_writes.send_modify(|writes| *writes += 1);
+        }
+        // missed events are indexed before the next search
+        if let Err(err) = search::index_event(self, event).await {
+            tracing::warn!("indexing event {} for search: {:?}", event.id, err);
         }
-        Ok(events)
+        self.hooks.event_written(self, event);
+    }
+
+    /// Full-text search over rows, tables & programs, best matches first.
+    pub async fn search(
+        &self,
+        query: &str,
+        offset: i64,
+        limit: i64,
+    ) -> Result<Vec<search::SearchMatch>> {
+        search::search(self, query, offset, limit).await
     }
 }
 
 const SPACES_FILENAME: &str = "spaces.json";
 
+/// An event matched by [`Spaces::search_all`], tagged with the space it's from.
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+#[serde(rename_all = "camelCase")]
+pub struct SearchResult {
+    pub space_id: Uuid,
+    pub space_name: String,
+    #[serde(flatten)]
+    pub event: Event,
+    /// relevance within its space, higher is better
+    pub score: f64,
+    pub snippet: Vec<search::SnippetPart>,
+}
+
+/// One page of a list, with the total to render page controls from. A `limit` of -1 means
+/// the page holds everything from `offset` on.
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+#[serde(rename_all = "camelCase")]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// how many items there are across every page
+    pub total: i64,
+    pub offset: i64,
+    pub limit: i64,
+}
+
+impl<T> Page<T> {
+    pub fn new(items: Vec<T>, total: i64, offset: i64, limit: i64) -> Self {
+        Page {
+            items,
+            total,
+            offset,
+            limit,
+        }
+    }
+
+    /// Turn each item into another, keeping the page's place.
+    pub fn map<U>(self, f: impl FnMut(T) -> U) -> Page<U> {
+        Page {
+            items: self.items.into_iter().map(f).collect(),
+            total: self.total,
+            offset: self.offset,
+            limit: self.limit,
+        }
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
 pub struct SpaceDetails {
     pub id: Uuid,
     pub name: String,
     // TODO - this shouldn't be here.
+    #[cfg_attr(feature = "specta", specta(skip))]
     pub secret: SpaceSecret,
 }
 
@@ -122,27 +434,65 @@ pub type SpaceId = NamespaceId;
 pub struct Spaces {
     path: PathBuf,
     spaces: Arc<RwLock<HashMap<Uuid, Space>>>,
+    /// listed spaces that failed to open, see [`recovery`]
+    unreadable: Arc<RwLock<Vec<UnreadableSpace>>>,
+    rates: EventRates,
+    hooks: Hooks,
+    skew: SkewLimit,
 }
 
 impl Spaces {
     pub async fn open_all(router: RouterClient, base_path: impl Into<PathBuf>) -> Result<Self> {
         let path = base_path.into();
         let spaces = Self::read_from_file(&path).await?;
+        let rates = EventRates::default();
+        let hooks = Hooks::default();
+        let skew = SkewLimit::default();
         let mut map = HashMap::new();
+        let mut unreadable = Vec::new();
         for deets in spaces {
-            let space = Space::open(
+            let opened = Space::open(
                 deets.id,
-                deets.name,
+                deets.name.clone(),
                 deets.secret,
                 router.clone(),
+                rates.clone(),
+                hooks.clone(),
+                skew.clone(),
                 path.clone(),
             )
-            .await?;
+            .await;
+            let space = match opened {
+                Ok(space) => space,
+                Err(err) => {
+                    tracing::warn!("leaving space {} unopened: {:?}", deets.name, err);
+                    unreadable.push(UnreadableSpace {
+                        id: deets.id,
+                        name: deets.name,
+                        error: format!("{err:#}"),
+                    });
+                    continue;
+                }
+            };
+            if let Err(err) = space.external_sources().watch_all().await {
+                tracing::warn!(
+                    "failed to resume external sources for {}: {:?}",
+                    space.name,
+                    err
+                );
+            }
+            space.imports().schedule_all();
+            space.retention().schedule_all();
+            space.mirrors().schedule_all();
             map.insert(space.id.clone(), space);
         }
         Ok(Self {
             path,
             spaces: Arc::new(RwLock::new(map)),
+            unreadable: Arc::new(RwLock::new(unreadable)),
+            rates,
+            hooks,
+            skew,
         })
     }
 
@@ -178,6 +528,9 @@ impl Spaces {
             name.to_string(),
             secret,
             router.clone(),
+            self.rates.clone(),
+            self.hooks.clone(),
+            self.skew.clone(),
             self.path.clone(),
         )
         .await?;
@@ -194,13 +547,213 @@ impl Spaces {
         let mut spaces = self.spaces.write().await;
         spaces.insert(id.clone(), space.clone());
 
-        let mut details = Spaces::read_from_file(self.path.join(SPACES_FILENAME)).await?;
+        let mut details = Spaces::read_from_file(&self.path).await?;
         details.push(new);
         self.write_to_file(details).await?;
 
         Ok(space)
     }
 
+    /// Create a space that only lives in memory. It can be found by id & name like any other
+    /// space, but isn't listed or persisted. Drop it with [`Spaces::remove_ephemeral`].
+    pub async fn create_ephemeral(
+        &self,
+        router: &RouterClient,
+        author: Author,
+        name: &str,
+    ) -> Result<Space> {
+        anyhow::ensure!(
+            self.get_by_name(name).await.is_none(),
+            "a space named {name} already exists"
+        );
+        let id = Uuid::new_v4();
+        let secret = NamespaceSecret::new(&mut rand::thread_rng());
+        let space = Space::open_in_memory(
+            id,
+            name.to_string(),
+            secret,
+            router.clone(),
+            self.rates.clone(),
+            self.hooks.clone(),
+            self.skew.clone(),
+        )
+        .await?;
+        space_events::SpaceEvents::new(space.clone())
+            .mutate(
+                author,
+                id,
+                space_events::SpaceDetails {
+                    title: name.to_string(),
+                    description: String::new(),
+                },
+            )
+            .await?;
+        self.spaces.write().await.insert(id, space.clone());
+        Ok(space)
+    }
+
+    /// Start joining the space `ticket` is a write ticket for, under `name`. See [`join`](mod@join).
+    pub async fn join(
+        &self,
+        router: &RouterClient,
+        ticket: DocTicket,
+        name: &str,
+    ) -> Result<join::SpaceJoin> {
+        join::start(self, router, ticket, name).await
+    }
+
+    /// Open the space in an archive written by [`Space::export_archive`] & add it to the list.
+    /// The space keeps its id, name & secret, so neither may already be on this node.
+    pub async fn import_archive(
+        &self,
+        router: &RouterClient,
+        path: impl AsRef<Path>,
+    ) -> Result<Space> {
+        archive::import(self, router, path.as_ref()).await
+    }
+
+    /// Open a space whose db was written by a join or an archive & add it to the list.
+    async fn register(
+        &self,
+        router: &RouterClient,
+        id: Uuid,
+        name: &str,
+        secret: SpaceSecret,
+    ) -> Result<Space> {
+        let space = Space::open(
+            id,
+            name.to_string(),
+            secret.clone(),
+            router.clone(),
+            self.rates.clone(),
+            self.hooks.clone(),
+            self.skew.clone(),
+            self.path.clone(),
+        )
+        .await?;
+        self.spaces.write().await.insert(id, space.clone());
+
+        let mut details = Spaces::read_from_file(&self.path).await?;
+        details.push(SpaceDetails {
+            id,
+            name: name.to_string(),
+            secret,
+        });
+        self.write_to_file(details).await?;
+        Ok(space)
+    }
+
+    pub async fn remove_ephemeral(&self, id: &Uuid) {
+        self.spaces.write().await.remove(id);
+    }
+
+    /// Create a new space with its own secret from the tables, selected rows & programs of
+    /// `source_id`. Everything copied is re-authored by `author`, nothing of the source's event
+    /// history comes along. If copying fails the new space is removed again.
+    pub async fn fork(
+        &mut self,
+        author: Author,
+        source_id: &Uuid,
+        name: &str,
+        options: ForkOptions,
+    ) -> Result<Space> {
+        let source = self
+            .get(source_id)
+            .await
+            .context("source space not found")?;
+        anyhow::ensure!(
+            self.get_by_name(name).await.is_none(),
+            "a space named {name} already exists"
+        );
+        let description = match options.description.clone() {
+            Some(description) => description,
+            None => space_events::SpaceEvents::new(source.clone())
+                .details()
+                .await?
+                .map(|details| details.description)
+                .unwrap_or_default(),
+        };
+
+        let router = source.router().clone();
+        let forked = self
+            .create(&router, author.clone(), name, &description)
+            .await?;
+        if let Err(err) = fork::copy_into(&source, &forked, author, &options).await {
+            if let Err(remove_err) = self.remove(&forked.id).await {
+                tracing::warn!(
+                    "failed to remove partial fork {}: {:?}",
+                    forked.name,
+                    remove_err
+                );
+            }
+            return Err(err.context(format!("forking {}", source.name)));
+        }
+        Ok(forked)
+    }
+
+    /// Create a new space from the template behind `ticket`, downloading it if it's on another
+    /// node. Everything in the template is written by `author`. If that fails the new space is
+    /// removed again.
+    pub async fn create_from_template(
+        &mut self,
+        router: &RouterClient,
+        author: Author,
+        name: &str,
+        ticket: &BlobTicket,
+    ) -> Result<Space> {
+        anyhow::ensure!(
+            self.get_by_name(name).await.is_none(),
+            "a space named {name} already exists"
+        );
+        let template = templates::fetch(router, ticket).await?;
+        let space = self
+            .create(router, author.clone(), name, &template.description)
+            .await?;
+        if let Err(err) =
+            templates::instantiate(&space, author, &template, ticket.node_addr()).await
+        {
+            if let Err(remove_err) = self.remove(&space.id).await {
+                tracing::warn!(
+                    "failed to remove partial space {}: {:?}",
+                    space.name,
+                    remove_err
+                );
+            }
+            return Err(err.context(format!("creating {name} from template {}", template.name)));
+        }
+        Ok(space)
+    }
+
+    /// Forget a space & delete its database.
+    pub(crate) async fn remove(&self, id: &Uuid) -> Result<()> {
+        let Some(space) = self.spaces.write().await.remove(id) else {
+            return Ok(());
+        };
+        let mut details = Spaces::read_from_file(&self.path).await?;
+        details.retain(|details| details.id != *id);
+        self.write_to_file(details).await?;
+        let db_path = self.path.join(format!("{}.db", space.name));
+        if db_path.exists() {
+            tokio::fs::remove_file(&db_path).await?;
+        }
+        Ok(())
+    }
+
+    /// Event write rates across all spaces, with spike alerts.
+    pub fn event_rates(&self) -> &EventRates {
+        &self.rates
+    }
+
+    /// Hooks called as any of the spaces change, see [`hooks`].
+    pub fn hooks(&self) -> &Hooks {
+        &self.hooks
+    }
+
+    /// How far ahead of this node's clock events other members write may be, see [`clock`].
+    pub fn clock_skew(&self) -> &SkewLimit {
+        &self.skew
+    }
+
     pub async fn get(&self, id: &Uuid) -> Option<Space> {
         self.spaces.read().await.get(id).cloned()
     }
@@ -214,6 +767,16 @@ impl Spaces {
             .cloned()
     }
 
+    /// Listed spaces that failed to open, eg: because their database is damaged.
+    pub async fn unreadable(&self) -> Vec<UnreadableSpace> {
+        self.unreadable.read().await.clone()
+    }
+
+    /// Open the database of the space named `name` read-only, to salvage what's in it.
+    pub fn recovery(&self, name: &str) -> Result<Recovery> {
+        Recovery::open(self.path.join(format!("{}.db", name)))
+    }
+
     fn spaces_path(path: impl Into<PathBuf>) -> PathBuf {
         path.into().join(SPACES_FILENAME)
     }
@@ -239,6 +802,40 @@ impl Spaces {
         Ok(results)
     }
 
+    /// Search every listed space, best matches first. Each space is searched for up to `limit`
+    /// events, the merged results are cut back to `limit`. Scores are relative to the space
+    /// they're from, so the order across spaces is approximate. Spaces that fail to search are
+    /// logged & left out.
+    pub async fn search_all(&self, query: &str, limit: i64) -> Result<Vec<SearchResult>> {
+        let mut spaces = Vec::new();
+        for details in self.list(0, -1).await? {
+            if let Some(space) = self.get(&details.id).await {
+                spaces.push(space);
+            }
+        }
+        let searches = spaces.iter().map(|space| space.search(query, 0, limit));
+        let mut results = Vec::new();
+        for (space, events) in spaces.iter().zip(futures::future::join_all(searches).await) {
+            let events = match events {
+                Ok(events) => events,
+                Err(err) => {
+                    tracing::warn!("searching space {}: {:?}", space.id, err);
+                    continue;
+                }
+            };
+            results.extend(events.into_iter().map(|found| SearchResult {
+                space_id: space.id,
+                space_name: space.name.clone(),
+                event: found.event,
+                score: found.score,
+                snippet: found.snippet,
+            }));
+        }
+        results.sort_by(|a, b| b.score.total_cmp(&a.score));
+        results.truncate(limit.max(0) as usize);
+        Ok(results)
+    }
+
     // async fn write_all(
     //     base_path: impl Into<PathBuf>,
     //     spaces: HashMap<String, Space>,
@@ -254,3 +851,108 @@ impl Spaces {
     //     Spaces::write_to_file(path, spaces).await
     // }
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn search_all_spans_spaces() -> Result<()> {
+        let node = iroh::node::Node::memory().spawn().await?;
+        let dir = tempfile::tempdir()?;
+        let router = node.client().clone();
+        let mut spaces = Spaces::open_all(router.clone(), dir.path()).await?;
+        let author = Author::new(&mut rand::thread_rng());
+
+        let schema = bytes::Bytes::from(json!({ "title": "notes", "type": "object" }).to_string());
+        let mut ids = Vec::new();
+        for name in ["one", "two"] {
+            let space = spaces
+                .create(&router, author.clone(), name, "a space")
+                .await?;
+            let table = space
+                .tables()
+                .create(author.clone(), schema.clone())
+                .await?;
+            space
+                .rows()
+                .create(
+                    author.clone(),
+                    table.content.hash,
+                    json!({ "note": format!("needle in {name}") }),
+                )
+                .await?;
+            ids.push(space.id);
+        }
+
+        let results = spaces.search_all("needle", 10).await?;
+        let mut found: Vec<Uuid> = results.iter().map(|result| result.space_id).collect();
+        found.sort();
+        ids.sort();
+        assert_eq!(found, ids);
+        assert!(results
+            .windows(2)
+            .all(|pair| pair[0].event.created_at >= pair[1].event.created_at));
+        assert_eq!(spaces.search_all("needle", 1).await?.len(), 1);
+        assert!(spaces.search_all("haystack", 10).await?.is_empty());
+
+        // a space that can't be searched doesn't hide the others
+        let broken = spaces.get(&ids[0]).await.unwrap();
+        broken
+            .db
+            .lock()
+            .await
+            .execute("DROP TABLE events_fts", [])?;
+        let results = spaces.search_all("needle", 10).await?;
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].space_id, ids[1]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn list_pages_carry_totals() -> Result<()> {
+        let node = iroh::node::Node::memory().enable_docs().spawn().await?;
+        let dir = tempfile::tempdir()?;
+        let router = node.client().clone();
+        let mut spaces = Spaces::open_all(router.clone(), dir.path()).await?;
+        let author = Author::new(&mut rand::thread_rng());
+        let space = spaces
+            .create(&router, author.clone(), "pages", "a space")
+            .await?;
+
+        for title in ["one", "two", "three"] {
+            let schema = json!({ "title": title, "type": "object" });
+            space
+                .tables()
+                .create(author.clone(), bytes::Bytes::from(schema.to_string()))
+                .await?;
+        }
+        let page = space.tables().page(1, 1).await?;
+        assert_eq!((page.items.len(), page.total), (1, 3));
+        assert_eq!((page.offset, page.limit), (1, 1));
+        let page = space.tables().page(0, -1).await?;
+        assert_eq!((page.items.len(), page.total), (3, 3));
+        let page = space.tables().page(5, 10).await?;
+        assert_eq!((page.items.len(), page.total), (0, 3));
+
+        let users = space.users();
+        let initial = users.count().await?;
+        let mut created = Vec::new();
+        for name in ["ada", "grace"] {
+            created.push(users.create(users::Profile::new(name, "", "")).await?);
+        }
+        let gone = created.pop().expect("created a user");
+        users
+            .remove(gone.author.clone().expect("own author"), gone.id)
+            .await?;
+        let page = users.page(0, -1).await?;
+        assert_eq!(page.total, initial + 1, "removed users aren't counted");
+        assert_eq!(page.items.len() as i64, page.total);
+        let page = page.map(|user| user.id);
+        assert!(page.items.contains(&created[0].id));
+        assert!(!page.items.contains(&gone.id));
+        Ok(())
+    }
+}
@@ -0,0 +1,462 @@
+//! Moving a space between nodes as a single file.
+//!
+//! An archive is a tar file holding a copy of the space's event db, every blob its events link
+//! to (row content & the attachments rows hold, table schemas, program packages) and a
+//! manifest naming the space & those blobs. [`Space::export_archive`] writes one,
+//! [`super::Spaces::import_archive`] opens it as a space on another node without any network.
+//! Program packages are stored as their files & rebuilt into the same collection on import.
+//!
+//! The manifest carries the space secret, so an archive gives whoever holds it write access
+//! to the space, like a write ticket does. Linked blobs the exporting node doesn't have are
+//! left out & listed as missing, once imported they can be fetched from peers like any other
+//! missing content.
+//!
+//! Archives are as untrusted as anything from a peer. Imports refuse space names that could
+//! reach outside the spaces dir & check every event in the db like events from peers, see
+//! [`super::validation`].
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Context, Result};
+use iroh::blobs::format::collection::Collection;
+use iroh::blobs::util::SetTagOption;
+use iroh::blobs::Hash;
+use iroh::client::blobs::{BlobStatus, WrapOption};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::router::RouterClient;
+
+use super::attachments::find_attachments;
+use super::db::{check_db, open_db};
+use super::events::{Event, EventKind, EVENT_SQL_SIGNED_READ_FIELDS};
+use super::validation;
+use super::{Space, SpaceSecret, Spaces};
+
+/// Version of the archive layout, archives from a newer version are refused.
+const ARCHIVE_VERSION: u32 = 1;
+const MANIFEST_FILENAME: &str = "manifest.json";
+const DB_FILENAME: &str = "events.db";
+const BLOBS_DIR: &str = "blobs";
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Manifest {
+    version: u32,
+    id: Uuid,
+    name: String,
+    secret: SpaceSecret,
+    exported_at: i64,
+    /// blobs in the archive, each stored under its hash
+    blobs: Vec<Hash>,
+    /// program packages, rebuilt from their entries on import
+    collections: Vec<ArchivedCollection>,
+    /// linked blobs the exporting node didn't have
+    missing: Vec<Hash>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ArchivedCollection {
+    hash: Hash,
+    entries: Vec<(String, Hash)>,
+}
+
+/// What went into an archive.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+#[serde(rename_all = "camelCase")]
+pub struct ArchiveSummary {
+    pub events: i64,
+    pub blobs: usize,
+    /// size of the blobs in the archive
+    pub bytes: u64,
+    /// linked blobs this node doesn't have, left out of the archive
+    #[cfg_attr(feature = "specta", specta(type = Vec<String>))]
+    pub missing: Vec<Hash>,
+}
+
+/// The blobs a space's events link to.
+#[derive(Debug, Default)]
+struct Linked {
+    blobs: BTreeSet<Hash>,
+    collections: Vec<ArchivedCollection>,
+    missing: BTreeSet<Hash>,
+}
+
+pub(crate) async fn export(space: &Space, path: &Path) -> Result<ArchiveSummary> {
+    let staging = tempfile::tempdir().context("creating staging dir")?;
+    let events = {
+        let conn = space.db().lock().await;
+        let db_path = staging.path().join(DB_FILENAME);
+        conn.execute("VACUUM INTO ?1", [db_path.to_string_lossy()])
+            .context("copying the event db")?;
+        conn.query_row("SELECT COUNT(*) FROM events", [], |row| row.get(0))?
+    };
+
+    let router = space.router();
+    let linked = linked_blobs(space).await?;
+    let blobs_dir = staging.path().join(BLOBS_DIR);
+    tokio::fs::create_dir_all(&blobs_dir).await?;
+    let mut bytes = 0;
+    for hash in linked.blobs.iter() {
+        let mut reader = router.blobs().read(*hash).await?;
+        let mut file = tokio::fs::File::create(blobs_dir.join(hash.to_string())).await?;
+        bytes += tokio::io::copy(&mut reader, &mut file)
+            .await
+            .with_context(|| format!("exporting blob {hash}"))?;
+    }
+
+    let manifest = Manifest {
+        version: ARCHIVE_VERSION,
+        id: space.id,
+        name: space.name.clone(),
+        secret: space.secret(),
+        exported_at: chrono::Utc::now().timestamp(),
+        blobs: linked.blobs.iter().copied().collect(),
+        collections: linked.collections,
+        missing: linked.missing.iter().copied().collect(),
+    };
+    tokio::fs::write(
+        staging.path().join(MANIFEST_FILENAME),
+        serde_json::to_vec_pretty(&manifest)?,
+    )
+    .await?;
+
+    let root = staging.path().to_path_buf();
+    let out = path.to_path_buf();
+    tokio::task::spawn_blocking(move || write_tar(&root, &out))
+        .await?
+        .with_context(|| format!("writing archive {}", path.display()))?;
+
+    Ok(ArchiveSummary {
+        events,
+        blobs: manifest.blobs.len(),
+        bytes,
+        missing: manifest.missing,
+    })
+}
+
+fn write_tar(root: &Path, path: &Path) -> Result<()> {
+    let file = std::fs::File::create(path)?;
+    let mut builder = tar::Builder::new(file);
+    builder.append_path_with_name(root.join(MANIFEST_FILENAME), MANIFEST_FILENAME)?;
+    builder.append_path_with_name(root.join(DB_FILENAME), DB_FILENAME)?;
+    builder.append_dir_all(BLOBS_DIR, root.join(BLOBS_DIR))?;
+    builder.into_inner()?.sync_all()?;
+    Ok(())
+}
+
+async fn linked_blobs(space: &Space) -> Result<Linked> {
+    let contents = {
+        let conn = space.db().lock().await;
+        let mut stmt = conn.prepare("SELECT DISTINCT kind, content_hash FROM events")?;
+        let mut rows = stmt.query([])?;
+        let mut contents = Vec::new();
+        while let Some(row) = rows.next()? {
+            let kind: EventKind = row.get(0)?;
+            let hash: String = row.get(1)?;
+            contents.push((kind, hash));
+        }
+        contents
+    };
+
+    let router = space.router();
+    let mut linked = Linked::default();
+    let mut packages = BTreeSet::new();
+    for (kind, hash) in contents {
+        let Ok(hash) = Hash::from_str(&hash) else {
+            continue;
+        };
+        if !is_complete(router, hash).await? {
+            linked.missing.insert(hash);
+            continue;
+        }
+        match kind {
+            // programs are collections, the package files go in the archive
+            EventKind::MutateProgram => {
+                if !packages.insert(hash) {
+                    continue;
+                }
+                let collection = router.blobs().get_collection(hash).await?;
+                let entries = collection.into_iter().collect::<Vec<_>>();
+                for (_, entry) in entries.iter() {
+                    linked.blobs.insert(*entry);
+                }
+                linked
+                    .collections
+                    .push(ArchivedCollection { hash, entries });
+            }
+            EventKind::MutateRow => {
+                linked.blobs.insert(hash);
+                let data = router.blobs().read_to_bytes(hash).await?;
+                if let Ok(value) = serde_json::from_slice(&data) {
+                    for attachment in find_attachments(&value) {
+                        linked.blobs.insert(attachment.hash);
+                    }
+                }
+            }
+            _ => {
+                linked.blobs.insert(hash);
+            }
+        }
+    }
+
+    // attachments & package files can be missing even when what links to them isn't
+    let mut present = BTreeSet::new();
+    for hash in linked.blobs {
+        if is_complete(router, hash).await? {
+            present.insert(hash);
+        } else {
+            linked.missing.insert(hash);
+        }
+    }
+    linked.blobs = present;
+    Ok(linked)
+}
+
+async fn is_complete(router: &RouterClient, hash: Hash) -> Result<bool> {
+    let status = router.blobs().status(hash).await?;
+    Ok(matches!(status, BlobStatus::Complete { .. }))
+}
+
+pub(crate) async fn import(spaces: &Spaces, router: &RouterClient, path: &Path) -> Result<Space> {
+    let staging = tempfile::tempdir().context("creating staging dir")?;
+    let root = staging.path().to_path_buf();
+    let archive = path.to_path_buf();
+    tokio::task::spawn_blocking(move || unpack(&archive, &root))
+        .await?
+        .with_context(|| format!("reading archive {}", path.display()))?;
+    let manifest: Manifest =
+        serde_json::from_slice(&tokio::fs::read(staging.path().join(MANIFEST_FILENAME)).await?)
+            .context("reading archive manifest")?;
+    if manifest.version > ARCHIVE_VERSION {
+        bail!(
+            "archive is from a newer version (layout {}, this node reads up to {ARCHIVE_VERSION})",
+            manifest.version
+        );
+    }
+    check_space_name(&manifest.name)?;
+    if spaces.get(&manifest.id).await.is_some() {
+        bail!("space {} is already on this node", manifest.id);
+    }
+    if spaces.get_by_name(&manifest.name).await.is_some() {
+        bail!("a space named {} already exists", manifest.name);
+    }
+    let db_path = spaces.path.join(format!("{}.db", manifest.name));
+    if db_path.exists() {
+        bail!("a database for {} already exists", manifest.name);
+    }
+
+    add_blobs(router, &staging.path().join(BLOBS_DIR), &manifest).await?;
+    validate_events(router, &staging.path().join(DB_FILENAME)).await?;
+    tokio::fs::copy(staging.path().join(DB_FILENAME), &db_path)
+        .await
+        .context("copying the event db")?;
+    let registered = spaces
+        .register(router, manifest.id, &manifest.name, manifest.secret.clone())
+        .await;
+    match registered {
+        Ok(space) => Ok(space),
+        Err(err) => {
+            if let Err(remove_err) = tokio::fs::remove_file(&db_path).await {
+                tracing::warn!(
+                    "failed to remove imported db {}: {:?}",
+                    db_path.display(),
+                    remove_err
+                );
+            }
+            Err(err.context(format!("importing {}", manifest.name)))
+        }
+    }
+}
+
+/// Space names become file names, one from an archive must name a file in the spaces dir.
+fn check_space_name(name: &str) -> Result<()> {
+    if name.is_empty() || name.contains(['/', '\\', '\0']) || name.contains("..") {
+        bail!("archive has an invalid space name {name:?}");
+    }
+    Ok(())
+}
+
+/// Check every event in the archive's db, with its blobs already in the store.
+async fn validate_events(router: &RouterClient, db_path: &Path) -> Result<()> {
+    let db = open_db(db_path).await?;
+    let events = {
+        let conn = db.lock().await;
+        check_db(&conn).context("checking the event db")?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {EVENT_SQL_SIGNED_READ_FIELDS} FROM events"
+        ))?;
+        let mut rows = stmt.query([])?;
+        let mut events = Vec::new();
+        while let Some(row) = rows.next()? {
+            events.push(Event::from_signed_sql_row(row)?);
+        }
+        events
+    };
+    for event in events.iter() {
+        validation::validate(&db, router, event).await?;
+    }
+    Ok(())
+}
+
+fn unpack(archive: &Path, root: &Path) -> Result<()> {
+    let file = std::fs::File::open(archive)?;
+    // entries that would land outside of `root` are skipped
+    tar::Archive::new(file).unpack(root)?;
+    Ok(())
+}
+
+/// Add the archive's blobs to the store, checking each is what the manifest says it is.
+async fn add_blobs(router: &RouterClient, dir: &Path, manifest: &Manifest) -> Result<()> {
+    for hash in manifest.blobs.iter() {
+        let path: PathBuf = dir.join(hash.to_string());
+        let outcome = router
+            .blobs()
+            .add_from_path(path, false, SetTagOption::Auto, WrapOption::NoWrap)
+            .await?
+            .finish()
+            .await
+            .with_context(|| format!("importing blob {hash}"))?;
+        if outcome.hash != *hash {
+            return Err(anyhow!(
+                "blob {hash} in the archive has hash {}",
+                outcome.hash
+            ));
+        }
+    }
+    for collection in manifest.collections.iter() {
+        let entries = Collection::from_iter(collection.entries.iter().cloned());
+        let (hash, _) = router
+            .blobs()
+            .create_collection(entries, SetTagOption::Auto, vec![])
+            .await?;
+        if hash != collection.hash {
+            bail!(
+                "program package {} didn't rebuild to the same hash",
+                collection.hash
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use iroh::docs::Author;
+    use serde_json::json;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn moves_spaces() -> Result<()> {
+        let node = iroh::node::Node::memory().spawn().await?;
+        let dir = tempfile::tempdir()?;
+        let router = node.client().clone();
+        let mut spaces = Spaces::open_all(router.clone(), dir.path()).await?;
+        let author = Author::new(&mut rand::thread_rng());
+        let space = spaces
+            .create(&router, author.clone(), "moving", "a space")
+            .await?;
+        let schema = bytes::Bytes::from(json!({ "title": "notes", "type": "object" }).to_string());
+        let table = space.tables().create(author.clone(), schema).await?;
+        let row = space
+            .rows()
+            .create(
+                author.clone(),
+                table.content.hash,
+                json!({ "note": "packed" }),
+            )
+            .await?;
+
+        let archive = dir.path().join("moving.tar");
+        let summary = space.export_archive(&archive).await?;
+        assert!(summary.events >= 3);
+        assert!(summary.missing.is_empty());
+
+        let other = iroh::node::Node::memory().spawn().await?;
+        let other_router = other.client().clone();
+        let other_dir = tempfile::tempdir()?;
+        let others = Spaces::open_all(other_router.clone(), other_dir.path()).await?;
+        let moved = others.import_archive(&other_router, &archive).await?;
+        assert_eq!(moved.id, space.id);
+        assert!(is_complete(&other_router, row.content.hash).await?);
+        let rows = moved
+            .rows()
+            .query(table.content.hash, String::new(), 0, -1)
+            .await?;
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].content.data, Some(json!({ "note": "packed" })));
+
+        assert!(
+            others
+                .import_archive(&other_router, &archive)
+                .await
+                .is_err(),
+            "the space is already here"
+        );
+        Ok(())
+    }
+
+    /// Rewrite the archive at `path`, changing what `edit` changes in its unpacked files.
+    fn tamper(path: &Path, edit: impl FnOnce(&Path) -> Result<()>) -> Result<()> {
+        let staging = tempfile::tempdir()?;
+        unpack(path, staging.path())?;
+        edit(staging.path())?;
+        write_tar(staging.path(), path)
+    }
+
+    #[tokio::test]
+    async fn refuses_tampered_archives() -> Result<()> {
+        let node = iroh::node::Node::memory().spawn().await?;
+        let dir = tempfile::tempdir()?;
+        let router = node.client().clone();
+        let mut spaces = Spaces::open_all(router.clone(), dir.path()).await?;
+        let author = Author::new(&mut rand::thread_rng());
+        let space = spaces
+            .create(&router, author.clone(), "tampered", "a space")
+            .await?;
+        let schema = bytes::Bytes::from(json!({ "title": "notes", "type": "object" }).to_string());
+        space.tables().create(author, schema).await?;
+
+        let other = iroh::node::Node::memory().spawn().await?;
+        let other_router = other.client().clone();
+        let other_dir = tempfile::tempdir()?;
+        let spaces_dir = other_dir.path().join("spaces");
+        tokio::fs::create_dir_all(&spaces_dir).await?;
+        let others = Spaces::open_all(other_router.clone(), &spaces_dir).await?;
+
+        // a name reaching out of the spaces dir
+        let escaping = dir.path().join("escaping.tar");
+        space.export_archive(&escaping).await?;
+        tamper(&escaping, |root| {
+            let path = root.join(MANIFEST_FILENAME);
+            let mut manifest: Manifest = serde_json::from_slice(&std::fs::read(&path)?)?;
+            manifest.name = "../escaped".to_string();
+            std::fs::write(&path, serde_json::to_vec(&manifest)?)?;
+            Ok(())
+        })?;
+        assert!(others
+            .import_archive(&other_router, &escaping)
+            .await
+            .is_err());
+        assert!(!other_dir.path().join("escaped.db").exists());
+
+        // an event changed after it was signed
+        let forged = dir.path().join("forged.tar");
+        space.export_archive(&forged).await?;
+        tamper(&forged, |root| {
+            let conn = rusqlite::Connection::open(root.join(DB_FILENAME))?;
+            conn.execute(
+                "UPDATE events SET created_at = created_at + 1 WHERE rowid = (SELECT MIN(rowid) FROM events)",
+                [],
+            )?;
+            Ok(())
+        })?;
+        assert!(others.import_archive(&other_router, &forged).await.is_err());
+        assert!(others.get(&space.id).await.is_none());
+        assert!(!spaces_dir.join("tampered.db").exists());
+        Ok(())
+    }
+}
@@ -0,0 +1,210 @@
+//! Images & videos attached to rows, and the thumbnails generated from them.
+//!
+//! An attachment is any object in a row's data with a blob `hash` & an image or video
+//! `mimeType`, eg: `{"hash": "<blake3>", "mimeType": "image/png", "name": "cat.png"}`. Rows
+//! can hold them at any depth. Thumbnails are derivative blobs, linked to their original by
+//! hash. They're generated by the VM, see [`crate::vm::thumbnails`].
+use std::str::FromStr;
+
+use anyhow::Result;
+use iroh::blobs::Hash;
+use rusqlite::{params, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::events::{Event, EventKind, EVENT_SQL_READ_FIELDS};
+use super::Space;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Attachment {
+    pub hash: Hash,
+    pub mime_type: String,
+    pub name: Option<String>,
+}
+
+impl Attachment {
+    /// Attachments a thumbnail can be made of.
+    fn from_value(value: &Value) -> Option<Self> {
+        let attachment: Attachment = serde_json::from_value(value.clone()).ok()?;
+        let previewable = attachment.mime_type.starts_with("image/")
+            || attachment.mime_type.starts_with("video/");
+        previewable.then_some(attachment)
+    }
+}
+
+/// Every attachment in a row's data.
+pub fn find_attachments(data: &Value) -> Vec<Attachment> {
+    let mut found = Vec::new();
+    collect(data, &mut found);
+    found
+}
+
+fn collect(value: &Value, found: &mut Vec<Attachment>) {
+    match value {
+        Value::Object(map) => {
+            if map.contains_key("hash") {
+                if let Some(attachment) = Attachment::from_value(value) {
+                    found.push(attachment);
+                    return;
+                }
+            }
+            map.values().for_each(|value| collect(value, found));
+        }
+        Value::Array(values) => values.iter().for_each(|value| collect(value, found)),
+        _ => {}
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Thumbnail {
+    pub original: Hash,
+    /// longest side, in pixels
+    pub size: u32,
+    pub hash: Hash,
+    pub mime_type: String,
+    pub created_at: i64,
+}
+
+/// Attachments found in rows written at or after a point in time, see
+/// [`Attachments::pending`].
+#[derive(Debug, Default)]
+pub struct PendingAttachments {
+    pub attachments: Vec<Attachment>,
+    /// pass as `since` to pick up where this scan stopped
+    pub next: i64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Attachments(Space);
+
+impl Attachments {
+    pub fn new(space: Space) -> Self {
+        Attachments(space)
+    }
+
+    /// Attachments of rows written at or after `since` that have no thumbnail of `size` yet.
+    /// Attachments that failed to thumbnail aren't returned again.
+    pub async fn pending(&self, since: i64, size: u32) -> Result<PendingAttachments> {
+        let conn = self.0.db.lock().await;
+        let mut stmt = conn.prepare(
+            format!("SELECT {EVENT_SQL_READ_FIELDS} FROM events WHERE kind = ?1 AND created_at >= ?2 ORDER BY created_at").as_str(),
+        )?;
+        let mut rows = stmt.query(params![EventKind::MutateRow, since])?;
+        let mut done =
+            conn.prepare("SELECT 1 FROM attachment_thumbnails WHERE original = ?1 AND size = ?2")?;
+        let mut pending = PendingAttachments {
+            attachments: Vec::new(),
+            next: since,
+        };
+        while let Some(row) = rows.next()? {
+            let event = Event::from_sql_row(row)?;
+            pending.next = pending.next.max(event.created_at);
+            let Some(data) = &event.content.data else {
+                continue;
+            };
+            for attachment in find_attachments(data) {
+                if pending
+                    .attachments
+                    .iter()
+                    .any(|a| a.hash == attachment.hash)
+                {
+                    continue;
+                }
+                if !done.exists(params![attachment.hash.to_string(), size])? {
+                    pending.attachments.push(attachment);
+                }
+            }
+        }
+        Ok(pending)
+    }
+
+    /// The thumbnail of an attachment, if one was generated.
+    pub async fn thumbnail(&self, original: Hash, size: u32) -> Result<Option<Thumbnail>> {
+        let conn = self.0.db.lock().await;
+        let thumbnail = conn
+            .query_row(
+                "SELECT thumbnail, mime_type, created_at FROM attachment_thumbnails
+                WHERE original = ?1 AND size = ?2 AND thumbnail IS NOT NULL",
+                params![original.to_string(), size],
+                |row| {
+                    let hash: String = row.get(0)?;
+                    Ok((hash, row.get(1)?, row.get(2)?))
+                },
+            )
+            .optional()?;
+        let Some((hash, mime_type, created_at)) = thumbnail else {
+            return Ok(None);
+        };
+        Ok(Some(Thumbnail {
+            original,
+            size,
+            hash: Hash::from_str(&hash)?,
+            mime_type,
+            created_at,
+        }))
+    }
+
+    /// Link a generated thumbnail to its original.
+    pub async fn record_thumbnail(&self, thumbnail: &Thumbnail) -> Result<()> {
+        let conn = self.0.db.lock().await;
+        conn.execute(
+            "INSERT OR REPLACE INTO attachment_thumbnails
+            (original, size, thumbnail, mime_type, created_at, error)
+            VALUES (?1, ?2, ?3, ?4, ?5, NULL)",
+            params![
+                thumbnail.original.to_string(),
+                thumbnail.size,
+                thumbnail.hash.to_string(),
+                thumbnail.mime_type,
+                thumbnail.created_at,
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Remember an attachment couldn't be thumbnailed, so it isn't tried again.
+    pub async fn record_failure(&self, original: Hash, size: u32, error: &str) -> Result<()> {
+        let conn = self.0.db.lock().await;
+        conn.execute(
+            "INSERT OR REPLACE INTO attachment_thumbnails
+            (original, size, thumbnail, mime_type, created_at, error)
+            VALUES (?1, ?2, NULL, NULL, ?3, ?4)",
+            params![
+                original.to_string(),
+                size,
+                chrono::Utc::now().timestamp(),
+                error
+            ],
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn finds_nested_attachments() {
+        let image = Hash::new(b"image");
+        let video = Hash::new(b"video");
+        let doc = Hash::new(b"doc");
+        let data = json!({
+            "title": "trip",
+            "cover": { "hash": image.to_string(), "mimeType": "image/png", "name": "a.png" },
+            "files": [
+                { "hash": video.to_string(), "mimeType": "video/mp4" },
+                { "hash": doc.to_string(), "mimeType": "application/pdf" },
+                { "hash": "not a hash", "mimeType": "image/png" },
+            ],
+        });
+        let found = find_attachments(&data);
+        let hashes: Vec<_> = found.iter().map(|a| a.hash).collect();
+        assert_eq!(hashes, vec![image, video]);
+        assert_eq!(found[0].name.as_deref(), Some("a.png"));
+    }
+}
@@ -0,0 +1,298 @@
+//! An append-only log of administrative actions taken in a space.
+//!
+//! Entries are [`EventKind::Audit`] events. Each one carries a sequence number & the id of the
+//! entry before it, so the log has one order every replica agrees on and an entry can't be
+//! dropped or moved without breaking the chain, see [`Audit::verify`].
+use anyhow::{anyhow, Context, Result};
+use iroh::docs::{Author, NamespaceId};
+use iroh::net::key::PublicKey;
+use rusqlite::params;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::events::{
+    Event, EventKind, HashLink, Sha256Digest, Tag, EVENT_SQL_READ_FIELDS, NOSTR_ID_TAG,
+};
+use super::grants::SpaceHandle;
+use super::Space;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum AuditAction {
+    #[serde(rename_all = "camelCase")]
+    MemberAdded { member: PublicKey },
+    #[serde(rename_all = "camelCase")]
+    MemberRemoved { member: PublicKey },
+    #[serde(rename_all = "camelCase")]
+    RoleChanged { member: PublicKey, role: String },
+    #[serde(rename_all = "camelCase")]
+    SecretRotated {
+        previous: NamespaceId,
+        current: NamespaceId,
+    },
+    #[serde(rename_all = "camelCase")]
+    ProgramInstalled {
+        program_id: Uuid,
+        name: String,
+        version: String,
+    },
+    #[serde(rename_all = "camelCase")]
+    CapabilityGranted { subject: String, resource: String },
+    /// a run of `program_id` from `space` that used `spaces`, recorded in each of them
+    #[serde(rename_all = "camelCase")]
+    CrossSpaceRun {
+        space: Uuid,
+        program_id: Uuid,
+        run_id: Uuid,
+        spaces: Vec<SpaceHandle>,
+    },
+}
+
+/// Content of an audit event.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AuditRecord {
+    seq: u64,
+    /// id of the entry before this one, none for the first
+    prev: Option<Sha256Digest>,
+    action: AuditAction,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditEntry {
+    pub id: Sha256Digest,
+    pub seq: u64,
+    pub prev: Option<Sha256Digest>,
+    /// who took the action
+    pub actor: PublicKey,
+    pub created_at: i64,
+    pub action: AuditAction,
+}
+
+impl AuditEntry {
+    fn from_event(event: Event) -> Result<Self> {
+        let content = event.content.data.context("audit event has no content")?;
+        let record: AuditRecord = serde_json::from_value(content)?;
+        Ok(AuditEntry {
+            id: event.id,
+            seq: record.seq,
+            prev: record.prev,
+            actor: event.pubkey,
+            created_at: event.created_at,
+            action: record.action,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Audit(Space);
+
+impl Audit {
+    pub fn new(space: Space) -> Self {
+        Audit(space)
+    }
+
+    /// Append an action to the log.
+    pub async fn record(&self, author: Author, action: AuditAction) -> Result<AuditEntry> {
+        // one writer at a time, otherwise two entries could claim the same place
+        let _guard = self.0.audit_lock.lock().await;
+        let last = self.entries().await?.pop();
+        let record = AuditRecord {
+            seq: last.as_ref().map(|entry| entry.seq + 1).unwrap_or_default(),
+            prev: last.map(|entry| entry.id),
+            action,
+        };
+        let content = serde_json::to_value(&record)?;
+        let outcome = self
+            .0
+            .router
+            .blobs()
+            .add_bytes(serde_json::to_vec(&content)?)
+            .await?;
+        let event = Event::create(
+            author,
+            chrono::Utc::now().timestamp(),
+            self.0.clock().tick(),
+            EventKind::Audit,
+            vec![Tag::new(NOSTR_ID_TAG, self.0.id.to_string().as_str())],
+            HashLink {
+                hash: outcome.hash,
+                data: Some(content),
+            },
+        )?;
+        self.0.write_event(&event).await?;
+        AuditEntry::from_event(event)
+    }
+
+    /// Entries in log order. A negative `limit` returns everything after `offset`.
+    pub async fn list(&self, offset: i64, limit: i64) -> Result<Vec<AuditEntry>> {
+        let entries = self
+            .entries()
+            .await?
+            .into_iter()
+            .skip(offset.max(0) as usize);
+        Ok(match usize::try_from(limit) {
+            Ok(limit) => entries.take(limit).collect(),
+            Err(_) => entries.collect(),
+        })
+    }
+
+    /// Check that no entry is missing, duplicated or out of place.
+    pub async fn verify(&self) -> Result<()> {
+        verify(&self.entries().await?)
+    }
+
+    async fn entries(&self) -> Result<Vec<AuditEntry>> {
+        let conn = self.0.db.lock().await;
+        let mut stmt = conn.prepare(
+            format!("SELECT {EVENT_SQL_READ_FIELDS} FROM events WHERE kind = ?1 AND data_id = ?2")
+                .as_str(),
+        )?;
+        let mut rows = stmt.query(params![EventKind::Audit, self.0.id])?;
+        let mut entries = Vec::new();
+        while let Some(row) = rows.next()? {
+            entries.push(AuditEntry::from_event(Event::from_sql_row(row)?)?);
+        }
+        sort(&mut entries);
+        Ok(entries)
+    }
+}
+
+/// Log order: by sequence number, entries that raced for the same place are broken by time,
+/// then id, so every replica lists them the same way.
+fn sort(entries: &mut [AuditEntry]) {
+    entries.sort_by(|a, b| {
+        (a.seq, a.created_at, a.id.as_bytes()).cmp(&(b.seq, b.created_at, b.id.as_bytes()))
+    });
+}
+
+fn verify(entries: &[AuditEntry]) -> Result<()> {
+    let mut prev: Option<&AuditEntry> = None;
+    for (seq, entry) in entries.iter().enumerate() {
+        if entry.seq != seq as u64 {
+            return Err(anyhow!(
+                "audit entry {} has sequence number {}, expected {}",
+                entry.id,
+                entry.seq,
+                seq
+            ));
+        }
+        if entry.prev.as_ref() != prev.map(|prev| &prev.id) {
+            return Err(anyhow!(
+                "audit entry {} doesn't follow the entry before it",
+                entry.id
+            ));
+        }
+        prev = Some(entry);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use iroh::docs::NamespaceSecret;
+    use serde_json::json;
+
+    use super::*;
+    use crate::space::capabilities::{Action, Grant, Subject};
+    use crate::space::users::Profile;
+
+    fn entry(seq: u64, prev: Option<&AuditEntry>, created_at: i64) -> AuditEntry {
+        let member = iroh::net::key::SecretKey::generate().public();
+        AuditEntry {
+            id: Sha256Digest::from_data(&[seq as u8, created_at as u8]),
+            seq,
+            prev: prev.map(|prev| prev.id.clone()),
+            actor: member,
+            created_at,
+            action: AuditAction::MemberAdded { member },
+        }
+    }
+
+    #[test]
+    fn verify_chain() {
+        let first = entry(0, None, 10);
+        let second = entry(1, Some(&first), 5);
+        let third = entry(2, Some(&second), 20);
+
+        // sequence numbers win over clocks
+        let mut entries = vec![third.clone(), second.clone(), first.clone()];
+        sort(&mut entries);
+        assert_eq!(entries, vec![first.clone(), second.clone(), third.clone()]);
+        verify(&entries).unwrap();
+
+        // a dropped entry breaks the chain
+        assert!(verify(&[first.clone(), third.clone()]).is_err());
+
+        // two entries claiming the same place
+        let rival = entry(1, Some(&first), 6);
+        let mut entries = vec![first, rival, second, third];
+        sort(&mut entries);
+        assert!(verify(&entries).is_err());
+    }
+
+    #[tokio::test]
+    async fn records_member_changes() -> Result<()> {
+        let node = iroh::node::Node::memory().enable_docs().spawn().await?;
+        let space = Space::open_in_memory(
+            Uuid::new_v4(),
+            "audit".to_string(),
+            NamespaceSecret::new(&mut rand::thread_rng()),
+            node.client().clone(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+        )
+        .await?;
+        let owner = Author::new(&mut rand::thread_rng());
+        let ada = space.users().create(Profile::new("ada", "", "")).await?;
+        let table = space
+            .tables()
+            .create(
+                owner.clone(),
+                Bytes::from(json!({ "title": "notes", "type": "object" }).to_string()),
+            )
+            .await?
+            .content
+            .hash;
+        let grant = Grant {
+            actions: vec![Action::CreateRow],
+            subject: Subject::Member(ada.pubkey),
+            row: None,
+            own_rows: false,
+        };
+        let capability = space
+            .capabilities()
+            .grant(owner.clone(), table, grant)
+            .await?;
+        space
+            .capabilities()
+            .revoke(owner.clone(), capability.id)
+            .await?;
+        space.users().remove(owner, ada.id).await?;
+        assert!(space.users().get(ada.id).await?.is_none());
+        assert_eq!(space.users().count().await?, 0);
+
+        let actions: Vec<_> = space
+            .audit()
+            .list(0, -1)
+            .await?
+            .into_iter()
+            .map(|entry| entry.action)
+            .collect();
+        assert_eq!(
+            actions[actions.len() - 2..],
+            [
+                AuditAction::RoleChanged {
+                    member: ada.pubkey,
+                    role: format!("nothing on table {table}"),
+                },
+                AuditAction::MemberRemoved { member: ada.pubkey },
+            ]
+        );
+        space.audit().verify().await?;
+        Ok(())
+    }
+}
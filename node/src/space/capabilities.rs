@@ -1,14 +1,790 @@
+//! Who may write what in a table, beyond being a member of the space.
+//!
+//! A capability is a `MutateCapability` event tagged with its table, granting a subject some
+//! actions on the table's rows, optionally only on one row or on rows the subject wrote first.
+//! Tables nobody granted anything on stay open to every member. Once a table has capabilities,
+//! row writes need one that permits them. The table's creator can always write, and is the only
+//! one who can grant & revoke capabilities on it.
+//!
+//! Policy is checked as events are written, both locally & as they're pulled from other
+//! members by the [`crate::space::inbox`], against the capabilities the node knows of then.
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{anyhow, ensure, Context, Result};
+use iroh::blobs::Hash;
+use iroh::docs::Author;
+use iroh::net::key::PublicKey;
+use rusqlite::{params, OptionalExtension};
 use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::router::RouterClient;
+
+use super::audit::AuditAction;
+use super::clock::Hlc;
+use super::events::{
+    Event, EventKind, EventObject, HashLink, Tag, EVENT_SQL_READ_FIELDS, NOSTR_ID_TAG,
+    NOSTR_SCHEMA_TAG,
+};
+use super::Space;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+#[serde(rename_all = "camelCase")]
+pub enum Action {
+    CreateRow,
+    UpdateRow,
+    DeleteRow,
+    /// grant & revoke capabilities, only ever allowed to the table's creator
+    ManageTable,
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let action = match self {
+            Action::CreateRow => "create",
+            Action::UpdateRow => "update",
+            Action::DeleteRow => "delete",
+            Action::ManageTable => "manage",
+        };
+        f.write_str(action)
+    }
+}
+
+/// What an action is taken on.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum Object {
+    Table {
+        #[cfg_attr(feature = "specta", specta(type = String))]
+        table: Hash,
+    },
+    Row {
+        #[cfg_attr(feature = "specta", specta(type = String))]
+        table: Hash,
+        id: Uuid,
+    },
+}
+
+impl Object {
+    pub fn table(&self) -> Hash {
+        match self {
+            Object::Table { table } | Object::Row { table, .. } => *table,
+        }
+    }
+}
+
+impl fmt::Display for Object {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Object::Table { table } => write!(f, "table {table}"),
+            Object::Row { table, id } => write!(f, "row {id} of table {table}"),
+        }
+    }
+}
+
+/// Who a capability is granted to.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "pubkey", rename_all = "camelCase")]
+pub enum Subject {
+    /// every member of the space
+    Anyone,
+    Member(PublicKey),
+}
+
+impl fmt::Display for Subject {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Subject::Anyone => f.write_str("anyone"),
+            Subject::Member(pubkey) => write!(f, "{pubkey}"),
+        }
+    }
+}
+
+/// What a capability allows, the content of its event.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Grant {
+    pub actions: Vec<Action>,
+    pub subject: Subject,
+    /// limits the grant to one row of the table
+    #[serde(default)]
+    pub row: Option<Uuid>,
+    /// limits the grant to rows the subject wrote first, and rows they create
+    #[serde(default)]
+    pub own_rows: bool,
+}
 
-#[derive(Debug, Deserialize, Serialize)]
-pub enum Actions {
-    TableRead,
-    TableWrite,
+impl Grant {
+    fn permits(
+        &self,
+        who: &PublicKey,
+        action: Action,
+        row: Option<Uuid>,
+        row_author: Option<&PublicKey>,
+    ) -> bool {
+        self.actions.contains(&action)
+            && match &self.subject {
+                Subject::Anyone => true,
+                Subject::Member(pubkey) => pubkey == who,
+            }
+            && self.row.map_or(true, |id| row == Some(id))
+            && (!self.own_rows || row_author.map_or(true, |author| author == who))
+    }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct Capability {
-    action: Vec<Actions>,
-    subject: String,
-    resource: String,
+    pub id: Uuid,
+    pub created_at: i64,
+    /// who granted the capability
+    pub author: PublicKey,
+    pub content: HashLink,
+    pub table: Hash,
+    #[serde(flatten)]
+    pub grant: Grant,
+}
+
+impl EventObject for Capability {
+    async fn from_event(event: Event, router: &RouterClient) -> Result<Self> {
+        if event.kind != EventKind::MutateCapability {
+            return Err(anyhow!("event is not a capability mutation"));
+        }
+        let id = event.data_id()?.ok_or_else(|| anyhow!("missing data id"))?;
+        let table = event.schema()?.ok_or_else(|| anyhow!("missing table"))?;
+        let mut content = event.content;
+        let grant: Grant = serde_json::from_value(content.resolve(router).await?)?;
+        Ok(Capability {
+            id,
+            created_at: event.created_at,
+            author: event.pubkey,
+            content,
+            table,
+            grant,
+        })
+    }
+
+    fn into_mutate_event(&self, author: Author, hlc: Hlc) -> Result<Event> {
+        let tags = vec![
+            Tag::new(NOSTR_SCHEMA_TAG, self.table.to_string().as_str()),
+            Tag::new(NOSTR_ID_TAG, self.id.to_string().as_str()),
+        ];
+        Event::create(
+            author,
+            self.created_at,
+            hlc,
+            EventKind::MutateCapability,
+            tags,
+            self.content.clone(),
+        )
+    }
+}
+
+/// The capabilities in force on a table.
+#[derive(Debug, Clone, Default)]
+pub struct Policy {
+    /// the table's creator, none if the table isn't known here
+    pub owner: Option<PublicKey>,
+    pub grants: Vec<Grant>,
+}
+
+impl Policy {
+    /// Whether `who` may take `action` on the table, or on `row` of it when `row_author` wrote
+    /// the row first.
+    pub fn allows(
+        &self,
+        who: &PublicKey,
+        action: Action,
+        row: Option<Uuid>,
+        row_author: Option<&PublicKey>,
+    ) -> bool {
+        if self.owner.as_ref() == Some(who) {
+            return true;
+        }
+        if action == Action::ManageTable {
+            return false;
+        }
+        self.grants.is_empty()
+            || self
+                .grants
+                .iter()
+                .any(|grant| grant.permits(who, action, row, row_author))
+    }
+}
+
+pub struct Capabilities(Space);
+
+impl Capabilities {
+    pub fn new(space: Space) -> Self {
+        Capabilities(space)
+    }
+
+    /// Grant a capability on `table`. Only the table's creator can.
+    pub async fn grant(&self, author: Author, table: Hash, grant: Grant) -> Result<Capability> {
+        ensure!(
+            !grant.actions.is_empty(),
+            "a capability has to allow something"
+        );
+        ensure!(
+            !grant.actions.contains(&Action::ManageTable),
+            "managing a table can't be granted"
+        );
+        self.0
+            .tables()
+            .get_by_hash(table)
+            .await
+            .context("loading capability table")?;
+
+        let value = serde_json::to_value(&grant)?;
+        let content = self
+            .0
+            .router
+            .blobs()
+            .add_bytes(serde_json::to_vec(&value)?)
+            .await?;
+        let capability = Capability {
+            id: Uuid::new_v4(),
+            created_at: chrono::Utc::now().timestamp(),
+            author: PublicKey::from_bytes(author.public_key().as_bytes())?,
+            content: HashLink {
+                hash: content.hash,
+                data: Some(value),
+            },
+            table,
+            grant,
+        };
+        self.0
+            .write_event(&capability.into_mutate_event(author.clone(), self.0.clock().tick())?)
+            .await?;
+        let resource = match capability.grant.row {
+            Some(id) => Object::Row { table, id },
+            None => Object::Table { table },
+        };
+        let action = AuditAction::CapabilityGranted {
+            subject: capability.grant.subject.to_string(),
+            resource: resource.to_string(),
+        };
+        self.0.audit().record(author, action).await?;
+        Ok(capability)
+    }
+
+    /// Take a capability back. Revoked capabilities are gone for good. Revoking a member's
+    /// grant is audited as a change of their role on the table.
+    pub async fn revoke(&self, author: Author, id: Uuid) -> Result<()> {
+        let capability = self.get(id).await?;
+        let tags = vec![
+            Tag::new(NOSTR_SCHEMA_TAG, capability.table.to_string().as_str()),
+            Tag::new(NOSTR_ID_TAG, id.to_string().as_str()),
+        ];
+        let event = Event::create(
+            author.clone(),
+            chrono::Utc::now().timestamp(),
+            self.0.clock().tick(),
+            EventKind::DeleteCapability,
+            tags,
+            HashLink::from(capability.content.hash),
+        )?;
+        self.0.write_event(&event).await?;
+        if let Subject::Member(member) = capability.grant.subject {
+            let role = self.role(&member, capability.table).await?;
+            self.0
+                .audit()
+                .record(author, AuditAction::RoleChanged { member, role })
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// What `member` was granted by name on `table`, eg: "create, update on table <hash>".
+    async fn role(&self, member: &PublicKey, table: Hash) -> Result<String> {
+        let mut actions = Vec::new();
+        for capability in self.list(table).await? {
+            if capability.grant.subject != Subject::Member(*member) {
+                continue;
+            }
+            for action in capability.grant.actions {
+                if !actions.contains(&action) {
+                    actions.push(action);
+                }
+            }
+        }
+        let actions = match actions.is_empty() {
+            true => "nothing".to_string(),
+            false => actions
+                .iter()
+                .map(Action::to_string)
+                .collect::<Vec<_>>()
+                .join(", "),
+        };
+        Ok(format!("{actions} on {}", Object::Table { table }))
+    }
+
+    pub async fn get(&self, id: Uuid) -> Result<Capability> {
+        let mut capabilities = self.select("data_id = ?2", &[&id]).await?;
+        capabilities
+            .pop()
+            .ok_or_else(|| anyhow!("capability not found"))
+    }
+
+    /// Capabilities granted on `table`.
+    pub async fn list(&self, table: Hash) -> Result<Vec<Capability>> {
+        self.select("schema_hash = ?2", &[&table.to_string()]).await
+    }
+
+    pub async fn policy(&self, table: Hash) -> Result<Policy> {
+        let owner = self.owner(table).await?;
+        let grants = self
+            .list(table)
+            .await?
+            .into_iter()
+            .map(|capability| capability.grant)
+            .collect();
+        Ok(Policy { owner, grants })
+    }
+
+    /// Whether `who` may take `action` on `object`.
+    pub async fn can(&self, who: &PublicKey, action: Action, object: &Object) -> Result<bool> {
+        let policy = self.policy(object.table()).await?;
+        let (row, row_author) = match object {
+            Object::Table { .. } => (None, None),
+            Object::Row { table, id } => (Some(*id), self.row_author(*table, *id).await?),
+        };
+        Ok(policy.allows(who, action, row, row_author.as_ref()))
+    }
+
+    /// Whether this node's default author may take `action` on `object`, for UIs to hide what
+    /// would be refused.
+    pub async fn can_i(&self, action: Action, object: &Object) -> Result<bool> {
+        let author = self.0.router.authors().default().await?;
+        let me = PublicKey::from_bytes(author.as_bytes())?;
+        self.can(&me, action, object).await
+    }
+
+    /// Refuse an event the table's policy doesn't allow its author to write.
+    ///
+    /// Owners & row authors are whoever wrote first by clock, and clocks are the author's to
+    /// pick. So nobody but the owner may write a table that's already known, and nobody but a
+    /// row's author may write it with a clock from before the row's first write.
+    pub(crate) async fn check(&self, event: &Event) -> Result<()> {
+        if event.kind == EventKind::MutateTable {
+            let table = event.content.hash;
+            if let Some(owner) = self.owner(table).await? {
+                ensure!(
+                    owner == event.pubkey,
+                    "{} may not take over table {table} from {owner}",
+                    event.pubkey
+                );
+            }
+            return Ok(());
+        }
+        if !matches!(
+            event.kind,
+            EventKind::MutateRow
+                | EventKind::DeleteRow
+                | EventKind::MutateCapability
+                | EventKind::DeleteCapability
+        ) {
+            return Ok(());
+        }
+        let table = event
+            .schema()?
+            .ok_or_else(|| anyhow!("event {} has no table", event.id))?;
+        let (action, object) = match event.kind {
+            EventKind::MutateRow | EventKind::DeleteRow => {
+                let id = event
+                    .data_id()?
+                    .ok_or_else(|| anyhow!("event {} has no row id", event.id))?;
+                let first = self.first_row_write(table, id).await?;
+                if let Some((author, clock)) = first {
+                    ensure!(
+                        author == event.pubkey || event.hlc()?.to_sql() >= clock,
+                        "{} may not write row {id} of table {table} before it was created",
+                        event.pubkey
+                    );
+                }
+                let action = if event.kind == EventKind::DeleteRow {
+                    Action::DeleteRow
+                } else if first.is_some() {
+                    Action::UpdateRow
+                } else {
+                    Action::CreateRow
+                };
+                (action, Object::Row { table, id })
+            }
+            EventKind::DeleteCapability => {
+                // a revocation only ever takes back the capability of that id on its own
+                // table, see `select`
+                let id = event
+                    .data_id()?
+                    .ok_or_else(|| anyhow!("event {} has no capability id", event.id))?;
+                ensure!(
+                    self.granted_on(table, id).await?,
+                    "capability {id} isn't granted on table {table}"
+                );
+                (Action::ManageTable, Object::Table { table })
+            }
+            _ => (Action::ManageTable, Object::Table { table }),
+        };
+        ensure!(
+            self.can(&event.pubkey, action, &object).await?,
+            "{} may not {} {}",
+            event.pubkey,
+            action,
+            object
+        );
+        Ok(())
+    }
+
+    /// Who created a table, none if the table isn't known here.
+    async fn owner(&self, table: Hash) -> Result<Option<PublicKey>> {
+        let conn = self.0.db.lock().await;
+        let owner: Option<String> = conn
+            .query_row(
+                "SELECT pubkey FROM events WHERE kind = ?1 AND content_hash = ?2
+                ORDER BY clock LIMIT 1",
+                params![EventKind::MutateTable, table.to_string()],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(owner.map(|owner| PublicKey::from_str(&owner)).transpose()?)
+    }
+
+    /// Who wrote a row first, none for rows that don't exist yet.
+    async fn row_author(&self, table: Hash, id: Uuid) -> Result<Option<PublicKey>> {
+        Ok(self
+            .first_row_write(table, id)
+            .await?
+            .map(|(author, _)| author))
+    }
+
+    /// The author & clock of the first write of a row.
+    async fn first_row_write(&self, table: Hash, id: Uuid) -> Result<Option<(PublicKey, i64)>> {
+        let conn = self.0.db.lock().await;
+        let first: Option<(String, i64)> = conn
+            .query_row(
+                "SELECT pubkey, clock FROM events
+                WHERE kind = ?1 AND schema_hash = ?2 AND data_id = ?3
+                ORDER BY clock LIMIT 1",
+                params![EventKind::MutateRow, table.to_string(), id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+        first
+            .map(|(author, clock)| Ok((PublicKey::from_str(&author)?, clock)))
+            .transpose()
+    }
+
+    /// Whether capability `id` was ever granted on `table`, revoked or not.
+    async fn granted_on(&self, table: Hash, id: Uuid) -> Result<bool> {
+        let conn = self.0.db.lock().await;
+        let granted = conn
+            .query_row(
+                "SELECT 1 FROM events WHERE kind = ?1 AND schema_hash = ?2 AND data_id = ?3",
+                params![EventKind::MutateCapability, table.to_string(), id],
+                |_| Ok(()),
+            )
+            .optional()?;
+        Ok(granted.is_some())
+    }
+
+    /// The latest version of each capability that hasn't been revoked & matches `condition`,
+    /// which takes its parameters from `?2` on.
+    async fn select(
+        &self,
+        condition: &str,
+        params: &[&(dyn rusqlite::ToSql + Sync)],
+    ) -> Result<Vec<Capability>> {
+        let sql = format!(
+            "SELECT {EVENT_SQL_READ_FIELDS}, MAX(clock) FROM events
+            WHERE kind = ?1 AND {condition}
+            AND NOT EXISTS (
+                SELECT 1 FROM events AS revoked WHERE revoked.kind = {deleted}
+                AND revoked.data_id = events.data_id AND revoked.schema_hash = events.schema_hash
+            )
+            GROUP BY data_id ORDER BY clock",
+            deleted = EventKind::DeleteCapability.kind(),
+        );
+        let events = {
+            let conn = self.0.db.lock().await;
+            let mut stmt = conn.prepare(&sql)?;
+            let mut params: Vec<&dyn rusqlite::ToSql> = params
+                .iter()
+                .map(|param| *param as &dyn rusqlite::ToSql)
+                .collect();
+            params.insert(0, &EventKind::MutateCapability);
+            let mut rows = stmt.query(params.as_slice())?;
+            let mut events = Vec::new();
+            while let Some(row) = rows.next()? {
+                events.push(Event::from_sql_row(row)?);
+            }
+            events
+        };
+        let mut capabilities = Vec::new();
+        for event in events {
+            capabilities.push(Capability::from_event(event, &self.0.router).await?);
+        }
+        Ok(capabilities)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use iroh::docs::NamespaceSecret;
+    use serde_json::json;
+
+    use super::*;
+    use crate::space::rows::Row;
+
+    fn pubkey(author: &Author) -> PublicKey {
+        PublicKey::from_bytes(author.public_key().as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn grants_narrow_open_tables() {
+        let (owner, ada, bob) = (
+            pubkey(&Author::new(&mut rand::thread_rng())),
+            pubkey(&Author::new(&mut rand::thread_rng())),
+            pubkey(&Author::new(&mut rand::thread_rng())),
+        );
+        let row = Uuid::new_v4();
+        let mut policy = Policy {
+            owner: Some(owner),
+            grants: Vec::new(),
+        };
+        assert!(policy.allows(&bob, Action::DeleteRow, Some(row), Some(&ada)));
+        assert!(!policy.allows(&bob, Action::ManageTable, None, None));
+        assert!(policy.allows(&owner, Action::ManageTable, None, None));
+
+        policy.grants.push(Grant {
+            actions: vec![Action::CreateRow, Action::UpdateRow],
+            subject: Subject::Anyone,
+            row: None,
+            own_rows: true,
+        });
+        assert!(policy.allows(&bob, Action::CreateRow, Some(row), None));
+        assert!(policy.allows(&bob, Action::UpdateRow, Some(row), Some(&bob)));
+        assert!(!policy.allows(&bob, Action::UpdateRow, Some(row), Some(&ada)));
+        assert!(!policy.allows(&bob, Action::DeleteRow, Some(row), Some(&bob)));
+        assert!(policy.allows(&owner, Action::DeleteRow, Some(row), Some(&ada)));
+
+        policy.grants.push(Grant {
+            actions: vec![Action::DeleteRow],
+            subject: Subject::Member(ada),
+            row: Some(row),
+            own_rows: false,
+        });
+        assert!(policy.allows(&ada, Action::DeleteRow, Some(row), Some(&bob)));
+        assert!(!policy.allows(&ada, Action::DeleteRow, Some(Uuid::new_v4()), Some(&bob)));
+        assert!(!policy.allows(&bob, Action::DeleteRow, Some(row), Some(&bob)));
+    }
+
+    async fn space(node: &iroh::node::Node<iroh::blobs::store::mem::Store>) -> Result<Space> {
+        Space::open_in_memory(
+            Uuid::new_v4(),
+            "capabilities".to_string(),
+            NamespaceSecret::new(&mut rand::thread_rng()),
+            node.client().clone(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+        )
+        .await
+    }
+
+    async fn table(space: &Space, owner: &Author, title: &str) -> Result<Hash> {
+        let schema = json!({
+            "title": title,
+            "type": "object",
+            "properties": { "body": { "type": "string" } },
+        });
+        let table = space
+            .tables()
+            .create(owner.clone(), Bytes::from(schema.to_string()))
+            .await?;
+        Ok(table.content.hash)
+    }
+
+    #[tokio::test]
+    async fn writes_follow_policy() -> Result<()> {
+        let node = iroh::node::Node::memory().spawn().await?;
+        let space = space(&node).await?;
+        let owner = Author::new(&mut rand::thread_rng());
+        let (ada, bob) = (
+            Author::new(&mut rand::thread_rng()),
+            Author::new(&mut rand::thread_rng()),
+        );
+        let table = table(&space, &owner, "notes").await?;
+        let rows = space.rows();
+        let capabilities = space.capabilities();
+
+        // open until something is granted
+        rows.create(bob.clone(), table, json!({ "body": "early" }))
+            .await?;
+        let grant = Grant {
+            actions: vec![Action::CreateRow, Action::UpdateRow],
+            subject: Subject::Anyone,
+            row: None,
+            own_rows: true,
+        };
+        assert!(capabilities
+            .grant(ada.clone(), table, grant.clone())
+            .await
+            .is_err());
+        let capability = capabilities.grant(owner.clone(), table, grant).await?;
+        assert_eq!(capabilities.list(table).await?.len(), 1);
+
+        let note = rows
+            .create(ada.clone(), table, json!({ "body": "ada's" }))
+            .await?;
+        rows.mutate(
+            ada.clone(),
+            table,
+            note.id,
+            json!({ "body": "still ada's" }),
+        )
+        .await?;
+        let err = rows
+            .mutate(
+                bob.clone(),
+                table,
+                note.id,
+                json!({ "body": "bob was here" }),
+            )
+            .await
+            .unwrap_err();
+        assert!(format!("{err:#}").contains("may not update"));
+        assert!(rows.delete(ada.clone(), table, note.id).await.is_err());
+        rows.delete(owner.clone(), table, note.id).await?;
+
+        let row = Object::Row { table, id: note.id };
+        assert!(
+            capabilities
+                .can(&pubkey(&ada), Action::UpdateRow, &row)
+                .await?
+        );
+        assert!(
+            !capabilities
+                .can(&pubkey(&bob), Action::UpdateRow, &row)
+                .await?
+        );
+        assert!(
+            !capabilities
+                .can(&pubkey(&bob), Action::ManageTable, &Object::Table { table })
+                .await?
+        );
+
+        capabilities.revoke(owner, capability.id).await?;
+        assert!(
+            capabilities
+                .can(&pubkey(&bob), Action::UpdateRow, &row)
+                .await?
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn revocations_stay_on_their_table() -> Result<()> {
+        let node = iroh::node::Node::memory().spawn().await?;
+        let space = space(&node).await?;
+        let (owner, bob, ada) = (
+            Author::new(&mut rand::thread_rng()),
+            Author::new(&mut rand::thread_rng()),
+            Author::new(&mut rand::thread_rng()),
+        );
+        let mine = table(&space, &owner, "mine").await?;
+        let theirs = table(&space, &bob, "theirs").await?;
+        let capabilities = space.capabilities();
+        let grant = Grant {
+            actions: vec![Action::CreateRow],
+            subject: Subject::Member(pubkey(&ada)),
+            row: None,
+            own_rows: false,
+        };
+        let capability = capabilities.grant(bob, theirs, grant).await?;
+
+        // the owner of one table revokes a capability of another, tagged with their own
+        let forged = Event::create(
+            owner,
+            chrono::Utc::now().timestamp(),
+            space.clock().tick(),
+            EventKind::DeleteCapability,
+            vec![
+                Tag::new(NOSTR_SCHEMA_TAG, mine.to_string().as_str()),
+                Tag::new(NOSTR_ID_TAG, capability.id.to_string().as_str()),
+            ],
+            HashLink::from(capability.content.hash),
+        )?;
+        let err = space.write_event(&forged).await.unwrap_err();
+        assert!(format!("{err:#}").contains("isn't granted on table"));
+        // and wouldn't reach it if it got in anyway
+        forged.write(&space.db).await?;
+        assert_eq!(capabilities.list(theirs).await?.len(), 1);
+        let carol = pubkey(&Author::new(&mut rand::thread_rng()));
+        let object = Object::Table { table: theirs };
+        assert!(
+            capabilities
+                .can(&pubkey(&ada), Action::CreateRow, &object)
+                .await?
+        );
+        assert!(
+            !capabilities.can(&carol, Action::CreateRow, &object).await?,
+            "still closed to everyone else"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn ownership_cant_be_backdated() -> Result<()> {
+        let node = iroh::node::Node::memory().spawn().await?;
+        let space = space(&node).await?;
+        let (owner, bob) = (
+            Author::new(&mut rand::thread_rng()),
+            Author::new(&mut rand::thread_rng()),
+        );
+        let table = table(&space, &owner, "notes").await?;
+        let note = space
+            .rows()
+            .create(owner.clone(), table, json!({ "body": "the owner's" }))
+            .await?;
+        let early = Hlc::from_created_at(1);
+
+        let takeover = space
+            .tables()
+            .get_by_hash(table)
+            .await?
+            .into_mutate_event(bob.clone(), early)?;
+        let err = space.write_event(&takeover).await.unwrap_err();
+        assert!(format!("{err:#}").contains("may not take over"));
+
+        let rewrite = Row {
+            id: note.id,
+            created_at: 1,
+            author: pubkey(&bob),
+            content: note.content,
+            schema: table,
+        }
+        .into_mutate_event(bob.clone(), early)?;
+        let err = space.write_event(&rewrite).await.unwrap_err();
+        assert!(format!("{err:#}").contains("before it was created"));
+
+        let capabilities = space.capabilities();
+        assert_eq!(
+            capabilities.policy(table).await?.owner,
+            Some(pubkey(&owner))
+        );
+        assert_eq!(
+            capabilities.row_author(table, note.id).await?,
+            Some(pubkey(&owner))
+        );
+        assert!(
+            !capabilities
+                .can(&pubkey(&bob), Action::ManageTable, &Object::Table { table })
+                .await?
+        );
+        Ok(())
+    }
 }
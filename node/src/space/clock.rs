@@ -0,0 +1,238 @@
+//! Hybrid logical clocks, ordering a space's events the same way on every node whatever the
+//! device clocks say.
+//!
+//! Every event carries the [`Hlc`] it was written at in an `hlc` tag, covered by its signature.
+//! An HLC is wall clock milliseconds plus a counter, and a space's clock never goes backwards:
+//! it moves past every event the node writes or receives, so anything written after seeing an
+//! event orders after it, even when the writer's wall clock is behind. Ordering & last writer
+//! wins go by the clock, `created_at` is only informational. Events from before clocks order by
+//! their `created_at`.
+//!
+//! Events from other members with a clock too far ahead of this node's wall clock are refused
+//! rather than dragging the space's clock into the future, see [`SkewLimit`].
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, ensure, Result};
+
+use super::db::DB;
+
+/// how far ahead of the wall clock remote events may be by default
+pub const DEFAULT_MAX_SKEW: Duration = Duration::from_secs(5 * 60);
+
+/// A hybrid logical clock reading. Orders by wall clock, then counter.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Hlc {
+    /// unix time in milliseconds
+    pub millis: u64,
+    /// orders readings within the same millisecond
+    pub counter: u16,
+}
+
+impl Hlc {
+    /// The reading events from before clocks are ordered by.
+    pub fn from_created_at(created_at: i64) -> Self {
+        Hlc {
+            millis: created_at.max(0) as u64 * 1000,
+            counter: 0,
+        }
+    }
+
+    /// Packed into one integer that sorts like the reading, for the `events.hlc` column.
+    pub(crate) fn to_sql(self) -> i64 {
+        ((self.millis << 16) | self.counter as u64) as i64
+    }
+
+    pub(crate) fn from_sql(packed: i64) -> Self {
+        let packed = packed as u64;
+        Hlc {
+            millis: packed >> 16,
+            counter: (packed & 0xffff) as u16,
+        }
+    }
+
+    fn successor(self) -> Self {
+        match self.counter.checked_add(1) {
+            Some(counter) => Hlc {
+                millis: self.millis,
+                counter,
+            },
+            None => Hlc {
+                millis: self.millis + 1,
+                counter: 0,
+            },
+        }
+    }
+}
+
+impl fmt::Display for Hlc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.millis, self.counter)
+    }
+}
+
+impl FromStr for Hlc {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (millis, counter) = s
+            .split_once('-')
+            .ok_or_else(|| anyhow!("invalid clock reading {s}"))?;
+        Ok(Hlc {
+            millis: millis.parse()?,
+            counter: counter.parse()?,
+        })
+    }
+}
+
+/// How far ahead of this node's wall clock events from other members may be. Shared by every
+/// space.
+#[derive(Debug, Clone)]
+pub struct SkewLimit(Arc<AtomicU64>);
+
+impl Default for SkewLimit {
+    fn default() -> Self {
+        SkewLimit(Arc::new(
+            AtomicU64::new(DEFAULT_MAX_SKEW.as_millis() as u64),
+        ))
+    }
+}
+
+impl SkewLimit {
+    pub fn get(&self) -> Duration {
+        Duration::from_millis(self.0.load(Ordering::Relaxed))
+    }
+
+    pub fn set(&self, max_skew: Duration) {
+        self.0.store(max_skew.as_millis() as u64, Ordering::Relaxed);
+    }
+}
+
+/// The clock of one space.
+#[derive(Debug, Clone)]
+pub(crate) struct Clock {
+    last: Arc<Mutex<Hlc>>,
+    limit: SkewLimit,
+}
+
+impl Clock {
+    /// A clock that's past `last`, the latest reading the space holds.
+    pub(crate) fn new(last: Hlc, limit: SkewLimit) -> Self {
+        Clock {
+            last: Arc::new(Mutex::new(last)),
+            limit,
+        }
+    }
+
+    /// The reading to write a new event at.
+    pub(crate) fn tick(&self) -> Hlc {
+        let now = Hlc {
+            millis: wall_millis(),
+            counter: 0,
+        };
+        let mut last = self.last.lock().expect("poisoned");
+        *last = if now > *last { now } else { last.successor() };
+        *last
+    }
+
+    /// Refuse a remote reading too far ahead of the wall clock, otherwise move past it.
+    pub(crate) fn observe(&self, remote: Hlc) -> Result<()> {
+        let now = wall_millis();
+        let max_skew = self.limit.get();
+        ensure!(
+            remote.millis <= now + max_skew.as_millis() as u64,
+            "event is {}s ahead of this node's clock, more than the {}s allowed",
+            (remote.millis - now) / 1000,
+            max_skew.as_secs()
+        );
+        self.witness(remote);
+        Ok(())
+    }
+
+    /// Move past a reading without checking it, for events this node already trusted.
+    pub(crate) fn witness(&self, reading: Hlc) {
+        let mut last = self.last.lock().expect("poisoned");
+        if reading > *last {
+            *last = reading;
+        }
+    }
+}
+
+/// The latest reading among the events of a space's db.
+pub(crate) async fn latest(db: &DB) -> Result<Hlc> {
+    let conn = db.lock().await;
+    let latest: Option<i64> =
+        conn.query_row("SELECT MAX(clock) FROM events", [], |row| row.get(0))?;
+    Ok(latest.map(Hlc::from_sql).unwrap_or_default())
+}
+
+fn wall_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn never_goes_backwards() -> Result<()> {
+        let clock = Clock::new(Hlc::default(), SkewLimit::default());
+        let first = clock.tick();
+        assert!(clock.tick() > first);
+
+        // a peer whose clock runs a minute ahead: later writes here still order after it
+        let ahead = Hlc {
+            millis: wall_millis() + 60_000,
+            counter: 7,
+        };
+        clock.observe(ahead)?;
+        let next = clock.tick();
+        assert!(next > ahead);
+        assert_eq!(next.millis, ahead.millis);
+
+        // a backdated reading doesn't move the clock back
+        clock.observe(first)?;
+        assert!(clock.tick() > next);
+
+        let far_ahead = Hlc {
+            millis: wall_millis() + 3_600_000,
+            counter: 0,
+        };
+        assert!(clock.observe(far_ahead).is_err());
+        clock.limit.set(Duration::from_secs(2 * 3600));
+        assert!(clock.observe(far_ahead).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn readings_round_trip() -> Result<()> {
+        let reading = Hlc {
+            millis: 1_700_000_000_123,
+            counter: 42,
+        };
+        assert_eq!(reading.to_string().parse::<Hlc>()?, reading);
+        assert_eq!(Hlc::from_sql(reading.to_sql()), reading);
+        assert!(
+            Hlc::from_created_at(1_700_000_000).to_sql() < reading.to_sql(),
+            "packed readings sort like readings"
+        );
+        let last = Hlc {
+            millis: 5,
+            counter: u16::MAX,
+        };
+        assert_eq!(
+            last.successor(),
+            Hlc {
+                millis: 6,
+                counter: 0
+            }
+        );
+        Ok(())
+    }
+}
@@ -1,17 +1,53 @@
 use std::path::PathBuf;
 use std::sync::Arc;
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use rusqlite::Connection;
 use tokio::sync::Mutex;
 
 pub(crate) type DB = Arc<Mutex<Connection>>;
 
+/// Version of the tables [`setup_db`] creates, kept in sqlite's `user_version`. Databases
+/// from a newer version are left alone, see [`check_db`].
+pub(crate) const SCHEMA_VERSION: i32 = 2;
+
 pub(crate) async fn open_db(path: impl Into<PathBuf>) -> Result<DB> {
     let db = Connection::open(path.into())?;
     Ok(Arc::new(Mutex::new(db)))
 }
 
+/// A database that's gone once the last handle is dropped.
+pub(crate) async fn open_memory_db() -> Result<DB> {
+    let db = Connection::open_in_memory()?;
+    Ok(Arc::new(Mutex::new(db)))
+}
+
+/// Refuse databases this version can't safely write to: ones that fail sqlite's integrity
+/// check, or were set up by a newer version. [`super::recovery`] reads what it can of those.
+pub(crate) fn check_db(conn: &Connection) -> Result<()> {
+    let version: i32 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    if version > SCHEMA_VERSION {
+        bail!("database is from a newer version (schema {version}, this node reads up to {SCHEMA_VERSION})");
+    }
+    let problems = integrity_problems(conn)?;
+    if !problems.is_empty() {
+        bail!(
+            "database failed its integrity check: {}",
+            problems.join("; ")
+        );
+    }
+    Ok(())
+}
+
+/// What sqlite's quick integrity check found wrong, empty if nothing.
+pub(crate) fn integrity_problems(conn: &Connection) -> Result<Vec<String>> {
+    let mut stmt = conn.prepare("PRAGMA quick_check(20)")?;
+    let problems = stmt
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(problems.into_iter().filter(|p| p != "ok").collect())
+}
+
 pub(crate) async fn setup_db(db: &DB) -> Result<()> {
     let conn = db.lock().await;
     conn.execute(
@@ -24,11 +60,255 @@ pub(crate) async fn setup_db(db: &DB) -> Result<()> {
             data_id      BLOB NOT NULL,
             sig          BLOB NOT NULL,
             content_hash TEXT NOT NULL,
-            content      BLOB
+            content      BLOB,
+            hlc          INTEGER,
+            clock        INTEGER GENERATED ALWAYS AS (COALESCE(hlc, created_at * 65536000)) VIRTUAL
+        )",
+        [],
+    )?;
+    // events are ordered by their hybrid logical clock, see space::clock. Events from before
+    // clocks have no hlc & order by created_at, packed the same way
+    let has_hlc: bool = conn.query_row(
+        "SELECT COUNT(*) > 0 FROM pragma_table_info('events') WHERE name = 'hlc'",
+        [],
+        |row| row.get(0),
+    )?;
+    if !has_hlc {
+        conn.execute("ALTER TABLE events ADD COLUMN hlc INTEGER", [])?;
+        conn.execute(
+            "ALTER TABLE events ADD COLUMN clock INTEGER GENERATED ALWAYS AS (COALESCE(hlc, created_at * 65536000)) VIRTUAL",
+            [],
+        )?;
+        conn.execute("DROP INDEX IF EXISTS events_schema_hash", [])?;
+        conn.execute("DROP INDEX IF EXISTS events_kind_created_at", [])?;
+    }
+
+    // rows are looked up & aggregated by table, keep that cheap
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS events_schema_hash_clock ON events (schema_hash, data_id, clock)",
+        [],
+    )?;
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS events_kind_clock ON events (kind, clock)",
+        [],
+    )?;
+
+    // text of row, table & program events for full-text search, by the rowid of the event
+    conn.execute(
+        "CREATE VIRTUAL TABLE IF NOT EXISTS events_fts USING fts5 (text, tokenize = 'porter unicode61')",
+        [],
+    )?;
+
+    // read-only data mirrored from iroh docs outside the space
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS external_sources (
+            id          BLOB PRIMARY KEY,
+            doc_id      TEXT NOT NULL,
+            ticket      TEXT NOT NULL,
+            mapping     TEXT NOT NULL,
+            created_at  INTEGER NOT NULL
+        )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS external_rows (
+            source_id   BLOB NOT NULL,
+            schema_hash TEXT NOT NULL,
+            key         TEXT NOT NULL,
+            author      TEXT NOT NULL,
+            updated_at  INTEGER NOT NULL,
+            content     BLOB NOT NULL,
+            PRIMARY KEY (source_id, key)
+        )",
+        [],
+    )?;
+
+    // tables kept in sync with a spreadsheet, see space::imports. Holds credentials, not synced
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS table_imports (
+            id             BLOB PRIMARY KEY,
+            table_id       BLOB NOT NULL,
+            schema_hash    TEXT,
+            source         TEXT NOT NULL,
+            auth           TEXT NOT NULL,
+            mapping        TEXT NOT NULL,
+            author         TEXT NOT NULL,
+            created_at     INTEGER NOT NULL,
+            last_synced_at INTEGER,
+            last_error     TEXT
+        )",
+        [],
+    )?;
+    // the row each source row was written to, and the hash of what was written
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS table_import_rows (
+            import_id    BLOB NOT NULL,
+            key          TEXT NOT NULL,
+            row_id       BLOB NOT NULL,
+            content_hash TEXT,
+            PRIMARY KEY (import_id, key)
+        )",
+        [],
+    )?;
+
+    // secrets the space was rotated to & from, see space::rotation. Revoked secrets are
+    // forgotten, only their namespace is kept. Not synced
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS space_secrets (
+            namespace  TEXT PRIMARY KEY,
+            secret     BLOB,
+            created_at INTEGER NOT NULL,
+            revoked_at INTEGER
+        )",
+        [],
+    )?;
+
+    // field keys of secrets the space rotated away from, so row fields sealed under them still
+    // open, see space::sensitive. Not synced
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS retired_field_keys (
+            namespace TEXT PRIMARY KEY,
+            key       BLOB NOT NULL
         )",
         [],
     )?;
 
+    // retention rules of tables, see space::retention. Enforced by the node that set them, so
+    // not synced
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS table_retention (
+            table_id    BLOB PRIMARY KEY,
+            policy      TEXT NOT NULL,
+            author      TEXT NOT NULL,
+            last_run_at INTEGER,
+            last_error  TEXT
+        )",
+        [],
+    )?;
+    // blobs holding rows archived by retention rules
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS table_retention_archives (
+            table_id   BLOB NOT NULL,
+            hash       TEXT NOT NULL,
+            rows       INTEGER NOT NULL,
+            created_at INTEGER NOT NULL
+        )",
+        [],
+    )?;
+
+    // tables replicated to an external database, see space::mirrors. The connection string can
+    // hold credentials, not synced
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS table_mirrors (
+            id             BLOB PRIMARY KEY,
+            table_id       BLOB NOT NULL,
+            connection     TEXT NOT NULL,
+            target_table   TEXT NOT NULL,
+            schema_hash    TEXT,
+            cursor         INTEGER NOT NULL DEFAULT 0,
+            created_at     INTEGER NOT NULL,
+            last_synced_at INTEGER,
+            last_error     TEXT
+        )",
+        [],
+    )?;
+
+    // thumbnails generated from row attachments, see space::attachments. Failed attempts have
+    // no thumbnail & an error. Not synced
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS attachment_thumbnails (
+            original   TEXT NOT NULL,
+            size       INTEGER NOT NULL,
+            thumbnail  TEXT,
+            mime_type  TEXT,
+            created_at INTEGER NOT NULL,
+            error      TEXT,
+            PRIMARY KEY (original, size)
+        )",
+        [],
+    )?;
+
+    // program runs started from this node, used for usage accounting. Not synced
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS program_runs (
+            id                BLOB PRIMARY KEY,
+            program_id        BLOB NOT NULL,
+            scope             BLOB NOT NULL,
+            started_at        INTEGER NOT NULL,
+            succeeded         INTEGER NOT NULL,
+            wall_time_ms      INTEGER NOT NULL,
+            cpu_time_ms       INTEGER,
+            peak_memory_bytes INTEGER,
+            artifact_bytes    INTEGER NOT NULL
+        )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS program_runs_program_id ON program_runs (program_id, started_at)",
+        [],
+    )?;
+    // outbound connections of each run, through the worker's egress proxy
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS program_run_egress (
+            run_id         BLOB NOT NULL,
+            destination    TEXT NOT NULL,
+            requests       INTEGER NOT NULL,
+            denied         INTEGER NOT NULL,
+            bytes_sent     INTEGER NOT NULL,
+            bytes_received INTEGER NOT NULL,
+            PRIMARY KEY (run_id, destination)
+        )",
+        [],
+    )?;
+
+    // results of runs started with an idempotency key, so a repeated request gets the first
+    // run's result. Not synced
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS program_run_keys (
+            program_id  BLOB NOT NULL,
+            key         TEXT NOT NULL,
+            output      TEXT NOT NULL,
+            created_at  INTEGER NOT NULL,
+            PRIMARY KEY (program_id, key)
+        )",
+        [],
+    )?;
+
+    // programs published as public sites through a gateway serving this node, see
+    // space::programs. Not synced
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS program_publications (
+            program_id   BLOB PRIMARY KEY,
+            name         TEXT NOT NULL UNIQUE,
+            hash         TEXT NOT NULL,
+            gateway      TEXT NOT NULL,
+            published_at INTEGER NOT NULL
+        )",
+        [],
+    )?;
+
+    // events written on this node that no peer has acknowledged yet, see space::outbox.
+    // Not synced
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS outbox (
+            event_id      TEXT PRIMARY KEY,
+            kind          INTEGER NOT NULL,
+            data_id       BLOB NOT NULL,
+            queued_at     INTEGER NOT NULL,
+            attempts      INTEGER NOT NULL DEFAULT 0,
+            last_error    TEXT,
+            published_at  INTEGER,
+            acked_at      INTEGER,
+            conflicts     TEXT
+        )",
+        [],
+    )?;
+    // pending entries are looked up on every flush & sync
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS outbox_acked_at ON outbox (acked_at, published_at)",
+        [],
+    )?;
+
     // a list of capabilities, either from others or self-issued
     // A capability is the association of an ability to a subject: subject x command x policy.
     conn.execute(
@@ -46,5 +326,6 @@ pub(crate) async fn setup_db(db: &DB) -> Result<()> {
         [],
     )?;
 
+    conn.pragma_update(None, "user_version", SCHEMA_VERSION)?;
     Ok(())
 }
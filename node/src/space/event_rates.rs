@@ -0,0 +1,278 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+use tracing::warn;
+use uuid::Uuid;
+
+/// weight given to the most recent window when updating an author's baseline
+const BASELINE_ALPHA: f64 = 0.1;
+const ALERT_CHANNEL_CAPACITY: usize = 64;
+
+/// Thresholds for spotting runaway writers. Programs write as an author, so per-author rates
+/// cover automation too.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+#[serde(rename_all = "camelCase")]
+pub struct RateLimits {
+    /// length of a counting window
+    pub window_secs: i64,
+    /// alert when a window holds this many times the author's baseline
+    pub spike_factor: f64,
+    /// never alert below this many events in a window. Authors with no history yet alert at
+    /// `min_events * spike_factor`
+    pub min_events: u64,
+    /// refuse writes from an author after an alert
+    pub throttle: bool,
+    /// how long a throttle lasts
+    pub throttle_secs: i64,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        Self {
+            window_secs: 60,
+            spike_factor: 100.0,
+            min_events: 120,
+            throttle: false,
+            throttle_secs: 10 * 60,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+#[serde(rename_all = "camelCase")]
+pub struct AuthorRate {
+    pub author: String,
+    /// events written in the current window
+    pub current: u64,
+    /// average events per window, None until a full window has passed
+    pub baseline: Option<f64>,
+    /// events written since the node started
+    pub total: u64,
+    pub throttled_until: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RateAlert {
+    pub space_id: Uuid,
+    pub author: String,
+    pub events: u64,
+    pub baseline: Option<f64>,
+    pub window_secs: i64,
+    pub throttled: bool,
+    pub created_at: i64,
+}
+
+#[derive(Debug, Default)]
+struct Counter {
+    window_start: i64,
+    count: u64,
+    baseline: Option<f64>,
+    total: u64,
+    alerted: bool,
+    throttled_until: Option<i64>,
+}
+
+impl Counter {
+    /// Fold finished windows into the baseline. Empty windows pull it toward zero.
+    fn roll(&mut self, now: i64, window_secs: i64) {
+        let elapsed = (now - self.window_start) / window_secs;
+        if elapsed < 1 {
+            return;
+        }
+        let count = self.count as f64;
+        let mut baseline = match self.baseline {
+            Some(b) => b * (1.0 - BASELINE_ALPHA) + count * BASELINE_ALPHA,
+            None => count,
+        };
+        baseline *= (1.0 - BASELINE_ALPHA).powi((elapsed - 1).min(i32::MAX as i64) as i32);
+        self.baseline = Some(baseline);
+        self.count = 0;
+        self.alerted = false;
+        self.window_start += elapsed * window_secs;
+    }
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    limits: RateLimits,
+    counters: HashMap<(Uuid, String), Counter>,
+}
+
+/// Counts event writes per space & author. Shared by all spaces, rates are kept in memory.
+#[derive(Debug, Clone)]
+pub struct EventRates {
+    inner: Arc<Mutex<Inner>>,
+    alerts: broadcast::Sender<RateAlert>,
+}
+
+impl Default for EventRates {
+    fn default() -> Self {
+        let (alerts, _) = broadcast::channel(ALERT_CHANNEL_CAPACITY);
+        Self {
+            inner: Default::default(),
+            alerts,
+        }
+    }
+}
+
+impl EventRates {
+    /// Alerts raised when an author's write rate spikes.
+    pub fn subscribe(&self) -> broadcast::Receiver<RateAlert> {
+        self.alerts.subscribe()
+    }
+
+    pub fn limits(&self) -> RateLimits {
+        self.inner.lock().unwrap().limits
+    }
+
+    pub fn set_limits(&self, limits: RateLimits) -> Result<()> {
+        anyhow::ensure!(limits.window_secs > 0, "window must be at least one second");
+        self.inner.lock().unwrap().limits = limits;
+        Ok(())
+    }
+
+    /// Current rates for every author that has written to a space.
+    pub fn rates(&self, space_id: Uuid) -> Vec<AuthorRate> {
+        let mut inner = self.inner.lock().unwrap();
+        let window_secs = inner.limits.window_secs;
+        let now = chrono::Utc::now().timestamp();
+        let mut rates = inner
+            .counters
+            .iter_mut()
+            .filter(|((space, _), _)| *space == space_id)
+            .map(|((_, author), counter)| {
+                counter.roll(now, window_secs);
+                AuthorRate {
+                    author: author.clone(),
+                    current: counter.count,
+                    baseline: counter.baseline,
+                    total: counter.total,
+                    throttled_until: counter.throttled_until.filter(|until| *until > now),
+                }
+            })
+            .collect::<Vec<_>>();
+        rates.sort_by(|a, b| b.current.cmp(&a.current));
+        rates
+    }
+
+    /// Lift a throttle before it expires.
+    pub fn unthrottle(&self, space_id: Uuid, author: &str) {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(counter) = inner.counters.get_mut(&(space_id, author.to_string())) {
+            counter.throttled_until = None;
+        }
+    }
+
+    /// Count a write, failing if the author is throttled.
+    pub(crate) fn record(&self, space_id: Uuid, author: &str) -> Result<()> {
+        self.record_at(space_id, author, chrono::Utc::now().timestamp())
+    }
+
+    fn record_at(&self, space_id: Uuid, author: &str, now: i64) -> Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        let limits = inner.limits;
+        let counter = inner
+            .counters
+            .entry((space_id, author.to_string()))
+            .or_insert_with(|| Counter {
+                window_start: now,
+                ..Default::default()
+            });
+
+        if let Some(until) = counter.throttled_until {
+            if until > now {
+                bail!("author {author} is throttled until {until} for writing events too quickly");
+            }
+            counter.throttled_until = None;
+        }
+
+        counter.roll(now, limits.window_secs);
+        counter.count += 1;
+        counter.total += 1;
+
+        let threshold = match counter.baseline {
+            Some(baseline) => (baseline * limits.spike_factor).max(limits.min_events as f64),
+            None => limits.min_events as f64 * limits.spike_factor,
+        };
+        if counter.alerted || (counter.count as f64) <= threshold {
+            return Ok(());
+        }
+
+        counter.alerted = true;
+        if limits.throttle {
+            counter.throttled_until = Some(now + limits.throttle_secs);
+        }
+        let alert = RateAlert {
+            space_id,
+            author: author.to_string(),
+            events: counter.count,
+            baseline: counter.baseline,
+            window_secs: limits.window_secs,
+            throttled: limits.throttle,
+            created_at: now,
+        };
+        warn!(
+            space = %space_id,
+            author,
+            events = alert.events,
+            baseline = ?alert.baseline,
+            "event write rate spike"
+        );
+        // no subscribers is fine
+        let _ = self.alerts.send(alert);
+
+        if limits.throttle {
+            bail!("author {author} is writing events too quickly and has been throttled");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spike_alerts_and_throttles() -> Result<()> {
+        let rates = EventRates::default();
+        rates.set_limits(RateLimits {
+            spike_factor: 10.0,
+            min_events: 5,
+            throttle: true,
+            ..Default::default()
+        })?;
+        let mut alerts = rates.subscribe();
+        let space = Uuid::new_v4();
+
+        // build a baseline of 2 events per window
+        let mut now = 0;
+        for _ in 0..20 {
+            rates.record_at(space, "bot", now)?;
+            rates.record_at(space, "bot", now + 1)?;
+            now += 60;
+        }
+        assert!(alerts.try_recv().is_err());
+
+        // baseline is ~2, so the 21st event in a window trips the alarm
+        for i in 0..20 {
+            rates.record_at(space, "bot", now + i)?;
+        }
+        assert!(rates.record_at(space, "bot", now + 20).is_err());
+        let alert = alerts.try_recv()?;
+        assert_eq!(alert.events, 21);
+        assert!(alert.throttled);
+
+        // other authors are unaffected, the throttle lifts on request
+        rates.record_at(space, "human", now + 21)?;
+        assert!(rates.record_at(space, "bot", now + 21).is_err());
+        rates.unthrottle(space, "bot");
+        rates.record_at(space, "bot", now + 22)?;
+        Ok(())
+    }
+}
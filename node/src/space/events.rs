@@ -18,16 +18,40 @@ use uuid::Uuid;
 
 use crate::router::RouterClient;
 
+use super::clock::Hlc;
 use super::db::DB;
+use super::validation;
 
 const NOSTR_EVENT_VERSION_NUMBER: u32 = 0;
 pub(crate) const NOSTR_SCHEMA_TAG: &str = "sch";
 pub(crate) const NOSTR_ID_TAG: &str = "id";
+/// the [`Hlc`] an event was written at, see [`super::clock`]
+pub(crate) const NOSTR_HLC_TAG: &str = "hlc";
+
+/// Fixtures of every [`EventFormat`], one `v<n>.json` file per version.
+#[cfg(test)]
+const EVENT_FIXTURES_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/events");
 
 pub(crate) const EVENT_SQL_READ_FIELDS: &str =
-    "id, pubkey, created_at, kind, schema_hash, data_id, content_hash, content";
+    "id, pubkey, created_at, kind, schema_hash, data_id, content_hash, content, hlc";
 const EVENT_SQL_WRITE_FIELDS: &str =
-    "id, pubkey, created_at, kind, schema_hash, data_id, content_hash, content, sig";
+    "id, pubkey, created_at, kind, schema_hash, data_id, content_hash, content, hlc, sig";
+/// read fields plus the signature, for events that are shared with others
+pub(crate) const EVENT_SQL_SIGNED_READ_FIELDS: &str = EVENT_SQL_WRITE_FIELDS;
+
+/// [`EVENT_SQL_SIGNED_READ_FIELDS`] for a db that may be from before event clocks, like a
+/// backup or a database that failed to open.
+pub(crate) fn signed_read_fields(conn: &rusqlite::Connection) -> Result<String> {
+    let has_hlc: bool = conn.query_row(
+        "SELECT COUNT(*) > 0 FROM pragma_table_info('events') WHERE name = 'hlc'",
+        [],
+        |row| row.get(0),
+    )?;
+    Ok(match has_hlc {
+        true => EVENT_SQL_SIGNED_READ_FIELDS.to_string(),
+        false => EVENT_SQL_SIGNED_READ_FIELDS.replace("hlc,", "NULL AS hlc,"),
+    })
+}
 
 #[derive(Debug, PartialEq, Copy, Clone)]
 pub enum EventKind {
@@ -43,6 +67,17 @@ pub enum EventKind {
     DeleteTable,
     MutateRow,
     DeleteRow,
+    RotateSecret,
+    Audit,
+    MutateRun,
+    MutateTrigger,
+    DeleteTrigger,
+    MutateCapability,
+    DeleteCapability,
+    MutateSchedule,
+    DeleteSchedule,
+    MutateGrant,
+    DeleteGrant,
 }
 
 impl EventKind {
@@ -62,6 +97,17 @@ impl EventKind {
             EventKind::DeleteTable => 100009,
             EventKind::MutateRow => 100010,
             EventKind::DeleteRow => 100011,
+            EventKind::RotateSecret => 100012,
+            EventKind::Audit => 100013,
+            EventKind::MutateRun => 100014,
+            EventKind::MutateTrigger => 100015,
+            EventKind::DeleteTrigger => 100016,
+            EventKind::MutateCapability => 100017,
+            EventKind::DeleteCapability => 100018,
+            EventKind::MutateSchedule => 100019,
+            EventKind::DeleteSchedule => 100020,
+            EventKind::MutateGrant => 100021,
+            EventKind::DeleteGrant => 100022,
         }
     }
 }
@@ -88,6 +134,17 @@ impl FromSql for EventKind {
             100009 => Ok(EventKind::DeleteTable),
             100010 => Ok(EventKind::MutateRow),
             100011 => Ok(EventKind::DeleteRow),
+            100012 => Ok(EventKind::RotateSecret),
+            100013 => Ok(EventKind::Audit),
+            100014 => Ok(EventKind::MutateRun),
+            100015 => Ok(EventKind::MutateTrigger),
+            100016 => Ok(EventKind::DeleteTrigger),
+            100017 => Ok(EventKind::MutateCapability),
+            100018 => Ok(EventKind::DeleteCapability),
+            100019 => Ok(EventKind::MutateSchedule),
+            100020 => Ok(EventKind::DeleteSchedule),
+            100021 => Ok(EventKind::MutateGrant),
+            100022 => Ok(EventKind::DeleteGrant),
             _ => Err(rusqlite::types::FromSqlError::OutOfRange(kind.into())),
         }
     }
@@ -102,6 +159,13 @@ impl Serialize for EventKind {
     }
 }
 
+#[cfg(feature = "specta")]
+impl specta::Type for EventKind {
+    fn inline(type_map: &mut specta::TypeMap, generics: specta::Generics) -> specta::DataType {
+        u32::inline(type_map, generics)
+    }
+}
+
 impl<'de> Deserialize<'de> for EventKind {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
@@ -121,6 +185,17 @@ impl<'de> Deserialize<'de> for EventKind {
             100009 => Ok(EventKind::DeleteTable),
             100010 => Ok(EventKind::MutateRow),
             100011 => Ok(EventKind::DeleteRow),
+            100012 => Ok(EventKind::RotateSecret),
+            100013 => Ok(EventKind::Audit),
+            100014 => Ok(EventKind::MutateRun),
+            100015 => Ok(EventKind::MutateTrigger),
+            100016 => Ok(EventKind::DeleteTrigger),
+            100017 => Ok(EventKind::MutateCapability),
+            100018 => Ok(EventKind::DeleteCapability),
+            100019 => Ok(EventKind::MutateSchedule),
+            100020 => Ok(EventKind::DeleteSchedule),
+            100021 => Ok(EventKind::MutateGrant),
+            100022 => Ok(EventKind::DeleteGrant),
             _ => Err(serde::de::Error::custom(format!(
                 "Unknown event kind: {}",
                 kind
@@ -194,6 +269,13 @@ impl<'de> Deserialize<'de> for Sha256Digest {
     }
 }
 
+#[cfg(feature = "specta")]
+impl specta::Type for Sha256Digest {
+    fn inline(type_map: &mut specta::TypeMap, generics: specta::Generics) -> specta::DataType {
+        String::inline(type_map, generics)
+    }
+}
+
 impl fmt::Display for Sha256Digest {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}", to_hex_string(&self.0))
@@ -238,6 +320,23 @@ impl Serialize for HashLink {
     }
 }
 
+/// The shape of a serialized [`HashLink`]: just the hash until the content is resolved.
+#[cfg(feature = "specta")]
+#[derive(Serialize, specta::Type)]
+#[serde(untagged)]
+#[allow(dead_code)]
+enum HashLinkRepr {
+    Hash(String),
+    Resolved { hash: String, value: Value },
+}
+
+#[cfg(feature = "specta")]
+impl specta::Type for HashLink {
+    fn inline(type_map: &mut specta::TypeMap, generics: specta::Generics) -> specta::DataType {
+        HashLinkRepr::inline(type_map, generics)
+    }
+}
+
 impl<'de> Deserialize<'de> for HashLink {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
@@ -298,6 +397,7 @@ impl HashLink {
 }
 
 #[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
 pub struct Tag(String, String, Option<String>);
 
 impl Tag {
@@ -320,13 +420,16 @@ impl Tag {
 // }
 
 #[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
 pub struct Event {
     pub id: Sha256Digest,
+    #[cfg_attr(feature = "specta", specta(type = String))]
     pub pubkey: PublicKey,
     #[serde(rename = "createdAt")]
     pub created_at: i64,
     pub kind: EventKind,
     pub tags: Vec<Tag>,
+    #[cfg_attr(feature = "specta", specta(type = Option<Vec<u8>>))]
     pub sig: Option<Signature>,
     pub content: HashLink,
 }
@@ -335,12 +438,14 @@ impl Event {
     pub(crate) fn create(
         author: Author,
         created_at: i64,
+        hlc: Hlc,
         kind: EventKind,
-        tags: Vec<Tag>,
+        mut tags: Vec<Tag>,
         content: HashLink,
     ) -> Result<Event> {
         // TODO(b5) - wat. why? you're doing something wrong with types.
         let pubkey = PublicKey::from_bytes(author.public_key().as_bytes())?;
+        tags.push(Tag::new(NOSTR_HLC_TAG, &hlc.to_string()));
 
         let id = Self::nostr_id(pubkey, created_at, kind, &tags, &content.hash)?;
         let sig = author.sign(id.as_bytes());
@@ -355,13 +460,13 @@ impl Event {
         })
     }
 
-    /// read a raw event, usually not what you want. Worth preferring higher-level reads like
-    /// programs, schemas, etc.
+    /// read the latest raw event for the object `id`, usually not what you want. Worth
+    /// preferring higher-level reads like programs, schemas, etc.
     pub(crate) async fn read_raw(db: &DB, id: Uuid) -> Result<Self> {
         let conn = db.lock().await;
         let mut stmt = conn.prepare(
             format!(
-                "SELECT {EVENT_SQL_READ_FIELDS} FROM events WHERE id = ?1 ORDER BY CREATED DESC"
+                "SELECT {EVENT_SQL_READ_FIELDS} FROM events WHERE data_id = ?1 ORDER BY clock DESC"
             )
             .as_str(),
         )?;
@@ -377,7 +482,8 @@ impl Event {
         hash: Hash,
     ) -> Result<Self> {
         let data = router.blobs().read_to_bytes(hash).await?;
-        let event: Self = serde_json::from_slice(&data)?;
+        let event = Self::decode(&data)?;
+        validation::validate(db, router, &event).await?;
         event.write(db).await?;
         Ok(event)
     }
@@ -393,7 +499,7 @@ impl Event {
         Ok((result.hash, result.tag))
     }
 
-    fn nostr_id(
+    pub(super) fn nostr_id(
         pubkey: PublicKey,
         created_at: i64,
         kind: EventKind,
@@ -435,9 +541,26 @@ impl Event {
         }
     }
 
+    /// The clock reading the event orders by: the one it was written at, or its `created_at`
+    /// for events from before clocks.
+    pub(crate) fn hlc(&self) -> Result<Hlc> {
+        Ok(self
+            .signed_hlc()?
+            .unwrap_or_else(|| Hlc::from_created_at(self.created_at)))
+    }
+
+    fn signed_hlc(&self) -> Result<Option<Hlc>> {
+        self.tags
+            .iter()
+            .find(|tag| tag.0 == NOSTR_HLC_TAG)
+            .map(|tag| Hlc::from_str(&tag.1))
+            .transpose()
+    }
+
     pub(crate) async fn write(&self, db: &DB) -> Result<()> {
         let schema = self.schema()?.map(|s| s.to_string());
         let data_id = self.data_id()?;
+        let hlc = self.signed_hlc()?.map(Hlc::to_sql);
         let sig = self.sig.map(|sig| Some(sig.to_bytes()));
         let value = match self.content.data {
             Some(ref v) => Some(v.to_bytes()?),
@@ -447,7 +570,7 @@ impl Event {
         let conn = db.lock().await;
         conn.execute(
             format!(
-                "INSERT INTO events ({EVENT_SQL_WRITE_FIELDS}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)"
+                "INSERT INTO events ({EVENT_SQL_WRITE_FIELDS}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)"
             )
             .as_str(),
             params![
@@ -459,6 +582,7 @@ impl Event {
                 data_id,
                 self.content.hash.to_string(),
                 value,
+                hlc,
                 sig,
             ],
         )
@@ -488,6 +612,10 @@ impl Event {
             tags.push(Tag(NOSTR_SCHEMA_TAG.to_string(), schema, None));
         }
         tags.push(Tag(NOSTR_ID_TAG.to_string(), data_id.to_string(), None));
+        let hlc: Option<i64> = row.get(8)?;
+        if let Some(hlc) = hlc {
+            tags.push(Tag::new(NOSTR_HLC_TAG, &Hlc::from_sql(hlc).to_string()));
+        }
 
         Ok(Self {
             id: Sha256Digest::from_str(&id).map_err(|e| anyhow!(e))?,
@@ -499,6 +627,50 @@ impl Event {
             sig: None,
         })
     }
+
+    /// Read a row selected with [`EVENT_SQL_SIGNED_READ_FIELDS`], signature included.
+    pub(crate) fn from_signed_sql_row(row: &rusqlite::Row) -> Result<Self> {
+        let mut event = Self::from_sql_row(row)?;
+        let sig: Vec<u8> = row.get(9)?;
+        event.sig = Some(Signature::from_slice(&sig).map_err(|e| anyhow!(e))?);
+        Ok(event)
+    }
+}
+
+/// The formats events have been shared in, oldest first. Peers keep events of every format
+/// around, so each of them has to stay decodable & keep its id & signature valid.
+///
+/// Changing the JSON of an event, or the data its id covers, takes a new format & fixtures
+/// for it, see the golden tests in this module.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum EventFormat {
+    /// before clocks: no `hlc` tag, ordered by `createdAt`
+    V1 = 1,
+    /// the clock reading is signed in an `hlc` tag
+    V2 = 2,
+}
+
+impl EventFormat {
+    /// the format events are written in
+    pub const CURRENT: EventFormat = EventFormat::V2;
+    pub const ALL: [EventFormat; 2] = [EventFormat::V1, EventFormat::V2];
+}
+
+impl Event {
+    /// Decode an event shared by a peer, in any [`EventFormat`].
+    pub(crate) fn decode(data: &[u8]) -> Result<Event> {
+        let event: Event = serde_json::from_slice(data).context("decoding event")?;
+        event.hlc().context("invalid clock reading")?;
+        Ok(event)
+    }
+
+    /// The format the event was written in.
+    pub fn format(&self) -> EventFormat {
+        match self.tags.iter().any(|tag| tag.0 == NOSTR_HLC_TAG) {
+            true => EventFormat::V2,
+            false => EventFormat::V1,
+        }
+    }
 }
 
 // Define the EventObject trait
@@ -506,5 +678,130 @@ pub(crate) trait EventObject {
     async fn from_event(event: Event, client: &RouterClient) -> Result<Self>
     where
         Self: Sized;
-    fn into_mutate_event(&self, author: Author) -> Result<Event>;
+    fn into_mutate_event(&self, author: Author, hlc: Hlc) -> Result<Event>;
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    /// The events in the fixture of the current format, rebuilt from scratch. Signatures are
+    /// deterministic, so the same events always encode the same.
+    fn current_events() -> Result<Vec<Event>> {
+        Ok(vec![
+            Event::create(
+                Author::from_bytes(&[1; 32]),
+                1_700_000_000,
+                Hlc::from_created_at(1_700_000_000),
+                EventKind::MutateRow,
+                vec![
+                    Tag::new(NOSTR_SCHEMA_TAG, &Hash::from_bytes([0x11; 32]).to_string()),
+                    Tag::new(
+                        NOSTR_ID_TAG,
+                        &Uuid::from_u128(0x6f1c2b9e3a4d4c5e8f70112233445566).to_string(),
+                    ),
+                ],
+                HashLink {
+                    hash: Hash::from_bytes([0x22; 32]),
+                    data: Some(json!({ "name": "ada" })),
+                },
+            )?,
+            Event::create(
+                Author::from_bytes(&[2; 32]),
+                1_700_000_060,
+                Hlc {
+                    millis: 1_700_000_060_000,
+                    counter: 3,
+                },
+                EventKind::MutateProgram,
+                vec![Tag::new(
+                    NOSTR_ID_TAG,
+                    &Uuid::from_u128(0x0b5e7a3c91f24d0e9a6b778899aabbcc).to_string(),
+                )],
+                HashLink::from(Hash::from_bytes([0x33; 32])),
+            )?,
+        ])
+    }
+
+    fn fixture_path(format: EventFormat) -> String {
+        format!("{EVENT_FIXTURES_DIR}/v{}.json", format as u32)
+    }
+
+    /// Fails when events encode differently than the fixture of [`EventFormat::CURRENT`].
+    /// Run with `UPDATE_GOLDEN=1` to rewrite the fixture once the change has a new format.
+    #[test]
+    fn golden_encoding() -> Result<()> {
+        let encoded = serde_json::to_string_pretty(&current_events()?)?;
+        let path = fixture_path(EventFormat::CURRENT);
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            std::fs::write(&path, format!("{encoded}\n"))?;
+        }
+        let fixture = std::fs::read_to_string(&path).with_context(|| format!("reading {path}"))?;
+        assert_eq!(
+            encoded,
+            fixture.trim_end(),
+            "event encoding changed, add an EventFormat & fixtures for it"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn decodes_every_format() -> Result<()> {
+        for format in EventFormat::ALL {
+            let path = fixture_path(format);
+            let fixture =
+                std::fs::read_to_string(&path).with_context(|| format!("reading {path}"))?;
+            let values: Vec<Value> = serde_json::from_str(&fixture)?;
+            for value in values {
+                let event = Event::decode(&serde_json::to_vec(&value)?)?;
+                assert_eq!(event.format(), format, "{path}");
+                let id = Event::nostr_id(
+                    event.pubkey,
+                    event.created_at,
+                    event.kind,
+                    &event.tags,
+                    &event.content.hash,
+                )?;
+                assert_eq!(id, event.id, "{path}: id doesn't cover the same data");
+                let sig = event.sig.expect("fixtures are signed");
+                event
+                    .pubkey
+                    .verify(event.id.as_bytes(), &sig)
+                    .map_err(|_| anyhow!("{path}: signature doesn't verify"))?;
+                assert_eq!(serde_json::to_value(&event)?, value, "{path}");
+            }
+        }
+        Ok(())
+    }
+
+    /// The bindings describe hash links by [`HashLinkRepr`], which must serialize the same.
+    #[cfg(feature = "specta")]
+    #[test]
+    fn hash_links_serialize_as_their_bindings_say() -> Result<()> {
+        let hash = Hash::new(b"content");
+        let unresolved = HashLink::from(hash);
+        assert_eq!(
+            serde_json::to_value(&unresolved)?,
+            serde_json::to_value(HashLinkRepr::Hash(hash.to_string()))?
+        );
+        let resolved = HashLink {
+            hash,
+            data: Some(json!({ "n": 1 })),
+        };
+        let repr = HashLinkRepr::Resolved {
+            hash: hash.to_string(),
+            value: json!({ "n": 1 }),
+        };
+        assert_eq!(
+            serde_json::to_value(&resolved)?,
+            serde_json::to_value(repr)?
+        );
+        assert_eq!(
+            serde_json::to_value(EventKind::MutateRow)?,
+            json!(EventKind::MutateRow.kind())
+        );
+        Ok(())
+    }
 }
@@ -0,0 +1,464 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use futures::StreamExt;
+use iroh::blobs::Hash;
+use iroh::client::docs::{Entry, LiveEvent};
+use iroh::docs::store::Query;
+use iroh::docs::{ContentStatus, DocTicket, NamespaceId};
+use rusqlite::params;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+use super::Space;
+
+const DEFAULT_KEY_FIELD: &str = "key";
+/// How often entries whose content is missing are checked again. Content that arrives
+/// without a `ContentReady` event, eg: fetched for another doc, is picked up this way.
+const PENDING_RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Describes how entries in an external doc become rows in a table.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+#[serde(rename_all = "camelCase")]
+pub struct SourceMapping {
+    /// schema hash of the table entries are mapped into
+    #[cfg_attr(feature = "specta", specta(type = String))]
+    pub table: Hash,
+    /// only map entries whose key starts with this prefix. The prefix is stripped from row keys
+    pub key_prefix: Option<String>,
+    /// row field the entry key is written to, defaults to "key"
+    pub key_field: Option<String>,
+}
+
+impl SourceMapping {
+    /// The row key for a doc entry key, None for entries outside the mapping.
+    fn row_key<'a>(&self, key: &'a [u8]) -> Option<&'a str> {
+        let key = std::str::from_utf8(key).ok()?;
+        match &self.key_prefix {
+            Some(prefix) => key.strip_prefix(prefix.as_str()),
+            None => Some(key),
+        }
+    }
+
+    /// Turn a doc entry into a row key & JSON object. Returns None for entries outside the
+    /// mapping.
+    fn map(&self, key: &[u8], content: &[u8]) -> Option<Result<(String, Value)>> {
+        let key = self.row_key(key)?;
+        let row = match serde_json::from_slice::<Value>(content) {
+            Ok(Value::Object(mut fields)) => {
+                let field = self.key_field.as_deref().unwrap_or(DEFAULT_KEY_FIELD);
+                fields.insert(field.to_string(), Value::String(key.to_string()));
+                Ok((key.to_string(), Value::Object(fields)))
+            }
+            Ok(_) => Err(anyhow!("entry {key} is not a JSON object")),
+            Err(e) => Err(anyhow!("entry {key} is not valid JSON: {e}")),
+        };
+        Some(row)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+#[serde(rename_all = "camelCase")]
+pub struct ExternalSource {
+    pub id: Uuid,
+    pub doc_id: String,
+    pub ticket: String,
+    pub mapping: SourceMapping,
+    pub created_at: i64,
+}
+
+/// A row mirrored from an external doc. These are never written as events, and can't be
+/// mutated from within the space.
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+#[serde(rename_all = "camelCase")]
+pub struct ExternalRow {
+    pub key: String,
+    pub author: String,
+    pub updated_at: i64,
+    pub content: Value,
+}
+
+#[derive(Clone)]
+pub struct ExternalSources(Space);
+
+impl ExternalSources {
+    pub fn new(space: Space) -> Self {
+        ExternalSources(space)
+    }
+
+    /// Subscribe to the doc in `ticket` & mirror its entries into a read-only table.
+    pub async fn attach(
+        &self,
+        ticket: DocTicket,
+        mapping: SourceMapping,
+    ) -> Result<ExternalSource> {
+        // make sure the target table exists before we start syncing
+        self.0.tables().get_by_hash(mapping.table).await?;
+
+        let doc = self.0.router.docs().import(ticket.clone()).await?;
+        let source = ExternalSource {
+            id: Uuid::new_v4(),
+            doc_id: doc.id().to_string(),
+            ticket: ticket.to_string(),
+            mapping,
+            created_at: chrono::Utc::now().timestamp(),
+        };
+
+        let conn = self.0.db.lock().await;
+        conn.execute(
+            "INSERT INTO external_sources (id, doc_id, ticket, mapping, created_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                source.id,
+                source.doc_id,
+                source.ticket,
+                serde_json::to_string(&source.mapping)?,
+                source.created_at,
+            ],
+        )?;
+        drop(conn);
+
+        self.watch(source.id).await?;
+        Ok(source)
+    }
+
+    /// Stop mirroring a source and remove its rows.
+    pub async fn detach(&self, id: Uuid) -> Result<()> {
+        let conn = self.0.db.lock().await;
+        conn.execute(
+            "DELETE FROM external_rows WHERE source_id = ?1",
+            params![id],
+        )?;
+        conn.execute("DELETE FROM external_sources WHERE id = ?1", params![id])?;
+        Ok(())
+    }
+
+    pub async fn get(&self, id: Uuid) -> Result<ExternalSource> {
+        self.list()
+            .await?
+            .into_iter()
+            .find(|source| source.id == id)
+            .ok_or_else(|| anyhow!("external source not found"))
+    }
+
+    pub async fn list(&self) -> Result<Vec<ExternalSource>> {
+        let conn = self.0.db.lock().await;
+        let mut stmt = conn
+            .prepare("SELECT id, doc_id, ticket, mapping, created_at FROM external_sources")
+            .context("selecting external sources")?;
+        let mut rows = stmt.query([])?;
+        let mut sources = Vec::new();
+        while let Some(row) = rows.next()? {
+            let mapping: String = row.get(3)?;
+            sources.push(ExternalSource {
+                id: row.get(0)?,
+                doc_id: row.get(1)?,
+                ticket: row.get(2)?,
+                mapping: serde_json::from_str(&mapping)?,
+                created_at: row.get(4)?,
+            });
+        }
+        Ok(sources)
+    }
+
+    pub async fn rows(&self, id: Uuid, offset: i64, limit: i64) -> Result<Vec<ExternalRow>> {
+        let conn = self.0.db.lock().await;
+        let mut stmt = conn.prepare(
+            "SELECT key, author, updated_at, content FROM external_rows WHERE source_id = ?1 ORDER BY key LIMIT ?2 OFFSET ?3",
+        )?;
+        let mut rows = stmt.query(params![id, limit, offset])?;
+        let mut results = Vec::new();
+        while let Some(row) = rows.next()? {
+            let content: Vec<u8> = row.get(3)?;
+            results.push(ExternalRow {
+                key: row.get(0)?,
+                author: row.get(1)?,
+                updated_at: row.get(2)?,
+                content: serde_json::from_slice(&content)?,
+            });
+        }
+        Ok(results)
+    }
+
+    /// Resume mirroring for every attached source, called when a space is opened.
+    pub async fn watch_all(&self) -> Result<()> {
+        for source in self.list().await? {
+            if let Err(err) = self.watch(source.id).await {
+                warn!("failed to watch external source {}: {:?}", source.id, err);
+            }
+        }
+        Ok(())
+    }
+
+    /// Copy the current state of the doc & spawn a task that applies updates as they arrive.
+    /// The task exits once the source is detached.
+    async fn watch(&self, id: Uuid) -> Result<()> {
+        let source = self.get(id).await?;
+        let namespace = NamespaceId::from_str(&source.doc_id)?;
+        let doc = self
+            .0
+            .router
+            .docs()
+            .open(namespace)
+            .await?
+            .ok_or_else(|| anyhow!("doc {} not found", source.doc_id))?;
+
+        // entries whose content hasn't arrived yet, applied once it does
+        let mut pending: HashMap<Hash, Entry> = HashMap::new();
+        let mut events = doc.subscribe().await?;
+        let mut entries = doc.get_many(Query::single_latest_per_key()).await?;
+        while let Some(entry) = entries.next().await {
+            let entry = entry?;
+            if !self.has_content(&entry).await? {
+                pending.insert(entry.content_hash(), entry);
+                continue;
+            }
+            if let Err(err) = self.apply(&source, &entry).await {
+                warn!(
+                    "external source {} failed to apply entry: {:?}",
+                    source.id, err
+                );
+            }
+        }
+
+        let this = self.clone();
+        tokio::spawn(async move {
+            let mut retry = tokio::time::interval(PENDING_RETRY_INTERVAL);
+            loop {
+                let ready = tokio::select! {
+                    event = events.next() => match event {
+                        Some(event) => this
+                            .ready_entry(&source, event, &mut pending)
+                            .into_iter()
+                            .collect(),
+                        None => break,
+                    },
+                    _ = retry.tick(), if !pending.is_empty() => {
+                        this.take_arrived(&mut pending).await
+                    }
+                };
+                if ready.is_empty() {
+                    continue;
+                }
+
+                match this.source_exists(source.id).await {
+                    Ok(true) => {}
+                    _ => break,
+                }
+                for entry in ready {
+                    if let Err(err) = this.apply(&source, &entry).await {
+                        warn!(
+                            "external source {} failed to apply entry: {:?}",
+                            source.id, err
+                        );
+                    }
+                }
+            }
+            debug!("stopped watching external source {}", source.id);
+        });
+        Ok(())
+    }
+
+    /// The entry a doc event makes ready to apply, if any. Entries whose content is still on
+    /// its way are parked in `pending`.
+    fn ready_entry(
+        &self,
+        source: &ExternalSource,
+        event: Result<LiveEvent>,
+        pending: &mut HashMap<Hash, Entry>,
+    ) -> Option<Entry> {
+        match event {
+            Ok(LiveEvent::InsertLocal { entry }) => Some(entry),
+            Ok(LiveEvent::InsertRemote {
+                entry,
+                content_status,
+                ..
+            }) => {
+                if content_status != ContentStatus::Complete {
+                    pending.insert(entry.content_hash(), entry);
+                    return None;
+                }
+                Some(entry)
+            }
+            Ok(LiveEvent::ContentReady { hash }) => pending.remove(&hash),
+            Ok(_) => None,
+            Err(err) => {
+                warn!("external source {} event error: {:?}", source.id, err);
+                None
+            }
+        }
+    }
+
+    /// Take the pending entries whose content is in the store by now.
+    async fn take_arrived(&self, pending: &mut HashMap<Hash, Entry>) -> Vec<Entry> {
+        let mut arrived = Vec::new();
+        for (hash, entry) in pending.iter() {
+            if self.has_content(entry).await.unwrap_or(false) {
+                arrived.push(*hash);
+            }
+        }
+        arrived
+            .into_iter()
+            .filter_map(|hash| pending.remove(&hash))
+            .collect()
+    }
+
+    /// Whether the content of an entry can be read, deletions have none to read.
+    async fn has_content(&self, entry: &Entry) -> Result<bool> {
+        if entry.content_len() == 0 {
+            return Ok(true);
+        }
+        self.0.router.blobs().has(entry.content_hash()).await
+    }
+
+    async fn source_exists(&self, id: Uuid) -> Result<bool> {
+        let conn = self.0.db.lock().await;
+        let count: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM external_sources WHERE id = ?1",
+            params![id],
+            |row| row.get(0),
+        )?;
+        Ok(count > 0)
+    }
+
+    async fn apply(&self, source: &ExternalSource, entry: &Entry) -> Result<()> {
+        // empty entries are deletions
+        if entry.content_len() == 0 {
+            if let Some(key) = source.mapping.row_key(entry.key()) {
+                let conn = self.0.db.lock().await;
+                conn.execute(
+                    "DELETE FROM external_rows WHERE source_id = ?1 AND key = ?2",
+                    params![source.id, key],
+                )?;
+            }
+            return Ok(());
+        }
+
+        let content = self
+            .0
+            .router
+            .blobs()
+            .read_to_bytes(entry.content_hash())
+            .await?;
+        let Some(mapped) = source.mapping.map(entry.key(), &content) else {
+            return Ok(());
+        };
+        let (key, row) = mapped?;
+
+        let conn = self.0.db.lock().await;
+        conn.execute(
+            "INSERT INTO external_rows (source_id, schema_hash, key, author, updated_at, content)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+            ON CONFLICT (source_id, key) DO UPDATE SET
+                author = excluded.author,
+                updated_at = excluded.updated_at,
+                content = excluded.content
+            WHERE excluded.updated_at >= external_rows.updated_at",
+            params![
+                source.id,
+                source.mapping.table.to_string(),
+                key,
+                entry.author().to_string(),
+                // entry timestamps are in microseconds
+                (entry.timestamp() / 1_000_000) as i64,
+                serde_json::to_vec(&row)?,
+            ],
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use iroh::base::node_addr::AddrInfoOptions;
+    use iroh::client::docs::ShareMode;
+    use iroh::docs::NamespaceSecret;
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn maps_entries() {
+        let mapping = SourceMapping {
+            table: Hash::new("table"),
+            key_prefix: Some("users/".to_string()),
+            key_field: Some("id".to_string()),
+        };
+        let (key, row) = mapping
+            .map(b"users/ada", br#"{"name": "Ada"}"#)
+            .unwrap()
+            .unwrap();
+        assert_eq!(key, "ada");
+        assert_eq!(row, json!({ "id": "ada", "name": "Ada" }));
+        assert!(mapping.map(b"teams/core", b"{}").is_none());
+        assert!(mapping.map(b"users/bob", b"[1, 2]").unwrap().is_err());
+    }
+
+    #[tokio::test]
+    async fn applies_entries_once_content_arrives() -> Result<()> {
+        let node = iroh::node::Node::memory().enable_docs().spawn().await?;
+        let space = Space::open_in_memory(
+            Uuid::new_v4(),
+            "external".to_string(),
+            NamespaceSecret::new(&mut rand::thread_rng()),
+            node.client().clone(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+        )
+        .await?;
+        let author = iroh::docs::Author::new(&mut rand::thread_rng());
+        let schema = json!({ "title": "users", "type": "object" });
+        let table = space
+            .tables()
+            .create(author, Bytes::from(schema.to_string()))
+            .await?;
+
+        // an entry whose content this node doesn't have yet
+        let doc = node.docs().create().await?;
+        let doc_author = node.authors().default().await?;
+        let content = Bytes::from(json!({ "name": "Ada" }).to_string());
+        let hash = Hash::new(&content);
+        doc.set_hash(doc_author, "ada", hash, content.len() as u64)
+            .await?;
+        let ticket = doc
+            .share(ShareMode::Read, AddrInfoOptions::default())
+            .await?;
+
+        let sources = space.external_sources();
+        let source = sources
+            .attach(
+                ticket,
+                SourceMapping {
+                    table: table.content.hash,
+                    key_prefix: None,
+                    key_field: None,
+                },
+            )
+            .await?;
+        assert!(sources.rows(source.id, 0, -1).await?.is_empty());
+
+        node.blobs().add_bytes(content).await?;
+        let deadline = tokio::time::Instant::now() + PENDING_RETRY_INTERVAL * 3;
+        loop {
+            let rows = sources.rows(source.id, 0, -1).await?;
+            if let Some(row) = rows.first() {
+                assert_eq!(row.content, json!({ "key": "ada", "name": "Ada" }));
+                break;
+            }
+            assert!(
+                tokio::time::Instant::now() < deadline,
+                "the entry was never applied"
+            );
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        Ok(())
+    }
+}
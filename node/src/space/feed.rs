@@ -0,0 +1,112 @@
+//! Publishing a space's events to an iroh doc, so read replicas can follow it.
+//!
+//! The doc shares the space's namespace. Every event is written under `events/<event id>` as
+//! signed event JSON. Publishing is opt-in: nothing is written until [`Feed::share`] imports
+//! the namespace, after that new events are published as they're written, by way of the
+//! [`crate::space::outbox`].
+use anyhow::Result;
+use futures::StreamExt;
+use iroh::base::node_addr::AddrInfoOptions;
+use iroh::client::docs::{Doc, ShareMode};
+use iroh::docs::{AuthorId, Capability, DocTicket};
+use iroh::net::{NodeAddr, NodeId};
+
+use super::events::{Event, Sha256Digest, EVENT_SQL_SIGNED_READ_FIELDS};
+use super::Space;
+
+pub(crate) const EVENTS_PREFIX: &str = "events/";
+
+pub(crate) fn event_key(id: &Sha256Digest) -> String {
+    format!("{EVENTS_PREFIX}{id}")
+}
+
+#[derive(Clone)]
+pub struct Feed(Space);
+
+impl Feed {
+    pub fn new(space: Space) -> Self {
+        Feed(space)
+    }
+
+    /// Start publishing the space, backfilling every event written so far. Returns a read-only
+    /// ticket for replicas.
+    pub async fn share(&self) -> Result<DocTicket> {
+        let doc = match self.doc().await? {
+            Some(doc) => doc,
+            None => {
+                let capability = Capability::Write(self.0.secret());
+                self.0.router.docs().import_namespace(capability).await?
+            }
+        };
+        let author = self.0.router.authors().default().await?;
+        for event in self.signed_events().await? {
+            publish_to(&doc, author, &event).await?;
+        }
+        doc.share(ShareMode::Read, AddrInfoOptions::RelayAndAddresses)
+            .await
+    }
+
+    /// Stop syncing the feed with `peer`: leave the doc's swarm & join it again through the
+    /// other peers it synced with. The outbox skips forgotten peers from then on, see
+    /// [`crate::peers::PeerBook::forget`].
+    pub(crate) async fn stop_sync_with(&self, peer: NodeId) -> Result<()> {
+        let Some(doc) = self.doc().await? else {
+            return Ok(());
+        };
+        let known = doc.get_sync_peers().await?.unwrap_or_default();
+        if !known.contains(peer.as_bytes()) {
+            return Ok(());
+        }
+        doc.leave().await?;
+        let others: Vec<NodeAddr> = known
+            .into_iter()
+            .filter_map(|other| NodeId::from_bytes(&other).ok())
+            .filter(|other| *other != peer)
+            .map(NodeAddr::new)
+            .collect();
+        if !others.is_empty() {
+            doc.start_sync(others).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn is_shared(&self) -> Result<bool> {
+        Ok(self.doc().await?.is_some())
+    }
+
+    pub(crate) async fn doc(&self) -> Result<Option<Doc>> {
+        // opening a doc the node doesn't have is an error, not `None`
+        let id = self.0.secret().id();
+        let mut docs = self.0.router.docs().list().await?;
+        while let Some(doc) = docs.next().await {
+            if doc?.0 == id {
+                return self.0.router.docs().open(id).await;
+            }
+        }
+        Ok(None)
+    }
+
+    async fn signed_events(&self) -> Result<Vec<Event>> {
+        let conn = self.0.db.lock().await;
+        let mut stmt = conn.prepare(
+            format!("SELECT {EVENT_SQL_SIGNED_READ_FIELDS} FROM events ORDER BY clock").as_str(),
+        )?;
+        let mut rows = stmt.query([])?;
+        let mut events = Vec::new();
+        while let Some(row) = rows.next()? {
+            events.push(Event::from_signed_sql_row(row)?);
+        }
+        Ok(events)
+    }
+}
+
+pub(crate) async fn publish_to(doc: &Doc, author: AuthorId, event: &Event) -> Result<()> {
+    let key = event_key(&event.id);
+    // events are immutable, re-writing one would only cost replicas a sync
+    if doc.get_exact(author, key.clone(), false).await?.is_some() {
+        return Ok(());
+    }
+    doc.set_bytes(author, key, serde_json::to_vec(event)?)
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,235 @@
+//! Copying a space into a brand-new one.
+//!
+//! A fork shares no history with its source. Everything copied is written as new events
+//! signed by the forker, so a shared template space can become your own without carrying
+//! along the original authors, secrets or users.
+use std::collections::HashMap;
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use iroh::blobs::Hash;
+use iroh::docs::Author;
+use iroh::net::key::PublicKey;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::events::EventObject;
+use super::programs::Program;
+use super::tables::Table;
+use super::Space;
+
+/// Which rows to copy into a fork. Tables are always copied.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+#[serde(rename_all = "camelCase", tag = "mode", content = "tables")]
+pub enum RowSelection {
+    /// empty tables only
+    #[default]
+    None,
+    All,
+    /// rows of the tables with these schema hashes
+    Tables(Vec<Hash>),
+}
+
+impl RowSelection {
+    fn includes(&self, schema: &Hash) -> bool {
+        match self {
+            RowSelection::None => false,
+            RowSelection::All => true,
+            RowSelection::Tables(tables) => tables.contains(schema),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+#[serde(rename_all = "camelCase", default)]
+pub struct ForkOptions {
+    /// description of the new space, defaults to the source's
+    pub description: Option<String>,
+    pub rows: RowSelection,
+    pub programs: bool,
+}
+
+impl Default for ForkOptions {
+    fn default() -> Self {
+        Self {
+            description: None,
+            rows: RowSelection::None,
+            programs: true,
+        }
+    }
+}
+
+/// Copy tables, selected rows & programs from `source` into the empty space `dest`. Only the
+/// latest version of each object is copied.
+pub(crate) async fn copy_into(
+    source: &Space,
+    dest: &Space,
+    author: Author,
+    options: &ForkOptions,
+) -> Result<()> {
+    let router = source.router();
+    let now = chrono::Utc::now().timestamp();
+
+    let mut tables: HashMap<Uuid, Table> = HashMap::new();
+    for table in source.tables().list(0, -1).await? {
+        // listed oldest first, later versions win
+        tables.insert(table.id, table);
+    }
+    for (id, mut table) in tables {
+        let source_schema = table.content.hash;
+        let data = table.content.resolve(router).await?;
+        let data = Bytes::from(serde_json::to_vec(&data)?);
+        let copy = dest
+            .tables()
+            .mutate(author.clone(), id, data)
+            .await
+            .with_context(|| format!("copying table {}", table.title))?;
+
+        if !options.rows.includes(&source_schema) {
+            continue;
+        }
+        let mut rows = HashMap::new();
+        for row in source
+            .rows()
+            .query(source_schema, String::new(), 0, -1)
+            .await?
+        {
+            match rows.get(&row.id) {
+                Some((created_at, _)) if *created_at > row.created_at => {}
+                _ => {
+                    rows.insert(row.id, (row.created_at, row.content));
+                }
+            }
+        }
+        for (id, (_, mut content)) in rows {
+            let data = content.resolve(router).await?;
+            dest.rows()
+                .mutate(author.clone(), copy.content.hash, id, data)
+                .await
+                .with_context(|| format!("copying row {id} of table {}", table.title))?;
+        }
+    }
+
+    if options.programs {
+        let mut programs: HashMap<Uuid, Program> = HashMap::new();
+        for program in source.programs().list(0, -1).await? {
+            match programs.get(&program.id) {
+                Some(prev) if prev.created_at > program.created_at => {}
+                _ => {
+                    programs.insert(program.id, program);
+                }
+            }
+        }
+        // program collections are already installed & tagged under their id, only the event
+        // is new
+        for (_, mut program) in programs {
+            program.created_at = now;
+            program.author = PublicKey::from_bytes(author.public_key().as_bytes())?;
+            let event = program.into_mutate_event(author.clone(), dest.clock().tick())?;
+            dest.write_event(&event)
+                .await
+                .with_context(|| format!("copying program {}", program.manifest.name))?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::space::space_events::SpaceEvents;
+    use crate::space::Spaces;
+
+    #[tokio::test]
+    async fn fork_copies_selected_rows() -> Result<()> {
+        let node = iroh::node::Node::memory().spawn().await?;
+        let dir = tempfile::tempdir()?;
+        let router = node.client().clone();
+        let mut spaces = Spaces::open_all(router.clone(), dir.path()).await?;
+        let author = Author::new(&mut rand::thread_rng());
+        let forker = Author::new(&mut rand::thread_rng());
+
+        let template = spaces
+            .create(&router, author.clone(), "template", "a template")
+            .await?;
+        let schema = |title: &str| {
+            let schema = json!({
+                "title": title,
+                "type": "object",
+                "properties": { "n": { "type": "number" } },
+            });
+            Bytes::from(schema.to_string())
+        };
+        let kept = template
+            .tables()
+            .create(author.clone(), schema("kept"))
+            .await?;
+        let empty = template
+            .tables()
+            .create(author.clone(), schema("empty"))
+            .await?;
+        let row = template
+            .rows()
+            .create(author.clone(), kept.content.hash, json!({ "n": 1 }))
+            .await?;
+        template
+            .rows()
+            .mutate(author.clone(), kept.content.hash, row.id, json!({ "n": 2 }))
+            .await?;
+        template
+            .rows()
+            .create(author.clone(), empty.content.hash, json!({ "n": 3 }))
+            .await?;
+
+        let options = ForkOptions {
+            rows: RowSelection::Tables(vec![kept.content.hash]),
+            ..Default::default()
+        };
+        let fork = spaces
+            .fork(forker.clone(), &template.id, "mine", options)
+            .await?;
+        assert_ne!(fork.id, template.id);
+        assert!(spaces.get_by_name("mine").await.is_some());
+
+        let details = SpaceEvents::new(fork.clone())
+            .details()
+            .await?
+            .expect("details");
+        assert_eq!(details.description, "a template");
+
+        let tables = fork.tables().list(0, -1).await?;
+        assert_eq!(tables.len(), 2);
+        let forker_key = PublicKey::from_bytes(forker.public_key().as_bytes())?;
+        assert!(tables.iter().all(|t| t.author == forker_key));
+
+        let schema_of = |title: &str| {
+            let table = tables.iter().find(|t| t.title == title).expect("table");
+            table.content.hash
+        };
+        let rows = fork
+            .rows()
+            .query(schema_of("kept"), String::new(), 0, -1)
+            .await?;
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].id, row.id);
+        assert_eq!(rows[0].content.data, Some(json!({ "n": 2 })));
+        assert_eq!(rows[0].author, forker_key);
+        let rows = fork
+            .rows()
+            .query(schema_of("empty"), String::new(), 0, -1)
+            .await?;
+        assert!(rows.is_empty());
+
+        // forks never reuse a name
+        let res = spaces
+            .fork(forker, &template.id, "mine", ForkOptions::default())
+            .await;
+        assert!(res.is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,300 @@
+//! Grants let programs of another space use this one, eg: a program in each member's personal
+//! space that adds up their hours in a team space.
+//!
+//! A grant is a `MutateGrant` event in the space being used, naming the other space, its
+//! program & whether the program may only read or also write. Runs list the spaces they use
+//! as [`SpaceHandle`]s, and are refused unless every one of those spaces granted the program
+//! at least the access the handle asks for. Grants are checked when the run starts & again by
+//! the worker executing it. Each run that uses a space is recorded in the audit log of both
+//! spaces, see [`crate::space::audit::AuditAction::CrossSpaceRun`].
+use anyhow::{anyhow, ensure, Result};
+use iroh::docs::Author;
+use iroh::net::key::PublicKey;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::router::RouterClient;
+use crate::Error;
+
+use super::audit::AuditAction;
+use super::clock::Hlc;
+use super::events::{
+    Event, EventKind, EventObject, HashLink, Tag, EVENT_SQL_READ_FIELDS, NOSTR_ID_TAG,
+};
+use super::{Space, Spaces};
+
+/// What a program may do in a space that granted it. Writers can read too.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+#[serde(rename_all = "lowercase")]
+pub enum Access {
+    Read,
+    Write,
+}
+
+/// Another space a run uses & how.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+#[serde(rename_all = "camelCase")]
+pub struct SpaceHandle {
+    pub space: Uuid,
+    pub access: Access,
+}
+
+/// What's stored about a grant, the content of its event.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GrantContent {
+    space: Uuid,
+    program_id: Uuid,
+    access: Access,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+#[serde(rename_all = "camelCase")]
+pub struct Grant {
+    pub id: Uuid,
+    pub created_at: i64,
+    #[cfg_attr(feature = "specta", specta(type = String))]
+    pub author: PublicKey,
+    pub content: HashLink,
+    /// the space the program belongs to
+    pub space: Uuid,
+    pub program_id: Uuid,
+    pub access: Access,
+}
+
+impl EventObject for Grant {
+    async fn from_event(event: Event, router: &RouterClient) -> Result<Self> {
+        if event.kind != EventKind::MutateGrant {
+            return Err(anyhow!("event is not a grant mutation"));
+        }
+        let id = event.data_id()?.ok_or_else(|| anyhow!("missing data id"))?;
+        let mut content = event.content;
+        let details: GrantContent = serde_json::from_value(content.resolve(router).await?)?;
+        Ok(Grant {
+            id,
+            created_at: event.created_at,
+            author: event.pubkey,
+            content,
+            space: details.space,
+            program_id: details.program_id,
+            access: details.access,
+        })
+    }
+
+    fn into_mutate_event(&self, author: Author, hlc: Hlc) -> Result<Event> {
+        let tags = vec![Tag::new(NOSTR_ID_TAG, self.id.to_string().as_str())];
+        Event::create(
+            author,
+            self.created_at,
+            hlc,
+            EventKind::MutateGrant,
+            tags,
+            self.content.clone(),
+        )
+    }
+}
+
+pub struct Grants(Space);
+
+impl Grants {
+    pub fn new(space: Space) -> Self {
+        Grants(space)
+    }
+
+    /// Let `program_id` of `space` use this space with `access`.
+    pub async fn create(
+        &self,
+        author: Author,
+        space: Uuid,
+        program_id: Uuid,
+        access: Access,
+    ) -> Result<Grant> {
+        ensure!(
+            space != self.0.id,
+            "programs can always use their own space"
+        );
+        let details = GrantContent {
+            space,
+            program_id,
+            access,
+        };
+        let value = serde_json::to_value(&details)?;
+        let content = self
+            .0
+            .router
+            .blobs()
+            .add_bytes(serde_json::to_vec(&value)?)
+            .await?;
+        let grant = Grant {
+            id: Uuid::new_v4(),
+            created_at: chrono::Utc::now().timestamp(),
+            author: PublicKey::from_bytes(author.public_key().as_bytes())?,
+            content: HashLink {
+                hash: content.hash,
+                data: Some(value),
+            },
+            space,
+            program_id,
+            access,
+        };
+        self.0
+            .write_event(&grant.into_mutate_event(author.clone(), self.0.clock().tick())?)
+            .await?;
+        let action = AuditAction::CapabilityGranted {
+            subject: format!("program {program_id} of space {space}"),
+            resource: format!("{access:?} access to this space").to_lowercase(),
+        };
+        self.0.audit().record(author, action).await?;
+        Ok(grant)
+    }
+
+    /// Take a grant back. Runs already going keep their access until they finish.
+    pub async fn delete(&self, author: Author, id: Uuid) -> Result<()> {
+        let grant = self.get(id).await?;
+        let tags = vec![Tag::new(NOSTR_ID_TAG, id.to_string().as_str())];
+        let event = Event::create(
+            author,
+            chrono::Utc::now().timestamp(),
+            self.0.clock().tick(),
+            EventKind::DeleteGrant,
+            tags,
+            HashLink::from(grant.content.hash),
+        )?;
+        self.0.write_event(&event).await
+    }
+
+    pub async fn get(&self, id: Uuid) -> Result<Grant> {
+        let mut grants = self.select("data_id = ?2", &[&id]).await?;
+        grants.pop().ok_or_else(|| anyhow!("grant not found"))
+    }
+
+    pub async fn list(&self) -> Result<Vec<Grant>> {
+        self.select("1", &[]).await
+    }
+
+    /// Refuse unless `program_id` of `space` was granted at least `access` to this space.
+    pub async fn check(&self, space: Uuid, program_id: Uuid, access: Access) -> Result<()> {
+        let granted = self.list().await?.iter().any(|grant| {
+            grant.space == space && grant.program_id == program_id && grant.access >= access
+        });
+        ensure!(
+            granted,
+            "space {} hasn't granted program {} of space {} {:?} access",
+            self.0.id,
+            program_id,
+            space,
+            access
+        );
+        Ok(())
+    }
+
+    /// The latest version of each grant that hasn't been deleted & matches `condition`,
+    /// which takes its parameters from `?2` on.
+    async fn select(
+        &self,
+        condition: &str,
+        params: &[&(dyn rusqlite::ToSql + Sync)],
+    ) -> Result<Vec<Grant>> {
+        let sql = format!(
+            "SELECT {EVENT_SQL_READ_FIELDS}, MAX(clock) FROM events
+            WHERE kind = ?1 AND {condition}
+            AND data_id NOT IN (SELECT data_id FROM events WHERE kind = {deleted})
+            GROUP BY data_id ORDER BY clock",
+            deleted = EventKind::DeleteGrant.kind(),
+        );
+        let events = {
+            let conn = self.0.db.lock().await;
+            let mut stmt = conn.prepare(&sql)?;
+            let mut params: Vec<&dyn rusqlite::ToSql> = params
+                .iter()
+                .map(|param| *param as &dyn rusqlite::ToSql)
+                .collect();
+            params.insert(0, &EventKind::MutateGrant);
+            let mut rows = stmt.query(params.as_slice())?;
+            let mut events = Vec::new();
+            while let Some(row) = rows.next()? {
+                events.push(Event::from_sql_row(row)?);
+            }
+            events
+        };
+        let mut grants = Vec::new();
+        for event in events {
+            grants.push(Grant::from_event(event, &self.0.router).await?);
+        }
+        Ok(grants)
+    }
+}
+
+/// Open the spaces a run of `program_id` from `space` uses, refusing any that didn't grant
+/// the program the access its handle asks for.
+pub async fn linked_spaces(
+    spaces: &Spaces,
+    space: Uuid,
+    program_id: Uuid,
+    handles: &[SpaceHandle],
+) -> Result<Vec<(Space, Access)>> {
+    let mut linked = Vec::with_capacity(handles.len());
+    for handle in handles {
+        ensure!(handle.space != space, "runs always have their own space");
+        let other = spaces
+            .get(&handle.space)
+            .await
+            .ok_or(Error::SpaceNotFound { id: handle.space })?;
+        other
+            .grants()
+            .check(space, program_id, handle.access)
+            .await?;
+        linked.push((other, handle.access));
+    }
+    Ok(linked)
+}
+
+#[cfg(test)]
+mod tests {
+    use iroh::docs::NamespaceSecret;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn grants_are_checked() -> Result<()> {
+        let node = iroh::node::Node::memory().spawn().await?;
+        let team = Space::open_in_memory(
+            Uuid::new_v4(),
+            "team".to_string(),
+            NamespaceSecret::new(&mut rand::thread_rng()),
+            node.client().clone(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+        )
+        .await?;
+        let author = Author::new(&mut rand::thread_rng());
+        let (personal, program) = (Uuid::new_v4(), Uuid::new_v4());
+        let grants = team.grants();
+        assert!(grants.check(personal, program, Access::Read).await.is_err());
+
+        let grant = grants
+            .create(author.clone(), personal, program, Access::Read)
+            .await?;
+        grants.check(personal, program, Access::Read).await?;
+        assert!(grants
+            .check(personal, program, Access::Write)
+            .await
+            .is_err());
+        assert!(grants
+            .check(personal, Uuid::new_v4(), Access::Read)
+            .await
+            .is_err());
+        assert!(grants
+            .create(author.clone(), team.id, program, Access::Read)
+            .await
+            .is_err());
+
+        grants.delete(author, grant.id).await?;
+        assert!(grants.check(personal, program, Access::Read).await.is_err());
+        Ok(())
+    }
+}
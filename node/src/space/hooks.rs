@@ -0,0 +1,221 @@
+//! Callbacks for crates embedding the node, to extend what happens when spaces change (custom
+//! indexes, mirroring rows to another database) without touching the write paths.
+//!
+//! One set of hooks is shared by every space a [`super::Spaces`] opens, hooks get the space
+//! along with what changed & can filter on it. Hooks run inline, after the change is stored:
+//! keep them quick & hand anything slow off to a task of your own.
+use std::fmt;
+use std::sync::{Arc, RwLock};
+
+use iroh::blobs::Hash;
+use uuid::Uuid;
+
+use super::events::{Event, EventKind, HashLink};
+use super::programs::Program;
+use super::Space;
+
+/// Identifies a registered hook, for [`Hooks::remove`].
+pub type HookId = u64;
+
+type EventHook = Arc<dyn Fn(&Space, &Event) + Send + Sync>;
+type RowHook = Arc<dyn Fn(&Space, &RowWrite) + Send + Sync>;
+type ProgramHook = Arc<dyn Fn(&Space, &Program) + Send + Sync>;
+
+/// A row written or deleted, as passed to [`Hooks::on_row_changed`].
+#[derive(Debug)]
+pub enum RowWrite<'a> {
+    /// a new row or a new version of one
+    Mutate {
+        table: Hash,
+        id: Uuid,
+        content: &'a HashLink,
+    },
+    Delete {
+        table: Hash,
+        id: Uuid,
+    },
+}
+
+#[derive(Default)]
+struct Registry {
+    next_id: HookId,
+    event_written: Vec<(HookId, EventHook)>,
+    row_changed: Vec<(HookId, RowHook)>,
+    program_installed: Vec<(HookId, ProgramHook)>,
+}
+
+#[derive(Clone, Default)]
+pub struct Hooks(Arc<RwLock<Registry>>);
+
+impl fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let registry = self.0.read().expect("poisoned");
+        f.debug_struct("Hooks")
+            .field("event_written", &registry.event_written.len())
+            .field("row_changed", &registry.row_changed.len())
+            .field("program_installed", &registry.program_installed.len())
+            .finish()
+    }
+}
+
+impl Hooks {
+    /// Call `hook` with every event written to a space.
+    pub fn on_event_written(
+        &self,
+        hook: impl Fn(&Space, &Event) + Send + Sync + 'static,
+    ) -> HookId {
+        self.register(|registry, id| registry.event_written.push((id, Arc::new(hook))))
+    }
+
+    /// Call `hook` whenever a row is written or deleted.
+    pub fn on_row_changed(
+        &self,
+        hook: impl Fn(&Space, &RowWrite) + Send + Sync + 'static,
+    ) -> HookId {
+        self.register(|registry, id| registry.row_changed.push((id, Arc::new(hook))))
+    }
+
+    /// Call `hook` whenever a program is installed, from a directory or a ticket.
+    pub fn on_program_installed(
+        &self,
+        hook: impl Fn(&Space, &Program) + Send + Sync + 'static,
+    ) -> HookId {
+        self.register(|registry, id| registry.program_installed.push((id, Arc::new(hook))))
+    }
+
+    /// Unregister a hook. Unknown ids are ignored.
+    pub fn remove(&self, id: HookId) {
+        let mut registry = self.0.write().expect("poisoned");
+        registry.event_written.retain(|(hook, _)| *hook != id);
+        registry.row_changed.retain(|(hook, _)| *hook != id);
+        registry.program_installed.retain(|(hook, _)| *hook != id);
+    }
+
+    fn register(&self, add: impl FnOnce(&mut Registry, HookId)) -> HookId {
+        let mut registry = self.0.write().expect("poisoned");
+        let id = registry.next_id;
+        registry.next_id += 1;
+        add(&mut registry, id);
+        id
+    }
+
+    // hooks are cloned out of the registry before they're called, so a hook can register or
+    // remove hooks without deadlocking
+
+    pub(crate) fn event_written(&self, space: &Space, event: &Event) {
+        let hooks = self.0.read().expect("poisoned").event_written.clone();
+        for (_, hook) in hooks {
+            hook(space, event);
+        }
+
+        let row_hooks = self.0.read().expect("poisoned").row_changed.clone();
+        if row_hooks.is_empty() {
+            return;
+        }
+        let Some(write) = row_write(event) else {
+            return;
+        };
+        for (_, hook) in row_hooks {
+            hook(space, &write);
+        }
+    }
+
+    pub(crate) fn program_installed(&self, space: &Space, program: &Program) {
+        let hooks = self.0.read().expect("poisoned").program_installed.clone();
+        for (_, hook) in hooks {
+            hook(space, program);
+        }
+    }
+}
+
+fn row_write(event: &Event) -> Option<RowWrite<'_>> {
+    let table = event.schema().ok().flatten()?;
+    let id = event.data_id().ok().flatten()?;
+    match event.kind {
+        EventKind::MutateRow => Some(RowWrite::Mutate {
+            table,
+            id,
+            content: &event.content,
+        }),
+        EventKind::DeleteRow => Some(RowWrite::Delete { table, id }),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use anyhow::Result;
+    use iroh::docs::Author;
+
+    use super::*;
+    use crate::space::events::{Tag, NOSTR_ID_TAG, NOSTR_SCHEMA_TAG};
+
+    #[tokio::test]
+    async fn hooks_see_writes() -> Result<()> {
+        let node = iroh::node::Node::memory().spawn().await?;
+        let space = Space::open_in_memory(
+            Uuid::new_v4(),
+            "hooks".to_string(),
+            iroh::docs::NamespaceSecret::new(&mut rand::thread_rng()),
+            node.client().clone(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+        )
+        .await?;
+
+        let events = Arc::new(Mutex::new(0));
+        let rows = Arc::new(Mutex::new(Vec::new()));
+        let events2 = events.clone();
+        let event_hook = space.hooks().on_event_written(move |_, _| {
+            *events2.lock().unwrap() += 1;
+        });
+        let rows2 = rows.clone();
+        space.hooks().on_row_changed(move |_, write| {
+            let id = match write {
+                RowWrite::Mutate { id, .. } => *id,
+                RowWrite::Delete { id, .. } => *id,
+            };
+            rows2.lock().unwrap().push(id);
+        });
+
+        let author = Author::new(&mut rand::thread_rng());
+        let table = Hash::new(b"table");
+        let row = Uuid::new_v4();
+        let tags = vec![
+            Tag::new(NOSTR_SCHEMA_TAG, &table.to_string()),
+            Tag::new(NOSTR_ID_TAG, &row.to_string()),
+        ];
+        let event = Event::create(
+            author.clone(),
+            1,
+            space.clock().tick(),
+            EventKind::MutateRow,
+            tags,
+            HashLink {
+                hash: Hash::new(b"{}"),
+                data: Some(serde_json::json!({})),
+            },
+        )?;
+        space.write_event(&event).await?;
+        assert_eq!(*events.lock().unwrap(), 1);
+        assert_eq!(*rows.lock().unwrap(), vec![row]);
+
+        space.hooks().remove(event_hook);
+        let tags = vec![Tag::new(NOSTR_ID_TAG, &Uuid::new_v4().to_string())];
+        let event = Event::create(
+            author,
+            2,
+            space.clock().tick(),
+            EventKind::MutateUser,
+            tags,
+            HashLink::from(Hash::new(b"user")),
+        )?;
+        space.write_event(&event).await?;
+        assert_eq!(*events.lock().unwrap(), 1, "removed hooks aren't called");
+        assert_eq!(rows.lock().unwrap().len(), 1, "only rows reach row hooks");
+        Ok(())
+    }
+}
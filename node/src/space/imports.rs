@@ -0,0 +1,659 @@
+//! Tables imported from Google Sheets & Airtable.
+//!
+//! An import creates a table shaped like its source & copies every source row into it, then
+//! syncs again on an interval for as long as the import exists. Rows are matched across syncs
+//! by a key column, and only rows whose content changed are written. When the columns of the
+//! source change, the table's schema is updated & every row is written again under it.
+//! Connectors only talk to their service's API host.
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Result};
+use bytes::Bytes;
+use iroh::blobs::Hash;
+use iroh::docs::{Author, AuthorId};
+use rusqlite::params;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+use super::sensitive;
+use super::{Space, SpaceSecret};
+
+const GOOGLE_SHEETS_API: &str = "https://sheets.googleapis.com/v4/spreadsheets";
+const AIRTABLE_API: &str = "https://api.airtable.com/v0";
+/// the first sheet of a spreadsheet, every column up to ZZ
+const DEFAULT_SHEET_RANGE: &str = "A:ZZ";
+const DEFAULT_REFRESH_SECS: u64 = 15 * 60;
+/// Airtable pages are at most 100 records, stop paging after this many
+const MAX_AIRTABLE_PAGES: usize = 1000;
+/// field of the stored credentials that's sealed
+const AUTH_FIELD: &str = "auth";
+
+/// Where an import reads rows from.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", tag = "kind")]
+pub enum ImportSource {
+    #[serde(rename_all = "camelCase")]
+    GoogleSheet {
+        sheet_id: String,
+        /// A1 notation, eg: "Sheet2!A:F". The first row holds column names.
+        range: Option<String>,
+    },
+    #[serde(rename_all = "camelCase")]
+    Airtable { base_id: String, table: String },
+}
+
+/// Credentials for the source's API. Stored in the space database, which isn't synced, sealed
+/// with the space secret.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", tag = "type", content = "value")]
+pub enum ImportAuth {
+    /// Google API key, for sheets readable by anyone with the link
+    ApiKey(String),
+    /// OAuth access token for Google, or a personal access token for Airtable
+    Bearer(String),
+}
+
+impl std::fmt::Debug for ImportAuth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImportAuth::ApiKey(_) => write!(f, "ApiKey(..)"),
+            ImportAuth::Bearer(_) => write!(f, "Bearer(..)"),
+        }
+    }
+}
+
+/// How source rows become table rows.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ImportMapping {
+    /// title of the created table, defaults to the sheet id or Airtable table name
+    pub title: Option<String>,
+    /// Source column that identifies a row across syncs. Defaults to the row number for
+    /// sheets, which shifts when rows are inserted, and the record id for Airtable.
+    pub key_column: Option<String>,
+    /// source column -> row field. When empty, every column is imported under its own name.
+    pub columns: BTreeMap<String, String>,
+    /// seconds between syncs, 0 only syncs when asked to
+    pub refresh_secs: u64,
+}
+
+impl Default for ImportMapping {
+    fn default() -> Self {
+        Self {
+            title: None,
+            key_column: None,
+            columns: BTreeMap::new(),
+            refresh_secs: DEFAULT_REFRESH_SECS,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TableImport {
+    pub id: Uuid,
+    /// id of the table rows are written to
+    pub table_id: Uuid,
+    /// schema hash of the table as of the last sync
+    pub schema_hash: Option<Hash>,
+    pub source: ImportSource,
+    pub mapping: ImportMapping,
+    /// author rows are written as
+    pub author: AuthorId,
+    pub created_at: i64,
+    pub last_synced_at: Option<i64>,
+    pub last_error: Option<String>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncReport {
+    pub written: usize,
+    pub unchanged: usize,
+    /// the table's schema was updated to match the source's columns
+    pub schema_changed: bool,
+}
+
+/// A source row: its key & fields.
+type Record = (String, Map<String, Value>);
+
+#[derive(Clone)]
+pub struct Imports(Space);
+
+impl Imports {
+    pub fn new(space: Space) -> Self {
+        Imports(space)
+    }
+
+    /// Create a table from `source`, copy its rows & keep it updated every
+    /// `mapping.refresh_secs`.
+    pub async fn create(
+        &self,
+        author: Author,
+        source: ImportSource,
+        auth: ImportAuth,
+        mapping: ImportMapping,
+    ) -> Result<TableImport> {
+        let import = TableImport {
+            id: Uuid::new_v4(),
+            table_id: Uuid::new_v4(),
+            schema_hash: None,
+            source,
+            mapping,
+            author: author.id(),
+            created_at: chrono::Utc::now().timestamp(),
+            last_synced_at: None,
+            last_error: None,
+        };
+        let conn = self.0.db.lock().await;
+        conn.execute(
+            "INSERT INTO table_imports (id, table_id, source, auth, mapping, author, created_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                import.id,
+                import.table_id,
+                serde_json::to_string(&import.source)?,
+                seal_auth(&self.0.secret(), &auth)?,
+                serde_json::to_string(&import.mapping)?,
+                import.author.to_string(),
+                import.created_at,
+            ],
+        )?;
+        drop(conn);
+
+        // a failing first sync most likely means bad credentials or a bad source
+        if let Err(err) = self.sync(import.id).await {
+            self.remove(import.id).await?;
+            return Err(err);
+        }
+        self.schedule(import.id);
+        self.get(import.id).await
+    }
+
+    /// Stop syncing. The table & its rows are kept.
+    pub async fn remove(&self, id: Uuid) -> Result<()> {
+        let conn = self.0.db.lock().await;
+        conn.execute(
+            "DELETE FROM table_import_rows WHERE import_id = ?1",
+            params![id],
+        )?;
+        conn.execute("DELETE FROM table_imports WHERE id = ?1", params![id])?;
+        Ok(())
+    }
+
+    pub async fn get(&self, id: Uuid) -> Result<TableImport> {
+        self.list()
+            .await?
+            .into_iter()
+            .find(|import| import.id == id)
+            .ok_or_else(|| anyhow!("table import not found"))
+    }
+
+    pub async fn list(&self) -> Result<Vec<TableImport>> {
+        let conn = self.0.db.lock().await;
+        let mut stmt = conn.prepare(
+            "SELECT id, table_id, schema_hash, source, mapping, author, created_at, last_synced_at, last_error FROM table_imports ORDER BY created_at",
+        )?;
+        let mut rows = stmt.query([])?;
+        let mut imports = Vec::new();
+        while let Some(row) = rows.next()? {
+            let schema_hash: Option<String> = row.get(2)?;
+            let source: String = row.get(3)?;
+            let mapping: String = row.get(4)?;
+            let author: String = row.get(5)?;
+            imports.push(TableImport {
+                id: row.get(0)?,
+                table_id: row.get(1)?,
+                schema_hash: schema_hash.map(|hash| hash.parse()).transpose()?,
+                source: serde_json::from_str(&source)?,
+                mapping: serde_json::from_str(&mapping)?,
+                author: author.parse()?,
+                created_at: row.get(6)?,
+                last_synced_at: row.get(7)?,
+                last_error: row.get(8)?,
+            });
+        }
+        Ok(imports)
+    }
+
+    /// Restart the sync loop of every import, called when a space is opened.
+    pub fn schedule_all(&self) {
+        let this = self.clone();
+        tokio::spawn(async move {
+            match this.list().await {
+                Ok(imports) => {
+                    for import in imports {
+                        this.schedule(import.id);
+                    }
+                }
+                Err(err) => warn!("failed to list table imports: {:?}", err),
+            }
+        });
+    }
+
+    /// Sync `id` every `refresh_secs` until it's removed.
+    fn schedule(&self, id: Uuid) {
+        let this = self.clone();
+        tokio::spawn(async move {
+            loop {
+                let refresh = match this.get(id).await {
+                    Ok(import) if import.mapping.refresh_secs > 0 => import.mapping.refresh_secs,
+                    _ => break,
+                };
+                tokio::time::sleep(Duration::from_secs(refresh)).await;
+                if let Err(err) = this.sync(id).await {
+                    warn!("table import {} failed to sync: {:?}", id, err);
+                }
+            }
+            debug!("stopped syncing table import {}", id);
+        });
+    }
+
+    /// Fetch the source & write rows that changed since the last sync.
+    pub async fn sync(&self, id: Uuid) -> Result<SyncReport> {
+        let res = self.sync_inner(id).await;
+        let conn = self.0.db.lock().await;
+        conn.execute(
+            "UPDATE table_imports SET last_synced_at = ?2, last_error = ?3 WHERE id = ?1",
+            params![
+                id,
+                chrono::Utc::now().timestamp(),
+                res.as_ref().err().map(|err| format!("{err:#}")),
+            ],
+        )?;
+        res
+    }
+
+    async fn sync_inner(&self, id: Uuid) -> Result<SyncReport> {
+        let import = self.get(id).await?;
+        let auth = self.auth(id).await?;
+        let author = self
+            .0
+            .router
+            .authors()
+            .export(import.author)
+            .await?
+            .ok_or_else(|| anyhow!("author {} not found", import.author))?;
+
+        let records = fetch(&import.source, &auth).await?;
+        let records = apply_mapping(records, &import.mapping)?;
+        let title = import
+            .mapping
+            .title
+            .clone()
+            .unwrap_or_else(|| match &import.source {
+                ImportSource::GoogleSheet { sheet_id, .. } => sheet_id.clone(),
+                ImportSource::Airtable { table, .. } => table.clone(),
+            });
+        let schema = infer_schema(&title, &records);
+        let schema = Bytes::from(serde_json::to_vec(&schema)?);
+
+        let mut report = SyncReport::default();
+        let schema_hash = match import.schema_hash {
+            Some(hash) if hash == Hash::new(&schema) => hash,
+            _ => {
+                let table = self
+                    .0
+                    .tables()
+                    .mutate(author.clone(), import.table_id, schema)
+                    .await?;
+                let conn = self.0.db.lock().await;
+                conn.execute(
+                    "UPDATE table_imports SET schema_hash = ?2 WHERE id = ?1",
+                    params![id, table.content.hash.to_string()],
+                )?;
+                // rows are tied to a schema, write all of them again
+                conn.execute(
+                    "UPDATE table_import_rows SET content_hash = NULL WHERE import_id = ?1",
+                    params![id],
+                )?;
+                report.schema_changed = true;
+                table.content.hash
+            }
+        };
+
+        let mut known = self.rows(id).await?;
+        for (key, fields) in records {
+            let data = Value::Object(fields);
+            let content_hash = Hash::new(serde_json::to_vec(&data)?);
+            let row_id = match known.remove(&key) {
+                Some((_, Some(hash))) if hash == content_hash => {
+                    report.unchanged += 1;
+                    continue;
+                }
+                Some((row_id, _)) => row_id,
+                None => Uuid::new_v4(),
+            };
+            self.0
+                .rows()
+                .mutate(author.clone(), schema_hash, row_id, data)
+                .await
+                .with_context(|| format!("writing row {key}"))?;
+            let conn = self.0.db.lock().await;
+            conn.execute(
+                "INSERT INTO table_import_rows (import_id, key, row_id, content_hash) VALUES (?1, ?2, ?3, ?4)
+                ON CONFLICT (import_id, key) DO UPDATE SET row_id = excluded.row_id, content_hash = excluded.content_hash",
+                params![id, key, row_id, content_hash.to_string()],
+            )?;
+            report.written += 1;
+        }
+        debug!(
+            "synced table import {} into {}: {:?}",
+            id, schema_hash, report
+        );
+        Ok(report)
+    }
+
+    async fn auth(&self, id: Uuid) -> Result<ImportAuth> {
+        let conn = self.0.db.lock().await;
+        let auth: String = conn.query_row(
+            "SELECT auth FROM table_imports WHERE id = ?1",
+            params![id],
+            |row| row.get(0),
+        )?;
+        open_auth(&self.0.secret(), &auth)
+    }
+
+    /// source key -> (row id, content hash of the last write)
+    async fn rows(&self, id: Uuid) -> Result<HashMap<String, (Uuid, Option<Hash>)>> {
+        let conn = self.0.db.lock().await;
+        let mut stmt = conn.prepare(
+            "SELECT key, row_id, content_hash FROM table_import_rows WHERE import_id = ?1",
+        )?;
+        let mut rows = stmt.query(params![id])?;
+        let mut known = HashMap::new();
+        while let Some(row) = rows.next()? {
+            let content_hash: Option<String> = row.get(2)?;
+            let content_hash = content_hash.map(|hash| hash.parse()).transpose()?;
+            known.insert(row.get(0)?, (row.get(1)?, content_hash));
+        }
+        Ok(known)
+    }
+}
+
+fn seal_auth(secret: &SpaceSecret, auth: &ImportAuth) -> Result<String> {
+    let mut stored = json!({ AUTH_FIELD: auth });
+    sensitive::seal_fields(secret, &[AUTH_FIELD.to_string()], &mut stored)?;
+    Ok(stored.to_string())
+}
+
+fn open_auth(secret: &SpaceSecret, stored: &str) -> Result<ImportAuth> {
+    let mut stored: Value = serde_json::from_str(stored)?;
+    sensitive::open_fields(&[sensitive::FieldKey::new(secret)], &mut stored)?;
+    let auth = stored
+        .get_mut(AUTH_FIELD)
+        .map(Value::take)
+        .context("import credentials are missing")?;
+    if sensitive::is_sealed(&auth) {
+        bail!("import credentials are sealed with a secret the space no longer holds");
+    }
+    Ok(serde_json::from_value(auth)?)
+}
+
+/// Seal the credentials of every import with `current`, after the space's secret rotated away
+/// from `previous`.
+pub(crate) fn reseal_credentials(
+    conn: &rusqlite::Connection,
+    previous: &SpaceSecret,
+    current: &SpaceSecret,
+) -> Result<()> {
+    let mut stmt = conn.prepare("SELECT id, auth FROM table_imports")?;
+    let stored = stmt
+        .query_map([], |row| {
+            Ok((row.get::<_, Uuid>(0)?, row.get::<_, String>(1)?))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    for (id, auth) in stored {
+        let auth = seal_auth(current, &open_auth(previous, &auth)?)?;
+        conn.execute(
+            "UPDATE table_imports SET auth = ?1 WHERE id = ?2",
+            params![auth, id],
+        )?;
+    }
+    Ok(())
+}
+
+async fn fetch(source: &ImportSource, auth: &ImportAuth) -> Result<Vec<Record>> {
+    let client = reqwest::Client::new();
+    match source {
+        ImportSource::GoogleSheet { sheet_id, range } => {
+            let range = range.as_deref().unwrap_or(DEFAULT_SHEET_RANGE);
+            let url = format!("{GOOGLE_SHEETS_API}/{sheet_id}/values/{range}");
+            let mut req = client
+                .get(&url)
+                .query(&[("valueRenderOption", "UNFORMATTED_VALUE")]);
+            req = match auth {
+                ImportAuth::ApiKey(key) => req.query(&[("key", key)]),
+                ImportAuth::Bearer(token) => req.bearer_auth(token),
+            };
+            let body = req.send().await?.error_for_status()?.bytes().await?;
+            parse_sheet_values(&body)
+        }
+        ImportSource::Airtable { base_id, table } => {
+            let token = match auth {
+                ImportAuth::ApiKey(token) | ImportAuth::Bearer(token) => token,
+            };
+            let url = format!("{AIRTABLE_API}/{base_id}/{table}");
+            let mut records = Vec::new();
+            let mut offset: Option<String> = None;
+            for _ in 0..MAX_AIRTABLE_PAGES {
+                let mut req = client.get(&url).bearer_auth(token);
+                if let Some(offset) = &offset {
+                    req = req.query(&[("offset", offset)]);
+                }
+                let body = req.send().await?.error_for_status()?.bytes().await?;
+                let (page, next) = parse_airtable_page(&body)?;
+                records.extend(page);
+                offset = next;
+                if offset.is_none() {
+                    return Ok(records);
+                }
+            }
+            bail!("airtable table {table} has more than {MAX_AIRTABLE_PAGES} pages")
+        }
+    }
+}
+
+/// Rows of a `spreadsheets.values.get` response, keyed by row number. The first row names
+/// the columns, empty cells are left out.
+fn parse_sheet_values(body: &[u8]) -> Result<Vec<Record>> {
+    #[derive(Deserialize)]
+    struct ValueRange {
+        #[serde(default)]
+        values: Vec<Vec<Value>>,
+    }
+    let range: ValueRange = serde_json::from_slice(body).context("parsing sheet values")?;
+    let mut rows = range.values.into_iter();
+    let Some(header) = rows.next() else {
+        return Ok(Vec::new());
+    };
+    let columns: Vec<String> = header
+        .iter()
+        .map(|cell| match cell {
+            Value::String(s) => s.trim().to_string(),
+            v => v.to_string(),
+        })
+        .collect();
+
+    let mut records = Vec::new();
+    // row 1 is the header
+    for (i, row) in rows.enumerate() {
+        let mut fields = Map::new();
+        for (column, cell) in columns.iter().zip(row) {
+            let empty = cell.is_null() || cell.as_str() == Some("");
+            if column.is_empty() || empty {
+                continue;
+            }
+            fields.insert(column.clone(), cell);
+        }
+        if !fields.is_empty() {
+            records.push(((i + 2).to_string(), fields));
+        }
+    }
+    Ok(records)
+}
+
+/// Records of an Airtable list records page, keyed by record id, & the next page's offset.
+fn parse_airtable_page(body: &[u8]) -> Result<(Vec<Record>, Option<String>)> {
+    #[derive(Deserialize)]
+    struct Page {
+        records: Vec<AirtableRecord>,
+        offset: Option<String>,
+    }
+    #[derive(Deserialize)]
+    struct AirtableRecord {
+        id: String,
+        #[serde(default)]
+        fields: Map<String, Value>,
+    }
+    let page: Page = serde_json::from_slice(body).context("parsing airtable records")?;
+    let records = page
+        .records
+        .into_iter()
+        .map(|record| (record.id, record.fields))
+        .collect();
+    Ok((records, page.offset))
+}
+
+/// Rename & filter columns, and re-key records by the mapping's key column.
+fn apply_mapping(records: Vec<Record>, mapping: &ImportMapping) -> Result<Vec<Record>> {
+    let mut mapped = Vec::with_capacity(records.len());
+    for (key, fields) in records {
+        let key = match &mapping.key_column {
+            Some(column) => match fields.get(column) {
+                Some(Value::String(s)) => s.clone(),
+                Some(Value::Null) | None => {
+                    warn!("skipping row {} without a value for {}", key, column);
+                    continue;
+                }
+                Some(v) => v.to_string(),
+            },
+            None => key,
+        };
+        let fields = if mapping.columns.is_empty() {
+            fields
+        } else {
+            fields
+                .into_iter()
+                .filter_map(|(column, value)| {
+                    let field = mapping.columns.get(&column)?;
+                    Some((field.clone(), value))
+                })
+                .collect()
+        };
+        mapped.push((key, fields));
+    }
+    Ok(mapped)
+}
+
+/// A JSON schema every record validates against. Columns are typed by the values seen, and
+/// are always nullable since cells can be empty.
+fn infer_schema(title: &str, records: &[Record]) -> Value {
+    let mut types: BTreeMap<&str, Vec<&'static str>> = BTreeMap::new();
+    for (_, fields) in records {
+        for (column, value) in fields {
+            let typ = match value {
+                Value::Null => continue,
+                Value::Bool(_) => "boolean",
+                Value::Number(_) => "number",
+                Value::String(_) => "string",
+                Value::Array(_) => "array",
+                Value::Object(_) => "object",
+            };
+            let seen = types.entry(column.as_str()).or_default();
+            if !seen.contains(&typ) {
+                seen.push(typ);
+            }
+        }
+    }
+    let properties: Map<String, Value> = types
+        .into_iter()
+        .map(|(column, mut seen)| {
+            seen.sort();
+            seen.push("null");
+            (column.to_string(), json!({ "type": seen }))
+        })
+        .collect();
+    json!({
+        "title": title,
+        "type": "object",
+        "properties": properties,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn credentials_are_sealed() {
+        let secret = SpaceSecret::new(&mut rand::thread_rng());
+        let auth = ImportAuth::Bearer("pat-secret-token".to_string());
+        let stored = seal_auth(&secret, &auth).unwrap();
+        assert!(!stored.contains("pat-secret-token"));
+        assert_eq!(open_auth(&secret, &stored).unwrap(), auth);
+
+        let rotated = SpaceSecret::new(&mut rand::thread_rng());
+        assert!(open_auth(&rotated, &stored).is_err());
+    }
+
+    #[test]
+    fn sheet_values_to_records() {
+        let body = json!({
+            "range": "Sheet1!A1:C4",
+            "values": [
+                ["name", "age", "member"],
+                ["ada", 36, true],
+                [],
+                ["grace", "", false, "extra"],
+            ],
+        });
+        let records = parse_sheet_values(body.to_string().as_bytes()).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].0, "2");
+        assert_eq!(records[0].1["age"], json!(36));
+        assert_eq!(records[1].0, "4");
+        assert!(!records[1].1.contains_key("age"));
+
+        let schema = infer_schema("people", &records);
+        assert_eq!(schema["title"], json!("people"));
+        assert_eq!(
+            schema["properties"]["age"]["type"],
+            json!(["number", "null"])
+        );
+        assert_eq!(
+            schema["properties"]["member"]["type"],
+            json!(["boolean", "null"])
+        );
+        let validator = jsonschema::validator_for(&schema).unwrap();
+        for (_, fields) in records {
+            assert!(validator.is_valid(&Value::Object(fields)));
+        }
+    }
+
+    #[test]
+    fn airtable_pages_and_mapping() {
+        let body = json!({
+            "records": [
+                { "id": "rec1", "fields": { "Name": "ada", "Email": "ada@example.com" } },
+                { "id": "rec2", "fields": { "Name": "grace" } },
+            ],
+            "offset": "itr2",
+        });
+        let (records, offset) = parse_airtable_page(body.to_string().as_bytes()).unwrap();
+        assert_eq!(offset.as_deref(), Some("itr2"));
+        assert_eq!(records[0].0, "rec1");
+
+        let mapping = ImportMapping {
+            key_column: Some("Email".into()),
+            columns: [("Name".to_string(), "name".to_string())].into(),
+            ..Default::default()
+        };
+        let mapped = apply_mapping(records, &mapping).unwrap();
+        assert_eq!(mapped.len(), 1);
+        assert_eq!(mapped[0].0, "ada@example.com");
+        assert_eq!(Value::Object(mapped[0].1.clone()), json!({ "name": "ada" }));
+    }
+}
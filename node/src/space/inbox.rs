@@ -0,0 +1,302 @@
+//! Events other members published to a shared space's feed, pulled into the space's db.
+//!
+//! Syncing is incremental. iroh docs reconcile the `events/<event id>` keys of the feed with
+//! peers, so only entries this node hasn't seen cross the wire, and the inbox writes the events
+//! among them its db doesn't hold yet. Catching up on a space after a few edits costs those
+//! edits, not the whole space. Events are checked against their key & validated, see
+//! [`crate::space::validation`], before they're written, and aren't queued in the
+//! [`crate::space::outbox`] again.
+use std::collections::{HashMap, HashSet};
+
+use anyhow::Result;
+use futures::StreamExt;
+use iroh::blobs::Hash;
+use iroh::client::blobs::BlobStatus;
+use iroh::client::docs::{Doc, Entry, LiveEvent};
+use iroh::docs::store::Query;
+use iroh::docs::ContentStatus;
+use iroh::net::key::PublicKey;
+use iroh::net::NodeAddr;
+use rusqlite::params;
+use tracing::{debug, warn};
+
+use super::events::Event;
+use super::feed::EVENTS_PREFIX;
+use super::Space;
+
+#[derive(Clone)]
+pub struct Inbox(Space);
+
+impl Inbox {
+    pub fn new(space: Space) -> Self {
+        Inbox(space)
+    }
+
+    /// Write every event in the feed the db doesn't hold yet, oldest first. Entries whose
+    /// content hasn't arrived are left for later. Returns how many events were written.
+    pub async fn pull(&self) -> Result<usize> {
+        let Some(doc) = self.0.feed().doc().await? else {
+            return Ok(0);
+        };
+        let known = self.known_ids().await?;
+        let query = Query::single_latest_per_key().key_prefix(EVENTS_PREFIX);
+        let mut entries = doc.get_many(query).await?;
+        let mut events = Vec::new();
+        while let Some(entry) = entries.next().await {
+            let entry = entry?;
+            if entry_id(&entry).map_or(true, |id| known.contains(id)) {
+                continue;
+            }
+            match self.read(&entry).await {
+                Ok(Some(event)) => events.push(event),
+                Ok(None) => {}
+                Err(err) => warn!("space {} skipping feed entry: {:?}", self.0.id, err),
+            }
+        }
+        if events.is_empty() {
+            return Ok(0);
+        }
+
+        // oldest first, so the space's clock moves past each in turn
+        events.sort_by_key(|event| event.hlc().unwrap_or_default());
+        let peers = sync_peers(&doc).await?;
+        let mut written = 0;
+        for event in events {
+            match self.ingest(&event, &peers).await {
+                Ok(true) => written += 1,
+                Ok(false) => {}
+                // refused by the space's policy, see [`crate::space::capabilities`]
+                Err(err) => warn!("space {} rejected event {}: {:?}", self.0.id, event.id, err),
+            }
+        }
+        debug!("space {}: pulled {} events", self.0.id, written);
+        Ok(written)
+    }
+
+    /// Follow the feed until its doc goes away, writing events as other members' entries
+    /// arrive. Pulls once up front to catch up on what arrived while nobody was following.
+    pub(crate) async fn follow(&self) -> Result<()> {
+        let Some(doc) = self.0.feed().doc().await? else {
+            return Ok(());
+        };
+        let mut events = doc.subscribe().await?;
+        if let Err(err) = self.pull().await {
+            warn!("space {} inbox pull: {:?}", self.0.id, err);
+        }
+        // entries whose content arrived after the entry itself
+        let mut pending: HashMap<Hash, (Entry, PublicKey)> = HashMap::new();
+        while let Some(event) = events.next().await {
+            let (entry, from) = match event {
+                Ok(LiveEvent::InsertRemote {
+                    from,
+                    entry,
+                    content_status,
+                }) => {
+                    if content_status != ContentStatus::Complete {
+                        pending.insert(entry.content_hash(), (entry, from));
+                        continue;
+                    }
+                    (entry, from)
+                }
+                Ok(LiveEvent::ContentReady { hash }) => match pending.remove(&hash) {
+                    Some(pending) => pending,
+                    None => continue,
+                },
+                Ok(LiveEvent::SyncFinished(sync)) => {
+                    if sync.result.is_ok() {
+                        if let Err(err) = self.pull().await {
+                            warn!("space {} inbox pull: {:?}", self.0.id, err);
+                        }
+                    }
+                    continue;
+                }
+                Ok(_) => continue,
+                Err(err) => {
+                    warn!("space {} feed event error: {:?}", self.0.id, err);
+                    continue;
+                }
+            };
+            if let Err(err) = self.receive(&entry, from).await {
+                warn!("space {} skipping feed entry: {:?}", self.0.id, err);
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the event of an entry `from` inserted, if it's new to the db.
+    async fn receive(&self, entry: &Entry, from: PublicKey) -> Result<()> {
+        let Some(id) = entry_id(entry) else {
+            return Ok(());
+        };
+        if self.contains(id).await? {
+            return Ok(());
+        }
+        if let Some(event) = self.read(entry).await? {
+            self.ingest(&event, &[NodeAddr::new(from)]).await?;
+        }
+        Ok(())
+    }
+
+    /// The event an entry holds, none if its content isn't here yet.
+    async fn read(&self, entry: &Entry) -> Result<Option<Event>> {
+        let status = self.0.router.blobs().status(entry.content_hash()).await?;
+        if !matches!(status, BlobStatus::Complete { .. }) {
+            return Ok(None);
+        }
+        let data = self
+            .0
+            .router
+            .blobs()
+            .read_to_bytes(entry.content_hash())
+            .await?;
+        let event = Event::decode(&data)?;
+        anyhow::ensure!(
+            entry_id(entry) == Some(event.id.to_string().as_str()),
+            "event {} is filed under another key",
+            event.id
+        );
+        Ok(Some(event))
+    }
+
+    /// Write an event unless the db has it, fetching content it links to from `peers` first
+    /// so hooks can read it. Returns whether the event was written.
+    async fn ingest(&self, event: &Event, peers: &[NodeAddr]) -> Result<bool> {
+        if self.contains(&event.id.to_string()).await? {
+            return Ok(false);
+        }
+        if event.content.data.is_none() {
+            self.fetch(event.content.hash, peers).await;
+        }
+        self.0.ingest_event(event).await?;
+        Ok(true)
+    }
+
+    /// Download a blob from the first peer that has it. Content that can't be fetched now is
+    /// resolved on read.
+    async fn fetch(&self, hash: Hash, peers: &[NodeAddr]) {
+        match self.0.router.blobs().status(hash).await {
+            Ok(BlobStatus::Complete { .. }) => return,
+            Ok(_) => {}
+            Err(err) => debug!("checking status of {}: {:?}", hash, err),
+        }
+        for addr in peers {
+            match self.0.router.blobs().download(hash, addr.clone()).await {
+                Ok(download) => match download.await {
+                    Ok(_) => return,
+                    Err(err) => debug!("fetching {} from {}: {:?}", hash, addr.node_id, err),
+                },
+                Err(err) => debug!("fetching {} from {}: {:?}", hash, addr.node_id, err),
+            }
+        }
+    }
+
+    async fn contains(&self, id: &str) -> Result<bool> {
+        let conn = self.0.db.lock().await;
+        let count: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM events WHERE id = ?1",
+            params![id],
+            |row| row.get(0),
+        )?;
+        Ok(count > 0)
+    }
+
+    async fn known_ids(&self) -> Result<HashSet<String>> {
+        let conn = self.0.db.lock().await;
+        let mut stmt = conn.prepare("SELECT id FROM events")?;
+        let mut rows = stmt.query([])?;
+        let mut ids = HashSet::new();
+        while let Some(row) = rows.next()? {
+            let id: String = row.get(0)?;
+            ids.insert(id);
+        }
+        Ok(ids)
+    }
+}
+
+/// The event id an entry is filed under, none for keys outside `events/`.
+fn entry_id(entry: &Entry) -> Option<&str> {
+    std::str::from_utf8(entry.key())
+        .ok()?
+        .strip_prefix(EVENTS_PREFIX)
+}
+
+async fn sync_peers(doc: &Doc) -> Result<Vec<NodeAddr>> {
+    Ok(doc
+        .get_sync_peers()
+        .await?
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|peer| PublicKey::from_bytes(&peer).ok())
+        .map(NodeAddr::new)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use iroh::docs::{Author, NamespaceSecret};
+    use serde_json::json;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::space::clock::Hlc;
+    use crate::space::events::{EventKind, HashLink, Sha256Digest, Tag, NOSTR_ID_TAG};
+    use crate::space::feed::{event_key, publish_to};
+
+    fn mutate_row(author: &Author, created_at: i64, row: Uuid) -> Result<Event> {
+        Event::create(
+            author.clone(),
+            created_at,
+            Hlc::from_created_at(created_at),
+            EventKind::MutateRow,
+            vec![Tag::new(NOSTR_ID_TAG, &row.to_string())],
+            HashLink {
+                hash: Hash::new(created_at.to_string()),
+                data: Some(json!({ "at": created_at })),
+            },
+        )
+    }
+
+    #[tokio::test]
+    async fn pull_writes_only_missing_events() -> Result<()> {
+        let node = iroh::node::Node::memory().enable_docs().spawn().await?;
+        let space = Space::open_in_memory(
+            Uuid::new_v4(),
+            "inbox".to_string(),
+            NamespaceSecret::new(&mut rand::thread_rng()),
+            node.client().clone(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+        )
+        .await?;
+        space.feed().share().await?;
+        let doc = space.feed().doc().await?.expect("shared");
+        let member = node.authors().create().await?;
+        let author = Author::new(&mut rand::thread_rng());
+        let inbox = space.inbox();
+        assert_eq!(inbox.pull().await?, 0);
+
+        // published by another member, straight to the feed
+        let (first, second) = (
+            mutate_row(&author, 1, Uuid::new_v4())?,
+            mutate_row(&author, 2, Uuid::new_v4())?,
+        );
+        publish_to(&doc, member, &second).await?;
+        publish_to(&doc, member, &first).await?;
+        assert_eq!(inbox.pull().await?, 2);
+        assert!(inbox.contains(&first.id.to_string()).await?);
+        assert_eq!(inbox.pull().await?, 0);
+
+        // entries that don't match their key aren't written
+        let forged = mutate_row(&author, 3, Uuid::new_v4())?;
+        doc.set_bytes(
+            member,
+            event_key(&Sha256Digest::from_data(b"elsewhere")),
+            serde_json::to_vec(&forged)?,
+        )
+        .await?;
+        assert_eq!(inbox.pull().await?, 0);
+        assert!(!inbox.contains(&forged.id.to_string()).await?);
+        Ok(())
+    }
+}
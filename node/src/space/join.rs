@@ -0,0 +1,332 @@
+//! Joining a space someone else created.
+//!
+//! The owner shares a write ticket for the space's feed doc (see [`super::feed`]), which
+//! carries the space secret. Joining imports the doc, syncs it with the peers in the ticket,
+//! downloads every event & the content it links to, then writes the events into a fresh
+//! space db. That can take a while for a big space, so [`super::Spaces::join`] hands back a
+//! [`SpaceJoin`] to watch progress through & cancel with. Nothing is registered with
+//! [`super::Spaces`] until everything is downloaded, a failed or canceled join cleans up
+//! after itself.
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use futures::StreamExt;
+use iroh::blobs::Hash;
+use iroh::client::blobs::BlobStatus;
+use iroh::client::docs::LiveEvent;
+use iroh::docs::store::Query;
+use iroh::docs::{Capability, DocTicket};
+use iroh::net::NodeAddr;
+use serde::Serialize;
+use tokio::sync::watch;
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+use crate::router::RouterClient;
+use crate::Error;
+
+use super::db::{open_db, setup_db};
+use super::events::{Event, EventKind};
+use super::feed::EVENTS_PREFIX;
+use super::validation;
+use super::{SpaceSecret, Spaces};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+#[serde(rename_all = "camelCase")]
+pub enum JoinPhase {
+    /// importing the ticket & reaching its peers
+    Connecting,
+    /// waiting for the first sync of the feed doc to finish
+    Syncing,
+    /// fetching events & the content they link to
+    Downloading,
+    /// writing events to the new space's db
+    Importing,
+    Done,
+    Failed,
+    Canceled,
+}
+
+impl JoinPhase {
+    pub fn is_finished(&self) -> bool {
+        matches!(
+            self,
+            JoinPhase::Done | JoinPhase::Failed | JoinPhase::Canceled
+        )
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+#[serde(rename_all = "camelCase")]
+pub struct JoinProgress {
+    pub phase: JoinPhase,
+    /// bytes fetched from peers so far
+    pub bytes: u64,
+    /// blobs fetched or found locally in the current phase
+    pub done: u64,
+    /// blobs to fetch in the current phase, 0 until known
+    pub total: u64,
+    /// the joined space, once done
+    pub space: Option<Uuid>,
+    pub error: Option<String>,
+}
+
+impl JoinProgress {
+    fn new() -> Self {
+        JoinProgress {
+            phase: JoinPhase::Connecting,
+            bytes: 0,
+            done: 0,
+            total: 0,
+            space: None,
+            error: None,
+        }
+    }
+}
+
+/// A join running in the background. Dropping the handle doesn't stop it, use
+/// [`SpaceJoin::cancel`] for that.
+#[derive(Debug, Clone)]
+pub struct SpaceJoin {
+    progress: watch::Receiver<JoinProgress>,
+    cancel: Arc<watch::Sender<bool>>,
+}
+
+impl SpaceJoin {
+    pub fn progress(&self) -> JoinProgress {
+        self.progress.borrow().clone()
+    }
+
+    /// Progress updates, the last one has a finished phase.
+    pub fn subscribe(&self) -> watch::Receiver<JoinProgress> {
+        self.progress.clone()
+    }
+
+    /// Stop the join & remove everything it downloaded. Does nothing once the join finished.
+    pub fn cancel(&self) {
+        self.cancel.send_replace(true);
+    }
+
+    /// Wait for the join to finish, returning the id of the joined space.
+    pub async fn finished(&self) -> Result<Uuid> {
+        let mut progress = self.progress.clone();
+        let progress = progress
+            .wait_for(|progress| progress.phase.is_finished())
+            .await?
+            .clone();
+        match progress.phase {
+            JoinPhase::Done => progress
+                .space
+                .ok_or_else(|| anyhow!("joined space has no id")),
+            JoinPhase::Canceled => Err(anyhow!("join canceled")),
+            _ => Err(anyhow!(progress.error.unwrap_or_default())),
+        }
+    }
+}
+
+/// Check `ticket` can be joined as `name` & start joining in the background.
+pub(crate) async fn start(
+    spaces: &Spaces,
+    router: &RouterClient,
+    ticket: DocTicket,
+    name: &str,
+) -> Result<SpaceJoin> {
+    let Capability::Write(secret) = ticket.capability.clone() else {
+        anyhow::bail!("joining a space needs a write ticket, this one is read-only");
+    };
+    anyhow::ensure!(
+        spaces.get_by_name(name).await.is_none(),
+        "a space named {name} already exists"
+    );
+    anyhow::ensure!(
+        !spaces
+            .spaces
+            .read()
+            .await
+            .values()
+            .any(|space| space.secret().id() == secret.id()),
+        "this space was already joined"
+    );
+    let (progress_tx, progress) = watch::channel(JoinProgress::new());
+    let (cancel, mut canceled) = watch::channel(false);
+    let spaces = spaces.clone();
+    let router = router.clone();
+    let name = name.to_string();
+    tokio::spawn(async move {
+        let db_path = spaces.path.join(format!("{name}.db"));
+        let result = tokio::select! {
+            result = download(&router, &ticket, &db_path, &progress_tx) => result,
+            // the handles going away without canceling ends the wait with an error
+            true = wait_canceled(&mut canceled) => {
+                cleanup(&router, &secret, &db_path).await;
+                progress_tx.send_modify(|progress| progress.phase = JoinPhase::Canceled);
+                return;
+            }
+        };
+        // past this point the join can't be canceled, registering is quick
+        let result = match result {
+            Ok(id) => spaces
+                .register(&router, id, &name, secret.clone())
+                .await
+                .map(|_| id),
+            Err(err) => Err(err),
+        };
+        match result {
+            Ok(id) => progress_tx.send_modify(|progress| {
+                progress.phase = JoinPhase::Done;
+                progress.space = Some(id);
+            }),
+            Err(err) => {
+                warn!("joining space {name}: {:?}", err);
+                cleanup(&router, &secret, &db_path).await;
+                progress_tx.send_modify(|progress| {
+                    progress.phase = JoinPhase::Failed;
+                    progress.error = Some(format!("{err:#}"));
+                });
+            }
+        }
+    });
+
+    Ok(SpaceJoin {
+        progress,
+        cancel: Arc::new(cancel),
+    })
+}
+
+/// Resolves true once the join is canceled, false if the handles went away. The borrow of
+/// the flag ends here, so it isn't held across the cleanup.
+async fn wait_canceled(canceled: &mut watch::Receiver<bool>) -> bool {
+    canceled.wait_for(|canceled| *canceled).await.is_ok()
+}
+
+/// Fetch everything in the feed & write the events that pass validation to a db at `db_path`.
+/// Returns the id of the space, taken from the first details event written for it.
+async fn download(
+    router: &RouterClient,
+    ticket: &DocTicket,
+    db_path: &Path,
+    progress: &watch::Sender<JoinProgress>,
+) -> Result<Uuid> {
+    let (doc, mut live) = router.docs().import_and_subscribe(ticket.clone()).await?;
+
+    set_phase(progress, JoinPhase::Syncing, 0);
+    let mut failed = HashSet::new();
+    while let Some(event) = live.next().await {
+        match event? {
+            LiveEvent::SyncFinished(sync) => match sync.result {
+                Ok(_) => break,
+                Err(err) => {
+                    debug!("join sync with {} failed: {}", sync.peer, err);
+                    failed.insert(sync.peer);
+                    if failed.len() >= ticket.nodes.len() {
+                        return Err(Error::sync(format!(
+                            "couldn't sync with any peer in the ticket: {err}"
+                        ))
+                        .into());
+                    }
+                }
+            },
+            _ => continue,
+        }
+    }
+
+    let query = Query::single_latest_per_key().key_prefix(EVENTS_PREFIX);
+    let entries = doc
+        .get_many(query)
+        .await?
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>>>()?;
+    set_phase(progress, JoinPhase::Downloading, entries.len() as u64);
+    let mut events = Vec::with_capacity(entries.len());
+    for entry in entries {
+        fetch(router, entry.content_hash(), &ticket.nodes, progress).await?;
+        let data = router.blobs().read_to_bytes(entry.content_hash()).await?;
+        events.push(Event::decode(&data)?);
+    }
+
+    set_phase(progress, JoinPhase::Downloading, events.len() as u64);
+    for event in events.iter() {
+        fetch(router, event.content.hash, &ticket.nodes, progress).await?;
+    }
+
+    set_phase(progress, JoinPhase::Importing, events.len() as u64);
+    events.sort_by_key(|event| event.created_at);
+    anyhow::ensure!(
+        events
+            .iter()
+            .any(|event| event.kind == EventKind::MutateSpace),
+        "the feed has no space details, is it a space feed?"
+    );
+    let db = open_db(db_path).await?;
+    setup_db(&db).await?;
+    let mut id = None;
+    for event in events.iter() {
+        progress.send_modify(|progress| progress.done += 1);
+        // one bad event in the feed shouldn't keep anyone from joining, it's left out
+        if let Err(err) = validation::validate(&db, router, event).await {
+            warn!("join skipping event: {:#}", err);
+            continue;
+        }
+        if id.is_none() && event.kind == EventKind::MutateSpace {
+            id = event.data_id()?;
+        }
+        event.write(&db).await?;
+    }
+    id.ok_or_else(|| anyhow!("the feed has no valid space details"))
+}
+
+/// Make sure `hash` is held locally, downloading it from the first of `nodes` that has it.
+async fn fetch(
+    router: &RouterClient,
+    hash: Hash,
+    nodes: &[NodeAddr],
+    progress: &watch::Sender<JoinProgress>,
+) -> Result<()> {
+    let status = router.blobs().status(hash).await?;
+    if !matches!(status, BlobStatus::Complete { .. }) {
+        let mut fetched = None;
+        for addr in nodes {
+            match router.blobs().download(hash, addr.clone()).await {
+                Ok(download) => match download.await {
+                    Ok(outcome) => {
+                        fetched = Some(outcome.downloaded_size);
+                        break;
+                    }
+                    Err(err) => debug!("fetching {} from {}: {:?}", hash, addr.node_id, err),
+                },
+                Err(err) => debug!("fetching {} from {}: {:?}", hash, addr.node_id, err),
+            }
+        }
+        let bytes =
+            fetched.ok_or_else(|| Error::sync(format!("no peer in the ticket has {hash}")))?;
+        progress.send_modify(|progress| progress.bytes += bytes);
+    }
+    progress.send_modify(|progress| progress.done += 1);
+    Ok(())
+}
+
+fn set_phase(progress: &watch::Sender<JoinProgress>, phase: JoinPhase, total: u64) {
+    progress.send_modify(|progress| {
+        progress.phase = phase;
+        progress.done = 0;
+        progress.total = total;
+    });
+}
+
+/// Forget the feed doc & remove the partially written db.
+async fn cleanup(router: &RouterClient, secret: &SpaceSecret, db_path: &Path) {
+    if let Err(err) = router.docs().drop_doc(secret.id()).await {
+        debug!("dropping joined doc: {:?}", err);
+    }
+    if db_path.exists() {
+        if let Err(err) = tokio::fs::remove_file(db_path).await {
+            warn!("removing {}: {:?}", db_path.display(), err);
+        }
+    }
+}
@@ -0,0 +1,651 @@
+//! Tables mirrored into an external SQL database, so BI tools can query them.
+//!
+//! A mirror follows a table's row changes (see [`super::rows::Rows::changes`]) & replays them
+//! on a table in the target database: one column per top-level property of the table's
+//! schema, plus `id`, `author` & `created_at`. Deleted rows are deleted from the target. When
+//! the table's schema changes, missing columns are added & the table is replayed under the new
+//! schema. Columns are never dropped.
+//!
+//! Targets are SQLite files, `sqlite://<path>`, or Postgres, `postgres://...`, when built
+//! with the `postgres` feature. Postgres connections don't use TLS.
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Result};
+use iroh::blobs::Hash;
+use rusqlite::params;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+use super::rows::{Row, RowChange};
+use super::Space;
+
+/// how long to let a burst of row writes settle before syncing
+const SYNC_DEBOUNCE: Duration = Duration::from_secs(1);
+/// sync at least this often, in case a write notification was missed
+const SYNC_INTERVAL: Duration = Duration::from_secs(5 * 60);
+/// columns every mirrored table has, schema properties with these names are left out
+const RESERVED_COLUMNS: [&str; 3] = ["id", "author", "created_at"];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TableMirror {
+    pub id: Uuid,
+    pub table_id: Uuid,
+    /// name of the table in the target database
+    pub target_table: String,
+    /// schema hash of the table as of the last sync
+    pub schema_hash: Option<Hash>,
+    /// position in the table's changes, see [`super::rows::Rows::changes`]
+    pub cursor: i64,
+    pub created_at: i64,
+    pub last_synced_at: Option<i64>,
+    pub last_error: Option<String>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MirrorReport {
+    pub upserted: usize,
+    pub deleted: usize,
+    /// columns added to the target table
+    pub added_columns: Vec<String>,
+}
+
+#[derive(Clone)]
+pub struct Mirrors(Space);
+
+impl Mirrors {
+    pub fn new(space: Space) -> Self {
+        Mirrors(space)
+    }
+
+    /// Start mirroring `table_id` to the database at `connection`. The target table defaults
+    /// to the table's title, lowercased.
+    pub async fn create(
+        &self,
+        table_id: Uuid,
+        connection: &str,
+        target_table: Option<String>,
+    ) -> Result<TableMirror> {
+        let table = self.0.tables().get(table_id).await?;
+        // fail early on a bad connection string
+        Target::connect(connection).await?;
+        let mirror = TableMirror {
+            id: Uuid::new_v4(),
+            table_id,
+            target_table: target_table.unwrap_or_else(|| table_name(&table.title)),
+            schema_hash: None,
+            cursor: 0,
+            created_at: chrono::Utc::now().timestamp(),
+            last_synced_at: None,
+            last_error: None,
+        };
+        let conn = self.0.db.lock().await;
+        conn.execute(
+            "INSERT INTO table_mirrors (id, table_id, connection, target_table, created_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                mirror.id,
+                mirror.table_id,
+                connection,
+                mirror.target_table,
+                mirror.created_at,
+            ],
+        )?;
+        drop(conn);
+
+        if let Err(err) = self.sync(mirror.id).await {
+            self.remove(mirror.id).await?;
+            return Err(err);
+        }
+        self.schedule(mirror.id);
+        self.get(mirror.id).await
+    }
+
+    /// Stop mirroring. The target table is left as it is.
+    pub async fn remove(&self, id: Uuid) -> Result<()> {
+        let conn = self.0.db.lock().await;
+        conn.execute("DELETE FROM table_mirrors WHERE id = ?1", params![id])?;
+        Ok(())
+    }
+
+    pub async fn get(&self, id: Uuid) -> Result<TableMirror> {
+        self.list()
+            .await?
+            .into_iter()
+            .find(|mirror| mirror.id == id)
+            .ok_or_else(|| anyhow!("table mirror not found"))
+    }
+
+    pub async fn list(&self) -> Result<Vec<TableMirror>> {
+        let conn = self.0.db.lock().await;
+        let mut stmt = conn.prepare(
+            "SELECT id, table_id, target_table, schema_hash, cursor, created_at, last_synced_at, last_error FROM table_mirrors ORDER BY created_at",
+        )?;
+        let mut rows = stmt.query([])?;
+        let mut mirrors = Vec::new();
+        while let Some(row) = rows.next()? {
+            let schema_hash: Option<String> = row.get(3)?;
+            mirrors.push(TableMirror {
+                id: row.get(0)?,
+                table_id: row.get(1)?,
+                target_table: row.get(2)?,
+                schema_hash: schema_hash.map(|hash| hash.parse()).transpose()?,
+                cursor: row.get(4)?,
+                created_at: row.get(5)?,
+                last_synced_at: row.get(6)?,
+                last_error: row.get(7)?,
+            });
+        }
+        Ok(mirrors)
+    }
+
+    /// Restart every mirror, called when a space is opened.
+    pub fn schedule_all(&self) {
+        let this = self.clone();
+        tokio::spawn(async move {
+            match this.list().await {
+                Ok(mirrors) => {
+                    for mirror in mirrors {
+                        this.schedule(mirror.id);
+                    }
+                }
+                Err(err) => warn!("failed to list table mirrors: {:?}", err),
+            }
+        });
+    }
+
+    /// Sync `id` after row writes until it's removed.
+    fn schedule(&self, id: Uuid) {
+        let this = self.clone();
+        tokio::spawn(async move {
+            let mut writes = this.0.rows().subscribe();
+            while this.get(id).await.is_ok() {
+                if let Err(err) = this.sync(id).await {
+                    warn!("table mirror {} failed to sync: {:?}", id, err);
+                }
+                tokio::select! {
+                    changed = writes.changed() => {
+                        if changed.is_err() {
+                            break;
+                        }
+                    }
+                    _ = tokio::time::sleep(SYNC_INTERVAL) => {}
+                }
+                tokio::time::sleep(SYNC_DEBOUNCE).await;
+                writes.borrow_and_update();
+            }
+            debug!("stopped syncing table mirror {}", id);
+        });
+    }
+
+    /// Replay the table's changes since the last sync on the target.
+    pub async fn sync(&self, id: Uuid) -> Result<MirrorReport> {
+        let res = self.sync_inner(id).await;
+        let conn = self.0.db.lock().await;
+        conn.execute(
+            "UPDATE table_mirrors SET last_synced_at = ?2, last_error = ?3 WHERE id = ?1",
+            params![
+                id,
+                chrono::Utc::now().timestamp(),
+                res.as_ref().err().map(|err| format!("{err:#}")),
+            ],
+        )?;
+        res
+    }
+
+    async fn sync_inner(&self, id: Uuid) -> Result<MirrorReport> {
+        let mirror = self.get(id).await?;
+        let connection = self.connection(id).await?;
+        let mut table = self.0.tables().get(mirror.table_id).await?;
+        let schema_hash = table.content.hash;
+        let schema = table.content.resolve(&self.0.router).await?;
+        let columns = columns(&schema);
+
+        let mut target = Target::connect(&connection).await?;
+        let mut report = MirrorReport {
+            added_columns: target.ensure_table(&mirror.target_table, &columns).await?,
+            ..Default::default()
+        };
+
+        // rows are tied to a schema, a new schema starts from the beginning
+        let mut cursor = match mirror.schema_hash {
+            Some(hash) if hash == schema_hash => mirror.cursor,
+            _ => 0,
+        };
+        loop {
+            let changes = self.0.rows().changes(schema_hash, cursor).await?;
+            if changes.changes.is_empty() {
+                break;
+            }
+            target.begin().await?;
+            for change in changes.changes.iter() {
+                match change {
+                    RowChange::Insert { row } | RowChange::Update { row } => {
+                        target.upsert(&mirror.target_table, &columns, row).await?;
+                        report.upserted += 1;
+                    }
+                    RowChange::Delete { id } => {
+                        target.delete(&mirror.target_table, *id).await?;
+                        report.deleted += 1;
+                    }
+                }
+            }
+            target.commit().await?;
+            cursor = changes.cursor;
+
+            let conn = self.0.db.lock().await;
+            conn.execute(
+                "UPDATE table_mirrors SET schema_hash = ?2, cursor = ?3 WHERE id = ?1",
+                params![id, schema_hash.to_string(), cursor],
+            )?;
+        }
+        debug!("synced table mirror {}: {:?}", id, report);
+        Ok(report)
+    }
+
+    async fn connection(&self, id: Uuid) -> Result<String> {
+        let conn = self.0.db.lock().await;
+        let connection = conn.query_row(
+            "SELECT connection FROM table_mirrors WHERE id = ?1",
+            params![id],
+            |row| row.get(0),
+        )?;
+        Ok(connection)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ColumnType {
+    Text,
+    Integer,
+    Real,
+    Boolean,
+    /// objects, arrays & anything without a single type, stored as JSON text
+    Json,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Column {
+    name: String,
+    ty: ColumnType,
+}
+
+/// A column for each top-level property of a table's JSON schema.
+fn columns(schema: &Value) -> Vec<Column> {
+    let Some(properties) = schema.get("properties").and_then(Value::as_object) else {
+        return Vec::new();
+    };
+    properties
+        .iter()
+        .filter(|(name, _)| !RESERVED_COLUMNS.contains(&name.as_str()))
+        .map(|(name, property)| {
+            // ["number", "null"] is a nullable number
+            let types: Vec<&str> = match property.get("type") {
+                Some(Value::String(ty)) => vec![ty.as_str()],
+                Some(Value::Array(types)) => types
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .filter(|ty| *ty != "null")
+                    .collect(),
+                _ => Vec::new(),
+            };
+            let ty = match types.as_slice() {
+                ["string"] => ColumnType::Text,
+                ["integer"] => ColumnType::Integer,
+                ["number"] => ColumnType::Real,
+                ["boolean"] => ColumnType::Boolean,
+                _ => ColumnType::Json,
+            };
+            Column {
+                name: name.clone(),
+                ty,
+            }
+        })
+        .collect()
+}
+
+/// A table name from a table title: lowercase letters, digits & underscores.
+fn table_name(title: &str) -> String {
+    let name: String = title
+        .trim()
+        .chars()
+        .map(|c| match c.is_ascii_alphanumeric() {
+            true => c.to_ascii_lowercase(),
+            false => '_',
+        })
+        .collect();
+    match name.is_empty() {
+        true => "squiggle_table".to_string(),
+        false => name,
+    }
+}
+
+fn quote(ident: &str) -> String {
+    format!("\"{}\"", ident.replace('"', "\"\""))
+}
+
+/// A field of a row, converted for its column.
+#[derive(Debug, Clone, PartialEq)]
+enum SqlValue {
+    Text(Option<String>),
+    Integer(Option<i64>),
+    Real(Option<f64>),
+    Boolean(Option<bool>),
+}
+
+fn sql_value(ty: ColumnType, value: Option<&Value>) -> SqlValue {
+    let value = value.filter(|value| !value.is_null());
+    match ty {
+        ColumnType::Text => SqlValue::Text(value.map(|value| match value {
+            Value::String(s) => s.clone(),
+            value => value.to_string(),
+        })),
+        ColumnType::Integer => SqlValue::Integer(value.and_then(Value::as_i64)),
+        ColumnType::Real => SqlValue::Real(value.and_then(Value::as_f64)),
+        ColumnType::Boolean => SqlValue::Boolean(value.and_then(Value::as_bool)),
+        ColumnType::Json => SqlValue::Text(value.map(Value::to_string)),
+    }
+}
+
+/// `id`, `author`, `created_at` & a value per column.
+fn row_values(columns: &[Column], row: &Row) -> Vec<SqlValue> {
+    let data = row.content.data.as_ref();
+    let mut values = vec![
+        SqlValue::Text(Some(row.id.to_string())),
+        SqlValue::Text(Some(row.author.to_string())),
+        SqlValue::Integer(Some(row.created_at)),
+    ];
+    values.extend(
+        columns
+            .iter()
+            .map(|column| sql_value(column.ty, data.and_then(|data| data.get(&column.name)))),
+    );
+    values
+}
+
+enum Target {
+    Sqlite(rusqlite::Connection),
+    #[cfg(feature = "postgres")]
+    Postgres(tokio_postgres::Client),
+}
+
+impl Target {
+    async fn connect(connection: &str) -> Result<Self> {
+        if let Some(path) = connection.strip_prefix("sqlite://") {
+            return Ok(Target::Sqlite(rusqlite::Connection::open(path)?));
+        }
+        if !(connection.starts_with("postgres://") || connection.starts_with("postgresql://")) {
+            bail!("unsupported connection string, expected sqlite:// or postgres://");
+        }
+        Self::connect_postgres(connection).await
+    }
+
+    #[cfg(feature = "postgres")]
+    async fn connect_postgres(connection: &str) -> Result<Self> {
+        let (client, conn) = tokio_postgres::connect(connection, tokio_postgres::NoTls).await?;
+        tokio::spawn(async move {
+            if let Err(err) = conn.await {
+                warn!("table mirror postgres connection: {:?}", err);
+            }
+        });
+        Ok(Target::Postgres(client))
+    }
+
+    #[cfg(not(feature = "postgres"))]
+    async fn connect_postgres(_connection: &str) -> Result<Self> {
+        bail!("built without postgres support, enable the `postgres` feature")
+    }
+
+    fn sql_type(&self, ty: ColumnType) -> &'static str {
+        match (self, ty) {
+            (_, ColumnType::Text) => "TEXT",
+            (Target::Sqlite(_), ColumnType::Integer) => "INTEGER",
+            (Target::Sqlite(_), ColumnType::Real) => "REAL",
+            (Target::Sqlite(_), ColumnType::Boolean) => "INTEGER",
+            (Target::Sqlite(_), ColumnType::Json) => "TEXT",
+            #[cfg(feature = "postgres")]
+            (Target::Postgres(_), ColumnType::Integer) => "BIGINT",
+            #[cfg(feature = "postgres")]
+            (Target::Postgres(_), ColumnType::Real) => "DOUBLE PRECISION",
+            #[cfg(feature = "postgres")]
+            (Target::Postgres(_), ColumnType::Boolean) => "BOOLEAN",
+            #[cfg(feature = "postgres")]
+            (Target::Postgres(_), ColumnType::Json) => "JSONB",
+        }
+    }
+
+    #[cfg_attr(not(feature = "postgres"), allow(unused_variables))]
+    fn placeholder(&self, i: usize, ty: ColumnType) -> String {
+        match self {
+            Target::Sqlite(_) => format!("?{i}"),
+            #[cfg(feature = "postgres")]
+            Target::Postgres(_) => match ty {
+                // JSON is bound as text
+                ColumnType::Json => format!("${i}::text::jsonb"),
+                _ => format!("${i}"),
+            },
+        }
+    }
+
+    async fn execute(&mut self, sql: &str, values: &[SqlValue]) -> Result<()> {
+        match self {
+            Target::Sqlite(conn) => {
+                let params = values.iter().map(|value| match value {
+                    SqlValue::Text(v) => rusqlite::types::Value::from(v.clone()),
+                    SqlValue::Integer(v) => rusqlite::types::Value::from(*v),
+                    SqlValue::Real(v) => rusqlite::types::Value::from(*v),
+                    SqlValue::Boolean(v) => rusqlite::types::Value::from(*v),
+                });
+                conn.execute(sql, rusqlite::params_from_iter(params))?;
+            }
+            #[cfg(feature = "postgres")]
+            Target::Postgres(client) => {
+                use tokio_postgres::types::ToSql;
+                let params: Vec<&(dyn ToSql + Sync)> = values
+                    .iter()
+                    .map(|value| match value {
+                        SqlValue::Text(v) => v as &(dyn ToSql + Sync),
+                        SqlValue::Integer(v) => v as &(dyn ToSql + Sync),
+                        SqlValue::Real(v) => v as &(dyn ToSql + Sync),
+                        SqlValue::Boolean(v) => v as &(dyn ToSql + Sync),
+                    })
+                    .collect();
+                client.execute(sql, &params).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn begin(&mut self) -> Result<()> {
+        self.execute("BEGIN", &[]).await
+    }
+
+    async fn commit(&mut self) -> Result<()> {
+        self.execute("COMMIT", &[]).await
+    }
+
+    async fn existing_columns(&mut self, table: &str) -> Result<Vec<String>> {
+        match self {
+            Target::Sqlite(conn) => {
+                let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", quote(table)))?;
+                let names = stmt
+                    .query_map([], |row| row.get::<_, String>(1))?
+                    .collect::<rusqlite::Result<Vec<_>>>()?;
+                Ok(names)
+            }
+            #[cfg(feature = "postgres")]
+            Target::Postgres(client) => {
+                let rows = client
+                    .query(
+                        "SELECT column_name FROM information_schema.columns WHERE table_name = $1",
+                        &[&table],
+                    )
+                    .await?;
+                Ok(rows.iter().map(|row| row.get(0)).collect())
+            }
+        }
+    }
+
+    /// Create the table if it's missing & add any missing columns, returning their names.
+    async fn ensure_table(&mut self, table: &str, columns: &[Column]) -> Result<Vec<String>> {
+        let create = format!(
+            "CREATE TABLE IF NOT EXISTS {} (\"id\" TEXT PRIMARY KEY, \"author\" TEXT NOT NULL, \"created_at\" {} NOT NULL)",
+            quote(table),
+            self.sql_type(ColumnType::Integer),
+        );
+        self.execute(&create, &[]).await?;
+
+        let existing = self.existing_columns(table).await?;
+        let mut added = Vec::new();
+        for column in columns.iter().filter(|c| !existing.contains(&c.name)) {
+            let alter = format!(
+                "ALTER TABLE {} ADD COLUMN {} {}",
+                quote(table),
+                quote(&column.name),
+                self.sql_type(column.ty),
+            );
+            self.execute(&alter, &[]).await?;
+            added.push(column.name.clone());
+        }
+        Ok(added)
+    }
+
+    async fn upsert(&mut self, table: &str, columns: &[Column], row: &Row) -> Result<()> {
+        let names: Vec<String> = RESERVED_COLUMNS
+            .iter()
+            .map(|name| quote(name))
+            .chain(columns.iter().map(|column| quote(&column.name)))
+            .collect();
+        let types = [ColumnType::Text, ColumnType::Text, ColumnType::Integer]
+            .into_iter()
+            .chain(columns.iter().map(|column| column.ty));
+        let placeholders: Vec<String> = types
+            .enumerate()
+            .map(|(i, ty)| self.placeholder(i + 1, ty))
+            .collect();
+        let updates: Vec<String> = names
+            .iter()
+            .skip(1)
+            .map(|name| format!("{name} = excluded.{name}"))
+            .collect();
+        let sql = format!(
+            "INSERT INTO {} ({}) VALUES ({}) ON CONFLICT (\"id\") DO UPDATE SET {}",
+            quote(table),
+            names.join(", "),
+            placeholders.join(", "),
+            updates.join(", "),
+        );
+        self.execute(&sql, &row_values(columns, row)).await
+    }
+
+    async fn delete(&mut self, table: &str, id: Uuid) -> Result<()> {
+        let sql = format!(
+            "DELETE FROM {} WHERE \"id\" = {}",
+            quote(table),
+            self.placeholder(1, ColumnType::Text)
+        );
+        self.execute(&sql, &[SqlValue::Text(Some(id.to_string()))])
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use iroh::net::key::SecretKey;
+    use serde_json::json;
+
+    use super::*;
+    use crate::space::events::HashLink;
+
+    fn row(id: Uuid, data: Value) -> Row {
+        Row {
+            id,
+            created_at: 10,
+            author: SecretKey::generate().public(),
+            content: HashLink {
+                hash: Hash::new(data.to_string()),
+                data: Some(data),
+            },
+            schema: Hash::new(b"schema"),
+        }
+    }
+
+    #[test]
+    fn schema_properties_become_columns() {
+        let schema = json!({
+            "title": "people",
+            "properties": {
+                "name": { "type": "string" },
+                "age": { "type": ["integer", "null"] },
+                "score": { "type": "number" },
+                "member": { "type": "boolean" },
+                "tags": { "type": "array" },
+                "id": { "type": "string" },
+            },
+        });
+        let columns = columns(&schema);
+        let ty = |name: &str| columns.iter().find(|c| c.name == name).map(|c| c.ty);
+        assert_eq!(ty("name"), Some(ColumnType::Text));
+        assert_eq!(ty("age"), Some(ColumnType::Integer));
+        assert_eq!(ty("score"), Some(ColumnType::Real));
+        assert_eq!(ty("member"), Some(ColumnType::Boolean));
+        assert_eq!(ty("tags"), Some(ColumnType::Json));
+        assert_eq!(ty("id"), None, "reserved");
+        assert_eq!(table_name("My People!"), "my_people_");
+    }
+
+    #[tokio::test]
+    async fn sqlite_mirror_upserts_and_deletes() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("mirror.db");
+        let mut target = Target::connect(&format!("sqlite://{}", path.display())).await?;
+
+        let mut columns = columns(&json!({ "properties": { "name": { "type": "string" } } }));
+        assert_eq!(target.ensure_table("people", &columns).await?, vec!["name"]);
+
+        let ada = Uuid::new_v4();
+        target
+            .upsert("people", &columns, &row(ada, json!({ "name": "ada" })))
+            .await?;
+        target
+            .upsert("people", &columns, &row(ada, json!({ "name": "ada l" })))
+            .await?;
+
+        // the schema grows a column
+        columns.push(Column {
+            name: "age".to_string(),
+            ty: ColumnType::Integer,
+        });
+        assert_eq!(target.ensure_table("people", &columns).await?, vec!["age"]);
+        let grace = Uuid::new_v4();
+        target
+            .upsert(
+                "people",
+                &columns,
+                &row(grace, json!({ "name": "grace", "age": 85 })),
+            )
+            .await?;
+
+        let Target::Sqlite(conn) = &target else {
+            unreachable!()
+        };
+        let names: Vec<(String, Option<i64>)> = conn
+            .prepare("SELECT name, age FROM people ORDER BY name")?
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<_>>()?;
+        assert_eq!(
+            names,
+            vec![("ada l".to_string(), None), ("grace".to_string(), Some(85))]
+        );
+
+        target.delete("people", ada).await?;
+        let Target::Sqlite(conn) = &target else {
+            unreachable!()
+        };
+        let count: i64 = conn.query_row("SELECT COUNT(*) FROM people", [], |row| row.get(0))?;
+        assert_eq!(count, 1);
+        Ok(())
+    }
+}
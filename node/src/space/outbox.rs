@@ -0,0 +1,524 @@
+//! Events written on this node that no peer has acknowledged yet.
+//!
+//! Every event a shared space writes is queued here, on disk, before it's published to the
+//! space's feed. Edits made offline survive restarts & go out once peers are reachable again.
+//! A peer acknowledges an event by finishing a sync that started after the event was
+//! published. Acknowledged events are checked for conflicts: events for the same object that
+//! other members published while ours was waiting. Last writer still wins, the annotation
+//! only tells the user their edit raced someone else's. Acknowledged entries without conflicts
+//! are dropped after [`ACKED_RETENTION`].
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Result};
+use futures::StreamExt;
+use iroh::client::docs::{Doc, LiveEvent};
+use iroh::net::key::PublicKey;
+use iroh::net::NodeAddr;
+use rusqlite::params;
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinHandle;
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+use super::events::{Event, EventKind, Sha256Digest, EVENT_SQL_SIGNED_READ_FIELDS};
+use super::feed::publish_to;
+use super::{Space, Spaces};
+use crate::peers::PeerBook;
+
+/// how often unpublished events are retried, and new shared spaces picked up
+const RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// how long acknowledged events without conflicts are kept around
+const ACKED_RETENTION: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+const OUTBOX_SQL_FIELDS: &str =
+    "event_id, kind, data_id, queued_at, attempts, last_error, published_at, acked_at, conflicts";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+#[serde(rename_all = "camelCase")]
+pub struct OutboxEntry {
+    pub event_id: Sha256Digest,
+    pub kind: EventKind,
+    /// the object the event writes
+    pub data_id: Uuid,
+    pub queued_at: i64,
+    /// publish attempts, including the first
+    pub attempts: u32,
+    pub last_error: Option<String>,
+    pub published_at: Option<i64>,
+    pub acked_at: Option<i64>,
+    /// ids of events other members wrote to the same object while this one was pending
+    pub conflicts: Vec<Sha256Digest>,
+}
+
+impl OutboxEntry {
+    fn from_sql_row(row: &rusqlite::Row) -> Result<Self> {
+        let event_id: String = row.get(0)?;
+        let conflicts: Option<String> = row.get(8)?;
+        Ok(OutboxEntry {
+            event_id: Sha256Digest::from_str(&event_id).map_err(|e| anyhow!(e))?,
+            kind: row.get(1)?,
+            data_id: row.get(2)?,
+            queued_at: row.get(3)?,
+            attempts: row.get(4)?,
+            last_error: row.get(5)?,
+            published_at: row.get(6)?,
+            acked_at: row.get(7)?,
+            conflicts: match conflicts {
+                Some(conflicts) => serde_json::from_str(&conflicts)?,
+                None => Vec::new(),
+            },
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+#[serde(rename_all = "camelCase")]
+pub struct OutboxStatus {
+    /// events no peer has acknowledged
+    pub pending: u64,
+    /// pending events that couldn't be published to the feed yet
+    pub unpublished: u64,
+    /// when the oldest pending event was queued
+    pub oldest_pending: Option<i64>,
+    /// acknowledged events that raced an edit by another member
+    pub conflicts: u64,
+}
+
+#[derive(Clone)]
+pub struct Outbox(Space);
+
+impl Outbox {
+    pub fn new(space: Space) -> Self {
+        Outbox(space)
+    }
+
+    /// Queue a newly written event & try to publish it. A no-op for spaces that aren't
+    /// shared. Failing to publish isn't an error, the event is retried later.
+    pub(crate) async fn send(&self, event: &Event) -> Result<()> {
+        let Some(doc) = self.0.feed().doc().await? else {
+            return Ok(());
+        };
+        let data_id = event.data_id()?.unwrap_or_default();
+        let conn = self.0.db.lock().await;
+        conn.execute(
+            "INSERT OR IGNORE INTO outbox (event_id, kind, data_id, queued_at, attempts) VALUES (?1, ?2, ?3, ?4, 0)",
+            params![event.id.to_string(), event.kind, data_id, chrono::Utc::now().timestamp()],
+        )?;
+        drop(conn);
+        self.publish(&doc, event).await
+    }
+
+    /// Publish every queued event the feed doesn't have yet & ask known peers to sync, if
+    /// any event is still waiting for one. Returns how many events were published.
+    pub async fn flush(&self) -> Result<usize> {
+        self.flush_to(None, None).await
+    }
+
+    /// Like [`Outbox::flush`], syncing only with `peer` if given & never with peers forgotten
+    /// in `book`.
+    async fn flush_to(&self, peer: Option<PublicKey>, book: Option<&PeerBook>) -> Result<usize> {
+        let Some(doc) = self.0.feed().doc().await? else {
+            return Ok(0);
+        };
+        let mut published = 0;
+        for event in self.unpublished().await? {
+            self.publish(&doc, &event).await?;
+            published += 1;
+        }
+        if self.status().await?.pending == 0 {
+            return Ok(published);
+        }
+
+        let candidates: Vec<PublicKey> = match peer {
+            Some(peer) => vec![peer],
+            None => doc
+                .get_sync_peers()
+                .await?
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|peer| PublicKey::from_bytes(&peer).ok())
+                .collect(),
+        };
+        let mut peers = Vec::with_capacity(candidates.len());
+        for peer in candidates {
+            if let Some(book) = book {
+                if book.is_forgotten(&peer).await {
+                    continue;
+                }
+            }
+            peers.push(NodeAddr::new(peer));
+        }
+        if !peers.is_empty() {
+            doc.start_sync(peers).await?;
+        }
+        Ok(published)
+    }
+
+    pub async fn status(&self) -> Result<OutboxStatus> {
+        let conn = self.0.db.lock().await;
+        let status = conn.query_row(
+            "SELECT
+                COUNT(*) FILTER (WHERE acked_at IS NULL),
+                COUNT(*) FILTER (WHERE acked_at IS NULL AND published_at IS NULL),
+                MIN(queued_at) FILTER (WHERE acked_at IS NULL),
+                COUNT(*) FILTER (WHERE conflicts IS NOT NULL)
+            FROM outbox",
+            [],
+            |row| {
+                Ok(OutboxStatus {
+                    pending: row.get::<_, i64>(0)? as u64,
+                    unpublished: row.get::<_, i64>(1)? as u64,
+                    oldest_pending: row.get(2)?,
+                    conflicts: row.get::<_, i64>(3)? as u64,
+                })
+            },
+        )?;
+        Ok(status)
+    }
+
+    /// Events waiting for a peer, oldest first.
+    pub async fn pending(&self, offset: i64, limit: i64) -> Result<Vec<OutboxEntry>> {
+        self.entries("acked_at IS NULL", offset, limit).await
+    }
+
+    /// Acknowledged events that raced another member's edit, oldest first.
+    pub async fn conflicts(&self, offset: i64, limit: i64) -> Result<Vec<OutboxEntry>> {
+        self.entries("conflicts IS NOT NULL", offset, limit).await
+    }
+
+    /// Forget about conflicts once the user has seen them.
+    pub async fn clear_conflicts(&self) -> Result<()> {
+        let conn = self.0.db.lock().await;
+        conn.execute("UPDATE outbox SET conflicts = NULL", [])?;
+        prune(&conn)?;
+        Ok(())
+    }
+
+    /// Follow the feed until its doc goes away: retry when a peer that isn't forgotten in
+    /// `book` comes online & mark events acknowledged when a sync finishes.
+    pub(crate) async fn follow(&self, book: &PeerBook) -> Result<()> {
+        let Some(doc) = self.0.feed().doc().await? else {
+            return Ok(());
+        };
+        let mut events = doc.subscribe().await?;
+        while let Some(event) = events.next().await {
+            match event {
+                Ok(LiveEvent::NeighborUp(peer)) => {
+                    debug!("space {} peer {} is online", self.0.id, peer);
+                    if let Err(err) = self.flush_to(Some(peer), Some(book)).await {
+                        warn!("space {} outbox flush: {:?}", self.0.id, err);
+                    }
+                }
+                Ok(LiveEvent::SyncFinished(sync)) if sync.result.is_ok() => {
+                    let started = unix_secs(sync.started);
+                    if let Err(err) = self.acknowledge(started).await {
+                        warn!("space {} outbox ack: {:?}", self.0.id, err);
+                    }
+                }
+                Ok(_) => {}
+                Err(err) => warn!("space {} feed event error: {:?}", self.0.id, err),
+            }
+        }
+        Ok(())
+    }
+
+    async fn publish(&self, doc: &Doc, event: &Event) -> Result<()> {
+        let author = self.0.router.authors().default().await?;
+        let result = publish_to(doc, author, event).await;
+        let now = chrono::Utc::now().timestamp();
+        let conn = self.0.db.lock().await;
+        match &result {
+            Ok(()) => conn.execute(
+                "UPDATE outbox SET attempts = attempts + 1, last_error = NULL, published_at = ?2 WHERE event_id = ?1",
+                params![event.id.to_string(), now],
+            )?,
+            Err(err) => {
+                debug!("publishing event {}: {:?}", event.id, err);
+                conn.execute(
+                    "UPDATE outbox SET attempts = attempts + 1, last_error = ?2 WHERE event_id = ?1",
+                    params![event.id.to_string(), format!("{err:#}")],
+                )?
+            }
+        };
+        Ok(())
+    }
+
+    async fn unpublished(&self) -> Result<Vec<Event>> {
+        let conn = self.0.db.lock().await;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {EVENT_SQL_SIGNED_READ_FIELDS} FROM events
+            WHERE id IN (SELECT event_id FROM outbox WHERE acked_at IS NULL AND published_at IS NULL)
+            ORDER BY clock"
+        ))?;
+        let mut rows = stmt.query([])?;
+        let mut events = Vec::new();
+        while let Some(row) = rows.next()? {
+            events.push(Event::from_signed_sql_row(row)?);
+        }
+        Ok(events)
+    }
+
+    async fn entries(&self, filter: &str, offset: i64, limit: i64) -> Result<Vec<OutboxEntry>> {
+        let conn = self.0.db.lock().await;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {OUTBOX_SQL_FIELDS} FROM outbox WHERE {filter} ORDER BY queued_at LIMIT ?1 OFFSET ?2"
+        ))?;
+        let mut rows = stmt.query(params![limit, offset])?;
+        let mut entries = Vec::new();
+        while let Some(row) = rows.next()? {
+            entries.push(OutboxEntry::from_sql_row(row)?);
+        }
+        Ok(entries)
+    }
+
+    /// Mark events published before a sync that started at `started` as acknowledged, then
+    /// look for conflicting edits among the events this node received from other members.
+    async fn acknowledge(&self, started: i64) -> Result<()> {
+        let conn = self.0.db.lock().await;
+        let acked = {
+            let mut stmt = conn.prepare(&format!(
+                "UPDATE outbox SET acked_at = ?1
+                WHERE acked_at IS NULL AND published_at IS NOT NULL AND published_at < ?2
+                RETURNING {OUTBOX_SQL_FIELDS}"
+            ))?;
+            let mut rows = stmt.query(params![chrono::Utc::now().timestamp(), started])?;
+            let mut acked = Vec::new();
+            while let Some(row) = rows.next()? {
+                acked.push(OutboxEntry::from_sql_row(row)?);
+            }
+            acked
+        };
+        if !acked.is_empty() {
+            debug!("space {}: {} events acknowledged", self.0.id, acked.len());
+        }
+
+        for entry in acked {
+            let conflicts = conflicting(&conn, &entry)?;
+            if conflicts.is_empty() {
+                continue;
+            }
+            conn.execute(
+                "UPDATE outbox SET conflicts = ?2 WHERE event_id = ?1",
+                params![
+                    entry.event_id.to_string(),
+                    serde_json::to_string(&conflicts)?
+                ],
+            )?;
+        }
+        prune(&conn)?;
+        Ok(())
+    }
+}
+
+/// Events for the same object that didn't come from this node's outbox, written after `entry`
+/// was queued. Content that hasn't arrived yet can't be compared.
+fn conflicting(conn: &rusqlite::Connection, entry: &OutboxEntry) -> Result<Vec<Sha256Digest>> {
+    // clocks pack seconds the same way, see space::db
+    let mut stmt = conn.prepare(
+        "SELECT id FROM events
+        WHERE kind = ?1 AND clock >= ?2 * 65536000 AND data_id = ?3
+            AND id NOT IN (SELECT event_id FROM outbox)
+        ORDER BY clock",
+    )?;
+    let mut rows = stmt.query(params![entry.kind, entry.queued_at, entry.data_id])?;
+    let mut conflicts = Vec::new();
+    while let Some(row) = rows.next()? {
+        let id: String = row.get(0)?;
+        conflicts.push(Sha256Digest::from_str(&id).map_err(|e| anyhow!(e))?);
+    }
+    Ok(conflicts)
+}
+
+/// Drop acknowledged entries without conflicts once they're older than [`ACKED_RETENTION`].
+fn prune(conn: &rusqlite::Connection) -> Result<usize> {
+    let cutoff = chrono::Utc::now().timestamp() - ACKED_RETENTION.as_secs() as i64;
+    let pruned = conn.execute(
+        "DELETE FROM outbox WHERE acked_at < ?1 AND conflicts IS NULL",
+        params![cutoff],
+    )?;
+    Ok(pruned)
+}
+
+fn unix_secs(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+/// Follow the outbox & [`crate::space::inbox`] of every shared space, picking up spaces as
+/// they're created or shared, and retry unpublished events every [`RETRY_INTERVAL`]. Peers
+/// forgotten in `book` aren't synced with.
+pub(crate) fn spawn(spaces: Spaces, book: PeerBook) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        // keyed by space & namespace, a rotated secret moves the feed to a new doc
+        let mut followers: HashMap<(Uuid, String), JoinHandle<()>> = HashMap::new();
+        let mut retry = tokio::time::interval(RETRY_INTERVAL);
+        loop {
+            retry.tick().await;
+            followers.retain(|_, handle| !handle.is_finished());
+            let details = match spaces.list(0, -1).await {
+                Ok(details) => details,
+                Err(err) => {
+                    warn!("listing spaces for outbox: {:?}", err);
+                    continue;
+                }
+            };
+            for details in details {
+                let Some(space) = spaces.get(&details.id).await else {
+                    continue;
+                };
+                let outbox = space.outbox();
+                if let Err(err) = outbox.flush_to(None, Some(&book)).await {
+                    warn!("space {} outbox flush: {:?}", space.id, err);
+                }
+                let key = (space.id, space.secret().id().to_string());
+                if followers.contains_key(&key)
+                    || !matches!(space.feed().is_shared().await, Ok(true))
+                {
+                    continue;
+                }
+                followers.retain(|(id, _), handle| {
+                    if *id == space.id {
+                        handle.abort();
+                    }
+                    *id != space.id
+                });
+                let inbox = space.inbox();
+                let book = book.clone();
+                let handle = tokio::spawn(async move {
+                    let (sent, received) = tokio::join!(outbox.follow(&book), inbox.follow());
+                    if let Err(err) = sent {
+                        warn!("following outbox of space {}: {:?}", outbox.0.id, err);
+                    }
+                    if let Err(err) = received {
+                        warn!("following inbox of space {}: {:?}", outbox.0.id, err);
+                    }
+                });
+                followers.insert(key, handle);
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use iroh::docs::{Author, NamespaceSecret};
+    use serde_json::json;
+
+    use super::*;
+
+    async fn shared_space(
+        node: &iroh::node::Node<iroh::blobs::store::mem::Store>,
+    ) -> Result<Space> {
+        let space = Space::open_in_memory(
+            Uuid::new_v4(),
+            "outbox".to_string(),
+            NamespaceSecret::new(&mut rand::thread_rng()),
+            node.client().clone(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+        )
+        .await?;
+        space.feed().share().await?;
+        Ok(space)
+    }
+
+    /// the only entry in the outbox
+    async fn entry(space: &Space) -> Result<OutboxEntry> {
+        let mut entries = space.outbox().entries("1 = 1", 0, -1).await?;
+        assert_eq!(entries.len(), 1);
+        Ok(entries.remove(0))
+    }
+
+    #[tokio::test]
+    async fn redelivers_unpublished_events() -> Result<()> {
+        let node = iroh::node::Node::memory().enable_docs().spawn().await?;
+        let space = shared_space(&node).await?;
+        let author = Author::new(&mut rand::thread_rng());
+        space
+            .tables()
+            .create(
+                author,
+                Bytes::from(json!({ "title": "notes", "type": "object" }).to_string()),
+            )
+            .await?;
+        let sent = entry(&space).await?;
+        assert_eq!(sent.attempts, 1);
+        assert!(sent.published_at.is_some());
+
+        // crashing between queueing & publishing leaves the entry unpublished
+        space
+            .db
+            .lock()
+            .await
+            .execute("UPDATE outbox SET published_at = NULL", [])?;
+        assert_eq!(space.outbox().status().await?.unpublished, 1);
+        assert_eq!(space.outbox().flush().await?, 1);
+        let resent = entry(&space).await?;
+        assert_eq!(resent.attempts, 2);
+        assert!(resent.published_at.is_some());
+        assert_eq!(space.outbox().flush().await?, 0);
+        assert_eq!(entry(&space).await?.attempts, 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn acknowledges_conflicts_and_prunes() -> Result<()> {
+        let node = iroh::node::Node::memory().enable_docs().spawn().await?;
+        let space = shared_space(&node).await?;
+        let author = Author::new(&mut rand::thread_rng());
+        space
+            .tables()
+            .create(
+                author,
+                Bytes::from(json!({ "title": "notes", "type": "object" }).to_string()),
+            )
+            .await?;
+
+        let ours = entry(&space).await?;
+
+        // another member writing the same object, as the inbox would store it
+        let remote = Sha256Digest::from_data(b"remote");
+        space.db.lock().await.execute(
+            "INSERT INTO events (id, pubkey, created_at, kind, schema_hash, data_id, sig, content_hash, content, hlc)
+            SELECT ?2, pubkey, created_at + 1, kind, schema_hash, data_id, sig, content_hash, content, NULL
+            FROM events WHERE id = ?1",
+            params![ours.event_id.to_string(), remote.to_string()],
+        )?;
+
+        let outbox = space.outbox();
+        outbox
+            .acknowledge(chrono::Utc::now().timestamp() + 1)
+            .await?;
+        assert_eq!(outbox.status().await?.pending, 0);
+        let conflicts = outbox.conflicts(0, -1).await?;
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].conflicts, vec![remote]);
+
+        // conflicts are kept until cleared, acknowledged entries only until they're stale
+        let stale = chrono::Utc::now().timestamp() - ACKED_RETENTION.as_secs() as i64 - 1;
+        space
+            .db
+            .lock()
+            .await
+            .execute("UPDATE outbox SET acked_at = ?1", params![stale])?;
+        outbox.acknowledge(chrono::Utc::now().timestamp()).await?;
+        assert_eq!(outbox.conflicts(0, -1).await?.len(), 1);
+        outbox.clear_conflicts().await?;
+        let remaining: i64 =
+            space
+                .db
+                .lock()
+                .await
+                .query_row("SELECT COUNT(*) FROM outbox", [], |row| row.get(0))?;
+        assert_eq!(remaining, 0);
+        Ok(())
+    }
+}
@@ -1,30 +1,46 @@
 use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
 
 use anyhow::{anyhow, Context, Result};
 use futures_buffered::BufferedStreamExt;
 use futures_lite::StreamExt;
 use iroh::blobs::format::collection::Collection;
 use iroh::blobs::util::SetTagOption;
-use iroh::blobs::Hash;
+use iroh::blobs::{Hash, Tag as BlobTag};
 use iroh::client::blobs::WrapOption;
 use iroh::docs::Author;
 use iroh::net::key::PublicKey;
-use rusqlite::params;
+use rusqlite::{params, OptionalExtension};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+use super::audit::AuditAction;
+use super::clock::Hlc;
 use super::events::{
     Event, EventKind, EventObject, HashLink, Tag, EVENT_SQL_READ_FIELDS, NOSTR_ID_TAG,
 };
 use super::tickets::ProgramTicket;
-use super::Space;
+use super::{Page, Space};
 use crate::router::RouterClient;
+use crate::vm::flow::TaskOutput;
+use crate::vm::{Artifacts, DockerNetwork, EgressRecord};
+use crate::Error;
 
 const MANIFEST_FILENAME: &str = "program.json";
 const DEFAULT_PROGRAM_ENTRY_FILENAME: &str = "index.wasm";
 const HTML_INDEX_FILENAME: &str = "index.html";
+/// tag prefix for program packages that have been imported but not yet recorded as an event
+const STAGING_TAG_PREFIX: &str = "program-staging/";
+/// tag prefix for installed program packages, keeps them from being garbage collected
+const INSTALLED_TAG_PREFIX: &str = "program/";
+/// tag prefix for published program packages, pins the published version until it's replaced
+/// or unpublished
+const PUBLISHED_TAG_PREFIX: &str = "program-published/";
+/// most programs one program can depend on, directly or not
+const MAX_DEPENDENCIES: usize = 64;
 
 #[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
 pub struct Manifest {
     pub name: String,
     pub version: String,
@@ -34,14 +50,223 @@ pub struct Manifest {
     pub license: Option<String>,
     pub main: Option<String>,
     pub config: Option<ProgramConfig>,
+    pub permissions: Option<ProgramPermissions>,
+    /// programs that must be installed in the same space for this one to run
+    pub dependencies: Option<Vec<ProgramDependency>>,
+    /// Tasks to run in place of `main`. Top level tasks run one after the other.
+    pub flow: Option<Vec<ProgramTask>>,
+}
+
+/// A task of a program's flow. The space, author & environment of its job come from the run.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+pub struct ProgramTask {
+    /// unique within the program
+    pub name: String,
+    #[serde(flatten)]
+    pub run: ProgramTaskRun,
+    /// Downloads are named relative to the run, `build/out.tar` is the `out.tar` upload of the
+    /// `build` task.
+    #[serde(default)]
+    pub artifacts: Artifacts,
+    /// tasks that run alongside this one, eg: to produce its downloads
+    #[serde(default)]
+    pub tasks: Vec<ProgramTask>,
+    /// eg: "90s" or "5m", an hour if unset
+    pub timeout: Option<String>,
+    /// docker only, whether a worker may stop the task for interactive work & run it again later
+    #[serde(default)]
+    pub preemptible: bool,
+    /// eg: "10m", how long after the run starts the task must be done by. No deadline if unset
+    pub deadline: Option<String>,
+    /// what a worker needs to run the task, eg: `["docker", "gpu"]`
+    #[serde(default)]
+    pub requires: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ProgramTaskRun {
+    Docker {
+        image: String,
+        command: Vec<String>,
+        #[serde(default)]
+        network: DockerNetwork,
+    },
+    Wasm {
+        /// file in the program
+        module: String,
+    },
+}
+
+impl Manifest {
+    /// A normalized, deduplicated list of everything this program asks to be allowed to do.
+    pub fn capabilities(&self) -> Vec<Capability> {
+        let mut caps = Vec::new();
+        if let Some(permissions) = &self.permissions {
+            for host in permissions.network.iter().flatten() {
+                caps.push(Capability::network(host));
+            }
+            for table in permissions.tables.iter().flatten() {
+                caps.push(Capability::write_table(table));
+            }
+            for key in permissions.secrets.iter().flatten() {
+                caps.push(Capability::secret(key, None, true));
+            }
+        }
+        // environment variables are populated from program secrets
+        if let Some(vars) = self.config.as_ref().and_then(|c| c.environment.as_ref()) {
+            for var in vars {
+                caps.retain(|c| !(c.kind == CapabilityKind::Secret && c.target == var.key));
+                caps.push(Capability::secret(
+                    &var.key,
+                    Some(&var.description),
+                    var.required,
+                ));
+            }
+        }
+
+        caps.sort_by(|a, b| (a.kind, &a.target).cmp(&(b.kind, &b.target)));
+        caps.dedup_by(|a, b| a.kind == b.kind && a.target == b.target);
+        caps
+    }
+}
+
+/// A program another program needs, by name & version, by package hash, or both.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+pub struct ProgramDependency {
+    pub name: String,
+    /// eg: `0.2.0` or `>=0.2.0`, any version if unset
+    pub version: Option<String>,
+    /// hash of the program package, pins an exact build
+    #[cfg_attr(feature = "specta", specta(type = Option<String>))]
+    pub hash: Option<Hash>,
+    /// where to download the program from when the space doesn't have it
+    #[cfg_attr(feature = "specta", specta(type = Option<String>))]
+    pub ticket: Option<ProgramTicket>,
+}
+
+impl ProgramDependency {
+    fn matches(&self, program: &Program) -> bool {
+        program.manifest.name == self.name
+            && self
+                .version
+                .as_deref()
+                .map_or(true, |req| version_matches(req, &program.manifest.version))
+            && self.hash.map_or(true, |hash| hash == program.content.hash)
+    }
+}
+
+impl std::fmt::Display for ProgramDependency {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name)?;
+        if let Some(version) = &self.version {
+            write!(f, " {version}")?;
+        }
+        if let Some(hash) = &self.hash {
+            write!(f, " ({})", hash.fmt_short())?;
+        }
+        Ok(())
+    }
+}
+
+/// Check a version against a requirement: a version, optionally prefixed by one of `=`, `>`,
+/// `>=`, `<` or `<=`.
+fn version_matches(requirement: &str, version: &str) -> bool {
+    let requirement = requirement.trim();
+    let (sign, wanted) = [">=", "<=", ">", "<", "="]
+        .iter()
+        .find_map(|sign| {
+            requirement
+                .strip_prefix(sign)
+                .map(|rest| (*sign, rest.trim()))
+        })
+        .unwrap_or(("=", requirement));
+    let Ok(cmp) = version_compare::Cmp::from_sign(sign) else {
+        return false;
+    };
+    version_compare::compare_to(version, wanted, cmp).unwrap_or(false)
+}
+
+/// Capabilities a program declares it needs. Hosts may be bare hostnames or URLs.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+pub struct ProgramPermissions {
+    pub network: Option<Vec<String>>,
+    /// most network requests per minute the program needs, lowers the worker's limit
+    pub network_rate_limit: Option<u32>,
+    pub tables: Option<Vec<String>>,
+    pub secrets: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+#[serde(rename_all = "camelCase")]
+pub enum CapabilityKind {
+    Network,
+    WriteTable,
+    Secret,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+pub struct Capability {
+    pub kind: CapabilityKind,
+    pub target: String,
+    pub required: bool,
+    pub description: String,
+}
+
+impl Capability {
+    fn network(host: &str) -> Self {
+        let host = host.trim().to_lowercase();
+        let host = host
+            .split_once("://")
+            .map_or(host.as_str(), |(_, rest)| rest);
+        let host = host.split(['/', '?', '#']).next().unwrap_or_default();
+        Capability {
+            kind: CapabilityKind::Network,
+            target: host.to_string(),
+            required: true,
+            description: format!("Make network requests to {host}"),
+        }
+    }
+
+    fn write_table(table: &str) -> Self {
+        let table = table.trim();
+        Capability {
+            kind: CapabilityKind::WriteTable,
+            target: table.to_string(),
+            required: true,
+            description: format!("Create and update rows in the \"{table}\" table"),
+        }
+    }
+
+    fn secret(key: &str, description: Option<&str>, required: bool) -> Self {
+        let key = key.trim();
+        let description = match description {
+            Some(d) if !d.is_empty() => format!("Read secret {key}: {d}"),
+            _ => format!("Read secret {key}"),
+        };
+        Capability {
+            kind: CapabilityKind::Secret,
+            target: key.to_string(),
+            required,
+            description,
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
 pub struct ProgramConfig {
     environment: Option<Vec<ProgramEnvVar>>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
 pub struct ProgramEnvVar {
     pub key: String,
     pub description: String,
@@ -49,17 +274,34 @@ pub struct ProgramEnvVar {
 }
 
 #[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
 pub struct Program {
     pub id: Uuid,
     #[serde(rename = "createdAt")]
     pub created_at: i64,
+    #[cfg_attr(feature = "specta", specta(type = String))]
     pub author: PublicKey,
     pub content: HashLink,
     pub manifest: Manifest,
+    #[cfg_attr(feature = "specta", specta(type = Option<String>))]
     pub html_index: Option<Hash>,
+    #[cfg_attr(feature = "specta", specta(type = Option<String>))]
     pub program_entry: Option<Hash>,
 }
 
+/// Read the manifest out of a program collection.
+pub(crate) async fn read_manifest(
+    client: &RouterClient,
+    collection: &Collection,
+) -> Result<Manifest> {
+    let (_, manifest_hash) = collection
+        .iter()
+        .find(|item| item.0 == MANIFEST_FILENAME)
+        .ok_or_else(|| anyhow!("missing manifest"))?;
+    let data = client.blobs().read_to_bytes(*manifest_hash).await?;
+    Ok(serde_json::from_slice(&data)?)
+}
+
 impl EventObject for Program {
     async fn from_event(event: Event, client: &RouterClient) -> Result<Self> {
         if event.kind != EventKind::MutateProgram {
@@ -70,14 +312,7 @@ impl EventObject for Program {
 
         // fetch collection content
         let collection = client.blobs().get_collection(event.content.hash).await?;
-
-        // extract the manifest
-        let (_, manifest_hash) = collection
-            .iter()
-            .find(|item| item.0 == MANIFEST_FILENAME)
-            .ok_or_else(|| anyhow!("missing manifest"))?;
-        let data = client.blobs().read_to_bytes(*manifest_hash).await?;
-        let manifest: Manifest = serde_json::from_slice(&data)?;
+        let manifest = read_manifest(client, &collection).await?;
         let (html_index, program_entry) = Program::hash_pointers(&manifest, &collection)?;
 
         Ok(Program {
@@ -91,12 +326,13 @@ impl EventObject for Program {
         })
     }
 
-    fn into_mutate_event(&self, author: Author) -> Result<Event> {
+    fn into_mutate_event(&self, author: Author, hlc: Hlc) -> Result<Event> {
         // assert!(author.public_key() == self.author);
         let tags = vec![Tag::new(NOSTR_ID_TAG, self.id.to_string().as_str())];
         Event::create(
             author,
             self.created_at,
+            hlc,
             EventKind::MutateProgram,
             tags,
             self.content.clone(),
@@ -125,11 +361,6 @@ impl Program {
         }
         Ok((html_index, program_entry))
     }
-
-    async fn from_sql_row(row: &rusqlite::Row<'_>, client: &RouterClient) -> Result<Program> {
-        let event = Event::from_sql_row(row)?;
-        Self::from_event(event, client).await
-    }
 }
 
 #[derive(Clone)]
@@ -164,28 +395,39 @@ impl Programs {
         // load manifest
         let data: Vec<u8> = tokio::fs::read(&manifest_path).await?;
         let manifest: Manifest = serde_json::from_slice(data.as_slice())?;
+        self.resolve_dependencies(&manifest)
+            .await
+            .with_context(|| format!("installing {}", manifest.name))?;
 
-        // create collection
-        let (hash, _size, collection) = import(self.0.router.blobs(), path).await?;
+        let space = self.0.clone();
+        install(&self.0.router, id, path, |hash, collection| async move {
+            // build program
+            let (html_index, program_entry) = Program::hash_pointers(&manifest, &collection)?;
+            let program = Program {
+                id,
+                // TODO(b5) - wat. why? you're doing something wrong with types.
+                author: PublicKey::from_bytes(author.public_key().as_bytes())?,
+                created_at: chrono::Utc::now().timestamp(),
+                manifest,
+                content: HashLink { hash, data: None },
+                html_index,
+                program_entry,
+            };
 
-        // build program
-        let (html_index, program_entry) = Program::hash_pointers(&manifest, &collection)?;
-        let program = Program {
-            id,
-            // TODO(b5) - wat. why? you're doing something wrong with types.
-            author: PublicKey::from_bytes(author.public_key().as_bytes())?,
-            created_at: chrono::Utc::now().timestamp(),
-            manifest,
-            content: HashLink { hash, data: None },
-            html_index,
-            program_entry,
-        };
+            // write event
+            let event = program.into_mutate_event(author.clone(), space.clock().tick())?;
+            space.write_event(&event).await?;
+            let action = AuditAction::ProgramInstalled {
+                program_id: id,
+                name: program.manifest.name.clone(),
+                version: program.manifest.version.clone(),
+            };
+            space.audit().record(author, action).await?;
+            space.hooks.program_installed(&space, &program);
 
-        // write event
-        let event = program.into_mutate_event(author)?;
-        event.write(&self.0.db).await?;
-
-        Ok(program)
+            Ok(program)
+        })
+        .await
     }
 
     pub async fn share(&self, router: &RouterClient, id: Uuid) -> Result<ProgramTicket> {
@@ -222,36 +464,111 @@ impl Programs {
     }
 
     pub async fn download(&self, router: &RouterClient, ticket: ProgramTicket) -> Result<Program> {
-        let addr = ticket.node_addr().clone();
-        // fetch the blob
-        router
-            .blobs()
-            .download(ticket.hash(), addr)
-            .await?
-            .finish()
-            .await?;
+        self.download_checked(router, ticket, |_| Ok(())).await
+    }
 
-        let mut collection = router
-            .blobs()
-            .get_collection(ticket.hash())
-            .await?
-            .into_iter();
-
-        // ingest the program event
-        let (_, hash) = collection
-            .next()
-            .ok_or_else(|| anyhow!("empty collection"))?;
-        let event = Event::ingest_from_blob(&self.0.db, router, hash).await?;
-
-        // consume the rest of the collection, adding as a new collection to re-surface the progra
-        // pacakge root hash in our local repo
-        let collection = Collection::from_iter(collection);
-        router
-            .blobs()
-            .create_collection(collection, SetTagOption::Auto, vec![])
-            .await?;
+    /// [`Programs::download`], installing the program only if `check` accepts it.
+    async fn download_checked(
+        &self,
+        router: &RouterClient,
+        ticket: ProgramTicket,
+        check: impl FnOnce(&Program) -> Result<()>,
+    ) -> Result<Program> {
+        let event_hash = fetch_package(router, &ticket).await?;
+        let data = router.blobs().read_to_bytes(event_hash).await?;
+        let event = Event::decode(&data)?;
+        if event.kind != EventKind::MutateProgram {
+            return Err(Error::invalid("ticket isn't for a program").into());
+        }
+        check(&Program::from_event(event, router).await?)?;
+        let event = Event::ingest_from_blob(&self.0.db, router, event_hash).await?;
+        self.0.hooks.event_written(&self.0, &event);
+        let program = Program::from_event(event, router).await?;
+        self.0.hooks.program_installed(&self.0, &program);
+        Ok(program)
+    }
+
+    /// Install a program shared with [`Programs::share`], from the string form of its ticket.
+    pub async fn import(&self, author: Author, ticket: &str) -> Result<Program> {
+        let ticket = ProgramTicket::from_str(ticket.trim()).map_err(Error::invalid)?;
+        if !ticket.recursive() {
+            return Err(Error::invalid("ticket isn't for a program package").into());
+        }
+        let program = self.download(&self.0.router, ticket).await?;
+        let action = AuditAction::ProgramInstalled {
+            program_id: program.id,
+            name: program.manifest.name.clone(),
+            version: program.manifest.version.clone(),
+        };
+        self.0.audit().record(author, action).await?;
+        Ok(program)
+    }
 
-        Program::from_event(event, router).await
+    /// Make sure every program `manifest` depends on is installed with its package present,
+    /// along with whatever those depend on. Missing programs are downloaded from the tickets
+    /// their dependents declare.
+    pub async fn resolve_dependencies(&self, manifest: &Manifest) -> Result<Vec<Program>> {
+        let mut resolved: Vec<Program> = Vec::new();
+        let mut pending = manifest.dependencies.clone().unwrap_or_default();
+        while let Some(dependency) = pending.pop() {
+            anyhow::ensure!(
+                dependency.name != manifest.name,
+                "{} depends on itself",
+                manifest.name
+            );
+            if resolved.iter().any(|program| dependency.matches(program)) {
+                continue;
+            }
+            anyhow::ensure!(
+                resolved.len() < MAX_DEPENDENCIES,
+                "{} has more than {MAX_DEPENDENCIES} dependencies",
+                manifest.name
+            );
+            let program = self
+                .resolve_dependency(&dependency)
+                .await
+                .with_context(|| format!("resolving dependency {dependency}"))?;
+            pending.extend(program.manifest.dependencies.clone().unwrap_or_default());
+            resolved.push(program);
+        }
+        Ok(resolved)
+    }
+
+    async fn resolve_dependency(&self, dependency: &ProgramDependency) -> Result<Program> {
+        let router = &self.0.router;
+        let installed = self
+            .list(0, -1)
+            .await?
+            .into_iter()
+            .filter(|program| dependency.matches(program))
+            .max_by_key(|program| program.created_at);
+        match (installed, &dependency.ticket) {
+            (Some(program), ticket) => {
+                let present = router
+                    .blobs()
+                    .get_collection(program.content.hash)
+                    .await
+                    .is_ok();
+                if !present {
+                    let ticket = ticket.as_ref().context("package is missing & no ticket")?;
+                    fetch_package(router, ticket).await?;
+                }
+                Ok(program)
+            }
+            (None, Some(ticket)) => {
+                self.download_checked(router, ticket.clone(), |program| {
+                    anyhow::ensure!(
+                        dependency.matches(program),
+                        "ticket is for {} {}",
+                        program.manifest.name,
+                        program.manifest.version
+                    );
+                    Ok(())
+                })
+                .await
+            }
+            (None, None) => Err(anyhow!("not installed & no ticket to fetch it from")),
+        }
     }
 
     pub async fn get_by_name(&self, name: String) -> Result<Program> {
@@ -260,26 +577,303 @@ impl Programs {
             .await?
             .into_iter()
             .find(|program| program.manifest.name == name)
-            .ok_or_else(|| anyhow!("Program not found"))
+            .ok_or_else(|| Error::ProgramNotFound { program: name }.into())
     }
 
     pub async fn get_by_id(&self, id: Uuid) -> Result<Program> {
-        let conn = self.0.db.lock().await;
-        let mut stmt = conn
-            .prepare(
-                format!(
-                    "SELECT {EVENT_SQL_READ_FIELDS} FROM events WHERE kind = ?1 AND data_id = ?2"
+        let event = {
+            let conn = self.0.db.lock().await;
+            let mut stmt = conn
+                .prepare(
+                    format!(
+                        "SELECT {EVENT_SQL_READ_FIELDS} FROM events WHERE kind = ?1 AND data_id = ?2"
+                    )
+                    .as_str(),
                 )
-                .as_str(),
+                .context("selecting Program by id from events table")?;
+            let mut rows = stmt.query(params![EventKind::MutateProgram, id])?;
+            match rows.next()? {
+                Some(row) => Event::from_sql_row(row)?,
+                None => {
+                    return Err(Error::ProgramNotFound {
+                        program: id.to_string(),
+                    }
+                    .into())
+                }
+            }
+        };
+        Program::from_event(event, &self.0.router).await
+    }
+
+    pub async fn required_capabilities(&self, program_id: Uuid) -> Result<Vec<Capability>> {
+        let program = self.get_by_id(program_id).await?;
+        Ok(program.manifest.capabilities())
+    }
+
+    /// Add a finished run to this node's run history.
+    pub(crate) async fn record_run(&self, run: &RunRecord) -> Result<()> {
+        let conn = self.0.db.lock().await;
+        conn.execute(
+            "INSERT INTO program_runs (id, program_id, scope, started_at, succeeded, wall_time_ms, cpu_time_ms, peak_memory_bytes, artifact_bytes)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            params![
+                run.id,
+                run.program_id,
+                run.scope,
+                run.started_at,
+                run.succeeded,
+                run.wall_time_ms as i64,
+                run.cpu_time_ms.map(|v| v as i64),
+                run.peak_memory_bytes.map(|v| v as i64),
+                run.artifact_bytes as i64,
+            ],
+        )
+        .context("recording program run")?;
+        for record in run.egress.iter() {
+            conn.execute(
+                "INSERT INTO program_run_egress (run_id, destination, requests, denied, bytes_sent, bytes_received)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    run.id,
+                    record.destination,
+                    record.requests as i64,
+                    record.denied as i64,
+                    record.bytes_sent as i64,
+                    record.bytes_received as i64,
+                ],
+            )
+            .context("recording program run egress")?;
+        }
+        Ok(())
+    }
+
+    /// Output of the run started with `key`, if it was recorded at or after `since`.
+    pub(crate) async fn keyed_run(
+        &self,
+        program_id: Uuid,
+        key: &str,
+        since: i64,
+    ) -> Result<Option<TaskOutput>> {
+        let conn = self.0.db.lock().await;
+        let output: Option<String> = conn
+            .query_row(
+                "SELECT output FROM program_run_keys WHERE program_id = ?1 AND key = ?2 AND created_at >= ?3",
+                params![program_id, key, since],
+                |row| row.get(0),
             )
-            .context("selecting Program by id from events table")?;
-        let mut rows = stmt.query(params![EventKind::MutateProgram, id])?;
+            .optional()?;
+        output
+            .map(|output| serde_json::from_str(&output).context("reading keyed run"))
+            .transpose()
+    }
+
+    /// Remember the output of a run started with `key`, forgetting keys recorded before
+    /// `expire_before`.
+    pub(crate) async fn record_keyed_run(
+        &self,
+        program_id: Uuid,
+        key: &str,
+        output: &TaskOutput,
+        expire_before: i64,
+    ) -> Result<()> {
+        let conn = self.0.db.lock().await;
+        conn.execute(
+            "DELETE FROM program_run_keys WHERE created_at < ?1",
+            params![expire_before],
+        )?;
+        conn.execute(
+            "INSERT OR REPLACE INTO program_run_keys (program_id, key, output, created_at) VALUES (?1, ?2, ?3, ?4)",
+            params![
+                program_id,
+                key,
+                serde_json::to_string(output)?,
+                chrono::Utc::now().timestamp()
+            ],
+        )
+        .context("recording keyed run")?;
+        Ok(())
+    }
+
+    /// Everywhere a run tried to connect to, allowed or not.
+    pub async fn run_egress(&self, run_id: Uuid) -> Result<Vec<EgressRecord>> {
+        let conn = self.0.db.lock().await;
+        let mut stmt = conn.prepare(
+            "SELECT destination, requests, denied, bytes_sent, bytes_received FROM program_run_egress WHERE run_id = ?1 ORDER BY destination",
+        )?;
+        let records = stmt
+            .query_map(params![run_id], |row| {
+                Ok(EgressRecord {
+                    destination: row.get(0)?,
+                    requests: row.get::<_, i64>(1)? as u64,
+                    denied: row.get::<_, i64>(2)? as u64,
+                    bytes_sent: row.get::<_, i64>(3)? as u64,
+                    bytes_received: row.get::<_, i64>(4)? as u64,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(records)
+    }
+
+    /// Artifact scopes of the most recent runs of a program on this node, newest first.
+    pub(crate) async fn recent_run_scopes(
+        &self,
+        program_id: Uuid,
+        limit: i64,
+    ) -> Result<Vec<Uuid>> {
+        let conn = self.0.db.lock().await;
+        let mut stmt = conn.prepare(
+            "SELECT scope FROM program_runs WHERE program_id = ?1 ORDER BY started_at DESC LIMIT ?2",
+        )?;
+        let scopes = stmt
+            .query_map(params![program_id, limit], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<Uuid>>>()?;
+        Ok(scopes)
+    }
+
+    /// Total resources spent running a program, from runs started on this node.
+    pub async fn usage(&self, program_id: Uuid, period: UsagePeriod) -> Result<ProgramUsage> {
+        let since = period
+            .seconds()
+            .map(|seconds| chrono::Utc::now().timestamp() - seconds)
+            .unwrap_or(0);
+        let conn = self.0.db.lock().await;
+        let usage = conn.query_row(
+            "SELECT
+                COUNT(*),
+                COALESCE(SUM(NOT succeeded), 0),
+                COALESCE(SUM(wall_time_ms), 0),
+                SUM(cpu_time_ms),
+                MAX(peak_memory_bytes),
+                COALESCE(SUM(cpu_time_ms IS NULL), 0),
+                COALESCE(SUM(artifact_bytes), 0)
+            FROM program_runs WHERE program_id = ?1 AND started_at >= ?2",
+            params![program_id, since],
+            |row| {
+                Ok(ProgramUsage {
+                    program_id,
+                    period: Some(period),
+                    since,
+                    runs: row.get::<_, i64>(0)? as u64,
+                    failed_runs: row.get::<_, i64>(1)? as u64,
+                    wall_time_ms: row.get::<_, i64>(2)? as u64,
+                    cpu_time_ms: row.get::<_, Option<i64>>(3)?.map(|v| v as u64),
+                    peak_memory_bytes: row.get::<_, Option<i64>>(4)?.map(|v| v as u64),
+                    unmetered_runs: row.get::<_, i64>(5)? as u64,
+                    artifact_bytes: row.get::<_, i64>(6)? as u64,
+                })
+            },
+        )?;
+        Ok(usage)
+    }
 
-        if let Some(row) = rows.next()? {
-            Program::from_sql_row(row, &self.0.router).await
-        } else {
-            Err(anyhow!("Program not found"))
+    /// Publish a program's UI as a public site on `gateway`, the base url of a gateway serving
+    /// this node. The current version of the program's package is pinned on this node, so it
+    /// stays up when the program is updated or uninstalled. Publishing again moves the site to
+    /// the current version, keeping its name based url.
+    pub async fn publish(&self, program_id: Uuid, gateway: &str) -> Result<Publication> {
+        let program = self.get_by_id(program_id).await?;
+        anyhow::ensure!(
+            program.html_index.is_some(),
+            "program {} has no {HTML_INDEX_FILENAME} to publish",
+            program.manifest.name
+        );
+        let name = match self.publication(program_id).await? {
+            Some(publication) => publication.name,
+            None => {
+                let name = publication_name(&program.manifest.name);
+                match self.publication_by_name(&name).await? {
+                    Some(_) => format!("{name}-{}", &program_id.as_simple().to_string()[..8]),
+                    None => name,
+                }
+            }
+        };
+
+        let hash = program.content.hash;
+        let collection = self.0.router.blobs().get_collection(hash).await?;
+        let tag = BlobTag::from(format!("{PUBLISHED_TAG_PREFIX}{program_id}"));
+        self.0
+            .router
+            .blobs()
+            .create_collection(collection, SetTagOption::Named(tag), vec![])
+            .await?;
+
+        let gateway = gateway.trim_end_matches('/');
+        let published_at = chrono::Utc::now().timestamp();
+        let conn = self.0.db.lock().await;
+        conn.execute(
+            "INSERT OR REPLACE INTO program_publications (program_id, name, hash, gateway, published_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![program_id, name, hash.to_string(), gateway, published_at],
+        )
+        .context("recording publication")?;
+        Ok(Publication::new(
+            self.0.id,
+            program_id,
+            name,
+            hash,
+            gateway,
+            published_at,
+        ))
+    }
+
+    /// Take a published program's site down & unpin its package. Does nothing if the program
+    /// isn't published.
+    pub async fn unpublish(&self, program_id: Uuid) -> Result<()> {
+        let conn = self.0.db.lock().await;
+        let removed = conn.execute(
+            "DELETE FROM program_publications WHERE program_id = ?1",
+            params![program_id],
+        )?;
+        drop(conn);
+        if removed > 0 {
+            let tag = BlobTag::from(format!("{PUBLISHED_TAG_PREFIX}{program_id}"));
+            self.0.router.tags().delete(tag).await?;
         }
+        Ok(())
+    }
+
+    /// Where a program is published, if it is.
+    pub async fn publication(&self, program_id: Uuid) -> Result<Option<Publication>> {
+        self.find_publication("program_id = ?1", program_id).await
+    }
+
+    /// The program published under `name`, as in its name based url.
+    pub async fn publication_by_name(&self, name: &str) -> Result<Option<Publication>> {
+        self.find_publication("name = ?1", name).await
+    }
+
+    async fn find_publication(
+        &self,
+        filter: &str,
+        value: impl rusqlite::ToSql + Send,
+    ) -> Result<Option<Publication>> {
+        let conn = self.0.db.lock().await;
+        let row = conn
+            .query_row(
+                &format!("SELECT program_id, name, hash, gateway, published_at FROM program_publications WHERE {filter}"),
+                params![value],
+                |row| {
+                    Ok((
+                        row.get::<_, Uuid>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get::<_, String>(2)?,
+                        row.get::<_, String>(3)?,
+                        row.get::<_, i64>(4)?,
+                    ))
+                },
+            )
+            .optional()?;
+        let Some((program_id, name, hash, gateway, published_at)) = row else {
+            return Ok(None);
+        };
+        let hash = hash.parse().context("reading publication")?;
+        Ok(Some(Publication::new(
+            self.0.id,
+            program_id,
+            name,
+            hash,
+            &gateway,
+            published_at,
+        )))
     }
 
     pub async fn get_by_hash(&self, _hash: Hash) -> Result<Program> {
@@ -293,24 +887,163 @@ impl Programs {
     }
 
     pub async fn list(&self, offset: i64, limit: i64) -> Result<Vec<Program>> {
-        let conn = self.0.db.lock().await;
-        let mut stmt = conn
-            .prepare(
-                format!(
-                    "SELECT {EVENT_SQL_READ_FIELDS} FROM events WHERE kind = ?1 LIMIT ?2 OFFSET ?3"
+        let events = {
+            let conn = self.0.db.lock().await;
+            let mut stmt = conn
+                .prepare(
+                    format!(
+                        "SELECT {EVENT_SQL_READ_FIELDS} FROM events WHERE kind = ?1 LIMIT ?2 OFFSET ?3"
+                    )
+                    .as_str(),
                 )
-                .as_str(),
-            )
-            .context("selecting Programs from events table")?;
-        let mut rows = stmt.query(params![EventKind::MutateProgram, limit, offset])?;
+                .context("selecting Programs from events table")?;
+            let mut rows = stmt.query(params![EventKind::MutateProgram, limit, offset])?;
+            let mut events = Vec::new();
+            while let Some(row) = rows.next()? {
+                events.push(Event::from_sql_row(row)?);
+            }
+            events
+        };
 
         let mut programs = Vec::new();
-        while let Some(row) = rows.next()? {
-            let program = Program::from_sql_row(row, &self.0.router).await?;
-            programs.push(program);
+        for event in events {
+            programs.push(Program::from_event(event, &self.0.router).await?);
         }
         Ok(programs)
     }
+
+    /// How many programs [`Programs::list`] pages through.
+    pub async fn count(&self) -> Result<i64> {
+        let conn = self.0.db.lock().await;
+        conn.query_row(
+            "SELECT COUNT(*) FROM events WHERE kind = ?1",
+            params![EventKind::MutateProgram],
+            |row| row.get(0),
+        )
+        .context("counting programs")
+    }
+
+    pub async fn page(&self, offset: i64, limit: i64) -> Result<Page<Program>> {
+        let programs = self.list(offset, limit).await?;
+        Ok(Page::new(programs, self.count().await?, offset, limit))
+    }
+}
+
+/// A program's UI served publicly by a gateway, see [`Programs::publish`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+#[serde(rename_all = "camelCase")]
+pub struct Publication {
+    pub program_id: Uuid,
+    /// identifies the program in its space's published sites
+    pub name: String,
+    /// the published version of the program's package
+    #[cfg_attr(feature = "specta", specta(type = String))]
+    pub hash: Hash,
+    /// follows the program across republishing
+    pub url: String,
+    /// always serves the version published as `hash`
+    pub immutable_url: String,
+    pub published_at: i64,
+}
+
+impl Publication {
+    fn new(
+        space_id: Uuid,
+        program_id: Uuid,
+        name: String,
+        hash: Hash,
+        gateway: &str,
+        published_at: i64,
+    ) -> Self {
+        Publication {
+            program_id,
+            url: format!("{gateway}/published/{space_id}/{name}"),
+            immutable_url: format!("{gateway}/{hash}/{HTML_INDEX_FILENAME}"),
+            name,
+            hash,
+            published_at,
+        }
+    }
+}
+
+/// A url path segment from a program's name: lowercase ascii letters, digits & dashes.
+fn publication_name(program_name: &str) -> String {
+    let mut name = String::new();
+    for c in program_name.chars() {
+        if c.is_ascii_alphanumeric() {
+            name.push(c.to_ascii_lowercase());
+        } else if !name.is_empty() && !name.ends_with('-') {
+            name.push('-');
+        }
+    }
+    let name = name.trim_end_matches('-');
+    match name.is_empty() {
+        true => "program".to_string(),
+        false => name.to_string(),
+    }
+}
+
+/// Window of time to total program usage over, ending now.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+#[serde(rename_all = "camelCase")]
+pub enum UsagePeriod {
+    Hour,
+    Day,
+    Week,
+    Month,
+    All,
+}
+
+impl UsagePeriod {
+    fn seconds(&self) -> Option<i64> {
+        match self {
+            UsagePeriod::Hour => Some(60 * 60),
+            UsagePeriod::Day => Some(24 * 60 * 60),
+            UsagePeriod::Week => Some(7 * 24 * 60 * 60),
+            UsagePeriod::Month => Some(30 * 24 * 60 * 60),
+            UsagePeriod::All => None,
+        }
+    }
+}
+
+/// Resources spent running a program over a period.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+#[serde(rename_all = "camelCase")]
+pub struct ProgramUsage {
+    pub program_id: Uuid,
+    pub period: Option<UsagePeriod>,
+    /// start of the period, unix seconds
+    pub since: i64,
+    pub runs: u64,
+    pub failed_runs: u64,
+    pub wall_time_ms: u64,
+    /// cpu time of the measured runs, None if no run was measured
+    pub cpu_time_ms: Option<u64>,
+    /// highest peak memory of any measured run
+    pub peak_memory_bytes: Option<u64>,
+    /// runs without cpu & memory measurements, ie. wasm ones, left out of the totals above
+    pub unmetered_runs: u64,
+    pub artifact_bytes: u64,
+}
+
+/// One program execution, as recorded in the local run history.
+#[derive(Debug, Clone)]
+pub(crate) struct RunRecord {
+    pub id: Uuid,
+    pub program_id: Uuid,
+    /// the scope artifacts of the run are stored under
+    pub scope: Uuid,
+    pub started_at: i64,
+    pub succeeded: bool,
+    pub wall_time_ms: u64,
+    pub cpu_time_ms: Option<u64>,
+    pub peak_memory_bytes: Option<u64>,
+    pub artifact_bytes: u64,
+    /// where the run's docker jobs connected to
+    pub egress: Vec<EgressRecord>,
 }
 
 /// This function converts an already canonicalized path to a string.
@@ -381,17 +1114,87 @@ pub fn canonicalized_path_to_string(
 //     "yarn.lock",
 // ];
 
-/// Import from a file or directory into the database.
+/// Download a shared program, see [`Programs::share`]. Returns the hash of the program's
+/// event, the package is kept under its own hash like a local install.
+async fn fetch_package(router: &RouterClient, ticket: &ProgramTicket) -> Result<Hash> {
+    let addr = ticket.node_addr().clone();
+    // fetch the blob
+    router
+        .blobs()
+        .download(ticket.hash(), addr)
+        .await?
+        .finish()
+        .await?;
+
+    let mut collection = router
+        .blobs()
+        .get_collection(ticket.hash())
+        .await?
+        .into_iter();
+
+    // the program event comes first
+    let (_, event_hash) = collection
+        .next()
+        .ok_or_else(|| anyhow!("empty collection"))?;
+
+    // consume the rest of the collection, adding as a new collection to re-surface the program
+    // package root hash in our local repo
+    let collection = Collection::from_iter(collection);
+    router
+        .blobs()
+        .create_collection(collection, SetTagOption::Auto, vec![])
+        .await?;
+    Ok(event_hash)
+}
+
+/// Install a program package from `path`, calling `commit` to record it. Blobs are staged under a
+/// temporary tag until commit succeeds, and removed if any step fails, so a failed install
+/// doesn't leave orphaned collections in the store.
+async fn install<F, Fut, T>(router: &RouterClient, id: Uuid, path: PathBuf, commit: F) -> Result<T>
+where
+    F: FnOnce(Hash, Collection) -> Fut,
+    Fut: std::future::Future<Output = Result<T>>,
+{
+    let staging = BlobTag::from(format!("{STAGING_TAG_PREFIX}{id}"));
+    let (hash, _size, collection) = import(router, path, staging.clone()).await?;
+
+    let committed = match commit(hash, collection.clone()).await {
+        Ok(committed) => committed,
+        Err(err) => {
+            if let Err(tag_err) = router.tags().delete(staging).await {
+                tracing::warn!("failed to roll back program install: {:?}", tag_err);
+            }
+            return Err(err);
+        }
+    };
+
+    // swap the staging tag for a permanent one
+    let installed = installed_tag(id);
+    router
+        .blobs()
+        .create_collection(collection, SetTagOption::Named(installed), vec![staging])
+        .await?;
+    Ok(committed)
+}
+
+/// Tag that keeps the installed package of program `id`.
+pub(super) fn installed_tag(id: Uuid) -> BlobTag {
+    BlobTag::from(format!("{INSTALLED_TAG_PREFIX}{id}"))
+}
+
+/// Import from a file or directory into the database, tagging the collection with `tag`.
 ///
 /// The returned tag always refers to a collection. If the input is a file, this
 /// is a collection with a single blob, named like the file.
 ///
 /// If the input is a directory, the collection contains all the files in the
-/// directory.
+/// directory. If any file fails to import, all files are untagged & the error is returned.
 async fn import(
-    db: &iroh::client::blobs::Client,
+    router: &RouterClient,
     path: PathBuf,
+    tag: BlobTag,
 ) -> anyhow::Result<(Hash, u64, Collection)> {
+    let db = router.blobs();
     let root = path.clone();
     // walkdir also works for files, so we don't need to special case them
     let files = ignore::WalkBuilder::new(path.clone())
@@ -431,7 +1234,17 @@ async fn import(
         })
         .buffered_unordered(num_cpus::get())
         .collect::<Vec<_>>()
-        .await
+        .await;
+
+    // don't short circuit on failure: every successful import holds a tag we need to remove
+    if let Some(pos) = names_and_tags.iter().position(|res| res.is_err()) {
+        let err = names_and_tags.swap_remove(pos).unwrap_err();
+        for (_, result) in names_and_tags.into_iter().flatten() {
+            router.tags().delete(result.tag).await.ok();
+        }
+        return Err(err);
+    }
+    let mut names_and_tags = names_and_tags
         .into_iter()
         .collect::<anyhow::Result<Vec<_>>>()?;
 
@@ -448,8 +1261,397 @@ async fn import(
         .map(|(name, result)| ((name, result.hash), result.tag))
         .unzip::<_, _, Collection, Vec<_>>();
     let (hash, _tag) = db
-        .create_collection(collection.clone(), SetTagOption::Auto, tags_to_delete)
+        .create_collection(collection.clone(), SetTagOption::Named(tag), tags_to_delete)
         .await?;
 
     Ok((hash, size, collection))
 }
+
+#[cfg(test)]
+mod tests {
+    use iroh::base::ticket::Ticket;
+    use iroh::blobs::BlobFormat;
+
+    use super::*;
+
+    async fn tag_names(router: &RouterClient) -> Result<Vec<String>> {
+        let mut tags = router.tags().list().await?;
+        let mut names = Vec::new();
+        while let Some(tag) = tags.next().await {
+            names.push(String::from_utf8_lossy(&tag?.name.0).into_owned());
+        }
+        Ok(names)
+    }
+
+    async fn program_dir() -> Result<tempfile::TempDir> {
+        let dir = tempfile::tempdir().context("tempdir")?;
+        tokio::fs::write(
+            dir.path().join(MANIFEST_FILENAME),
+            r#"{"name":"test","version":"0.0.1"}"#,
+        )
+        .await?;
+        tokio::fs::write(dir.path().join(DEFAULT_PROGRAM_ENTRY_FILENAME), b"wasm").await?;
+        Ok(dir)
+    }
+
+    #[test]
+    fn capabilities_are_normalized() -> Result<()> {
+        let manifest: Manifest = serde_json::from_value(serde_json::json!({
+            "name": "stars",
+            "version": "0.1.0",
+            "permissions": {
+                "network": ["https://API.github.com/repos?page=1", "api.github.com"],
+                "tables": [" stars "],
+                "secrets": ["GITHUB_TOKEN"],
+            },
+            "config": {
+                "environment": [
+                    { "key": "GITHUB_TOKEN", "description": "a read token", "required": false },
+                ],
+            },
+        }))?;
+        let caps = manifest
+            .capabilities()
+            .into_iter()
+            .map(|cap| (cap.kind, cap.target, cap.required))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            caps,
+            vec![
+                (CapabilityKind::Network, "api.github.com".to_string(), true),
+                (CapabilityKind::WriteTable, "stars".to_string(), true),
+                // the environment variable's description & optionality win
+                (CapabilityKind::Secret, "GITHUB_TOKEN".to_string(), false),
+            ]
+        );
+        let secret = manifest.capabilities().pop().expect("secret");
+        assert_eq!(secret.description, "Read secret GITHUB_TOKEN: a read token");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn install_commits_tag() -> Result<()> {
+        let node = iroh::node::Node::memory().spawn().await?;
+        let dir = program_dir().await?;
+        let id = Uuid::new_v4();
+
+        let path = dir.path().canonicalize()?;
+        install(node.client(), id, path, |_, _| async { Ok(()) }).await?;
+
+        assert_eq!(
+            tag_names(node.client()).await?,
+            vec![format!("{INSTALLED_TAG_PREFIX}{id}")]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn install_rolls_back_failed_commit() -> Result<()> {
+        let node = iroh::node::Node::memory().spawn().await?;
+        let dir = program_dir().await?;
+
+        let path = dir.path().canonicalize()?;
+        let res = install(node.client(), Uuid::new_v4(), path, |_, _| async {
+            Err::<(), _>(anyhow!("event write failed"))
+        })
+        .await;
+
+        assert!(res.is_err());
+        assert!(tag_names(node.client()).await?.is_empty());
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn install_rolls_back_failed_import() -> Result<()> {
+        let node = iroh::node::Node::memory().spawn().await?;
+        let dir = program_dir().await?;
+        // a dangling symlink fails to import after the other files have been added
+        std::os::unix::fs::symlink(dir.path().join("missing"), dir.path().join("broken"))?;
+
+        let path = dir.path().canonicalize()?;
+        let res = install(node.client(), Uuid::new_v4(), path, |_, _| async { Ok(()) }).await;
+
+        assert!(res.is_err());
+        assert!(tag_names(node.client()).await?.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn usage_totals_runs() -> Result<()> {
+        let node = iroh::node::Node::memory().spawn().await?;
+        let dir = tempfile::tempdir().context("tempdir")?;
+        let space = Space::open(
+            Uuid::new_v4(),
+            "usage".to_string(),
+            iroh::docs::NamespaceSecret::new(&mut rand::thread_rng()),
+            node.client().clone(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            dir.path(),
+        )
+        .await?;
+        let programs = space.programs();
+        let program_id = Uuid::new_v4();
+        let now = chrono::Utc::now().timestamp();
+
+        let run = RunRecord {
+            id: Uuid::new_v4(),
+            program_id,
+            scope: Uuid::new_v4(),
+            started_at: now,
+            succeeded: true,
+            wall_time_ms: 1_000,
+            cpu_time_ms: None,
+            peak_memory_bytes: None,
+            artifact_bytes: 10,
+            egress: vec![EgressRecord {
+                destination: "api.github.com:443".to_string(),
+                requests: 3,
+                denied: 1,
+                bytes_sent: 100,
+                bytes_received: 2_000,
+            }],
+        };
+        programs.record_run(&run).await?;
+        programs
+            .record_run(&RunRecord {
+                id: Uuid::new_v4(),
+                scope: Uuid::new_v4(),
+                started_at: now - 2 * 24 * 60 * 60,
+                succeeded: false,
+                cpu_time_ms: Some(200),
+                peak_memory_bytes: Some(4096),
+                ..run.clone()
+            })
+            .await?;
+
+        let day = programs.usage(program_id, UsagePeriod::Day).await?;
+        assert_eq!((day.runs, day.failed_runs, day.wall_time_ms), (1, 0, 1_000));
+        assert_eq!((day.artifact_bytes, day.cpu_time_ms), (10, None));
+        assert_eq!(day.unmetered_runs, 1);
+
+        let all = programs.usage(program_id, UsagePeriod::All).await?;
+        assert_eq!((all.runs, all.failed_runs, all.wall_time_ms), (2, 1, 2_000));
+        assert_eq!(
+            (all.cpu_time_ms, all.peak_memory_bytes),
+            (Some(200), Some(4096))
+        );
+        assert_eq!(all.unmetered_runs, 1);
+        assert_eq!(all.artifact_bytes, 20);
+
+        let other = programs.usage(Uuid::new_v4(), UsagePeriod::All).await?;
+        assert_eq!(other.runs, 0);
+
+        let scopes = programs.recent_run_scopes(program_id, 10).await?;
+        assert_eq!(scopes.first(), Some(&run.scope));
+        assert_eq!(scopes.len(), 2);
+
+        assert_eq!(programs.run_egress(run.id).await?, run.egress);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn mismatched_dependency_isnt_installed() -> Result<()> {
+        let node = iroh::node::Node::memory().enable_docs().spawn().await?;
+        let open = |name: &str| {
+            Space::open_in_memory(
+                Uuid::new_v4(),
+                name.to_string(),
+                iroh::docs::NamespaceSecret::new(&mut rand::thread_rng()),
+                node.client().clone(),
+                Default::default(),
+                Default::default(),
+                Default::default(),
+            )
+        };
+        let (origin, space) = (open("origin").await?, open("deps").await?);
+        let author = node.client().authors().default().await?;
+        let author = node.client().authors().export(author).await?.unwrap();
+        let dir = program_dir().await?;
+        let program = origin.programs().create(author, dir.path()).await?;
+        let ticket = origin.programs().share(node.client(), program.id).await?;
+
+        let dependency = ProgramDependency {
+            name: "test".to_string(),
+            version: Some(">=1.0.0".to_string()),
+            hash: None,
+            ticket: Some(ticket),
+        };
+        let err = space
+            .programs()
+            .resolve_dependency(&dependency)
+            .await
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("ticket is for test 0.0.1"),
+            "{err}"
+        );
+        assert!(space.programs().list(0, -1).await?.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn keyed_runs_expire() -> Result<()> {
+        let node = iroh::node::Node::memory().spawn().await?;
+        let space = Space::open_in_memory(
+            Uuid::new_v4(),
+            "keys".to_string(),
+            iroh::docs::NamespaceSecret::new(&mut rand::thread_rng()),
+            node.client().clone(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+        )
+        .await?;
+        let programs = space.programs();
+        let program_id = Uuid::new_v4();
+        let now = chrono::Utc::now().timestamp();
+        let output = TaskOutput {
+            name: "hook".to_string(),
+            id: Uuid::new_v4(),
+            result: Default::default(),
+            ..Default::default()
+        };
+
+        assert_eq!(programs.keyed_run(program_id, "a", now - 60).await?, None);
+        programs
+            .record_keyed_run(program_id, "a", &output, now - 60)
+            .await?;
+        assert_eq!(
+            programs.keyed_run(program_id, "a", now - 60).await?,
+            Some(output.clone())
+        );
+        assert_eq!(programs.keyed_run(program_id, "b", now - 60).await?, None);
+        assert_eq!(
+            programs.keyed_run(Uuid::new_v4(), "a", now - 60).await?,
+            None
+        );
+        // outside the window
+        assert_eq!(programs.keyed_run(program_id, "a", now + 60).await?, None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn publish_and_unpublish() -> Result<()> {
+        let node = iroh::node::Node::memory().spawn().await?;
+        let space = Space::open_in_memory(
+            Uuid::new_v4(),
+            "publish".to_string(),
+            iroh::docs::NamespaceSecret::new(&mut rand::thread_rng()),
+            node.client().clone(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+        )
+        .await?;
+        let programs = space.programs();
+        let author = Author::new(&mut rand::thread_rng());
+        let dir = program_dir().await?;
+        let headless = programs.create(author.clone(), dir.path()).await?;
+        assert!(programs.publish(headless.id, "http://gw").await.is_err());
+
+        tokio::fs::write(dir.path().join(HTML_INDEX_FILENAME), b"<html></html>").await?;
+        let program = programs.create(author, dir.path()).await?;
+        let published = programs.publish(program.id, "https://gw.example/").await?;
+        assert_eq!(published.name, "test");
+        assert_eq!(
+            published.url,
+            format!("https://gw.example/published/{}/test", space.id)
+        );
+        assert_eq!(published.hash, program.content.hash);
+        assert_eq!(
+            programs.publication_by_name("test").await?,
+            Some(published.clone())
+        );
+        let tag = format!("{PUBLISHED_TAG_PREFIX}{}", program.id);
+        assert!(tag_names(node.client()).await?.contains(&tag));
+
+        // republishing keeps the name
+        let republished = programs.publish(program.id, "https://gw.example").await?;
+        assert_eq!(republished.url, published.url);
+
+        programs.unpublish(program.id).await?;
+        assert_eq!(programs.publication(program.id).await?, None);
+        assert!(!tag_names(node.client()).await?.contains(&tag));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn import_shared_programs() -> Result<()> {
+        let mut spaces = Vec::new();
+        let mut nodes = Vec::new();
+        for name in ["sharer", "importer"] {
+            let node = iroh::node::Node::memory().spawn().await?;
+            let space = Space::open_in_memory(
+                Uuid::new_v4(),
+                name.to_string(),
+                iroh::docs::NamespaceSecret::new(&mut rand::thread_rng()),
+                node.client().clone(),
+                Default::default(),
+                Default::default(),
+                Default::default(),
+            )
+            .await?;
+            spaces.push(space);
+            nodes.push(node);
+        }
+        let author = Author::new(&mut rand::thread_rng());
+        let dir = program_dir().await?;
+        let program = spaces[0]
+            .programs()
+            .create(author.clone(), dir.path())
+            .await?;
+        let shared = spaces[0]
+            .programs()
+            .share(nodes[0].client(), program.id)
+            .await?;
+        // dial the sharer directly, there's no discovery between in-memory nodes
+        let addr = nodes[0].net().node_addr().await?;
+        let ticket = ProgramTicket::new(addr.clone(), shared.hash(), BlobFormat::HashSeq)?;
+
+        let programs = spaces[1].programs();
+        let imported = programs
+            .import(author.clone(), &format!(" {}\n", ticket.serialize()))
+            .await?;
+        assert_eq!(imported.id, program.id);
+        assert_eq!(imported.manifest.name, "test");
+        assert_eq!(programs.get_by_id(program.id).await?.id, program.id);
+
+        let raw = ProgramTicket::new(addr, shared.hash(), BlobFormat::Raw)?;
+        for ticket in ["not a ticket".to_string(), raw.serialize()] {
+            let err = programs.import(author.clone(), &ticket).await.unwrap_err();
+            assert!(
+                matches!(Error::from(err), Error::Invalid { .. }),
+                "{ticket} is refused"
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn publication_names() {
+        assert_eq!(publication_name("Sales Dashboard!"), "sales-dashboard");
+        assert_eq!(publication_name("  --a__b--  "), "a-b");
+        assert_eq!(publication_name("日本"), "program");
+    }
+
+    #[test]
+    fn dependency_versions() {
+        assert!(version_matches("0.2.0", "0.2.0"));
+        assert!(!version_matches("0.2.0", "0.2.1"));
+        assert!(version_matches(">=0.2.0", "0.10.0"));
+        assert!(!version_matches(">= 0.2.0", "0.1.9"));
+        assert!(version_matches("<1.0.0", "0.9.9"));
+        assert!(!version_matches(">=0.2.0", "not a version"));
+
+        let manifest: Manifest = serde_json::from_str(
+            r#"{"name":"report","version":"1.0.0","dependencies":[{"name":"charts","version":">=0.3"}]}"#,
+        )
+        .unwrap();
+        let dependencies = manifest.dependencies.unwrap();
+        assert_eq!(dependencies[0].to_string(), "charts >=0.3");
+        assert!(dependencies[0].ticket.is_none());
+    }
+}
@@ -0,0 +1,290 @@
+//! Getting what's left out of a space database the node won't open.
+//!
+//! Spaces whose database fails sqlite's integrity check, or was set up by a newer version of
+//! the node, are left unopened, see [`Spaces::unreadable`]. [`Recovery`] opens such a
+//! database read-only and reads events one at a time, so a damaged page only costs the rows
+//! on it. Events that read & pass the checks of [`validation::check_event`] are salvageable,
+//! and can be exported into a fresh space, where they're validated in full as they're ingested.
+//! Sensitive fields stay sealed with the old space's secret.
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use iroh::docs::Author;
+use rusqlite::{Connection, OpenFlags};
+use serde::Serialize;
+use tokio::sync::Mutex;
+
+use crate::router::RouterClient;
+
+use super::db::{integrity_problems, DB, SCHEMA_VERSION};
+use super::events::{signed_read_fields, Event, EventKind};
+use super::restore::{EventSummary, InvalidEvent};
+use super::{validation, Space, Spaces};
+
+/// A space that failed to open along with the rest of the node.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+#[serde(rename_all = "camelCase")]
+pub struct UnreadableSpace {
+    pub id: uuid::Uuid,
+    pub name: String,
+    pub error: String,
+}
+
+/// What can be read from a damaged database.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+#[serde(rename_all = "camelCase")]
+pub struct Salvage {
+    /// why the node won't open the database
+    pub problems: Vec<String>,
+    /// events that read & pass the checks that need nothing but the event
+    pub events: Vec<EventSummary>,
+    /// events that read, but not as valid signed events
+    pub invalid: Vec<InvalidEvent>,
+    /// row ids that couldn't be read at all
+    pub skipped: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+#[serde(rename_all = "camelCase")]
+pub struct RecoveryReport {
+    pub space_id: uuid::Uuid,
+    /// events written to the new space
+    pub exported: u64,
+    pub invalid: u64,
+    pub skipped: u64,
+}
+
+/// A space database opened read-only.
+#[derive(Debug, Clone)]
+pub struct Recovery {
+    path: PathBuf,
+    db: DB,
+}
+
+impl Recovery {
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        anyhow::ensure!(path.exists(), "no database at {}", path.display());
+        let conn = Connection::open_with_flags(
+            &path,
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )
+        .with_context(|| format!("opening {} read-only", path.display()))?;
+        Ok(Recovery {
+            path,
+            db: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// List what can be salvaged, without changing anything.
+    pub async fn salvage(&self) -> Result<Salvage> {
+        let (salvage, _) = self.read().await?;
+        Ok(salvage)
+    }
+
+    /// Create a space named `name` holding every salvageable event. Events that describe the
+    /// old space itself are left out, the new space gets its own details.
+    pub async fn export(
+        &self,
+        spaces: &mut Spaces,
+        router: &RouterClient,
+        author: Author,
+        name: &str,
+    ) -> Result<(Space, RecoveryReport)> {
+        let (salvage, events) = self.read().await?;
+        let description = format!("recovered from {}", self.path.display());
+        let space = spaces.create(router, author, name, &description).await?;
+        let mut report = RecoveryReport {
+            space_id: space.id,
+            invalid: salvage.invalid.len() as u64,
+            skipped: salvage.skipped,
+            ..Default::default()
+        };
+        for event in events {
+            if event.kind == EventKind::MutateSpace {
+                continue;
+            }
+            if let Err(err) = space.ingest_event(&event).await {
+                tracing::warn!("not exporting event {}: {:#}", event.id, err);
+                report.invalid += 1;
+                continue;
+            }
+            if let Err(err) = space.outbox().send(&event).await {
+                tracing::warn!("queueing event {} for the feed: {:?}", event.id, err);
+            }
+            report.exported += 1;
+        }
+        Ok((space, report))
+    }
+
+    /// Read events by row id, skipping over the ones that can't be read.
+    async fn read(&self) -> Result<(Salvage, Vec<Event>)> {
+        let conn = self.db.lock().await;
+        let mut salvage = Salvage {
+            problems: problems(&conn),
+            ..Default::default()
+        };
+        let last: Option<i64> = conn
+            .query_row("SELECT MAX(rowid) FROM events", [], |row| row.get(0))
+            .context("the events table is unreadable")?;
+        let Some(last) = last else {
+            return Ok((salvage, Vec::new()));
+        };
+
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {}, rowid FROM events WHERE rowid > ?1 ORDER BY rowid LIMIT 1",
+            signed_read_fields(&conn)?
+        ))?;
+        let mut events = Vec::new();
+        let mut cursor = i64::MIN;
+        while cursor < last {
+            let next = (|| -> Result<Option<(i64, Result<Event>)>> {
+                let mut rows = stmt.query([cursor])?;
+                let Some(row) = rows.next()? else {
+                    return Ok(None);
+                };
+                let rowid: i64 = row.get("rowid")?;
+                Ok(Some((rowid, Event::from_signed_sql_row(row))))
+            })();
+            match next {
+                Ok(None) => break,
+                Ok(Some((rowid, event))) => {
+                    cursor = rowid;
+                    let event =
+                        event.and_then(|event| validation::check_event(&event).map(|_| event));
+                    match event {
+                        Ok(event) => {
+                            salvage.events.push(EventSummary::new(&event)?);
+                            events.push(event);
+                        }
+                        Err(err) => salvage.invalid.push(InvalidEvent {
+                            id: format!("row {rowid}"),
+                            error: format!("{err:#}"),
+                        }),
+                    }
+                }
+                Err(_) => {
+                    // nothing past a damaged page can be seeked from this row id, try the next
+                    cursor = cursor.max(0) + 1;
+                    salvage.skipped += 1;
+                }
+            }
+        }
+        Ok((salvage, events))
+    }
+}
+
+/// Why the node refuses the database, like [`super::db::check_db`] but reporting everything.
+fn problems(conn: &Connection) -> Vec<String> {
+    let mut problems = Vec::new();
+    match conn.query_row("PRAGMA user_version", [], |row| row.get::<_, i32>(0)) {
+        Ok(version) if version > SCHEMA_VERSION => problems.push(format!(
+            "database is from a newer version (schema {version}, this node reads up to {SCHEMA_VERSION})"
+        )),
+        Ok(_) => {}
+        Err(err) => problems.push(format!("reading the schema version: {err}")),
+    }
+    match integrity_problems(conn) {
+        Ok(found) => problems.extend(found),
+        Err(err) => problems.push(format!("checking integrity: {err:#}")),
+    }
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use iroh::blobs::Hash;
+
+    use super::*;
+    use crate::space::clock::Hlc;
+    use crate::space::db::{open_db, setup_db};
+    use crate::space::events::{HashLink, Tag, NOSTR_ID_TAG, NOSTR_SCHEMA_TAG};
+
+    #[tokio::test]
+    async fn recover_newer_space() -> Result<()> {
+        let node = iroh::node::Node::memory().spawn().await?;
+        let dir = tempfile::tempdir()?;
+        let mut spaces = Spaces::open_all(node.client().clone(), dir.path()).await?;
+        let author = Author::new(&mut rand::thread_rng());
+        let space = spaces
+            .create(node.client(), author.clone(), "future", "")
+            .await?;
+        let schema = serde_json::json!({ "title": "notes", "type": "object" });
+        let table = space
+            .tables()
+            .create(author.clone(), schema.to_string().into())
+            .await?;
+        let row = Event::create(
+            author.clone(),
+            1,
+            Hlc::from_created_at(1),
+            EventKind::MutateRow,
+            vec![
+                Tag::new(NOSTR_SCHEMA_TAG, &table.content.hash.to_string()),
+                Tag::new(NOSTR_ID_TAG, &uuid::Uuid::new_v4().to_string()),
+            ],
+            HashLink {
+                hash: Hash::new("row"),
+                data: Some(serde_json::json!({ "a": 1 })),
+            },
+        )?;
+        space.write_event(&row).await?;
+        space
+            .db()
+            .lock()
+            .await
+            .pragma_update(None, "user_version", SCHEMA_VERSION + 1)?;
+        drop(space);
+        drop(spaces);
+
+        let mut spaces = Spaces::open_all(node.client().clone(), dir.path()).await?;
+        assert!(spaces.get_by_name("future").await.is_none());
+        let unreadable = spaces.unreadable().await;
+        assert_eq!(unreadable.len(), 1);
+        assert!(
+            unreadable[0].error.contains("newer version"),
+            "{unreadable:?}"
+        );
+
+        let recovery = spaces.recovery("future")?;
+        let salvage = recovery.salvage().await?;
+        assert_eq!(salvage.problems.len(), 1);
+        let row_id = row.id.to_string();
+        assert!(salvage.events.iter().any(|e| e.id.to_string() == row_id));
+        assert!(salvage.invalid.is_empty());
+        assert_eq!(salvage.skipped, 0);
+
+        let (recovered, report) = recovery
+            .export(&mut spaces, node.client(), author, "recovered")
+            .await?;
+        // everything but the old space's details
+        assert_eq!(report.exported as usize, salvage.events.len() - 1);
+        assert_eq!(report.invalid, 0);
+        let conn = recovered.db().lock().await;
+        let found: i64 =
+            conn.query_row("SELECT COUNT(*) FROM events WHERE id = ?1", [row_id], |r| {
+                r.get(0)
+            })?;
+        assert_eq!(found, 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn current_databases_open() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("ok.db");
+        let db = open_db(&path).await?;
+        setup_db(&db).await?;
+        crate::space::db::check_db(&*db.lock().await)?;
+        assert!(Recovery::open(&path)?.salvage().await?.problems.is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,173 @@
+//! Structured output programs emit for the app to display.
+//!
+//! A program that wants its result shown as more than text prints a JSON object with a
+//! `render` key, either as its whole output or as the last line of it:
+//!
+//! ```json
+//! {"render": {"type": "table", "columns": ["city", "visits"], "rows": [["Oslo", 3]]}}
+//! ```
+//!
+//! The render is checked & stored with the run, see [`crate::space::runs`], so any program's
+//! results can be displayed without a frontend of its own.
+use anyhow::{ensure, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::vm::flow::TaskOutput;
+use crate::vm::job::{JobOutput, JobResultStatus};
+
+/// largest render a run may carry, serialized
+pub const MAX_RENDER_BYTES: usize = 256 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum Render {
+    /// rows of cells, one per column
+    Table {
+        columns: Vec<String>,
+        rows: Vec<Vec<Value>>,
+    },
+    /// series of values, one per label
+    Chart {
+        chart: ChartKind,
+        labels: Vec<String>,
+        series: Vec<Series>,
+    },
+    Markdown {
+        text: String,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+#[serde(rename_all = "camelCase")]
+pub enum ChartKind {
+    Line,
+    Bar,
+    Pie,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+pub struct Series {
+    pub name: String,
+    pub values: Vec<f64>,
+}
+
+#[derive(Deserialize)]
+struct Envelope {
+    render: Render,
+}
+
+impl Render {
+    /// The render a task printed, none if its output doesn't carry one. Errors for renders
+    /// that don't hold together.
+    pub fn from_output(output: &TaskOutput) -> Result<Option<Render>> {
+        let text = match &output.result.status {
+            JobResultStatus::Ok(JobOutput::Wasm { output }) => output.as_str(),
+            JobResultStatus::Ok(JobOutput::Docker { stdout, .. }) => stdout.as_str(),
+            _ => return Ok(None),
+        };
+        let Some(render) = parse(text) else {
+            return Ok(None);
+        };
+        render.validate()?;
+        Ok(Some(render))
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        match self {
+            Render::Table { columns, rows } => {
+                for (i, row) in rows.iter().enumerate() {
+                    ensure!(
+                        row.len() == columns.len(),
+                        "table row {i} has {} cells for {} columns",
+                        row.len(),
+                        columns.len()
+                    );
+                }
+            }
+            Render::Chart { labels, series, .. } => {
+                for series in series {
+                    ensure!(
+                        series.values.len() == labels.len(),
+                        "chart series {} has {} values for {} labels",
+                        series.name,
+                        series.values.len(),
+                        labels.len()
+                    );
+                }
+            }
+            Render::Markdown { .. } => {}
+        }
+        let size = serde_json::to_vec(self)?.len();
+        ensure!(
+            size <= MAX_RENDER_BYTES,
+            "render is {size} bytes, more than the {MAX_RENDER_BYTES} allowed"
+        );
+        Ok(())
+    }
+}
+
+fn parse(text: &str) -> Option<Render> {
+    let text = text.trim();
+    if let Ok(envelope) = serde_json::from_str::<Envelope>(text) {
+        return Some(envelope.render);
+    }
+    let last = text.lines().last()?;
+    serde_json::from_str::<Envelope>(last)
+        .ok()
+        .map(|envelope| envelope.render)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::vm::job::JobResult;
+
+    fn printed(output: &str) -> TaskOutput {
+        TaskOutput {
+            result: JobResult {
+                status: JobResultStatus::Ok(JobOutput::Docker {
+                    code: 0,
+                    stderr: String::new(),
+                    stdout: output.to_string(),
+                }),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn renders_from_output() -> Result<()> {
+        let table = json!({"render": {"type": "table", "columns": ["a", "b"], "rows": [[1, "x"]]}});
+        assert_eq!(
+            Render::from_output(&printed(&table.to_string()))?,
+            Some(Render::Table {
+                columns: vec!["a".to_string(), "b".to_string()],
+                rows: vec![vec![json!(1), json!("x")]],
+            })
+        );
+
+        // logs before the render are fine
+        let markdown = json!({"render": {"type": "markdown", "text": "# hi"}});
+        let output = format!("fetching\ndone\n{markdown}\n");
+        assert_eq!(
+            Render::from_output(&printed(&output))?,
+            Some(Render::Markdown {
+                text: "# hi".to_string()
+            })
+        );
+
+        assert_eq!(Render::from_output(&printed("just text"))?, None);
+        assert_eq!(Render::from_output(&printed(r#"{"other": 1}"#))?, None);
+
+        let ragged = json!({"render": {"type": "chart", "chart": "bar", "labels": ["a", "b"], "series": [{"name": "n", "values": [1.0]}]}});
+        assert!(Render::from_output(&printed(&ragged.to_string())).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,390 @@
+//! Restoring a space from a backup of its database.
+//!
+//! A backup is a copy of the space's sqlite db. Restoring it over a space that kept changing
+//! after the backup was taken can lose work, so [`Restore::preview`] diffs the backup against
+//! the live db first: events only in the backup, events only here, and objects both sides
+//! changed since they diverged. Every backup event is validated like an event from another
+//! node, events that don't validate are reported & never restored.
+//!
+//! [`RestoreMode::Merge`] adds the backup's events to the space through
+//! [`Space::ingest_event`], so table policies apply, and the latest write to an object by its
+//! clock wins as it always does. [`RestoreMode::Replace`] also drops the events the backup
+//! doesn't have. Dropping only affects this node: peers that already synced those events keep
+//! them. Events are restored without their content blobs, content the backup's events link to
+//! has to be fetched separately.
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use rusqlite::params;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::clock::Hlc;
+use super::db::open_db;
+use super::events::{signed_read_fields, Event, EventKind, Sha256Digest};
+use super::{validation, Space};
+
+/// What to do with events that are only in the live db.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+#[serde(rename_all = "camelCase")]
+pub enum RestoreMode {
+    /// keep them, the newest write to each object wins
+    Merge,
+    /// drop them, the space ends up as it was in the backup
+    Replace,
+}
+
+/// An event in a restore preview.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+#[serde(rename_all = "camelCase")]
+pub struct EventSummary {
+    pub id: Sha256Digest,
+    pub kind: EventKind,
+    /// the object the event writes
+    pub data_id: Uuid,
+    pub created_at: i64,
+    pub author: String,
+}
+
+impl EventSummary {
+    pub(super) fn new(event: &Event) -> Result<Self> {
+        Ok(EventSummary {
+            id: event.id.clone(),
+            kind: event.kind,
+            data_id: event.data_id()?.context("event has no data id")?,
+            created_at: event.created_at,
+            author: event.pubkey.to_string(),
+        })
+    }
+}
+
+/// An object written on both sides since the backup & the live db diverged.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+#[serde(rename_all = "camelCase")]
+pub struct RestoreConflict {
+    pub data_id: Uuid,
+    pub archive: Vec<EventSummary>,
+    pub local: Vec<EventSummary>,
+    /// whether the backup's version is the newest, and so the one a merge keeps
+    pub archive_wins: bool,
+}
+
+/// A backup event that failed its integrity check.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+#[serde(rename_all = "camelCase")]
+pub struct InvalidEvent {
+    pub id: String,
+    pub error: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+#[serde(rename_all = "camelCase")]
+pub struct RestorePreview {
+    /// events the backup would add, outside of conflicts
+    pub archive_only: Vec<EventSummary>,
+    /// events a replace would drop, outside of conflicts
+    pub local_only: Vec<EventSummary>,
+    pub conflicts: Vec<RestoreConflict>,
+    /// events on both sides
+    pub shared: u64,
+    pub invalid: Vec<InvalidEvent>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+#[serde(rename_all = "camelCase")]
+pub struct RestoreReport {
+    /// events written from the backup
+    pub restored: u64,
+    /// local events dropped, replace only
+    pub dropped: u64,
+    /// backup events that failed validation or their table's policy
+    pub invalid: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Restore(Space);
+
+impl Restore {
+    pub fn new(space: Space) -> Self {
+        Restore(space)
+    }
+
+    /// Compare the backup at `archive` with the space, without changing anything.
+    pub async fn preview(&self, archive: impl AsRef<Path>) -> Result<RestorePreview> {
+        let diff = self.diff(archive.as_ref()).await?;
+        let mut preview = RestorePreview {
+            shared: diff.shared,
+            invalid: diff.invalid,
+            ..Default::default()
+        };
+        let conflicted: HashSet<Uuid> = diff
+            .archive_only
+            .iter()
+            .filter_map(|event| event.data_id().ok().flatten())
+            .filter(|id| diff.local_only_ids.contains(id))
+            .collect();
+        let mut conflicts: BTreeMap<Uuid, RestoreConflict> = BTreeMap::new();
+        // the latest clock reading of each side of a conflict
+        let mut latest: HashMap<(Uuid, bool), Hlc> = HashMap::new();
+        for (archive, events) in [(true, &diff.archive_only), (false, &diff.local_only)] {
+            for event in events {
+                let summary = EventSummary::new(event)?;
+                if !conflicted.contains(&summary.data_id) {
+                    match archive {
+                        true => preview.archive_only.push(summary),
+                        false => preview.local_only.push(summary),
+                    }
+                    continue;
+                }
+                let conflict =
+                    conflicts
+                        .entry(summary.data_id)
+                        .or_insert_with(|| RestoreConflict {
+                            data_id: summary.data_id,
+                            archive: Vec::new(),
+                            local: Vec::new(),
+                            archive_wins: false,
+                        });
+                let hlc = event.hlc()?;
+                latest
+                    .entry((summary.data_id, archive))
+                    .and_modify(|latest| *latest = (*latest).max(hlc))
+                    .or_insert(hlc);
+                match archive {
+                    true => conflict.archive.push(summary),
+                    false => conflict.local.push(summary),
+                }
+            }
+        }
+        for mut conflict in conflicts.into_values() {
+            let newest = |archive| latest.get(&(conflict.data_id, archive)).copied();
+            conflict.archive_wins = newest(true) > newest(false);
+            preview.conflicts.push(conflict);
+        }
+        Ok(preview)
+    }
+
+    /// Restore the backup at `archive` into the space.
+    pub async fn apply(
+        &self,
+        archive: impl AsRef<Path>,
+        mode: RestoreMode,
+    ) -> Result<RestoreReport> {
+        let diff = self.diff(archive.as_ref()).await?;
+        let mut report = RestoreReport {
+            invalid: diff.invalid.len() as u64,
+            ..Default::default()
+        };
+        if mode == RestoreMode::Replace {
+            let conn = self.0.db.lock().await;
+            for event in diff.local_only.iter() {
+                let id = event.id.to_string();
+                conn.execute("DELETE FROM events WHERE id = ?1", params![id])?;
+                conn.execute("DELETE FROM outbox WHERE event_id = ?1", params![id])?;
+                report.dropped += 1;
+            }
+        }
+        for event in diff.archive_only.iter() {
+            // restored events were already counted toward their author's rate when written
+            if let Err(err) = self.0.ingest_event(event).await {
+                tracing::warn!("not restoring event {}: {:#}", event.id, err);
+                report.invalid += 1;
+                continue;
+            }
+            if let Err(err) = self.0.outbox().send(event).await {
+                tracing::warn!("queueing event {} for the feed: {:?}", event.id, err);
+            }
+            report.restored += 1;
+        }
+        if report.dropped > 0 {
+            self.0.row_writes.send_modify(|writes| *writes += 1);
+        }
+        Ok(report)
+    }
+
+    async fn diff(&self, archive: &Path) -> Result<Diff> {
+        anyhow::ensure!(archive.exists(), "no backup at {}", archive.display());
+        let archive_db = open_db(archive).await?;
+        let archived = read_events(&archive_db)
+            .await
+            .with_context(|| format!("reading backup {}", archive.display()))?;
+        let local = read_events(&self.0.db).await?;
+
+        let mut diff = Diff::default();
+        let mut archive_ids = HashSet::new();
+        for event in archived {
+            // tables the backup's rows name are resolved from the backup
+            if let Err(err) = validation::validate(&archive_db, &self.0.router, &event).await {
+                diff.invalid.push(InvalidEvent {
+                    id: event.id.to_string(),
+                    error: format!("{err:#}"),
+                });
+                continue;
+            }
+            if event.kind == EventKind::MutateSpace && event.data_id()? != Some(self.0.id) {
+                return Err(anyhow!("the backup is of a different space"));
+            }
+            archive_ids.insert(event.id.to_string());
+            diff.archive_only.push(event);
+        }
+
+        let mut local_ids = HashSet::new();
+        for event in local {
+            let id = event.id.to_string();
+            if archive_ids.contains(&id) {
+                diff.shared += 1;
+            } else {
+                if let Some(data_id) = event.data_id()? {
+                    diff.local_only_ids.insert(data_id);
+                }
+                diff.local_only.push(event);
+            }
+            local_ids.insert(id);
+        }
+        diff.archive_only
+            .retain(|event| !local_ids.contains(&event.id.to_string()));
+        Ok(diff)
+    }
+}
+
+#[derive(Debug, Default)]
+struct Diff {
+    archive_only: Vec<Event>,
+    local_only: Vec<Event>,
+    /// objects written by local only events
+    local_only_ids: HashSet<Uuid>,
+    shared: u64,
+    invalid: Vec<InvalidEvent>,
+}
+
+async fn read_events(db: &super::db::DB) -> Result<Vec<Event>> {
+    let conn = db.lock().await;
+    // backups can be from before event clocks
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM events ORDER BY created_at",
+        signed_read_fields(&conn)?
+    ))?;
+    let mut rows = stmt.query([])?;
+    let mut events = Vec::new();
+    while let Some(row) = rows.next()? {
+        events.push(Event::from_signed_sql_row(row)?);
+    }
+    Ok(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use iroh::blobs::Hash;
+    use iroh::docs::Author;
+
+    use super::*;
+    use crate::space::db::setup_db;
+    use crate::space::events::{HashLink, Tag, NOSTR_ID_TAG, NOSTR_SCHEMA_TAG};
+
+    fn mutate_row(author: &Author, table: Hash, created_at: i64, row: Uuid) -> Result<Event> {
+        Event::create(
+            author.clone(),
+            created_at,
+            Hlc::from_created_at(created_at),
+            EventKind::MutateRow,
+            vec![
+                Tag::new(NOSTR_SCHEMA_TAG, &table.to_string()),
+                Tag::new(NOSTR_ID_TAG, &row.to_string()),
+            ],
+            HashLink {
+                hash: Hash::new(created_at.to_string()),
+                data: Some(serde_json::json!({ "at": created_at })),
+            },
+        )
+    }
+
+    #[tokio::test]
+    async fn preview_and_apply() -> Result<()> {
+        let node = iroh::node::Node::memory().spawn().await?;
+        let space = Space::open_in_memory(
+            Uuid::new_v4(),
+            "restore".to_string(),
+            iroh::docs::NamespaceSecret::new(&mut rand::thread_rng()),
+            node.client().clone(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+        )
+        .await?;
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("backup.db");
+        let backup = open_db(&path).await?;
+        setup_db(&backup).await?;
+
+        let author = Author::new(&mut rand::thread_rng());
+        let schema = serde_json::json!({ "title": "notes", "type": "object" });
+        let table = space
+            .tables()
+            .create(author.clone(), schema.to_string().into())
+            .await?
+            .content
+            .hash;
+        let mutate_row = |created_at, row| mutate_row(&author, table, created_at, row);
+        let (kept, contested, restored, dropped) = (
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+        );
+        // on both sides
+        let shared = mutate_row(1, kept)?;
+        shared.write(&backup).await?;
+        space.write_event(&shared).await?;
+        // the backup changed `contested` last
+        mutate_row(3, contested)?.write(&backup).await?;
+        space.write_event(&mutate_row(2, contested)?).await?;
+        mutate_row(4, restored)?.write(&backup).await?;
+        space.write_event(&mutate_row(5, dropped)?).await?;
+        // signed by someone other than its author
+        let mut forged = mutate_row(6, Uuid::new_v4())?;
+        forged.pubkey = iroh::net::key::SecretKey::generate().public();
+        forged.write(&backup).await?;
+        // altered after it was signed, the id & signature still match each other
+        let mut tampered = mutate_row(7, Uuid::new_v4())?;
+        tampered.content.hash = Hash::new("something else");
+        tampered.write(&backup).await?;
+
+        let preview = space.restore().preview(&path).await?;
+        assert_eq!(preview.shared, 1);
+        let invalid = preview
+            .invalid
+            .iter()
+            .map(|e| e.id.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            invalid,
+            vec![forged.id.to_string(), tampered.id.to_string()]
+        );
+        let ids = |events: &[EventSummary]| events.iter().map(|e| e.data_id).collect::<Vec<_>>();
+        assert_eq!(ids(&preview.archive_only), vec![restored]);
+        assert_eq!(ids(&preview.local_only), vec![dropped]);
+        assert_eq!(preview.conflicts.len(), 1);
+        assert_eq!(preview.conflicts[0].data_id, contested);
+        assert!(preview.conflicts[0].archive_wins);
+
+        let report = space.restore().apply(&path, RestoreMode::Merge).await?;
+        assert_eq!((report.restored, report.dropped, report.invalid), (2, 0, 2));
+        let preview = space.restore().preview(&path).await?;
+        assert!(preview.archive_only.is_empty() && preview.conflicts.is_empty());
+        assert_eq!(preview.local_only.len(), 2);
+
+        let report = space.restore().apply(&path, RestoreMode::Replace).await?;
+        assert_eq!((report.restored, report.dropped), (0, 2));
+        let preview = space.restore().preview(&path).await?;
+        assert!(preview.local_only.is_empty());
+        assert_eq!(preview.shared, 4);
+        Ok(())
+    }
+}
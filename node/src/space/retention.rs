@@ -0,0 +1,403 @@
+//! Per-table retention rules, so sensor-style tables don't grow without bound.
+//!
+//! A policy expires rows that haven't been written for `max_age_days`, and rows beyond the
+//! newest `max_rows`. Expired rows get a tombstone event, archiving first copies their
+//! content into a blob recorded against the table. Policies are enforced on an interval for
+//! as long as they exist, by the node that set them. Expiring a row isn't final: a later write
+//! to it, or restoring a backup from before it expired (see [`super::restore`]), brings it
+//! back, and the next run expires it again if it's still out of policy.
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use iroh::blobs::Hash;
+use iroh::docs::{Author, AuthorId};
+use rusqlite::{params, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use super::events::EventKind;
+use super::rows::not_deleted;
+use super::Space;
+
+const DEFAULT_INTERVAL_SECS: u64 = 60 * 60;
+/// tombstones written in one run, the rest wait for the next run
+const MAX_EXPIRED_PER_RUN: usize = 1000;
+const SECS_PER_DAY: i64 = 60 * 60 * 24;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RetentionPolicy {
+    /// expire rows whose latest write is older than this
+    pub max_age_days: Option<u32>,
+    /// expire the oldest rows beyond this many, ordered by latest write
+    pub max_rows: Option<u64>,
+    #[serde(default)]
+    pub action: RetentionAction,
+    #[serde(default = "default_interval_secs")]
+    pub interval_secs: u64,
+}
+
+fn default_interval_secs() -> u64 {
+    DEFAULT_INTERVAL_SECS
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum RetentionAction {
+    #[default]
+    Delete,
+    /// copy expired rows into a blob before deleting them, see [`Retention::archives`]
+    Archive,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TableRetention {
+    pub table_id: Uuid,
+    pub policy: RetentionPolicy,
+    /// author tombstones are written as
+    pub author: AuthorId,
+    pub last_run_at: Option<i64>,
+    pub last_error: Option<String>,
+}
+
+/// Rows expired in one run, as a JSON array of `{id, schema, updatedAt, content}` objects.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RetentionArchive {
+    pub table_id: Uuid,
+    pub hash: Hash,
+    pub rows: usize,
+    pub created_at: i64,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RetentionReport {
+    pub expired: usize,
+    /// set when expired rows were archived
+    pub archive: Option<Hash>,
+}
+
+/// The latest version of a row, as far as retention cares.
+struct LiveRow {
+    id: Uuid,
+    schema: Hash,
+    content: Hash,
+    updated_at: i64,
+}
+
+#[derive(Clone)]
+pub struct Retention(Space);
+
+impl Retention {
+    pub fn new(space: Space) -> Self {
+        Retention(space)
+    }
+
+    /// Set the retention policy of a table, replacing any existing one, and enforce it now.
+    pub async fn set(
+        &self,
+        author: Author,
+        table_id: Uuid,
+        policy: RetentionPolicy,
+    ) -> Result<RetentionReport> {
+        anyhow::ensure!(
+            policy.max_age_days.is_some() || policy.max_rows.is_some(),
+            "retention policy needs max_age_days or max_rows"
+        );
+        let existed = self.get(table_id).await.is_ok();
+        let conn = self.0.db.lock().await;
+        conn.execute(
+            "INSERT OR REPLACE INTO table_retention (table_id, policy, author, last_run_at, last_error) VALUES (?1, ?2, ?3, NULL, NULL)",
+            params![table_id, serde_json::to_string(&policy)?, author.id().to_string()],
+        )?;
+        drop(conn);
+
+        let report = self.enforce(table_id).await?;
+        if !existed {
+            self.schedule(table_id);
+        }
+        Ok(report)
+    }
+
+    /// Stop enforcing a table's policy. Rows already expired stay deleted.
+    pub async fn remove(&self, table_id: Uuid) -> Result<()> {
+        let conn = self.0.db.lock().await;
+        conn.execute(
+            "DELETE FROM table_retention WHERE table_id = ?1",
+            params![table_id],
+        )?;
+        Ok(())
+    }
+
+    pub async fn get(&self, table_id: Uuid) -> Result<TableRetention> {
+        self.list()
+            .await?
+            .into_iter()
+            .find(|retention| retention.table_id == table_id)
+            .ok_or_else(|| anyhow!("table has no retention policy"))
+    }
+
+    pub async fn list(&self) -> Result<Vec<TableRetention>> {
+        let conn = self.0.db.lock().await;
+        let mut stmt = conn.prepare(
+            "SELECT table_id, policy, author, last_run_at, last_error FROM table_retention",
+        )?;
+        let mut rows = stmt.query([])?;
+        let mut policies = Vec::new();
+        while let Some(row) = rows.next()? {
+            let policy: String = row.get(1)?;
+            let author: String = row.get(2)?;
+            policies.push(TableRetention {
+                table_id: row.get(0)?,
+                policy: serde_json::from_str(&policy)?,
+                author: author.parse()?,
+                last_run_at: row.get(3)?,
+                last_error: row.get(4)?,
+            });
+        }
+        Ok(policies)
+    }
+
+    /// Archives written for a table, newest first.
+    pub async fn archives(&self, table_id: Uuid) -> Result<Vec<RetentionArchive>> {
+        let conn = self.0.db.lock().await;
+        let mut stmt = conn.prepare(
+            "SELECT hash, rows, created_at FROM table_retention_archives WHERE table_id = ?1 ORDER BY created_at DESC",
+        )?;
+        let mut rows = stmt.query(params![table_id])?;
+        let mut archives = Vec::new();
+        while let Some(row) = rows.next()? {
+            let hash: String = row.get(0)?;
+            archives.push(RetentionArchive {
+                table_id,
+                hash: hash.parse()?,
+                rows: row.get(1)?,
+                created_at: row.get(2)?,
+            });
+        }
+        Ok(archives)
+    }
+
+    /// Restart enforcement of every policy, called when a space is opened.
+    pub fn schedule_all(&self) {
+        let this = self.clone();
+        tokio::spawn(async move {
+            match this.list().await {
+                Ok(policies) => {
+                    for retention in policies {
+                        this.schedule(retention.table_id);
+                    }
+                }
+                Err(err) => warn!("failed to list retention policies: {:?}", err),
+            }
+        });
+    }
+
+    /// Enforce a table's policy every `interval_secs` until it's removed.
+    fn schedule(&self, table_id: Uuid) {
+        let this = self.clone();
+        tokio::spawn(async move {
+            loop {
+                let interval = match this.get(table_id).await {
+                    Ok(retention) => retention.policy.interval_secs.max(1),
+                    Err(_) => break,
+                };
+                tokio::time::sleep(Duration::from_secs(interval)).await;
+                if let Err(err) = this.enforce(table_id).await {
+                    warn!("enforcing retention of table {}: {:?}", table_id, err);
+                }
+            }
+            debug!("stopped enforcing retention of table {}", table_id);
+        });
+    }
+
+    /// Expire the rows of a table its policy no longer keeps.
+    pub async fn enforce(&self, table_id: Uuid) -> Result<RetentionReport> {
+        let res = self.enforce_inner(table_id).await;
+        let conn = self.0.db.lock().await;
+        conn.execute(
+            "UPDATE table_retention SET last_run_at = ?2, last_error = ?3 WHERE table_id = ?1",
+            params![
+                table_id,
+                chrono::Utc::now().timestamp(),
+                res.as_ref().err().map(|err| format!("{err:#}")),
+            ],
+        )?;
+        res
+    }
+
+    async fn enforce_inner(&self, table_id: Uuid) -> Result<RetentionReport> {
+        let retention = self.get(table_id).await?;
+        let author = self
+            .0
+            .router
+            .authors()
+            .export(retention.author)
+            .await?
+            .ok_or_else(|| anyhow!("author {} not found", retention.author))?;
+
+        let rows = self.live_rows(table_id).await?;
+        let now = chrono::Utc::now().timestamp();
+        let mut expired = expired(&rows, &retention.policy, now);
+        expired.truncate(MAX_EXPIRED_PER_RUN);
+        let mut report = RetentionReport {
+            expired: expired.len(),
+            archive: None,
+        };
+        if expired.is_empty() {
+            return Ok(report);
+        }
+
+        if retention.policy.action == RetentionAction::Archive {
+            report.archive = Some(self.archive(table_id, &expired).await?);
+        }
+        for row in &expired {
+            self.0
+                .rows()
+                .tombstone(author.clone(), row.schema, row.id, row.content)
+                .await?;
+        }
+        info!("expired {} rows of table {}", expired.len(), table_id);
+        Ok(report)
+    }
+
+    /// The latest version of every row in the table, across all of its schemas, newest first.
+    async fn live_rows(&self, table_id: Uuid) -> Result<Vec<LiveRow>> {
+        let conn = self.0.db.lock().await;
+        let sql = format!(
+            "SELECT data_id, schema_hash, content_hash, created_at AS updated_at, MAX(clock) FROM events
+            WHERE kind = ?1 AND {} AND schema_hash IN (
+                SELECT content_hash FROM events WHERE kind = ?2 AND data_id = ?3
+            )
+            GROUP BY data_id
+            ORDER BY updated_at DESC",
+            not_deleted()
+        );
+        let mut stmt = conn.prepare(&sql).context("preparing retention query")?;
+        let mut rows = stmt.query(params![
+            EventKind::MutateRow,
+            EventKind::MutateTable,
+            table_id
+        ])?;
+        let mut live = Vec::new();
+        while let Some(row) = rows.next()? {
+            let schema: String = row.get(1)?;
+            let content: String = row.get(2)?;
+            live.push(LiveRow {
+                id: row.get(0)?,
+                schema: schema.parse()?,
+                content: content.parse()?,
+                updated_at: row.get(3)?,
+            });
+        }
+        Ok(live)
+    }
+
+    /// Copy rows into a single JSON blob & record it against the table.
+    async fn archive(&self, table_id: Uuid, rows: &[&LiveRow]) -> Result<Hash> {
+        let mut archived = Vec::with_capacity(rows.len());
+        for row in rows {
+            let content = self.row_content(row).await?;
+            archived.push(serde_json::json!({
+                "id": row.id,
+                "schema": row.schema.to_string(),
+                "updatedAt": row.updated_at,
+                "content": content,
+            }));
+        }
+        let data = serde_json::to_vec(&archived)?;
+        let outcome = self.0.router.blobs().add_bytes(data).await?;
+        let conn = self.0.db.lock().await;
+        conn.execute(
+            "INSERT INTO table_retention_archives (table_id, hash, rows, created_at) VALUES (?1, ?2, ?3, ?4)",
+            params![
+                table_id,
+                outcome.hash.to_string(),
+                rows.len(),
+                chrono::Utc::now().timestamp(),
+            ],
+        )?;
+        Ok(outcome.hash)
+    }
+
+    /// Content is usually stored with the event, rows synced without it are read from blobs.
+    async fn row_content(&self, row: &LiveRow) -> Result<Value> {
+        let conn = self.0.db.lock().await;
+        let stored: Option<Vec<u8>> = conn
+            .query_row(
+                "SELECT content FROM events WHERE kind = ?1 AND data_id = ?2 AND content_hash = ?3 LIMIT 1",
+                params![EventKind::MutateRow, row.id, row.content.to_string()],
+                |row| row.get(0),
+            )
+            .optional()?
+            .flatten();
+        drop(conn);
+        let data = match stored {
+            Some(data) => data,
+            None => self
+                .0
+                .router
+                .blobs()
+                .read_to_bytes(row.content)
+                .await?
+                .to_vec(),
+        };
+        Ok(serde_json::from_slice(&data)?)
+    }
+}
+
+/// Rows a policy expires at `now`, given rows ordered newest first.
+fn expired<'a>(rows: &'a [LiveRow], policy: &RetentionPolicy, now: i64) -> Vec<&'a LiveRow> {
+    let cutoff = policy
+        .max_age_days
+        .map(|days| now - i64::from(days) * SECS_PER_DAY);
+    let keep = policy
+        .max_rows
+        .map(|max| max as usize)
+        .unwrap_or(usize::MAX);
+    rows.iter()
+        .enumerate()
+        .filter(|(i, row)| *i >= keep || cutoff.is_some_and(|cutoff| row.updated_at < cutoff))
+        .map(|(_, row)| row)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expires_old_and_excess_rows() {
+        let now = 100 * SECS_PER_DAY;
+        let rows: Vec<_> = [0, 1, 2, 10, 40]
+            .into_iter()
+            .map(|age_days| LiveRow {
+                id: Uuid::new_v4(),
+                schema: Hash::new(b"schema"),
+                content: Hash::new(b"content"),
+                updated_at: now - age_days * SECS_PER_DAY,
+            })
+            .collect();
+        let ages = |policy: RetentionPolicy| -> Vec<i64> {
+            expired(&rows, &policy, now)
+                .iter()
+                .map(|row| (now - row.updated_at) / SECS_PER_DAY)
+                .collect()
+        };
+        let policy = |max_age_days, max_rows| RetentionPolicy {
+            max_age_days,
+            max_rows,
+            action: RetentionAction::Delete,
+            interval_secs: DEFAULT_INTERVAL_SECS,
+        };
+
+        assert_eq!(ages(policy(Some(7), None)), vec![10, 40]);
+        assert_eq!(ages(policy(None, Some(2))), vec![2, 10, 40]);
+        assert_eq!(ages(policy(Some(30), Some(4))), vec![40]);
+        assert_eq!(ages(policy(Some(365), Some(10))), Vec::<i64>::new());
+    }
+}
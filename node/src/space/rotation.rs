@@ -0,0 +1,356 @@
+//! Rotating a space's namespace secret, eg: after a device is lost or a share link leaks.
+//!
+//! Rotation swaps in a fresh secret & writes a [`EventKind::RotateSecret`] event that carries
+//! the new secret sealed to each member's key, so members following the space can pick it
+//! up. The old secret is marked revoked & the feed moves to the new namespace, replicas need
+//! the new ticket. Whatever was published under the old secret stays readable to anyone who
+//! holds it. This node keeps only the old secret's [`FieldKey`], so sensitive row fields sealed
+//! before the rotation still open here.
+use std::str::FromStr;
+
+use anyhow::{anyhow, Context, Result};
+use iroh::docs::{Author, DocTicket, NamespaceId, NamespaceSecret};
+use iroh::net::key::{PublicKey, SecretKey};
+use rusqlite::{params, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use super::audit::AuditAction;
+use super::db::DB;
+use super::events::{Event, EventKind, HashLink, Tag, EVENT_SQL_READ_FIELDS, NOSTR_ID_TAG};
+use super::sensitive::FieldKey;
+use super::users::not_removed;
+use super::Space;
+
+/// The new secret, sealed to one member.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SecretGrant {
+    pub recipient: PublicKey,
+    /// hex encoded
+    pub sealed: String,
+}
+
+/// A rotation as recorded in the space's events.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Rotation {
+    /// who rotated the secret, grants are sealed with their key
+    pub author: PublicKey,
+    pub previous: NamespaceId,
+    pub current: NamespaceId,
+    pub grants: Vec<SecretGrant>,
+}
+
+/// The outcome of [`Space::rotate_secret`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SecretRotation {
+    pub previous: NamespaceId,
+    pub current: NamespaceId,
+    /// members the new secret was sealed to
+    pub members: Vec<PublicKey>,
+    /// read ticket for the space's feed under the new secret, if the space was shared
+    pub ticket: Option<DocTicket>,
+}
+
+impl Rotation {
+    /// The new secret, if it was granted to `recipient`.
+    pub fn unseal(&self, recipient: &Author) -> Result<Option<NamespaceSecret>> {
+        let key = SecretKey::from_bytes(&recipient.to_bytes());
+        let Some(grant) = self.grants.iter().find(|g| g.recipient == key.public()) else {
+            return Ok(None);
+        };
+        let mut buffer = hex::decode(&grant.sealed).context("decoding sealed secret")?;
+        key.shared(&self.author)
+            .open(&mut buffer)
+            .map_err(|_| anyhow!("sealed secret doesn't open with this key"))?;
+        let bytes: [u8; 32] = buffer
+            .try_into()
+            .map_err(|_| anyhow!("sealed secret has the wrong length"))?;
+        let secret = NamespaceSecret::from_bytes(&bytes);
+        anyhow::ensure!(secret.id() == self.current, "sealed secret doesn't match");
+        Ok(Some(secret))
+    }
+}
+
+/// Generate a new secret, seal it to every member but `exclude` & make it the space's secret.
+pub(crate) async fn rotate(
+    space: &Space,
+    author: Author,
+    exclude: &[PublicKey],
+) -> Result<SecretRotation> {
+    let previous = space.secret();
+    let secret = NamespaceSecret::new(&mut rand::thread_rng());
+    let shared = space.feed().is_shared().await?;
+
+    let sender = SecretKey::from_bytes(&author.to_bytes());
+    let members: Vec<_> = members(&space.db)
+        .await?
+        .into_iter()
+        .filter(|member| !exclude.contains(member))
+        .collect();
+    let grants = members
+        .iter()
+        .map(|member| {
+            let mut buffer = secret.to_bytes().to_vec();
+            sender.shared(member).seal(&mut buffer);
+            SecretGrant {
+                recipient: *member,
+                sealed: hex::encode(buffer),
+            }
+        })
+        .collect();
+    let rotation = Rotation {
+        author: sender.public(),
+        previous: previous.id(),
+        current: secret.id(),
+        grants,
+    };
+
+    // written before the swap, so followers of the old namespace learn about the new one
+    let content = serde_json::to_value(&rotation)?;
+    let outcome = space
+        .router
+        .blobs()
+        .add_bytes(serde_json::to_vec(&content)?)
+        .await?;
+    let event = Event::create(
+        author.clone(),
+        chrono::Utc::now().timestamp(),
+        space.clock().tick(),
+        EventKind::RotateSecret,
+        vec![Tag::new(NOSTR_ID_TAG, space.id.to_string().as_str())],
+        HashLink {
+            hash: outcome.hash,
+            data: Some(content),
+        },
+    )?;
+    space.write_event(&event).await?;
+
+    let now = chrono::Utc::now().timestamp();
+    let conn = space.db.lock().await;
+    conn.execute(
+        "INSERT OR REPLACE INTO space_secrets (namespace, secret, created_at, revoked_at) VALUES (?1, NULL, ?2, ?2)",
+        params![previous.id().to_string(), now],
+    )?;
+    conn.execute(
+        "INSERT INTO space_secrets (namespace, secret, created_at) VALUES (?1, ?2, ?3)",
+        params![secret.id().to_string(), secret.to_bytes().to_vec(), now],
+    )?;
+    let retired = FieldKey::new(&previous);
+    conn.execute(
+        "INSERT OR REPLACE INTO retired_field_keys (namespace, key) VALUES (?1, ?2)",
+        params![retired.namespace(), retired.to_bytes().to_vec()],
+    )?;
+    super::imports::reseal_credentials(&conn, &previous, &secret)?;
+    drop(conn);
+    space.set_secret(secret.clone());
+    space.retire_key(retired);
+    space
+        .audit()
+        .record(
+            author,
+            AuditAction::SecretRotated {
+                previous: previous.id(),
+                current: secret.id(),
+            },
+        )
+        .await?;
+
+    let ticket = match shared {
+        true => Some(space.feed().share().await?),
+        false => None,
+    };
+    Ok(SecretRotation {
+        previous: previous.id(),
+        current: secret.id(),
+        members,
+        ticket,
+    })
+}
+
+/// Keys of everyone with a user in the space, leaving out removed members.
+async fn members(db: &DB) -> Result<Vec<PublicKey>> {
+    let conn = db.lock().await;
+    let mut stmt = conn.prepare(&format!(
+        "SELECT DISTINCT pubkey FROM events WHERE kind = ?1 AND {}",
+        not_removed()
+    ))?;
+    let mut rows = stmt.query(params![EventKind::MutateUser])?;
+    let mut members = Vec::new();
+    while let Some(row) = rows.next()? {
+        let pubkey: String = row.get(0)?;
+        members.push(PublicKey::from_str(&pubkey)?);
+    }
+    Ok(members)
+}
+
+/// Rotations of the space, oldest first.
+pub(crate) async fn list(space: &Space) -> Result<Vec<Rotation>> {
+    let conn = space.db.lock().await;
+    let mut stmt = conn.prepare(
+        format!(
+            "SELECT {EVENT_SQL_READ_FIELDS} FROM events WHERE kind = ?1 AND data_id = ?2 ORDER BY clock"
+        )
+        .as_str(),
+    )?;
+    let mut rows = stmt.query(params![EventKind::RotateSecret, space.id])?;
+    let mut rotations = Vec::new();
+    while let Some(row) = rows.next()? {
+        let event = Event::from_sql_row(row)?;
+        let content = event
+            .content
+            .data
+            .context("rotation event has no content")?;
+        let rotation: Rotation = serde_json::from_value(content)?;
+        // grants are only sealed by whoever signed the event
+        if rotation.author != event.pubkey {
+            warn!(
+                "ignoring rotation event {} with a mismatched author",
+                event.id
+            );
+            continue;
+        }
+        rotations.push(rotation);
+    }
+    Ok(rotations)
+}
+
+/// The secret the space was last rotated to, if it ever was.
+pub(crate) async fn current_secret(db: &DB) -> Result<Option<NamespaceSecret>> {
+    let conn = db.lock().await;
+    let secret: Option<Vec<u8>> = conn
+        .query_row(
+            "SELECT secret FROM space_secrets WHERE revoked_at IS NULL ORDER BY created_at DESC LIMIT 1",
+            [],
+            |row| row.get(0),
+        )
+        .optional()?;
+    let Some(secret) = secret else {
+        return Ok(None);
+    };
+    let bytes: [u8; 32] = secret
+        .try_into()
+        .map_err(|_| anyhow!("stored space secret has the wrong length"))?;
+    Ok(Some(NamespaceSecret::from_bytes(&bytes)))
+}
+
+/// Field keys of every secret the space rotated away from, see [`super::sensitive`].
+pub(crate) async fn retired_field_keys(db: &DB) -> Result<Vec<FieldKey>> {
+    let conn = db.lock().await;
+    let mut stmt = conn.prepare("SELECT namespace, key FROM retired_field_keys")?;
+    let mut rows = stmt.query([])?;
+    let mut keys = Vec::new();
+    while let Some(row) = rows.next()? {
+        let key: Vec<u8> = row.get(1)?;
+        let key: [u8; 32] = key
+            .try_into()
+            .map_err(|_| anyhow!("stored field key has the wrong length"))?;
+        keys.push(FieldKey::from_parts(row.get(0)?, &key));
+    }
+    Ok(keys)
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use serde_json::json;
+    use uuid::Uuid;
+
+    use super::*;
+
+    #[test]
+    fn unseal_grant() {
+        let sender = Author::new(&mut rand::thread_rng());
+        let member = Author::new(&mut rand::thread_rng());
+        let stranger = Author::new(&mut rand::thread_rng());
+        let secret = NamespaceSecret::new(&mut rand::thread_rng());
+
+        let sender_key = SecretKey::from_bytes(&sender.to_bytes());
+        let member_key = SecretKey::from_bytes(&member.to_bytes()).public();
+        let mut buffer = secret.to_bytes().to_vec();
+        sender_key.shared(&member_key).seal(&mut buffer);
+        let rotation = Rotation {
+            author: sender_key.public(),
+            previous: NamespaceSecret::new(&mut rand::thread_rng()).id(),
+            current: secret.id(),
+            grants: vec![SecretGrant {
+                recipient: member_key,
+                sealed: hex::encode(buffer),
+            }],
+        };
+
+        let unsealed = rotation.unseal(&member).unwrap().expect("granted");
+        assert_eq!(unsealed.id(), secret.id());
+        assert!(rotation.unseal(&stranger).unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn sensitive_fields_open_after_rotation() -> Result<()> {
+        let node = iroh::node::Node::memory().enable_docs().spawn().await?;
+        let dir = tempfile::tempdir()?;
+        let id = Uuid::new_v4();
+        let original = NamespaceSecret::new(&mut rand::thread_rng());
+        let open = || {
+            Space::open(
+                id,
+                "rotated".to_string(),
+                original.clone(),
+                node.client().clone(),
+                Default::default(),
+                Default::default(),
+                Default::default(),
+                dir.path(),
+            )
+        };
+        let space = open().await?;
+        let author = Author::new(&mut rand::thread_rng());
+        let schema = json!({
+            "title": "accounts",
+            "type": "object",
+            "properties": {
+                "login": { "type": "string" },
+                "token": { "type": "string", "sensitive": true },
+            },
+        });
+        let table = space
+            .tables()
+            .create(author.clone(), Bytes::from(schema.to_string()))
+            .await?;
+        let schema = table.content.hash;
+        let before = space
+            .rows()
+            .create(
+                author.clone(),
+                schema,
+                json!({ "login": "ada", "token": "hunter2" }),
+            )
+            .await?;
+
+        space.rotate_secret(author.clone(), &[]).await?;
+        let after = space
+            .rows()
+            .create(
+                author.clone(),
+                schema,
+                json!({ "login": "bob", "token": "swordfish" }),
+            )
+            .await?;
+
+        let read = |space: Space| async move {
+            let mut tokens = Vec::new();
+            for id in [before.id, after.id] {
+                let row = space.rows().get(schema, id).await?.expect("row exists");
+                tokens.push(row.content.data.expect("row content")["token"].clone());
+            }
+            anyhow::Ok(tokens)
+        };
+        assert_eq!(read(space.clone()).await?, vec!["hunter2", "swordfish"]);
+
+        // the retired key outlives the process
+        drop(space);
+        assert_eq!(read(open().await?).await?, vec!["hunter2", "swordfish"]);
+        Ok(())
+    }
+}
@@ -1,8 +1,12 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
 use anyhow::{anyhow, Context, Result};
 use iroh::blobs::Hash;
+use iroh::client::blobs::BlobStatus;
 use iroh::docs::Author;
 use iroh::net::key::PublicKey;
-use rusqlite::params;
+use rusqlite::{params, OptionalExtension};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use uuid::Uuid;
@@ -10,23 +14,59 @@ use uuid::Uuid;
 use crate::router::RouterClient;
 use crate::space::events::Tag;
 
+use super::clock::Hlc;
 use super::events::{
     Event, EventKind, EventObject, HashLink, EVENT_SQL_READ_FIELDS, NOSTR_ID_TAG, NOSTR_SCHEMA_TAG,
 };
+use super::sensitive;
 use super::Space;
 
+pub mod query;
+
+use query::RowQuery;
+
 #[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
 pub struct Row {
     pub id: Uuid,
     #[serde(rename = "createdAt")]
     pub created_at: i64,
+    #[cfg_attr(feature = "specta", specta(type = String))]
     pub author: PublicKey,
     pub content: HashLink,
+    #[cfg_attr(feature = "specta", specta(type = String))]
     pub schema: Hash,
 }
 
 impl EventObject for Row {
     async fn from_event(event: Event, client: &RouterClient) -> Result<Self> {
+        let mut row = Row::from_event_unresolved(event)?;
+        // fetch content if necessary
+        row.content.resolve(client).await?;
+        Ok(row)
+    }
+
+    fn into_mutate_event(&self, author: Author, hlc: Hlc) -> Result<Event> {
+        // assert!(author.public_key() == self.author);
+        let tags = vec![
+            Tag::new(NOSTR_SCHEMA_TAG, self.schema.to_string().as_str()),
+            Tag::new(NOSTR_ID_TAG, self.id.to_string().as_str()),
+        ];
+        Event::create(
+            author,
+            self.created_at,
+            hlc,
+            EventKind::MutateRow,
+            tags,
+            self.content.clone(),
+        )
+    }
+}
+
+impl Row {
+    /// A row with its content as the event holds it, which is just a hash unless the content
+    /// was stored inline.
+    fn from_event_unresolved(event: Event) -> Result<Row> {
         if event.kind != EventKind::MutateRow {
             return Err(anyhow!("event is not a row mutation"));
         }
@@ -35,51 +75,262 @@ impl EventObject for Row {
         let schema = event.schema()?.ok_or_else(|| anyhow!("no schema found"))?;
         let id = event.data_id()?.ok_or_else(|| anyhow!("missing data id"))?;
 
-        // fetch content if necessary
-        let content = match event.content.data {
-            Some(_) => event.content,
-            None => {
-                let content = client.blobs().read_to_bytes(event.content.hash).await?;
-                let content = serde_json::from_slice::<Value>(&content).map_err(|e| anyhow!(e))?;
-                HashLink {
-                    hash: event.content.hash,
-                    data: Some(content),
-                }
-            }
-        };
-
         Ok(Row {
             author: event.pubkey,
             id,
             schema,
             created_at: event.created_at,
-            content,
+            content: event.content,
         })
     }
 
-    fn into_mutate_event(&self, author: Author) -> Result<Event> {
-        // assert!(author.public_key() == self.author);
+    /// A tombstone for a row, pointing at the content it deleted.
+    fn delete_event(
+        author: Author,
+        hlc: Hlc,
+        schema: Hash,
+        id: Uuid,
+        content: Hash,
+    ) -> Result<Event> {
         let tags = vec![
-            Tag::new(NOSTR_SCHEMA_TAG, self.schema.to_string().as_str()),
-            Tag::new(NOSTR_ID_TAG, self.id.to_string().as_str()),
+            Tag::new(NOSTR_SCHEMA_TAG, schema.to_string().as_str()),
+            Tag::new(NOSTR_ID_TAG, id.to_string().as_str()),
         ];
         Event::create(
             author,
-            self.created_at,
-            EventKind::MutateRow,
+            chrono::Utc::now().timestamp(),
+            hlc,
+            EventKind::DeleteRow,
             tags,
-            self.content.clone(),
+            HashLink::from(content),
         )
     }
 }
 
-impl Row {
-    async fn from_sql_row(row: &rusqlite::Row<'_>, client: &RouterClient) -> Result<Row> {
-        let event = Event::from_sql_row(row)?;
-        Self::from_event(event, client).await
+/// An aggregate function computed over the rows of a table.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+#[serde(rename_all = "camelCase", tag = "op", content = "field")]
+pub enum Aggregation {
+    Count,
+    Sum(String),
+    Avg(String),
+    Min(String),
+    Max(String),
+}
+
+impl Aggregation {
+    /// the key this aggregation is reported under in an [`AggregateRow`]
+    pub fn label(&self) -> String {
+        match self {
+            Aggregation::Count => "count".to_string(),
+            Aggregation::Sum(field) => format!("sum({})", field),
+            Aggregation::Avg(field) => format!("avg({})", field),
+            Aggregation::Min(field) => format!("min({})", field),
+            Aggregation::Max(field) => format!("max({})", field),
+        }
+    }
+
+    fn sql(&self, param: usize) -> String {
+        let value = format!("json_extract(CAST(content AS TEXT), ?{})", param);
+        match self {
+            Aggregation::Count => "COUNT(*)".to_string(),
+            Aggregation::Sum(_) => format!("TOTAL({})", value),
+            Aggregation::Avg(_) => format!("AVG({})", value),
+            Aggregation::Min(_) => format!("MIN({})", value),
+            Aggregation::Max(_) => format!("MAX({})", value),
+        }
+    }
+
+    fn field(&self) -> Option<&str> {
+        match self {
+            Aggregation::Count => None,
+            Aggregation::Sum(f)
+            | Aggregation::Avg(f)
+            | Aggregation::Min(f)
+            | Aggregation::Max(f) => Some(f.as_str()),
+        }
+    }
+}
+
+/// A single group of an aggregate query. `group` holds the values of the group_by fields,
+/// `values` holds the result of each aggregation, keyed by [`Aggregation::label`].
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+pub struct AggregateRow {
+    pub group: HashMap<String, Value>,
+    pub values: HashMap<String, Value>,
+}
+
+/// Width of the buckets returned by a bucketed range query.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+#[serde(rename_all = "camelCase")]
+pub enum Bucket {
+    Hour,
+    Day,
+}
+
+impl Bucket {
+    fn seconds(&self) -> i64 {
+        match self {
+            Bucket::Hour => 60 * 60,
+            Bucket::Day => 60 * 60 * 24,
+        }
+    }
+}
+
+/// The number of rows that fall into the bucket starting at `start` (unix seconds).
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+pub struct TimeBucket {
+    pub start: i64,
+    pub count: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+#[serde(rename_all = "camelCase")]
+pub enum RangeResult {
+    Rows(Vec<Row>),
+    Buckets(Vec<TimeBucket>),
+}
+
+/// A write to a table, as seen by [`Rows::changes`].
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+#[serde(rename_all = "camelCase", tag = "op")]
+pub enum RowChange {
+    /// first write to a row
+    Insert {
+        row: Row,
+    },
+    Update {
+        row: Row,
+    },
+    Delete {
+        id: Uuid,
+    },
+}
+
+/// Changes to a table since a cursor, oldest first. Pass `cursor` back in to pick up where
+/// this batch ends.
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+pub struct RowChanges {
+    pub changes: Vec<RowChange>,
+    pub cursor: i64,
+}
+
+/// most changes returned at once, callers page through the rest with the cursor
+const MAX_ROW_CHANGES: i64 = 500;
+
+/// how many row contents [`Rows::query_hydrated`] reads from the blob store at once
+const HYDRATE_BATCH: usize = 32;
+/// default size budget for [`Rows::query_hydrated`], in bytes
+pub const DEFAULT_HYDRATE_BUDGET: u64 = 4 * 1024 * 1024;
+
+/// the time a row was first written
+pub const CREATED_AT_FIELD: &str = "createdAt";
+/// the time of the latest write to a row
+pub const UPDATED_AT_FIELD: &str = "updatedAt";
+
+/// sql condition that leaves out row writes a tombstone supersedes, for queries over
+/// `events`. Deletes & writes to a row are ordered by their clock (see [`super::clock`]) no
+/// matter the order they synced in, a write after a delete brings the row back. A delete wins
+/// a tie.
+pub(crate) fn not_deleted() -> String {
+    format!(
+        "NOT EXISTS (
+            SELECT 1 FROM events AS tombstone
+            WHERE tombstone.kind = {} AND tombstone.data_id = events.data_id
+            AND tombstone.schema_hash = events.schema_hash
+            AND tombstone.clock >= events.clock
+        )",
+        EventKind::DeleteRow.kind()
+    )
+}
+
+/// equality filters applied to row fields before aggregating
+pub type RowFilter = HashMap<String, Value>;
+
+/// Refuse field names that can't be written as a top level json path. Quotes used to be
+/// dropped from names, which silently read a different field.
+pub(crate) fn check_field(field: &str) -> Result<()> {
+    anyhow::ensure!(
+        !field.is_empty() && !field.contains(['"', '\\']),
+        "invalid field name {field:?}, names can't be empty or contain quotes or backslashes"
+    );
+    Ok(())
+}
+
+/// convert a top level field name into a sqlite json path
+pub(crate) fn json_path(field: &str) -> String {
+    format!("$.\"{}\"", field.replace('"', ""))
+}
+
+pub(crate) fn sql_to_json(value: rusqlite::types::Value) -> Value {
+    match value {
+        rusqlite::types::Value::Null => Value::Null,
+        rusqlite::types::Value::Integer(i) => Value::from(i),
+        rusqlite::types::Value::Real(f) => Value::from(f),
+        rusqlite::types::Value::Text(s) => Value::from(s),
+        rusqlite::types::Value::Blob(b) => Value::from(hex::encode(b)),
     }
 }
 
+pub(crate) fn json_to_sql(value: &Value) -> rusqlite::types::Value {
+    match value {
+        Value::Null => rusqlite::types::Value::Null,
+        Value::Bool(b) => rusqlite::types::Value::Integer(*b as i64),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => rusqlite::types::Value::Integer(i),
+            None => rusqlite::types::Value::Real(n.as_f64().unwrap_or_default()),
+        },
+        Value::String(s) => rusqlite::types::Value::Text(s.clone()),
+        v => rusqlite::types::Value::Text(v.to_string()),
+    }
+}
+
+/// Select the latest version of each row of a table that matches `query`. The schema hash is
+/// written inline so sqlite can use the table's partial indexes.
+fn latest_rows_sql(
+    schema: Hash,
+    query: &RowQuery,
+    offset: i64,
+    limit: i64,
+) -> (String, Vec<rusqlite::types::Value>) {
+    let (conditions, order, filter_params) = query.sql(3);
+    let conditions: String = conditions
+        .iter()
+        .map(|condition| format!(" AND {condition}"))
+        .collect();
+    // `+kind` keeps sqlite off the index on event kinds, which would read every event
+    let (source, kind) = match query.index(schema) {
+        Some(index) => (format!("events INDEXED BY \"{index}\""), "kind"),
+        None => ("events".to_string(), "+kind"),
+    };
+    let sql = format!(
+        "SELECT {EVENT_SQL_READ_FIELDS} FROM {source}
+        WHERE {kind} = {mutate} AND schema_hash = '{schema}' AND {not_deleted}
+        AND NOT EXISTS (
+            SELECT 1 FROM events AS newer
+            WHERE newer.schema_hash = events.schema_hash AND newer.data_id = events.data_id
+            AND +newer.kind = {mutate}
+            AND (newer.clock, newer.rowid) > (events.clock, events.rowid)
+        ){conditions}
+        {order} LIMIT ?1 OFFSET ?2",
+        mutate = EventKind::MutateRow.kind(),
+        not_deleted = not_deleted(),
+    );
+    let mut params = vec![
+        rusqlite::types::Value::Integer(limit),
+        rusqlite::types::Value::Integer(offset),
+    ];
+    params.extend(filter_params);
+    (sql, params)
+}
+
 #[derive(Clone)]
 pub struct Rows(Space);
 
@@ -114,22 +365,862 @@ impl Rows {
             .await
     }
 
+    /// The latest version of a row, None if it was never written or is deleted.
+    pub async fn get(&self, schema: Hash, id: Uuid) -> Result<Option<Row>> {
+        let event = {
+            let conn = self.0.db.lock().await;
+            let sql = format!(
+                "SELECT {EVENT_SQL_READ_FIELDS} FROM events
+                WHERE kind = ?1 AND schema_hash = ?2 AND data_id = ?3 AND {}
+                ORDER BY clock DESC LIMIT 1",
+                not_deleted()
+            );
+            let mut stmt = conn.prepare(&sql)?;
+            let mut rows = stmt.query(params![EventKind::MutateRow, schema.to_string(), id])?;
+            match rows.next()? {
+                Some(row) => Event::from_sql_row(row)?,
+                None => return Ok(None),
+            }
+        };
+        let mut row = Row::from_event(event, &self.0.router).await?;
+        self.open(&mut row)?;
+        Ok(Some(row))
+    }
+
+    /// Delete a row, leaving it out of queries until it's written again.
+    pub async fn delete(&self, author: Author, schema: Hash, id: Uuid) -> Result<()> {
+        let content = {
+            let conn = self.0.db.lock().await;
+            let sql = format!(
+                "SELECT content_hash FROM events
+                WHERE kind = ?1 AND schema_hash = ?2 AND data_id = ?3 AND {}
+                ORDER BY clock DESC LIMIT 1",
+                not_deleted()
+            );
+            let content: Option<String> = conn
+                .query_row(
+                    &sql,
+                    params![EventKind::MutateRow, schema.to_string(), id],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            content.ok_or_else(|| anyhow!("row not found: {}", id))?
+        };
+        self.tombstone(author, schema, id, content.parse()?).await
+    }
+
+    /// Write a tombstone for a row, leaving it out of queries until it's written again.
+    pub(crate) async fn tombstone(
+        &self,
+        author: Author,
+        schema: Hash,
+        id: Uuid,
+        content: Hash,
+    ) -> Result<()> {
+        let event = Row::delete_event(author, self.0.clock().tick(), schema, id, content)?;
+        self.0.write_event(&event).await
+    }
+
+    /// Inserts, updates & deletes in a table since `since`, in the order this node stored
+    /// them. A cursor of 0 starts from the beginning. Cursors are local to this node, they
+    /// don't carry over to other replicas of the space.
+    pub async fn changes(&self, schema: Hash, since: i64) -> Result<RowChanges> {
+        // a write is an insert when no earlier write to the same row exists
+        let sql = format!(
+            "SELECT {EVENT_SQL_READ_FIELDS}, rowid, EXISTS(
+                SELECT 1 FROM events AS earlier
+                WHERE earlier.kind = {mutate} AND earlier.schema_hash = events.schema_hash
+                AND earlier.data_id = events.data_id AND earlier.rowid < events.rowid
+            ) FROM events
+            WHERE kind IN ({mutate}, {delete}) AND schema_hash = ?1 AND rowid > ?2
+            ORDER BY rowid LIMIT ?3",
+            mutate = EventKind::MutateRow.kind(),
+            delete = EventKind::DeleteRow.kind(),
+        );
+        let written = {
+            let conn = self.0.db.lock().await;
+            let mut stmt = conn.prepare(&sql)?;
+            let mut rows = stmt.query(params![schema.to_string(), since, MAX_ROW_CHANGES])?;
+            let mut written = Vec::new();
+            while let Some(row) = rows.next()? {
+                let event = Event::from_sql_row(row)?;
+                // the two columns after the event fields
+                let cursor: i64 = row.get(9)?;
+                let existed: bool = row.get(10)?;
+                written.push((event, cursor, existed));
+            }
+            written
+        };
+
+        // row content is read after the db is released, it may need fetching
+        let mut changes = Vec::new();
+        let mut cursor = since;
+        for (event, rowid, existed) in written {
+            cursor = rowid;
+            let change = match event.kind {
+                EventKind::DeleteRow => RowChange::Delete {
+                    id: event.data_id()?.context("missing data id")?,
+                },
+                _ => {
+                    let mut row = Row::from_event(event, &self.0.router).await?;
+                    self.open(&mut row)?;
+                    match existed {
+                        true => RowChange::Update { row },
+                        false => RowChange::Insert { row },
+                    }
+                }
+            };
+            changes.push(change);
+        }
+        Ok(RowChanges { changes, cursor })
+    }
+
+    /// Open the row's sealed fields, see [`super::sensitive`].
+    fn open(&self, row: &mut Row) -> Result<()> {
+        match row.content.data.as_mut() {
+            Some(data) => sensitive::open_fields(&self.0.field_keys(), data),
+            None => Ok(()),
+        }
+    }
+
+    /// Wait on the returned receiver to learn when a row in the space is written. Pair it
+    /// with [`Rows::changes`] to follow a table.
+    pub fn subscribe(&self) -> tokio::sync::watch::Receiver<u64> {
+        self.0.row_writes.subscribe()
+    }
+
+    /// Query the latest version of each row in a table, `query` is parsed as a [`RowQuery`].
     pub async fn query(
         &self,
         schema: Hash,
-        _query: String,
+        query: String,
+        offset: i64,
+        limit: i64,
+    ) -> Result<Vec<Row>> {
+        let query = RowQuery::from_str(&query).context("parsing row query")?;
+        self.query_with(schema, &query, offset, limit).await
+    }
+
+    /// Query the latest version of each row in a table.
+    pub async fn query_with(
+        &self,
+        schema: Hash,
+        query: &RowQuery,
         offset: i64,
         limit: i64,
     ) -> Result<Vec<Row>> {
+        self.index(schema, &query.indexed_fields()).await?;
+        let events = {
+            let conn = self.0.db.lock().await;
+            let (sql, params) = latest_rows_sql(schema, query, offset, limit);
+            let mut stmt = conn.prepare(&sql).context("preparing row query")?;
+            let mut rows = stmt.query(rusqlite::params_from_iter(params))?;
+            let mut events = Vec::new();
+            while let Some(row) = rows.next()? {
+                events.push(Event::from_sql_row(row)?);
+            }
+            events
+        };
+
+        let mut results = Vec::new();
+        for event in events {
+            let mut row = Row::from_event(event, &self.0.router).await?;
+            self.open(&mut row)?;
+            if let Some(data) = row.content.data.as_mut() {
+                query.project(data);
+            }
+            results.push(row);
+        }
+        Ok(results)
+    }
+
+    /// How many rows of a table match `query`, across every page of it.
+    pub async fn count(&self, schema: Hash, query: &RowQuery) -> Result<i64> {
+        self.index(schema, &query.indexed_fields()).await?;
+        let conn = self.0.db.lock().await;
+        let (sql, params) = latest_rows_sql(schema, query, 0, -1);
+        let count = conn
+            .query_row(
+                &format!("SELECT COUNT(*) FROM ({sql})"),
+                rusqlite::params_from_iter(params),
+                |row| row.get(0),
+            )
+            .context("counting rows")?;
+        Ok(count)
+    }
+
+    /// Index `fields` of the rows of a table, for queries that filter or order on them.
+    /// Creating an index reads every row of the table once, later calls are cheap.
+    pub(crate) async fn index(&self, schema: Hash, fields: &[&str]) -> Result<()> {
+        let conn = self.0.db.lock().await;
+        for field in fields {
+            conn.execute(&query::index_sql(schema, field), [])
+                .with_context(|| format!("indexing field {field}"))?;
+        }
+        Ok(())
+    }
+
+    /// Like [`Rows::query`], but content that isn't stored inline is read from the blob store
+    /// in batches after the query, for up to `budget` bytes. Rows whose content is missing
+    /// locally or doesn't fit the budget are returned with just the content hash, so one slow
+    /// or huge blob doesn't hold up the page.
+    pub async fn query_hydrated(
+        &self,
+        schema: Hash,
+        query: &RowQuery,
+        offset: i64,
+        limit: i64,
+        budget: u64,
+    ) -> Result<Vec<Row>> {
+        self.index(schema, &query.indexed_fields()).await?;
+        let mut result = {
+            let conn = self.0.db.lock().await;
+            let (sql, params) = latest_rows_sql(schema, query, offset, limit);
+            let mut stmt = conn.prepare(&sql).context("preparing row query")?;
+            let mut rows = stmt.query(rusqlite::params_from_iter(params))?;
+            let mut result = Vec::new();
+            while let Some(row) = rows.next()? {
+                result.push(Row::from_event_unresolved(Event::from_sql_row(row)?)?);
+            }
+            result
+        };
+
+        // pick the content that fits the budget, in row order
+        let mut sizes = HashMap::new();
+        let mut spent = 0;
+        for row in result.iter().filter(|row| row.content.data.is_none()) {
+            let hash = row.content.hash;
+            if sizes.contains_key(&hash) {
+                continue;
+            }
+            let size = match self.0.router.blobs().status(hash).await? {
+                BlobStatus::Complete { size } => size,
+                _ => continue,
+            };
+            if spent + size > budget {
+                continue;
+            }
+            spent += size;
+            sizes.insert(hash, size);
+        }
+
+        let hashes = sizes.into_keys().collect::<Vec<_>>();
+        let mut values = HashMap::new();
+        for batch in hashes.chunks(HYDRATE_BATCH) {
+            let reads = batch
+                .iter()
+                .map(|hash| self.0.router.blobs().read_to_bytes(*hash));
+            for (hash, data) in batch.iter().zip(futures::future::join_all(reads).await) {
+                let value = serde_json::from_slice::<Value>(&data?)?;
+                values.insert(*hash, value);
+            }
+        }
+        for row in result.iter_mut() {
+            if row.content.data.is_none() {
+                row.content.data = values.get(&row.content.hash).cloned();
+            }
+            self.open(row)?;
+            if let Some(data) = row.content.data.as_mut() {
+                query.project(data);
+            }
+        }
+        Ok(result)
+    }
+
+    /// Query the latest version of each row in a table where `field` falls within
+    /// `[from, to]`. `field` is either [`CREATED_AT_FIELD`], [`UPDATED_AT_FIELD`], or the name of
+    /// a top-level row field holding a unix timestamp in seconds. When a bucket is given, rows
+    /// are counted per hour or day instead of returned.
+    pub async fn query_range(
+        &self,
+        schema: Hash,
+        field: &str,
+        from: i64,
+        to: i64,
+        bucket: Option<Bucket>,
+    ) -> Result<RangeResult> {
+        let mut params: Vec<rusqlite::types::Value> = vec![
+            rusqlite::types::Value::Integer(EventKind::MutateRow.kind().into()),
+            rusqlite::types::Value::Text(schema.to_string()),
+            rusqlite::types::Value::Integer(from),
+            rusqlite::types::Value::Integer(to),
+        ];
+        let ts = match field {
+            CREATED_AT_FIELD => "first.first_at".to_string(),
+            UPDATED_AT_FIELD => "latest.created_at".to_string(),
+            field => {
+                check_field(field)?;
+                params.push(rusqlite::types::Value::Text(json_path(field)));
+                format!(
+                    "json_extract(CAST(latest.content AS TEXT), ?{})",
+                    params.len()
+                )
+            }
+        };
+
+        let not_deleted = not_deleted();
+        let ranged = |select: &str| {
+            format!(
+                "WITH first AS (
+                    SELECT data_id, MIN(created_at) AS first_at FROM events
+                    WHERE kind = ?1 AND schema_hash = ?2
+                    GROUP BY data_id
+                ), latest AS (
+                    SELECT {EVENT_SQL_READ_FIELDS}, MAX(clock) FROM events
+                    WHERE kind = ?1 AND schema_hash = ?2 AND {not_deleted}
+                    GROUP BY data_id
+                )
+                SELECT {select} FROM latest JOIN first USING (data_id)
+                WHERE {ts} BETWEEN ?3 AND ?4"
+            )
+        };
+
         let conn = self.0.db.lock().await;
-        let mut stmt = conn.prepare(format!("SELECT {EVENT_SQL_READ_FIELDS} FROM events WHERE schema_hash = ?1 LIMIT ?2 OFFSET ?3").as_str())?;
-        let mut rows = stmt.query(params![schema.to_string(), limit, offset])?;
-        let mut events = Vec::new();
+        match bucket {
+            Some(bucket) => {
+                let width = bucket.seconds();
+                let sql = ranged(&format!("({ts} / {width}) * {width} AS bucket, COUNT(*)"))
+                    + " GROUP BY bucket ORDER BY bucket";
+                let mut stmt = conn.prepare(&sql).context("preparing range query")?;
+                let mut rows = stmt.query(rusqlite::params_from_iter(params))?;
+                let mut buckets = Vec::new();
+                while let Some(row) = rows.next()? {
+                    buckets.push(TimeBucket {
+                        start: row.get(0)?,
+                        count: row.get(1)?,
+                    });
+                }
+                Ok(RangeResult::Buckets(buckets))
+            }
+            None => {
+                let sql = ranged(
+                    "latest.id, latest.pubkey, latest.created_at, latest.kind, latest.schema_hash, latest.data_id, latest.content_hash, latest.content, latest.hlc",
+                ) + &format!(" ORDER BY {ts}");
+                let events = {
+                    let mut stmt = conn.prepare(&sql).context("preparing range query")?;
+                    let mut rows = stmt.query(rusqlite::params_from_iter(params))?;
+                    let mut events = Vec::new();
+                    while let Some(row) = rows.next()? {
+                        events.push(Event::from_sql_row(row)?);
+                    }
+                    events
+                };
+                drop(conn);
+                let mut results = Vec::new();
+                for event in events {
+                    let mut row = Row::from_event(event, &self.0.router).await?;
+                    self.open(&mut row)?;
+                    results.push(row);
+                }
+                Ok(RangeResult::Rows(results))
+            }
+        }
+    }
+
+    /// Compute aggregates over the latest version of each row in a table, grouped by zero or
+    /// more top-level fields. Aggregation happens in sqlite, so callers never need to load
+    /// the rows themselves.
+    pub async fn aggregate(
+        &self,
+        schema: Hash,
+        group_by: Vec<String>,
+        aggregations: Vec<Aggregation>,
+        filter: Option<RowFilter>,
+    ) -> Result<Vec<AggregateRow>> {
+        anyhow::ensure!(
+            !group_by.is_empty() || !aggregations.is_empty(),
+            "aggregate requires at least one group_by field or aggregation"
+        );
+        let filter = filter.unwrap_or_default();
+        let fields = group_by
+            .iter()
+            .map(String::as_str)
+            .chain(aggregations.iter().filter_map(Aggregation::field))
+            .chain(filter.keys().map(String::as_str));
+        for field in fields {
+            check_field(field)?;
+        }
 
+        let mut params: Vec<rusqlite::types::Value> =
+            vec![rusqlite::types::Value::Text(schema.to_string())];
+        let mut push = |v: rusqlite::types::Value| {
+            params.push(v);
+            params.len()
+        };
+
+        let mut columns = Vec::new();
+        for field in &group_by {
+            let i = push(rusqlite::types::Value::Text(json_path(field)));
+            columns.push(format!("json_extract(CAST(content AS TEXT), ?{})", i));
+        }
+        for agg in &aggregations {
+            let i = match agg.field() {
+                Some(field) => push(rusqlite::types::Value::Text(json_path(field))),
+                None => 0,
+            };
+            columns.push(agg.sql(i));
+        }
+
+        let mut wheres = Vec::new();
+        for (field, value) in filter {
+            let path = push(rusqlite::types::Value::Text(json_path(&field)));
+            let value = push(json_to_sql(&value));
+            wheres.push(format!(
+                "json_extract(CAST(content AS TEXT), ?{}) = ?{}",
+                path, value
+            ));
+        }
+        let wheres = match wheres.is_empty() {
+            true => String::new(),
+            false => format!("WHERE {}", wheres.join(" AND ")),
+        };
+        let group_clause = match group_by.is_empty() {
+            true => String::new(),
+            false => format!(
+                "GROUP BY {}",
+                (1..=group_by.len())
+                    .map(|i| i.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        };
+
+        // sqlite returns the bare columns from the row that satisfies MAX(clock),
+        // giving us last-writer-wins row state
+        let sql = format!(
+            "WITH latest AS (
+                SELECT content, MAX(clock) FROM events
+                WHERE kind = {} AND schema_hash = ?1 AND {}
+                GROUP BY data_id
+            )
+            SELECT {} FROM latest {} {}",
+            EventKind::MutateRow.kind(),
+            not_deleted(),
+            columns.join(", "),
+            wheres,
+            group_clause,
+        );
+
+        let conn = self.0.db.lock().await;
+        let mut stmt = conn.prepare(&sql).context("preparing aggregate query")?;
+        let mut rows = stmt.query(rusqlite::params_from_iter(params))?;
+
+        let mut results = Vec::new();
         while let Some(row) = rows.next()? {
-            let event = Row::from_sql_row(row, &self.0.router).await?;
-            events.push(event);
+            let mut group = HashMap::new();
+            for (i, field) in group_by.iter().enumerate() {
+                group.insert(field.clone(), sql_to_json(row.get(i)?));
+            }
+            let mut values = HashMap::new();
+            for (i, agg) in aggregations.iter().enumerate() {
+                values.insert(agg.label(), sql_to_json(row.get(group_by.len() + i)?));
+            }
+            results.push(AggregateRow { group, values });
+        }
+        Ok(results)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use serde_json::json;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn delete_rows() -> Result<()> {
+        let node = iroh::node::Node::memory().spawn().await?;
+        let space = Space::open_in_memory(
+            Uuid::new_v4(),
+            "deletes".to_string(),
+            iroh::docs::NamespaceSecret::new(&mut rand::thread_rng()),
+            node.client().clone(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+        )
+        .await?;
+        let author = Author::new(&mut rand::thread_rng());
+        let schema = json!({
+            "title": "notes",
+            "type": "object",
+            "properties": { "text": { "type": "string" } },
+        });
+        let table = space
+            .tables()
+            .create(author.clone(), Bytes::from(schema.to_string()))
+            .await?;
+        let schema = table.content.hash;
+        let rows = space.rows();
+        let kept = rows
+            .create(author.clone(), schema, json!({ "text": "kept" }))
+            .await?;
+        let gone = rows
+            .create(author.clone(), schema, json!({ "text": "gone" }))
+            .await?;
+
+        rows.delete(author.clone(), schema, gone.id).await?;
+        let ids = |found: Vec<Row>| found.into_iter().map(|row| row.id).collect::<Vec<_>>();
+        assert_eq!(
+            ids(rows.query(schema, String::new(), 0, -1).await?),
+            vec![kept.id]
+        );
+        assert!(rows.delete(author.clone(), schema, gone.id).await.is_err());
+        assert_eq!(rows.count(schema, &RowQuery::default()).await?, 1);
+        let count = rows
+            .aggregate(schema, Vec::new(), vec![Aggregation::Count], None)
+            .await?;
+        assert_eq!(count[0].values["count"], json!(1));
+
+        // writes from peers land in any order, their clock decides & created_at doesn't
+        let pubkey = PublicKey::from_bytes(author.public_key().as_bytes())?;
+        let write = |id: Uuid, created_at: i64, hlc: Hlc, text: &str| {
+            let content = json!({ "text": text });
+            Row {
+                id,
+                created_at,
+                author: pubkey,
+                content: HashLink {
+                    hash: Hash::new(content.to_string()),
+                    data: Some(content),
+                },
+                schema,
+            }
+            .into_mutate_event(author.clone(), hlc)
+        };
+        let now = chrono::Utc::now().timestamp();
+        let stale = Hlc::from_created_at(now - 60);
+        space
+            .write_event(&write(gone.id, now + 3600, stale, "stale")?)
+            .await?;
+        assert_eq!(
+            ids(rows.query(schema, String::new(), 0, -1).await?),
+            vec![kept.id],
+            "writes from before the delete stay deleted"
+        );
+        // written after the delete by a device whose clock is an hour behind
+        let later = space.clock().tick();
+        space
+            .write_event(&write(gone.id, now - 3600, later, "back")?)
+            .await?;
+        let found = rows
+            .query(schema, r#"where text = "back""#.to_string(), 0, -1)
+            .await?;
+        assert_eq!(ids(found), vec![gone.id], "a later write brings it back");
+        let query = RowQuery::from_str(r#"where text = "back""#)?;
+        assert_eq!(rows.count(schema, &query).await?, 1);
+        assert_eq!(rows.count(schema, &RowQuery::default()).await?, 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn aggregate_rows() -> Result<()> {
+        let node = iroh::node::Node::memory().spawn().await?;
+        let space = Space::open_in_memory(
+            Uuid::new_v4(),
+            "aggregates".to_string(),
+            iroh::docs::NamespaceSecret::new(&mut rand::thread_rng()),
+            node.client().clone(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+        )
+        .await?;
+        let author = Author::new(&mut rand::thread_rng());
+        let schema = json!({
+            "title": "orders",
+            "type": "object",
+            "properties": {
+                "region": { "type": "string" },
+                "total": { "type": "number" },
+            },
+        });
+        let table = space
+            .tables()
+            .create(author.clone(), Bytes::from(schema.to_string()))
+            .await?;
+        let schema = table.content.hash;
+        let rows = space.rows();
+        for (region, total) in [("north", 10), ("north", 30), ("south", 5)] {
+            rows.create(
+                author.clone(),
+                schema,
+                json!({ "region": region, "total": total }),
+            )
+            .await?;
         }
-        Ok(events)
+        // a later version of a row replaces it in every aggregate
+        let updated = rows
+            .create(
+                author.clone(),
+                schema,
+                json!({ "region": "south", "total": 1 }),
+            )
+            .await?;
+        rows.mutate(
+            author.clone(),
+            schema,
+            updated.id,
+            json!({ "region": "south", "total": 7 }),
+        )
+        .await?;
+
+        let mut groups = rows
+            .aggregate(
+                schema,
+                vec!["region".to_string()],
+                vec![
+                    Aggregation::Count,
+                    Aggregation::Sum("total".to_string()),
+                    Aggregation::Max("total".to_string()),
+                ],
+                None,
+            )
+            .await?;
+        groups.sort_by_key(|group| group.group["region"].to_string());
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].group["region"], json!("north"));
+        assert_eq!(groups[0].values[&Aggregation::Count.label()], json!(2));
+        assert_eq!(
+            groups[0].values[&Aggregation::Sum("total".to_string()).label()],
+            json!(40.0)
+        );
+        assert_eq!(groups[1].values[&Aggregation::Count.label()], json!(2));
+        assert_eq!(
+            groups[1].values[&Aggregation::Max("total".to_string()).label()],
+            json!(7)
+        );
+
+        let filter = RowFilter::from([("region".to_string(), json!("south"))]);
+        let avg = Aggregation::Avg("total".to_string());
+        let south = rows
+            .aggregate(schema, Vec::new(), vec![avg.clone()], Some(filter))
+            .await?;
+        assert_eq!(south[0].values[&avg.label()], json!(6.0));
+
+        for field in ["", "tot\"al", "a\\b"] {
+            let sum = Aggregation::Sum(field.to_string());
+            assert!(
+                rows.aggregate(schema, Vec::new(), vec![sum], None)
+                    .await
+                    .is_err(),
+                "{field:?} is refused"
+            );
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn query_rows_in_range() -> Result<()> {
+        let node = iroh::node::Node::memory().spawn().await?;
+        let space = Space::open_in_memory(
+            Uuid::new_v4(),
+            "ranges".to_string(),
+            iroh::docs::NamespaceSecret::new(&mut rand::thread_rng()),
+            node.client().clone(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+        )
+        .await?;
+        let author = Author::new(&mut rand::thread_rng());
+        let schema = json!({
+            "title": "events",
+            "type": "object",
+            "properties": { "at": { "type": "number" } },
+        });
+        let table = space
+            .tables()
+            .create(author.clone(), Bytes::from(schema.to_string()))
+            .await?;
+        let schema = table.content.hash;
+        let rows = space.rows();
+        for at in [100, 200, 300] {
+            rows.create(author.clone(), schema, json!({ "at": at }))
+                .await?;
+        }
+
+        let RangeResult::Rows(found) = rows.query_range(schema, "at", 150, 300, None).await? else {
+            panic!("rows without a bucket");
+        };
+        let found = found
+            .into_iter()
+            .map(|row| row.content.data.expect("content")["at"].clone())
+            .collect::<Vec<_>>();
+        assert_eq!(found, vec![json!(200), json!(300)]);
+
+        // quotes would otherwise be stripped, reading another field than asked for
+        for field in ["", "a\"t", "a\\t"] {
+            assert!(
+                rows.query_range(schema, field, 0, 1000, None)
+                    .await
+                    .is_err(),
+                "{field:?} is refused"
+            );
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn follow_row_changes() -> Result<()> {
+        let node = iroh::node::Node::memory().spawn().await?;
+        let space = Space::open_in_memory(
+            Uuid::new_v4(),
+            "changes".to_string(),
+            iroh::docs::NamespaceSecret::new(&mut rand::thread_rng()),
+            node.client().clone(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+        )
+        .await?;
+        let author = Author::new(&mut rand::thread_rng());
+        let schema = json!({ "title": "notes", "type": "object" });
+        let table = space
+            .tables()
+            .create(author.clone(), Bytes::from(schema.to_string()))
+            .await?;
+        let schema = table.content.hash;
+        let rows = space.rows();
+        let row = rows
+            .create(author.clone(), schema, json!({ "text": "one" }))
+            .await?;
+
+        let first = rows.changes(schema, 0).await?;
+        assert!(matches!(&first.changes[..], [RowChange::Insert { row: r }] if r.id == row.id));
+
+        rows.mutate(author.clone(), schema, row.id, json!({ "text": "two" }))
+            .await?;
+        rows.delete(author.clone(), schema, row.id).await?;
+        let rest = rows.changes(schema, first.cursor).await?;
+        match &rest.changes[..] {
+            [RowChange::Update { row: updated }, RowChange::Delete { id }] => {
+                assert_eq!(updated.content.data, Some(json!({ "text": "two" })));
+                assert_eq!(*id, row.id);
+            }
+            other => panic!("unexpected changes {other:?}"),
+        }
+        assert!(rows.changes(schema, rest.cursor).await?.changes.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn aggregate_only_live_rows_of_the_table() -> Result<()> {
+        let node = iroh::node::Node::memory().spawn().await?;
+        let space = Space::open_in_memory(
+            Uuid::new_v4(),
+            "live aggregates".to_string(),
+            iroh::docs::NamespaceSecret::new(&mut rand::thread_rng()),
+            node.client().clone(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+        )
+        .await?;
+        let author = Author::new(&mut rand::thread_rng());
+        let mut tables = Vec::new();
+        for title in ["orders", "refunds"] {
+            let schema = json!({ "title": title, "type": "object" });
+            let table = space
+                .tables()
+                .create(author.clone(), Bytes::from(schema.to_string()))
+                .await?;
+            tables.push(table.content.hash);
+        }
+        let (orders, refunds) = (tables[0], tables[1]);
+        let rows = space.rows();
+        for (region, kind, total) in [("north", "a", 10), ("north", "b", 20), ("north", "a", 4)] {
+            rows.create(
+                author.clone(),
+                orders,
+                json!({ "region": region, "kind": kind, "total": total }),
+            )
+            .await?;
+        }
+        let gone = rows
+            .create(
+                author.clone(),
+                orders,
+                json!({ "region": "north", "kind": "a", "total": 1 }),
+            )
+            .await?;
+        rows.delete(author.clone(), orders, gone.id).await?;
+        rows.create(
+            author.clone(),
+            refunds,
+            json!({ "region": "north", "kind": "a", "total": 2 }),
+        )
+        .await?;
+
+        let min = Aggregation::Min("total".to_string());
+        let mut groups = rows
+            .aggregate(
+                orders,
+                vec!["region".to_string(), "kind".to_string()],
+                vec![Aggregation::Count, min.clone()],
+                None,
+            )
+            .await?;
+        groups.sort_by_key(|group| group.group["kind"].to_string());
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].group["kind"], json!("a"));
+        assert_eq!(groups[0].values["count"], json!(2));
+        assert_eq!(groups[0].values[&min.label()], json!(4));
+        assert_eq!(groups[1].values["count"], json!(1));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn hydrate_rows_within_budget() -> Result<()> {
+        let node = iroh::node::Node::memory().spawn().await?;
+        let space = Space::open_in_memory(
+            Uuid::new_v4(),
+            "hydration".to_string(),
+            iroh::docs::NamespaceSecret::new(&mut rand::thread_rng()),
+            node.client().clone(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+        )
+        .await?;
+        let author = Author::new(&mut rand::thread_rng());
+        let schema = json!({ "title": "notes", "type": "object" });
+        let table = space
+            .tables()
+            .create(author.clone(), Bytes::from(schema.to_string()))
+            .await?;
+        let schema = table.content.hash;
+        let rows = space.rows();
+        let mut size = 0;
+        for text in ["a", "b", "c"] {
+            let row = rows
+                .create(author.clone(), schema, json!({ "text": text }))
+                .await?;
+            size = serde_json::to_vec(&row.content.data)?.len() as u64;
+        }
+        // as if the rows came in over sync, with only their content hashes
+        space.db.lock().await.execute(
+            "UPDATE events SET content = NULL WHERE kind = ?1",
+            params![EventKind::MutateRow],
+        )?;
+
+        let hydrated = |rows: Vec<Row>| rows.iter().filter(|r| r.content.data.is_some()).count();
+        let query = RowQuery::default();
+        let found = rows.query_hydrated(schema, &query, 0, -1, 0).await?;
+        assert_eq!(found.len(), 3);
+        assert_eq!(hydrated(found), 0);
+        let found = rows.query_hydrated(schema, &query, 0, -1, 2 * size).await?;
+        assert_eq!(found.len(), 3);
+        assert_eq!(hydrated(found), 2);
+        let found = rows
+            .query_hydrated(schema, &query, 0, -1, DEFAULT_HYDRATE_BUDGET)
+            .await?;
+        assert!(found.iter().all(|row| row
+            .content
+            .data
+            .as_ref()
+            .is_some_and(|data| data["text"].is_string())));
+        Ok(())
     }
 }
@@ -0,0 +1,684 @@
+//! Queries over the rows of a table.
+//!
+//! A [`RowQuery`] filters rows on the values of their top-level fields, orders them & picks
+//! which fields to return. Queries are either built as a struct or parsed from a small syntax:
+//!
+//! ```text
+//! select name, age where age >= 21 and team = "blue" order by age desc, name
+//! ```
+//!
+//! Every clause is optional, an empty query matches every row. `select *` selects every field.
+//! A `from <table>` after the selection is accepted & ignored, queries always run against the
+//! table they're given. Values are JSON literals. Field names that aren't plain identifiers
+//! are wrapped in backticks. [`UPDATED_AT_FIELD`] refers to the time of the latest write to a
+//! row.
+//!
+//! Fields that are filtered or ordered on get an sqlite index for the table, so queries don't
+//! scan every row of big tables. Sealed fields (see [`crate::space::sensitive`]) can't be
+//! queried, sqlite only sees the sealed value.
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Result};
+use iroh::blobs::Hash;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::{json_path, json_to_sql, UPDATED_AT_FIELD};
+use crate::space::events::EventKind;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+#[serde(rename_all = "camelCase")]
+pub struct RowQuery {
+    /// fields to return, all of them when empty
+    #[serde(default)]
+    pub select: Vec<String>,
+    /// rows have to match every filter
+    #[serde(default)]
+    pub filters: Vec<Filter>,
+    #[serde(default)]
+    pub order: Vec<Order>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+#[serde(rename_all = "camelCase")]
+pub struct Filter {
+    pub field: String,
+    pub op: Op,
+    pub value: Value,
+}
+
+impl Filter {
+    pub fn matches(&self, row: &Value) -> bool {
+        let value = match self.field.as_str() {
+            // not part of the content, only known to the db
+            UPDATED_AT_FIELD => return true,
+            field => row.get(field).map(sql_value).unwrap_or(SqlValue::Null),
+        };
+        match (sql_value(&self.value), self.op) {
+            (SqlValue::Null, Op::Eq) => value == SqlValue::Null,
+            (SqlValue::Null, Op::Ne) => value != SqlValue::Null,
+            // any other comparison with null is null, which doesn't match
+            (SqlValue::Null, _) => false,
+            _ if value == SqlValue::Null => false,
+            (other, op) => {
+                let ordering = value.partial_cmp(&other);
+                match op {
+                    Op::Eq => ordering == Some(Ordering::Equal),
+                    Op::Ne => ordering != Some(Ordering::Equal),
+                    Op::Lt => ordering == Some(Ordering::Less),
+                    Op::Lte => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+                    Op::Gt => ordering == Some(Ordering::Greater),
+                    Op::Gte => matches!(ordering, Some(Ordering::Greater | Ordering::Equal)),
+                }
+            }
+        }
+    }
+}
+
+/// A JSON value as sqlite sees it after `json_extract`: booleans are integers, objects &
+/// arrays are JSON text & numbers sort before text.
+#[derive(Debug, PartialEq, PartialOrd)]
+enum SqlValue {
+    Null,
+    Number(f64),
+    Text(String),
+}
+
+fn sql_value(value: &Value) -> SqlValue {
+    match value {
+        Value::Null => SqlValue::Null,
+        Value::Bool(b) => SqlValue::Number(*b as u8 as f64),
+        Value::Number(n) => SqlValue::Number(n.as_f64().unwrap_or_default()),
+        Value::String(s) => SqlValue::Text(s.clone()),
+        v => SqlValue::Text(v.to_string()),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+#[serde(rename_all = "camelCase")]
+pub enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Lte,
+    Gt,
+    Gte,
+}
+
+impl Op {
+    fn sql(&self) -> &'static str {
+        match self {
+            Op::Eq => "=",
+            Op::Ne => "!=",
+            Op::Lt => "<",
+            Op::Lte => "<=",
+            Op::Gt => ">",
+            Op::Gte => ">=",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+#[serde(rename_all = "camelCase")]
+pub struct Order {
+    pub field: String,
+    #[serde(default)]
+    pub descending: bool,
+}
+
+impl RowQuery {
+    /// Fields the query filters or orders on, which are worth indexing.
+    pub(crate) fn indexed_fields(&self) -> Vec<&str> {
+        let mut fields: Vec<&str> = self
+            .filters
+            .iter()
+            .map(|filter| filter.field.as_str())
+            .chain(self.order.iter().map(|order| order.field.as_str()))
+            .filter(|field| *field != UPDATED_AT_FIELD)
+            .collect();
+        fields.sort();
+        fields.dedup();
+        fields
+    }
+
+    /// The index the query should read rows of table `schema` through, if any. Without stats
+    /// sqlite prefers the index on event kinds, which reads every row of the table, so
+    /// queries name the index themselves. A filter that narrows the rows down beats one that
+    /// doesn't, which beats the ordering.
+    pub(crate) fn index(&self, schema: Hash) -> Option<String> {
+        let narrowing = self
+            .filters
+            .iter()
+            .filter(|filter| filter.op != Op::Ne)
+            .map(|filter| &filter.field);
+        let field = narrowing
+            .chain(self.filters.iter().map(|filter| &filter.field))
+            .chain(self.order.iter().map(|order| &order.field))
+            .find(|field| *field != UPDATED_AT_FIELD)?;
+        Some(index_name(&schema.to_string(), field))
+    }
+
+    /// The WHERE conditions & ORDER BY clause of the query, parameters are numbered from
+    /// `first_param`.
+    pub(crate) fn sql(
+        &self,
+        first_param: usize,
+    ) -> (Vec<String>, String, Vec<rusqlite::types::Value>) {
+        let mut conditions = Vec::new();
+        let mut params = Vec::new();
+        for filter in &self.filters {
+            let column = column(&filter.field);
+            match (&filter.value, filter.op) {
+                (Value::Null, Op::Eq) => conditions.push(format!("{column} IS NULL")),
+                (Value::Null, Op::Ne) => conditions.push(format!("{column} IS NOT NULL")),
+                (value, op) => {
+                    params.push(json_to_sql(value));
+                    conditions.push(format!(
+                        "{column} {} ?{}",
+                        op.sql(),
+                        first_param + params.len() - 1
+                    ));
+                }
+            }
+        }
+        let order = match self.order.is_empty() {
+            true => String::new(),
+            false => {
+                let terms: Vec<_> = self
+                    .order
+                    .iter()
+                    .map(|order| match order.descending {
+                        true => format!("{} DESC", column(&order.field)),
+                        false => column(&order.field),
+                    })
+                    // ties in a stable order, so pages don't overlap
+                    .chain(std::iter::once("data_id".to_string()))
+                    .collect();
+                format!("ORDER BY {}", terms.join(", "))
+            }
+        };
+        (conditions, order, params)
+    }
+
+    /// Whether a row's content matches every filter, compared the way sqlite compares the
+    /// values when querying.
+    pub fn matches(&self, row: &Value) -> bool {
+        self.filters.iter().all(|filter| filter.matches(row))
+    }
+
+    /// Leave only the selected fields in a row's content.
+    pub(crate) fn project(&self, data: &mut Value) {
+        if self.select.is_empty() {
+            return;
+        }
+        if let Some(object) = data.as_object_mut() {
+            object.retain(|field, _| self.select.iter().any(|selected| selected == field));
+        }
+    }
+}
+
+/// The sql expression for a field of a row. Paths are written inline rather than bound,
+/// sqlite only uses an index on an expression when the query repeats it exactly.
+fn column(field: &str) -> String {
+    match field {
+        UPDATED_AT_FIELD => "created_at".to_string(),
+        field => format!(
+            "json_extract(CAST(content AS TEXT), '{}')",
+            json_path(field).replace('\'', "''")
+        ),
+    }
+}
+
+fn index_name(schema: &str, field: &str) -> String {
+    format!("rows_{}_{}", &schema[..16], hex::encode(field))
+}
+
+/// A partial index on `field` of the rows of table `schema`.
+pub(crate) fn index_sql(schema: Hash, field: &str) -> String {
+    let schema = schema.to_string();
+    format!(
+        "CREATE INDEX IF NOT EXISTS \"{}\" ON events ({}, data_id) WHERE kind = {} AND schema_hash = '{}'",
+        index_name(&schema, field),
+        column(field),
+        EventKind::MutateRow.kind(),
+        schema,
+    )
+}
+
+impl FromStr for RowQuery {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parser = Parser {
+            tokens: tokenize(s)?,
+            pos: 0,
+        };
+        let mut query = RowQuery::default();
+        if parser.keyword("select") && !parser.star() {
+            loop {
+                query.select.push(parser.field()?);
+                if !parser.comma() {
+                    break;
+                }
+            }
+        }
+        // the table comes from the schema the query runs against, a name is only for show
+        if parser.keyword("from") {
+            parser.field()?;
+        }
+        if parser.keyword("where") {
+            loop {
+                let field = parser.field()?;
+                let op = match parser.next() {
+                    Some(Token::Op(op)) => op,
+                    other => bail!(
+                        "expected a comparison after {field}, got {}",
+                        describe(&other)
+                    ),
+                };
+                let value = match parser.next() {
+                    Some(Token::Value(value)) => value,
+                    Some(Token::Ident(ident)) => match ident.as_str() {
+                        "true" => Value::Bool(true),
+                        "false" => Value::Bool(false),
+                        "null" => Value::Null,
+                        _ => bail!("expected a value, got {ident}, quote strings"),
+                    },
+                    other => bail!("expected a value, got {}", describe(&other)),
+                };
+                query.filters.push(Filter { field, op, value });
+                if !parser.keyword("and") {
+                    break;
+                }
+            }
+        }
+        if parser.keyword("order") {
+            if !parser.keyword("by") {
+                bail!("expected by after order");
+            }
+            loop {
+                let field = parser.field()?;
+                let descending = parser.keyword("desc");
+                if !descending {
+                    parser.keyword("asc");
+                }
+                query.order.push(Order { field, descending });
+                if !parser.comma() {
+                    break;
+                }
+            }
+        }
+        if let Some(token) = parser.next() {
+            bail!("unexpected {}", describe(&Some(token)));
+        }
+        Ok(query)
+    }
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.sql())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    /// a backtick quoted field name
+    Quoted(String),
+    Value(Value),
+    Op(Op),
+    Comma,
+    Star,
+}
+
+fn describe(token: &Option<Token>) -> String {
+    match token {
+        None => "the end of the query".to_string(),
+        Some(Token::Ident(ident)) | Some(Token::Quoted(ident)) => ident.clone(),
+        Some(Token::Value(value)) => value.to_string(),
+        Some(Token::Op(op)) => op.to_string(),
+        Some(Token::Comma) => ",".to_string(),
+        Some(Token::Star) => "*".to_string(),
+    }
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = s.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            ',' => {
+                chars.next();
+                tokens.push(Token::Comma);
+            }
+            '*' => {
+                chars.next();
+                tokens.push(Token::Star);
+            }
+            '=' | '!' | '<' | '>' => {
+                chars.next();
+                let eq = chars.next_if(|(_, c)| *c == '=').is_some();
+                let op = match (c, eq) {
+                    ('=', _) => Op::Eq,
+                    ('!', true) => Op::Ne,
+                    ('<', false) => Op::Lt,
+                    ('<', true) => Op::Lte,
+                    ('>', false) => Op::Gt,
+                    ('>', true) => Op::Gte,
+                    _ => bail!("unexpected ! at {start}"),
+                };
+                tokens.push(Token::Op(op));
+            }
+            '`' => {
+                chars.next();
+                let mut name = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '`')) => break,
+                        Some((_, c)) => name.push(c),
+                        None => bail!("unclosed ` at {start}"),
+                    }
+                }
+                tokens.push(Token::Quoted(name));
+            }
+            '"' => {
+                chars.next();
+                let mut end = None;
+                let mut escaped = false;
+                for (i, c) in chars.by_ref() {
+                    match c {
+                        '\\' if !escaped => escaped = true,
+                        '"' if !escaped => {
+                            end = Some(i);
+                            break;
+                        }
+                        _ => escaped = false,
+                    }
+                }
+                let end = end.ok_or_else(|| anyhow!("unclosed string at {start}"))?;
+                tokens.push(Token::Value(serde_json::from_str(&s[start..=end])?));
+            }
+            c if c == '-' || c.is_ascii_digit() => {
+                let mut end = start;
+                while let Some((i, c)) = chars
+                    .next_if(|(_, c)| c.is_ascii_alphanumeric() || matches!(c, '-' | '+' | '.'))
+                {
+                    end = i + c.len_utf8();
+                }
+                let number: serde_json::Number = s[start..end]
+                    .parse()
+                    .map_err(|_| anyhow!("invalid number {}", &s[start..end]))?;
+                tokens.push(Token::Value(Value::Number(number)));
+            }
+            c if c.is_alphanumeric() || c == '_' => {
+                let mut ident = String::new();
+                while let Some((_, c)) = chars.next_if(|(_, c)| c.is_alphanumeric() || *c == '_') {
+                    ident.push(c);
+                }
+                tokens.push(Token::Ident(ident));
+            }
+            c => bail!("unexpected {c} at {start}"),
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    /// Consume `keyword` if it's next, ignoring case.
+    fn keyword(&mut self, keyword: &str) -> bool {
+        match self.tokens.get(self.pos) {
+            Some(Token::Ident(ident)) if ident.eq_ignore_ascii_case(keyword) => {
+                self.pos += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn comma(&mut self) -> bool {
+        self.token(Token::Comma)
+    }
+
+    fn star(&mut self) -> bool {
+        self.token(Token::Star)
+    }
+
+    /// Consume `token` if it's next.
+    fn token(&mut self, token: Token) -> bool {
+        let found = self.tokens.get(self.pos) == Some(&token);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn field(&mut self) -> Result<String> {
+        match self.next() {
+            Some(Token::Ident(field)) | Some(Token::Quoted(field)) => Ok(field),
+            other => Err(anyhow!("expected a field, got {}", describe(&other))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use iroh::docs::Author;
+    use serde_json::json;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::space::Space;
+
+    #[test]
+    fn parse_queries() {
+        assert_eq!(RowQuery::from_str("").unwrap(), RowQuery::default());
+        assert_eq!(RowQuery::from_str("  ").unwrap(), RowQuery::default());
+        assert_eq!(
+            RowQuery::from_str("select * from github_users").unwrap(),
+            RowQuery::default()
+        );
+
+        let query = RowQuery::from_str(
+            r#"SELECT name, `full name` WHERE age >= 21 AND team = "blue \"b\"" and gone != null ORDER BY age DESC, name"#,
+        )
+        .unwrap();
+        assert_eq!(
+            query,
+            RowQuery {
+                select: vec!["name".into(), "full name".into()],
+                filters: vec![
+                    Filter {
+                        field: "age".into(),
+                        op: Op::Gte,
+                        value: json!(21)
+                    },
+                    Filter {
+                        field: "team".into(),
+                        op: Op::Eq,
+                        value: json!("blue \"b\"")
+                    },
+                    Filter {
+                        field: "gone".into(),
+                        op: Op::Ne,
+                        value: Value::Null
+                    },
+                ],
+                order: vec![
+                    Order {
+                        field: "age".into(),
+                        descending: true
+                    },
+                    Order {
+                        field: "name".into(),
+                        descending: false
+                    },
+                ],
+            }
+        );
+        assert_eq!(query.indexed_fields(), vec!["age", "gone", "name", "team"]);
+
+        let query = RowQuery::from_str("where score < -1.5e3 and ok = true").unwrap();
+        assert_eq!(query.filters[0].value, json!(-1500.0));
+        assert_eq!(query.filters[1].value, json!(true));
+
+        for bad in [
+            "where age",
+            "where age > ",
+            "where name = ada",
+            "order age",
+            "select",
+            "where a = 1 or b = 2",
+            "where a = \"open",
+        ] {
+            assert!(RowQuery::from_str(bad).is_err(), "{bad} should fail");
+        }
+    }
+
+    #[test]
+    fn match_rows() {
+        let row = json!({ "name": "ada", "age": 36, "admin": true, "tags": ["a"] });
+        let matches = |query: &str| RowQuery::from_str(query).unwrap().matches(&row);
+        assert!(matches(""));
+        assert!(matches(r#"where name = "ada" and age > 30 and age <= 36"#));
+        assert!(!matches("where age < 36"));
+        assert!(matches("where admin = true and admin != 0"));
+        assert!(matches("where missing = null and name != null"));
+        assert!(
+            !matches("where missing != 1"),
+            "comparing null never matches"
+        );
+        assert!(matches(r#"where age < "1""#), "numbers sort before text");
+        assert!(matches(r#"where tags = "[\"a\"]""#));
+    }
+
+    #[test]
+    fn filters_bind_values() {
+        let query =
+            RowQuery::from_str(r#"where age > 3 and name = "o'neil" and x = null"#).unwrap();
+        let (conditions, order, params) = query.sql(4);
+        assert_eq!(
+            conditions,
+            vec![
+                r#"json_extract(CAST(content AS TEXT), '$."age"') > ?4"#,
+                r#"json_extract(CAST(content AS TEXT), '$."name"') = ?5"#,
+                r#"json_extract(CAST(content AS TEXT), '$."x"') IS NULL"#,
+            ]
+        );
+        assert!(order.is_empty());
+        assert_eq!(params.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn query_rows() -> Result<()> {
+        let node = iroh::node::Node::memory().spawn().await?;
+        let space = Space::open_in_memory(
+            Uuid::new_v4(),
+            "query".to_string(),
+            iroh::docs::NamespaceSecret::new(&mut rand::thread_rng()),
+            node.client().clone(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+        )
+        .await?;
+        let author = Author::new(&mut rand::thread_rng());
+        let schema = json!({
+            "title": "people",
+            "type": "object",
+            "properties": {
+                "name": { "type": "string" },
+                "age": { "type": "number" },
+            },
+        });
+        let table = space
+            .tables()
+            .create(author.clone(), Bytes::from(schema.to_string()))
+            .await?;
+        let rows = space.rows();
+        let schema = table.content.hash;
+        for (name, age) in [("ada", 36), ("grace", 85), ("alan", 41)] {
+            rows.create(author.clone(), schema, json!({ "name": name, "age": age }))
+                .await?;
+        }
+        let linus = rows
+            .create(
+                author.clone(),
+                schema,
+                json!({ "name": "linus", "age": 20 }),
+            )
+            .await?;
+        // only the latest version of a row is matched
+        rows.mutate(
+            author.clone(),
+            schema,
+            linus.id,
+            json!({ "name": "linus", "age": 55 }),
+        )
+        .await?;
+
+        let names = |rows: &[crate::space::rows::Row]| {
+            rows.iter()
+                .map(|row| row.content.data.as_ref().unwrap()["name"].clone())
+                .collect::<Vec<_>>()
+        };
+        let all = rows.query(schema, String::new(), 0, -1).await?;
+        assert_eq!(all.len(), 4);
+
+        let query = "select name where age > 40 order by age desc".to_string();
+        let found = rows.query(schema, query.clone(), 0, -1).await?;
+        assert_eq!(
+            names(&found),
+            vec![json!("grace"), json!("linus"), json!("alan")]
+        );
+        assert_eq!(found[0].content.data, Some(json!({ "name": "grace" })));
+        let page = rows.query(schema, query, 1, 1).await?;
+        assert_eq!(names(&page), vec![json!("linus")]);
+
+        let found = rows
+            .query(schema, r#"where name = "ada""#.to_string(), 0, -1)
+            .await?;
+        assert_eq!(names(&found), vec![json!("ada")]);
+        assert!(rows
+            .query(schema, "where".to_string(), 0, -1)
+            .await
+            .is_err());
+
+        // the table's fields are indexed, and the query uses the index
+        let conn = space.db().lock().await;
+        let (sql, params) = super::super::latest_rows_sql(
+            schema,
+            &RowQuery::from_str("where age > 40").unwrap(),
+            0,
+            -1,
+        );
+        let mut stmt = conn.prepare(&format!("EXPLAIN QUERY PLAN {sql}"))?;
+        let plan: Vec<String> = stmt
+            .query_map(rusqlite::params_from_iter(params), |row| row.get(3))?
+            .collect::<rusqlite::Result<_>>()?;
+        let index = index_name(&schema.to_string(), "age");
+        assert!(plan.iter().any(|step| step.contains(&index)), "{plan:?}");
+        Ok(())
+    }
+}
@@ -0,0 +1,320 @@
+//! The history of program runs in a space.
+//!
+//! Every run of a program is recorded as a `MutateRun` event, so run history syncs to peers
+//! like any other space data. A run records which environment keys it was given, not their
+//! values, when it started & finished, how it ended & the hash of its full output, which is
+//! kept in the blob store. Runs whose output carries a [`Render`] keep it alongside, for the
+//! app to display.
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+use iroh::blobs::Hash;
+use iroh::docs::Author;
+use iroh::net::key::PublicKey;
+use rusqlite::params;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::router::RouterClient;
+use crate::vm::flow::TaskOutput;
+use crate::vm::job::JobResultStatus;
+
+use super::clock::Hlc;
+use super::events::{Event, EventKind, EventObject, HashLink, Tag, NOSTR_ID_TAG};
+use super::render::Render;
+use super::{Space, EVENT_SQL_READ_FIELDS};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+#[serde(rename_all = "camelCase")]
+pub enum RunStatus {
+    Succeeded,
+    Failed,
+    TimedOut,
+    /// the run ended without a result
+    Unknown,
+}
+
+impl From<&JobResultStatus> for RunStatus {
+    fn from(status: &JobResultStatus) -> Self {
+        match status {
+            JobResultStatus::Ok(_) => RunStatus::Succeeded,
+            JobResultStatus::Err(_) => RunStatus::Failed,
+            JobResultStatus::ErrTimeout | JobResultStatus::ErrDeadlineExceeded => {
+                RunStatus::TimedOut
+            }
+            JobResultStatus::Unknown => RunStatus::Unknown,
+        }
+    }
+}
+
+/// What's recorded about a run, the content of its event.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RunContent {
+    program_id: Uuid,
+    environment_keys: Vec<String>,
+    started_at: i64,
+    finished_at: i64,
+    status: RunStatus,
+    output: Hash,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    render: Option<Render>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+#[serde(rename_all = "camelCase")]
+pub struct Run {
+    pub id: Uuid,
+    pub created_at: i64,
+    /// who ran the program
+    #[cfg_attr(feature = "specta", specta(type = String))]
+    pub author: PublicKey,
+    pub content: HashLink,
+    pub program_id: Uuid,
+    /// keys of the environment the program was run with, sorted
+    pub environment_keys: Vec<String>,
+    /// unix seconds
+    pub started_at: i64,
+    /// unix seconds
+    pub finished_at: i64,
+    pub status: RunStatus,
+    /// the run's [`TaskOutput`], see [`Runs::output`]
+    #[cfg_attr(feature = "specta", specta(type = String))]
+    pub output: Hash,
+    /// what the program asked to be displayed as, if anything
+    pub render: Option<Render>,
+}
+
+impl EventObject for Run {
+    async fn from_event(event: Event, router: &RouterClient) -> Result<Self> {
+        if event.kind != EventKind::MutateRun {
+            return Err(anyhow!("event is not a run mutation"));
+        }
+        let id = event.data_id()?.ok_or_else(|| anyhow!("missing data id"))?;
+        let mut content = event.content;
+        let details: RunContent = serde_json::from_value(content.resolve(router).await?)?;
+        Ok(Run {
+            id,
+            created_at: event.created_at,
+            author: event.pubkey,
+            content,
+            program_id: details.program_id,
+            environment_keys: details.environment_keys,
+            started_at: details.started_at,
+            finished_at: details.finished_at,
+            status: details.status,
+            output: details.output,
+            render: details.render,
+        })
+    }
+
+    fn into_mutate_event(&self, author: Author, hlc: Hlc) -> Result<Event> {
+        let tags = vec![Tag::new(NOSTR_ID_TAG, self.id.to_string().as_str())];
+        Event::create(
+            author,
+            self.created_at,
+            hlc,
+            EventKind::MutateRun,
+            tags,
+            self.content.clone(),
+        )
+    }
+}
+
+impl Run {
+    async fn from_sql_row(row: &rusqlite::Row<'_>, router: &RouterClient) -> Result<Run> {
+        let event = Event::from_sql_row(row)?;
+        Self::from_event(event, router).await
+    }
+}
+
+pub struct Runs(Space);
+
+impl Runs {
+    pub fn new(space: Space) -> Self {
+        Runs(space)
+    }
+
+    /// Record a finished run of `program_id`. `started_at` is in unix seconds.
+    pub async fn record(
+        &self,
+        author: Author,
+        program_id: Uuid,
+        environment: &HashMap<String, String>,
+        started_at: i64,
+        output: &TaskOutput,
+    ) -> Result<Run> {
+        let blobs = self.0.router.blobs();
+        let output_hash = blobs.add_bytes(serde_json::to_vec(output)?).await?.hash;
+
+        let mut environment_keys: Vec<_> = environment.keys().cloned().collect();
+        environment_keys.sort();
+        let render = Render::from_output(output).unwrap_or_else(|err| {
+            warn!(
+                "program {} printed an invalid render: {:?}",
+                program_id, err
+            );
+            None
+        });
+        let details = RunContent {
+            program_id,
+            environment_keys,
+            started_at,
+            finished_at: chrono::Utc::now().timestamp(),
+            status: RunStatus::from(&output.result.status),
+            output: output_hash,
+            render,
+        };
+        let value = serde_json::to_value(&details)?;
+        let content_hash = blobs.add_bytes(serde_json::to_vec(&value)?).await?.hash;
+
+        let pubkey = PublicKey::from_bytes(author.public_key().as_bytes())?;
+        let run = Run {
+            id: Uuid::new_v4(),
+            created_at: details.finished_at,
+            author: pubkey,
+            content: HashLink {
+                hash: content_hash,
+                data: Some(value),
+            },
+            program_id,
+            environment_keys: details.environment_keys,
+            started_at,
+            finished_at: details.finished_at,
+            status: details.status,
+            output: output_hash,
+            render: details.render,
+        };
+        self.0
+            .write_event(&run.into_mutate_event(author, self.0.clock().tick())?)
+            .await?;
+        Ok(run)
+    }
+
+    pub async fn get_by_id(&self, id: Uuid) -> Result<Run> {
+        let conn = self.0.db.lock().await;
+        let mut stmt = conn.prepare(
+            format!("SELECT {EVENT_SQL_READ_FIELDS} FROM events WHERE kind = ?1 AND data_id = ?2 ORDER BY clock DESC LIMIT 1")
+                .as_str(),
+        )?;
+        let mut rows = stmt.query(params![EventKind::MutateRun, id])?;
+        let row = rows.next()?.ok_or_else(|| anyhow!("run not found"))?;
+        Run::from_sql_row(row, &self.0.router).await
+    }
+
+    /// Runs of a program, most recent first.
+    pub async fn list_for_program(
+        &self,
+        program_id: Uuid,
+        offset: i64,
+        limit: i64,
+    ) -> Result<Vec<Run>> {
+        let conn = self.0.db.lock().await;
+        let mut stmt = conn.prepare(
+            format!("SELECT {EVENT_SQL_READ_FIELDS} FROM events WHERE kind = ?1 AND json_extract(CAST(content AS TEXT), '$.programId') = ?2 ORDER BY clock DESC LIMIT ?3 OFFSET ?4")
+                .as_str(),
+        )?;
+        let mut rows = stmt.query(params![
+            EventKind::MutateRun,
+            program_id.to_string(),
+            limit,
+            offset
+        ])?;
+
+        let mut runs = Vec::new();
+        while let Some(row) = rows.next()? {
+            runs.push(Run::from_sql_row(row, &self.0.router).await?);
+        }
+        Ok(runs)
+    }
+
+    /// The full output of a run.
+    pub async fn output(&self, run: &Run) -> Result<TaskOutput> {
+        let data = self.0.router.blobs().read_to_bytes(run.output).await?;
+        Ok(serde_json::from_slice(&data)?)
+    }
+
+    /// What a run asked to be displayed as, none for runs that printed plain output.
+    pub async fn render(&self, id: Uuid) -> Result<Option<Render>> {
+        Ok(self.get_by_id(id).await?.render)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::vm::job::{JobOutput, JobResult};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn record_and_list() -> Result<()> {
+        let node = iroh::node::Node::memory().spawn().await?;
+        let space = Space::open_in_memory(
+            Uuid::new_v4(),
+            "runs".to_string(),
+            iroh::docs::NamespaceSecret::new(&mut rand::thread_rng()),
+            node.client().clone(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+        )
+        .await?;
+        let author = Author::new(&mut rand::thread_rng());
+        let (program, other) = (Uuid::new_v4(), Uuid::new_v4());
+        let output = TaskOutput {
+            name: "main".to_string(),
+            id: Uuid::new_v4(),
+            result: JobResult {
+                status: JobResultStatus::Ok(JobOutput::Wasm {
+                    output: "hi".to_string(),
+                }),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let environment = HashMap::from([
+            ("b".to_string(), "2".to_string()),
+            ("a".to_string(), "1".to_string()),
+        ]);
+
+        let runs = space.runs();
+        let first = runs
+            .record(author.clone(), program, &environment, 1, &output)
+            .await?;
+        runs.record(author.clone(), other, &HashMap::new(), 2, &output)
+            .await?;
+        assert_eq!(first.environment_keys, vec!["a", "b"]);
+        assert_eq!(first.status, RunStatus::Succeeded);
+
+        let listed = runs.list_for_program(program, 0, -1).await?;
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].id, first.id);
+        let fetched = runs.get_by_id(first.id).await?;
+        assert_eq!(fetched.program_id, program);
+        assert_eq!(runs.output(&fetched).await?, output);
+        assert_eq!(runs.render(first.id).await?, None);
+
+        let rendered = TaskOutput {
+            result: JobResult {
+                status: JobResultStatus::Ok(JobOutput::Wasm {
+                    output: r#"{"render": {"type": "markdown", "text": "**hi**"}}"#.to_string(),
+                }),
+                ..Default::default()
+            },
+            ..output.clone()
+        };
+        let run = runs
+            .record(author.clone(), program, &HashMap::new(), 3, &rendered)
+            .await?;
+        assert_eq!(
+            runs.render(run.id).await?,
+            Some(Render::Markdown {
+                text: "**hi**".to_string()
+            })
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,346 @@
+//! Schedules run a program on a timer: every interval, eg: `15m`, or whenever a cron expression
+//! matches, eg: `0 9 * * MON-FRI`.
+//!
+//! A schedule is a `MutateSchedule` event holding the program to run, its [`Cadence`] & when it
+//! last ran. Firing a schedule writes a new version of its event with the time it fired, so the
+//! bookkeeping syncs along with the schedule. Schedules only fire on nodes holding the key of
+//! the author that created them, see [`crate::vm::cron`] for how they're run. Runs missed
+//! while no such node was up are made up for with a single run.
+use std::str::FromStr;
+
+use anyhow::{anyhow, ensure, Context, Result};
+use chrono::{DateTime, Utc};
+use iroh::docs::Author;
+use iroh::net::key::PublicKey;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::router::RouterClient;
+use crate::vm::duration;
+
+use super::clock::Hlc;
+use super::events::{
+    Event, EventKind, EventObject, HashLink, Tag, EVENT_SQL_READ_FIELDS, NOSTR_ID_TAG,
+};
+use super::Space;
+
+/// shortest time between two runs of a schedule
+pub const MIN_INTERVAL: i64 = 60;
+
+/// When a schedule runs.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum Cadence {
+    /// a cron expression in UTC, the seconds field is optional
+    Cron { expression: String },
+    /// a duration between runs like `90s`, `15m` or `1h30m`
+    Interval { every: String },
+}
+
+impl Cadence {
+    pub fn validate(&self) -> Result<()> {
+        match self {
+            Cadence::Cron { expression } => {
+                let schedule = parse_cron(expression)?;
+                let mut upcoming = schedule.upcoming(Utc);
+                if let (Some(first), Some(second)) = (upcoming.next(), upcoming.next()) {
+                    ensure!(
+                        (second - first).num_seconds() >= MIN_INTERVAL,
+                        "{expression:?} runs more often than every {MIN_INTERVAL} seconds"
+                    );
+                }
+            }
+            Cadence::Interval { every } => {
+                let interval = duration::parse(every)?;
+                ensure!(
+                    interval.whole_seconds() >= MIN_INTERVAL,
+                    "schedules run at most every {MIN_INTERVAL} seconds, not every {every}"
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// The first time to run after `after`, both in unix seconds. None for cron expressions
+    /// that never match again.
+    pub fn next_after(&self, after: i64) -> Result<Option<i64>> {
+        match self {
+            Cadence::Cron { expression } => {
+                let after = DateTime::<Utc>::from_timestamp(after, 0)
+                    .ok_or_else(|| anyhow!("invalid timestamp {after}"))?;
+                let next = parse_cron(expression)?.after(&after).next();
+                Ok(next.map(|next| next.timestamp()))
+            }
+            Cadence::Interval { every } => {
+                let interval = duration::parse(every)?.whole_seconds();
+                Ok(Some(after + interval.max(MIN_INTERVAL)))
+            }
+        }
+    }
+}
+
+/// Parse a cron expression, defaulting the seconds field to 0 for the common five field form.
+fn parse_cron(expression: &str) -> Result<cron::Schedule> {
+    let expression = expression.trim();
+    let expression = match expression.split_whitespace().count() {
+        5 => format!("0 {expression}"),
+        _ => expression.to_string(),
+    };
+    cron::Schedule::from_str(&expression)
+        .with_context(|| format!("invalid cron expression {expression:?}"))
+}
+
+/// What's stored about a schedule, the content of its event.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ScheduleContent {
+    program_id: Uuid,
+    cadence: Cadence,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    last_run: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+#[serde(rename_all = "camelCase")]
+pub struct Schedule {
+    pub id: Uuid,
+    pub created_at: i64,
+    #[cfg_attr(feature = "specta", specta(type = String))]
+    pub author: PublicKey,
+    pub content: HashLink,
+    pub program_id: Uuid,
+    pub cadence: Cadence,
+    /// when the schedule last fired, in unix seconds. None if it hasn't yet
+    pub last_run: Option<i64>,
+    /// when the schedule fires next, in unix seconds. None if it never will
+    pub next_run: Option<i64>,
+}
+
+impl Schedule {
+    /// Whether the schedule should fire at `now`.
+    pub fn is_due(&self, now: i64) -> bool {
+        self.next_run.is_some_and(|next| next <= now)
+    }
+
+    fn details(&self) -> ScheduleContent {
+        ScheduleContent {
+            program_id: self.program_id,
+            cadence: self.cadence.clone(),
+            last_run: self.last_run,
+        }
+    }
+}
+
+impl EventObject for Schedule {
+    async fn from_event(event: Event, router: &RouterClient) -> Result<Self> {
+        if event.kind != EventKind::MutateSchedule {
+            return Err(anyhow!("event is not a schedule mutation"));
+        }
+        let id = event.data_id()?.ok_or_else(|| anyhow!("missing data id"))?;
+        let mut content = event.content;
+        let details: ScheduleContent = serde_json::from_value(content.resolve(router).await?)?;
+        let next_run = details
+            .cadence
+            .next_after(details.last_run.unwrap_or(event.created_at))?;
+        Ok(Schedule {
+            id,
+            created_at: event.created_at,
+            author: event.pubkey,
+            content,
+            program_id: details.program_id,
+            cadence: details.cadence,
+            last_run: details.last_run,
+            next_run,
+        })
+    }
+
+    fn into_mutate_event(&self, author: Author, hlc: Hlc) -> Result<Event> {
+        let tags = vec![Tag::new(NOSTR_ID_TAG, self.id.to_string().as_str())];
+        Event::create(
+            author,
+            self.created_at,
+            hlc,
+            EventKind::MutateSchedule,
+            tags,
+            self.content.clone(),
+        )
+    }
+}
+
+pub struct Schedules(Space);
+
+impl Schedules {
+    pub fn new(space: Space) -> Self {
+        Schedules(space)
+    }
+
+    /// Run `program_id` on `cadence`, starting from now.
+    pub async fn create(
+        &self,
+        author: Author,
+        program_id: Uuid,
+        cadence: Cadence,
+    ) -> Result<Schedule> {
+        cadence.validate()?;
+        self.0
+            .programs()
+            .get_by_id(program_id)
+            .await
+            .context("loading scheduled program")?;
+
+        let created_at = chrono::Utc::now().timestamp();
+        let details = ScheduleContent {
+            program_id,
+            cadence,
+            last_run: None,
+        };
+        let schedule = Schedule {
+            id: Uuid::new_v4(),
+            created_at,
+            author: PublicKey::from_bytes(author.public_key().as_bytes())?,
+            content: self.store(&details).await?,
+            program_id,
+            next_run: details.cadence.next_after(created_at)?,
+            cadence: details.cadence,
+            last_run: None,
+        };
+        self.write(author, &schedule).await?;
+        Ok(schedule)
+    }
+
+    /// Note that a schedule fired at `at`, moving its next run along.
+    pub async fn record_run(&self, author: Author, id: Uuid, at: i64) -> Result<Schedule> {
+        let mut schedule = self.get(id).await?;
+        schedule.last_run = Some(at);
+        schedule.next_run = schedule.cadence.next_after(at)?;
+        schedule.content = self.store(&schedule.details()).await?;
+        self.write(author, &schedule).await?;
+        Ok(schedule)
+    }
+
+    /// Stop a schedule from firing. Deleted schedules are gone for good.
+    pub async fn delete(&self, author: Author, id: Uuid) -> Result<()> {
+        let schedule = self.get(id).await?;
+        let tags = vec![Tag::new(NOSTR_ID_TAG, id.to_string().as_str())];
+        let event = Event::create(
+            author,
+            chrono::Utc::now().timestamp(),
+            self.0.clock().tick(),
+            EventKind::DeleteSchedule,
+            tags,
+            HashLink::from(schedule.content.hash),
+        )?;
+        self.0.write_event(&event).await
+    }
+
+    pub async fn get(&self, id: Uuid) -> Result<Schedule> {
+        let mut schedules = self.select("data_id = ?2", &[&id]).await?;
+        schedules.pop().ok_or_else(|| anyhow!("schedule not found"))
+    }
+
+    pub async fn list(&self) -> Result<Vec<Schedule>> {
+        self.select("1", &[]).await
+    }
+
+    async fn store(&self, details: &ScheduleContent) -> Result<HashLink> {
+        let value = serde_json::to_value(details)?;
+        let content = self
+            .0
+            .router
+            .blobs()
+            .add_bytes(serde_json::to_vec(&value)?)
+            .await?;
+        Ok(HashLink {
+            hash: content.hash,
+            data: Some(value),
+        })
+    }
+
+    /// Write a new version of the schedule's event.
+    async fn write(&self, author: Author, schedule: &Schedule) -> Result<()> {
+        self.0
+            .write_event(&schedule.into_mutate_event(author, self.0.clock().tick())?)
+            .await
+    }
+
+    /// The latest version of each schedule that hasn't been deleted & matches `condition`,
+    /// which takes its parameters from `?2` on.
+    async fn select(
+        &self,
+        condition: &str,
+        params: &[&(dyn rusqlite::ToSql + Sync)],
+    ) -> Result<Vec<Schedule>> {
+        let sql = format!(
+            "SELECT {EVENT_SQL_READ_FIELDS}, MAX(clock) FROM events
+            WHERE kind = ?1 AND {condition}
+            AND data_id NOT IN (SELECT data_id FROM events WHERE kind = {deleted})
+            GROUP BY data_id ORDER BY clock",
+            deleted = EventKind::DeleteSchedule.kind(),
+        );
+        let events = {
+            let conn = self.0.db.lock().await;
+            let mut stmt = conn.prepare(&sql)?;
+            let mut params: Vec<&dyn rusqlite::ToSql> = params
+                .iter()
+                .map(|param| *param as &dyn rusqlite::ToSql)
+                .collect();
+            params.insert(0, &EventKind::MutateSchedule);
+            let mut rows = stmt.query(params.as_slice())?;
+            let mut events = Vec::new();
+            while let Some(row) = rows.next()? {
+                events.push(Event::from_sql_row(row)?);
+            }
+            events
+        };
+        let mut schedules = Vec::new();
+        for event in events {
+            schedules.push(Schedule::from_event(event, &self.0.router).await?);
+        }
+        Ok(schedules)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cron(expression: &str) -> Cadence {
+        Cadence::Cron {
+            expression: expression.to_string(),
+        }
+    }
+
+    #[test]
+    fn next_runs() -> Result<()> {
+        // 2024-01-01T00:00:30Z, a monday
+        let start = 1_704_067_230;
+        let every = Cadence::Interval {
+            every: "15m".to_string(),
+        };
+        every.validate()?;
+        assert_eq!(every.next_after(start)?, Some(start + 15 * 60));
+
+        let weekdays = cron("0 9 * * MON-FRI");
+        weekdays.validate()?;
+        assert_eq!(
+            weekdays.next_after(start)?,
+            Some(1_704_067_230 - 30 + 9 * 3600)
+        );
+        // with seconds
+        assert_eq!(
+            cron("30 0 0 * * *").next_after(start)?,
+            Some(start + 24 * 3600)
+        );
+
+        assert!(Cadence::Interval {
+            every: "10s".to_string()
+        }
+        .validate()
+        .is_err());
+        assert!(cron("* * * * * *").validate().is_err());
+        assert!(cron("not cron").validate().is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,356 @@
+//! Full-text search over the content of rows, tables & programs.
+//!
+//! Text is pulled out of resolved event content and kept in the `events_fts` FTS5 table, keyed
+//! by the rowid of the event it came from. Events are indexed as [`Space::write_event`] writes
+//! them. Events that arrive any other way, through joining, restores or recovery, are picked up
+//! before the next search. Content that can't be resolved is indexed as empty, so it isn't
+//! retried on every search.
+use anyhow::Result;
+use rusqlite::params;
+use serde::Serialize;
+use serde_json::Value;
+use tracing::debug;
+
+use super::events::{Event, EventKind, EVENT_SQL_READ_FIELDS};
+use super::programs;
+use super::Space;
+
+/// Kinds of events whose content is searchable. Secrets & users are left out on purpose.
+const INDEXED_KINDS: [EventKind; 3] = [
+    EventKind::MutateRow,
+    EventKind::MutateTable,
+    EventKind::MutateProgram,
+];
+/// unindexed events read per batch when catching up
+const CATCH_UP_BATCH: i64 = 500;
+/// tokens of context a snippet shows around matches
+const SNIPPET_TOKENS: i64 = 16;
+/// marks highlighted text in snippets, characters that don't turn up in indexed text
+const HIGHLIGHT_START: char = '\u{2}';
+const HIGHLIGHT_END: char = '\u{3}';
+
+/// An event matched by [`Space::search`].
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+#[serde(rename_all = "camelCase")]
+pub struct SearchMatch {
+    #[serde(flatten)]
+    pub event: Event,
+    /// relevance of the match, higher is better
+    pub score: f64,
+    /// the part of the event's text that matched best
+    pub snippet: Vec<SnippetPart>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+#[serde(rename_all = "camelCase")]
+pub struct SnippetPart {
+    pub text: String,
+    /// whether the text matched a search term
+    pub highlight: bool,
+}
+
+/// Index the text of an event that was just written.
+pub(crate) async fn index_event(space: &Space, event: &Event) -> Result<()> {
+    if !INDEXED_KINDS.contains(&event.kind) {
+        return Ok(());
+    }
+    let text = event_text(space, event).await;
+    let conn = space.db.lock().await;
+    conn.execute(
+        "INSERT OR REPLACE INTO events_fts (rowid, text) SELECT rowid, ?2 FROM events WHERE id = ?1",
+        params![event.id.to_string(), text],
+    )?;
+    Ok(())
+}
+
+/// Index events that were written without going through [`Space::write_event`].
+async fn catch_up(space: &Space) -> Result<()> {
+    let kinds = INDEXED_KINDS
+        .iter()
+        .map(|kind| kind.kind().to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+    loop {
+        let pending = {
+            let conn = space.db.lock().await;
+            let mut stmt = conn.prepare(&format!(
+                "SELECT {EVENT_SQL_READ_FIELDS}, rowid FROM events
+                 WHERE kind IN ({kinds})
+                 AND NOT EXISTS (SELECT 1 FROM events_fts WHERE events_fts.rowid = events.rowid)
+                 LIMIT ?1"
+            ))?;
+            let mut rows = stmt.query(params![CATCH_UP_BATCH])?;
+            let mut pending = Vec::new();
+            while let Some(row) = rows.next()? {
+                let rowid: i64 = row.get(9)?;
+                pending.push((rowid, Event::from_sql_row(row)?));
+            }
+            pending
+        };
+        if pending.is_empty() {
+            return Ok(());
+        }
+        debug!(
+            "indexing {} events of {} for search",
+            pending.len(),
+            space.name
+        );
+        for (rowid, event) in pending {
+            let text = event_text(space, &event).await;
+            let conn = space.db.lock().await;
+            conn.execute(
+                "INSERT OR REPLACE INTO events_fts (rowid, text) VALUES (?1, ?2)",
+                params![rowid, text],
+            )?;
+        }
+    }
+}
+
+/// Events whose text matches `query`, best matches first. Every word in the query has to
+/// match, the last one as a prefix so results can follow typing.
+pub(crate) async fn search(
+    space: &Space,
+    query: &str,
+    offset: i64,
+    limit: i64,
+) -> Result<Vec<SearchMatch>> {
+    let Some(query) = fts_query(query) else {
+        return Ok(Vec::new());
+    };
+    catch_up(space).await?;
+
+    let conn = space.db.lock().await;
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {fields}, bm25(events_fts), snippet(events_fts, 0, ?4, ?5, '…', ?6)
+         FROM events_fts JOIN events ON events.rowid = events_fts.rowid
+         WHERE events_fts MATCH ?1
+         ORDER BY bm25(events_fts), events.clock DESC
+         LIMIT ?2 OFFSET ?3",
+        fields = qualified_fields()
+    ))?;
+    let mut rows = stmt.query(params![
+        query,
+        limit,
+        offset,
+        HIGHLIGHT_START.to_string(),
+        HIGHLIGHT_END.to_string(),
+        SNIPPET_TOKENS,
+    ])?;
+    let mut matches = Vec::new();
+    while let Some(row) = rows.next()? {
+        let event = Event::from_sql_row(row)?;
+        let bm25: f64 = row.get(9)?;
+        let snippet: String = row.get(10)?;
+        matches.push(SearchMatch {
+            event,
+            // bm25 is lower for better matches
+            score: -bm25,
+            snippet: snippet_parts(&snippet),
+        });
+    }
+    Ok(matches)
+}
+
+/// Event fields prefixed with the table, `events_fts` has a rowid too.
+fn qualified_fields() -> String {
+    EVENT_SQL_READ_FIELDS
+        .split(", ")
+        .map(|field| format!("events.{field}"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Turn free text into an FTS5 query, quoting every word so query syntax in the text doesn't
+/// get interpreted.
+fn fts_query(query: &str) -> Option<String> {
+    let words = query
+        .split_whitespace()
+        .map(|word| format!("\"{}\"", word.replace('"', "\"\"")))
+        .collect::<Vec<_>>();
+    if words.is_empty() {
+        return None;
+    }
+    Some(format!("{}*", words.join(" ")))
+}
+
+/// Split a snippet at its highlight markers.
+fn snippet_parts(snippet: &str) -> Vec<SnippetPart> {
+    let mut parts = Vec::new();
+    let mut rest = snippet;
+    while !rest.is_empty() {
+        let Some(start) = rest.find(HIGHLIGHT_START) else {
+            parts.push(SnippetPart {
+                text: rest.to_string(),
+                highlight: false,
+            });
+            break;
+        };
+        if start > 0 {
+            parts.push(SnippetPart {
+                text: rest[..start].to_string(),
+                highlight: false,
+            });
+        }
+        rest = &rest[start + HIGHLIGHT_START.len_utf8()..];
+        let end = rest.find(HIGHLIGHT_END).unwrap_or(rest.len());
+        parts.push(SnippetPart {
+            text: rest[..end].to_string(),
+            highlight: true,
+        });
+        rest = rest[end..]
+            .strip_prefix(HIGHLIGHT_END)
+            .unwrap_or(&rest[end..]);
+    }
+    parts
+}
+
+/// Searchable text of an event, empty if its content can't be read.
+async fn event_text(space: &Space, event: &Event) -> String {
+    let router = space.router();
+    let mut text = Vec::new();
+    let mut content = event.content.clone();
+    let res = match event.kind {
+        EventKind::MutateProgram => {
+            let manifest = match router.blobs().get_collection(content.hash).await {
+                Ok(collection) => programs::read_manifest(router, &collection).await,
+                Err(err) => Err(err),
+            };
+            manifest.map(|manifest| {
+                text.push(manifest.name);
+                text.extend(manifest.description);
+                for task in manifest.flow.into_iter().flatten() {
+                    text.push(task.name);
+                }
+            })
+        }
+        EventKind::MutateTable => content.resolve(router).await.map(|schema| {
+            // property names are what people remember of a table
+            if let Some(Value::Object(properties)) = schema.get("properties") {
+                text.extend(properties.keys().cloned());
+            }
+            collect_strings(&schema, &mut text);
+        }),
+        _ => content
+            .resolve(router)
+            .await
+            .map(|data| collect_strings(&data, &mut text)),
+    };
+    if let Err(err) = res {
+        debug!("can't index content for search: {:?}", err);
+    }
+    text.join("\n")
+}
+
+fn collect_strings(value: &Value, out: &mut Vec<String>) {
+    match value {
+        Value::String(s) => out.push(s.clone()),
+        Value::Number(n) => out.push(n.to_string()),
+        Value::Array(values) => values.iter().for_each(|v| collect_strings(v, out)),
+        Value::Object(map) => map.values().for_each(|v| collect_strings(v, out)),
+        Value::Bool(_) | Value::Null => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use iroh::docs::{Author, NamespaceSecret};
+    use serde_json::json;
+    use uuid::Uuid;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn search_ranks_and_highlights() -> Result<()> {
+        let node = iroh::node::Node::memory().spawn().await?;
+        let space = Space::open_in_memory(
+            Uuid::new_v4(),
+            "search".to_string(),
+            NamespaceSecret::new(&mut rand::thread_rng()),
+            node.client().clone(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+        )
+        .await?;
+        let author = Author::new(&mut rand::thread_rng());
+        let schema = json!({
+            "title": "notes",
+            "type": "object",
+            "properties": { "body": { "type": "string" } },
+        });
+        let table = space
+            .tables()
+            .create(author.clone(), Bytes::from(schema.to_string()))
+            .await?;
+        let hash = table.content.hash;
+        let rows = space.rows();
+        rows.create(
+            author.clone(),
+            hash,
+            json!({ "body": "a walk by the river" }),
+        )
+        .await?;
+        rows.create(
+            author.clone(),
+            hash,
+            json!({ "body": "river trips: the river is high, bring boots for the river" }),
+        )
+        .await?;
+        rows.create(
+            author.clone(),
+            hash,
+            json!({ "body": "nothing to see here" }),
+        )
+        .await?;
+
+        let results = space.search("river", 0, 10).await?;
+        assert_eq!(results.len(), 2);
+        assert!(results[0].score >= results[1].score);
+        assert!(results[0]
+            .snippet
+            .iter()
+            .any(|p| p.highlight && p.text == "river"));
+        assert!(!results[0]
+            .snippet
+            .iter()
+            .any(|p| p.text.contains(HIGHLIGHT_START)));
+
+        // prefix matching on the last word, and query syntax is taken literally
+        assert_eq!(space.search("riv", 0, 10).await?.len(), 2);
+        assert_eq!(space.search("walk riv", 0, 10).await?.len(), 1);
+        assert!(space.search("\"river OR", 0, 10).await?.is_empty());
+        assert!(space.search("   ", 0, 10).await?.is_empty());
+
+        // tables are found by their property names
+        let results = space.search("body", 0, 10).await?;
+        assert!(results
+            .iter()
+            .any(|r| r.event.kind == EventKind::MutateTable));
+        Ok(())
+    }
+
+    #[test]
+    fn snippet_markers() {
+        let snippet = format!("…the {HIGHLIGHT_START}river{HIGHLIGHT_END} is high");
+        assert_eq!(
+            snippet_parts(&snippet),
+            vec![
+                SnippetPart {
+                    text: "…the ".to_string(),
+                    highlight: false
+                },
+                SnippetPart {
+                    text: "river".to_string(),
+                    highlight: true
+                },
+                SnippetPart {
+                    text: " is high".to_string(),
+                    highlight: false
+                },
+            ]
+        );
+    }
+}
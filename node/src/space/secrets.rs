@@ -9,15 +9,18 @@ use uuid::Uuid;
 
 use crate::router::RouterClient;
 
+use super::clock::Hlc;
 use super::events::{Event, EventKind, EventObject, HashLink, Tag, NOSTR_ID_TAG};
 use super::{Space, EVENT_SQL_READ_FIELDS};
 
 pub type SecretsConfig = HashMap<String, String>;
 
 #[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
 pub struct Secret {
     pub program_id: Uuid, // always maps to the program ID
     pub created_at: i64,
+    #[cfg_attr(feature = "specta", specta(type = String))]
     pub pubkey: PublicKey,
     pub content: HashLink,
     pub config: SecretsConfig,
@@ -53,12 +56,13 @@ impl EventObject for Secret {
         })
     }
 
-    fn into_mutate_event(&self, author: Author) -> Result<Event> {
+    fn into_mutate_event(&self, author: Author, hlc: Hlc) -> Result<Event> {
         // assert!(author.public_key() == self.author);
         let tags = vec![Tag::new(NOSTR_ID_TAG, self.program_id.to_string().as_str())];
         Event::create(
             author,
             self.created_at,
+            hlc,
             EventKind::MutateSecret,
             tags,
             self.content.clone(),
@@ -103,15 +107,15 @@ impl Secrets {
             },
             config,
         };
-        let event = secret.into_mutate_event(author)?;
-        event.write(&self.0.db).await?;
+        let event = secret.into_mutate_event(author, self.0.clock().tick())?;
+        self.0.write_event(&event).await?;
         Ok(secret)
     }
 
     pub async fn for_program_id(&self, program_id: Uuid) -> Result<Option<Secret>> {
         let conn = self.0.db.lock().await;
         let mut stmt = conn.prepare(
-            format!("SELECT {EVENT_SQL_READ_FIELDS} FROM events WHERE kind = ?1 AND data_id = ?2 ORDER BY created_at DESC LIMIT 1")
+            format!("SELECT {EVENT_SQL_READ_FIELDS} FROM events WHERE kind = ?1 AND data_id = ?2 ORDER BY clock DESC LIMIT 1")
                 .as_str(),
         )?;
         let mut rows = stmt.query(params![EventKind::MutateSecret, program_id])?;
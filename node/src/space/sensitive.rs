@@ -0,0 +1,180 @@
+//! Encryption of sensitive row fields.
+//!
+//! A table schema marks a top-level property as sensitive with `"sensitive": true`. When a
+//! row is written, the value of each sensitive field is sealed with a key derived from the
+//! space secret before it reaches the blob store or the feed, so tokens & PII don't sit in
+//! plaintext blobs replicated to workers & peers. [`super::rows::Rows`] opens sealed values
+//! again when reading, for anyone holding the space secret.
+//!
+//! A sealed value is replaced by `{"$sealed": {"namespace": ..., "data": ...}}`, recording
+//! which secret sealed it. When the space rotates its secret (see [`super::rotation`]) it keeps
+//! the old secret's [`FieldKey`], which opens values sealed before the rotation but can't write
+//! to the old namespace. Values sealed under a secret this node never held are returned sealed.
+//! Sealed fields can't be filtered, ranged or aggregated on, sqlite only ever sees the sealed
+//! value.
+use anyhow::{anyhow, Context, Result};
+use iroh::net::key::SecretKey;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use super::SpaceSecret;
+
+/// schema keyword marking a property as sensitive
+pub const SENSITIVE_KEYWORD: &str = "sensitive";
+/// key of the object a sealed value is replaced with
+const SEALED_KEY: &str = "$sealed";
+/// keeps the field key distinct from any other use of the space secret
+const FIELD_KEY_CONTEXT: &[u8] = b"squiggle sensitive fields v1";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Sealed {
+    /// the namespace of the secret the value was sealed with
+    namespace: String,
+    /// hex encoded, sealed JSON
+    data: String,
+}
+
+/// Top-level properties of a table schema marked sensitive.
+pub fn sensitive_fields(schema: &Value) -> Vec<String> {
+    let Some(properties) = schema.get("properties").and_then(Value::as_object) else {
+        return Vec::new();
+    };
+    properties
+        .iter()
+        .filter(|(_, property)| property.get(SENSITIVE_KEYWORD) == Some(&Value::Bool(true)))
+        .map(|(name, _)| name.clone())
+        .collect()
+}
+
+/// Whether `value` is a sealed field value.
+pub fn is_sealed(value: &Value) -> bool {
+    value
+        .as_object()
+        .is_some_and(|object| object.len() == 1 && object.contains_key(SEALED_KEY))
+}
+
+/// The key fields sealed under one space secret open with, by the secret's namespace.
+#[derive(Debug, Clone)]
+pub(crate) struct FieldKey {
+    namespace: String,
+    key: SecretKey,
+}
+
+impl FieldKey {
+    pub fn new(secret: &SpaceSecret) -> Self {
+        let mut hasher = Sha256::new();
+        hasher.update(FIELD_KEY_CONTEXT);
+        hasher.update(secret.to_bytes());
+        let bytes: [u8; 32] = hasher.finalize().into();
+        FieldKey {
+            namespace: secret.id().to_string(),
+            key: SecretKey::from_bytes(&bytes),
+        }
+    }
+
+    /// A key stored with [`FieldKey::namespace`] & [`FieldKey::to_bytes`].
+    pub fn from_parts(namespace: String, bytes: &[u8; 32]) -> Self {
+        FieldKey {
+            namespace,
+            key: SecretKey::from_bytes(bytes),
+        }
+    }
+
+    pub fn namespace(&self) -> &str {
+        &self.namespace
+    }
+
+    pub fn to_bytes(&self) -> [u8; 32] {
+        self.key.to_bytes()
+    }
+}
+
+/// Seal the values of `fields` in `data`. Missing & null fields are left as they are.
+pub(crate) fn seal_fields(secret: &SpaceSecret, fields: &[String], data: &mut Value) -> Result<()> {
+    let Some(object) = data.as_object_mut() else {
+        return Ok(());
+    };
+    let FieldKey { namespace, key } = FieldKey::new(secret);
+    let shared = key.shared(&key.public());
+    for field in fields {
+        let Some(value) = object.get_mut(field) else {
+            continue;
+        };
+        if value.is_null() || is_sealed(value) {
+            continue;
+        }
+        let mut buffer = serde_json::to_vec(value)?;
+        shared.seal(&mut buffer);
+        let sealed = Sealed {
+            namespace: namespace.clone(),
+            data: hex::encode(buffer),
+        };
+        *value = serde_json::json!({ SEALED_KEY: sealed });
+    }
+    Ok(())
+}
+
+/// Open every sealed top-level value in `data` that was sealed under the secret of one of
+/// `keys`.
+pub(crate) fn open_fields(keys: &[FieldKey], data: &mut Value) -> Result<()> {
+    let Some(object) = data.as_object_mut() else {
+        return Ok(());
+    };
+    for value in object.values_mut().filter(|value| is_sealed(value)) {
+        let sealed: Sealed = serde_json::from_value(value[SEALED_KEY].clone())?;
+        let Some(FieldKey { key, .. }) = keys.iter().find(|k| k.namespace == sealed.namespace)
+        else {
+            continue;
+        };
+        let mut buffer = hex::decode(&sealed.data).context("decoding sealed field")?;
+        key.shared(&key.public())
+            .open(&mut buffer)
+            .map_err(|_| anyhow!("sealed field doesn't open with the space secret"))?;
+        *value = serde_json::from_slice(&buffer)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use iroh::docs::NamespaceSecret;
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn seal_and_open() {
+        let schema = json!({
+            "properties": {
+                "name": { "type": "string" },
+                "token": { "type": "string", "sensitive": true },
+                "ssn": { "type": ["string", "null"], "sensitive": true },
+            },
+        });
+        let mut fields = sensitive_fields(&schema);
+        fields.sort();
+        assert_eq!(fields, vec!["ssn", "token"]);
+
+        let secret = NamespaceSecret::new(&mut rand::thread_rng());
+        let original = json!({ "name": "ada", "token": "hunter2", "ssn": null });
+        let mut data = original.clone();
+        seal_fields(&secret, &fields, &mut data).unwrap();
+        assert_eq!(data["name"], "ada");
+        assert!(is_sealed(&data["token"]));
+        assert!(!data.to_string().contains("hunter2"));
+        assert!(data["ssn"].is_null(), "nulls stay null");
+
+        // another space's secret leaves the value sealed
+        let mut other = data.clone();
+        let stranger = NamespaceSecret::new(&mut rand::thread_rng());
+        open_fields(&[FieldKey::new(&stranger)], &mut other).unwrap();
+        assert!(is_sealed(&other["token"]));
+
+        // a stored key opens what its secret sealed
+        let key = FieldKey::new(&secret);
+        let stored = FieldKey::from_parts(key.namespace().to_string(), &key.to_bytes());
+        open_fields(&[FieldKey::new(&stranger), stored], &mut data).unwrap();
+        assert_eq!(data, original);
+    }
+}
@@ -5,7 +5,10 @@ use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use uuid::Uuid;
 
-use super::events::{Event, EventKind, EventObject, HashLink, Tag, NOSTR_ID_TAG};
+use super::clock::Hlc;
+use super::events::{
+    Event, EventKind, EventObject, HashLink, Tag, EVENT_SQL_READ_FIELDS, NOSTR_ID_TAG,
+};
 use super::Space;
 use crate::router::RouterClient;
 
@@ -64,12 +67,13 @@ impl EventObject for SpaceEvent {
         })
     }
 
-    fn into_mutate_event(&self, author: Author) -> Result<Event> {
+    fn into_mutate_event(&self, author: Author, hlc: Hlc) -> Result<Event> {
         // assert!(author.public_key() == self.author);
         let tags = vec![Tag::new(NOSTR_ID_TAG, self.id.to_string().as_str())];
         Event::create(
             author,
             self.created_at,
+            hlc,
             EventKind::MutateSpace,
             tags,
             self.content.clone(),
@@ -108,9 +112,30 @@ impl SpaceEvents {
             },
         };
 
-        let event = schema.into_mutate_event(author)?;
-        event.write(&self.0.db).await?;
+        let event = schema.into_mutate_event(author, self.0.clock().tick())?;
+        self.0.write_event(&event).await?;
 
         Ok(schema)
     }
+
+    /// The most recent details written for the space, if any.
+    pub async fn details(&self) -> Result<Option<SpaceDetails>> {
+        let event = {
+            let conn = self.0.db.lock().await;
+            let mut stmt = conn.prepare(
+                format!(
+                    "SELECT {EVENT_SQL_READ_FIELDS} FROM events WHERE kind = ?1 ORDER BY clock DESC LIMIT 1"
+                )
+                .as_str(),
+            )?;
+            let mut rows = stmt.query(rusqlite::params![EventKind::MutateSpace])?;
+            match rows.next()? {
+                Some(row) => Event::from_sql_row(row)?,
+                None => return Ok(None),
+            }
+        };
+        let mut event = SpaceEvent::from_event(event, &self.0.router).await?;
+        let value = event.content.resolve(&self.0.router).await?;
+        Ok(Some(serde_json::from_value(value)?))
+    }
 }
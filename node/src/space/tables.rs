@@ -1,4 +1,6 @@
-use anyhow::{anyhow, Context, Result};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Context, Result};
 use bytes::Bytes;
 use iroh::blobs::Hash;
 use iroh::docs::Author;
@@ -8,11 +10,14 @@ use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use uuid::Uuid;
 
+use super::clock::Hlc;
 use super::events::{
     Event, EventKind, EventObject, HashLink, Tag, EVENT_SQL_READ_FIELDS, NOSTR_ID_TAG,
 };
+use super::imports::{ImportAuth, ImportMapping, ImportSource, TableImport};
 use super::rows::Row;
-use super::Space;
+use super::sensitive::{self, sensitive_fields};
+use super::{Page, Space};
 use crate::router::RouterClient;
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -21,10 +26,12 @@ struct TableMetadata {
 }
 
 #[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
 pub struct Table {
     pub id: Uuid,
     #[serde(rename = "createdAt")]
     pub created_at: i64,
+    #[cfg_attr(feature = "specta", specta(type = String))]
     pub author: PublicKey,
     pub content: HashLink,
     pub title: String,
@@ -72,12 +79,13 @@ impl EventObject for Table {
         })
     }
 
-    fn into_mutate_event(&self, author: Author) -> Result<Event> {
+    fn into_mutate_event(&self, author: Author, hlc: Hlc) -> Result<Event> {
         // assert!(author.public_key() == self.author);
         let tags = vec![Tag::new(NOSTR_ID_TAG, self.id.to_string().as_str())];
         Event::create(
             author,
             self.created_at,
+            hlc,
             EventKind::MutateTable,
             tags,
             self.content.clone(),
@@ -86,11 +94,6 @@ impl EventObject for Table {
 }
 
 impl Table {
-    async fn from_sql_row(row: &rusqlite::Row<'_>, router: &RouterClient) -> Result<Table> {
-        let event = Event::from_sql_row(row)?;
-        Self::from_event(event, router).await
-    }
-
     // pub async fn load(router: &RouterClient, hash: Hash) -> Result<Self> {
     //     let bytes = router.blobs().read_to_bytes(hash).await?;
     //     let meta: SchemaMetadata = serde_json::from_slice(&bytes)?;
@@ -137,8 +140,13 @@ impl Table {
             return Err(anyhow!("validation error: {}", e.to_string()));
         };
 
+        // seal sensitive fields before anything leaves this node
+        let schema = self.content.resolve(router).await?;
+        let mut sealed = data.clone();
+        sensitive::seal_fields(&space.secret(), &sensitive_fields(&schema), &mut sealed)?;
+
         // add to iroh
-        let data2 = serde_json::to_vec(&data)?;
+        let data2 = serde_json::to_vec(&sealed)?;
         let outcome = router.blobs().add_bytes(data2).await?;
         let created_at = chrono::Utc::now().timestamp();
         let hash = outcome.hash;
@@ -152,15 +160,22 @@ impl Table {
             created_at,
             content: HashLink {
                 hash,
-                data: Some(data),
+                data: Some(sealed),
             },
         };
 
         // write event
-        let event = row.into_mutate_event(author)?;
-        event.write(&space.db).await?;
+        let event = row.into_mutate_event(author, space.clock().tick())?;
+        space.write_event(&event).await?;
 
-        Ok(row)
+        // callers get back what they wrote
+        Ok(Row {
+            content: HashLink {
+                hash,
+                data: Some(data),
+            },
+            ..row
+        })
     }
 }
 
@@ -198,12 +213,12 @@ impl Tables {
         let meta: TableMetadata = serde_json::from_slice(&data)?;
 
         // confirm our data is a valid JSON schema
-        let schema = serde_json::from_slice(&data)?;
-        jsonschema::validator_for(&schema)?;
+        let value: Value = serde_json::from_slice(&data)?;
+        jsonschema::validator_for(&value)?;
 
         // serialize data & add locally
         // TODO - test that this enforces field ordering
-        let serialized = serde_json::to_vec(&schema)?;
+        let serialized = serde_json::to_vec(&value)?;
 
         let res = self.0.router.blobs().add_bytes(serialized).await?;
 
@@ -219,12 +234,93 @@ impl Tables {
             },
         };
 
-        let event = schema.into_mutate_event(author)?;
-        event.write(&self.0.db).await?;
+        let event = schema.into_mutate_event(author, self.0.clock().tick())?;
+        self.0.write_event(&event).await?;
+
+        // index every plain top-level property up front, queries index the rest as they go
+        let sensitive = sensitive_fields(&value);
+        let fields: Vec<&str> = value
+            .get("properties")
+            .and_then(Value::as_object)
+            .into_iter()
+            .flat_map(|properties| properties.keys())
+            .map(String::as_str)
+            .filter(|field| !sensitive.iter().any(|s| s == field))
+            .collect();
+        self.0.rows().index(res.hash, &fields).await?;
 
         Ok(schema)
     }
 
+    /// Create a table from a Google Sheet & keep it in sync, see [`super::imports`].
+    pub async fn import_from_google_sheet(
+        &self,
+        author: Author,
+        auth: ImportAuth,
+        sheet_id: &str,
+        mapping: ImportMapping,
+    ) -> Result<TableImport> {
+        let source = ImportSource::GoogleSheet {
+            sheet_id: sheet_id.to_string(),
+            range: None,
+        };
+        self.0.imports().create(author, source, auth, mapping).await
+    }
+
+    /// Create a table from an Airtable table & keep it in sync, see [`super::imports`].
+    pub async fn import_from_airtable(
+        &self,
+        author: Author,
+        auth: ImportAuth,
+        base_id: &str,
+        table: &str,
+        mapping: ImportMapping,
+    ) -> Result<TableImport> {
+        let source = ImportSource::Airtable {
+            base_id: base_id.to_string(),
+            table: table.to_string(),
+        };
+        self.0.imports().create(author, source, auth, mapping).await
+    }
+
+    /// Create a table from a JSON Schema file. `$ref`s to other files, relative to the one
+    /// they're in, are inlined so the stored schema stands on its own. References within the
+    /// file are left as they are.
+    pub async fn create_from_schema_file(
+        &self,
+        author: Author,
+        path: impl Into<PathBuf>,
+    ) -> Result<Table> {
+        let path = path.into();
+        let schema = tokio::task::spawn_blocking(move || load_schema_file(&path)).await??;
+        self.create(author, serde_json::to_vec(&schema)?.into())
+            .await
+    }
+
+    /// Write the latest schema of a table to `path` as pretty-printed JSON, ready to check in
+    /// & pass to [`Tables::create_from_schema_file`] in another space.
+    pub async fn export_schema(&self, id: Uuid, path: impl AsRef<Path>) -> Result<()> {
+        let mut table = self.get(id).await?;
+        let schema = table.content.resolve(&self.0.router).await?;
+        let mut data = serde_json::to_vec_pretty(&schema)?;
+        data.push(b'\n');
+        tokio::fs::write(path.as_ref(), data)
+            .await
+            .with_context(|| format!("writing {}", path.as_ref().display()))?;
+        Ok(())
+    }
+
+    /// The latest version of a table.
+    pub async fn get(&self, id: Uuid) -> Result<Table> {
+        // TODO - SLOW
+        self.list(0, -1)
+            .await?
+            .into_iter()
+            .filter(|table| table.id == id)
+            .last()
+            .ok_or_else(|| anyhow!("table {} not found", id))
+    }
+
     pub async fn get_by_title(&self, name: &str) -> Result<Table> {
         // TODO - SLOW
         self.list(0, -1)
@@ -236,39 +332,49 @@ impl Tables {
 
     pub async fn get_by_hash(&self, hash: Hash) -> Result<Table> {
         // TODO - SLOW
-        let conn = self.0.db.lock().await;
-        let mut stmt = conn
-            .prepare(
-                format!(
-                    "SELECT {EVENT_SQL_READ_FIELDS} FROM events WHERE kind = ?1 AND content_hash = ?2"
+        let event = {
+            let conn = self.0.db.lock().await;
+            let mut stmt = conn
+                .prepare(
+                    format!(
+                        "SELECT {EVENT_SQL_READ_FIELDS} FROM events WHERE kind = ?1 AND content_hash = ?2"
+                    )
+                    .as_str(),
                 )
-                .as_str(),
-            )
-            .context("selecting schemas from events table")?;
+                .context("selecting schemas from events table")?;
 
-        let mut rows = stmt.query(params![EventKind::MutateTable, hash.to_string()])?;
-        if let Some(row) = rows.next()? {
-            return Table::from_sql_row(row, &self.0.router).await;
-        }
-
-        Err(anyhow!("schema not found"))
+            let mut rows = stmt.query(params![EventKind::MutateTable, hash.to_string()])?;
+            match rows.next()? {
+                Some(row) => Event::from_sql_row(row)?,
+                None => return Err(anyhow!("schema not found")),
+            }
+        };
+        Table::from_event(event, &self.0.router).await
     }
 
+    /// Every version of every table, oldest first by their clock.
     pub async fn list(&self, offset: i64, limit: i64) -> Result<Vec<Table>> {
-        let conn = self.0.db.lock().await;
-        let mut stmt = conn
-            .prepare(
-                format!(
-                    "SELECT {EVENT_SQL_READ_FIELDS} FROM events WHERE kind = ?1 LIMIT ?2 OFFSET ?3"
+        let events = {
+            let conn = self.0.db.lock().await;
+            let mut stmt = conn
+                .prepare(
+                    format!(
+                        "SELECT {EVENT_SQL_READ_FIELDS} FROM events WHERE kind = ?1 ORDER BY clock LIMIT ?2 OFFSET ?3"
+                    )
+                    .as_str(),
                 )
-                .as_str(),
-            )
-            .context("selecting schemas from events table")?;
-        let mut rows = stmt.query(rusqlite::params![EventKind::MutateTable, limit, offset])?;
+                .context("selecting schemas from events table")?;
+            let mut rows = stmt.query(rusqlite::params![EventKind::MutateTable, limit, offset])?;
+            let mut events = Vec::new();
+            while let Some(row) = rows.next()? {
+                events.push(Event::from_sql_row(row)?);
+            }
+            events
+        };
 
         let mut schemas = Vec::new();
-        while let Some(row) = rows.next()? {
-            let schema = Table::from_sql_row(row, &self.0.router)
+        for event in events {
+            let schema = Table::from_event(event, &self.0.router)
                 .await
                 .context("parsing schema row")?;
             schemas.push(schema);
@@ -276,4 +382,237 @@ impl Tables {
 
         Ok(schemas)
     }
+
+    /// How many table versions [`Tables::list`] pages through.
+    pub async fn count(&self) -> Result<i64> {
+        let conn = self.0.db.lock().await;
+        conn.query_row(
+            "SELECT COUNT(*) FROM events WHERE kind = ?1",
+            rusqlite::params![EventKind::MutateTable],
+            |row| row.get(0),
+        )
+        .context("counting schemas")
+    }
+
+    pub async fn page(&self, offset: i64, limit: i64) -> Result<Page<Table>> {
+        let tables = self.list(offset, limit).await?;
+        Ok(Page::new(tables, self.count().await?, offset, limit))
+    }
+}
+
+/// Read a schema file & inline the files it references, checking the result is a valid table
+/// schema.
+fn load_schema_file(path: &Path) -> Result<Value> {
+    let path = path
+        .canonicalize()
+        .with_context(|| format!("reading {}", path.display()))?;
+    let schema = read_json(&path)?;
+    let mut stack = vec![(path.clone(), String::new())];
+    let schema = inline_refs(schema, &path, None, &mut stack)?;
+
+    jsonschema::validator_for(&schema)
+        .map_err(|e| anyhow!("{}: invalid JSON schema: {}", path.display(), e))?;
+    serde_json::from_value::<TableMetadata>(schema.clone())
+        .map_err(|_| anyhow!("{}: schema needs a title", path.display()))?;
+    Ok(schema)
+}
+
+fn read_json(path: &Path) -> Result<Value> {
+    let data = std::fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+    serde_json::from_slice(&data).with_context(|| format!("parsing {}", path.display()))
+}
+
+/// Replace `$ref`s to other files with what they point to. `document` is the file `value` was
+/// read from, `None` for the root file, whose own `#...` references stay valid once stored.
+/// References within referenced files are inlined too, since they'd point into the wrong
+/// document otherwise. `stack` holds the references being resolved, to catch cycles.
+fn inline_refs(
+    value: Value,
+    path: &Path,
+    document: Option<&Value>,
+    stack: &mut Vec<(PathBuf, String)>,
+) -> Result<Value> {
+    match value {
+        Value::Object(mut map) => {
+            let reference = match map.get("$ref") {
+                Some(Value::String(reference)) => Some(reference.clone()),
+                _ => None,
+            };
+            if let Some(reference) = reference {
+                let (file, pointer) = reference.split_once('#').unwrap_or((&reference, ""));
+                if !(file.is_empty() && document.is_none()) {
+                    map.remove("$ref");
+                    let resolved = resolve_ref(file, pointer, path, document, stack)
+                        .with_context(|| format!("{}: $ref {}", path.display(), reference))?;
+                    if map.is_empty() {
+                        return Ok(resolved);
+                    }
+                    // keywords next to a $ref apply on top of it
+                    let mut siblings = serde_json::Map::new();
+                    for (key, value) in map {
+                        siblings.insert(key, inline_refs(value, path, document, stack)?);
+                    }
+                    siblings.insert("allOf".to_string(), Value::Array(vec![resolved]));
+                    return Ok(Value::Object(siblings));
+                }
+            }
+            let mut inlined = serde_json::Map::new();
+            for (key, value) in map {
+                inlined.insert(key, inline_refs(value, path, document, stack)?);
+            }
+            Ok(Value::Object(inlined))
+        }
+        Value::Array(values) => values
+            .into_iter()
+            .map(|value| inline_refs(value, path, document, stack))
+            .collect::<Result<_>>()
+            .map(Value::Array),
+        value => Ok(value),
+    }
+}
+
+fn resolve_ref(
+    file: &str,
+    pointer: &str,
+    path: &Path,
+    document: Option<&Value>,
+    stack: &mut Vec<(PathBuf, String)>,
+) -> Result<Value> {
+    if file.contains("://") {
+        bail!("only references to local files are supported");
+    }
+    let target;
+    let owned;
+    let document = match (file.is_empty(), document) {
+        (true, Some(document)) => {
+            target = path.to_path_buf();
+            document
+        }
+        _ => {
+            let dir = path.parent().context("schema file has no parent dir")?;
+            target = dir
+                .join(file)
+                .canonicalize()
+                .with_context(|| format!("reading {}", file))?;
+            owned = read_json(&target)?;
+            &owned
+        }
+    };
+
+    let key = (target.clone(), pointer.to_string());
+    if stack.contains(&key) {
+        bail!("recursive reference");
+    }
+    let value = document
+        .pointer(pointer)
+        .with_context(|| format!("nothing at #{}", pointer))?
+        .clone();
+
+    stack.push(key);
+    let value = inline_refs(value, &target, Some(document), stack);
+    stack.pop();
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    fn write(dir: &Path, name: &str, value: Value) {
+        std::fs::create_dir_all(dir.join(name).parent().unwrap()).unwrap();
+        std::fs::write(dir.join(name), serde_json::to_vec(&value).unwrap()).unwrap();
+    }
+
+    #[test]
+    fn schema_files_inline_refs() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        write(
+            dir.path(),
+            "common/defs.json",
+            json!({
+                "definitions": {
+                    "name": { "type": "string", "minLength": 1 },
+                    "person": {
+                        "type": "object",
+                        "properties": { "name": { "$ref": "#/definitions/name" } }
+                    }
+                }
+            }),
+        );
+        write(
+            dir.path(),
+            "contacts.json",
+            json!({
+                "title": "contacts",
+                "type": "object",
+                "definitions": { "email": { "type": "string" } },
+                "properties": {
+                    "person": { "$ref": "common/defs.json#/definitions/person" },
+                    "email": { "$ref": "#/definitions/email" },
+                    "nickname": {
+                        "$ref": "common/defs.json#/definitions/name",
+                        "description": "what friends call them"
+                    }
+                }
+            }),
+        );
+
+        let schema = load_schema_file(&dir.path().join("contacts.json"))?;
+        assert_eq!(
+            schema["properties"]["person"],
+            json!({
+                "type": "object",
+                "properties": { "name": { "type": "string", "minLength": 1 } }
+            })
+        );
+        // references within the root file stay
+        assert_eq!(
+            schema["properties"]["email"],
+            json!({ "$ref": "#/definitions/email" })
+        );
+        assert_eq!(
+            schema["properties"]["nickname"],
+            json!({
+                "description": "what friends call them",
+                "allOf": [{ "type": "string", "minLength": 1 }]
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn schema_files_reject_bad_refs() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        write(
+            dir.path(),
+            "tree.json",
+            json!({
+                "title": "tree",
+                "properties": { "node": { "$ref": "node.json" } }
+            }),
+        );
+        write(
+            dir.path(),
+            "node.json",
+            json!({ "properties": { "children": { "items": { "$ref": "node.json" } } } }),
+        );
+        let err = load_schema_file(&dir.path().join("tree.json")).unwrap_err();
+        assert!(
+            format!("{:#}", err).contains("recursive reference"),
+            "{err:#}"
+        );
+
+        write(
+            dir.path(),
+            "missing.json",
+            json!({ "title": "missing", "properties": { "a": { "$ref": "nope.json" } } }),
+        );
+        assert!(load_schema_file(&dir.path().join("missing.json")).is_err());
+
+        write(dir.path(), "untitled.json", json!({ "type": "object" }));
+        assert!(load_schema_file(&dir.path().join("untitled.json")).is_err());
+        Ok(())
+    }
 }
@@ -0,0 +1,415 @@
+//! Templates: the shape of a space without its data, packaged to start other spaces from.
+//!
+//! [`Space::export_template`] captures the latest schema of each table, programs, the
+//! triggers between them & table retention policies into a collection: `template.json`
+//! describing the space, plus the package of every program under `programs/<id>`. Rows,
+//! secrets, users & event history stay behind. Spaces don't have saved views or other settings
+//! beyond these yet. [`Spaces::create_from_template`] makes a new space from the ticket of a
+//! template, writing everything as new events by the instantiating author, like a fork.
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Context, Result};
+use bytes::Bytes;
+use iroh::base::node_addr::AddrInfoOptions;
+use iroh::base::ticket::BlobTicket;
+use iroh::blobs::format::collection::Collection;
+use iroh::blobs::util::SetTagOption;
+use iroh::blobs::{BlobFormat, Hash};
+use iroh::client::blobs::{DownloadMode, DownloadOptions};
+use iroh::docs::Author;
+use iroh::net::NodeAddr;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::router::RouterClient;
+
+use super::events::{Event, EventKind, HashLink, Tag, NOSTR_ID_TAG};
+use super::programs::installed_tag;
+use super::retention::RetentionPolicy;
+use super::space_events::SpaceEvents;
+use super::tables::Table;
+use super::Space;
+
+/// name of the template description within a template collection
+pub const TEMPLATE_FILENAME: &str = "template.json";
+/// newest template format this node reads
+const TEMPLATE_VERSION: u32 = 1;
+/// prefix of program packages within a template collection
+const PROGRAMS_PREFIX: &str = "programs/";
+
+/// What goes into a template besides table schemas.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+#[serde(rename_all = "camelCase", default)]
+pub struct TemplateOptions {
+    /// description of spaces made from the template, defaults to the space's
+    pub description: Option<String>,
+    pub programs: bool,
+    /// triggers are only kept along with programs
+    pub triggers: bool,
+    pub retention: bool,
+}
+
+impl Default for TemplateOptions {
+    fn default() -> Self {
+        TemplateOptions {
+            description: None,
+            programs: true,
+            triggers: true,
+            retention: true,
+        }
+    }
+}
+
+/// The contents of `template.json`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Template {
+    pub version: u32,
+    /// name of the space the template was exported from
+    pub name: String,
+    pub description: String,
+    pub created_at: i64,
+    pub tables: Vec<TemplateTable>,
+    #[serde(default)]
+    pub programs: Vec<TemplateProgram>,
+    #[serde(default)]
+    pub triggers: Vec<TemplateTrigger>,
+    #[serde(default)]
+    pub retention: Vec<TemplateRetention>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TemplateTable {
+    pub id: Uuid,
+    pub title: String,
+    pub schema: Value,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TemplateProgram {
+    pub id: Uuid,
+    pub name: String,
+    /// hash of the program's package collection
+    pub package: Hash,
+}
+
+/// A trigger, by the ids of its table & program since schema hashes change with the space.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TemplateTrigger {
+    pub table_id: Uuid,
+    pub program_id: Uuid,
+    pub filter: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TemplateRetention {
+    pub table_id: Uuid,
+    pub policy: RetentionPolicy,
+}
+
+#[derive(Debug, Clone)]
+pub struct ExportedTemplate {
+    pub template: Template,
+    /// hash of the template collection
+    pub hash: Hash,
+    /// for others to make spaces from the template with
+    pub ticket: BlobTicket,
+}
+
+/// Capture `space` as a template collection in the local blob store.
+pub(crate) async fn export(space: &Space, options: &TemplateOptions) -> Result<ExportedTemplate> {
+    let router = space.router();
+    let description = match options.description.clone() {
+        Some(description) => description,
+        None => SpaceEvents::new(space.clone())
+            .details()
+            .await?
+            .map(|details| details.description)
+            .unwrap_or_default(),
+    };
+
+    // every version of a table's schema, for finding the table of a trigger
+    let mut table_ids: HashMap<Hash, Uuid> = HashMap::new();
+    let mut latest: HashMap<Uuid, Table> = HashMap::new();
+    for table in space.tables().list(0, -1).await? {
+        table_ids.insert(table.content.hash, table.id);
+        // listed oldest first, later versions win
+        latest.insert(table.id, table);
+    }
+    let mut tables = Vec::with_capacity(latest.len());
+    for (id, mut table) in latest {
+        let schema = table.content.resolve(router).await?;
+        tables.push(TemplateTable {
+            id,
+            title: table.title,
+            schema,
+        });
+    }
+    tables.sort_by(|a, b| (&a.title, a.id).cmp(&(&b.title, b.id)));
+
+    let mut programs: HashMap<Uuid, TemplateProgram> = HashMap::new();
+    if options.programs {
+        let mut created: HashMap<Uuid, i64> = HashMap::new();
+        for program in space.programs().list(0, -1).await? {
+            if created
+                .get(&program.id)
+                .is_some_and(|at| *at > program.created_at)
+            {
+                continue;
+            }
+            created.insert(program.id, program.created_at);
+            programs.insert(
+                program.id,
+                TemplateProgram {
+                    id: program.id,
+                    name: program.manifest.name,
+                    package: program.content.hash,
+                },
+            );
+        }
+    }
+
+    let mut triggers = Vec::new();
+    if options.programs && options.triggers {
+        for trigger in space.triggers().list().await? {
+            let Some(table_id) = table_ids.get(&trigger.table) else {
+                continue;
+            };
+            if !programs.contains_key(&trigger.program_id) {
+                continue;
+            }
+            triggers.push(TemplateTrigger {
+                table_id: *table_id,
+                program_id: trigger.program_id,
+                filter: trigger.filter,
+            });
+        }
+    }
+
+    let mut retention = Vec::new();
+    if options.retention {
+        for table in space.retention().list().await? {
+            retention.push(TemplateRetention {
+                table_id: table.table_id,
+                policy: table.policy,
+            });
+        }
+    }
+
+    let mut programs = programs.into_values().collect::<Vec<_>>();
+    programs.sort_by(|a, b| (&a.name, a.id).cmp(&(&b.name, b.id)));
+    let template = Template {
+        version: TEMPLATE_VERSION,
+        name: space.name.clone(),
+        description,
+        created_at: chrono::Utc::now().timestamp(),
+        tables,
+        programs,
+        triggers,
+        retention,
+    };
+
+    let json = router
+        .blobs()
+        .add_bytes(serde_json::to_vec_pretty(&template)?)
+        .await?;
+    let mut entries = vec![(TEMPLATE_FILENAME.to_string(), json.hash)];
+    for program in &template.programs {
+        entries.push((format!("{PROGRAMS_PREFIX}{}", program.id), program.package));
+    }
+    let (hash, _) = router
+        .blobs()
+        .create_collection(
+            Collection::from_iter(entries),
+            SetTagOption::Auto,
+            vec![json.tag],
+        )
+        .await?;
+
+    let mut addr = router.net().node_addr().await?;
+    addr.apply_options(AddrInfoOptions::Id);
+    let ticket = BlobTicket::new(addr, hash, BlobFormat::HashSeq)?;
+    Ok(ExportedTemplate {
+        template,
+        hash,
+        ticket,
+    })
+}
+
+/// Get the template behind `ticket` into the local store & read it.
+pub(crate) async fn fetch(router: &RouterClient, ticket: &BlobTicket) -> Result<Template> {
+    download(
+        router,
+        ticket.hash(),
+        ticket.node_addr(),
+        SetTagOption::Auto,
+    )
+    .await?;
+    let collection = router.blobs().get_collection(ticket.hash()).await?;
+    let (_, hash) = collection
+        .iter()
+        .find(|(name, _)| name == TEMPLATE_FILENAME)
+        .ok_or_else(|| anyhow!("not a template, {} is missing", TEMPLATE_FILENAME))?;
+    let data = router.blobs().read_to_bytes(*hash).await?;
+    let template: Template = serde_json::from_slice(&data).context("reading template")?;
+    anyhow::ensure!(
+        template.version <= TEMPLATE_VERSION,
+        "template is from a newer version (format {}, this node reads up to {})",
+        template.version,
+        TEMPLATE_VERSION
+    );
+    Ok(template)
+}
+
+/// Write the contents of `template` into the empty space `dest`, fetching program packages
+/// from `node`.
+pub(crate) async fn instantiate(
+    dest: &Space,
+    author: Author,
+    template: &Template,
+    node: &NodeAddr,
+) -> Result<()> {
+    let router = dest.router();
+    let mut schemas = HashMap::new();
+    for table in &template.tables {
+        let data = Bytes::from(serde_json::to_vec(&table.schema)?);
+        let created = dest
+            .tables()
+            .mutate(author.clone(), table.id, data)
+            .await
+            .with_context(|| format!("creating table {}", table.title))?;
+        schemas.insert(table.id, created.content.hash);
+    }
+
+    let now = chrono::Utc::now().timestamp();
+    for program in &template.programs {
+        let tag = SetTagOption::Named(installed_tag(program.id));
+        download(router, program.package, node, tag)
+            .await
+            .with_context(|| format!("fetching program {}", program.name))?;
+        let tags = vec![Tag::new(NOSTR_ID_TAG, program.id.to_string().as_str())];
+        let event = Event::create(
+            author.clone(),
+            now,
+            dest.clock().tick(),
+            EventKind::MutateProgram,
+            tags,
+            HashLink::from(program.package),
+        )?;
+        dest.write_event(&event)
+            .await
+            .with_context(|| format!("adding program {}", program.name))?;
+    }
+
+    for trigger in &template.triggers {
+        let table = schemas
+            .get(&trigger.table_id)
+            .ok_or_else(|| anyhow!("trigger on unknown table {}", trigger.table_id))?;
+        dest.triggers()
+            .create(author.clone(), *table, trigger.program_id, &trigger.filter)
+            .await?;
+    }
+    for table in &template.retention {
+        dest.retention()
+            .set(author.clone(), table.table_id, table.policy.clone())
+            .await?;
+    }
+    Ok(())
+}
+
+/// Fetch a collection & everything in it from `node`, unless it's this node.
+async fn download(
+    router: &RouterClient,
+    hash: Hash,
+    node: &NodeAddr,
+    tag: SetTagOption,
+) -> Result<()> {
+    if node.node_id == router.net().node_id().await? {
+        return Ok(());
+    }
+    let options = DownloadOptions {
+        format: BlobFormat::HashSeq,
+        nodes: vec![node.clone()],
+        tag,
+        mode: DownloadMode::Queued,
+    };
+    router
+        .blobs()
+        .download_with_opts(hash, options)
+        .await?
+        .finish()
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::space::retention::RetentionAction;
+    use crate::space::Spaces;
+
+    #[tokio::test]
+    async fn export_and_instantiate() -> Result<()> {
+        let node = iroh::node::Node::memory().spawn().await?;
+        let router = node.client().clone();
+        let dir = tempfile::tempdir()?;
+        let mut spaces = Spaces::open_all(router.clone(), dir.path()).await?;
+        let author = Author::new(&mut rand::thread_rng());
+        let source = spaces
+            .create(&router, author.clone(), "crm", "contacts & deals")
+            .await?;
+        let schema = json!({
+            "title": "contacts",
+            "type": "object",
+            "properties": { "name": { "type": "string" } },
+        });
+        let table = source
+            .tables()
+            .create(author.clone(), Bytes::from(schema.to_string()))
+            .await?;
+        source
+            .rows()
+            .create(author.clone(), table.content.hash, json!({ "name": "ada" }))
+            .await?;
+        let policy = RetentionPolicy {
+            max_age_days: Some(30),
+            max_rows: None,
+            action: RetentionAction::Delete,
+            interval_secs: 3600,
+        };
+        source
+            .retention()
+            .set(author.clone(), table.id, policy.clone())
+            .await?;
+
+        let exported = source.export_template(TemplateOptions::default()).await?;
+        assert_eq!(exported.template.description, "contacts & deals");
+        assert_eq!(exported.template.tables.len(), 1);
+        assert_eq!(exported.template.tables[0].schema, schema);
+        assert_eq!(exported.template.retention[0].policy, policy);
+
+        let other = Author::new(&mut rand::thread_rng());
+        let made = spaces
+            .create_from_template(&router, other, "my crm", &exported.ticket)
+            .await?;
+        assert_ne!(made.id, source.id);
+        let tables = made.tables().list(0, -1).await?;
+        assert_eq!(tables.len(), 1);
+        assert_eq!(tables[0].id, table.id);
+        let rows = made
+            .rows()
+            .query(tables[0].content.hash, String::new(), 0, -1)
+            .await?;
+        assert!(rows.is_empty(), "rows stay behind");
+        assert_eq!(made.retention().get(table.id).await?.policy, policy);
+        Ok(())
+    }
+}
@@ -0,0 +1,229 @@
+//! Triggers run a program whenever a row matching a filter is written to a table.
+//!
+//! A trigger is a `MutateTrigger` event tagged with its table, holding the program to run &
+//! a filter in the [`RowQuery`] syntax, eg: `where status = "new"`. Triggers sync like any
+//! other space data, but only fire on nodes holding the key of the author that created them,
+//! see [`crate::vm::triggers`] for how they're run.
+use std::str::FromStr;
+
+use anyhow::{anyhow, ensure, Context, Result};
+use iroh::blobs::Hash;
+use iroh::docs::Author;
+use iroh::net::key::PublicKey;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::router::RouterClient;
+
+use super::clock::Hlc;
+use super::events::{
+    Event, EventKind, EventObject, HashLink, Tag, EVENT_SQL_READ_FIELDS, NOSTR_ID_TAG,
+    NOSTR_SCHEMA_TAG,
+};
+use super::rows::query::RowQuery;
+use super::Space;
+
+/// What's stored about a trigger, the content of its event.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TriggerContent {
+    program_id: Uuid,
+    filter: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+#[serde(rename_all = "camelCase")]
+pub struct Trigger {
+    pub id: Uuid,
+    pub created_at: i64,
+    #[cfg_attr(feature = "specta", specta(type = String))]
+    pub author: PublicKey,
+    pub content: HashLink,
+    /// the table whose rows fire the trigger
+    #[cfg_attr(feature = "specta", specta(type = String))]
+    pub table: Hash,
+    pub program_id: Uuid,
+    /// rows have to match this to fire the trigger, every row does when it's empty
+    pub filter: String,
+}
+
+impl Trigger {
+    /// Whether a write of `row` fires the trigger.
+    pub fn matches(&self, row: &Value) -> Result<bool> {
+        Ok(parse_filter(&self.filter)?.matches(row))
+    }
+}
+
+impl EventObject for Trigger {
+    async fn from_event(event: Event, router: &RouterClient) -> Result<Self> {
+        if event.kind != EventKind::MutateTrigger {
+            return Err(anyhow!("event is not a trigger mutation"));
+        }
+        let id = event.data_id()?.ok_or_else(|| anyhow!("missing data id"))?;
+        let table = event.schema()?.ok_or_else(|| anyhow!("missing table"))?;
+        let mut content = event.content;
+        let details: TriggerContent = serde_json::from_value(content.resolve(router).await?)?;
+        Ok(Trigger {
+            id,
+            created_at: event.created_at,
+            author: event.pubkey,
+            content,
+            table,
+            program_id: details.program_id,
+            filter: details.filter,
+        })
+    }
+
+    fn into_mutate_event(&self, author: Author, hlc: Hlc) -> Result<Event> {
+        let tags = vec![
+            Tag::new(NOSTR_SCHEMA_TAG, self.table.to_string().as_str()),
+            Tag::new(NOSTR_ID_TAG, self.id.to_string().as_str()),
+        ];
+        Event::create(
+            author,
+            self.created_at,
+            hlc,
+            EventKind::MutateTrigger,
+            tags,
+            self.content.clone(),
+        )
+    }
+}
+
+/// Parse a trigger filter, a [`RowQuery`] with only a where clause.
+fn parse_filter(filter: &str) -> Result<RowQuery> {
+    let query = RowQuery::from_str(filter).context("parsing trigger filter")?;
+    ensure!(
+        query.select.is_empty() && query.order.is_empty(),
+        "trigger filters only take a where clause"
+    );
+    Ok(query)
+}
+
+pub struct Triggers(Space);
+
+impl Triggers {
+    pub fn new(space: Space) -> Self {
+        Triggers(space)
+    }
+
+    /// Run `program_id` whenever a row of `table` matching `filter` is written.
+    pub async fn create(
+        &self,
+        author: Author,
+        table: Hash,
+        program_id: Uuid,
+        filter: &str,
+    ) -> Result<Trigger> {
+        parse_filter(filter)?;
+        self.0
+            .tables()
+            .get_by_hash(table)
+            .await
+            .context("loading trigger table")?;
+        self.0
+            .programs()
+            .get_by_id(program_id)
+            .await
+            .context("loading trigger program")?;
+
+        let details = TriggerContent {
+            program_id,
+            filter: filter.to_string(),
+        };
+        let value = serde_json::to_value(&details)?;
+        let content = self
+            .0
+            .router
+            .blobs()
+            .add_bytes(serde_json::to_vec(&value)?)
+            .await?;
+        let trigger = Trigger {
+            id: Uuid::new_v4(),
+            created_at: chrono::Utc::now().timestamp(),
+            author: PublicKey::from_bytes(author.public_key().as_bytes())?,
+            content: HashLink {
+                hash: content.hash,
+                data: Some(value),
+            },
+            table,
+            program_id,
+            filter: details.filter,
+        };
+        self.0
+            .write_event(&trigger.into_mutate_event(author, self.0.clock().tick())?)
+            .await?;
+        Ok(trigger)
+    }
+
+    /// Stop a trigger from firing. Deleted triggers are gone for good.
+    pub async fn delete(&self, author: Author, id: Uuid) -> Result<()> {
+        let trigger = self.get(id).await?;
+        let tags = vec![
+            Tag::new(NOSTR_SCHEMA_TAG, trigger.table.to_string().as_str()),
+            Tag::new(NOSTR_ID_TAG, id.to_string().as_str()),
+        ];
+        let event = Event::create(
+            author,
+            chrono::Utc::now().timestamp(),
+            self.0.clock().tick(),
+            EventKind::DeleteTrigger,
+            tags,
+            HashLink::from(trigger.content.hash),
+        )?;
+        self.0.write_event(&event).await
+    }
+
+    pub async fn get(&self, id: Uuid) -> Result<Trigger> {
+        let mut triggers = self.select("data_id = ?2", &[&id]).await?;
+        triggers.pop().ok_or_else(|| anyhow!("trigger not found"))
+    }
+
+    /// Triggers on any table.
+    pub async fn list(&self) -> Result<Vec<Trigger>> {
+        self.select("1", &[]).await
+    }
+
+    /// Triggers fired by writes to `table`.
+    pub async fn for_table(&self, table: Hash) -> Result<Vec<Trigger>> {
+        self.select("schema_hash = ?2", &[&table.to_string()]).await
+    }
+
+    /// The latest version of each trigger that hasn't been deleted & matches `condition`,
+    /// which takes its parameters from `?2` on.
+    async fn select(
+        &self,
+        condition: &str,
+        params: &[&(dyn rusqlite::ToSql + Sync)],
+    ) -> Result<Vec<Trigger>> {
+        let sql = format!(
+            "SELECT {EVENT_SQL_READ_FIELDS}, MAX(clock) FROM events
+            WHERE kind = ?1 AND {condition}
+            AND data_id NOT IN (SELECT data_id FROM events WHERE kind = {deleted})
+            GROUP BY data_id ORDER BY clock",
+            deleted = EventKind::DeleteTrigger.kind(),
+        );
+        let events = {
+            let conn = self.0.db.lock().await;
+            let mut stmt = conn.prepare(&sql)?;
+            let mut params: Vec<&dyn rusqlite::ToSql> = params
+                .iter()
+                .map(|param| *param as &dyn rusqlite::ToSql)
+                .collect();
+            params.insert(0, &EventKind::MutateTrigger);
+            let mut rows = stmt.query(params.as_slice())?;
+            let mut events = Vec::new();
+            while let Some(row) = rows.next()? {
+                events.push(Event::from_sql_row(row)?);
+            }
+            events
+        };
+        let mut triggers = Vec::new();
+        for event in events {
+            triggers.push(Trigger::from_event(event, &self.0.router).await?);
+        }
+        Ok(triggers)
+    }
+}
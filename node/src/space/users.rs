@@ -1,29 +1,53 @@
 use anyhow::{anyhow, Result};
 use iroh::docs::{Author, AuthorId};
 use iroh::net::key::PublicKey;
-use rusqlite::params;
+use rusqlite::{params, OptionalExtension};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 use crate::router::RouterClient;
 
+use super::audit::AuditAction;
+use super::clock::Hlc;
 use super::events::{Event, EventKind, EventObject, HashLink, Tag, NOSTR_ID_TAG};
-use super::{Space, EVENT_SQL_READ_FIELDS};
+use super::{Page, Space, EVENT_SQL_READ_FIELDS};
 
 #[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
 pub struct Profile {
     name: String,
     description: String,
     picture: String,
 }
 
+impl Profile {
+    pub fn new(
+        name: impl Into<String>,
+        description: impl Into<String>,
+        picture: impl Into<String>,
+    ) -> Self {
+        Profile {
+            name: name.into(),
+            description: description.into(),
+            picture: picture.into(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
 pub struct User {
     pub id: Uuid,
     pub created_at: i64,
+    #[cfg_attr(feature = "specta", specta(type = String))]
     pub pubkey: PublicKey,
     pub content: HashLink,
     pub blankame: String,
+    #[cfg_attr(feature = "specta", specta(skip))]
     pub author: Option<Author>,
     pub profile: Option<Profile>,
 }
@@ -64,12 +88,13 @@ impl EventObject for User {
         })
     }
 
-    fn into_mutate_event(&self, author: Author) -> Result<Event> {
+    fn into_mutate_event(&self, author: Author, hlc: Hlc) -> Result<Event> {
         // assert!(author.public_key() == self.author);
         let tags = vec![Tag::new(NOSTR_ID_TAG, self.id.to_string().as_str())];
         Event::create(
             author,
             self.created_at,
+            hlc,
             EventKind::MutateUser,
             tags,
             self.content.clone(),
@@ -78,11 +103,6 @@ impl EventObject for User {
 }
 
 impl User {
-    async fn from_sql_row(row: &rusqlite::Row<'_>, client: &RouterClient) -> Result<User> {
-        let event = Event::from_sql_row(row)?;
-        Self::from_event(event, client).await
-    }
-
     pub async fn create(router: &RouterClient, space: &Space, profile: Profile) -> Result<User> {
         let id = Uuid::new_v4();
         let author_id = router.authors().create().await?;
@@ -112,7 +132,13 @@ impl User {
             author: Some(author.clone()),
         };
 
-        user.into_mutate_event(author)?.write(&space.db).await?;
+        space
+            .write_event(&user.into_mutate_event(author.clone(), space.clock().tick())?)
+            .await?;
+        space
+            .audit()
+            .record(author, AuditAction::MemberAdded { member: pubkey })
+            .await?;
         Ok(user)
     }
 }
@@ -134,33 +160,124 @@ impl Users {
             .clone()
             .ok_or_else(|| anyhow!("missing author"))?;
         user.created_at = chrono::Utc::now().timestamp();
-        let event = user.into_mutate_event(author)?;
-        event.write(&self.0.db).await?;
+        let event = user.into_mutate_event(author, self.0.clock().tick())?;
+        self.0.write_event(&event).await?;
         Ok(user)
     }
 
-    pub async fn list(&self, offset: i64, limit: i64) -> Result<Vec<User>> {
-        let conn = self.0.db.lock().await;
-        let mut stmt = conn.prepare(
-            format!(
-                "SELECT {EVENT_SQL_READ_FIELDS} FROM events WHERE kind = ?1 LIMIT ?2 OFFSET ?3"
+    /// A member by user id, None if there's no such user or they were removed.
+    pub async fn get(&self, id: Uuid) -> Result<Option<User>> {
+        let event = {
+            let conn = self.0.db.lock().await;
+            conn.query_row(
+                format!(
+                    "SELECT {EVENT_SQL_READ_FIELDS} FROM events WHERE kind = ?1 AND data_id = ?2 AND {} ORDER BY clock DESC LIMIT 1",
+                    not_removed()
+                )
+                .as_str(),
+                params![EventKind::MutateUser, id],
+                |row| Ok(Event::from_sql_row(row)),
             )
-            .as_str(),
+            .optional()?
+            .transpose()?
+        };
+        match event {
+            Some(event) => Ok(Some(User::from_event(event, &self.0.router).await?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Remove a member from the space. They keep what they already synced, rotate the space's
+    /// secret to keep them from following it, see [`super::rotation`].
+    pub async fn remove(&self, author: Author, id: Uuid) -> Result<()> {
+        let user = self
+            .get(id)
+            .await?
+            .ok_or_else(|| anyhow!("user not found: {}", id))?;
+        let tags = vec![Tag::new(NOSTR_ID_TAG, id.to_string().as_str())];
+        let event = Event::create(
+            author.clone(),
+            chrono::Utc::now().timestamp(),
+            self.0.clock().tick(),
+            EventKind::DeleteUser,
+            tags,
+            HashLink::from(user.content.hash),
         )?;
-        let mut rows = stmt.query(params![EventKind::MutateUser, limit, offset])?;
+        self.0.write_event(&event).await?;
+        self.0
+            .audit()
+            .record(
+                author,
+                AuditAction::MemberRemoved {
+                    member: user.pubkey,
+                },
+            )
+            .await?;
+        Ok(())
+    }
+
+    pub async fn list(&self, offset: i64, limit: i64) -> Result<Vec<User>> {
+        let events = {
+            let conn = self.0.db.lock().await;
+            let mut stmt = conn.prepare(
+                format!(
+                    "SELECT {EVENT_SQL_READ_FIELDS} FROM events WHERE kind = ?1 AND {} LIMIT ?2 OFFSET ?3",
+                    not_removed()
+                )
+                .as_str(),
+            )?;
+            let mut rows = stmt.query(params![EventKind::MutateUser, limit, offset])?;
+            let mut events = Vec::new();
+            while let Some(row) = rows.next()? {
+                events.push(Event::from_sql_row(row)?);
+            }
+            events
+        };
 
         let mut users = Vec::new();
-        while let Some(row) = rows.next()? {
-            let user = User::from_sql_row(row, &self.0.router).await?;
-            users.push(user);
+        for event in events {
+            users.push(User::from_event(event, &self.0.router).await?);
         }
 
         Ok(users)
     }
+
+    /// How many users [`Users::list`] pages through.
+    pub async fn count(&self) -> Result<i64> {
+        let conn = self.0.db.lock().await;
+        let count = conn.query_row(
+            format!(
+                "SELECT COUNT(*) FROM events WHERE kind = ?1 AND {}",
+                not_removed()
+            )
+            .as_str(),
+            params![EventKind::MutateUser],
+            |row| row.get(0),
+        )?;
+        Ok(count)
+    }
+
+    pub async fn page(&self, offset: i64, limit: i64) -> Result<Page<User>> {
+        let users = self.list(offset, limit).await?;
+        Ok(Page::new(users, self.count().await?, offset, limit))
+    }
+}
+
+/// sql condition that leaves out user writes a later removal supersedes, for queries over
+/// `events`. Writing a removed user again brings them back.
+pub(crate) fn not_removed() -> String {
+    format!(
+        "NOT EXISTS (
+            SELECT 1 FROM events AS removed
+            WHERE removed.kind = {} AND removed.data_id = events.data_id
+            AND removed.clock >= events.clock
+        )",
+        EventKind::DeleteUser.kind()
+    )
 }
 
 // TODO: have this accept a hash & use the hash to deterministically generate a name
-fn get_blankname(key: PublicKey) -> String {
+pub(crate) fn get_blankname(key: PublicKey) -> String {
     let bytes = key.as_bytes();
     let adjectives = get_adjectives();
     let colors = get_color_names();
@@ -0,0 +1,210 @@
+//! Checks events from other nodes before they're written.
+//!
+//! Events arrive through the inbox, joins, mirrors & program downloads, and are only as
+//! trustworthy as the peer that sent them. Before one is written its id is recomputed from
+//! the data it covers, its signature is checked against its author's key, and invariants of
+//! its kind are checked: every event names the object it's about, and row events name a table
+//! this node can resolve, from a table event it holds or the table's schema blob. Events that
+//! fail are rejected & counted in the `events_rejected_*` metrics. A row that arrives before
+//! its table is rejected until the table arrives, the inbox tries it again on the next pull.
+//! [`awaits_table`] tells those apart from events that will never be written.
+use anyhow::{anyhow, bail, Context, Result};
+use iroh::blobs::Hash;
+use iroh::client::blobs::BlobStatus;
+use rusqlite::params;
+use tracing::debug;
+
+use crate::router::RouterClient;
+use crate::vm::metrics::Metrics;
+
+use super::db::DB;
+use super::events::{Event, EventKind};
+
+/// Why an event was rejected, each counted separately.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Rejection {
+    /// the id doesn't cover the event's data
+    Id,
+    /// unsigned, or signed by someone other than its author
+    Signature,
+    /// doesn't hold what events of its kind must
+    Invariant,
+    /// names a table this node can't resolve
+    Schema,
+}
+
+impl Rejection {
+    fn count(self) {
+        match self {
+            Rejection::Id => iroh_metrics::inc!(Metrics, events_rejected_id),
+            Rejection::Signature => iroh_metrics::inc!(Metrics, events_rejected_signature),
+            Rejection::Invariant => iroh_metrics::inc!(Metrics, events_rejected_invariant),
+            Rejection::Schema => iroh_metrics::inc!(Metrics, events_rejected_schema),
+        }
+    }
+}
+
+/// A row names a table this node doesn't hold (yet).
+#[derive(Debug, thiserror::Error)]
+#[error("table {0} can't be resolved")]
+struct UnresolvedTable(Hash);
+
+/// Whether `err` from [`validate`] rejected a row only because its table hasn't arrived, so
+/// the row may be written once it does.
+pub(crate) fn awaits_table(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<UnresolvedTable>().is_some()
+}
+
+/// Check an event from another node can be written to `db`.
+pub(crate) async fn validate(db: &DB, router: &RouterClient, event: &Event) -> Result<()> {
+    let checked = match check(event) {
+        Ok(()) => resolve_schema(db, router, event).await,
+        Err(rejected) => Err(rejected),
+    };
+    checked.map_err(|(rejection, err)| {
+        debug!("rejected event {}: {:?} {:#}", event.id, rejection, err);
+        rejection.count();
+        err.context(format!("rejected event {}", event.id))
+    })
+}
+
+/// The checks of [`validate`] that need nothing but the event, for listing events read from
+/// somewhere their tables can't be resolved from. Writing them still needs [`validate`].
+pub(crate) fn check_event(event: &Event) -> Result<()> {
+    check(event).map_err(|(_, err)| err)
+}
+
+/// The checks that need nothing but the event.
+fn check(event: &Event) -> Result<(), (Rejection, anyhow::Error)> {
+    let id = Event::nostr_id(
+        event.pubkey,
+        event.created_at,
+        event.kind,
+        &event.tags,
+        &event.content.hash,
+    )
+    .map_err(|err| (Rejection::Id, err))?;
+    if id != event.id {
+        return Err((Rejection::Id, anyhow!("id doesn't match the event's data")));
+    }
+
+    let Some(sig) = &event.sig else {
+        return Err((Rejection::Signature, anyhow!("event isn't signed")));
+    };
+    event.pubkey.verify(event.id.as_bytes(), sig).map_err(|_| {
+        (
+            Rejection::Signature,
+            anyhow!("signature doesn't match the event's author"),
+        )
+    })?;
+
+    invariants(event).map_err(|err| (Rejection::Invariant, err))
+}
+
+fn invariants(event: &Event) -> Result<()> {
+    event.hlc().context("invalid clock reading")?;
+    if event.data_id().context("invalid data id")?.is_none() {
+        bail!("{:?} event has no data id", event.kind);
+    }
+    let schema = event.schema().context("invalid schema hash")?;
+    if is_row(event.kind) && schema.is_none() {
+        bail!("{:?} event names no table", event.kind);
+    }
+    Ok(())
+}
+
+fn is_row(kind: EventKind) -> bool {
+    matches!(kind, EventKind::MutateRow | EventKind::DeleteRow)
+}
+
+/// Row events must name a table the db holds, or whose schema blob is here.
+async fn resolve_schema(
+    db: &DB,
+    router: &RouterClient,
+    event: &Event,
+) -> Result<(), (Rejection, anyhow::Error)> {
+    if !is_row(event.kind) {
+        return Ok(());
+    }
+    let Ok(Some(schema)) = event.schema() else {
+        return Ok(());
+    };
+    match schema_known(db, router, schema).await {
+        Ok(true) => Ok(()),
+        Ok(false) => Err((Rejection::Schema, UnresolvedTable(schema).into())),
+        Err(err) => Err((Rejection::Schema, err.context("resolving table"))),
+    }
+}
+
+async fn schema_known(db: &DB, router: &RouterClient, schema: Hash) -> Result<bool> {
+    let held: bool = db.lock().await.query_row(
+        "SELECT COUNT(*) > 0 FROM events WHERE kind = ?1 AND content_hash = ?2",
+        params![EventKind::MutateTable, schema.to_string()],
+        |row| row.get(0),
+    )?;
+    if held {
+        return Ok(true);
+    }
+    let status = router.blobs().status(schema).await?;
+    Ok(matches!(status, BlobStatus::Complete { .. }))
+}
+
+#[cfg(test)]
+mod tests {
+    use iroh::docs::Author;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::space::clock::Hlc;
+    use crate::space::db::{open_memory_db, setup_db};
+    use crate::space::events::{HashLink, Tag, NOSTR_ID_TAG, NOSTR_SCHEMA_TAG};
+
+    fn row(author: &Author, schema: Option<Hash>) -> Result<Event> {
+        let mut tags = vec![Tag::new(NOSTR_ID_TAG, &Uuid::new_v4().to_string())];
+        if let Some(schema) = schema {
+            tags.push(Tag::new(NOSTR_SCHEMA_TAG, &schema.to_string()));
+        }
+        Event::create(
+            author.clone(),
+            1_700_000_000,
+            Hlc::from_created_at(1_700_000_000),
+            EventKind::MutateRow,
+            tags,
+            HashLink::from(Hash::new("ada")),
+        )
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_events() -> Result<()> {
+        let node = iroh::node::Node::memory().spawn().await?;
+        let router = node.client().clone();
+        let db = open_memory_db().await?;
+        setup_db(&db).await?;
+        let author = Author::new(&mut rand::thread_rng());
+        let schema = router.blobs().add_bytes(b"{}".to_vec()).await?.hash;
+
+        validate(&db, &router, &row(&author, Some(schema))?).await?;
+
+        let mut tampered = row(&author, Some(schema))?;
+        tampered.created_at += 1;
+        assert!(validate(&db, &router, &tampered).await.is_err());
+
+        let mut forged = row(&author, Some(schema))?;
+        let other = row(&Author::new(&mut rand::thread_rng()), Some(schema))?;
+        forged.sig = other.sig;
+        assert!(validate(&db, &router, &forged).await.is_err());
+
+        let mut unsigned = row(&author, Some(schema))?;
+        unsigned.sig = None;
+        assert!(validate(&db, &router, &unsigned).await.is_err());
+
+        assert!(validate(&db, &router, &row(&author, None)?).await.is_err());
+        let unknown = row(&author, Some(Hash::new("not here")))?;
+        let err = validate(&db, &router, &unknown).await.unwrap_err();
+        assert!(awaits_table(&err));
+        assert!(!awaits_table(
+            &validate(&db, &router, &forged).await.unwrap_err()
+        ));
+        Ok(())
+    }
+}
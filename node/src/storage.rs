@@ -0,0 +1,247 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use anyhow::Result;
+use futures::StreamExt;
+use iroh::blobs::Hash;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::router::RouterClient;
+use crate::space::events::EventKind;
+use crate::space::{Space, Spaces};
+
+/// number of objects listed in `StorageReport::largest`
+const LARGEST_OBJECTS: usize = 20;
+
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+pub struct Usage {
+    pub objects: u64,
+    pub bytes: u64,
+}
+
+impl Usage {
+    fn add(&mut self, bytes: u64) {
+        self.objects += 1;
+        self.bytes += bytes;
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+#[serde(rename_all = "camelCase")]
+pub struct SpaceUsage {
+    pub id: Uuid,
+    pub name: String,
+    pub total: Usage,
+    /// row content by table schema hash, all versions included
+    pub tables: HashMap<String, Usage>,
+    /// program package size by program id
+    pub programs: HashMap<Uuid, Usage>,
+    /// everything else: users, secrets, schemas, space details
+    pub other: Usage,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+#[serde(rename_all = "camelCase")]
+pub struct SharedObject {
+    #[cfg_attr(feature = "specta", specta(type = String))]
+    pub hash: Hash,
+    pub size: u64,
+    pub references: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+pub struct LargeObject {
+    #[cfg_attr(feature = "specta", specta(type = String))]
+    pub hash: Hash,
+    pub size: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+#[serde(rename_all = "camelCase")]
+pub struct StorageReport {
+    /// everything in the blob store
+    pub total: Usage,
+    pub spaces: Vec<SpaceUsage>,
+    /// blobs no event references, eg: job artifacts & orphaned imports
+    pub unreferenced: Usage,
+    /// blobs referenced more than once. These are only stored once
+    pub shared: Vec<SharedObject>,
+    /// bytes saved by content addressing
+    pub dedup_savings: u64,
+    pub largest: Vec<LargeObject>,
+}
+
+pub(crate) async fn report(router: &RouterClient, spaces: &Spaces) -> Result<StorageReport> {
+    let mut sizes = HashMap::new();
+    let mut total = Usage::default();
+    let mut blobs = router.blobs().list().await?;
+    while let Some(blob) = blobs.next().await {
+        let blob = blob?;
+        total.add(blob.size);
+        sizes.insert(blob.hash, blob.size);
+    }
+
+    let mut references: HashMap<Hash, u64> = HashMap::new();
+    let mut space_usage = Vec::new();
+    for details in spaces.list(0, -1).await? {
+        let Some(space) = spaces.get(&details.id).await else {
+            continue;
+        };
+        let usage = space_report(router, &space, &sizes, &mut references).await?;
+        space_usage.push(usage);
+    }
+
+    let mut unreferenced = Usage::default();
+    for (hash, size) in sizes.iter() {
+        if !references.contains_key(hash) {
+            unreferenced.add(*size);
+        }
+    }
+
+    let mut shared = references
+        .iter()
+        .filter(|(_, count)| **count > 1)
+        .map(|(hash, count)| SharedObject {
+            hash: *hash,
+            size: sizes.get(hash).copied().unwrap_or_default(),
+            references: *count,
+        })
+        .collect::<Vec<_>>();
+    shared.sort_by(|a, b| (b.size * b.references).cmp(&(a.size * a.references)));
+    let dedup_savings = shared.iter().map(|o| o.size * (o.references - 1)).sum();
+
+    let mut largest = sizes
+        .into_iter()
+        .map(|(hash, size)| LargeObject { hash, size })
+        .collect::<Vec<_>>();
+    largest.sort_by(|a, b| b.size.cmp(&a.size));
+    largest.truncate(LARGEST_OBJECTS);
+
+    Ok(StorageReport {
+        total,
+        spaces: space_usage,
+        unreferenced,
+        shared,
+        dedup_savings,
+        largest,
+    })
+}
+
+async fn space_report(
+    router: &RouterClient,
+    space: &Space,
+    sizes: &HashMap<Hash, u64>,
+    references: &mut HashMap<Hash, u64>,
+) -> Result<SpaceUsage> {
+    let events = {
+        let conn = space.db().lock().await;
+        let mut stmt =
+            conn.prepare("SELECT kind, schema_hash, data_id, content_hash FROM events")?;
+        let mut rows = stmt.query([])?;
+        let mut events = Vec::new();
+        while let Some(row) = rows.next()? {
+            let kind: EventKind = row.get(0)?;
+            let schema: Option<String> = row.get(1)?;
+            let data_id: Option<Uuid> = row.get(2)?;
+            let content_hash: String = row.get(3)?;
+            events.push((kind, schema, data_id, content_hash));
+        }
+        events
+    };
+
+    let mut usage = SpaceUsage {
+        id: space.id,
+        name: space.name.clone(),
+        total: Usage::default(),
+        tables: HashMap::new(),
+        programs: HashMap::new(),
+        other: Usage::default(),
+    };
+
+    for (kind, schema, data_id, content_hash) in events {
+        let Ok(hash) = Hash::from_str(&content_hash) else {
+            continue;
+        };
+
+        // programs are collections, count the package contents too
+        let mut hashes = vec![hash];
+        if kind == EventKind::MutateProgram {
+            if let Ok(collection) = router.blobs().get_collection(hash).await {
+                hashes.extend(collection.iter().map(|(_, hash)| *hash));
+            }
+        }
+
+        for hash in hashes {
+            *references.entry(hash).or_default() += 1;
+            let size = sizes.get(&hash).copied().unwrap_or_default();
+            usage.total.add(size);
+            match (&kind, &schema, data_id) {
+                (EventKind::MutateRow, Some(schema), _) => {
+                    usage.tables.entry(schema.clone()).or_default().add(size)
+                }
+                (EventKind::MutateProgram, _, Some(id)) => {
+                    usage.programs.entry(id).or_default().add(size)
+                }
+                _ => usage.other.add(size),
+            }
+        }
+    }
+
+    Ok(usage)
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use iroh::docs::Author;
+    use serde_json::json;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn report_counts_shared_and_unreferenced_blobs() -> Result<()> {
+        let node = iroh::node::Node::memory().enable_docs().spawn().await?;
+        let dir = tempfile::tempdir()?;
+        let router = node.client().clone();
+        let mut spaces = Spaces::open_all(router.clone(), dir.path()).await?;
+        let author = Author::new(&mut rand::thread_rng());
+        let space = spaces
+            .create(&router, author.clone(), "storage", "a space")
+            .await?;
+        let schema = json!({ "title": "notes", "type": "object" });
+        let table = space
+            .tables()
+            .create(author.clone(), Bytes::from(schema.to_string()))
+            .await?;
+        let schema = table.content.hash;
+        // the same content twice is stored once
+        for _ in 0..2 {
+            space
+                .rows()
+                .create(author.clone(), schema, json!({ "text": "same" }))
+                .await?;
+        }
+        let content = Hash::new(json!({ "text": "same" }).to_string());
+        let loose = router.blobs().add_bytes(b"a job artifact".to_vec()).await?;
+
+        let report = report(&router, &spaces).await?;
+        let usage = &report.spaces[0];
+        assert_eq!(usage.tables[&schema.to_string()].objects, 2);
+        let shared = report
+            .shared
+            .iter()
+            .find(|object| object.hash == content)
+            .expect("row content is shared");
+        assert_eq!(shared.references, 2);
+        assert!(report.dedup_savings >= shared.size);
+        assert!(report.unreferenced.objects >= 1);
+        assert!(report.unreferenced.bytes >= loose.size);
+        Ok(())
+    }
+}
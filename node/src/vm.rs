@@ -1,49 +1,102 @@
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 use std::path::PathBuf;
+use std::sync::Arc;
 
 use anyhow::{Context, Result};
-use flow::{Flow, Task, TaskOutput};
+use flow::{Flow, FlowCheckpoint, FlowRuns, Task, TaskOutput};
 use futures::StreamExt;
 use iroh::base::node_addr::AddrInfoOptions;
+use iroh::blobs::format::collection::Collection;
 use iroh::client::docs::ShareMode;
 use iroh::docs::{Author, AuthorId, DocTicket, NamespaceId};
 use iroh::net::NodeId;
-use job::{Artifacts, DEFAULT_TIMEOUT};
+use job::{JobNameContext, DEFAULT_TIMEOUT};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
 use tokio::task::JoinHandle;
 use tracing::{debug, info, info_span, warn, Instrument};
 use uuid::Uuid;
 
+use crate::blob_store::BlobStore;
+use crate::disk::DiskMonitor;
+use crate::join_codes::{PublishedCode, RendezvousClient};
+use crate::peers::PeerBook;
 use crate::router::RouterClient;
 
+use crate::space::audit::AuditAction;
+use crate::space::grants::{self, SpaceHandle};
+use crate::space::programs::{Program, ProgramTask, ProgramTaskRun, RunRecord};
 use crate::space::{Space, Spaces};
 use crate::vm::blobs::Blobs;
+use crate::vm::chaos::Chaos;
 use crate::vm::content_routing::AutofetchPolicy;
 use crate::vm::doc::{create_doc, join_doc, subscribe, Doc, DocEventHandler};
 use crate::vm::job::JobDescription;
 use crate::vm::metrics::Metrics;
 use crate::vm::scheduler::Scheduler;
+use crate::vm::thumbnails::Thumbnailer;
 use crate::vm::worker::Worker;
 
+pub mod admission;
 mod blobs;
-mod config;
+pub mod chaos;
+pub mod config;
 pub mod content_routing;
+pub(crate) mod cron;
 mod doc;
-mod docker;
+pub(crate) mod docker;
+pub(crate) mod duration;
 pub mod flow;
-mod job;
-mod metrics;
+pub mod headless;
+pub(crate) mod job;
+pub mod lint;
+mod logs;
+pub(crate) mod metrics;
+pub mod profile;
+mod replay;
 mod scheduler;
+#[cfg(test)]
+mod sim;
+mod store;
+pub mod test_runner;
+pub mod thumbnails;
+pub(crate) mod triggers;
 mod worker;
 
+pub use admission::{AdmissionPolicy, ArtifactViolation, ScannerCommand};
+pub use job::{
+    Artifact, ArtifactRef, Artifacts, DockerNetwork, EgressRecord, JobPriority, JobType,
+};
+pub use logs::{LogChunk, LogStream};
+pub use replay::{ReplayReport, ReplayStep, ReplayedJob};
+pub use scheduler::recovery::RecoveryReport;
+pub use scheduler::simulate::{
+    BlockedTask, FlowSimulation, SimulatedFlow, SimulationReport, TaskEstimate, TaskSimulation,
+    WorkerProfile, WorkerSimulation,
+};
+pub use scheduler::{DeadlineExceeded, SchedulerSnapshot, SlaStats, UnfinishedJob};
+pub use thumbnails::ThumbnailConfig;
+pub use worker::egress::EgressConfig;
+pub use worker::pickup::PickupConfig;
+pub use worker::quota::DiskQuotaConfig;
+pub use worker::slots::SlotsConfig;
+pub use worker::WorkerCapabilities;
+
 #[derive(Debug)]
 pub struct VM {
     router: RouterClient,
+    spaces: Spaces,
     doc: Doc,
     blobs: Blobs,
     scheduler: Scheduler,
     worker: Worker,
+    /// one lock per idempotency key with a run in progress
+    run_keys: Arc<Mutex<HashMap<String, Arc<Mutex<()>>>>>,
+    /// checkpoints of the flows running right now
+    flows: FlowRuns,
     /// Tracks the subscription task, canceling it when the vm gets dropped.
     _doc_subscription_handle: JoinHandle<()>,
+    _thumbnails_handle: Option<JoinHandle<()>>,
 }
 
 impl VM {
@@ -70,29 +123,50 @@ impl VM {
         cfg: VMConfig,
     ) -> Result<Self> {
         let node_id = router.net().node_id().await?;
-        let blobs = Blobs::new(node_id, doc.clone(), router.clone(), cfg.autofetch);
+        let blobs = Blobs::new(
+            node_id,
+            doc.clone(),
+            router.clone(),
+            cfg.autofetch,
+            cfg.peers,
+            cfg.disk,
+            cfg.blob_store,
+            cfg.chaos.clone(),
+        );
         let author_id = node_author_id(&node_id);
         let scheduler =
             Scheduler::new(author_id, doc.clone(), blobs.clone(), router.clone()).await?;
         let worker = Worker::new(
-            spaces,
+            Some(spaces.clone()),
             router.clone(),
             author_id,
             doc.clone(),
             blobs.clone(),
             &cfg.worker_root,
+            &cfg.egress,
+            &cfg.slots,
+            cfg.pickup,
+            cfg.disk_quota,
+            cfg.labels,
+            cfg.admission_scanner,
+            cfg.chaos.clone(),
         )
         .await?;
+        worker.advertise().await?;
 
         let events = subscribe(&doc, node_id).await?;
         let scheduler2 = scheduler.clone();
         let worker2 = worker.clone();
         let blobs2 = blobs.clone();
+        let chaos = cfg.chaos;
 
         let handle = tokio::task::spawn(
             async move {
                 let mut events = std::pin::pin!(events);
                 while let Some(event) = events.next().await {
+                    if chaos.drop_doc_event() {
+                        continue;
+                    }
                     if let Err(err) = scheduler2.handle_event(event.clone()).await {
                         warn!("scheduler failed to handle event: {:?}", err);
                     }
@@ -109,13 +183,34 @@ impl VM {
             .instrument(info_span!("workspace_eventsub", %node_id)),
         );
 
+        // with events handled again, resume the jobs scheduled before the node stopped
+        if let Err(err) = scheduler.recover().await {
+            warn!("failed to recover scheduled jobs: {:?}", err);
+        }
+
+        let thumbnails_handle = cfg.thumbnails.enabled.then(|| {
+            Thumbnailer::new(
+                cfg.thumbnails,
+                spaces.clone(),
+                router.clone(),
+                blobs.clone(),
+                scheduler.clone(),
+                author_id.to_string(),
+            )
+            .spawn()
+        });
+
         let ws = Self {
             router: router.clone(),
+            spaces,
             doc,
             blobs,
             scheduler,
             worker,
+            run_keys: Default::default(),
+            flows: Default::default(),
             _doc_subscription_handle: handle.into(),
+            _thumbnails_handle: thumbnails_handle,
         };
 
         iroh_metrics::inc!(Metrics, workspaces);
@@ -134,6 +229,20 @@ impl VM {
         self.doc.share(ShareMode::Write, opts).await
     }
 
+    /// Leave a write ticket with a rendezvous service, returning a short code another machine
+    /// can join with, e.g. `squiggle-worker --code`. Anyone with the code can join until it's
+    /// used or expires.
+    pub async fn share_code(
+        &self,
+        rendezvous: &RendezvousClient,
+        ttl: std::time::Duration,
+    ) -> Result<PublishedCode> {
+        let ticket = self
+            .get_write_ticket(AddrInfoOptions::RelayAndAddresses)
+            .await?;
+        rendezvous.publish(&ticket.to_string(), ttl).await
+    }
+
     pub fn blobs(&self) -> &Blobs {
         &self.blobs
     }
@@ -146,6 +255,61 @@ impl VM {
         &self.worker
     }
 
+    /// Output of a job as its worker writes it, from the start. The stream ends once the job
+    /// has finished & all of its output was read.
+    pub fn subscribe_job_logs(&self, job_id: Uuid) -> impl futures::Stream<Item = LogChunk> {
+        logs::subscribe(self.blobs.clone(), job_id)
+    }
+
+    /// Get ready for the node process to stop: drain the worker for up to `timeout`, then
+    /// checkpoint the flows still running & snapshot the scheduler. The worker stays disabled
+    /// afterwards, so nothing new starts before the restart.
+    pub async fn prepare_restart(&self, timeout: std::time::Duration) -> Result<RestartCheckpoint> {
+        let interrupted_jobs = self.worker.drain(timeout).await;
+        if !interrupted_jobs.is_empty() {
+            warn!(
+                "{} jobs still running after draining the worker",
+                interrupted_jobs.len()
+            );
+        }
+        let mut flows = self
+            .flows
+            .lock()
+            .expect("poisoned")
+            .values()
+            .cloned()
+            .collect::<Vec<_>>();
+        flows.sort_by_key(|flow| flow.started_at);
+        Ok(RestartCheckpoint {
+            interrupted_jobs,
+            flows,
+            scheduler: self.scheduler.snapshot().await?,
+        })
+    }
+
+    /// The workspace's artifact admission policy, without a scanner, workers each run their
+    /// own.
+    pub async fn admission_policy(&self) -> Result<AdmissionPolicy> {
+        admission::load(&self.blobs, &self.router, None).await
+    }
+
+    /// Replace the workspace's artifact admission policy. Workers pick it up on the next job.
+    /// A scanner in `policy` isn't stored, see [`admission`].
+    pub async fn set_admission_policy(&self, policy: &AdmissionPolicy) -> Result<()> {
+        let node_id = self.router.net().node_id().await?;
+        admission::store(&self.blobs, node_author_id(&node_id), policy).await
+    }
+
+    /// Dry-run the workspace doc entries written within `range`, in microseconds since the
+    /// unix epoch, tracing what the scheduler, workers & blobs would do with each. See
+    /// `vm::replay` for what's modeled.
+    pub async fn replay_events(
+        &self,
+        range: impl std::ops::RangeBounds<u64>,
+    ) -> Result<ReplayReport> {
+        replay::replay(&self.doc, range).await
+    }
+
     // pub async fn run_job(&self, scope: Uuid, id: Uuid, jd: JobDescription) -> Result<Uuid> {
     //     let id = self.scheduler.run_job(scope, id, jd).await?;
     //     Ok(id)
@@ -161,48 +325,606 @@ impl VM {
     //     Ok(result)
     // }
 
+    /// Run a program on behalf of a user, ahead of any batch jobs. With `profile` set, every
+    /// job of the run uploads a flamegraph of where its time went, see [`profile`].
     pub async fn run_program(
         &self,
         space: &Space,
         author: Author,
         id: Uuid,
         environment: HashMap<String, String>,
+        profile: bool,
+    ) -> Result<TaskOutput> {
+        self.run_program_as(
+            space,
+            author,
+            id,
+            environment,
+            JobPriority::Interactive,
+            profile,
+        )
+        .await
+    }
+
+    /// Run a program at most once per `key`. Repeating a request, eg: a webhook that's
+    /// delivered twice, returns the first run's output instead of running the program again,
+    /// as long as the first run finished less than [`RUN_KEY_TTL`] ago. A request that arrives
+    /// while the first run is in progress waits for it.
+    pub async fn run_program_once(
+        &self,
+        space: &Space,
+        author: Author,
+        id: Uuid,
+        environment: HashMap<String, String>,
+        key: &str,
+    ) -> Result<TaskOutput> {
+        let lock_key = format!("{}/{id}/{key}", space.id);
+        let lock = self
+            .run_keys
+            .lock()
+            .await
+            .entry(lock_key.clone())
+            .or_default()
+            .clone();
+        let guard = lock.lock().await;
+        let result = async {
+            let ttl_start = chrono::Utc::now().timestamp() - RUN_KEY_TTL.whole_seconds();
+            if let Some(output) = space.programs().keyed_run(id, key, ttl_start).await? {
+                debug!("program {} already ran with key {}", id, key);
+                return Ok(output);
+            }
+            let output = self
+                .run_program(space, author, id, environment, false)
+                .await?;
+            space
+                .programs()
+                .record_keyed_run(id, key, &output, ttl_start)
+                .await?;
+            Ok(output)
+        }
+        .await;
+        drop(guard);
+
+        // the map & this function hold the only references when nobody else is waiting
+        let mut run_keys = self.run_keys.lock().await;
+        if Arc::strong_count(&lock) == 2 {
+            run_keys.remove(&lock_key);
+        }
+        result
+    }
+
+    pub async fn run_program_as(
+        &self,
+        space: &Space,
+        author: Author,
+        id: Uuid,
+        environment: HashMap<String, String>,
+        priority: JobPriority,
+        profile: bool,
+    ) -> Result<TaskOutput> {
+        let options = RunOptions {
+            priority,
+            profile,
+            spaces: Vec::new(),
+        };
+        self.run_program_with(space, author, id, environment, options)
+            .await
+    }
+
+    /// Run a program with `options`. Every other space the run uses must have granted the
+    /// program access, see [`crate::space::grants`]. Runs that use other spaces are recorded
+    /// in the audit log of each space involved.
+    pub async fn run_program_with(
+        &self,
+        space: &Space,
+        author: Author,
+        id: Uuid,
+        environment: HashMap<String, String>,
+        options: RunOptions,
     ) -> Result<TaskOutput> {
         let program = space.programs().get_by_id(id).await?;
+        space
+            .programs()
+            .resolve_dependencies(&program.manifest)
+            .await
+            .context("resolving program dependencies")?;
+        let linked = grants::linked_spaces(&self.spaces, space.id, program.id, &options.spaces)
+            .await
+            .context("opening spaces the run uses")?;
+        let started_at = chrono::Utc::now().timestamp();
+        let flow = program_flow(space, &author, &program, environment.clone(), &options).await?;
+        let last = flow.tasks.last().map(|task| task.description.name.clone());
+        let result = flow.run(&self).await?;
+        let output = run_output(&result.tasks, last.as_deref()).context("program ran no tasks")?;
+        record_run(
+            space,
+            &author,
+            program.id,
+            result.id,
+            &environment,
+            started_at,
+            &output,
+        )
+        .await;
+        if !linked.is_empty() {
+            let action = AuditAction::CrossSpaceRun {
+                space: space.id,
+                program_id: program.id,
+                run_id: result.id,
+                spaces: options.spaces.clone(),
+            };
+            let involved = std::iter::once(space).chain(linked.iter().map(|(other, _)| other));
+            for involved in involved {
+                if let Err(err) = involved
+                    .audit()
+                    .record(author.clone(), action.clone())
+                    .await
+                {
+                    warn!(
+                        "failed to record run of program {} in space {}: {:?}",
+                        program.id, involved.id, err
+                    );
+                }
+            }
+        }
+        Ok(output)
+    }
+
+    /// Run a single task of a program, without the rest of its flow. Dependencies are taken
+    /// from the artifacts of the most recent runs that produced them, it's an error if one
+    /// can't be found.
+    pub async fn run_task(
+        &self,
+        space: &Space,
+        author: Author,
+        program_id: Uuid,
+        task_name: &str,
+        environment: HashMap<String, String>,
+    ) -> Result<TaskOutput> {
+        let program = space.programs().get_by_id(program_id).await?;
+        space
+            .programs()
+            .resolve_dependencies(&program.manifest)
+            .await
+            .context("resolving program dependencies")?;
+        let options = RunOptions {
+            priority: JobPriority::Interactive,
+            ..Default::default()
+        };
+        let flow = program_flow(space, &author, &program, environment.clone(), &options).await?;
+        let description = flow
+            .task(task_name)
+            .with_context(|| format!("program {} has no task {task_name}", program.manifest.name))?
+            .description
+            .clone();
+
+        let scope = Uuid::new_v4();
+        let prior_scopes = space
+            .programs()
+            .recent_run_scopes(program_id, PRIOR_RUN_LIMIT)
+            .await?;
+        for artifact in &description.artifacts.downloads {
+            let name = JobNameContext { scope }.render(&artifact.name)?;
+            let mut found = false;
+            for prior in &prior_scopes {
+                let prior_name = JobNameContext { scope: *prior }.render(&artifact.name)?;
+                if self.blobs.has_object(&prior_name).await? {
+                    debug!("reusing {} as {}", prior_name, name);
+                    self.blobs.copy_object(&prior_name, &name).await?;
+                    found = true;
+                    break;
+                }
+            }
+            anyhow::ensure!(
+                found,
+                "dependency {} of task {task_name} not found in prior runs",
+                artifact.name
+            );
+        }
+
+        let started_at = chrono::Utc::now().timestamp();
+        let task = Task {
+            tasks: vec![],
+            description,
+        };
+        let outputs = task
+            .run(
+                scope,
+                self.scheduler.clone(),
+                self.blobs.clone(),
+                Uuid::new_v4(),
+            )
+            .await;
+        let output = outputs.into_iter().next().expect("single task");
+        record_run(
+            space,
+            &author,
+            program_id,
+            scope,
+            &environment,
+            started_at,
+            &output,
+        )
+        .await;
+        Ok(output)
+    }
+}
+
+/// How long the output of a run started with an idempotency key is kept.
+pub const RUN_KEY_TTL: time::Duration = time::Duration::DAY;
+
+/// Start of artifact names that live under a run's scope.
+const SCOPE_PREFIX: &str = "{scope}";
+
+/// How many recent runs to search for the dependencies of a single task run.
+const PRIOR_RUN_LIMIT: i64 = 20;
+
+/// The flow a program runs as: the flow its manifest declares, or a single wasm task named
+/// after the program, running its main entry.
+async fn program_flow(
+    space: &Space,
+    author: &Author,
+    program: &Program,
+    environment: HashMap<String, String>,
+    options: &RunOptions,
+) -> Result<Flow> {
+    let run = ProgramRun {
+        space,
+        author,
+        program,
+        environment,
+        options,
+    };
+    let Some(tasks) = &program.manifest.flow else {
         let program_entry_hash = program.program_entry.context("program has no main entry")?;
-        // construct a task so we can schedule it with the VM
-        let result = Flow {
+        return Ok(Flow {
             name: program.manifest.name.clone(),
             tasks: vec![Task {
                 tasks: vec![],
-                description: JobDescription {
-                    space: space.name.clone(),
-                    name: program.manifest.name.clone(),
-                    program_id: program.id,
-                    author: author.id().to_string(),
-                    environment,
-                    details: job::JobDetails::Wasm {
+                description: run.job(
+                    program.manifest.name.clone(),
+                    job::JobDetails::Wasm {
                         module: job::Source::LocalBlob(program_entry_hash),
                     },
-                    artifacts: Artifacts::default(),
-                    timeout: DEFAULT_TIMEOUT,
-                },
+                    Artifacts::default(),
+                    DEFAULT_TIMEOUT,
+                    false,
+                    None,
+                ),
             }],
             uploads: Default::default(),
             downloads: Default::default(),
+            max_concurrency: None,
+        });
+    };
+
+    anyhow::ensure!(!tasks.is_empty(), "program flow has no tasks");
+    let collection = space
+        .router()
+        .blobs()
+        .get_collection(program.content.hash)
+        .await
+        .context("loading program files")?;
+    let flow = Flow {
+        name: program.manifest.name.clone(),
+        tasks: tasks
+            .iter()
+            .map(|task| run.task(&collection, task))
+            .collect::<Result<_>>()?,
+        uploads: Default::default(),
+        downloads: Default::default(),
+        max_concurrency: None,
+    };
+    flow.validate()?;
+    Ok(flow)
+}
+
+/// How a program runs, besides the environment it's given.
+#[derive(Debug, Clone, Default)]
+pub struct RunOptions {
+    pub priority: JobPriority,
+    /// have every job of the run upload a flamegraph of where its time went, see [`profile`]
+    pub profile: bool,
+    /// other spaces the run uses, each must have granted the program the access asked for
+    pub spaces: Vec<SpaceHandle>,
+}
+
+/// Everything a program's jobs share.
+struct ProgramRun<'a> {
+    space: &'a Space,
+    author: &'a Author,
+    program: &'a Program,
+    environment: HashMap<String, String>,
+    options: &'a RunOptions,
+}
+
+impl ProgramRun<'_> {
+    fn job(
+        &self,
+        name: String,
+        details: job::JobDetails,
+        artifacts: Artifacts,
+        timeout: time::Duration,
+        preemptible: bool,
+        deadline: Option<time::OffsetDateTime>,
+    ) -> JobDescription {
+        JobDescription {
+            space: self.space.name.clone(),
+            name,
+            program_id: self.program.id,
+            author: self.author.id().to_string(),
+            environment: self.environment.clone(),
+            details,
+            artifacts,
+            timeout,
+            priority: self.options.priority,
+            preemptible,
+            deadline,
+            requires: Vec::new(),
+            profile: self.options.profile,
+            spaces: self.options.spaces.clone(),
         }
-        .run(&self)
-        .await?;
-        let output = result.tasks.first().expect("single task").clone();
-        Ok(output)
+    }
+
+    fn task(&self, collection: &Collection, task: &ProgramTask) -> Result<Task> {
+        let (details, preemptible) = match &task.run {
+            ProgramTaskRun::Docker {
+                image,
+                command,
+                network,
+            } => (
+                job::JobDetails::Docker {
+                    image: image.clone(),
+                    command: command.clone(),
+                    network: *network,
+                },
+                task.preemptible,
+            ),
+            ProgramTaskRun::Wasm { module } => {
+                let (_, hash) = collection
+                    .iter()
+                    .find(|(name, _)| name == module)
+                    .with_context(|| format!("task {}: program has no file {module}", task.name))?;
+                // wasm jobs can't be interrupted
+                let details = job::JobDetails::Wasm {
+                    module: job::Source::LocalBlob(*hash),
+                };
+                (details, false)
+            }
+        };
+        let timeout = match &task.timeout {
+            Some(timeout) => {
+                duration::parse(timeout).with_context(|| format!("task {}", task.name))?
+            }
+            None => DEFAULT_TIMEOUT,
+        };
+        let deadline = match &task.deadline {
+            Some(deadline) => {
+                let deadline =
+                    duration::parse(deadline).with_context(|| format!("task {}", task.name))?;
+                Some(time::OffsetDateTime::now_utc() + deadline)
+            }
+            None => None,
+        };
+        Ok(Task {
+            tasks: task
+                .tasks
+                .iter()
+                .map(|task| self.task(collection, task))
+                .collect::<Result<_>>()?,
+            description: JobDescription {
+                requires: task.requires.clone(),
+                ..self.job(
+                    task.name.clone(),
+                    details,
+                    scope_artifacts(&task.artifacts),
+                    timeout,
+                    preemptible,
+                    deadline,
+                )
+            },
+        })
+    }
+}
+
+/// Put the downloads of a program task under the run's scope, so runs don't read each
+/// other's artifacts. Uploads are always stored under the scope.
+fn scope_artifacts(artifacts: &Artifacts) -> Artifacts {
+    let downloads = artifacts
+        .downloads
+        .iter()
+        .map(|artifact| match artifact.name.starts_with(SCOPE_PREFIX) {
+            true => artifact.clone(),
+            false => Artifact {
+                name: format!("{SCOPE_PREFIX}/{}", artifact.name),
+                ..artifact.clone()
+            },
+        })
+        .collect();
+    Artifacts {
+        downloads,
+        uploads: artifacts.uploads.clone(),
+    }
+}
+
+/// The output a program run reports: its first failed task, or its last top level task.
+fn run_output(outputs: &[TaskOutput], last: Option<&str>) -> Option<TaskOutput> {
+    outputs
+        .iter()
+        .find(|output| !matches!(output.result.status, job::JobResultStatus::Ok(_)))
+        .or_else(|| {
+            outputs
+                .iter()
+                .find(|output| Some(output.name.as_str()) == last)
+        })
+        .or(outputs.last())
+        .cloned()
+}
+
+/// Add a finished run to the node's usage records & the space's shared run history. Failing
+/// to record doesn't fail the run.
+async fn record_run(
+    space: &Space,
+    author: &Author,
+    program_id: Uuid,
+    scope: Uuid,
+    environment: &HashMap<String, String>,
+    started_at: i64,
+    output: &TaskOutput,
+) {
+    let usage = output.result.usage.clone().unwrap_or_default();
+    let run = RunRecord {
+        id: output.id,
+        program_id,
+        scope,
+        started_at,
+        succeeded: matches!(output.result.status, job::JobResultStatus::Ok(_)),
+        wall_time_ms: usage.wall_time_ms,
+        cpu_time_ms: usage.cpu_time_ms,
+        peak_memory_bytes: usage.peak_memory_bytes,
+        artifact_bytes: usage.artifact_bytes,
+        egress: usage.egress,
+    };
+    if let Err(err) = space.programs().record_run(&run).await {
+        warn!("failed to record run of program {}: {:?}", program_id, err);
+    }
+    if let Err(err) = space
+        .runs()
+        .record(author.clone(), program_id, environment, started_at, output)
+        .await
+    {
+        warn!(
+            "failed to add run of program {} to history: {:?}",
+            program_id, err
+        );
     }
 }
 
 pub struct VMConfig {
     pub autofetch: AutofetchPolicy,
     pub worker_root: PathBuf,
+    pub peers: PeerBook,
+    pub disk: DiskMonitor,
+    pub blob_store: BlobStore,
+    /// how docker jobs reach the network
+    pub egress: EgressConfig,
+    /// how many jobs the worker runs at once
+    pub slots: SlotsConfig,
+    /// when the worker asks for jobs
+    pub pickup: PickupConfig,
+    /// how much disk the worker's jobs may take
+    pub disk_quota: DiskQuotaConfig,
+    /// previews of images & videos attached to rows
+    pub thumbnails: ThumbnailConfig,
+    /// advertised by the worker, for jobs that require them
+    pub labels: BTreeSet<String>,
+    /// run by the worker on every job artifact before it's uploaded
+    pub admission_scanner: Option<ScannerCommand>,
+    /// failure injection, disabled unless built for tests or with the `chaos` feature
+    pub chaos: Chaos,
+}
+
+/// What was still going on when a node got ready to restart, see [`VM::prepare_restart`].
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+#[serde(rename_all = "camelCase")]
+pub struct RestartCheckpoint {
+    /// jobs the worker was still running when draining timed out
+    pub interrupted_jobs: Vec<Uuid>,
+    pub flows: Vec<FlowCheckpoint>,
+    pub scheduler: SchedulerSnapshot,
 }
 
 pub(crate) fn node_author_id(node_id: &NodeId) -> AuthorId {
     AuthorId::from(node_id.as_bytes())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::vm::job::{JobDescription, JobDetails, DEFAULT_TIMEOUT};
+    use crate::vm::test_utils::{create_nodes, setup_logging};
+
+    #[test]
+    fn scopes_program_artifacts() -> Result<()> {
+        let task: ProgramTask = serde_json::from_str(
+            r#"{
+                "name": "report",
+                "type": "docker",
+                "image": "alpine:latest",
+                "command": ["sh", "-c", "wc -l /downloads/rows.csv > /uploads/report.txt"],
+                "artifacts": {
+                    "downloads": [{ "name": "export/rows.csv", "path": "rows.csv" }],
+                    "uploads": [{ "name": "report.txt", "path": "report.txt" }]
+                },
+                "timeout": "5m"
+            }"#,
+        )?;
+        assert!(matches!(
+            task.run,
+            ProgramTaskRun::Docker {
+                network: job::DockerNetwork::Allowlist,
+                ..
+            }
+        ));
+
+        let artifacts = scope_artifacts(&task.artifacts);
+        let names: Vec<_> = artifacts
+            .downloads
+            .iter()
+            .map(|a| a.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["{scope}/export/rows.csv"]);
+        // already scoped names are left alone
+        assert_eq!(scope_artifacts(&artifacts), artifacts);
+        assert_eq!(artifacts.uploads, task.artifacts.uploads);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn prepare_restart_checkpoints_unfinished_jobs() -> Result<()> {
+        setup_logging();
+
+        let dir = tempfile::tempdir().context("tempdir")?;
+        let nodes = create_nodes(&dir, 1).await?;
+        let (_, vm) = &nodes[0];
+        vm.worker().disable();
+
+        let job_id = Uuid::new_v4();
+        vm.scheduler()
+            .run_job(
+                Uuid::new_v4(),
+                job_id,
+                JobDescription {
+                    name: "waiting".into(),
+                    details: JobDetails::Wasm {
+                        module: "min.wat".into(),
+                    },
+                    artifacts: Default::default(),
+                    timeout: DEFAULT_TIMEOUT,
+                },
+            )
+            .await?;
+
+        let checkpoint = vm.prepare_restart(Duration::ZERO).await?;
+        assert!(checkpoint.interrupted_jobs.is_empty());
+        assert!(checkpoint.flows.is_empty());
+        assert_eq!(
+            checkpoint.scheduler.unfinished,
+            vec![UnfinishedJob {
+                id: job_id,
+                status: "scheduling".into(),
+            }]
+        );
+
+        // survives the trip through `restart.json`
+        let data = serde_json::to_vec(&checkpoint)?;
+        assert_eq!(
+            serde_json::from_slice::<RestartCheckpoint>(&data)?,
+            checkpoint
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,242 @@
+//! Checks run on job artifacts before they're written to the workspace.
+//!
+//! Each workspace carries an [`AdmissionPolicy`] in its doc: size caps & blocked file
+//! extensions. Workers load it before uploading a job's artifacts, files that fail a check
+//! are left out of the workspace & reported in the job's usage as [`ArtifactViolation`]s.
+//!
+//! A worker can also run an external scanner on every artifact. The scanner is a command the
+//! worker runs, so it only ever comes from the node's own config, never from the doc.
+use std::path::Path;
+use std::process::Stdio;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use iroh::docs::store::Query;
+use iroh::docs::AuthorId;
+use serde::{Deserialize, Serialize};
+use tokio::process::Command;
+
+use crate::router::RouterClient;
+
+use super::blobs::Blobs;
+
+/// doc key the workspace's policy is stored under
+pub(crate) const ADMISSION_POLICY_KEY: &str = "config/admission";
+
+/// how much scanner output is kept in a violation
+const MAX_SCANNER_OUTPUT: usize = 512;
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AdmissionPolicy {
+    /// largest single artifact file, in bytes
+    pub max_artifact_bytes: Option<u64>,
+    /// largest sum of artifact files uploaded by one job, in bytes
+    pub max_total_bytes: Option<u64>,
+    /// file extensions that are never uploaded, without the leading dot. Case insensitive.
+    pub blocked_extensions: Vec<String>,
+    /// the worker's scanner, from its config, see [`load`]
+    #[serde(skip)]
+    pub scanner: Option<ScannerCommand>,
+}
+
+/// An external program run on every artifact, with the file path appended to `command`.
+/// A non-zero exit rejects the artifact.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScannerCommand {
+    pub command: Vec<String>,
+    #[serde(default = "default_scanner_timeout")]
+    pub timeout_secs: u64,
+}
+
+fn default_scanner_timeout() -> u64 {
+    60
+}
+
+/// An artifact that wasn't uploaded, and why.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+pub struct ArtifactViolation {
+    /// path of the file, relative to the job's upload directory
+    pub artifact: String,
+    pub reason: String,
+}
+
+impl AdmissionPolicy {
+    pub fn is_empty(&self) -> bool {
+        self == &AdmissionPolicy::default()
+    }
+
+    /// Check a file that's `size` bytes, given `uploaded` bytes were already accepted from
+    /// the same job. Returns the reason the file is rejected, if it is.
+    pub async fn check(&self, path: &Path, size: u64, uploaded: u64) -> Option<String> {
+        if let Some(max) = self.max_artifact_bytes {
+            if size > max {
+                return Some(format!("artifact is {size} bytes, limit is {max}"));
+            }
+        }
+        if let Some(max) = self.max_total_bytes {
+            if uploaded + size > max {
+                return Some(format!("job artifacts exceed {max} bytes"));
+            }
+        }
+        if let Some(ext) = path.extension().and_then(|ext| ext.to_str()) {
+            let blocked = self
+                .blocked_extensions
+                .iter()
+                .any(|b| b.trim_start_matches('.').eq_ignore_ascii_case(ext));
+            if blocked {
+                return Some(format!("extension .{ext} is blocked"));
+            }
+        }
+        match &self.scanner {
+            Some(scanner) => scanner.scan(path).await,
+            None => None,
+        }
+    }
+}
+
+impl ScannerCommand {
+    async fn scan(&self, path: &Path) -> Option<String> {
+        let Some((program, args)) = self.command.split_first() else {
+            return Some("scanner command is empty".to_string());
+        };
+        let child = Command::new(program)
+            .args(args)
+            .arg(path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn();
+        let child = match child {
+            Ok(child) => child,
+            Err(err) => return Some(format!("running scanner {program}: {err}")),
+        };
+        let timeout = Duration::from_secs(self.timeout_secs);
+        let output = match tokio::time::timeout(timeout, child.wait_with_output()).await {
+            Ok(Ok(output)) => output,
+            Ok(Err(err)) => return Some(format!("running scanner {program}: {err}")),
+            Err(_) => return Some(format!("scanner timed out after {}s", self.timeout_secs)),
+        };
+        if output.status.success() {
+            return None;
+        }
+        let mut message = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        if message.is_empty() {
+            message = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        }
+        if message.len() > MAX_SCANNER_OUTPUT {
+            let mut end = MAX_SCANNER_OUTPUT;
+            while !message.is_char_boundary(end) {
+                end -= 1;
+            }
+            message.truncate(end);
+        }
+        match output.status.code() {
+            Some(code) if message.is_empty() => Some(format!("scanner exited with {code}")),
+            Some(code) => Some(format!("scanner exited with {code}: {message}")),
+            None => Some("scanner was killed".to_string()),
+        }
+    }
+}
+
+/// The workspace's policy with this worker's `scanner`, the default policy lets everything
+/// through.
+pub(crate) async fn load(
+    blobs: &Blobs,
+    router: &RouterClient,
+    scanner: Option<&ScannerCommand>,
+) -> Result<AdmissionPolicy> {
+    let query = Query::single_latest_per_key().key_exact(ADMISSION_POLICY_KEY);
+    let mut policy = match blobs.doc().get_one(query).await? {
+        Some(entry) => {
+            blobs.fetch_blob(entry.content_hash()).await?;
+            let data = router.blobs().read_to_bytes(entry.content_hash()).await?;
+            serde_json::from_slice::<AdmissionPolicy>(&data)
+                .context("parsing artifact admission policy")?
+        }
+        None => AdmissionPolicy::default(),
+    };
+    policy.scanner = scanner.cloned();
+    Ok(policy)
+}
+
+pub(crate) async fn store(blobs: &Blobs, author: AuthorId, policy: &AdmissionPolicy) -> Result<()> {
+    let data = serde_json::to_vec(policy)?;
+    blobs
+        .doc()
+        .set_bytes(author, ADMISSION_POLICY_KEY, data)
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn admission_checks() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.exe");
+        tokio::fs::write(&path, b"hello").await.unwrap();
+
+        let policy = AdmissionPolicy::default();
+        assert!(policy.is_empty());
+        assert_eq!(policy.check(&path, 5, 0).await, None);
+
+        let policy = AdmissionPolicy {
+            max_artifact_bytes: Some(4),
+            ..Default::default()
+        };
+        assert!(policy.check(&path, 5, 0).await.is_some());
+
+        let policy = AdmissionPolicy {
+            max_total_bytes: Some(10),
+            ..Default::default()
+        };
+        assert_eq!(policy.check(&path, 5, 5).await, None);
+        assert!(policy.check(&path, 5, 6).await.is_some());
+
+        let policy = AdmissionPolicy {
+            blocked_extensions: vec![".EXE".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(
+            policy.check(&path, 5, 0).await,
+            Some("extension .exe is blocked".to_string())
+        );
+
+        let scanner = |script: &str| AdmissionPolicy {
+            scanner: Some(ScannerCommand {
+                command: vec!["sh".into(), "-c".into(), script.into(), "scan".into()],
+                timeout_secs: 5,
+            }),
+            ..Default::default()
+        };
+        assert_eq!(scanner("test -f \"$1\"").check(&path, 5, 0).await, None);
+        assert_eq!(
+            scanner("echo infected; exit 3").check(&path, 5, 0).await,
+            Some("scanner exited with 3: infected".to_string())
+        );
+    }
+
+    #[test]
+    fn scanner_isnt_synced() -> Result<()> {
+        let policy: AdmissionPolicy = serde_json::from_str(
+            r#"{ "blocked_extensions": ["exe"], "scanner": { "command": ["sh", "-c", "id"] } }"#,
+        )?;
+        assert_eq!(policy.scanner, None);
+        assert_eq!(policy.blocked_extensions, vec!["exe".to_string()]);
+
+        let policy = AdmissionPolicy {
+            scanner: Some(ScannerCommand {
+                command: vec!["clamscan".into()],
+                timeout_secs: 5,
+            }),
+            ..Default::default()
+        };
+        assert!(!serde_json::to_string(&policy)?.contains("clamscan"));
+        Ok(())
+    }
+}
@@ -1,21 +1,44 @@
-use anyhow::{anyhow, Result};
+use std::collections::BTreeMap;
+use std::ops::Bound;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Context, Result};
 use bytes::Bytes;
 use futures::TryStreamExt;
+use iroh::blobs::util::SetTagOption;
 use iroh::blobs::Hash;
+use iroh::client::blobs::WrapOption;
 use iroh::client::docs::Entry;
 use iroh::docs::store::Query;
 use iroh::docs::AuthorId;
 use iroh::net::NodeId;
-
+use serde::{Deserialize, Serialize};
 use tracing::{debug, warn};
 
+use crate::blob_store::{BlobBackend, BlobStore};
+use crate::disk::DiskMonitor;
+use crate::peers::PeerBook;
 use crate::router::RouterClient;
 
+use super::chaos::Chaos;
 use super::content_routing::{AutofetchPolicy, ContentRouter};
 use super::doc::{Doc, Event, EventData};
 
 /// prefix used for blobs in the doc
 pub(crate) const BLOBS_DOC_PREFIX: &str = "blobs";
+/// prefix of object metadata in the doc, see [`ObjectMeta`]
+const META_DOC_PREFIX: &str = "blobs-meta";
+/// Ends every object key. Docs delete by prefix, the terminator keeps a name from being the
+/// prefix of another object's key
+const OBJECT_KEY_END: char = '\0';
+/// Files at least this big are stored compressed by [`Blobs::put_file`].
+pub(crate) const COMPRESSION_THRESHOLD: u64 = 64 * 1024;
+/// Compressed files are only kept when they're at most this share of the original size.
+const MAX_COMPRESSION_RATIO: f64 = 0.9;
+const ZSTD_LEVEL: i32 = 3;
+/// object writes buffered per subscriber, see [`Blobs::subscribe_objects`]
+const OBJECT_EVENTS_CAPACITY: usize = 1024;
 
 #[derive(Debug, Clone)]
 pub struct Blobs {
@@ -24,18 +47,45 @@ pub struct Blobs {
     node: RouterClient,
     doc: Doc,
     content_router: ContentRouter,
+    /// durable tier behind the local iroh store
+    store: BlobStore,
+    chaos: Chaos,
+    /// names of objects written to the doc, by anyone
+    objects: tokio::sync::broadcast::Sender<String>,
+    /// the newest entry of every object by name, tombstones included. Loaded on first use
+    index: Arc<tokio::sync::RwLock<Option<BTreeMap<String, ObjectInfo>>>>,
 }
 
 impl Blobs {
-    pub fn new(node_id: NodeId, doc: Doc, node: RouterClient, autofetch: AutofetchPolicy) -> Self {
+    pub fn new(
+        node_id: NodeId,
+        doc: Doc,
+        node: RouterClient,
+        autofetch: AutofetchPolicy,
+        peers: PeerBook,
+        disk: DiskMonitor,
+        store: BlobStore,
+        chaos: Chaos,
+    ) -> Self {
         let author_id = iroh::docs::AuthorId::from(node_id.as_bytes());
-        let content_router =
-            ContentRouter::new(author_id, node_id, doc.clone(), node.clone(), autofetch);
+        let content_router = ContentRouter::new(
+            author_id,
+            node_id,
+            doc.clone(),
+            node.clone(),
+            autofetch,
+            peers,
+            disk,
+        );
         Self {
             node_id,
             doc,
             node,
             content_router,
+            store,
+            chaos,
+            objects: tokio::sync::broadcast::Sender::new(OBJECT_EVENTS_CAPACITY),
+            index: Default::default(),
         }
     }
 
@@ -47,84 +97,392 @@ impl Blobs {
         &self.content_router
     }
 
+    /// Change whether content is fetched as soon as a provider announces it.
+    pub fn set_autofetch(&self, policy: AutofetchPolicy) {
+        self.content_router.set_autofetch(policy);
+    }
+
     pub async fn fetch_blob(&self, hash: Hash) -> Result<()> {
-        self.content_router.fetch_blob(hash).await
+        self.chaos.delay_blob_fetch().await;
+        match self.content_router.fetch_blob(hash).await {
+            Err(err) if self.store.is_remote() => {
+                debug!("falling back to blob store for {}: {:?}", hash, err);
+                let data = self.store.get(hash).await?.ok_or(err)?;
+                self.node.blobs().add_bytes(data).await?;
+                self.router()
+                    .announce_provide(self.author_id(), hash, self.node_id)
+                    .await
+            }
+            res => res,
+        }
     }
 
     fn author_id(&self) -> AuthorId {
         self.node_id.as_bytes().into()
     }
 
-    pub async fn list_objects(&self) -> Result<Vec<Entry>> {
-        let query = Query::key_prefix(BLOBS_DOC_PREFIX);
-        let entries = self
-            .doc
-            .get_many(query)
-            .await?
-            .map_ok(|e| {
-                debug!("entry: {:?}", e);
-                e
-            })
-            .try_collect()
-            .await?;
-        Ok(entries)
+    /// List objects whose names start with `prefix`, in name order. Pass the cursor of a
+    /// page back in to get the next one.
+    pub async fn list_objects(
+        &self,
+        prefix: &str,
+        cursor: Option<&str>,
+        limit: usize,
+    ) -> Result<ObjectPage> {
+        self.load_index().await?;
+        let index = self.index.read().await;
+        let index = index.as_ref().expect("index is loaded");
+        let start = match cursor {
+            Some(cursor) if cursor >= prefix => Bound::Excluded(cursor),
+            _ => Bound::Included(prefix),
+        };
+        let mut objects = Vec::new();
+        let mut more = false;
+        for (name, object) in index.range::<str, _>((start, Bound::Unbounded)) {
+            if !name.starts_with(prefix) {
+                break;
+            }
+            // tombstones
+            if object.size == 0 {
+                continue;
+            }
+            if objects.len() == limit {
+                more = true;
+                break;
+            }
+            objects.push(object.clone());
+        }
+        let cursor = match more {
+            true => objects.last().map(|object| object.name.clone()),
+            false => None,
+        };
+        Ok(ObjectPage { objects, cursor })
+    }
+
+    /// Read every object entry into the index, unless that's been done already.
+    async fn load_index(&self) -> Result<()> {
+        if self.index.read().await.is_some() {
+            return Ok(());
+        }
+        let mut index = self.index.write().await;
+        if index.is_some() {
+            return Ok(());
+        }
+        // tombstones have to be included, otherwise an older version of a deleted object,
+        // written by another author, stands in for it
+        let query = Query::single_latest_per_key()
+            .key_prefix(format!("{}/", BLOBS_DOC_PREFIX))
+            .include_empty();
+        let mut entries = self.doc.get_many(query).await?;
+        let mut objects = BTreeMap::new();
+        while let Some(entry) = entries.try_next().await? {
+            if let Some(name) = std::str::from_utf8(entry.key())
+                .ok()
+                .and_then(|key| event_components(key).ok())
+            {
+                objects.insert(name.to_string(), ObjectInfo::new(name, &entry));
+            }
+        }
+        *index = Some(objects);
+        Ok(())
+    }
+
+    /// Keep a loaded index up to date with an entry for object `name`, the newest entry wins.
+    async fn index_entry(&self, name: &str, entry: &Entry) {
+        let mut index = self.index.write().await;
+        let Some(index) = index.as_mut() else {
+            return;
+        };
+        match index.get(name) {
+            Some(indexed) if indexed.updated_at > entry.timestamp() => {}
+            _ => {
+                index.insert(name.to_string(), ObjectInfo::new(name, entry));
+            }
+        }
     }
 
     pub async fn put_bytes(&self, key: &str, data: impl Into<bytes::Bytes>) -> Result<(Hash, u64)> {
-        let res = self.node.blobs().add_bytes(data.into()).await?;
-        self.put_object(key, res.hash, res.size).await?;
+        let data = data.into();
+        let res = self.node.blobs().add_bytes(data.clone()).await?;
+        if self.store.is_remote() {
+            self.store.put(res.hash, data).await?;
+        }
+        self.set_object(key, res.hash, res.size).await?;
         Ok((res.hash, res.size))
     }
 
+    /// Name a blob that's already in the local store.
     pub async fn put_object(&self, key: &str, hash: Hash, size: u64) -> Result<()> {
-        let key = object_key(key);
+        if self.store.is_remote() {
+            let data = self.node.blobs().read_to_bytes(hash).await?;
+            self.store.put(hash, data).await?;
+        }
+        self.set_object(key, hash, size).await
+    }
+
+    async fn set_object(&self, key: &str, hash: Hash, size: u64) -> Result<()> {
+        if key.contains(OBJECT_KEY_END) {
+            return Err(anyhow!("object names can't contain NUL: {:?}", key));
+        }
         let author_id = self.author_id();
-        self.doc.set_hash(author_id, key, hash, size).await?;
+        self.doc
+            .set_hash(author_id, object_key(key), hash, size)
+            .await?;
+        self.index_latest(key).await?;
         self.router()
             .announce_provide(author_id, hash, self.node_id)
             .await
     }
 
+    /// Index what was just written to object `key`, so it's listed right away.
+    async fn index_latest(&self, key: &str) -> Result<()> {
+        let query = Query::single_latest_per_key()
+            .key_exact(object_key(key))
+            .include_empty();
+        if let Some(entry) = self.doc.get_one(query).await? {
+            self.index_entry(key, &entry).await;
+        }
+        Ok(())
+    }
+
+    /// Store a file under `key`, zstd compressed if it's big & compresses well. Returns the
+    /// size of the file.
+    pub async fn put_file(&self, key: &str, path: &Path) -> Result<(Hash, u64)> {
+        let size = tokio::fs::metadata(path).await?.len();
+        if size >= COMPRESSION_THRESHOLD {
+            let compressed = compress_file(path.to_path_buf()).await?;
+            if compressed.as_file().metadata()?.len() as f64 <= size as f64 * MAX_COMPRESSION_RATIO
+            {
+                let res = self
+                    .node
+                    .blobs()
+                    .add_from_path(
+                        compressed.path().to_path_buf(),
+                        false,
+                        SetTagOption::Auto,
+                        WrapOption::NoWrap,
+                    )
+                    .await?
+                    .finish()
+                    .await?;
+                let meta = ObjectMeta {
+                    hash: res.hash,
+                    content_encoding: ContentEncoding::Zstd,
+                    size,
+                };
+                // metadata goes first so it syncs along with, or ahead of, the object
+                self.set_meta(key, &meta).await?;
+                self.put_object(key, res.hash, res.size).await?;
+                return Ok((res.hash, size));
+            }
+        }
+        let source = tokio::fs::File::open(path).await?;
+        let res = self
+            .node
+            .blobs()
+            .add_reader(source, SetTagOption::Auto)
+            .await?
+            .await?;
+        self.put_object(key, res.hash, res.size).await?;
+        Ok((res.hash, size))
+    }
+
+    /// Name the content of object `from` as `to` too, metadata included.
+    pub async fn copy_object(&self, from: &str, to: &str) -> Result<()> {
+        let entry = self.get_object_info(from).await?;
+        if let Some(meta) = self.object_meta(from, &entry).await? {
+            self.set_meta(to, &meta).await?;
+        }
+        self.put_object(to, entry.content_hash(), entry.content_len())
+            .await
+    }
+
+    /// How the current content of an object is encoded.
+    pub async fn object_encoding(&self, key: &str, entry: &Entry) -> Result<ContentEncoding> {
+        let meta = self.object_meta(key, entry).await?;
+        Ok(meta.map(|meta| meta.content_encoding).unwrap_or_default())
+    }
+
+    /// Metadata of `entry`, the current version of object `key`. Metadata written for an
+    /// older version doesn't count.
+    async fn object_meta(&self, key: &str, entry: &Entry) -> Result<Option<ObjectMeta>> {
+        let query = Query::single_latest_per_key().key_exact(meta_key(key));
+        let Some(meta_entry) = self.doc.get_one(query).await? else {
+            return Ok(None);
+        };
+        self.fetch_blob(meta_entry.content_hash()).await?;
+        let data = self
+            .node
+            .blobs()
+            .read_to_bytes(meta_entry.content_hash())
+            .await?;
+        let meta: ObjectMeta = serde_json::from_slice(&data)
+            .with_context(|| format!("reading metadata of {}", key))?;
+        Ok((meta.hash == entry.content_hash()).then_some(meta))
+    }
+
+    async fn set_meta(&self, key: &str, meta: &ObjectMeta) -> Result<()> {
+        let data = Bytes::from(serde_json::to_vec(meta)?);
+        let res = self.node.blobs().add_bytes(data.clone()).await?;
+        if self.store.is_remote() {
+            self.store.put(res.hash, data).await?;
+        }
+        let author_id = self.author_id();
+        self.doc
+            .set_hash(author_id, meta_key(key), res.hash, res.size)
+            .await?;
+        self.router()
+            .announce_provide(author_id, res.hash, self.node_id)
+            .await
+    }
+
     pub async fn fetch_object(&self, key: &str) -> Result<()> {
         let info = self.get_object_info(key).await?;
         self.fetch_blob(info.content_hash()).await?;
         Ok(())
     }
 
+    /// The content of an object, decompressed.
     pub async fn get_object(&self, key: &str) -> Result<Bytes> {
         let info = self.get_object_info(key).await?;
         self.fetch_blob(info.content_hash()).await?;
         let data = self.node.blobs().read_to_bytes(info.content_hash()).await?;
-        Ok(data)
+        match self.object_encoding(key, &info).await? {
+            ContentEncoding::Identity => Ok(data),
+            ContentEncoding::Zstd => {
+                let data = tokio::task::spawn_blocking(move || zstd::decode_all(&data[..]))
+                    .await?
+                    .with_context(|| format!("decompressing {}", key))?;
+                Ok(data.into())
+            }
+        }
     }
 
     pub async fn get_object_info(&self, key: &str) -> Result<Entry> {
-        let key = object_key(key);
-        let query = Query::key_exact(key.clone());
-        match self.doc.get_one(query).await? {
+        match self.latest_object(key).await? {
             Some(entry) => Ok(entry),
             None => Err(anyhow!("object not found: {}", key)),
         }
     }
 
     pub async fn has_object(&self, key: &str) -> Result<bool> {
-        let key = object_key(key);
-        let query = Query::key_exact(key);
-        let res = self.doc.get_one(query).await?;
-        Ok(res.is_some())
+        Ok(self.latest_object(key).await?.is_some())
+    }
+
+    /// Remove an object from the namespace. The blob itself stays in stores that have it.
+    pub async fn delete_object(&self, key: &str) -> Result<()> {
+        if !self.has_object(key).await? {
+            return Err(anyhow!("object not found: {}", key));
+        }
+        // keys are terminated, so this only clears the object itself
+        self.doc.del(self.author_id(), object_key(key)).await?;
+        self.index_latest(key).await?;
+        debug!("deleted object {}", key);
+        Ok(())
+    }
+
+    /// The newest entry for an object, None if there isn't one or it was deleted.
+    async fn latest_object(&self, key: &str) -> Result<Option<Entry>> {
+        let query = Query::single_latest_per_key()
+            .key_exact(object_key(key))
+            .include_empty();
+        let entry = self.doc.get_one(query).await?;
+        Ok(entry.filter(|entry| entry.content_len() > 0))
     }
 
-    pub async fn delete_object(&self, _key: &str) -> Result<()> {
-        todo!();
+    /// Names of objects as they're written, locally or by peers. Slow receivers miss names,
+    /// see [`tokio::sync::broadcast`].
+    pub fn subscribe_objects(&self) -> tokio::sync::broadcast::Receiver<String> {
+        self.objects.subscribe()
     }
 
     pub(crate) async fn handle_event(&self, event: Event) -> Result<()> {
+        match event.data {
+            EventData::Blobs(BlobsEvent::ObjectPut { ref name }) => {
+                self.index_entry(name, &event.entry).await;
+                // nobody listening is fine
+                let _ = self.objects.send(name.clone());
+            }
+            EventData::Blobs(BlobsEvent::ObjectDeleted { ref name }) => {
+                self.index_entry(name, &event.entry).await;
+            }
+            _ => {}
+        }
         self.content_router.handle_event(event).await
     }
 }
 
 fn object_key(key: &str) -> String {
-    format!("{}/{}", BLOBS_DOC_PREFIX, key)
+    format!("{}/{}{}", BLOBS_DOC_PREFIX, key, OBJECT_KEY_END)
+}
+
+fn meta_key(key: &str) -> String {
+    format!("{}/{}", META_DOC_PREFIX, key)
+}
+
+/// Compress a file into a temporary one, which is removed once dropped.
+async fn compress_file(path: std::path::PathBuf) -> Result<tempfile::NamedTempFile> {
+    tokio::task::spawn_blocking(move || {
+        let source = std::fs::File::open(&path)?;
+        let mut compressed = tempfile::NamedTempFile::new()?;
+        zstd::stream::copy_encode(source, compressed.as_file_mut(), ZSTD_LEVEL)
+            .with_context(|| format!("compressing {}", path.display()))?;
+        Ok(compressed)
+    })
+    .await?
+}
+
+/// How an object's content is stored.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ContentEncoding {
+    /// as written
+    #[default]
+    Identity,
+    Zstd,
+}
+
+/// What's recorded about an object beyond its hash, kept next to it in the doc. Objects
+/// without metadata are stored as written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ObjectMeta {
+    /// the content the metadata describes
+    pub hash: Hash,
+    pub content_encoding: ContentEncoding,
+    /// size of the content once decoded
+    pub size: u64,
+}
+
+/// A named blob.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjectInfo {
+    pub name: String,
+    pub hash: Hash,
+    pub size: u64,
+    /// who last wrote the name
+    pub author: AuthorId,
+    /// microseconds since the unix epoch
+    pub updated_at: u64,
+}
+
+impl ObjectInfo {
+    fn new(name: &str, entry: &Entry) -> Self {
+        ObjectInfo {
+            name: name.to_string(),
+            hash: entry.content_hash(),
+            size: entry.content_len(),
+            author: entry.author(),
+            updated_at: entry.timestamp(),
+        }
+    }
+}
+
+/// A page of [`Blobs::list_objects`]. `cursor` is None on the last page.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjectPage {
+    pub objects: Vec<ObjectInfo>,
+    pub cursor: Option<String>,
 }
 
 impl std::hash::Hash for Blobs {
@@ -135,19 +493,24 @@ impl std::hash::Hash for Blobs {
 
 #[derive(Debug, Clone)]
 pub(crate) enum BlobsEvent {
-    // ObjectPut { key: String, hash: Hash, size: u64 },
-    BlobPut,
-    // ObjectDeleted { key: String, hash: Hash, size: u64 },
+    ObjectPut {
+        name: String,
+    },
+    /// a tombstone for the object
+    ObjectDeleted {
+        name: String,
+    },
 }
 
-pub(crate) fn parse_blobs_event(key: &str) -> Option<EventData> {
+pub(crate) fn parse_blobs_event(key: &str, entry: &Entry) -> Option<EventData> {
     match event_components(key) {
         // matched key here has the BLOBS_DOC_PREFIX removed
-        Ok(_key) => {
-            // let hash = entry.content_hash();
-            // let size = entry.content_len();
-            Some(EventData::Blobs(BlobsEvent::BlobPut))
-        }
+        Ok(name) if entry.content_len() == 0 => Some(EventData::Blobs(BlobsEvent::ObjectDeleted {
+            name: name.to_string(),
+        })),
+        Ok(name) => Some(EventData::Blobs(BlobsEvent::ObjectPut {
+            name: name.to_string(),
+        })),
         Err(e) => {
             warn!("parse_blobs_event: {:?}", e);
             None
@@ -158,6 +521,7 @@ pub(crate) fn parse_blobs_event(key: &str) -> Option<EventData> {
 fn event_components(key: &str) -> Result<&str> {
     let object_name = key
         .strip_prefix(&format!("{}/", BLOBS_DOC_PREFIX))
+        .and_then(|name| name.strip_suffix(OBJECT_KEY_END))
         .ok_or_else(|| anyhow!("invalid object key"))?;
 
     Ok(object_name)
@@ -165,6 +529,7 @@ fn event_components(key: &str) -> Result<&str> {
 
 #[cfg(test)]
 mod tests {
+    use super::ContentEncoding;
     use crate::vm::test_utils::create_nodes;
     use anyhow::{Context, Result};
 
@@ -185,4 +550,103 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn list_and_delete_objects() -> Result<()> {
+        let temp_dir = tempfile::tempdir().context("tempdir")?;
+        let nodes = create_nodes(&temp_dir, 1).await?;
+        let (_node, ws) = &nodes[0];
+        let blobs = ws.blobs();
+
+        for name in ["logs/a", "logs/a.txt", "logs/b", "logs/c", "out/d"] {
+            blobs.put_bytes(name, name.to_string()).await?;
+        }
+
+        let page = blobs.list_objects("logs/", None, 2).await?;
+        let names: Vec<_> = page.objects.iter().map(|o| o.name.as_str()).collect();
+        assert_eq!(names, vec!["logs/a", "logs/a.txt"]);
+        let page = blobs
+            .list_objects("logs/", page.cursor.as_deref(), 2)
+            .await?;
+        let names: Vec<_> = page.objects.iter().map(|o| o.name.as_str()).collect();
+        assert_eq!(names, vec!["logs/b", "logs/c"]);
+        assert_eq!(page.cursor, None);
+
+        // deleting a name leaves names it's a prefix of alone
+        blobs.delete_object("logs/a").await?;
+        assert!(!blobs.has_object("logs/a").await?);
+        assert!(blobs.get_object("logs/a").await.is_err());
+        assert!(blobs.has_object("logs/a.txt").await?);
+        let page = blobs.list_objects("logs/", None, 10).await?;
+        let names: Vec<_> = page.objects.iter().map(|o| o.name.as_str()).collect();
+        assert_eq!(names, vec!["logs/a.txt", "logs/b", "logs/c"]);
+        assert!(blobs.delete_object("logs/a").await.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn lists_replicated_objects() -> Result<()> {
+        let temp_dir = tempfile::tempdir().context("tempdir")?;
+        let nodes = create_nodes(&temp_dir, 2).await?;
+        let (_, ws1) = &nodes[0];
+        let (_, ws2) = &nodes[1];
+
+        // the index is loaded before anything's written, later writes keep it current
+        assert!(ws2
+            .blobs()
+            .list_objects("", None, 10)
+            .await?
+            .objects
+            .is_empty());
+        ws1.blobs().put_bytes("logs/a", "a").await?;
+        ws1.blobs().put_bytes("logs/b", "b").await?;
+        ws1.blobs().delete_object("logs/a").await?;
+
+        // silly wait for replication
+        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+
+        let page = ws2.blobs().list_objects("logs/", None, 10).await?;
+        let names: Vec<_> = page.objects.iter().map(|o| o.name.as_str()).collect();
+        assert_eq!(names, vec!["logs/b"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn compress_large_files() -> Result<()> {
+        let temp_dir = tempfile::tempdir().context("tempdir")?;
+        let nodes = create_nodes(&temp_dir, 1).await?;
+        let (_node, ws) = &nodes[0];
+        let blobs = ws.blobs();
+
+        let log = "a line of a rather repetitive log\n".repeat(10_000);
+        let path = temp_dir.path().join("job.log");
+        std::fs::write(&path, &log)?;
+        assert_eq!(blobs.put_file("job.log", &path).await?.1, log.len() as u64);
+        let entry = blobs.get_object_info("job.log").await?;
+        assert!(entry.content_len() < log.len() as u64 / 10);
+        assert_eq!(
+            blobs.object_encoding("job.log", &entry).await?,
+            ContentEncoding::Zstd
+        );
+        assert_eq!(blobs.get_object("job.log").await?, log.as_bytes());
+
+        // copies keep their encoding, overwrites drop it
+        blobs.copy_object("job.log", "copy.log").await?;
+        assert_eq!(blobs.get_object("copy.log").await?, log.as_bytes());
+        blobs.put_bytes("job.log", "short").await?;
+        let entry = blobs.get_object_info("job.log").await?;
+        assert_eq!(
+            blobs.object_encoding("job.log", &entry).await?,
+            ContentEncoding::Identity
+        );
+        assert_eq!(blobs.get_object("job.log").await?, "short".as_bytes());
+
+        let small = temp_dir.path().join("small.txt");
+        std::fs::write(&small, "small")?;
+        blobs.put_file("small.txt", &small).await?;
+        let entry = blobs.get_object_info("small.txt").await?;
+        assert_eq!(entry.content_len(), 5);
+        Ok(())
+    }
 }
@@ -0,0 +1,147 @@
+//! Failure injection for workspace tests.
+//!
+//! A [`Chaos`] handle is threaded through the VM, blobs & worker. It's disabled by default &
+//! can only be turned on in tests or with the `chaos` feature, so production builds only ever
+//! pay for an `Option` check. Every hook draws from its own rng stream, derived from a single
+//! seed, so a failing run can be replayed by reusing its seed.
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rand::rngs::StdRng;
+use rand::Rng;
+use tracing::debug;
+
+/// Probabilities are in `[0, 1]`, `0` disables a hook.
+#[derive(Debug, Clone, Default)]
+pub struct ChaosConfig {
+    pub seed: u64,
+    /// chance that a doc event is dropped before the scheduler, worker & blobs see it
+    pub drop_doc_event: f64,
+    /// chance that a blob fetch is delayed
+    pub delay_blob_fetch: f64,
+    /// upper bound for blob fetch delays
+    pub max_blob_fetch_delay: Duration,
+    /// chance that a worker is killed while running a job, never reporting a result
+    pub kill_job: f64,
+    /// upper bound for how long a job runs before its worker is killed
+    pub max_kill_after: Duration,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Chaos(Option<Arc<Inner>>);
+
+#[derive(Debug)]
+struct Inner {
+    config: ChaosConfig,
+    drop_event: Mutex<StdRng>,
+    delay_fetch: Mutex<StdRng>,
+    kill_job: Mutex<StdRng>,
+}
+
+impl Chaos {
+    #[cfg(any(test, feature = "chaos"))]
+    pub fn new(config: ChaosConfig) -> Self {
+        use rand::SeedableRng;
+
+        let stream = |n: u64| Mutex::new(StdRng::seed_from_u64(config.seed.wrapping_add(n)));
+        Chaos(Some(Arc::new(Inner {
+            drop_event: stream(0),
+            delay_fetch: stream(1),
+            kill_job: stream(2),
+            config,
+        })))
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.0.is_some()
+    }
+
+    /// Should the next doc event be dropped?
+    pub(crate) fn drop_doc_event(&self) -> bool {
+        let Some(inner) = &self.0 else {
+            return false;
+        };
+        let drop = roll(&inner.drop_event, inner.config.drop_doc_event);
+        if drop {
+            debug!("chaos: dropping doc event");
+        }
+        drop
+    }
+
+    /// Wait before a blob fetch, if this one is picked.
+    pub(crate) async fn delay_blob_fetch(&self) {
+        let Some(inner) = &self.0 else {
+            return;
+        };
+        if !roll(&inner.delay_fetch, inner.config.delay_blob_fetch) {
+            return;
+        }
+        let delay = pick(&inner.delay_fetch, inner.config.max_blob_fetch_delay);
+        debug!("chaos: delaying blob fetch by {:?}", delay);
+        tokio::time::sleep(delay).await;
+    }
+
+    /// How long the next job may run before its worker is killed, if it's picked.
+    pub(crate) fn kill_job_after(&self) -> Option<Duration> {
+        let inner = self.0.as_ref()?;
+        if !roll(&inner.kill_job, inner.config.kill_job) {
+            return None;
+        }
+        Some(pick(&inner.kill_job, inner.config.max_kill_after))
+    }
+}
+
+fn roll(rng: &Mutex<StdRng>, probability: f64) -> bool {
+    probability > 0.0 && rng.lock().unwrap().gen_bool(probability.min(1.0))
+}
+
+fn pick(rng: &Mutex<StdRng>, max: Duration) -> Duration {
+    if max.is_zero() {
+        return max;
+    }
+    rng.lock().unwrap().gen_range(Duration::ZERO..=max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(seed: u64) -> ChaosConfig {
+        ChaosConfig {
+            seed,
+            drop_doc_event: 0.5,
+            kill_job: 0.5,
+            max_kill_after: Duration::from_secs(1),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn seeds_are_deterministic() {
+        let decisions = |chaos: &Chaos| {
+            (0..32)
+                .map(|_| (chaos.drop_doc_event(), chaos.kill_job_after()))
+                .collect::<Vec<_>>()
+        };
+        let a = decisions(&Chaos::new(config(7)));
+        assert_eq!(a, decisions(&Chaos::new(config(7))));
+        assert_ne!(a, decisions(&Chaos::new(config(8))));
+        assert!(a.iter().any(|(drop, _)| *drop));
+        assert!(a.iter().any(|(_, kill)| kill.is_some()));
+
+        // hooks don't disturb each other's streams
+        let chaos = Chaos::new(config(7));
+        let drops = (0..32).map(|_| chaos.drop_doc_event()).collect::<Vec<_>>();
+        assert_eq!(drops, a.iter().map(|(drop, _)| *drop).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn disabled_by_default() {
+        let chaos = Chaos::default();
+        assert!(!chaos.is_enabled());
+        assert!((0..32).all(|_| !chaos.drop_doc_event() && chaos.kill_job_after().is_none()));
+
+        let chaos = Chaos::new(ChaosConfig::default());
+        assert!((0..32).all(|_| !chaos.drop_doc_event() && chaos.kill_job_after().is_none()));
+    }
+}
@@ -1,11 +1,26 @@
-use std::path::PathBuf;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Context, Result};
 
 use iroh::net::defaults::prod::{default_eu_relay_node, default_na_relay_node};
 use iroh::net::relay::RelayNode;
 use iroh::node::GcPolicy;
 use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+use tracing_subscriber::EnvFilter;
 
 use super::content_routing::AutofetchPolicy;
+use super::{
+    DiskQuotaConfig, EgressConfig, PickupConfig, ScannerCommand, SlotsConfig, ThumbnailConfig,
+};
+use crate::admin::AdminConfig;
+use crate::blob_store::BlobStoreConfig;
+use crate::space::clock::DEFAULT_MAX_SKEW;
+
+const CONFIG_FILENAME: &str = "config.toml";
+const DEFAULT_METRICS_PORT: u16 = 8016;
 
 /// The configuration for an iroh node.
 #[derive(PartialEq, Eq, Debug, Deserialize, Serialize, Clone)]
@@ -17,6 +32,11 @@ pub struct NodeConfig {
     pub api_port: u16,
     /// Bind address on which to serve Prometheus metrics
     pub metrics_port: Option<u16>,
+    /// Where & how `/metrics` & `/admin` are served.
+    pub admin: AdminConfig,
+    /// Bearer tokens scripts call the gateway's `/api` routes with. Each may read & write
+    /// every space as the node, so keep them secret.
+    pub api_tokens: Vec<String>,
 
     /// Port for iroh to listen on for direct connections. Defaults to 0 for random available
     /// port assignement.
@@ -31,6 +51,48 @@ pub struct NodeConfig {
 
     /// Root folder used for storing and retrieving assets shared with the worker.
     pub worker_root: PathBuf,
+
+    /// Durable storage for blobs, local unless configured otherwise.
+    pub blob_store: BlobStoreConfig,
+
+    /// Network access for docker jobs.
+    pub egress: EgressConfig,
+
+    /// Concurrent jobs on this node's worker, and whether batch jobs can be preempted.
+    pub slots: SlotsConfig,
+
+    /// Thumbnails of images & videos attached to rows.
+    pub thumbnails: ThumbnailConfig,
+
+    /// Tracing filter directives, eg: `info` or `squiggle_node=debug`. Falls back to
+    /// `RUST_LOG` when unset.
+    pub log_level: Option<String>,
+
+    /// Whether this node's worker accepts jobs.
+    pub worker_enabled: bool,
+
+    /// Labels this node's worker advertises, for jobs that require them, eg: `gpu`.
+    pub worker_labels: BTreeSet<String>,
+
+    /// When this node's worker asks for jobs: a random delay, longer the more jobs it holds,
+    /// & how many jobs it may hold before it stops asking.
+    pub worker_pickup: PickupConfig,
+
+    /// Most bytes of downloads & uploads a job may keep on this node's disk. Unlimited when
+    /// unset.
+    pub worker_job_disk_quota: Option<u64>,
+
+    /// Percentage of the disk in use, 1 - 100, past which this node's worker stops taking
+    /// jobs. Unset to take jobs until the disk is full.
+    pub worker_disk_high_watermark: Option<u8>,
+
+    /// Command this node's worker runs on every job artifact before uploading it, see
+    /// [`crate::vm::admission`]. Spaces can't set one, it runs on this machine.
+    pub admission_scanner: Option<ScannerCommand>,
+
+    /// How far ahead of this node's clock, in seconds, events from other members of a space
+    /// may be. Events further ahead are refused.
+    pub max_clock_skew_secs: u64,
 }
 
 impl Default for NodeConfig {
@@ -40,13 +102,353 @@ impl Default for NodeConfig {
         let worker_root = worker_root.into_path();
         Self {
             api_port: 8015,
-            metrics_port: Some(8016),
+            metrics_port: Some(DEFAULT_METRICS_PORT),
+            admin: AdminConfig::default(),
+            api_tokens: Vec::new(),
             iroh_port: 0,
             relay_nodes: [default_na_relay_node(), default_eu_relay_node()].into(),
             gc_policy: GcPolicy::Disabled,
             autofetch_default: AutofetchPolicy::Disabled,
             tracing_endpoint: None,
             worker_root,
+            blob_store: BlobStoreConfig::default(),
+            egress: EgressConfig::default(),
+            slots: SlotsConfig::default(),
+            thumbnails: ThumbnailConfig::default(),
+            log_level: None,
+            worker_enabled: true,
+            worker_labels: BTreeSet::new(),
+            worker_pickup: PickupConfig::default(),
+            worker_job_disk_quota: None,
+            worker_disk_high_watermark: DiskQuotaConfig::default().high_watermark,
+            admission_scanner: None,
+            max_clock_skew_secs: DEFAULT_MAX_SKEW.as_secs(),
+        }
+    }
+}
+
+impl NodeConfig {
+    /// Read `config.toml` from a repo, if there is one.
+    pub async fn load(repo_path: impl AsRef<Path>) -> Result<Option<Self>> {
+        let path = repo_path.as_ref().join(CONFIG_FILENAME);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let data = tokio::fs::read_to_string(&path).await?;
+        let config =
+            toml::from_str(&data).with_context(|| format!("reading {}", path.display()))?;
+        Ok(Some(config))
+    }
+
+    /// How much disk the worker's jobs may take.
+    pub fn worker_disk_quota(&self) -> DiskQuotaConfig {
+        DiskQuotaConfig {
+            job_quota: self.worker_job_disk_quota,
+            high_watermark: self.worker_disk_high_watermark,
+        }
+    }
+}
+
+/// The parts of [`NodeConfig`] that can change while the node runs.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RuntimeConfig {
+    pub autofetch: AutofetchPolicy,
+    pub metrics_port: Option<u16>,
+    pub log_level: Option<String>,
+    pub worker_enabled: bool,
+}
+
+impl RuntimeConfig {
+    fn from_file(config: Option<&NodeConfig>) -> Self {
+        match config {
+            Some(config) => Self {
+                autofetch: config.autofetch_default.clone(),
+                metrics_port: config.metrics_port,
+                log_level: config.log_level.clone(),
+                worker_enabled: config.worker_enabled,
+            },
+            None => Self {
+                autofetch: AutofetchPolicy::default(),
+                metrics_port: Some(DEFAULT_METRICS_PORT),
+                log_level: None,
+                worker_enabled: true,
+            },
+        }
+    }
+}
+
+/// Values set at runtime. They win over `config.toml` until cleared, and aren't written to it.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RuntimeOverrides {
+    pub autofetch: Option<AutofetchPolicy>,
+    /// `Some(None)` turns metrics off
+    pub metrics_port: Option<Option<u16>>,
+    pub log_level: Option<String>,
+    pub worker_enabled: Option<bool>,
+}
+
+impl RuntimeOverrides {
+    fn apply(&self, mut runtime: RuntimeConfig) -> RuntimeConfig {
+        if let Some(autofetch) = &self.autofetch {
+            runtime.autofetch = autofetch.clone();
+        }
+        if let Some(metrics_port) = self.metrics_port {
+            runtime.metrics_port = metrics_port;
+        }
+        if let Some(log_level) = &self.log_level {
+            runtime.log_level = Some(log_level.clone());
+        }
+        if let Some(worker_enabled) = self.worker_enabled {
+            runtime.worker_enabled = worker_enabled;
+        }
+        runtime
+    }
+}
+
+/// The outcome of [`LiveConfig::reload`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConfigReload {
+    /// values in effect after the reload
+    pub runtime: RuntimeConfig,
+    /// fields of `config.toml` that changed, but only take effect when the node restarts
+    pub requires_restart: Vec<String>,
+}
+
+#[derive(Debug)]
+struct LiveState {
+    file: Option<NodeConfig>,
+    overrides: RuntimeOverrides,
+}
+
+impl LiveState {
+    fn runtime(&self) -> RuntimeConfig {
+        self.overrides
+            .apply(RuntimeConfig::from_file(self.file.as_ref()))
+    }
+}
+
+/// A node's config while it runs. `config.toml` is read once when the node opens, and again
+/// on [`LiveConfig::reload`]. Subsystems follow [`RuntimeConfig`] changes with
+/// [`LiveConfig::subscribe`], everything else needs a restart.
+#[derive(Debug, Clone)]
+pub struct LiveConfig {
+    repo_path: PathBuf,
+    state: Arc<Mutex<LiveState>>,
+    changes: watch::Sender<RuntimeConfig>,
+}
+
+impl LiveConfig {
+    pub async fn open(repo_path: impl Into<PathBuf>) -> Result<Self> {
+        let repo_path = repo_path.into();
+        let file = NodeConfig::load(&repo_path).await?;
+        validate(file.as_ref())?;
+        let state = LiveState {
+            file,
+            overrides: RuntimeOverrides::default(),
+        };
+        let (changes, _) = watch::channel(state.runtime());
+        Ok(Self {
+            repo_path,
+            state: Arc::new(Mutex::new(state)),
+            changes,
+        })
+    }
+
+    /// `config.toml` as last read, none if the repo doesn't have one.
+    pub fn file(&self) -> Option<NodeConfig> {
+        self.state.lock().expect("poisoned").file.clone()
+    }
+
+    /// Values in effect right now, overrides included.
+    pub fn runtime(&self) -> RuntimeConfig {
+        self.changes.borrow().clone()
+    }
+
+    pub fn overrides(&self) -> RuntimeOverrides {
+        self.state.lock().expect("poisoned").overrides.clone()
+    }
+
+    /// Get notified whenever the runtime config changes.
+    pub fn subscribe(&self) -> watch::Receiver<RuntimeConfig> {
+        self.changes.subscribe()
+    }
+
+    /// Read `config.toml` again. Runtime values apply right away, unless overridden.
+    pub async fn reload(&self) -> Result<ConfigReload> {
+        let file = NodeConfig::load(&self.repo_path).await?;
+        validate(file.as_ref())?;
+        let mut state = self.state.lock().expect("poisoned");
+        let requires_restart = match (&state.file, &file) {
+            (Some(old), Some(new)) => restart_fields(old, new),
+            (None, None) => Vec::new(),
+            // added or removed, anything could've changed
+            _ => RESTART_FIELDS.to_vec(),
+        };
+        state.file = file;
+        self.publish(&state);
+        Ok(ConfigReload {
+            runtime: state.runtime(),
+            requires_restart: requires_restart.into_iter().map(String::from).collect(),
+        })
+    }
+
+    pub fn set_autofetch(&self, policy: AutofetchPolicy) {
+        self.update(|overrides| overrides.autofetch = Some(policy));
+    }
+
+    pub fn set_metrics_port(&self, port: Option<u16>) {
+        self.update(|overrides| overrides.metrics_port = Some(port));
+    }
+
+    pub fn set_log_level(&self, level: &str) -> Result<()> {
+        EnvFilter::try_new(level).with_context(|| format!("invalid log level {level}"))?;
+        self.update(|overrides| overrides.log_level = Some(level.to_string()));
+        Ok(())
+    }
+
+    pub fn set_worker_enabled(&self, enabled: bool) {
+        self.update(|overrides| overrides.worker_enabled = Some(enabled));
+    }
+
+    /// Drop every override, going back to `config.toml`.
+    pub fn clear_overrides(&self) {
+        self.update(|overrides| *overrides = RuntimeOverrides::default());
+    }
+
+    fn update(&self, f: impl FnOnce(&mut RuntimeOverrides)) {
+        let mut state = self.state.lock().expect("poisoned");
+        f(&mut state.overrides);
+        self.publish(&state);
+    }
+
+    /// Notify subscribers, if anything changed.
+    fn publish(&self, state: &LiveState) {
+        let next = state.runtime();
+        self.changes.send_if_modified(|current| {
+            if *current == next {
+                return false;
+            }
+            *current = next;
+            true
+        });
+    }
+}
+
+fn validate(config: Option<&NodeConfig>) -> Result<()> {
+    if let Some(config) = config {
+        config.admin.validate()?;
+        anyhow::ensure!(
+            config.api_tokens.iter().all(|token| !token.is_empty()),
+            "api_tokens has an empty token"
+        );
+        if let Some(watermark) = config.worker_disk_high_watermark {
+            anyhow::ensure!(
+                (1..=100).contains(&watermark),
+                "worker_disk_high_watermark must be a percentage from 1 to 100, not {watermark}"
+            );
         }
     }
+    if let Some(level) = config.and_then(|config| config.log_level.as_deref()) {
+        EnvFilter::try_new(level).with_context(|| format!("invalid log_level {level}"))?;
+    }
+    Ok(())
+}
+
+/// Fields of [`NodeConfig`] that aren't part of [`RuntimeConfig`].
+const RESTART_FIELDS: &[&str] = &[
+    "api_port",
+    "admin",
+    "api_tokens",
+    "iroh_port",
+    "relay_nodes",
+    "gc_policy",
+    "tracing_endpoint",
+    "worker_root",
+    "blob_store",
+    "egress",
+    "slots",
+    "thumbnails",
+    "max_clock_skew_secs",
+    "worker_job_disk_quota",
+    "worker_disk_high_watermark",
+    "worker_pickup",
+    "admission_scanner",
+    "worker_labels",
+];
+
+fn restart_fields(old: &NodeConfig, new: &NodeConfig) -> Vec<&'static str> {
+    let changed = [
+        old.api_port != new.api_port,
+        old.admin != new.admin,
+        old.api_tokens != new.api_tokens,
+        old.iroh_port != new.iroh_port,
+        old.relay_nodes != new.relay_nodes,
+        old.gc_policy != new.gc_policy,
+        old.tracing_endpoint != new.tracing_endpoint,
+        old.worker_root != new.worker_root,
+        old.blob_store != new.blob_store,
+        old.egress != new.egress,
+        old.slots != new.slots,
+        old.thumbnails != new.thumbnails,
+        old.max_clock_skew_secs != new.max_clock_skew_secs,
+        old.worker_job_disk_quota != new.worker_job_disk_quota,
+        old.worker_disk_high_watermark != new.worker_disk_high_watermark,
+        old.worker_pickup != new.worker_pickup,
+        old.admission_scanner != new.admission_scanner,
+        old.worker_labels != new.worker_labels,
+    ];
+    RESTART_FIELDS
+        .iter()
+        .zip(changed)
+        .filter_map(|(field, changed)| changed.then_some(*field))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overrides_win_over_file() {
+        let file = NodeConfig {
+            autofetch_default: AutofetchPolicy::All,
+            log_level: Some("info".to_string()),
+            ..Default::default()
+        };
+        let overrides = RuntimeOverrides {
+            metrics_port: Some(None),
+            worker_enabled: Some(false),
+            ..Default::default()
+        };
+        let runtime = overrides.apply(RuntimeConfig::from_file(Some(&file)));
+        assert_eq!(
+            runtime,
+            RuntimeConfig {
+                autofetch: AutofetchPolicy::All,
+                metrics_port: None,
+                log_level: Some("info".to_string()),
+                worker_enabled: false,
+            }
+        );
+    }
+
+    #[test]
+    fn detects_restart_fields() {
+        let old = NodeConfig::default();
+        let mut new = old.clone();
+        new.metrics_port = None;
+        new.log_level = Some("debug".to_string());
+        assert!(restart_fields(&old, &new).is_empty());
+
+        new.iroh_port = 1234;
+        new.slots.slots += 1;
+        new.worker_labels.insert("gpu".to_string());
+        assert_eq!(
+            restart_fields(&old, &new),
+            vec!["iroh_port", "slots", "worker_labels"]
+        );
+    }
 }
@@ -1,4 +1,5 @@
 use std::str::FromStr;
+use std::sync::{Arc, RwLock};
 
 use anyhow::{Context, Result};
 use futures::StreamExt;
@@ -9,6 +10,8 @@ use iroh::net::{AddrInfo, NodeAddr, NodeId};
 use serde::{Deserialize, Serialize};
 use tracing::trace;
 
+use crate::disk::DiskMonitor;
+use crate::peers::PeerBook;
 use crate::router::RouterClient;
 
 use super::doc::{Doc, Event, EventData, EMPTY_OK_VALUE};
@@ -36,7 +39,10 @@ pub(crate) struct ContentRouter {
     node_id: NodeId,
     doc: Doc,
     node: RouterClient,
-    autofetch: AutofetchPolicy,
+    /// shared by every clone, so a policy change reaches all of them
+    autofetch: Arc<RwLock<AutofetchPolicy>>,
+    peers: PeerBook,
+    disk: DiskMonitor,
 }
 
 impl ContentRouter {
@@ -46,16 +52,28 @@ impl ContentRouter {
         doc: Doc,
         node: RouterClient,
         autofetch: AutofetchPolicy,
+        peers: PeerBook,
+        disk: DiskMonitor,
     ) -> Self {
         Self {
             author_id,
             node_id,
             doc,
             node,
-            autofetch,
+            autofetch: Arc::new(RwLock::new(autofetch)),
+            peers,
+            disk,
         }
     }
 
+    pub(crate) fn set_autofetch(&self, policy: AutofetchPolicy) {
+        *self.autofetch.write().expect("poisoned") = policy;
+    }
+
+    fn autofetch(&self) -> AutofetchPolicy {
+        self.autofetch.read().expect("poisoned").clone()
+    }
+
     pub(crate) async fn fetch_blob(&self, hash: Hash) -> Result<()> {
         let provs = self.find_providers(hash).await?;
         if provs.contains(&self.node_id) {
@@ -68,6 +86,11 @@ impl ContentRouter {
             return Err(anyhow::anyhow!("No providers found for hash {}", hash));
         }
 
+        if self.disk.is_paused() {
+            iroh_metrics::inc!(Metrics, content_routing_fetches_paused);
+            return Err(anyhow::anyhow!("blob sync is paused: disk space is low"));
+        }
+
         trace!(
             "Found {} providers for hash {}: {:?}",
             provs.len(),
@@ -76,13 +99,19 @@ impl ContentRouter {
         );
 
         for prov in provs {
+            if self.peers.is_forgotten(&prov).await {
+                trace!("skipping forgotten provider {}", prov);
+                continue;
+            }
             match fetch_blob_from_provider(&self.node, hash, prov).await {
-                Ok(_) => {
+                Ok(size) => {
                     iroh_metrics::inc!(Metrics, content_routing_blobs_fetched);
+                    self.peers.record_fetch(prov, Some(size)).await;
                     return Ok(());
                 }
                 Err(err) => {
                     trace!("failed to fetch from provider: {:?}", err);
+                    self.peers.record_fetch(prov, None).await;
                     continue;
                 }
             }
@@ -123,7 +152,11 @@ impl ContentRouter {
     pub(crate) async fn handle_event(&self, event: Event) -> Result<()> {
         // we listen for provider addition instead of blob creation because blobs are useless
         // unless they can be fetched
-        if self.autofetch == AutofetchPolicy::All {
+        if self.autofetch() == AutofetchPolicy::All {
+            if self.disk.is_paused() {
+                iroh_metrics::inc!(Metrics, content_routing_fetches_paused);
+                return Ok(());
+            }
             if let EventData::ContentRouting(e) = event.data {
                 match e {
                     ContentRoutingEvent::ProviderAdded { hash, provider } => {
@@ -156,7 +189,11 @@ impl ContentRouter {
     }
 }
 
-async fn fetch_blob_from_provider(node: &RouterClient, hash: Hash, provider: NodeId) -> Result<()> {
+async fn fetch_blob_from_provider(
+    node: &RouterClient,
+    hash: Hash,
+    provider: NodeId,
+) -> Result<u64> {
     trace!(
         hash = %hash,
         provider = %provider,
@@ -168,7 +205,7 @@ async fn fetch_blob_from_provider(node: &RouterClient, hash: Hash, provider: Nod
     };
     let outcome = node.blobs().download(hash, addr).await?.await?;
     trace!("Downloaded blob: {:?}", outcome);
-    Ok(())
+    Ok(outcome.downloaded_size)
 }
 
 #[derive(Debug, Clone)]
@@ -187,7 +224,6 @@ pub(crate) fn parse_content_routing_event(key: &str) -> Option<EventData> {
             None
         }
     }
-    // TODO - when we support deletes, we'll need to check for null hash values
 }
 
 fn event_components(key: &str) -> Result<(Hash, NodeId)> {
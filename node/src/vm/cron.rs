@@ -0,0 +1,112 @@
+//! Fires the schedules of every space as they come due, see [`crate::space::schedules`].
+//!
+//! A task wakes up for the next schedule due, or every [`MAX_SLEEP`] to pick up schedules
+//! created or synced in the meantime, and queues a batch run of each due schedule's program.
+//! The run gets `SCHEDULE_ID` & `SCHEDULED_AT`, the time it was due in unix seconds. Runs are
+//! made as the schedule's author, nodes without that author's key leave the schedule to a node
+//! that has it. A schedule whose last run is still going skips its turn.
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::Result;
+use iroh::docs::AuthorId;
+use tokio::task::JoinHandle;
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+use crate::space::{Space, Spaces};
+
+use super::job::JobPriority;
+use super::VM;
+
+/// Longest the task sleeps between looking at schedules.
+const MAX_SLEEP: Duration = Duration::from_secs(30);
+
+/// Schedules with a run in progress, by space & schedule id.
+type Running = Arc<Mutex<HashSet<(Uuid, Uuid)>>>;
+
+/// Fire schedules as they come due, for as long as the returned task runs.
+pub(crate) fn spawn(vm: Arc<VM>, spaces: Spaces) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let running = Running::default();
+        loop {
+            let now = chrono::Utc::now().timestamp();
+            let mut wake = now + MAX_SLEEP.as_secs() as i64;
+            match spaces.list(0, -1).await {
+                Ok(listed) => {
+                    for details in listed {
+                        let Some(space) = spaces.get(&details.id).await else {
+                            continue;
+                        };
+                        match fire(&vm, &space, &running, now).await {
+                            Ok(Some(next)) => wake = wake.min(next),
+                            Ok(None) => {}
+                            Err(err) => warn!("space {} schedules: {:?}", space.id, err),
+                        }
+                    }
+                }
+                Err(err) => warn!("listing spaces for schedules: {:?}", err),
+            }
+            let sleep = (wake - chrono::Utc::now().timestamp()).max(1);
+            tokio::time::sleep(Duration::from_secs(sleep as u64)).await;
+        }
+    })
+}
+
+/// Run the schedules of a space that are due at `now`. Returns when the next one is due.
+async fn fire(vm: &Arc<VM>, space: &Space, running: &Running, now: i64) -> Result<Option<i64>> {
+    let mut next = None;
+    for schedule in space.schedules().list().await? {
+        if !schedule.is_due(now) {
+            next = next.into_iter().chain(schedule.next_run).min();
+            continue;
+        }
+        let author_id = AuthorId::from(schedule.author.as_bytes());
+        let Some(author) = space.router().authors().export(author_id).await? else {
+            debug!("no key for the author of schedule {}", schedule.id);
+            continue;
+        };
+        let key = (space.id, schedule.id);
+        let due = schedule.next_run.unwrap_or(now);
+        // move the schedule along first, so a run that fails or outlasts a turn isn't retried
+        // on every wake
+        let schedule = space
+            .schedules()
+            .record_run(author.clone(), schedule.id, now)
+            .await?;
+        next = next.into_iter().chain(schedule.next_run).min();
+        if !running.lock().expect("poisoned").insert(key) {
+            warn!(
+                "schedule {} is still running from its last turn, skipping",
+                schedule.id
+            );
+            continue;
+        }
+
+        let environment = HashMap::from([
+            ("SCHEDULE_ID".to_string(), schedule.id.to_string()),
+            ("SCHEDULED_AT".to_string(), due.to_string()),
+        ]);
+        let vm = vm.clone();
+        let space = space.clone();
+        let running = running.clone();
+        tokio::spawn(async move {
+            let res = vm
+                .run_program_as(
+                    &space,
+                    author,
+                    schedule.program_id,
+                    environment,
+                    JobPriority::Batch,
+                    false,
+                )
+                .await;
+            if let Err(err) = res {
+                warn!("running program of schedule {}: {:?}", schedule.id, err);
+            }
+            running.lock().expect("poisoned").remove(&key);
+        });
+    }
+    Ok(next)
+}
@@ -83,6 +83,25 @@ fn parse_key(key: &[u8]) -> Option<(&str, &str)> {
     Some((key, demux))
 }
 
+/// Read an entry the way handlers see it, `from` is the node it came from.
+pub(crate) fn parse_entry(entry: &Entry, from: NodeId) -> Option<Event> {
+    // empty entries are tombstones, only deleted objects mean anything
+    let deleted = entry.content_len() == 0;
+    parse_key(entry.key())
+        .and_then(|(key, demux)| match demux {
+            BLOBS_DOC_PREFIX => parse_blobs_event(key, entry),
+            _ if deleted => None,
+            JOBS_PREFIX => parse_scheduler_event(key, &from, entry),
+            WORKER_PREFIX => parse_worker_event(key, &from, entry),
+            CONTENT_ROUTING_PREFIX => parse_content_routing_event(key),
+            _ => None,
+        })
+        .map(|data| Event {
+            entry: entry.clone(),
+            data,
+        })
+}
+
 pub(crate) async fn subscribe(doc: &Doc, node_id: NodeId) -> Result<impl Stream<Item = Event>> {
     let stream = doc.subscribe().await?;
     let stream = stream.filter_map(move |event| async move {
@@ -96,19 +115,7 @@ pub(crate) async fn subscribe(doc: &Doc, node_id: NodeId) -> Result<impl Stream<
                     iroh::client::docs::LiveEvent::InsertLocal { ref entry } => (node_id, entry),
                     _ => return None,
                 };
-
-                parse_key(entry.key())
-                    .and_then(|(key, demux)| match demux {
-                        JOBS_PREFIX => parse_scheduler_event(key, &from, entry),
-                        WORKER_PREFIX => parse_worker_event(key, &from, entry),
-                        BLOBS_DOC_PREFIX => parse_blobs_event(key),
-                        CONTENT_ROUTING_PREFIX => parse_content_routing_event(key),
-                        _ => None,
-                    })
-                    .map(|data| Event {
-                        entry: entry.clone(),
-                        data,
-                    })
+                parse_entry(entry, from)
             }
             Err(err) => {
                 warn!("error: {:?}", err);
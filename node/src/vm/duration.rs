@@ -0,0 +1,145 @@
+//! Human-friendly durations for job & flow configuration.
+//!
+//! Accepts unit suffixed strings like "90s", "5m", "2h" or "1h30m", as well as the original
+//! format of plain seconds: "1.0", 90 or 1.5. Durations serialize in the original format so
+//! descriptions stay readable by older nodes.
+use std::fmt;
+
+use anyhow::{anyhow, bail, ensure, Result};
+use serde::de::{self, Visitor};
+use serde::{Deserializer, Serialize, Serializer};
+use time::Duration;
+
+/// Parse a duration, see the module docs for accepted formats.
+pub fn parse(s: &str) -> Result<Duration> {
+    let s = s.trim();
+    ensure!(!s.is_empty(), "empty duration");
+    if let Ok(seconds) = s.parse::<f64>() {
+        return from_secs_f64(seconds);
+    }
+
+    let mut total = Duration::ZERO;
+    let mut rest = s;
+    while !rest.is_empty() {
+        let number_len = rest
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(rest.len());
+        let (number, tail) = rest.split_at(number_len);
+        let unit_len = tail
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(tail.len());
+        let (unit, tail) = tail.split_at(unit_len);
+
+        let value: f64 = number
+            .parse()
+            .map_err(|_| anyhow!("invalid duration {s:?}: expected a number before {unit:?}"))?;
+        let scale = match unit {
+            "ms" => 0.001,
+            "s" | "sec" | "secs" => 1.0,
+            "m" | "min" | "mins" => 60.0,
+            "h" | "hr" | "hrs" => 60.0 * 60.0,
+            "d" | "day" | "days" => 24.0 * 60.0 * 60.0,
+            "" => bail!("invalid duration {s:?}: missing unit after {number}, eg: \"{number}s\""),
+            _ => bail!("invalid duration {s:?}: unknown unit {unit:?}, use ms, s, m, h or d"),
+        };
+        total += from_secs_f64(value * scale)?;
+        rest = tail.trim_start();
+    }
+    Ok(total)
+}
+
+fn from_secs_f64(seconds: f64) -> Result<Duration> {
+    let duration = std::time::Duration::try_from_secs_f64(seconds)
+        .map_err(|_| anyhow!("invalid duration: {seconds} seconds"))?;
+    Ok(Duration::try_from(duration)?)
+}
+
+pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    duration.serialize(serializer)
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    deserializer.deserialize_any(DurationVisitor)
+}
+
+struct DurationVisitor;
+
+impl<'de> Visitor<'de> for DurationVisitor {
+    type Value = Duration;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a duration like \"90s\", \"5m\" or \"2h\", or a number of seconds")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Duration, E> {
+        parse(v).map_err(E::custom)
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<Duration, E> {
+        Ok(Duration::seconds(v.min(i64::MAX as u64) as i64))
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<Duration, E> {
+        if v < 0 {
+            return Err(E::custom(format!("invalid duration: {v} seconds")));
+        }
+        Ok(Duration::seconds(v))
+    }
+
+    fn visit_f64<E: de::Error>(self, v: f64) -> Result<Duration, E> {
+        from_secs_f64(v).map_err(E::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_durations() -> Result<()> {
+        assert_eq!(parse("1.0")?, Duration::SECOND);
+        assert_eq!(parse("90")?, Duration::seconds(90));
+        assert_eq!(parse("90s")?, Duration::seconds(90));
+        assert_eq!(parse("5m")?, Duration::minutes(5));
+        assert_eq!(parse("2h")?, Duration::hours(2));
+        assert_eq!(parse("1h30m")?, Duration::minutes(90));
+        assert_eq!(parse("1h 30m")?, Duration::minutes(90));
+        assert_eq!(parse("1.5h")?, Duration::minutes(90));
+        assert_eq!(parse("250ms")?, Duration::milliseconds(250));
+
+        assert!(parse("").is_err());
+        assert!(parse("5").is_ok());
+        assert!(parse("5 parsecs").is_err());
+        assert!(parse("1h5").is_err());
+        assert!(parse("-1.0").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn deserialize_formats() -> Result<()> {
+        #[derive(serde::Deserialize, serde::Serialize)]
+        struct Config {
+            #[serde(with = "super")]
+            timeout: Duration,
+        }
+
+        for (src, expected) in [
+            ("timeout = \"1.0\"", Duration::SECOND),
+            ("timeout = \"2m\"", Duration::minutes(2)),
+            ("timeout = 30", Duration::seconds(30)),
+            ("timeout = 0.5", Duration::milliseconds(500)),
+        ] {
+            let config: Config = toml::from_str(src)?;
+            assert_eq!(config.timeout, expected, "{src}");
+        }
+
+        // serialized in the original seconds format
+        let json = serde_json::to_string(&Config {
+            timeout: Duration::minutes(2),
+        })?;
+        assert_eq!(json, r#"{"timeout":"120.000000000"}"#);
+        let config: Config = serde_json::from_str(&json)?;
+        assert_eq!(config.timeout, Duration::minutes(2));
+        Ok(())
+    }
+}
@@ -1,27 +1,44 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
-use anyhow::{ensure, Result};
+use anyhow::{anyhow, ensure, Result};
 use bytes::Bytes;
 use futures::future::BoxFuture;
 use futures::FutureExt;
 use iroh::blobs::util::SetTagOption;
 use serde::{Deserialize, Serialize};
 use tokio::io::BufReader;
+use tokio::sync::broadcast::error::RecvError;
 use tokio::task::JoinSet;
 use tracing::{debug, info, instrument, warn};
 use uuid::Uuid;
 
 use super::blobs::Blobs;
-use super::job::{JobDescription, JobNameContext, JobResult, JobResultStatus};
+use super::job::{
+    upload_template, ArtifactRef, JobDescription, JobNameContext, JobResult, JobResultStatus,
+};
+use super::lint::{self, Diagnostic};
 use super::metrics::Metrics;
 use super::scheduler::Scheduler;
 use super::VM;
 
+/// Object writes arrive as doc events, which can be dropped or arrive late. Jobs waiting on
+/// inputs look them up again this often regardless.
+const DEPENDENCY_RECHECK: Duration = Duration::from_secs(5);
+/// Start of artifact names under a flow run's scope.
+const SCOPED_NAME_PREFIX: &str = "{scope}/";
+/// Objects listed per page when collecting a job's uploaded artifacts.
+const ARTIFACTS_PAGE_SIZE: usize = 100;
+
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
 pub struct Flow {
     pub name: String,
+    /// How many top-level tasks run at once, all of them when unset. Tasks start in order as
+    /// earlier ones finish, `1` runs them one after another.
+    #[serde(default)]
+    pub max_concurrency: Option<usize>,
     pub tasks: Vec<Task>,
     /// Uploads that are added into the system from the scheduler.
     #[serde(default)]
@@ -73,18 +90,77 @@ pub struct FlowOutput {
     pub downloads: Vec<Download>,
 }
 
+/// How far a running flow got, see [`VM::prepare_restart`]. Flows run in the node process,
+/// so a restart ends them, the checkpoint says which tasks need running again.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+#[serde(rename_all = "camelCase")]
+pub struct FlowCheckpoint {
+    pub name: String,
+    /// the scope of the run
+    pub id: Uuid,
+    /// unix timestamp, in seconds
+    pub started_at: i64,
+    /// output of every finished top-level task & its subtasks
+    pub tasks: Vec<TaskOutput>,
+    /// names of top-level tasks that haven't finished
+    pub pending: Vec<String>,
+}
+
+/// Checkpoints of the flows running in a [`VM`], by scope.
+pub(crate) type FlowRuns = Arc<Mutex<HashMap<Uuid, FlowCheckpoint>>>;
+
+/// Removes a flow's checkpoint once it stops running, however it stops.
+struct Running(FlowRuns, Uuid);
+
+impl Running {
+    fn update(&self, f: impl FnOnce(&mut FlowCheckpoint)) {
+        if let Some(checkpoint) = self.0.lock().expect("poisoned").get_mut(&self.1) {
+            f(checkpoint);
+        }
+    }
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        self.0.lock().expect("poisoned").remove(&self.1);
+    }
+}
+
 impl Flow {
     pub async fn load(path: impl AsRef<std::path::Path>) -> Result<Self> {
         let flow = tokio::fs::read_to_string(path).await?;
-        let flow = toml::from_str(&flow)?;
+        let flow = toml::from_str(&flow)
+            .map_err(|e| anyhow!("invalid flow: {}", Diagnostic::from_toml_error(&flow, &e)))?;
         Ok(flow)
     }
 
+    /// Check flow TOML for problems without running it. An empty list means the flow is ready
+    /// to submit.
+    pub fn lint(src: &str) -> Vec<Diagnostic> {
+        lint::lint(src)
+    }
+
     #[instrument(skip_all, fields(flow_name = %self.name))]
     pub async fn run(self, vm: &VM) -> Result<FlowOutput> {
         iroh_metrics::inc!(Metrics, flow_run_started);
         let scope = Uuid::new_v4();
         let router = vm.router.clone();
+        vm.flows.lock().expect("poisoned").insert(
+            scope,
+            FlowCheckpoint {
+                name: self.name.clone(),
+                id: scope,
+                started_at: chrono::Utc::now().timestamp(),
+                tasks: Vec::new(),
+                pending: self
+                    .tasks
+                    .iter()
+                    .map(|task| task.description.name.clone())
+                    .collect(),
+            },
+        );
+        let running = Running(vm.flows.clone(), scope);
 
         // Upload inputs
         for upload in &self.uploads {
@@ -114,14 +190,47 @@ impl Flow {
             vm.blobs().put_object(&name, res.hash, res.size).await?;
         }
 
-        let mut out = Vec::new();
-        for task in self.tasks.into_iter() {
-            let job_id = Uuid::new_v4();
-            let i = task
-                .run(scope, vm.scheduler().clone(), vm.blobs().clone(), job_id)
-                .await;
-            out.extend(i);
+        // top-level tasks only download from earlier ones, see `graph`, so starting them in
+        // order never leaves a running task waiting on one that can't start
+        let limit = self.max_concurrency.unwrap_or(usize::MAX);
+        let mut outputs: Vec<Vec<TaskOutput>> = vec![Vec::new(); self.tasks.len()];
+        let mut meta = HashMap::new();
+        let mut set = JoinSet::new();
+        let mut tasks = self.tasks.into_iter().enumerate();
+        loop {
+            while set.len() < limit {
+                let Some((i, task)) = tasks.next() else {
+                    break;
+                };
+                let job_id = Uuid::new_v4();
+                let name = task.description.name.clone();
+                let handle =
+                    set.spawn(task.run(scope, vm.scheduler().clone(), vm.blobs().clone(), job_id));
+                meta.insert(handle.id(), (i, name, job_id));
+            }
+            let Some(res) = set.join_next_with_id().await else {
+                break;
+            };
+            let (i, name, task_outputs) = match res {
+                Ok((id, task_outputs)) => {
+                    let (i, name, _) = meta.remove(&id).expect("invalid state");
+                    (i, name, task_outputs)
+                }
+                Err(err) => {
+                    let (i, name, job_id) = meta.remove(&err.id()).expect("invalid state");
+                    let status = JobResultStatus::Err(err.to_string());
+                    let failed = TaskOutput::failed(name.clone(), job_id, status);
+                    (i, name, vec![failed])
+                }
+            };
+            running.update(|checkpoint| {
+                checkpoint.tasks.extend(task_outputs.iter().cloned());
+                checkpoint.pending.retain(|pending| *pending != name);
+            });
+            outputs[i] = task_outputs;
         }
+        // in the order the flow lists its tasks, however they finished
+        let out: Vec<TaskOutput> = outputs.into_iter().flatten().collect();
 
         iroh_metrics::inc!(Metrics, flow_run_completed);
 
@@ -148,6 +257,20 @@ impl Flow {
         })
     }
 
+    /// Find a task by job name, at any depth.
+    pub fn task(&self, name: &str) -> Option<&Task> {
+        let mut task_list = vec![&self.tasks[..]];
+        while let Some(tasks) = task_list.pop() {
+            for task in tasks {
+                if task.description.name == name {
+                    return Some(task);
+                }
+                task_list.push(&task.tasks);
+            }
+        }
+        None
+    }
+
     /// Check that invariants are upheld
     pub fn validate(&self) -> Result<()> {
         let mut job_names = HashSet::new();
@@ -170,10 +293,188 @@ impl Flow {
             }
         }
 
+        ensure!(
+            self.max_concurrency != Some(0),
+            "max_concurrency must be at least 1"
+        );
+
+        // jobs must not wait on inputs that never arrive
+        self.graph()?;
+
+        Ok(())
+    }
+
+    /// Which jobs wait on which for their inputs. A download named `{scope}/<job>/...` comes
+    /// from `<job>`, `{scope}/<name>` from the flow's uploads, and anything outside the scope
+    /// is expected to exist already. Fails on inputs nothing in the flow produces, inputs from
+    /// a top-level task that only runs later & dependency cycles, all of which would leave the
+    /// flow waiting forever.
+    pub fn graph(&self) -> Result<FlowGraph> {
+        // every job with the index of the top-level task it runs under
+        let mut jobs = Vec::new();
+        let mut task_list: Vec<(usize, &Task)> = self.tasks.iter().enumerate().collect();
+        while let Some((group, task)) = task_list.pop() {
+            jobs.push((group, task));
+            task_list.extend(task.tasks.iter().map(|task| (group, task)));
+        }
+        let groups: HashMap<&str, usize> = jobs
+            .iter()
+            .map(|(group, task)| (task.description.name.as_str(), *group))
+            .collect();
+        let uploads: HashSet<&str> = self.uploads.iter().map(|u| u.name.as_str()).collect();
+
+        let mut graph = FlowGraph::default();
+        for (group, task) in jobs {
+            let name = &task.description.name;
+            let waits_on = graph.edges.entry(name.clone()).or_default();
+            for artifact in task.description.artifacts.downloads.iter() {
+                let Some(object) = artifact.name.strip_prefix(SCOPED_NAME_PREFIX) else {
+                    continue;
+                };
+                if uploads.contains(object) {
+                    continue;
+                }
+                let producer = object.split('/').next().unwrap_or_default();
+                let Some(producer_group) = groups.get(producer) else {
+                    anyhow::bail!(
+                        "job {name} downloads {}, which nothing in the flow uploads",
+                        artifact.name
+                    );
+                };
+                ensure!(
+                    producer != name.as_str(),
+                    "job {name} downloads its own upload {}",
+                    artifact.name
+                );
+                ensure!(
+                    *producer_group <= group,
+                    "job {name} downloads {} from {producer}, which runs after it",
+                    artifact.name
+                );
+                waits_on.insert(producer.to_string());
+            }
+        }
+        graph.check_acyclic()?;
+        Ok(graph)
+    }
+}
+
+/// The jobs of a flow & the jobs each waits on for inputs, see [`Flow::graph`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct FlowGraph {
+    /// job name to the names of jobs it downloads artifacts from
+    pub edges: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl FlowGraph {
+    fn check_acyclic(&self) -> Result<()> {
+        #[derive(Clone, Copy, PartialEq)]
+        enum Mark {
+            Visiting,
+            Done,
+        }
+        fn visit<'a>(
+            graph: &'a FlowGraph,
+            job: &'a str,
+            marks: &mut HashMap<&'a str, Mark>,
+            path: &mut Vec<&'a str>,
+        ) -> Result<()> {
+            match marks.get(job) {
+                Some(Mark::Done) => return Ok(()),
+                Some(Mark::Visiting) => {
+                    let start = path.iter().position(|p| *p == job).unwrap_or_default();
+                    let mut cycle = path[start..].to_vec();
+                    cycle.push(job);
+                    anyhow::bail!("dependency cycle: {}", cycle.join(" -> "));
+                }
+                None => {}
+            }
+            marks.insert(job, Mark::Visiting);
+            path.push(job);
+            for dep in graph.edges.get(job).into_iter().flatten() {
+                visit(graph, dep, marks, path)?;
+            }
+            path.pop();
+            marks.insert(job, Mark::Done);
+            Ok(())
+        }
+
+        let mut marks = HashMap::new();
+        for job in self.edges.keys() {
+            visit(self, job, &mut marks, &mut Vec::new())?;
+        }
         Ok(())
     }
 }
 
+/// Wait until every object in `deps` exists. Object writes in the doc wake the wait, with a
+/// lookup every [`DEPENDENCY_RECHECK`] for writes whose event went missing.
+async fn wait_for_inputs(blobs: &Blobs, mut deps: HashSet<String>) -> Result<()> {
+    // subscribe before looking, so an object written in between isn't missed
+    let mut puts = blobs.subscribe_objects();
+    let mut lookup = true;
+    loop {
+        if lookup {
+            let mut missing = HashSet::new();
+            for dep in deps {
+                match blobs.has_object(&dep).await? {
+                    true => info!("found dependency: {}", dep),
+                    false => {
+                        info!("waiting for dependency: {}", dep);
+                        missing.insert(dep);
+                    }
+                }
+            }
+            deps = missing;
+        }
+        if deps.is_empty() {
+            return Ok(());
+        }
+        lookup = tokio::select! {
+            put = puts.recv() => match put {
+                Ok(name) => {
+                    if deps.remove(&name) {
+                        info!("found dependency: {}", name);
+                    }
+                    false
+                }
+                Err(RecvError::Lagged(_)) => true,
+                Err(RecvError::Closed) => {
+                    tokio::time::sleep(DEPENDENCY_RECHECK).await;
+                    true
+                }
+            },
+            _ = tokio::time::sleep(DEPENDENCY_RECHECK) => true,
+        };
+    }
+}
+
+/// Objects `job_name` uploaded under `scope`, for results of workers that didn't record
+/// them. Sizes are as stored, compressed or not.
+async fn uploaded_artifacts(
+    blobs: &Blobs,
+    scope: Uuid,
+    job_name: &str,
+) -> Result<Vec<ArtifactRef>> {
+    let prefix = JobNameContext { scope }.render(&upload_template(job_name, ""))?;
+    let mut artifacts = Vec::new();
+    let mut cursor = None;
+    loop {
+        let page = blobs
+            .list_objects(&prefix, cursor.as_deref(), ARTIFACTS_PAGE_SIZE)
+            .await?;
+        artifacts.extend(page.objects.into_iter().map(|object| ArtifactRef {
+            name: object.name,
+            hash: object.hash,
+            size: object.size,
+        }));
+        match page.cursor {
+            Some(next) => cursor = Some(next),
+            None => return Ok(artifacts),
+        }
+    }
+}
+
 impl FlowOutput {
     /// Helper function to generate the name of an artifact.
     pub fn artifact_name(&self, job_name: &str, artifact_name: &str) -> String {
@@ -204,7 +505,8 @@ impl std::str::FromStr for Flow {
     type Err = anyhow::Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let parsed: Flow = toml::from_str(s)?;
+        let parsed: Flow = toml::from_str(s)
+            .map_err(|e| anyhow!("invalid flow: {}", Diagnostic::from_toml_error(s, &e)))?;
         parsed.validate()?;
         Ok(parsed)
     }
@@ -217,12 +519,46 @@ pub struct Task {
     pub description: JobDescription,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
 pub struct TaskOutput {
     pub name: String,
     /// The assigned id of this job
     pub id: Uuid,
+    /// Includes the worker that ran the job, if one picked it up
     pub result: JobResult,
+    /// Unix milliseconds when the job's inputs were available & it was handed to the
+    /// scheduler, unset if it never got that far
+    #[serde(default)]
+    pub started_at: Option<i64>,
+    /// Unix milliseconds when the task finished, successfully or not
+    #[serde(default)]
+    pub finished_at: Option<i64>,
+    /// Milliseconds the job waited for a worker to pick it up
+    #[serde(default)]
+    pub queue_ms: Option<u64>,
+    /// Times the job was started over on its worker
+    #[serde(default)]
+    pub retries: u32,
+    /// Artifacts the job uploaded, so they can be listed without knowing how they're named
+    #[serde(default)]
+    pub artifacts: Vec<ArtifactRef>,
+}
+
+impl TaskOutput {
+    fn failed(name: String, id: Uuid, status: JobResultStatus) -> Self {
+        TaskOutput {
+            name,
+            id,
+            result: JobResult {
+                worker: None,
+                status,
+                usage: None,
+            },
+            finished_at: Some(chrono::Utc::now().timestamp_millis()),
+            ..Default::default()
+        }
+    }
 }
 
 impl Task {
@@ -252,86 +588,75 @@ impl Task {
         let job_name = description.name.clone();
 
         let sched = scheduler.clone();
+        let b2 = blobs.clone();
         let execute_job = async move {
             // Wait for dependencies to be available
             let job_name_ctx = JobNameContext { scope };
-            let mut deps: HashSet<String> = description
+            let deps: HashSet<String> = description
                 .dependencies(job_name_ctx)
                 .collect::<Result<_>>()?;
-            let job_name = description.name.clone();
-
-            loop {
-                // TODO: avoid polling
-                let mut found_deps = Vec::new();
-                for dep in &deps {
-                    info!("looking for dependency: {}", dep);
-                    if blobs.has_object(dep).await? {
-                        found_deps.push(dep.clone());
-                        info!("found dependency: {}", dep);
-                    }
-                }
-
-                for dep in found_deps {
-                    deps.remove(&dep);
-                }
-                if deps.is_empty() {
-                    break;
-                }
-                tokio::time::sleep(Duration::from_millis(100)).await;
-            }
+            wait_for_inputs(&blobs, deps).await?;
 
             // run principle job
             let timeout = description.timeout.try_into()?;
-
-            let res = tokio::time::timeout(timeout, async {
-                let result = sched.run_job_and_wait(scope, job_id, description).await;
-
-                let result = result?;
-                anyhow::Ok(TaskOutput {
-                    name: job_name,
-                    id: job_id,
-                    result,
-                })
-            })
-            .await;
-            anyhow::Ok(res)
+            let started_at = chrono::Utc::now().timestamp_millis();
+            let res =
+                tokio::time::timeout(timeout, sched.run_job_timed(scope, job_id, description))
+                    .await;
+            anyhow::Ok((started_at, res))
         };
 
         let sched = scheduler.clone();
         let handle = set.spawn(async move {
-            let out = match execute_job.await {
-                Ok(Ok(Ok(job))) => job,
-                Ok(Ok(Err(err))) => TaskOutput {
-                    name: job_name,
-                    id: job_id,
-                    result: JobResult {
-                        worker: None,
-                        status: JobResultStatus::Err(err.to_string()),
-                    },
-                },
-                Ok(Err(_)) => {
+            let (started_at, res) = match execute_job.await {
+                Ok(started) => started,
+                Err(err) => {
+                    let status = JobResultStatus::Err(err.to_string());
+                    return vec![TaskOutput::failed(job_name, job_id, status)];
+                }
+            };
+            let mut out = match res {
+                Ok(Ok(run)) => {
+                    let mut out = TaskOutput {
+                        name: job_name.clone(),
+                        id: job_id,
+                        queue_ms: run.queued.map(|queued| queued.as_millis() as u64),
+                        retries: run
+                            .result
+                            .usage
+                            .as_ref()
+                            .map_or(0, |usage| usage.preemptions),
+                        ..Default::default()
+                    };
+                    let recorded = run
+                        .result
+                        .usage
+                        .as_ref()
+                        .map_or(&[][..], |usage| &usage.artifacts[..]);
+                    if !recorded.is_empty() {
+                        out.artifacts = recorded.to_vec();
+                    } else if matches!(run.result.status, JobResultStatus::Ok(_)) {
+                        match uploaded_artifacts(&b2, scope, &job_name).await {
+                            Ok(artifacts) => out.artifacts = artifacts,
+                            Err(err) => warn!("listing artifacts of {}: {:?}", job_name, err),
+                        }
+                    }
+                    out.result = run.result;
+                    out.finished_at = Some(chrono::Utc::now().timestamp_millis());
+                    out
+                }
+                Ok(Err(err)) => {
+                    TaskOutput::failed(job_name, job_id, JobResultStatus::Err(err.to_string()))
+                }
+                Err(_) => {
                     if let Err(err) = sched.cancel_job(job_id).await {
                         warn!("failed to cancel job: {:?}", err);
                     }
                     // timeout
-                    TaskOutput {
-                        name: job_name,
-                        id: job_id,
-                        result: JobResult {
-                            worker: None,
-                            status: JobResultStatus::ErrTimeout,
-                        },
-                    }
+                    TaskOutput::failed(job_name, job_id, JobResultStatus::ErrTimeout)
                 }
-                Err(err) => TaskOutput {
-                    name: job_name,
-                    id: job_id,
-                    result: JobResult {
-                        worker: None,
-                        status: JobResultStatus::Err(err.to_string()),
-                    },
-                },
             };
+            out.started_at = Some(started_at);
             vec![out]
         });
         meta.insert(handle.id(), (self.description.name.clone(), job_id));
@@ -346,14 +671,8 @@ impl Task {
                     Err(err) => {
                         let id = err.id();
                         let (job_name, job_id) = meta.remove(&id).expect("invalid state");
-                        task_ids.push(TaskOutput {
-                            name: job_name,
-                            id: job_id,
-                            result: JobResult {
-                                worker: None,
-                                status: JobResultStatus::Err(err.to_string()),
-                            },
-                        })
+                        let status = JobResultStatus::Err(err.to_string());
+                        task_ids.push(TaskOutput::failed(job_name, job_id, status))
                     }
                 }
             }
@@ -401,6 +720,7 @@ mod tests {
         let f = Flow {
             name: "test".into(),
             downloads: Vec::new(),
+            max_concurrency: Some(2),
             uploads: vec![Upload {
                 name: "foo".into(),
                 source: UploadSource::File {
@@ -424,6 +744,7 @@ mod tests {
                         details: JobDetails::Docker {
                             image: "docker-image".into(),
                             command: vec!["ls".into()],
+                            network: Default::default(),
                         },
                         artifacts: Default::default(),
                         timeout: DEFAULT_TIMEOUT,
@@ -438,6 +759,86 @@ mod tests {
         assert_eq!(f, f2);
     }
 
+    #[test]
+    fn test_flow_parse_timeouts() {
+        let flow = r#"
+            name = "flow1"
+
+            [[tasks]]
+            [tasks.description]
+            space = "personal"
+            program_id = "67e55044-10b1-426f-9247-bb680e5fe0c8"
+            name = "job1"
+            author = "me"
+            environment = {}
+            timeout = "1h30m"
+            [tasks.description.details.docker]
+            image = "alpine:3"
+            command = ["sleep", "1"]
+
+            [[tasks]]
+            [tasks.description]
+            space = "personal"
+            program_id = "67e55044-10b1-426f-9247-bb680e5fe0c8"
+            name = "job2"
+            author = "me"
+            environment = {}
+            timeout = "5.0"
+            [tasks.description.details.docker]
+            image = "alpine:3"
+            command = ["sleep", "1"]
+        "#;
+
+        let flow: Flow = flow.parse().unwrap();
+        assert_eq!(
+            flow.tasks[0].description.timeout,
+            time::Duration::minutes(90)
+        );
+        assert_eq!(
+            flow.tasks[1].description.timeout,
+            time::Duration::seconds(5)
+        );
+
+        let reparsed: Flow = flow.to_string().parse().unwrap();
+        assert_eq!(flow, reparsed);
+    }
+
+    #[test]
+    fn finds_tasks_at_any_depth() {
+        let flow: Flow = r#"
+            name = "flow1"
+
+            [[tasks]]
+            [tasks.description]
+            space = "personal"
+            program_id = "67e55044-10b1-426f-9247-bb680e5fe0c8"
+            name = "export"
+            author = "me"
+            environment = {}
+            [tasks.description.details.docker]
+            image = "alpine:3"
+            command = ["true"]
+
+            [[tasks.tasks]]
+            [tasks.tasks.description]
+            space = "personal"
+            program_id = "67e55044-10b1-426f-9247-bb680e5fe0c8"
+            name = "report"
+            author = "me"
+            environment = {}
+            [tasks.tasks.description.details.docker]
+            image = "alpine:3"
+            command = ["true"]
+        "#
+        .parse()
+        .unwrap();
+
+        assert_eq!(flow.task("export").unwrap().tasks.len(), 1);
+        let nested = flow.task("report").expect("nested task");
+        assert!(nested.tasks.is_empty());
+        assert!(flow.task("missing").is_none());
+    }
+
     #[ignore]
     #[tokio::test]
     async fn test_flow_docker_timeout() -> Result<()> {
@@ -935,6 +1336,18 @@ mod tests {
                 .get_artifact(ws, &task.name, "out-final.txt")
                 .await?;
             assert_eq!(blob_back, "good job 1\ngood job 2\n");
+
+            let name = flow_res.artifact_name(&task.name, "out-final.txt");
+            let artifact = task
+                .artifacts
+                .iter()
+                .find(|artifact| artifact.name == name)
+                .expect("recorded artifact");
+            assert_eq!(artifact.size, blob_back.len() as u64);
+            assert_eq!(
+                ws.blobs().get_object_info(&name).await?.content_hash(),
+                artifact.hash
+            );
         }
 
         Ok(())
@@ -946,6 +1359,7 @@ mod tests {
             name: "flow".into(),
             uploads: Vec::new(),
             downloads: Vec::new(),
+            max_concurrency: None,
             tasks: vec![
                 Task {
                     description: JobDescription {
@@ -985,6 +1399,95 @@ mod tests {
         assert!(err.to_string().contains("duplicate-1-job"));
     }
 
+    fn graph_flow(tasks: &[(&str, &[&str])], nested: bool) -> Result<Flow> {
+        let tasks = tasks.iter().map(|(name, downloads)| {
+            let downloads = downloads
+                .iter()
+                .map(|d| format!("{{ name = \"{d}\", path = \"in\" }}"))
+                .collect::<Vec<_>>()
+                .join(", ");
+            format!(
+                r#"
+[tasks.description]
+space = "personal"
+program_id = "67e55044-10b1-426f-9247-bb680e5fe0c8"
+name = "{name}"
+author = "me"
+environment = {{}}
+artifacts = {{ downloads = [{downloads}], uploads = [{{ name = "out", path = "out" }}] }}
+[tasks.description.details.wasm]
+module = {{ LocalPath = "main.wasm" }}
+"#
+            )
+        });
+        // nested tasks run alongside the first one
+        let header = if nested {
+            "\n[[tasks.tasks]]"
+        } else {
+            "\n[[tasks]]"
+        };
+        let src = tasks
+            .enumerate()
+            .fold("name = \"graph\"\n".to_string(), |src, (i, task)| {
+                let header = if i == 0 { "\n[[tasks]]" } else { header };
+                let task = if i == 0 || !nested {
+                    task
+                } else {
+                    task.replace("[tasks.description", "[tasks.tasks.description")
+                };
+                src + header + &task
+            });
+        src.parse()
+    }
+
+    #[test]
+    fn test_flow_max_concurrency() {
+        let mut flow = graph_flow(&[("a", &[]), ("b", &["{scope}/a/out"])], false).unwrap();
+        assert_eq!(flow.max_concurrency, None);
+        flow.max_concurrency = Some(0);
+        let err = flow.validate().unwrap_err();
+        assert!(err.to_string().contains("max_concurrency"));
+
+        let src = flow
+            .to_string()
+            .replace("max_concurrency = 0", "max_concurrency = 4");
+        let flow: Flow = src.parse().unwrap();
+        assert_eq!(flow.max_concurrency, Some(4));
+    }
+
+    #[test]
+    fn test_flow_graph() {
+        let flow = graph_flow(
+            &[
+                ("a", &[]),
+                ("b", &["{scope}/a/out"]),
+                ("c", &["{scope}/a/out", "{scope}/b/out", "elsewhere"]),
+            ],
+            false,
+        )
+        .unwrap();
+        let graph = flow.graph().unwrap();
+        assert!(graph.edges["a"].is_empty());
+        assert_eq!(
+            graph.edges["c"],
+            BTreeSet::from(["a".to_string(), "b".to_string()])
+        );
+
+        let err = graph_flow(&[("a", &["{scope}/b/out"]), ("b", &[])], false).unwrap_err();
+        assert!(err.to_string().contains("runs after it"), "{err}");
+        let err = graph_flow(&[("a", &["{scope}/missing"])], false).unwrap_err();
+        assert!(
+            err.to_string().contains("nothing in the flow uploads"),
+            "{err}"
+        );
+        let err = graph_flow(
+            &[("a", &["{scope}/b/out"]), ("b", &["{scope}/a/out"])],
+            true,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("dependency cycle"), "{err}");
+    }
+
     #[test]
     fn test_flow_dependencies() {
         let task = Task {
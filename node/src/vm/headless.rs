@@ -0,0 +1,197 @@
+//! A worker on its own, for machines that only run jobs. Joins a workspace doc & executes
+//! docker jobs assigned to it, without spaces, a scheduler or any sqlite databases. Wasm jobs
+//! work against their space, so headless workers never take them.
+
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+
+use anyhow::Result;
+use futures::StreamExt;
+use iroh::docs::DocTicket;
+use tokio::task::JoinHandle;
+use tracing::{debug, info_span, warn, Instrument};
+
+use crate::blob_store::BlobStore;
+use crate::disk::DiskMonitor;
+use crate::peers::PeerBook;
+use crate::router::RouterClient;
+use crate::vm::blobs::Blobs;
+use crate::vm::chaos::Chaos;
+use crate::vm::content_routing::AutofetchPolicy;
+use crate::vm::doc::{join_doc, subscribe, Doc, DocEventHandler};
+use crate::vm::node_author_id;
+use crate::vm::worker::Worker;
+use crate::vm::{DiskQuotaConfig, EgressConfig, PickupConfig, ScannerCommand, SlotsConfig};
+
+pub struct WorkerConfig {
+    pub autofetch: AutofetchPolicy,
+    pub worker_root: PathBuf,
+    pub peers: PeerBook,
+    pub disk: DiskMonitor,
+    pub blob_store: BlobStore,
+    /// how docker jobs reach the network
+    pub egress: EgressConfig,
+    /// how many jobs the worker runs at once
+    pub slots: SlotsConfig,
+    /// when the worker asks for jobs
+    pub pickup: PickupConfig,
+    /// how much disk the worker's jobs may take
+    pub disk_quota: DiskQuotaConfig,
+    /// advertised by the worker, for jobs that require them
+    pub labels: BTreeSet<String>,
+    /// run by the worker on every job artifact before it's uploaded
+    pub admission_scanner: Option<ScannerCommand>,
+    /// failure injection, disabled unless built for tests or with the `chaos` feature
+    pub chaos: Chaos,
+}
+
+#[derive(Debug)]
+pub struct HeadlessWorker {
+    doc: Doc,
+    blobs: Blobs,
+    worker: Worker,
+    /// Tracks the subscription task, canceling it when the worker gets dropped.
+    _doc_subscription_handle: JoinHandle<()>,
+}
+
+impl HeadlessWorker {
+    pub async fn join(router: &RouterClient, ticket: DocTicket, cfg: WorkerConfig) -> Result<Self> {
+        debug!("joining {} as a headless worker", ticket);
+        let doc = join_doc(router, ticket).await?;
+        let node_id = router.net().node_id().await?;
+        let blobs = Blobs::new(
+            node_id,
+            doc.clone(),
+            router.clone(),
+            cfg.autofetch,
+            cfg.peers,
+            cfg.disk,
+            cfg.blob_store,
+            cfg.chaos.clone(),
+        );
+        let author_id = node_author_id(&node_id);
+        let worker = Worker::new(
+            None,
+            router.clone(),
+            author_id,
+            doc.clone(),
+            blobs.clone(),
+            &cfg.worker_root,
+            &cfg.egress,
+            &cfg.slots,
+            cfg.pickup,
+            cfg.disk_quota,
+            cfg.labels,
+            cfg.admission_scanner,
+            cfg.chaos.clone(),
+        )
+        .await?;
+        worker.advertise().await?;
+
+        let events = subscribe(&doc, node_id).await?;
+        let worker2 = worker.clone();
+        let blobs2 = blobs.clone();
+        let chaos = cfg.chaos;
+
+        let handle = tokio::task::spawn(
+            async move {
+                let mut events = std::pin::pin!(events);
+                while let Some(event) = events.next().await {
+                    if chaos.drop_doc_event() {
+                        continue;
+                    }
+                    if let Err(err) = worker2.handle_event(event.clone()).await {
+                        warn!("worker failed to handle event: {:?}", err);
+                    }
+                    if let Err(err) = blobs2.handle_event(event).await {
+                        warn!("blobs failed to handle event: {:?}", err);
+                    }
+                }
+
+                debug!("exiting event handling");
+            }
+            .instrument(info_span!("headless_eventsub", %node_id)),
+        );
+
+        Ok(Self {
+            doc,
+            blobs,
+            worker,
+            _doc_subscription_handle: handle,
+        })
+    }
+
+    pub fn doc(&self) -> &Doc {
+        &self.doc
+    }
+
+    pub fn blobs(&self) -> &Blobs {
+        &self.blobs
+    }
+
+    pub fn worker(&self) -> &Worker {
+        &self.worker
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use iroh::base::node_addr::AddrInfoOptions;
+    use iroh::client::docs::ShareMode;
+
+    use super::*;
+    use crate::vm::doc::create_doc;
+    use crate::vm::job::JobType;
+    use crate::vm::worker::capabilities::capabilities_key;
+    use crate::vm::worker::WorkerCapabilities;
+
+    #[tokio::test]
+    async fn joins_and_advertises_without_spaces() -> Result<()> {
+        let origin = iroh::node::Node::memory().enable_docs().spawn().await?;
+        let doc = create_doc(origin.client()).await?;
+        let ticket = doc
+            .share(ShareMode::Write, AddrInfoOptions::RelayAndAddresses)
+            .await?;
+
+        let node = iroh::node::Node::memory().enable_docs().spawn().await?;
+        let author = iroh::docs::Author::from_bytes(&node.endpoint().secret_key().to_bytes());
+        node.authors().import(author).await?;
+        let dir = tempfile::tempdir()?;
+        let worker = HeadlessWorker::join(
+            node.client(),
+            ticket,
+            WorkerConfig {
+                autofetch: Default::default(),
+                worker_root: dir.path().to_path_buf(),
+                peers: PeerBook::load(dir.path()).await?,
+                disk: DiskMonitor::new(dir.path(), Default::default()),
+                blob_store: BlobStore::open(&Default::default(), node.client().clone())?,
+                egress: Default::default(),
+                slots: Default::default(),
+                pickup: Default::default(),
+                disk_quota: Default::default(),
+                labels: BTreeSet::from(["gpu".to_string()]),
+                admission_scanner: None,
+                chaos: Default::default(),
+            },
+        )
+        .await?;
+
+        let capabilities = worker.worker().capabilities();
+        assert!(capabilities.headless);
+        assert!(!capabilities.job_types.contains(&JobType::Wasm));
+        assert!(!capabilities.satisfies(&["spaces".to_string()]));
+
+        // what's advertised is what the worker reports
+        let author_id = node_author_id(&node.node_id());
+        let entry = worker
+            .doc()
+            .get_exact(author_id, capabilities_key(author_id), false)
+            .await?
+            .expect("capabilities are advertised");
+        let data = node.blobs().read_to_bytes(entry.content_hash()).await?;
+        let advertised: WorkerCapabilities = serde_json::from_slice(&data)?;
+        assert_eq!(advertised, capabilities);
+        Ok(())
+    }
+}
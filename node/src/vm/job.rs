@@ -5,7 +5,7 @@ use std::{
 
 use anyhow::{bail, Context, Result};
 use bytes::Bytes;
-use iroh::blobs::{util::SetTagOption, Hash};
+use iroh::blobs::Hash;
 use iroh::docs::{Author, AuthorId};
 use serde::{Deserialize, Serialize};
 use tinytemplate::TinyTemplate;
@@ -14,8 +14,10 @@ use tracing::debug;
 use uuid::Uuid;
 
 use crate::router::RouterClient;
+use crate::space::grants::SpaceHandle;
 
-use super::blobs::Blobs;
+use super::admission::{AdmissionPolicy, ArtifactViolation};
+use super::blobs::{Blobs, ContentEncoding};
 
 pub(crate) const JOBS_PREFIX: &str = "jobs";
 
@@ -81,6 +83,11 @@ impl JobStatus {
                     *self
                 }
             }
+            // canceling ends a job that hasn't completed, assigned or not
+            (JobStatus::Scheduling | JobStatus::Assigned(_), JobStatus::Canceled(_)) => {
+                replaced = true;
+                other
+            }
             (status, _) => status,
         };
         replaced
@@ -102,6 +109,9 @@ pub enum JobDetails {
         image: String,
         /// Command to execute
         command: Vec<String>,
+        /// What the container may reach
+        #[serde(default)]
+        network: DockerNetwork,
     },
     #[serde(rename = "wasm")]
     Wasm {
@@ -120,6 +130,22 @@ impl JobDetails {
     }
 }
 
+/// Network access of a docker job. Lookups jobs make on the worker's egress network are
+/// written to the job's log & summed up in [`JobUsage::dns`].
+#[derive(Default, Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+#[serde(rename_all = "lowercase")]
+pub enum DockerNetwork {
+    /// no network at all, only loopback
+    None,
+    /// on the worker's egress network, with no way out of it
+    Internal,
+    /// out through the egress proxy, to the hosts the job's program declares. No network at
+    /// all on workers without egress.
+    #[default]
+    Allowlist,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub enum JobType {
     Docker,
@@ -144,8 +170,41 @@ pub struct JobDescription {
     pub details: JobDetails,
     #[serde(default)]
     pub artifacts: Artifacts,
-    #[serde(default = "default_timeout")]
+    /// how long the job may run, eg: "90s", "5m" or "2h"
+    #[serde(default = "default_timeout", with = "super::duration")]
     pub timeout: time::Duration,
+    #[serde(default)]
+    pub priority: JobPriority,
+    /// Whether a worker may stop this job to make room for an interactive job, running it
+    /// again from the start later. Only batch jobs are preempted.
+    #[serde(default)]
+    pub preemptible: bool,
+    /// when the job must be done by. The scheduler gives up on jobs that aren't, see
+    /// [`JobResultStatus::ErrDeadlineExceeded`]
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub deadline: Option<time::OffsetDateTime>,
+    /// what a worker needs to run the job, eg: `["docker", "gpu"]`. See
+    /// [`super::worker::capabilities`] for what can be required
+    #[serde(default)]
+    pub requires: Vec<String>,
+    /// sample the job as it runs & upload a flamegraph of where its time went, see
+    /// [`super::profile`]
+    #[serde(default)]
+    pub profile: bool,
+    /// other spaces the job may use, see [`crate::space::grants`]
+    #[serde(default)]
+    pub spaces: Vec<SpaceHandle>,
+}
+
+/// Interactive jobs, eg: program runs started from the UI, go ahead of batch jobs on a worker.
+#[derive(
+    Default, Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum JobPriority {
+    #[default]
+    Batch,
+    Interactive,
 }
 
 pub const DEFAULT_TIMEOUT: time::Duration = time::Duration::HOUR;
@@ -155,6 +214,7 @@ fn default_timeout() -> time::Duration {
 }
 
 #[derive(Default, Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
 pub struct Artifacts {
     /// List of artifacts to download.
     #[serde(default)]
@@ -171,6 +231,7 @@ impl Artifacts {
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
 pub struct Artifact {
     /// The object name of the artifact
     pub name: String,
@@ -206,6 +267,12 @@ impl JobNameContext {
     }
 }
 
+/// The name an artifact uploaded by `job_name` is stored under, before rendering. Files of an
+/// uploaded folder are stored under it.
+pub(crate) fn upload_template(job_name: &str, artifact: &str) -> String {
+    format!("{{scope}}/{job_name}/{artifact}")
+}
+
 impl Artifact {
     /// Get the file to use for this file
     pub fn mode(&self) -> u32 {
@@ -230,7 +297,7 @@ impl Artifact {
         hash: Hash,
         size: u64,
     ) -> Result<()> {
-        let template = format!("{{scope}}/{job_name}/{}", self.name);
+        let template = upload_template(job_name, &self.name);
         let name = job_name_ctx.render(&template)?;
         blobs.put_object(&name, hash, size).await?;
 
@@ -292,22 +359,99 @@ impl TryFrom<Bytes> for ScheduledJob {
 }
 
 #[derive(Default, Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
 pub struct JobResult {
     /// The worker that executed the job.
+    #[cfg_attr(feature = "specta", specta(type = Option<String>))]
     pub worker: Option<AuthorId>,
     pub status: JobResultStatus,
+    /// Resources the job consumed, None if it never reached a worker.
+    #[serde(default)]
+    pub usage: Option<JobUsage>,
 }
 
+/// Resources consumed by a single job execution. Measurements an executor can't take are None.
 #[derive(Default, Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+pub struct JobUsage {
+    pub wall_time_ms: u64,
+    /// from docker stats. None for wasm jobs: extism doesn't expose the fuel a call consumed
+    pub cpu_time_ms: Option<u64>,
+    /// from docker stats, None for wasm jobs
+    pub peak_memory_bytes: Option<u64>,
+    /// total size of uploaded artifacts
+    pub artifact_bytes: u64,
+    /// destinations the job contacted through the egress proxy, docker only
+    #[serde(default)]
+    pub egress: Vec<EgressRecord>,
+    /// names the job looked up, docker only
+    #[serde(default)]
+    pub dns: Vec<DnsRecord>,
+    /// artifacts the workspace's admission policy kept out of the workspace
+    #[serde(default)]
+    pub rejected_artifacts: Vec<ArtifactViolation>,
+    /// times the job was stopped to make room for an interactive job & started over
+    #[serde(default)]
+    pub preemptions: u32,
+    /// name of the flamegraph artifact of a profiled job
+    #[serde(default)]
+    pub profile: Option<String>,
+    /// every artifact the job uploaded, the flamegraph included
+    #[serde(default)]
+    pub artifacts: Vec<ArtifactRef>,
+}
+
+/// An artifact a job uploaded, as stored in the workspace.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+pub struct ArtifactRef {
+    /// object name, under the scope of the run, eg: `{scope}/job/out.txt`
+    pub name: String,
+    /// content of the object, compressed if the artifact is stored compressed
+    #[cfg_attr(feature = "specta", specta(type = String))]
+    pub hash: Hash,
+    /// bytes the job wrote
+    pub size: u64,
+}
+
+/// Outbound traffic from a job to one destination.
+#[derive(Default, Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+pub struct EgressRecord {
+    /// `host:port`
+    pub destination: String,
+    /// requests let through
+    pub requests: u64,
+    /// requests refused, because the destination isn't allowed or the job was rate limited
+    pub denied: u64,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+}
+
+/// DNS lookups a job made for one name & record type.
+#[derive(Default, Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+pub struct DnsRecord {
+    pub name: String,
+    /// eg: `A` or `AAAA`
+    pub record_type: String,
+    pub lookups: u64,
+}
+
+#[derive(Default, Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
 pub enum JobResultStatus {
     #[default]
     Unknown,
     Ok(JobOutput),
     Err(String),
     ErrTimeout,
+    /// not assigned or completed before the job's deadline
+    ErrDeadlineExceeded,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
 pub enum JobOutput {
     Docker {
         code: i64,
@@ -333,21 +477,38 @@ pub struct JobContext {
     pub name_context: JobNameContext,
     pub author: Author,
     pub artifacts: Artifacts,
+    /// whether to profile the job
+    pub profile: bool,
+    /// other spaces the job may use
+    pub spaces: Vec<SpaceHandle>,
+    /// most bytes the job's directory may hold, see [`super::worker::quota`]
+    pub disk_quota: Option<u64>,
 }
 
 impl JobContext {
-    pub fn downloads_path(&self, root: impl AsRef<Path>) -> PathBuf {
+    /// Directory holding the job's downloads & uploads.
+    pub fn job_path(&self, root: impl AsRef<Path>) -> PathBuf {
         root.as_ref()
             .join(self.name_context.scope.as_simple().to_string())
             .join(&self.name)
-            .join("downloads")
+    }
+
+    pub fn downloads_path(&self, root: impl AsRef<Path>) -> PathBuf {
+        self.job_path(root).join("downloads")
     }
 
     pub fn uploads_path(&self, root: impl AsRef<Path>) -> PathBuf {
-        root.as_ref()
-            .join(self.name_context.scope.as_simple().to_string())
-            .join(&self.name)
-            .join("uploads")
+        self.job_path(root).join("uploads")
+    }
+
+    /// Fail if the job would hold more than its quota with `size` bytes.
+    fn check_disk_quota(&self, size: u64) -> Result<()> {
+        match self.disk_quota {
+            Some(quota) if size > quota => {
+                bail!("job needs {size} bytes of disk, over its quota of {quota} bytes")
+            }
+            _ => Ok(()),
+        }
     }
 
     pub fn job_scope(&self, ctx: &str) -> String {
@@ -378,10 +539,16 @@ impl JobContext {
             .await
             .context("create_dir_all")?;
 
+        let mut written = 0;
         for artifact in &self.artifacts.downloads {
             debug!("writing download {:?}", artifact);
-            let artifact_hash = artifact.content_hash(&self.name_context, blobs).await?;
-            let mut blob_reader = node.blobs().read(artifact_hash).await?;
+            let name = self.name_context.render(&artifact.name)?;
+            let entry = blobs.get_object_info(&name).await?;
+            let encoding = blobs.object_encoding(&name, &entry).await?;
+            // compressed downloads grow when decoded, they're checked again once written
+            self.check_disk_quota(written + entry.content_len())
+                .with_context(|| format!("writing download {name}"))?;
+            let mut blob_reader = node.blobs().read(entry.content_hash()).await?;
             let file_path = path.join(&artifact.path);
 
             let mode = artifact.mode();
@@ -392,11 +559,33 @@ impl JobContext {
                 out_file.mode(mode);
             }
             let mut out = out_file.open(&file_path).await.context("open")?;
-            tokio::io::copy(&mut blob_reader, &mut out)
-                .await
-                .context("copy")?;
+            match encoding {
+                ContentEncoding::Identity => {
+                    tokio::io::copy(&mut blob_reader, &mut out)
+                        .await
+                        .context("copy")?;
+                }
+                ContentEncoding::Zstd => {
+                    let compressed = tempfile::NamedTempFile::new()?;
+                    let mut staging = tokio::fs::File::create(compressed.path()).await?;
+                    tokio::io::copy(&mut blob_reader, &mut staging)
+                        .await
+                        .context("copy")?;
+                    drop(staging);
+                    let out = out.try_clone().await?.into_std().await;
+                    tokio::task::spawn_blocking(move || {
+                        let mut out = out;
+                        zstd::stream::copy_decode(compressed.as_file(), &mut out)
+                    })
+                    .await?
+                    .with_context(|| format!("decompressing {}", name))?;
+                }
+            }
             out.flush().await?;
-            drop(out)
+            drop(out);
+            written += tokio::fs::metadata(&file_path).await?.len();
+            self.check_disk_quota(written)
+                .with_context(|| format!("writing download {name}"))?;
         }
 
         Ok(())
@@ -406,39 +595,56 @@ impl JobContext {
         &self,
         path: impl AsRef<Path>,
         blobs: &Blobs,
-        node: &RouterClient,
-    ) -> Result<()> {
+        policy: &AdmissionPolicy,
+    ) -> Result<(u64, Vec<ArtifactRef>, Vec<ArtifactViolation>)> {
         // Todo: parallelize
         let path = path.as_ref();
+        let mut uploaded = 0;
+        let mut artifacts = Vec::new();
+        let mut rejected = Vec::new();
 
         debug!("uploading from {}", path.display());
+        if self.disk_quota.is_some() {
+            // everything the job wrote, next to its downloads
+            let used = super::worker::quota::dir_size(path.parent().unwrap_or(path)).await?;
+            self.check_disk_quota(used).context("reading uploads")?;
+        }
 
         for artifact in &self.artifacts.uploads {
             debug!("reading upload {:?}", artifact);
             let file_path = path.join(&artifact.path);
 
-            let upload_file = |fp: PathBuf, prefix: Option<PathBuf>| async {
+            let upload_file = |fp: PathBuf, prefix: Option<PathBuf>, uploaded: u64| async move {
                 debug!("reading {}", fp.display());
-                let source = tokio::fs::File::open(fp).await?;
-                let res = node
-                    .blobs()
-                    .add_reader(source, SetTagOption::Auto)
-                    .await?
-                    .await?;
-
+                let size = tokio::fs::metadata(&fp).await?.len();
+                if let Some(reason) = policy.check(&fp, size, uploaded).await {
+                    let name = fp.strip_prefix(path).unwrap_or(&fp);
+                    let name = name.to_string_lossy().to_string();
+                    debug!("rejected artifact {}: {}", name, reason);
+                    return anyhow::Ok(Err(ArtifactViolation {
+                        artifact: name,
+                        reason,
+                    }));
+                }
                 let template = if let Some(prefix) = prefix {
-                    format!("{{scope}}/{}/{}", self.name, prefix.to_string_lossy())
+                    upload_template(&self.name, &prefix.to_string_lossy())
                 } else {
-                    format!("{{scope}}/{}/{}", self.name, artifact.name)
+                    upload_template(&self.name, &artifact.name)
                 };
                 let name = self.name_context.render(&template)?;
+                let (hash, size) = blobs.put_file(&name, &fp).await?;
                 debug!("uploaded artifact {}", name);
-                blobs.put_object(&name, res.hash, res.size).await?;
-                anyhow::Ok(())
+                anyhow::Ok(Ok(ArtifactRef { name, hash, size }))
             };
 
             if file_path.is_file() {
-                upload_file(file_path, None).await?;
+                match upload_file(file_path, None, uploaded).await? {
+                    Ok(artifact) => {
+                        uploaded += artifact.size;
+                        artifacts.push(artifact);
+                    }
+                    Err(violation) => rejected.push(violation),
+                }
             } else if file_path.is_dir() {
                 let root = file_path.clone();
                 let sources = tokio::task::spawn_blocking(move || {
@@ -460,14 +666,20 @@ impl JobContext {
                 debug!("found {} files in {}", sources.len(), file_path.display());
                 for source in sources {
                     let prefix = source.strip_prefix(path)?.into();
-                    upload_file(source, Some(prefix)).await?;
+                    match upload_file(source, Some(prefix), uploaded).await? {
+                        Ok(artifact) => {
+                            uploaded += artifact.size;
+                            artifacts.push(artifact);
+                        }
+                        Err(violation) => rejected.push(violation),
+                    }
                 }
             } else {
                 bail!("unable to read file: {}", file_path.display());
             }
         }
 
-        Ok(())
+        Ok((uploaded, artifacts, rejected))
     }
 }
 
@@ -476,6 +688,7 @@ mod tests {
     use rand::thread_rng;
 
     use super::*;
+    use crate::vm::test_utils::create_nodes;
 
     #[test]
     fn test_render_job_name() {
@@ -501,6 +714,7 @@ mod tests {
             details: JobDetails::Docker {
                 image: "alpine:latest".into(),
                 command: vec!["ls".into()],
+                network: Default::default(),
             },
             artifacts: Artifacts {
                 downloads: vec!["foo".into(), "bar".into(), "baz".into()]
@@ -552,4 +766,104 @@ mod tests {
             JobStatus::Canceled(None)
         );
     }
+
+    #[test]
+    fn test_merge_job_status() {
+        let id = iroh::docs::Author::new(&mut thread_rng()).id();
+        // status keys in the order the doc lists them
+        let merged = |statuses: &[JobStatus]| {
+            let mut status = statuses[0];
+            for next in &statuses[1..] {
+                status.merge(*next);
+            }
+            status
+        };
+        assert_eq!(
+            merged(&[
+                JobStatus::Assigned(id),
+                JobStatus::Completed(id),
+                JobStatus::Scheduling
+            ]),
+            JobStatus::Completed(id)
+        );
+        assert_eq!(
+            merged(&[
+                JobStatus::Assigned(id),
+                JobStatus::Canceled(Some(id)),
+                JobStatus::Scheduling
+            ]),
+            JobStatus::Canceled(Some(id))
+        );
+        assert_eq!(
+            merged(&[JobStatus::Canceled(None), JobStatus::Scheduling]),
+            JobStatus::Canceled(None)
+        );
+    }
+
+    #[tokio::test]
+    async fn uploads_record_artifact_refs() -> Result<()> {
+        let temp_dir = tempfile::tempdir().context("tempdir")?;
+        let nodes = create_nodes(&temp_dir, 1).await?;
+        let (_node, ws) = &nodes[0];
+        let scope = Uuid::new_v4();
+        let ctx = JobContext {
+            space: "default".into(),
+            program_id: Uuid::new_v4(),
+            id: Uuid::new_v4(),
+            environment: Default::default(),
+            name: "report".into(),
+            name_context: JobNameContext { scope },
+            author: Author::new(&mut thread_rng()),
+            artifacts: Artifacts {
+                downloads: Default::default(),
+                uploads: [
+                    Artifact {
+                        name: "out.txt".into(),
+                        path: "out.txt".into(),
+                        executable: false,
+                    },
+                    Artifact {
+                        name: "logs".into(),
+                        path: "logs".into(),
+                        executable: false,
+                    },
+                ]
+                .into_iter()
+                .collect(),
+            },
+            profile: false,
+            spaces: Vec::new(),
+            disk_quota: None,
+        };
+
+        let job_dir = temp_dir.path().join("job");
+        tokio::fs::create_dir_all(job_dir.join("logs")).await?;
+        tokio::fs::write(job_dir.join("out.txt"), "done\n").await?;
+        tokio::fs::write(job_dir.join("logs/run.log"), "a line\nanother\n").await?;
+
+        let (uploaded, mut artifacts, rejected) = ctx
+            .read_uploads(&job_dir, ws.blobs(), &AdmissionPolicy::default())
+            .await?;
+        assert!(rejected.is_empty());
+        assert_eq!(uploaded, 20);
+        artifacts.sort_by(|a, b| a.name.cmp(&b.name));
+        let names = artifacts
+            .iter()
+            .map(|a| a.name.as_str())
+            .collect::<Vec<_>>();
+        let scope = scope.as_simple();
+        assert_eq!(
+            names,
+            vec![
+                format!("{scope}/report/logs/run.log"),
+                format!("{scope}/report/out.txt"),
+            ]
+        );
+        assert_eq!(artifacts[1].size, 5);
+        for artifact in &artifacts {
+            let info = ws.blobs().get_object_info(&artifact.name).await?;
+            assert_eq!(info.content_hash(), artifact.hash);
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,377 @@
+//! Human-readable diagnostics for flow TOML.
+use std::fmt;
+use std::ops::Range;
+
+use serde::{Deserialize, Serialize};
+use toml_edit::{ImDocument, Item, TableLike};
+
+use super::flow::Flow;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+#[serde(rename_all = "camelCase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A problem found in a flow. Line & column are 1-based, and absent for problems that aren't
+/// tied to a single location, like duplicate job names.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    pub message: String,
+}
+
+impl Diagnostic {
+    fn new(severity: Severity, src: &str, span: Option<Range<usize>>, message: String) -> Self {
+        let (line, column) = match span {
+            Some(span) => {
+                let (line, column) = line_col(src, span.start);
+                (Some(line), Some(column))
+            }
+            None => (None, None),
+        };
+        Diagnostic {
+            severity,
+            line,
+            column,
+            message,
+        }
+    }
+
+    pub(crate) fn from_toml_error(src: &str, err: &toml::de::Error) -> Self {
+        let span = err.span();
+        let mut message = err.message().trim().to_string();
+        let key = span
+            .clone()
+            .map(|span| line_text(src, span.start).trim_start().to_string())
+            .unwrap_or_default();
+        if key.starts_with("timeout") {
+            message.push_str(". timeouts are durations like timeout = \"90s\", \"5m\" or \"2h\"");
+        } else if message.starts_with("unknown variant") {
+            message.push_str(". job details must be one of [details.docker] or [details.wasm]");
+        }
+        Diagnostic::new(Severity::Error, src, span, message)
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.line, self.column) {
+            (Some(line), Some(column)) => write!(f, "{line}:{column}: {}", self.message),
+            _ => f.write_str(&self.message),
+        }
+    }
+}
+
+/// Expected structure of a TOML document, used to spot misspelled & misplaced keys.
+enum Shape {
+    /// anything goes, no further checks
+    Any,
+    Table(&'static [(&'static str, Shape)]),
+    /// an array of values or tables
+    List(&'static Shape),
+    /// an externally tagged enum: a table with exactly one of the listed keys
+    OneOf(&'static [(&'static str, Shape)]),
+}
+
+static FLOW: Shape = Shape::Table(&[
+    ("name", Shape::Any),
+    ("tasks", Shape::List(&TASK)),
+    ("uploads", Shape::List(&UPLOAD)),
+    ("max_concurrency", Shape::Any),
+    (
+        "downloads",
+        Shape::List(&Shape::Table(&[("name", Shape::Any), ("path", Shape::Any)])),
+    ),
+]);
+
+static TASK: Shape = Shape::Table(&[
+    ("tasks", Shape::List(&TASK)),
+    ("description", Shape::Table(JOB)),
+]);
+
+static UPLOAD: Shape = Shape::Table(&[
+    ("name", Shape::Any),
+    (
+        "source",
+        Shape::OneOf(&[
+            ("file", Shape::Table(&[("path", Shape::Any)])),
+            ("inline", Shape::Table(&[("content", Shape::Any)])),
+        ]),
+    ),
+]);
+
+static ARTIFACT: Shape = Shape::Table(&[
+    ("name", Shape::Any),
+    ("path", Shape::Any),
+    ("executable", Shape::Any),
+]);
+
+static JOB: &[(&str, Shape)] = &[
+    ("space", Shape::Any),
+    ("program_id", Shape::Any),
+    ("name", Shape::Any),
+    ("author", Shape::Any),
+    ("environment", Shape::Any),
+    (
+        "details",
+        Shape::OneOf(&[
+            (
+                "docker",
+                Shape::Table(&[
+                    ("image", Shape::Any),
+                    ("command", Shape::Any),
+                    ("network", Shape::Any),
+                ]),
+            ),
+            ("wasm", Shape::Table(&[("module", Shape::Any)])),
+        ]),
+    ),
+    (
+        "artifacts",
+        Shape::Table(&[
+            ("downloads", Shape::List(&ARTIFACT)),
+            ("uploads", Shape::List(&ARTIFACT)),
+        ]),
+    ),
+    ("timeout", Shape::Any),
+    ("priority", Shape::Any),
+    ("preemptible", Shape::Any),
+    ("deadline", Shape::Any),
+    ("requires", Shape::Any),
+    ("profile", Shape::Any),
+    (
+        "spaces",
+        Shape::List(&Shape::Table(&[
+            ("space", Shape::Any),
+            ("access", Shape::Any),
+        ])),
+    ),
+];
+
+/// Check flow TOML without running it: syntax, unknown keys, types & flow invariants.
+pub(crate) fn lint(src: &str) -> Vec<Diagnostic> {
+    let doc = match ImDocument::parse(src) {
+        Ok(doc) => doc,
+        Err(err) => {
+            let message = err.message().trim().to_string();
+            return vec![Diagnostic::new(Severity::Error, src, err.span(), message)];
+        }
+    };
+
+    let mut diagnostics = Vec::new();
+    check_table(src, doc.as_table(), &FLOW, "", &mut diagnostics);
+
+    match toml::from_str::<Flow>(src) {
+        Ok(flow) => {
+            if let Err(err) = flow.validate() {
+                diagnostics.push(Diagnostic::new(Severity::Error, src, None, err.to_string()));
+            }
+        }
+        Err(err) => {
+            // an unknown key already explains the failure better than serde's message
+            let diagnostic = Diagnostic::from_toml_error(src, &err);
+            if !diagnostics.iter().any(|d| d.line == diagnostic.line) {
+                diagnostics.push(diagnostic);
+            }
+        }
+    }
+
+    diagnostics.sort_by_key(|d| (d.line.unwrap_or(usize::MAX), d.column));
+    diagnostics
+}
+
+fn check_item(src: &str, item: &Item, shape: &Shape, path: &str, out: &mut Vec<Diagnostic>) {
+    match shape {
+        Shape::Any => {}
+        Shape::Table(_) | Shape::OneOf(_) => {
+            if let Some(table) = item.as_table_like() {
+                check_table(src, table, shape, path, out);
+            }
+        }
+        Shape::List(inner) => {
+            if let Some(tables) = item.as_array_of_tables() {
+                for table in tables.iter() {
+                    check_table(src, table, inner, path, out);
+                }
+            } else if let Some(array) = item.as_array() {
+                for value in array.iter() {
+                    if let Some(table) = value.as_inline_table() {
+                        check_table(src, table, inner, path, out);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn check_table(
+    src: &str,
+    table: &dyn TableLike,
+    shape: &Shape,
+    path: &str,
+    out: &mut Vec<Diagnostic>,
+) {
+    let fields = match shape {
+        Shape::Table(fields) | Shape::OneOf(fields) => *fields,
+        _ => return,
+    };
+
+    let mut variants = 0;
+    for (key, item) in table.iter() {
+        let span = table.key(key).and_then(|k| k.span());
+        let field_path = if path.is_empty() {
+            key.to_string()
+        } else {
+            format!("{path}.{key}")
+        };
+        match fields.iter().find(|(name, _)| *name == key) {
+            Some((_, field_shape)) => {
+                variants += 1;
+                check_item(src, item, field_shape, &field_path, out);
+            }
+            None => {
+                let names = fields.iter().map(|(name, _)| *name).collect::<Vec<_>>();
+                let mut message = match path {
+                    "" => format!("unknown field `{key}`"),
+                    path => format!("unknown field `{key}` in `{path}`"),
+                };
+                match closest(key, &names) {
+                    Some(name) => message.push_str(&format!(", did you mean `{name}`?")),
+                    None => message.push_str(&format!(", expected one of {}", names.join(", "))),
+                }
+                out.push(Diagnostic::new(Severity::Error, src, span, message));
+            }
+        }
+    }
+
+    if matches!(shape, Shape::OneOf(_)) && variants > 1 {
+        let names = fields.iter().map(|(name, _)| *name).collect::<Vec<_>>();
+        out.push(Diagnostic::new(
+            Severity::Error,
+            src,
+            None,
+            format!("`{path}` must set only one of {}", names.join(", ")),
+        ));
+    }
+}
+
+/// The known name closest to `key`, if it's plausibly a typo.
+fn closest<'a>(key: &str, names: &[&'a str]) -> Option<&'a str> {
+    names
+        .iter()
+        .map(|name| (edit_distance(key, name), *name))
+        .filter(|(distance, name)| *distance <= name.len().max(3) / 3)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, name)| name)
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut prev = (0..=b.len()).collect::<Vec<_>>();
+    for (i, ca) in a.chars().enumerate() {
+        let mut curr = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == *cb { 0 } else { 1 };
+            curr[j + 1] = (prev[j] + cost).min(prev[j + 1] + 1).min(curr[j] + 1);
+        }
+        prev = curr;
+    }
+    prev[b.len()]
+}
+
+fn line_col(src: &str, offset: usize) -> (usize, usize) {
+    let before = &src[..offset.min(src.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.chars().rev().take_while(|c| *c != '\n').count() + 1;
+    (line, column)
+}
+
+fn line_text(src: &str, offset: usize) -> &str {
+    let offset = offset.min(src.len());
+    let start = src[..offset].rfind('\n').map_or(0, |i| i + 1);
+    let end = src[offset..].find('\n').map_or(src.len(), |i| offset + i);
+    &src[start..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FLOW_TOML: &str = r#"name = "flow"
+
+[[tasks]]
+[tasks.description]
+space = "personal"
+program_id = "67e55044-10b1-426f-9247-bb680e5fe0c8"
+name = "run"
+author = "me"
+environment = {}
+timout = "10.0"
+
+[tasks.description.details.wasm]
+module = { LocalPath = "main.wasm" }
+"#;
+
+    #[test]
+    fn lint_unknown_field() {
+        let diagnostics = lint(FLOW_TOML);
+        assert_eq!(diagnostics.len(), 1, "{:?}", diagnostics);
+        let d = &diagnostics[0];
+        assert_eq!((d.line, d.column), (Some(10), Some(1)));
+        assert!(
+            d.message.contains("did you mean `timeout`"),
+            "{}",
+            d.message
+        );
+    }
+
+    #[test]
+    fn lint_wrong_type() {
+        let src = FLOW_TOML
+            .replace("timout", "timeout")
+            .replace("author = \"me\"", "author = 5");
+        let diagnostics = lint(&src);
+        assert_eq!(diagnostics.len(), 1, "{:?}", diagnostics);
+        assert_eq!(diagnostics[0].severity, Severity::Error);
+        assert_eq!(diagnostics[0].line, Some(8));
+    }
+
+    #[test]
+    fn lint_clean() {
+        let src = FLOW_TOML.replace("timout", "timeout");
+        assert_eq!(lint(&src), Vec::new());
+    }
+
+    #[test]
+    fn lint_all_job_fields() {
+        let src = r#"name = "flow"
+
+[[tasks]]
+[tasks.description]
+space = "personal"
+program_id = "67e55044-10b1-426f-9247-bb680e5fe0c8"
+name = "run"
+author = "me"
+environment = {}
+timeout = "10m"
+priority = "interactive"
+preemptible = true
+deadline = "2030-01-01T00:00:00Z"
+requires = ["docker"]
+profile = true
+spaces = [{ space = "67e55044-10b1-426f-9247-bb680e5fe0c8", access = "read" }]
+
+[tasks.description.details.docker]
+image = "alpine"
+command = ["true"]
+network = "none"
+"#;
+        assert_eq!(lint(src), Vec::new());
+    }
+}
@@ -0,0 +1,347 @@
+//! Output of jobs as they run.
+//!
+//! A worker hands what a job prints to a [`LogSink`], which writes it out in chunks a few times
+//! a second as objects named `jobs/<job>/logs/<seq>`. Objects are announced & fetched like any
+//! other, so whoever is watching the job can read its output before it finishes, see
+//! [`super::VM::subscribe_job_logs`]. The full output still ends up in the
+//! job's result.
+use std::collections::{BTreeMap, HashSet};
+use std::time::Duration;
+
+use anyhow::Result;
+use futures::Stream;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::task::JoinHandle;
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+use super::blobs::Blobs;
+use super::job::JOBS_PREFIX;
+
+/// How often buffered output is written out.
+const FLUSH_INTERVAL: Duration = Duration::from_millis(500);
+/// Buffered output is written out early once it's this big.
+const MAX_CHUNK_BYTES: usize = 64 * 1024;
+/// Chunks held for a subscriber that isn't reading.
+const SUBSCRIBER_CAPACITY: usize = 64;
+/// Object writes arrive as doc events, which can be dropped. Subscribers list the job's chunks
+/// again this often regardless.
+const LIST_INTERVAL: Duration = Duration::from_secs(5);
+/// Chunks listed per page.
+const LIST_PAGE_SIZE: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+#[serde(rename_all = "lowercase")]
+pub enum LogStream {
+    Stdout,
+    Stderr,
+}
+
+/// A piece of a job's output.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+pub struct LogChunk {
+    pub job_id: Uuid,
+    /// Chunks of a job are numbered from 0, in the order they were written
+    pub seq: u64,
+    pub stream: LogStream,
+    pub data: String,
+    /// Unix milliseconds when the chunk was written
+    pub at: i64,
+    /// Set on the job's last chunk, which can be empty
+    #[serde(default)]
+    pub last: bool,
+}
+
+/// Where an executor sends a job's output, cheap to clone.
+#[derive(Debug, Clone)]
+pub(crate) struct LogSink(flume::Sender<(LogStream, String)>);
+
+impl LogSink {
+    pub(crate) fn write(&self, stream: LogStream, data: impl Into<String>) {
+        // the writer only goes away once every sink is dropped
+        let _ = self.0.send((stream, data.into()));
+    }
+}
+
+/// A sink whose output is read straight from the returned receiver.
+#[cfg(test)]
+pub(crate) fn capture() -> (LogSink, flume::Receiver<(LogStream, String)>) {
+    let (tx, rx) = flume::unbounded();
+    (LogSink(tx), rx)
+}
+
+/// Writes what's sent to the sinks of one job, see [`start`].
+#[derive(Debug)]
+pub(crate) struct LogWriter(JoinHandle<()>);
+
+impl LogWriter {
+    /// Wait for the last chunk to be written. Every sink has to be dropped first.
+    pub(crate) async fn finish(self) {
+        if let Err(err) = self.0.await {
+            warn!("log writer failed: {:?}", err);
+        }
+    }
+}
+
+/// Start writing the output of `job_id`.
+pub(crate) fn start(blobs: Blobs, job_id: Uuid) -> (LogSink, LogWriter) {
+    let (tx, rx) = flume::unbounded();
+    let handle = tokio::spawn(async move {
+        let mut writer = ChunkWriter {
+            blobs,
+            job_id,
+            seq: 0,
+            pending: Vec::new(),
+            pending_bytes: 0,
+        };
+        let mut flush = tokio::time::interval(FLUSH_INTERVAL);
+        loop {
+            tokio::select! {
+                message = rx.recv_async() => match message {
+                    Ok((stream, data)) => {
+                        writer.push(stream, data);
+                        if writer.pending_bytes >= MAX_CHUNK_BYTES {
+                            writer.flush(false).await;
+                        }
+                    }
+                    Err(_) => break,
+                },
+                _ = flush.tick() => writer.flush(false).await,
+            }
+        }
+        writer.flush(true).await;
+    });
+    (LogSink(tx), LogWriter(handle))
+}
+
+struct ChunkWriter {
+    blobs: Blobs,
+    job_id: Uuid,
+    seq: u64,
+    /// output not written yet, consecutive writes to a stream are merged
+    pending: Vec<(LogStream, String)>,
+    pending_bytes: usize,
+}
+
+impl ChunkWriter {
+    fn push(&mut self, stream: LogStream, data: String) {
+        self.pending_bytes += data.len();
+        match self.pending.last_mut() {
+            Some((last, buffer)) if *last == stream => buffer.push_str(&data),
+            _ => self.pending.push((stream, data)),
+        }
+    }
+
+    /// Write out everything pending. The last flush always writes a chunk, to mark the end.
+    async fn flush(&mut self, last: bool) {
+        let mut pending: Vec<_> = self.pending.drain(..).collect();
+        self.pending_bytes = 0;
+        if last && pending.is_empty() {
+            pending.push((LogStream::Stdout, String::new()));
+        }
+        let count = pending.len();
+        for (i, (stream, data)) in pending.into_iter().enumerate() {
+            let chunk = LogChunk {
+                job_id: self.job_id,
+                seq: self.seq,
+                stream,
+                data,
+                at: chrono::Utc::now().timestamp_millis(),
+                last: last && i + 1 == count,
+            };
+            self.seq += 1;
+            if let Err(err) = self.write(&chunk).await {
+                warn!("writing logs of job {}: {:?}", self.job_id, err);
+            }
+        }
+    }
+
+    async fn write(&self, chunk: &LogChunk) -> Result<()> {
+        let data = serde_json::to_vec(chunk)?;
+        self.blobs
+            .put_bytes(&log_name(chunk.job_id, chunk.seq), data)
+            .await?;
+        Ok(())
+    }
+}
+
+fn log_prefix(job_id: Uuid) -> String {
+    format!("{}/{}/logs/", JOBS_PREFIX, job_id.as_u128())
+}
+
+fn log_name(job_id: Uuid, seq: u64) -> String {
+    // zero padded, so names sort in the order chunks were written
+    format!("{}{:020}", log_prefix(job_id), seq)
+}
+
+/// Chunks of `job_id`'s output in order, from the first one, as they're written. The stream
+/// ends after the job's last chunk.
+pub(crate) fn subscribe(blobs: Blobs, job_id: Uuid) -> impl Stream<Item = LogChunk> {
+    let (tx, rx) = flume::bounded(SUBSCRIBER_CAPACITY);
+    tokio::spawn(async move {
+        if let Err(err) = follow(&blobs, job_id, &tx).await {
+            debug!("following logs of job {}: {:?}", job_id, err);
+        }
+    });
+    rx.into_stream()
+}
+
+async fn follow(blobs: &Blobs, job_id: Uuid, tx: &flume::Sender<LogChunk>) -> Result<()> {
+    let prefix = log_prefix(job_id);
+    // subscribe before listing, so a chunk written in between isn't missed
+    let mut puts = blobs.subscribe_objects();
+    let mut order = ChunkOrder::default();
+    let mut list = true;
+    loop {
+        if list {
+            let mut cursor = None;
+            loop {
+                let page = blobs
+                    .list_objects(&prefix, cursor.as_deref(), LIST_PAGE_SIZE)
+                    .await?;
+                for object in page.objects {
+                    order.read(blobs, object.name).await;
+                }
+                match page.cursor {
+                    Some(next) => cursor = Some(next),
+                    None => break,
+                }
+            }
+        }
+        for chunk in order.ready() {
+            let last = chunk.last;
+            if tx.send_async(chunk).await.is_err() || last {
+                // the subscriber went away, or that was everything
+                return Ok(());
+            }
+        }
+        list = tokio::select! {
+            put = puts.recv() => match put {
+                Ok(name) => {
+                    if name.starts_with(&prefix) {
+                        order.read(blobs, name).await;
+                    }
+                    false
+                }
+                Err(RecvError::Lagged(_)) => true,
+                Err(RecvError::Closed) => {
+                    tokio::time::sleep(LIST_INTERVAL).await;
+                    true
+                }
+            },
+            _ = tokio::time::sleep(LIST_INTERVAL) => true,
+        };
+    }
+}
+
+/// Puts chunks that arrive out of order back in order.
+#[derive(Debug, Default)]
+struct ChunkOrder {
+    /// names of chunks read so far
+    seen: HashSet<String>,
+    next: u64,
+    waiting: BTreeMap<u64, LogChunk>,
+}
+
+impl ChunkOrder {
+    /// Read a chunk if it wasn't already. Chunks that can't be read yet, because the writer's
+    /// announcement hasn't arrived, are picked up by the next listing.
+    async fn read(&mut self, blobs: &Blobs, name: String) {
+        if self.seen.contains(&name) {
+            return;
+        }
+        let chunk = async {
+            let data = blobs.get_object(&name).await?;
+            anyhow::Ok(serde_json::from_slice(&data)?)
+        };
+        match chunk.await {
+            Ok(chunk) => self.add(name, chunk),
+            Err(err) => debug!("reading log chunk {}: {:?}", name, err),
+        }
+    }
+
+    fn add(&mut self, name: String, chunk: LogChunk) {
+        self.seen.insert(name);
+        if chunk.seq >= self.next {
+            self.waiting.insert(chunk.seq, chunk);
+        }
+    }
+
+    /// Chunks that follow the ones already handed out, without gaps.
+    fn ready(&mut self) -> Vec<LogChunk> {
+        let mut ready = Vec::new();
+        while let Some(chunk) = self.waiting.remove(&self.next) {
+            self.next += 1;
+            ready.push(chunk);
+        }
+        ready
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Context;
+    use futures::StreamExt;
+
+    use super::*;
+    use crate::vm::test_utils::create_nodes;
+
+    fn chunk(seq: u64) -> LogChunk {
+        LogChunk {
+            job_id: Uuid::nil(),
+            seq,
+            stream: LogStream::Stdout,
+            data: seq.to_string(),
+            at: 0,
+            last: false,
+        }
+    }
+
+    #[test]
+    fn chunks_come_out_in_order() {
+        let mut order = ChunkOrder::default();
+        order.add("1".into(), chunk(1));
+        assert!(order.ready().is_empty(), "waits for the first chunk");
+        order.add("0".into(), chunk(0));
+        order.add("3".into(), chunk(3));
+        let seqs: Vec<_> = order.ready().into_iter().map(|chunk| chunk.seq).collect();
+        assert_eq!(seqs, vec![0, 1]);
+        order.add("2".into(), chunk(2));
+        let seqs: Vec<_> = order.ready().into_iter().map(|chunk| chunk.seq).collect();
+        assert_eq!(seqs, vec![2, 3]);
+    }
+
+    #[tokio::test]
+    async fn subscribers_tail_logs() -> Result<()> {
+        let temp_dir = tempfile::tempdir().context("tempdir")?;
+        let nodes = create_nodes(&temp_dir, 1).await?;
+        let (_node, vm) = &nodes[0];
+        let job_id = Uuid::new_v4();
+
+        let logs = subscribe(vm.blobs().clone(), job_id);
+        let (sink, writer) = start(vm.blobs().clone(), job_id);
+        sink.write(LogStream::Stdout, "hello ");
+        sink.write(LogStream::Stdout, "world\n");
+        sink.write(LogStream::Stderr, "oops\n");
+        drop(sink);
+        writer.finish().await;
+
+        let chunks: Vec<_> = logs.collect().await;
+        let seqs: Vec<_> = chunks.iter().map(|chunk| chunk.seq).collect();
+        assert_eq!(seqs, (0..chunks.len() as u64).collect::<Vec<_>>());
+        let output = |stream| {
+            chunks
+                .iter()
+                .filter(|chunk| chunk.stream == stream)
+                .map(|chunk| chunk.data.as_str())
+                .collect::<String>()
+        };
+        assert_eq!(output(LogStream::Stdout), "hello world\n");
+        assert_eq!(output(LogStream::Stderr), "oops\n");
+        assert!(chunks.last().expect("chunks").last);
+        Ok(())
+    }
+}
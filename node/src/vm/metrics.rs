@@ -17,14 +17,23 @@ pub struct Metrics {
     pub scheduler_jobs_assigned: Counter,
     pub scheduler_jobs_completed: Counter,
     pub scheduler_jobs_canceled: Counter,
+    pub scheduler_jobs_deadline_exceeded: Counter,
 
     pub worker_jobs_requested: Counter,
     pub worker_jobs_skipped: Counter,
+    pub worker_jobs_throttled: Counter,
     pub worker_jobs_running: Counter,
     pub worker_jobs_completed: Counter,
+    pub worker_jobs_preempted: Counter,
 
     pub content_routing_blobs_announced: Counter,
     pub content_routing_blobs_fetched: Counter,
+    pub content_routing_fetches_paused: Counter,
+
+    pub events_rejected_id: Counter,
+    pub events_rejected_signature: Counter,
+    pub events_rejected_invariant: Counter,
+    pub events_rejected_schema: Counter,
 }
 
 impl Default for Metrics {
@@ -42,14 +51,23 @@ impl Default for Metrics {
             scheduler_jobs_assigned: Counter::new("Count of jobs assigned by the scheduler"),
             scheduler_jobs_completed: Counter::new("Count of jobs completed by the scheduler"),
             scheduler_jobs_canceled: Counter::new("Count of jobs canceled by the scheduler"),
+            scheduler_jobs_deadline_exceeded: Counter::new("Count of jobs canceled for missing their deadline"),
 
             worker_jobs_requested: Counter::new("Count of jobs requested by the worker"),
             worker_jobs_skipped: Counter::new("Count of jobs skipped by the worker"),
+            worker_jobs_throttled: Counter::new("Count of jobs the worker didn't request, holding too many"),
             worker_jobs_running: Counter::new("Count of jobs ever started by the worker"),
             worker_jobs_completed: Counter::new("Count of jobs completed by the worker"),
+            worker_jobs_preempted: Counter::new("Count of jobs stopped to make room for interactive jobs"),
 
             content_routing_blobs_announced: Counter::new("Count of blobs announced by the content router"),
             content_routing_blobs_fetched: Counter::new("Count of blobs fetched by the content router"),
+            content_routing_fetches_paused: Counter::new("Count of blob fetches skipped because disk space is low"),
+
+            events_rejected_id: Counter::new("Count of events rejected for an id that doesn't match their data"),
+            events_rejected_signature: Counter::new("Count of events rejected for a missing or invalid signature"),
+            events_rejected_invariant: Counter::new("Count of events rejected for missing what their kind requires"),
+            events_rejected_schema: Counter::new("Count of row events rejected for a table that can't be resolved"),
         }
     }
 }
@@ -60,6 +78,13 @@ impl Metric for Metrics {
     }
 }
 
+/// Register our metrics with the global collector, once per process.
+pub(crate) fn try_init_metrics_collection() -> std::io::Result<()> {
+    iroh_metrics::core::Core::try_init(|reg, metrics| {
+        metrics.insert(Metrics::new(reg));
+    })
+}
+
 // pub fn try_init_metrics_collection() -> std::io::Result<()> {
 //     iroh_metrics::core::Core::try_init(|reg, metrics| {
 //         metrics.insert(crate::vm::metrics::Metrics::new(reg));
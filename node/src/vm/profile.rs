@@ -0,0 +1,215 @@
+//! Profiles of jobs run with [`super::job::JobDescription::profile`] set, for finding out why a
+//! program is slow.
+//!
+//! Executors sample while the job runs & fold the samples into stacks, which are rendered as a
+//! flamegraph & uploaded as the job's [`PROFILE_ARTIFACT`], next to its other artifacts:
+//!
+//! - docker jobs sample the container's cgroup cpu time, splitting it between the processes
+//!   running in the container at the time. Stacks are process ancestry, weighed in cpu time.
+//! - wasm jobs time calls into the host. Stacks are the module & the host functions it
+//!   calls, weighed in wall time, with the fuel the module burned in the title.
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use inferno::flamegraph::{self, Options};
+
+use super::blobs::Blobs;
+use super::job::{upload_template, ArtifactRef, JobContext};
+
+/// name of the flamegraph artifact of a profiled job
+pub const PROFILE_ARTIFACT: &str = "profile.svg";
+
+/// Samples folded into stacks.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Profile {
+    /// frames joined with `;`, root first, to their total weight
+    stacks: BTreeMap<String, u64>,
+}
+
+impl Profile {
+    /// Add `weight` to a stack of frames, root first.
+    pub fn add<S: AsRef<str>>(&mut self, frames: &[S], weight: u64) {
+        if frames.is_empty() || weight == 0 {
+            return;
+        }
+        let stack = frames
+            .iter()
+            // `;` separates frames & the last space the weight
+            .map(|frame| frame.as_ref().replace([';', ' '], "_"))
+            .collect::<Vec<_>>()
+            .join(";");
+        *self.stacks.entry(stack).or_default() += weight;
+    }
+
+    pub fn add_duration<S: AsRef<str>>(&mut self, frames: &[S], elapsed: Duration) {
+        self.add(frames, elapsed.as_micros() as u64);
+    }
+
+    /// The summed weight of every stack.
+    pub fn total(&self) -> u64 {
+        self.stacks.values().sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stacks.is_empty()
+    }
+
+    /// The stacks in the folded format flamegraph tools read, one `frame;frame weight` a line.
+    pub fn folded(&self) -> String {
+        self.stacks
+            .iter()
+            .map(|(stack, weight)| format!("{stack} {weight}\n"))
+            .collect()
+    }
+
+    /// Render the profile as an svg flamegraph. `unit` is what the weights count.
+    pub fn flamegraph(&self, title: &str, unit: &str) -> Result<Vec<u8>> {
+        let mut options = Options::default();
+        options.title = title.to_string();
+        options.count_name = unit.to_string();
+        let folded = self.folded();
+        let mut svg = Vec::new();
+        flamegraph::from_lines(&mut options, folded.lines(), &mut svg)
+            .context("rendering flamegraph")?;
+        Ok(svg)
+    }
+
+    /// Upload the profile's flamegraph as an artifact of the job, returning the artifact.
+    /// Empty profiles aren't uploaded.
+    pub(crate) async fn upload(
+        &self,
+        ctx: &JobContext,
+        blobs: &Blobs,
+        title: &str,
+        unit: &str,
+    ) -> Result<Option<ArtifactRef>> {
+        if self.is_empty() {
+            return Ok(None);
+        }
+        let svg = self.flamegraph(title, unit)?;
+        let name = ctx
+            .name_context
+            .render(&upload_template(&ctx.name, PROFILE_ARTIFACT))?;
+        let (hash, size) = blobs.put_bytes(&name, svg).await?;
+        Ok(Some(ArtifactRef { name, hash, size }))
+    }
+}
+
+/// Processes in a container at one point, as `docker top` lists them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ProcessSample {
+    pub(crate) pid: u32,
+    pub(crate) ppid: u32,
+    /// running rather than sleeping or waiting on io
+    pub(crate) running: bool,
+    pub(crate) command: String,
+}
+
+impl ProcessSample {
+    /// The `ps` arguments [`ProcessSample::parse`] reads.
+    pub(crate) const PS_ARGS: &'static str = "-o pid,ppid,stat,comm";
+
+    /// Read the rows of a `docker top` listing made with [`ProcessSample::PS_ARGS`].
+    pub(crate) fn parse(titles: &[String], processes: &[Vec<String>]) -> Vec<ProcessSample> {
+        let column = |name: &str| titles.iter().position(|title| title == name);
+        let (Some(pid), Some(ppid), Some(stat), Some(command)) = (
+            column("PID"),
+            column("PPID"),
+            column("STAT"),
+            column("COMMAND"),
+        ) else {
+            return Vec::new();
+        };
+        processes
+            .iter()
+            .filter_map(|row| {
+                Some(ProcessSample {
+                    pid: row.get(pid)?.parse().ok()?,
+                    ppid: row.get(ppid)?.parse().ok()?,
+                    running: row.get(stat)?.starts_with('R'),
+                    command: row.get(command)?.clone(),
+                })
+            })
+            .collect()
+    }
+}
+
+/// Split `cpu_time` between the processes that were running when it was sampled, or the
+/// oldest process if none were caught running. Each process is a stack of its ancestors'
+/// commands.
+pub(crate) fn add_process_sample(
+    profile: &mut Profile,
+    processes: &[ProcessSample],
+    cpu_time: Duration,
+) {
+    let mut running: Vec<_> = processes.iter().filter(|p| p.running).collect();
+    if running.is_empty() {
+        running.extend(processes.iter().min_by_key(|p| p.pid));
+    }
+    if running.is_empty() {
+        return;
+    }
+    let share = cpu_time / running.len() as u32;
+    for process in running {
+        let mut frames = vec![process.command.as_str()];
+        let mut parent = process.ppid;
+        // bounded, in case pids got reused into a loop between listings
+        while frames.len() < processes.len() {
+            let Some(next) = processes.iter().find(|p| p.pid == parent) else {
+                break;
+            };
+            frames.push(next.command.as_str());
+            parent = next.ppid;
+        }
+        frames.reverse();
+        profile.add_duration(&frames, share);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn process(pid: u32, ppid: u32, running: bool, command: &str) -> ProcessSample {
+        ProcessSample {
+            pid,
+            ppid,
+            running,
+            command: command.to_string(),
+        }
+    }
+
+    #[test]
+    fn folds_process_samples() -> Result<()> {
+        let titles = ["PID", "PPID", "STAT", "COMMAND"].map(String::from);
+        let rows = vec![
+            ["4100", "4000", "Ss", "sh"].map(String::from).to_vec(),
+            ["4101", "4100", "R+", "python"].map(String::from).to_vec(),
+            ["4102", "4100", "S", "sleep"].map(String::from).to_vec(),
+        ];
+        let processes = ProcessSample::parse(&titles, &rows);
+        assert_eq!(processes.len(), 3);
+
+        let mut profile = Profile::default();
+        add_process_sample(&mut profile, &processes, Duration::from_millis(30));
+        add_process_sample(
+            &mut profile,
+            &[process(4100, 4000, false, "sh")],
+            Duration::from_millis(5),
+        );
+        assert_eq!(profile.folded(), "sh 5000\nsh;python 30000\n");
+
+        let svg = profile.flamegraph("job", "microseconds")?;
+        assert!(String::from_utf8(svg)?.contains("python"));
+        Ok(())
+    }
+
+    #[test]
+    fn sanitizes_frames() {
+        let mut profile = Profile::default();
+        profile.add(&["main", "rows query;x"], 3);
+        profile.add(&["main"], 0);
+        assert_eq!(profile.folded(), "main;rows_query_x 3\n");
+    }
+}
@@ -0,0 +1,330 @@
+//! Replaying the workspace doc, to reproduce jobs that got stuck.
+//!
+//! [`VM::replay_events`](super::VM::replay_events) reads doc entries back in the order they
+//! were written & passes each through the rules the scheduler, workers & blobs follow when
+//! handling live events ([`on_worker_status`], [`ExecutionStatus::advance`],
+//! [`assignment_action`]), as a dry run: nothing is written, fetched or executed. Every step is
+//! traced along with what each node would have done, so replaying a copy of the doc from a
+//! "job stuck" report shows where coordination stopped.
+//!
+//! Entries before the replayed range are read to build up state, but not reported. Live
+//! events carry the node that delivered them, replayed ones are attributed to the entry's
+//! author, which is the node that wrote it.
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::RangeBounds;
+
+use anyhow::Result;
+use futures::TryStreamExt;
+use iroh::client::docs::Entry;
+use iroh::docs::store::Query;
+use iroh::docs::AuthorId;
+use iroh::net::NodeId;
+use serde::Serialize;
+use tracing::{info, info_span, warn};
+use uuid::Uuid;
+
+use super::blobs::BlobsEvent;
+use super::doc::{parse_entry, Doc, Event, EventData};
+use super::job::JobStatus;
+use super::scheduler::{on_worker_status, SchedulerEvent};
+use super::worker::{assignment_action, AssignmentAction, ExecutionStatus, WorkerEvent};
+
+/// What a replay went through.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplayReport {
+    /// entries in the range, oldest first
+    pub steps: Vec<ReplayStep>,
+    /// state of every job seen once the range is replayed
+    pub jobs: Vec<ReplayedJob>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplayStep {
+    /// when the entry was written, in microseconds since the unix epoch
+    pub timestamp: u64,
+    pub author: String,
+    pub key: String,
+    /// the event handlers got, `None` for entries they don't follow
+    pub event: Option<String>,
+    /// what the scheduler, workers & blobs would have done
+    pub actions: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplayedJob {
+    pub id: Uuid,
+    /// the job's status as its scheduler wrote it, `None` if no status was seen
+    pub status: Option<String>,
+    /// execution status by worker
+    pub executions: BTreeMap<String, String>,
+}
+
+/// Coordination state built up from the entries replayed so far.
+#[derive(Debug, Default)]
+struct State {
+    /// status of each job & the scheduler that wrote it
+    jobs: BTreeMap<Uuid, (AuthorId, JobStatus)>,
+    executions: BTreeMap<(Uuid, AuthorId), ExecutionStatus>,
+    /// workers that wrote anything, they'd all get job events
+    workers: BTreeSet<AuthorId>,
+    /// jobs seen in any entry
+    seen: BTreeSet<Uuid>,
+}
+
+impl State {
+    fn execution(&self, job_id: Uuid, worker: AuthorId) -> ExecutionStatus {
+        self.executions
+            .get(&(job_id, worker))
+            .copied()
+            .unwrap_or(ExecutionStatus::Unknown)
+    }
+
+    /// Fold an event into the state, returning what handlers would have done with it.
+    fn apply(&mut self, event: &Event) -> Vec<String> {
+        let mut actions = Vec::new();
+        match &event.data {
+            EventData::Worker(WorkerEvent::ExecutionStatusChanged {
+                worker,
+                job_id,
+                status,
+                ..
+            }) => {
+                self.seen.insert(*job_id);
+                self.workers.insert(*worker);
+                let execution = self.execution(*job_id, *worker).advance(*status);
+                self.executions.insert((*job_id, *worker), execution);
+                match self.jobs.get(job_id) {
+                    None => actions.push(format!(
+                        "no scheduler has job {job_id}, {} reporting {status} is ignored",
+                        worker.fmt_short()
+                    )),
+                    Some((scheduler, current)) => {
+                        let scheduler = scheduler.fmt_short();
+                        match on_worker_status(Some(*current), *worker, *status) {
+                            Some(JobStatus::Assigned(worker)) => actions.push(format!(
+                                "scheduler {scheduler} assigns job {job_id} to {}",
+                                worker.fmt_short()
+                            )),
+                            Some(JobStatus::Completed(worker)) => actions.push(format!(
+                                "scheduler {scheduler} marks job {job_id} completed by {}",
+                                worker.fmt_short()
+                            )),
+                            _ => actions.push(format!(
+                                "scheduler {scheduler} ignores it, job {job_id} is {current}"
+                            )),
+                        }
+                    }
+                }
+            }
+            EventData::Scheduler(SchedulerEvent::JobStatusChanged {
+                from,
+                job_id,
+                status,
+                ..
+            }) => {
+                self.seen.insert(*job_id);
+                let current = match self.jobs.get(job_id) {
+                    Some((scheduler, current)) if scheduler == from => {
+                        let mut current = *current;
+                        current.merge(*status);
+                        current
+                    }
+                    _ => *status,
+                };
+                self.jobs.insert(*job_id, (*from, current));
+                actions.push(format!(
+                    "scheduler {} tells subscribers job {job_id} is {status}",
+                    from.fmt_short()
+                ));
+                if *status == JobStatus::Scheduling {
+                    for worker in &self.workers {
+                        let execution = self.execution(*job_id, *worker);
+                        if execution == ExecutionStatus::Unknown {
+                            actions.push(format!(
+                                "worker {} fetches the job & requests it if it can run it",
+                                worker.fmt_short()
+                            ));
+                        }
+                    }
+                    if self.workers.is_empty() {
+                        actions.push("no workers known yet to request it".to_string());
+                    }
+                }
+            }
+            EventData::Scheduler(SchedulerEvent::JobAssigned { job_id, worker, .. }) => {
+                self.seen.insert(*job_id);
+                let mut workers = self.workers.clone();
+                workers.insert(*worker);
+                for candidate in workers {
+                    let execution = self.execution(*job_id, candidate);
+                    let short = candidate.fmt_short();
+                    match assignment_action(candidate == *worker, execution) {
+                        AssignmentAction::Execute => {
+                            actions.push(format!("worker {short} runs job {job_id}"))
+                        }
+                        AssignmentAction::Skip => {
+                            actions.push(format!("worker {short} withdraws its request"))
+                        }
+                        AssignmentAction::Ignore if candidate == *worker => actions.push(format!(
+                            "worker {short} ignores the assignment, its execution is {execution}"
+                        )),
+                        AssignmentAction::Ignore => {}
+                    }
+                }
+            }
+            EventData::Blobs(BlobsEvent::ObjectPut { name }) => {
+                actions.push(format!("blobs tell subscribers {name} was written"))
+            }
+            EventData::Blobs(BlobsEvent::ObjectDeleted { name }) => {
+                actions.push(format!("blobs drop {name}"))
+            }
+            EventData::ContentRouting(event) => {
+                actions.push(format!("content router records {event:?}"))
+            }
+        }
+        actions
+    }
+
+    fn jobs(&self) -> Vec<ReplayedJob> {
+        self.seen
+            .iter()
+            .map(|id| ReplayedJob {
+                id: *id,
+                status: self.jobs.get(id).map(|(_, status)| status.to_string()),
+                executions: self
+                    .executions
+                    .iter()
+                    .filter(|((job_id, _), _)| job_id == id)
+                    .map(|((_, worker), status)| (worker.to_string(), status.to_string()))
+                    .collect(),
+            })
+            .collect()
+    }
+}
+
+/// Replay the entries of `doc` written within `range`, in microseconds since the unix epoch.
+pub(super) async fn replay(doc: &Doc, range: impl RangeBounds<u64>) -> Result<ReplayReport> {
+    let mut entries: Vec<Entry> = doc
+        .get_many(Query::all().include_empty())
+        .await?
+        .try_collect()
+        .await?;
+    entries.sort_by(|a, b| (a.timestamp(), a.key()).cmp(&(b.timestamp(), b.key())));
+
+    let _span = info_span!("replay", doc = %doc.id().fmt_short()).entered();
+    let mut state = State::default();
+    let mut steps = Vec::new();
+    for entry in entries {
+        let timestamp = entry.timestamp();
+        let key = String::from_utf8_lossy(entry.key()).to_string();
+        let event = match NodeId::from_bytes(entry.author().as_bytes()) {
+            Ok(from) => parse_entry(&entry, from),
+            Err(err) => {
+                warn!("entry {} has an author that isn't a node: {:?}", key, err);
+                None
+            }
+        };
+        let actions = event
+            .as_ref()
+            .map(|event| state.apply(event))
+            .unwrap_or_default();
+        if !range.contains(&timestamp) {
+            continue;
+        }
+        info!(
+            "{} {} {}: {:?}",
+            timestamp,
+            entry.author().fmt_short(),
+            key,
+            event.as_ref().map(|event| &event.data)
+        );
+        for action in &actions {
+            info!("  {}", action);
+        }
+        steps.push(ReplayStep {
+            timestamp,
+            author: entry.author().to_string(),
+            key,
+            event: event.map(|event| format!("{:?}", event.data)),
+            actions,
+        });
+    }
+    Ok(ReplayReport {
+        steps,
+        jobs: state.jobs(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use iroh::blobs::Hash;
+
+    use super::*;
+    use crate::vm::doc::create_doc;
+    use crate::vm::scheduler::{job_assignment_key, job_status_key};
+    use crate::vm::worker::Worker;
+
+    #[tokio::test]
+    async fn replay_stuck_job() -> Result<()> {
+        let node = iroh::node::Node::memory().spawn().await?;
+        let doc = create_doc(node.client()).await?;
+        let scheduler = node.authors().create().await?;
+        let worker = node.authors().create().await?;
+        let late = node.authors().create().await?;
+        let job_id = Uuid::new_v4();
+        let job = Hash::new("job");
+
+        let write = |author: AuthorId, key: String| {
+            let doc = doc.clone();
+            async move {
+                // keep timestamps apart, entries are replayed in timestamp order
+                tokio::time::sleep(std::time::Duration::from_millis(2)).await;
+                doc.set_hash(author, key, job, 3).await
+            }
+        };
+        let requested = Worker::execution_status_key(job_id, ExecutionStatus::Requested);
+        write(scheduler, job_status_key(job_id, JobStatus::Scheduling)).await?;
+        write(worker, requested.clone()).await?;
+        write(late, requested).await?;
+        write(scheduler, job_assignment_key(job_id, worker)).await?;
+        // the worker never reports running
+        write(
+            scheduler,
+            job_status_key(job_id, JobStatus::Assigned(worker)),
+        )
+        .await?;
+
+        let report = replay(&doc, ..).await?;
+        assert_eq!(report.steps.len(), 5);
+        assert!(report.steps[1].actions[0].contains("assigns job"));
+        let assigned = &report.steps[3].actions;
+        assert!(assigned.iter().any(|a| a.contains("runs job")));
+        assert!(assigned.iter().any(|a| a.contains("withdraws")));
+        assert_eq!(report.jobs.len(), 1);
+        assert_eq!(
+            report.jobs[0].status,
+            Some(JobStatus::Assigned(worker).to_string())
+        );
+        assert_eq!(
+            report.jobs[0].executions[&worker.to_string()],
+            ExecutionStatus::Requested.to_string()
+        );
+
+        // a dry run: nothing was written
+        let entries: Vec<Entry> = doc.get_many(Query::all()).await?.try_collect().await?;
+        assert_eq!(entries.len(), 5);
+
+        // entries before the range still count toward state
+        let from = report.steps[3].timestamp;
+        let report = replay(&doc, from..).await?;
+        assert_eq!(report.steps.len(), 2);
+        assert!(report.steps[0]
+            .actions
+            .iter()
+            .any(|a| a.contains("runs job")));
+        Ok(())
+    }
+}
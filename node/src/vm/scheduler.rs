@@ -1,13 +1,16 @@
 use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use anyhow::{bail, Context, Result};
 use bytes::Bytes;
-use futures::StreamExt;
 use iroh::blobs::Hash;
 use iroh::client::docs::Entry;
 use iroh::docs::AuthorId;
 use iroh::net::NodeId;
-use tracing::{debug, info, trace};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use tracing::{debug, info, trace, warn};
 use uuid::Uuid;
 
 use crate::router::RouterClient;
@@ -19,16 +22,76 @@ use super::job::{
 };
 use super::metrics::Metrics;
 use super::node_author_id;
-use super::worker::{ExecutionStatus, WorkerEvent};
+use super::store::{JobStore, NodeStore, Store};
+use super::worker::{capabilities, ExecutionStatus, WorkerEvent};
+
+pub mod recovery;
+pub mod simulate;
+
+use self::simulate::{SimulatedFlow, SimulationReport, WorkerProfile};
+
+/// A job run to completion by [`Scheduler::run_job_timed`].
+#[derive(Clone, Debug)]
+pub struct JobRun {
+    pub result: JobResult,
+    /// from scheduling the job to a worker picking it up, if one did
+    pub queued: Option<Duration>,
+}
 
 #[derive(Clone, Debug)]
 pub struct Scheduler {
     author_id: AuthorId, // author_id must be matched to the node_id doing the scheduling
-    blobs: Blobs,
-    node: RouterClient,
-    doc: Doc,
+    store: Store,
     job_subscriptions: async_broadcast::Sender<(Uuid, JobStatus)>,
     job_r: async_broadcast::InactiveReceiver<(Uuid, JobStatus)>,
+    deadline_subscriptions: async_broadcast::Sender<DeadlineExceeded>,
+    deadline_r: async_broadcast::InactiveReceiver<DeadlineExceeded>,
+    sla: Arc<Mutex<SlaStats>>,
+}
+
+/// A job the scheduler gave up on because it wasn't done by its deadline.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeadlineExceeded {
+    pub job_id: Uuid,
+    pub name: String,
+    pub space: String,
+    pub deadline: OffsetDateTime,
+    /// the worker the job was assigned to, `None` if no worker picked it up in time
+    pub worker: Option<AuthorId>,
+}
+
+/// How jobs scheduled with a deadline fared in this workspace since the node started.
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+#[serde(rename_all = "camelCase")]
+pub struct SlaStats {
+    /// completed before their deadline
+    pub met: u64,
+    /// no worker was assigned before the deadline
+    pub exceeded_unassigned: u64,
+    /// assigned, but not completed before the deadline
+    pub exceeded_assigned: u64,
+}
+
+/// What the scheduler had in flight at some point, see [`Scheduler::snapshot`].
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+#[serde(rename_all = "camelCase")]
+pub struct SchedulerSnapshot {
+    /// unix timestamp, in seconds
+    pub taken_at: i64,
+    pub sla: SlaStats,
+    /// jobs scheduled from this node that hadn't completed or been canceled
+    pub unfinished: Vec<UnfinishedJob>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "specta", derive(specta::Type))]
+#[serde(rename_all = "camelCase")]
+pub struct UnfinishedJob {
+    pub id: Uuid,
+    /// `scheduling` or `assigned-<worker>`
+    pub status: String,
 }
 
 type ScheduledJobRef = (Hash, u64);
@@ -40,18 +103,28 @@ impl Scheduler {
         blobs: Blobs,
         node: RouterClient,
     ) -> Result<Self> {
+        let store = Store::Node(NodeStore::new(doc, blobs, node));
+        Ok(Self::with_store(author_id, store))
+    }
+
+    /// A scheduler keeping jobs in `store`.
+    pub(crate) fn with_store(author_id: AuthorId, store: Store) -> Self {
         let (mut s, r) = async_broadcast::broadcast(128);
         s.set_await_active(false);
+        // slow listeners miss notifications instead of holding up the scheduler
+        let (mut ds, dr) = async_broadcast::broadcast(128);
+        ds.set_await_active(false);
+        ds.set_overflow(true);
 
-        let s = Self {
+        Self {
             author_id,
-            doc,
-            node,
-            blobs,
+            store,
             job_subscriptions: s,
             job_r: r.deactivate(),
-        };
-        Ok(s)
+            deadline_subscriptions: ds,
+            deadline_r: dr.deactivate(),
+            sla: Default::default(),
+        }
     }
 
     pub async fn run_job(
@@ -66,6 +139,7 @@ impl Scheduler {
         );
 
         let author = AuthorId::from_str(&job_description.author.as_str())?;
+        let deadline = job_description.deadline;
 
         let scheduled_job = ScheduledJob {
             author,
@@ -74,10 +148,23 @@ impl Scheduler {
             result: JobResult::default(),
         };
 
+        // subscribe before writing the job, so the deadline watch sees every status change
+        let deadline_watch =
+            deadline.map(|deadline| (deadline, self.subscribe_job_status_change()));
+
         // phase 1 of 2 phase commit: write the job to the doc
         self.set_job_state(id, JobStatus::Scheduling, &scheduled_job)
             .await?;
 
+        if let Some((deadline, recv)) = deadline_watch {
+            let this = self.clone();
+            tokio::task::spawn(async move {
+                if let Err(err) = this.enforce_deadline(id, deadline, recv).await {
+                    warn!("failed to enforce deadline of job {}: {:?}", id, err);
+                }
+            });
+        }
+
         Ok(id)
     }
 
@@ -87,11 +174,25 @@ impl Scheduler {
         job_id: Uuid,
         job_description: JobDescription,
     ) -> Result<JobResult> {
+        let run = self.run_job_timed(scope, job_id, job_description).await?;
+        Ok(run.result)
+    }
+
+    /// Like [`Scheduler::run_job_and_wait`], also measuring how long the job waited for a
+    /// worker.
+    pub async fn run_job_timed(
+        &self,
+        scope: Uuid,
+        job_id: Uuid,
+        job_description: JobDescription,
+    ) -> Result<JobRun> {
         // subscribe before running, to make sure not events are missed
         let mut recv = self.subscribe_job_status_change();
         self.run_job(scope, job_id, job_description).await?;
+        let scheduled = Instant::now();
 
         let mut worker_id = None;
+        let mut queued = None;
         loop {
             let msg = recv.recv_direct().await;
             match msg {
@@ -105,12 +206,20 @@ impl Scheduler {
                             JobStatus::Scheduling => {}
                             JobStatus::Assigned(id) => {
                                 worker_id.replace(id);
+                                queued.get_or_insert_with(|| scheduled.elapsed());
                             }
                             JobStatus::Canceled(id) => {
-                                return Ok(JobResult {
+                                if let Some((_, result)) = self.get_job_result(job_id).await? {
+                                    if result.status == JobResultStatus::ErrDeadlineExceeded {
+                                        return Ok(JobRun { result, queued });
+                                    }
+                                }
+                                let result = JobResult {
                                     worker: worker_id,
                                     status: JobResultStatus::Err(format!("canceled: {:?}", id)),
-                                });
+                                    usage: None,
+                                };
+                                return Ok(JobRun { result, queued });
                             }
                             JobStatus::Completed(id) => {
                                 if let Some(worker_id) = worker_id {
@@ -130,7 +239,7 @@ impl Scheduler {
             Some((JobStatus::Completed(id), result)) => {
                 if id == worker_id {
                     iroh_metrics::inc!(Metrics, task_run_completed);
-                    return Ok(result);
+                    return Ok(JobRun { result, queued });
                 }
                 bail!(
                     "unexpected worker completed the job {}: {} != {}",
@@ -172,10 +281,113 @@ impl Scheduler {
         Ok(())
     }
 
+    /// Cancel the job with [`JobResultStatus::ErrDeadlineExceeded`] if it isn't completed by
+    /// `deadline`, notifying [`Scheduler::subscribe_deadlines_exceeded`].
+    async fn enforce_deadline(
+        &self,
+        job_id: Uuid,
+        deadline: OffsetDateTime,
+        mut recv: async_broadcast::Receiver<(Uuid, JobStatus)>,
+    ) -> Result<()> {
+        // a deadline in the past is enforced right away
+        let remaining = (deadline - OffsetDateTime::now_utc())
+            .try_into()
+            .unwrap_or_default();
+        let sleep = tokio::time::sleep(remaining);
+        tokio::pin!(sleep);
+        loop {
+            tokio::select! {
+                _ = &mut sleep => break,
+                msg = recv.recv_direct() => match msg {
+                    Ok((id, status)) if id == job_id && on_deadline(status).is_none() => break,
+                    Ok(_) | Err(async_broadcast::RecvError::Overflowed(_)) => {}
+                    Err(async_broadcast::RecvError::Closed) => return Ok(()),
+                },
+            }
+        }
+
+        // read what's in the doc, status changes may have been missed
+        let Some((status, mut job)) = self.get_job(job_id).await? else {
+            bail!("unknown job {}", job_id);
+        };
+        let Some(canceled) = on_deadline(status) else {
+            if let JobStatus::Completed(_) = status {
+                self.sla.lock().unwrap().met += 1;
+            }
+            return Ok(());
+        };
+        if OffsetDateTime::now_utc() < deadline {
+            // only done waiting because the job ended, which it hasn't yet
+            bail!("job {} is still {}", job_id, status);
+        }
+
+        let worker = match canceled {
+            JobStatus::Canceled(worker) => worker,
+            _ => None,
+        };
+        warn!("job {} missed its deadline of {}", job_id, deadline);
+        job.result = JobResult {
+            worker,
+            status: JobResultStatus::ErrDeadlineExceeded,
+            usage: None,
+        };
+        self.set_job_state(job_id, canceled, &job).await?;
+        iroh_metrics::inc!(Metrics, scheduler_jobs_deadline_exceeded);
+        {
+            let mut sla = self.sla.lock().unwrap();
+            match worker {
+                Some(_) => sla.exceeded_assigned += 1,
+                None => sla.exceeded_unassigned += 1,
+            }
+        }
+
+        self.deadline_subscriptions
+            .broadcast_direct(DeadlineExceeded {
+                job_id,
+                name: job.description.name,
+                space: job.description.space,
+                deadline,
+                worker,
+            })
+            .await?;
+        Ok(())
+    }
+
+    /// Get notified about jobs canceled for missing their deadline. Notifications are dropped
+    /// for receivers that fall behind.
+    pub fn subscribe_deadlines_exceeded(&self) -> async_broadcast::Receiver<DeadlineExceeded> {
+        self.deadline_r.activate_cloned()
+    }
+
+    /// Deadline stats of the jobs scheduled from this node.
+    pub fn sla_stats(&self) -> SlaStats {
+        *self.sla.lock().unwrap()
+    }
+
+    /// Record the jobs this node scheduled that are still in flight, along with the deadline
+    /// stats, which only live in memory.
+    pub async fn snapshot(&self) -> Result<SchedulerSnapshot> {
+        let mut unfinished = self
+            .unfinished_jobs()
+            .await?
+            .into_iter()
+            .map(|(id, state)| UnfinishedJob {
+                id,
+                status: state.status.to_string(),
+            })
+            .collect::<Vec<_>>();
+        unfinished.sort_by_key(|job| job.id);
+        Ok(SchedulerSnapshot {
+            taken_at: OffsetDateTime::now_utc().unix_timestamp(),
+            sla: self.sla_stats(),
+            unfinished,
+        })
+    }
+
     async fn set_job_state(&self, id: Uuid, status: JobStatus, job: &ScheduledJob) -> Result<()> {
         let data = job.to_bytes()?;
         let key = format!("{}/{}.json", JOBS_PREFIX, id.as_u128());
-        let (hash, size) = self.blobs.put_bytes(key.as_str(), data).await?;
+        let (hash, size) = self.store.put_bytes(key.as_str(), data).await?;
 
         self.set_job_state_ref(id, status, (hash, size)).await?;
         Ok(())
@@ -214,6 +426,19 @@ impl Scheduler {
         worker_id: AuthorId,
         job_ref: ScheduledJobRef,
     ) -> Result<()> {
+        let job = self.get_scheduled_job(job_ref.0).await?;
+        let requires = &job.description.requires;
+        if !requires.is_empty() {
+            let capabilities = capabilities::read(&self.store, worker_id).await?;
+            if !capabilities.is_some_and(|c| c.satisfies(requires)) {
+                // leave the job to a worker that can run it
+                debug!(
+                    "worker {} doesn't meet the requirements of job {}: {:?}",
+                    worker_id, job_id, requires
+                );
+                return Ok(());
+            }
+        }
         info!("assigning job {} to {}", job_id, worker_id);
         let key = job_assignment_key(job_id, worker_id);
         // write the key that awards the job to worker_id
@@ -256,12 +481,10 @@ impl Scheduler {
     pub async fn get_job(&self, job_id: Uuid) -> Result<Option<(JobStatus, ScheduledJob)>> {
         let job_id = job_id.as_u128();
         let mut status: Option<(JobStatus, Hash)> = None;
-        let q = iroh::docs::store::Query::author(self.author_id)
-            .key_prefix(format!("{}/status/{}/", JOBS_PREFIX, job_id));
-        let mut entries = self.doc.get_many(q).await?;
+        let prefix = format!("{}/status/{}/", JOBS_PREFIX, job_id);
+        let entries = self.store.entries(Some(self.author_id), &prefix).await?;
 
-        while let Some(entry) = entries.next().await {
-            let entry = entry?;
+        for entry in entries {
             let key = std::str::from_utf8(entry.key())?;
 
             trace!("checking status: {}", key);
@@ -289,8 +512,7 @@ impl Scheduler {
     }
 
     async fn get_scheduled_job(&self, job_hash: Hash) -> Result<ScheduledJob> {
-        self.blobs.fetch_blob(job_hash).await?;
-        let data = self.node.blobs().read_to_bytes(job_hash).await?;
+        let data = self.store.read_blob(job_hash).await?;
         let jd = ScheduledJob::try_from(data)?;
         Ok(jd)
     }
@@ -306,16 +528,13 @@ impl Scheduler {
         status: ExecutionStatus,
         job_ref: ScheduledJobRef,
     ) -> Result<()> {
-        match self.get_job_status(job_id).await? {
-            Some(JobStatus::Scheduling) => {
-                if status == ExecutionStatus::Requested {
-                    self.assign_job(job_id, worker, job_ref).await?;
-                }
+        let current = self.get_job_status(job_id).await?;
+        match on_worker_status(current, worker, status) {
+            Some(JobStatus::Assigned(worker)) => {
+                self.assign_job(job_id, worker, job_ref).await?;
             }
-            Some(JobStatus::Assigned(worker_id)) => {
-                if status == ExecutionStatus::Completed && worker == worker_id {
-                    self.mark_job_completed(job_id, worker, job_ref).await?;
-                }
+            Some(JobStatus::Completed(worker)) => {
+                self.mark_job_completed(job_id, worker, job_ref).await?;
             }
             _ => {}
         };
@@ -323,10 +542,20 @@ impl Scheduler {
         Ok(())
     }
 
+    /// Estimate how `flows` would run on a fleet of `workers` without running anything, for
+    /// sizing a fleet before an expensive pipeline. See [`simulate`] for what's modeled.
+    pub fn simulate(
+        &self,
+        flows: &[SimulatedFlow],
+        workers: &[WorkerProfile],
+    ) -> Result<SimulationReport> {
+        simulate::simulate(flows, workers)
+    }
+
     /// Returns `true` if an actual update has occured.
     async fn set_hash_iff_new(&self, key: impl Into<Bytes>, hash: Hash, size: u64) -> Result<bool> {
         let key: Bytes = key.into();
-        match self.doc.get_exact(self.author_id, &key, true).await? {
+        match self.store.get_exact(self.author_id, &key, true).await? {
             Some(entry) => {
                 if entry.content_hash() == hash {
                     return Ok(false);
@@ -336,16 +565,41 @@ impl Scheduler {
                 // No entry, lets set it
             }
         }
-        self.doc.set_hash(self.author_id, key, hash, size).await?;
+        self.store.set_hash(self.author_id, key, hash, size).await?;
         Ok(true)
     }
 }
 
-fn job_status_key(id: Uuid, status: JobStatus) -> String {
+/// The status a job moves to when `worker` reports `status`, if any. The first worker to
+/// request a job gets it, only the assigned worker can complete it.
+pub(super) fn on_worker_status(
+    current: Option<JobStatus>,
+    worker: AuthorId,
+    status: ExecutionStatus,
+) -> Option<JobStatus> {
+    match (current?, status) {
+        (JobStatus::Scheduling, ExecutionStatus::Requested) => Some(JobStatus::Assigned(worker)),
+        (JobStatus::Assigned(assigned), ExecutionStatus::Completed) if assigned == worker => {
+            Some(JobStatus::Completed(worker))
+        }
+        _ => None,
+    }
+}
+
+/// The status a job still running at its deadline moves to. `None` if the job already ended.
+pub(super) fn on_deadline(current: JobStatus) -> Option<JobStatus> {
+    match current {
+        JobStatus::Scheduling => Some(JobStatus::Canceled(None)),
+        JobStatus::Assigned(worker) => Some(JobStatus::Canceled(Some(worker))),
+        JobStatus::Completed(_) | JobStatus::Canceled(_) => None,
+    }
+}
+
+pub(super) fn job_status_key(id: Uuid, status: JobStatus) -> String {
     format!("{}/status/{}/{}", JOBS_PREFIX, id.as_u128(), status)
 }
 
-fn job_assignment_key(id: Uuid, author_id: AuthorId) -> String {
+pub(super) fn job_assignment_key(id: Uuid, author_id: AuthorId) -> String {
     format!("{}/assign/{}/{}", JOBS_PREFIX, id.as_u128(), author_id)
 }
 
@@ -485,6 +739,21 @@ mod tests {
     use crate::vm::job::{Artifact, Artifacts, JobDetails, JobOutput, DEFAULT_TIMEOUT};
     use crate::vm::test_utils::{create_nodes, setup_logging};
 
+    #[test]
+    fn deadlines_cancel_unfinished_jobs() {
+        let worker = iroh::docs::Author::new(&mut rand::thread_rng()).id();
+        assert_eq!(
+            on_deadline(JobStatus::Scheduling),
+            Some(JobStatus::Canceled(None))
+        );
+        assert_eq!(
+            on_deadline(JobStatus::Assigned(worker)),
+            Some(JobStatus::Canceled(Some(worker)))
+        );
+        assert_eq!(on_deadline(JobStatus::Completed(worker)), None);
+        assert_eq!(on_deadline(JobStatus::Canceled(None)), None);
+    }
+
     #[tokio::test]
     async fn test_work_schedule_assign() -> Result<()> {
         setup_logging();
@@ -0,0 +1,291 @@
+//! Picking up where the scheduler left off when the node restarts.
+//!
+//! Waiting on jobs & enforcing their deadlines only happens in memory, and worker events that
+//! arrive while the node is down aren't handled by anyone. [`Scheduler::recover`] runs when a
+//! workspace opens: it goes over the jobs this node scheduled that never finished, catches up
+//! on what their workers wrote in the meantime, publishes `scheduling` again for jobs still
+//! waiting on a worker, re-arms deadlines & gives up on assigned jobs whose worker doesn't
+//! report back within the job's timeout.
+use std::collections::hash_map;
+use std::collections::{HashMap, HashSet};
+
+use anyhow::{bail, Result};
+use iroh::docs::AuthorId;
+use time::OffsetDateTime;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use super::{job_status_key, parse_status, ScheduledJobRef, Scheduler};
+use crate::vm::job::{JobResult, JobResultStatus, JobStatus, JOBS_PREFIX};
+use crate::vm::store::JobStore;
+use crate::vm::worker::{event_components, WORKER_PREFIX};
+
+/// how long past a job's timeout its assigned worker has to report the job completed
+pub const ASSIGNMENT_GRACE: time::Duration = time::Duration::minutes(1);
+
+/// What [`Scheduler::recover`] found to do.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RecoveryReport {
+    /// jobs workers requested or completed while nobody was listening
+    pub caught_up: usize,
+    /// jobs published as `scheduling` again, for workers that missed them
+    pub rescheduled: usize,
+    /// assigned jobs watched for their worker to finish
+    pub watched: usize,
+    /// jobs whose deadline is enforced again
+    pub deadlines: usize,
+}
+
+/// The current status of a job this node scheduled.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct JobState {
+    pub(super) status: JobStatus,
+    pub(super) job_ref: ScheduledJobRef,
+    /// when the status was written, in microseconds since the unix epoch
+    pub(super) since: u64,
+}
+
+impl Scheduler {
+    /// Resume the jobs this node scheduled before it stopped, see the module docs.
+    pub async fn recover(&self) -> Result<RecoveryReport> {
+        let mut report = RecoveryReport::default();
+        for job_id in self.unfinished_jobs().await?.into_keys() {
+            if self.catch_up(job_id).await? {
+                report.caught_up += 1;
+            }
+        }
+
+        for (job_id, state) in self.unfinished_jobs().await? {
+            let job = self.get_scheduled_job(state.job_ref.0).await?;
+            // subscribe before publishing, so the watches see every status change
+            let recv = self.subscribe_job_status_change();
+            match state.status {
+                JobStatus::Scheduling => {
+                    self.republish(job_id, state.job_ref).await?;
+                    report.rescheduled += 1;
+                }
+                JobStatus::Assigned(worker) => {
+                    let assigned_at =
+                        OffsetDateTime::from_unix_timestamp_nanos(state.since as i128 * 1000)?;
+                    let expires = assigned_at + job.description.timeout + ASSIGNMENT_GRACE;
+                    let this = self.clone();
+                    let recv = recv.clone();
+                    tokio::task::spawn(async move {
+                        if let Err(err) =
+                            this.expire_assignment(job_id, worker, expires, recv).await
+                        {
+                            warn!("failed to expire assignment of job {}: {:?}", job_id, err);
+                        }
+                    });
+                    report.watched += 1;
+                }
+                JobStatus::Completed(_) | JobStatus::Canceled(_) => continue,
+            }
+            if let Some(deadline) = job.description.deadline {
+                let this = self.clone();
+                tokio::task::spawn(async move {
+                    if let Err(err) = this.enforce_deadline(job_id, deadline, recv).await {
+                        warn!("failed to enforce deadline of job {}: {:?}", job_id, err);
+                    }
+                });
+                report.deadlines += 1;
+            }
+        }
+
+        if report != RecoveryReport::default() {
+            info!("recovered scheduler: {:?}", report);
+        }
+        Ok(report)
+    }
+
+    /// Jobs this node scheduled that haven't completed or been canceled.
+    pub(super) async fn unfinished_jobs(&self) -> Result<HashMap<Uuid, JobState>> {
+        let prefix = format!("{}/status/", JOBS_PREFIX);
+        let entries = self.store.entries(Some(self.author_id), &prefix).await?;
+        let mut jobs: HashMap<Uuid, JobState> = HashMap::new();
+        let mut finished = HashSet::new();
+        for entry in entries {
+            let (id, status) = parse_status(std::str::from_utf8(entry.key())?)?;
+            if matches!(status, JobStatus::Completed(_) | JobStatus::Canceled(_)) {
+                finished.insert(id);
+                continue;
+            }
+            let state = JobState {
+                status,
+                job_ref: (entry.content_hash(), entry.content_len()),
+                since: entry.timestamp(),
+            };
+            match jobs.entry(id) {
+                hash_map::Entry::Occupied(mut current) => {
+                    if current.get_mut().status.merge(status) {
+                        current.insert(state);
+                    }
+                }
+                hash_map::Entry::Vacant(vacant) => {
+                    vacant.insert(state);
+                }
+            }
+        }
+        jobs.retain(|id, _| !finished.contains(id));
+        Ok(jobs)
+    }
+
+    /// Handle the execution statuses workers wrote for a job, oldest first. Returns whether
+    /// that moved the job along.
+    async fn catch_up(&self, job_id: Uuid) -> Result<bool> {
+        let before = self.get_job_status(job_id).await?;
+        let prefix = format!("{}/status/{}/", WORKER_PREFIX, job_id.as_u128());
+        let mut entries = self.store.entries(None, &prefix).await?;
+        entries.sort_by_key(|entry| entry.timestamp());
+        for entry in entries {
+            let (_, status) = event_components(std::str::from_utf8(entry.key())?)?;
+            self.handle_worker_execution_status_change(
+                job_id,
+                entry.author(),
+                status,
+                (entry.content_hash(), entry.content_len()),
+            )
+            .await?;
+        }
+        Ok(self.get_job_status(job_id).await? != before)
+    }
+
+    /// Write the `scheduling` status of a job again, so workers that missed it see it.
+    async fn republish(&self, job_id: Uuid, (hash, size): ScheduledJobRef) -> Result<()> {
+        let key = job_status_key(job_id, JobStatus::Scheduling);
+        self.store
+            .set_hash(self.author_id, key.into(), hash, size)
+            .await?;
+        Ok(())
+    }
+
+    /// Cancel a job with [`JobResultStatus::ErrTimeout`] if `worker` hasn't completed it by
+    /// `expires`.
+    async fn expire_assignment(
+        &self,
+        job_id: Uuid,
+        worker: AuthorId,
+        expires: OffsetDateTime,
+        mut recv: async_broadcast::Receiver<(Uuid, JobStatus)>,
+    ) -> Result<()> {
+        let remaining = (expires - OffsetDateTime::now_utc())
+            .try_into()
+            .unwrap_or_default();
+        let sleep = tokio::time::sleep(remaining);
+        tokio::pin!(sleep);
+        loop {
+            tokio::select! {
+                _ = &mut sleep => break,
+                msg = recv.recv_direct() => match msg {
+                    Ok((id, JobStatus::Completed(_) | JobStatus::Canceled(_))) if id == job_id => break,
+                    Ok(_) | Err(async_broadcast::RecvError::Overflowed(_)) => {}
+                    Err(async_broadcast::RecvError::Closed) => return Ok(()),
+                },
+            }
+        }
+
+        let Some((status, mut job)) = self.get_job(job_id).await? else {
+            bail!("unknown job {}", job_id);
+        };
+        if status != JobStatus::Assigned(worker) {
+            return Ok(());
+        }
+        if OffsetDateTime::now_utc() < expires {
+            bail!("job {} is still {}", job_id, status);
+        }
+
+        warn!(
+            "worker {} never finished job {}, giving up on it",
+            worker.fmt_short(),
+            job_id
+        );
+        job.result = JobResult {
+            worker: Some(worker),
+            status: JobResultStatus::ErrTimeout,
+            usage: None,
+        };
+        self.set_job_state(job_id, JobStatus::Canceled(Some(worker)), &job)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Context;
+
+    use super::*;
+    use crate::vm::job::{JobDescription, JobDetails, DEFAULT_TIMEOUT};
+    use crate::vm::test_utils::{create_nodes, setup_logging};
+    use crate::vm::worker::{ExecutionStatus, Worker};
+
+    #[tokio::test]
+    async fn recovers_unfinished_jobs() -> Result<()> {
+        setup_logging();
+
+        let dir = tempfile::tempdir().context("tempdir")?;
+        let nodes = create_nodes(&dir, 1).await?;
+        let (node, ws) = &nodes[0];
+        ws.worker().disable();
+        let scheduler = ws.scheduler();
+
+        let job_id = Uuid::new_v4();
+        scheduler
+            .run_job(
+                Uuid::new_v4(),
+                job_id,
+                JobDescription {
+                    name: "orphan".into(),
+                    details: JobDetails::Wasm {
+                        module: "min.wat".into(),
+                    },
+                    artifacts: Default::default(),
+                    timeout: DEFAULT_TIMEOUT,
+                },
+            )
+            .await?;
+
+        // nobody picked it up: published again
+        let report = scheduler.recover().await?;
+        assert_eq!(report.rescheduled, 1);
+        assert_eq!(
+            scheduler.get_job_status(job_id).await?,
+            Some(JobStatus::Scheduling)
+        );
+
+        // a worker asked for it while the scheduler wasn't listening
+        let worker = node.authors().create().await?;
+        let state = scheduler.unfinished_jobs().await?[&job_id];
+        let (hash, size) = state.job_ref;
+        scheduler
+            .doc
+            .set_hash(
+                worker,
+                Worker::execution_status_key(job_id, ExecutionStatus::Requested),
+                hash,
+                size,
+            )
+            .await?;
+        let report = scheduler.recover().await?;
+        assert_eq!(report.rescheduled, 0);
+        assert_eq!(report.watched, 1);
+        assert_eq!(
+            scheduler.get_job_status(job_id).await?,
+            Some(JobStatus::Assigned(worker))
+        );
+
+        // the worker never reports back
+        scheduler
+            .expire_assignment(
+                job_id,
+                worker,
+                OffsetDateTime::now_utc(),
+                scheduler.subscribe_job_status_change(),
+            )
+            .await?;
+        let (status, result) = scheduler.get_job_result(job_id).await?.expect("job");
+        assert_eq!(status, JobStatus::Canceled(Some(worker)));
+        assert_eq!(result.status, JobResultStatus::ErrTimeout);
+        assert!(scheduler.unfinished_jobs().await?.is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,603 @@
+//! Capacity planning: how a set of flows would schedule on a fleet of workers, without running
+//! anything.
+//!
+//! Flows are modeled the way [`Flow::run`] runs them: top-level tasks in order, up to the
+//! flow's `max_concurrency` at once, each task's nested tasks alongside it, & every job waiting
+//! for the artifacts it downloads. A job
+//! is ready once its artifacts exist, ready jobs go to workers in the order a worker's slots
+//! take them (interactive before batch, then first come first served), & run for their
+//! estimated duration scaled by the worker's speed. Preemption, transfer times & replication
+//! lag aren't modeled, so results are a lower bound on what a real run takes.
+use std::collections::{HashMap, HashSet};
+
+use anyhow::{ensure, Result};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::vm::flow::{Flow, Task};
+use crate::vm::job::{upload_template, JobNameContext, JobPriority, JobType};
+
+/// A flow to simulate, with how long its jobs are expected to run.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SimulatedFlow {
+    pub flow: Flow,
+    /// expected run time of jobs, by job name. Jobs without an estimate are assumed to run
+    /// until their timeout
+    #[serde(default)]
+    pub estimates: Vec<TaskEstimate>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TaskEstimate {
+    pub name: String,
+    #[serde(with = "crate::vm::duration")]
+    pub duration: time::Duration,
+}
+
+/// A synthetic worker.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkerProfile {
+    pub name: String,
+    /// jobs the worker runs at once
+    #[serde(default = "default_slots")]
+    pub slots: usize,
+    /// job types the worker runs, all of them when empty
+    #[serde(default)]
+    pub job_types: Vec<JobType>,
+    /// how fast the worker runs jobs compared to the estimates, 2.0 halves run times
+    #[serde(default = "default_speed")]
+    pub speed: f64,
+}
+
+fn default_slots() -> usize {
+    4
+}
+
+fn default_speed() -> f64 {
+    1.0
+}
+
+impl WorkerProfile {
+    fn runs(&self, job_type: JobType) -> bool {
+        self.job_types.is_empty() || self.job_types.contains(&job_type)
+    }
+}
+
+/// Times are in seconds from the start of the simulation.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SimulationReport {
+    /// when the last job finished
+    pub makespan: f64,
+    pub flows: Vec<FlowSimulation>,
+    /// every job that ran, in the order they started
+    pub tasks: Vec<TaskSimulation>,
+    pub workers: Vec<WorkerSimulation>,
+    /// the chain of jobs that decided the makespan, first to last. Jobs with a long queue
+    /// time on it need more workers, jobs with a long run time need faster ones
+    pub bottlenecks: Vec<TaskSimulation>,
+    /// jobs that would never run
+    pub blocked: Vec<BlockedTask>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct FlowSimulation {
+    pub name: String,
+    /// `None` when some of the flow's jobs never run
+    pub finished_at: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct TaskSimulation {
+    pub flow: String,
+    pub name: String,
+    pub worker: String,
+    /// when the job's artifacts were all available
+    pub ready_at: f64,
+    pub started_at: f64,
+    pub finished_at: f64,
+    /// time spent waiting for a free slot
+    pub queue_time: f64,
+    /// the job ran into its timeout, artifacts it uploads are never available
+    pub timed_out: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkerSimulation {
+    pub name: String,
+    pub jobs: usize,
+    /// share of the worker's slot time spent running jobs, between 0 & 1
+    pub utilization: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct BlockedTask {
+    pub flow: String,
+    pub name: String,
+    pub reason: String,
+}
+
+/// A job of a flow, flattened out of its task tree.
+#[derive(Debug)]
+struct Job {
+    flow: usize,
+    /// index of the top-level task the job belongs to
+    group: usize,
+    name: String,
+    job_type: JobType,
+    priority: JobPriority,
+    needs: Vec<String>,
+    uploads: Vec<String>,
+    estimate: f64,
+    timeout: f64,
+    state: State,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    /// the job's top-level task hasn't started
+    Pending,
+    Waiting,
+    Ready {
+        at: f64,
+        seq: usize,
+    },
+    Running {
+        worker: usize,
+        ready_at: f64,
+        started_at: f64,
+        finished_at: f64,
+    },
+    Done {
+        finished_at: f64,
+    },
+}
+
+/// Run `flows` on `workers`, see the module docs for what's modeled.
+pub(crate) fn simulate(
+    flows: &[SimulatedFlow],
+    workers: &[WorkerProfile],
+) -> Result<SimulationReport> {
+    ensure!(!workers.is_empty(), "simulating needs at least one worker");
+    for worker in workers {
+        ensure!(worker.slots > 0, "worker {} has no slots", worker.name);
+        ensure!(
+            worker.speed.is_finite() && worker.speed > 0.0,
+            "worker {} needs a positive speed",
+            worker.name
+        );
+    }
+
+    let mut jobs = Vec::new();
+    let mut available = HashSet::new();
+    let mut groups = Vec::with_capacity(flows.len());
+    let mut limits = Vec::with_capacity(flows.len());
+    for (i, simulated) in flows.iter().enumerate() {
+        simulated.flow.validate()?;
+        // scopes only need to keep flows' artifacts apart
+        let ctx = JobNameContext {
+            scope: Uuid::from_u128(i as u128 + 1),
+        };
+        for upload in simulated.flow.uploads.iter() {
+            available.insert(format!("{}/{}", ctx.scope.as_simple(), upload.name));
+        }
+        let estimates: HashMap<&str, f64> = simulated
+            .estimates
+            .iter()
+            .map(|estimate| (estimate.name.as_str(), estimate.duration.as_seconds_f64()))
+            .collect();
+        for (group, task) in simulated.flow.tasks.iter().enumerate() {
+            flatten(i, group, task, &ctx, &estimates, &mut jobs)?;
+        }
+        groups.push(simulated.flow.tasks.len());
+        limits.push(simulated.flow.max_concurrency.unwrap_or(usize::MAX));
+    }
+
+    let mut running = vec![0; workers.len()];
+    let mut busy = vec![0.0; workers.len()];
+    let mut started = vec![0; workers.len()];
+    // top-level tasks started so far, by flow
+    let mut started_groups = vec![0; flows.len()];
+    let mut group_started_at: Vec<Vec<f64>> = groups.iter().map(|n| vec![0.0; *n]).collect();
+    // what happened to each job, by index
+    let mut ran: Vec<Option<TaskSimulation>> = vec![None; jobs.len()];
+    let mut seq = 0;
+    let mut now = 0.0;
+
+    loop {
+        // start the next top-level tasks of flows with room for them
+        for (flow, next) in started_groups.iter_mut().enumerate() {
+            let mut active = (0..*next)
+                .filter(|group| !group_done(&jobs, flow, *group))
+                .count();
+            while *next < groups[flow] && active < limits[flow] {
+                group_started_at[flow][*next] = now;
+                for job in jobs
+                    .iter_mut()
+                    .filter(|job| job.flow == flow && job.group == *next)
+                {
+                    job.state = State::Waiting;
+                }
+                *next += 1;
+                active += 1;
+            }
+        }
+
+        // jobs whose artifacts all exist join the queue
+        for job in jobs.iter_mut() {
+            if job.state == State::Waiting && job.needs.iter().all(|n| available.contains(n)) {
+                job.state = State::Ready { at: now, seq };
+                seq += 1;
+            }
+        }
+
+        // hand out free slots, interactive jobs first, then in the order jobs got ready
+        let mut queue: Vec<usize> = (0..jobs.len())
+            .filter(|i| matches!(jobs[*i].state, State::Ready { .. }))
+            .collect();
+        queue.sort_by(|a, b| {
+            let key = |i: usize| match jobs[i].state {
+                State::Ready { seq, .. } => seq,
+                _ => unreachable!(),
+            };
+            jobs[*b]
+                .priority
+                .cmp(&jobs[*a].priority)
+                .then(key(*a).cmp(&key(*b)))
+        });
+        for i in queue {
+            let job = &mut jobs[i];
+            let worker = (0..workers.len())
+                .filter(|w| workers[*w].runs(job.job_type) && running[*w] < workers[*w].slots)
+                .max_by(|a, b| {
+                    workers[*a]
+                        .speed
+                        .total_cmp(&workers[*b].speed)
+                        .then(running[*b].cmp(&running[*a]))
+                        .then(b.cmp(a))
+                });
+            let (Some(w), State::Ready { at, .. }) = (worker, job.state) else {
+                continue;
+            };
+            let run = (job.estimate / workers[w].speed).min(job.timeout);
+            running[w] += 1;
+            started[w] += 1;
+            busy[w] += run;
+            job.state = State::Running {
+                worker: w,
+                ready_at: at,
+                started_at: now,
+                finished_at: now + run,
+            };
+        }
+
+        // move on to the next job to finish
+        let next = jobs
+            .iter()
+            .filter_map(|job| match job.state {
+                State::Running { finished_at, .. } => Some(finished_at),
+                _ => None,
+            })
+            .min_by(f64::total_cmp);
+        let Some(next) = next else {
+            break;
+        };
+        now = next;
+        for (i, job) in jobs.iter_mut().enumerate() {
+            let State::Running {
+                worker,
+                ready_at,
+                started_at,
+                finished_at,
+            } = job.state
+            else {
+                continue;
+            };
+            if finished_at > now {
+                continue;
+            }
+            let timed_out = job.estimate / workers[worker].speed > job.timeout;
+            if !timed_out {
+                available.extend(job.uploads.iter().cloned());
+            }
+            running[worker] -= 1;
+            job.state = State::Done { finished_at };
+            ran[i] = Some(TaskSimulation {
+                flow: flows[job.flow].flow.name.clone(),
+                name: job.name.clone(),
+                worker: workers[worker].name.clone(),
+                ready_at,
+                started_at,
+                finished_at,
+                queue_time: started_at - ready_at,
+                timed_out,
+            });
+        }
+    }
+
+    let makespan = ran
+        .iter()
+        .flatten()
+        .map(|t| t.finished_at)
+        .fold(0.0, f64::max);
+    let blocked: Vec<BlockedTask> = jobs
+        .iter()
+        .filter(|job| !matches!(job.state, State::Done { .. }))
+        .map(|job| BlockedTask {
+            flow: flows[job.flow].flow.name.clone(),
+            name: job.name.clone(),
+            reason: match job.state {
+                State::Ready { .. } => format!("no worker runs {:?} jobs", job.job_type),
+                State::Waiting => {
+                    let missing: Vec<&str> = job
+                        .needs
+                        .iter()
+                        .filter(|n| !available.contains(*n))
+                        .map(String::as_str)
+                        .collect();
+                    format!("waiting for artifacts: {}", missing.join(", "))
+                }
+                _ => "an earlier task of the flow never finishes".to_string(),
+            },
+        })
+        .collect();
+    let flow_reports = flows
+        .iter()
+        .enumerate()
+        .map(|(flow, simulated)| {
+            let mut finished = jobs
+                .iter()
+                .zip(ran.iter())
+                .filter(|(job, _)| job.flow == flow);
+            FlowSimulation {
+                name: simulated.flow.name.clone(),
+                finished_at: finished.try_fold(0.0, |at: f64, (_, ran)| {
+                    ran.as_ref().map(|t| at.max(t.finished_at))
+                }),
+            }
+        })
+        .collect();
+    let worker_reports = workers
+        .iter()
+        .enumerate()
+        .map(|(i, worker)| WorkerSimulation {
+            name: worker.name.clone(),
+            jobs: started[i],
+            utilization: match makespan > 0.0 {
+                true => busy[i] / (worker.slots as f64 * makespan),
+                false => 0.0,
+            },
+        })
+        .collect();
+    let bottlenecks = critical_path(&jobs, &group_started_at)
+        .into_iter()
+        .filter_map(|i| ran[i].clone())
+        .collect();
+    let mut tasks: Vec<TaskSimulation> = ran.into_iter().flatten().collect();
+    tasks.sort_by(|a, b| a.started_at.total_cmp(&b.started_at));
+
+    Ok(SimulationReport {
+        makespan,
+        flows: flow_reports,
+        tasks,
+        workers: worker_reports,
+        bottlenecks,
+        blocked,
+    })
+}
+
+fn group_done(jobs: &[Job], flow: usize, group: usize) -> bool {
+    jobs.iter()
+        .filter(|job| job.flow == flow && job.group == group)
+        .all(|job| matches!(job.state, State::Done { .. }))
+}
+
+fn flatten(
+    flow: usize,
+    group: usize,
+    task: &Task,
+    ctx: &JobNameContext,
+    estimates: &HashMap<&str, f64>,
+    jobs: &mut Vec<Job>,
+) -> Result<()> {
+    let description = &task.description;
+    let timeout = description.timeout.as_seconds_f64();
+    jobs.push(Job {
+        flow,
+        group,
+        name: description.name.clone(),
+        job_type: description.job_type(),
+        priority: description.priority,
+        needs: description
+            .dependencies(ctx.clone())
+            .collect::<Result<_>>()?,
+        uploads: description
+            .artifacts
+            .uploads
+            .iter()
+            .map(|artifact| ctx.render(&upload_template(&description.name, &artifact.name)))
+            .collect::<Result<_>>()?,
+        estimate: estimates
+            .get(description.name.as_str())
+            .copied()
+            .unwrap_or(timeout),
+        timeout,
+        state: State::Pending,
+    });
+    for task in task.tasks.iter() {
+        flatten(flow, group, task, ctx, estimates, jobs)?;
+    }
+    Ok(())
+}
+
+/// Walk back from the last job to finish: a job waited either on the artifact that arrived
+/// last, or on the earlier top-level task of its flow that made room for its own to start.
+fn critical_path(jobs: &[Job], group_started_at: &[Vec<f64>]) -> Vec<usize> {
+    let finished = |i: usize| match jobs[i].state {
+        State::Done { finished_at } => Some(finished_at),
+        _ => None,
+    };
+    let last_of = |candidates: &mut dyn Iterator<Item = usize>| {
+        candidates
+            .filter_map(|i| finished(i).map(|at| (i, at)))
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(i, _)| i)
+    };
+
+    let mut path = Vec::new();
+    let mut current = last_of(&mut (0..jobs.len()));
+    while let Some(i) = current {
+        path.push(i);
+        let job = &jobs[i];
+        let group_start = group_started_at[job.flow][job.group];
+        let producers = &mut (0..jobs.len()).filter(|p| {
+            jobs[*p].flow == job.flow && jobs[*p].uploads.iter().any(|u| job.needs.contains(u))
+        });
+        current = match last_of(producers) {
+            Some(p) if finished(p).is_some_and(|at| at > group_start) => Some(p),
+            _ if group_start > 0.0 => last_of(&mut (0..jobs.len()).filter(|p| {
+                jobs[*p].flow == job.flow
+                    && jobs[*p].group < job.group
+                    && finished(*p).is_some_and(|at| at <= group_start)
+            })),
+            _ => None,
+        };
+    }
+    path.reverse();
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `downloads` are object names, `uploads` are artifact names
+    fn job(name: &str, downloads: &[&str], uploads: &[&str]) -> String {
+        let list = |names: &[&str]| {
+            names
+                .iter()
+                .map(|n| format!("{{ name = \"{n}\", path = \"file\" }}"))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        format!(
+            r#"
+[tasks.description]
+space = "personal"
+program_id = "67e55044-10b1-426f-9247-bb680e5fe0c8"
+name = "{name}"
+author = "me"
+environment = {{}}
+timeout = "10m"
+artifacts = {{ downloads = [{}], uploads = [{}] }}
+
+[tasks.description.details.wasm]
+module = {{ LocalPath = "main.wasm" }}
+"#,
+            list(downloads),
+            list(uploads)
+        )
+    }
+
+    fn flow(name: &str, jobs: &[String], estimates: &[(&str, u64)]) -> SimulatedFlow {
+        let src = jobs
+            .iter()
+            .fold(format!("name = \"{name}\"\n"), |src, job| {
+                src + "\n[[tasks]]" + job
+            });
+        SimulatedFlow {
+            flow: src.parse().unwrap(),
+            estimates: estimates
+                .iter()
+                .map(|(name, secs)| TaskEstimate {
+                    name: name.to_string(),
+                    duration: time::Duration::seconds(*secs as i64),
+                })
+                .collect(),
+        }
+    }
+
+    fn worker(name: &str, slots: usize) -> WorkerProfile {
+        WorkerProfile {
+            name: name.to_string(),
+            slots,
+            job_types: Vec::new(),
+            speed: 1.0,
+        }
+    }
+
+    #[test]
+    fn tasks_run_in_order_and_queue_for_slots() {
+        let flows = vec![flow(
+            "etl",
+            &[
+                job("extract", &[], &["raw"]),
+                job("load", &["{scope}/extract/raw"], &[]),
+            ],
+            &[("extract", 60), ("load", 30)],
+        )];
+        let report = simulate(&flows, &[worker("a", 1)]).unwrap();
+        assert_eq!(report.makespan, 90.0);
+        assert_eq!(report.flows[0].finished_at, Some(90.0));
+        let names: Vec<&str> = report.bottlenecks.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, vec!["extract", "load"]);
+
+        // two copies of the flow on one slot queue behind each other
+        let flows = vec![
+            flow("one", &[job("work", &[], &[])], &[("work", 60)]),
+            flow("two", &[job("work", &[], &[])], &[("work", 60)]),
+        ];
+        let report = simulate(&flows, &[worker("a", 1)]).unwrap();
+        assert_eq!(report.makespan, 120.0);
+        assert_eq!(report.tasks[1].queue_time, 60.0);
+        assert_eq!(report.workers[0].utilization, 1.0);
+
+        let report = simulate(&flows, &[worker("a", 1), worker("b", 1)]).unwrap();
+        assert_eq!(report.makespan, 60.0);
+    }
+
+    #[test]
+    fn missing_artifacts_block() {
+        let mut flows = vec![flow(
+            "stuck",
+            &[job("wait", &["never"], &[]), job("after", &[], &[])],
+            &[],
+        )];
+        flows[0].flow.max_concurrency = Some(1);
+        let report = simulate(&flows, &[worker("a", 2)]).unwrap();
+        assert_eq!(report.flows[0].finished_at, None);
+        assert_eq!(report.blocked.len(), 2);
+        assert!(report.blocked[0].reason.contains("never"));
+
+        // with room for both, only the stuck one is blocked
+        flows[0].flow.max_concurrency = None;
+        let report = simulate(&flows, &[worker("a", 2)]).unwrap();
+        assert_eq!(report.blocked.len(), 1);
+    }
+
+    #[test]
+    fn top_level_tasks_share_the_concurrency_limit() {
+        let jobs: Vec<String> = ["a", "b", "c", "d"]
+            .iter()
+            .map(|name| job(name, &[], &[]))
+            .collect();
+        let estimates = [("a", 60), ("b", 60), ("c", 60), ("d", 60)];
+        let mut flows = vec![flow("fanout", &jobs, &estimates)];
+        let report = simulate(&flows, &[worker("a", 4)]).unwrap();
+        assert_eq!(report.makespan, 60.0);
+
+        flows[0].flow.max_concurrency = Some(2);
+        let report = simulate(&flows, &[worker("a", 4)]).unwrap();
+        assert_eq!(report.makespan, 120.0);
+        // one task of the first pair, then the one that took its place
+        assert_eq!(report.bottlenecks.len(), 2);
+
+        flows[0].flow.max_concurrency = Some(1);
+        let report = simulate(&flows, &[worker("a", 4)]).unwrap();
+        assert_eq!(report.makespan, 240.0);
+    }
+}
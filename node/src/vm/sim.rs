@@ -0,0 +1,281 @@
+//! Deterministic-ish simulation of scheduler/worker coordination.
+//!
+//! The scheduler & workers coordinate through entries in a shared iroh doc. Running that with
+//! real nodes takes seconds per scenario, so this runs the real [`Scheduler`] & [`Worker`]s
+//! against a [`MemStore`] instead, with workers that finish their jobs right away. Every node
+//! gets its own event inbox, and proptest picks which event is delivered next & when spawned
+//! tasks get to run, so races, duplicate deliveries & reordering all get covered in
+//! milliseconds. All nodes read one shared store, replication lag beyond event delivery isn't
+//! modeled. Keys & ids come from a seed proptest picks too, so a failing case replays as is.
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+
+use iroh::docs::{Author, AuthorId};
+use iroh::net::key::SecretKey;
+use iroh::net::NodeId;
+use proptest::prelude::*;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use tracing::warn;
+use uuid::Uuid;
+
+use super::doc::{parse_entry, DocEventHandler, Event};
+use super::job::{DockerNetwork, JobDescription, JobDetails, JobStatus, JOBS_PREFIX};
+use super::node_author_id;
+use super::scheduler::Scheduler;
+use super::store::mem::MemStore;
+use super::store::{JobStore, Store};
+use super::worker::pickup::PickupConfig;
+use super::worker::{ExecutionStatus, Worker, WORKER_PREFIX};
+
+/// Rounds of delivering everything in order before a run counts as stuck.
+const MAX_DRAIN_ROUNDS: usize = 1_000;
+
+/// One simulated workspace: a scheduler, some workers & a shared store.
+struct Sim {
+    store: MemStore,
+    scheduler: Scheduler,
+    scheduler_id: AuthorId,
+    workers: Vec<Worker>,
+    /// undelivered events per node, the scheduler's inbox comes first
+    inboxes: Vec<VecDeque<Event>>,
+    jobs: Vec<Uuid>,
+}
+
+impl Sim {
+    async fn new(workers: usize, jobs: usize, seed: u64) -> Self {
+        let store = MemStore::default();
+        let mut rng = StdRng::seed_from_u64(seed);
+        let author = |rng: &mut StdRng| node_author_id(&SecretKey::generate_with_rng(rng).public());
+
+        let scheduler_id = author(&mut rng);
+        let scheduler = Scheduler::with_store(scheduler_id, Store::Mem(store.clone()));
+        let pickup = PickupConfig {
+            max_concurrent_jobs: Some(usize::MAX),
+            jitter_ms: 0,
+            backoff_ms: 0,
+            max_backoff_ms: 0,
+        };
+        let workers = (0..workers)
+            .map(|_| Worker::simulated(author(&mut rng), Store::Mem(store.clone()), pickup.clone()))
+            .collect::<Vec<_>>();
+
+        // jobs run as a local author, workers export it to build the job context
+        let job_author = Author::new(&mut rng);
+        store.add_author(job_author.clone());
+
+        let mut sim = Sim {
+            store,
+            scheduler,
+            scheduler_id,
+            inboxes: vec![VecDeque::new(); workers.len() + 1],
+            workers,
+            jobs: (0..jobs).map(|_| uuid(&mut rng)).collect(),
+        };
+        for job_id in sim.jobs.clone() {
+            let description = job_description(job_id, job_author.id());
+            sim.scheduler
+                .run_job(uuid(&mut rng), job_id, description)
+                .await
+                .expect("schedule job");
+        }
+        sim.collect();
+        sim
+    }
+
+    /// Queue what was written since last time for every node, including the writer.
+    fn collect(&mut self) {
+        for entry in self.store.take_inserts() {
+            let from = NodeId::from_bytes(entry.author().as_bytes()).expect("node author");
+            if let Some(event) = parse_entry(&entry, from) {
+                for inbox in &mut self.inboxes {
+                    inbox.push_back(event.clone());
+                }
+            }
+        }
+    }
+
+    /// Hand `event` to a node, logging failures like the workspace's event loop does.
+    async fn deliver(&self, inbox: usize, event: Event) {
+        let res = match inbox {
+            0 => self.scheduler.handle_event(event).await,
+            i => self.workers[i - 1].handle_event(event).await,
+        };
+        if let Err(err) = res {
+            warn!("node {} failed to handle event: {:?}", inbox, err);
+        }
+    }
+
+    /// Let spawned tasks run until they're all waiting on something.
+    async fn settle() {
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+
+    /// Take one step picked by `choice`. Returns false once there's nothing left to do.
+    async fn step(&mut self, choice: Step) -> bool {
+        if choice.settle {
+            Self::settle().await;
+            self.collect();
+        }
+        let busy = self
+            .inboxes
+            .iter()
+            .enumerate()
+            .filter(|(_, inbox)| !inbox.is_empty())
+            .map(|(i, _)| i)
+            .collect::<Vec<_>>();
+        if busy.is_empty() {
+            return false;
+        }
+
+        let inbox = busy[choice.node % busy.len()];
+        let position = choice.pick % self.inboxes[inbox].len();
+        let event = if choice.duplicate {
+            self.inboxes[inbox][position].clone()
+        } else {
+            self.inboxes[inbox].remove(position).expect("in range")
+        };
+        self.deliver(inbox, event).await;
+        self.collect();
+        true
+    }
+
+    async fn run(&mut self, schedule: &[Step]) {
+        for choice in schedule {
+            self.step(*choice).await;
+        }
+        // deliver in order, without duplicates, until everything settles
+        for _ in 0..MAX_DRAIN_ROUNDS {
+            while self.step(Step::default()).await {}
+            Self::settle().await;
+            self.collect();
+            if self.inboxes.iter().all(|inbox| inbox.is_empty()) {
+                return;
+            }
+        }
+        panic!("simulation didn't settle");
+    }
+
+    /// Authors that wrote `status` for `job_id`.
+    async fn executions(&self, job_id: Uuid, status: ExecutionStatus) -> Vec<AuthorId> {
+        let prefix = format!("{}/status/{}/{}", WORKER_PREFIX, job_id.as_u128(), status);
+        let entries = self.store.entries(None, &prefix).await.expect("entries");
+        entries.iter().map(|entry| entry.author()).collect()
+    }
+
+    async fn check(&self) -> Result<(), TestCaseError> {
+        for job_id in &self.jobs {
+            let running = self.executions(*job_id, ExecutionStatus::Running).await;
+            prop_assert_eq!(running.len(), 1, "job {} ran on {:?}", job_id, running);
+            let worker = running[0];
+
+            let prefix = format!("{JOBS_PREFIX}/assign/{}/", job_id.as_u128());
+            let assigned = self
+                .store
+                .entries(Some(self.scheduler_id), &prefix)
+                .await
+                .expect("entries")
+                .len();
+            prop_assert_eq!(assigned, 1, "job {} assigned {} times", job_id, assigned);
+
+            let status = self
+                .scheduler
+                .get_job_status(*job_id)
+                .await
+                .expect("status");
+            prop_assert_eq!(status, Some(JobStatus::Completed(worker)));
+            let completed = self.executions(*job_id, ExecutionStatus::Completed).await;
+            prop_assert_eq!(completed, vec![worker]);
+        }
+        Ok(())
+    }
+}
+
+fn job_description(job_id: Uuid, author: AuthorId) -> JobDescription {
+    JobDescription {
+        space: "sim".to_string(),
+        program_id: Uuid::nil(),
+        name: job_id.to_string(),
+        author: author.to_string(),
+        environment: HashMap::new(),
+        details: JobDetails::Docker {
+            image: "alpine".to_string(),
+            command: vec!["true".to_string()],
+            network: DockerNetwork::None,
+        },
+        artifacts: Default::default(),
+        timeout: time::Duration::minutes(1),
+        priority: Default::default(),
+        preemptible: false,
+        deadline: None,
+        requires: Vec::new(),
+        profile: false,
+        spaces: Vec::new(),
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Step {
+    node: usize,
+    pick: usize,
+    duplicate: bool,
+    /// let spawned tasks run before delivering
+    settle: bool,
+}
+
+fn step() -> impl Strategy<Value = Step> {
+    (
+        any::<usize>(),
+        any::<usize>(),
+        prop::bool::weighted(0.1),
+        prop::bool::weighted(0.2),
+    )
+        .prop_map(|(node, pick, duplicate, settle)| Step {
+            node,
+            pick,
+            duplicate,
+            settle,
+        })
+}
+
+fn uuid(rng: &mut StdRng) -> Uuid {
+    uuid::Builder::from_random_bytes(rng.gen()).into_uuid()
+}
+
+fn simulate(
+    workers: usize,
+    jobs: usize,
+    seed: u64,
+    schedule: &[Step],
+) -> Result<(), TestCaseError> {
+    // paused time skips the workers' pickup delays
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .start_paused(true)
+        .build()
+        .expect("runtime");
+    rt.block_on(async {
+        let mut sim = Sim::new(workers, jobs, seed).await;
+        sim.run(schedule).await;
+        sim.check().await
+    })
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(1_000))]
+
+    #[test]
+    fn every_job_runs_exactly_once(
+        workers in 1usize..5,
+        jobs in 1usize..5,
+        seed in any::<u64>(),
+        schedule in prop::collection::vec(step(), 0..300),
+    ) {
+        simulate(workers, jobs, seed, &schedule)?;
+    }
+}
+
+#[test]
+fn in_order_delivery() {
+    simulate(3, 2, 0, &[]).unwrap();
+}
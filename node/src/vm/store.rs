@@ -0,0 +1,166 @@
+//! Doc & blob access of the scheduler & workers.
+//!
+//! Jobs are coordinated through entries in the workspace doc, see [`super::scheduler`]. The
+//! scheduler & workers only reach the doc & blobs through a [`Store`], so tests can run them
+//! against an in-memory [`mem::MemStore`] instead of a node, see `super::sim`.
+use anyhow::Result;
+use bytes::Bytes;
+use futures::TryStreamExt;
+use iroh::blobs::Hash;
+use iroh::client::docs::Entry;
+use iroh::docs::store::Query;
+use iroh::docs::{Author, AuthorId};
+
+use crate::router::RouterClient;
+
+use super::blobs::Blobs;
+use super::doc::Doc;
+
+#[cfg(test)]
+pub(crate) mod mem;
+
+/// The doc & blob operations jobs are coordinated with.
+pub(crate) trait JobStore {
+    /// Entries with keys starting with `prefix`, written by `author` or by anyone.
+    async fn entries(&self, author: Option<AuthorId>, prefix: &str) -> Result<Vec<Entry>>;
+    async fn get_exact(
+        &self,
+        author: AuthorId,
+        key: &[u8],
+        include_empty: bool,
+    ) -> Result<Option<Entry>>;
+    async fn set_hash(&self, author: AuthorId, key: Bytes, hash: Hash, len: u64) -> Result<()>;
+    async fn set_bytes(&self, author: AuthorId, key: Bytes, data: Bytes) -> Result<()>;
+    /// Content of a blob, fetched from whoever has it if it isn't local.
+    async fn read_blob(&self, hash: Hash) -> Result<Bytes>;
+    /// Store `data` as the object `name`, see [`Blobs::put_bytes`].
+    async fn put_bytes(&self, name: &str, data: Bytes) -> Result<(Hash, u64)>;
+    /// The secret half of a local author.
+    async fn export_author(&self, author: AuthorId) -> Result<Option<Author>>;
+}
+
+/// The workspace doc & blobs of a node.
+#[derive(Debug, Clone)]
+pub(crate) struct NodeStore {
+    doc: Doc,
+    blobs: Blobs,
+    router: RouterClient,
+}
+
+impl NodeStore {
+    pub(crate) fn new(doc: Doc, blobs: Blobs, router: RouterClient) -> Self {
+        Self { doc, blobs, router }
+    }
+}
+
+impl JobStore for NodeStore {
+    async fn entries(&self, author: Option<AuthorId>, prefix: &str) -> Result<Vec<Entry>> {
+        let q = match author {
+            Some(author) => Query::author(author).key_prefix(prefix),
+            None => Query::key_prefix(prefix),
+        };
+        self.doc.get_many(q).await?.try_collect().await
+    }
+
+    async fn get_exact(
+        &self,
+        author: AuthorId,
+        key: &[u8],
+        include_empty: bool,
+    ) -> Result<Option<Entry>> {
+        self.doc.get_exact(author, key, include_empty).await
+    }
+
+    async fn set_hash(&self, author: AuthorId, key: Bytes, hash: Hash, len: u64) -> Result<()> {
+        self.doc.set_hash(author, key, hash, len).await
+    }
+
+    async fn set_bytes(&self, author: AuthorId, key: Bytes, data: Bytes) -> Result<()> {
+        self.doc.set_bytes(author, key, data).await?;
+        Ok(())
+    }
+
+    async fn read_blob(&self, hash: Hash) -> Result<Bytes> {
+        self.blobs.fetch_blob(hash).await?;
+        self.router.blobs().read_to_bytes(hash).await
+    }
+
+    async fn put_bytes(&self, name: &str, data: Bytes) -> Result<(Hash, u64)> {
+        self.blobs.put_bytes(name, data).await
+    }
+
+    async fn export_author(&self, author: AuthorId) -> Result<Option<Author>> {
+        self.router.authors().export(author).await
+    }
+}
+
+/// Where the scheduler & workers keep jobs.
+#[derive(Debug, Clone)]
+pub(crate) enum Store {
+    Node(NodeStore),
+    #[cfg(test)]
+    Mem(mem::MemStore),
+}
+
+impl JobStore for Store {
+    async fn entries(&self, author: Option<AuthorId>, prefix: &str) -> Result<Vec<Entry>> {
+        match self {
+            Store::Node(store) => store.entries(author, prefix).await,
+            #[cfg(test)]
+            Store::Mem(store) => store.entries(author, prefix).await,
+        }
+    }
+
+    async fn get_exact(
+        &self,
+        author: AuthorId,
+        key: &[u8],
+        include_empty: bool,
+    ) -> Result<Option<Entry>> {
+        match self {
+            Store::Node(store) => store.get_exact(author, key, include_empty).await,
+            #[cfg(test)]
+            Store::Mem(store) => store.get_exact(author, key, include_empty).await,
+        }
+    }
+
+    async fn set_hash(&self, author: AuthorId, key: Bytes, hash: Hash, len: u64) -> Result<()> {
+        match self {
+            Store::Node(store) => store.set_hash(author, key, hash, len).await,
+            #[cfg(test)]
+            Store::Mem(store) => store.set_hash(author, key, hash, len).await,
+        }
+    }
+
+    async fn set_bytes(&self, author: AuthorId, key: Bytes, data: Bytes) -> Result<()> {
+        match self {
+            Store::Node(store) => store.set_bytes(author, key, data).await,
+            #[cfg(test)]
+            Store::Mem(store) => store.set_bytes(author, key, data).await,
+        }
+    }
+
+    async fn read_blob(&self, hash: Hash) -> Result<Bytes> {
+        match self {
+            Store::Node(store) => store.read_blob(hash).await,
+            #[cfg(test)]
+            Store::Mem(store) => store.read_blob(hash).await,
+        }
+    }
+
+    async fn put_bytes(&self, name: &str, data: Bytes) -> Result<(Hash, u64)> {
+        match self {
+            Store::Node(store) => store.put_bytes(name, data).await,
+            #[cfg(test)]
+            Store::Mem(store) => store.put_bytes(name, data).await,
+        }
+    }
+
+    async fn export_author(&self, author: AuthorId) -> Result<Option<Author>> {
+        match self {
+            Store::Node(store) => store.export_author(author).await,
+            #[cfg(test)]
+            Store::Mem(store) => store.export_author(author).await,
+        }
+    }
+}
@@ -0,0 +1,108 @@
+//! An in-memory [`JobStore`], for running the scheduler & workers without nodes.
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use iroh::blobs::Hash;
+use iroh::client::docs::Entry;
+use iroh::docs::{Author, AuthorId, NamespaceId, Record, RecordIdentifier};
+
+use super::JobStore;
+
+/// A doc & blob store shared by everyone holding a clone. Nothing is delivered on its own:
+/// entries written are collected until [`MemStore::take_inserts`], so the caller decides who
+/// hears about which write & when.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct MemStore(Arc<Mutex<Inner>>);
+
+#[derive(Debug, Default)]
+struct Inner {
+    /// latest entry by author & key
+    entries: BTreeMap<(AuthorId, Bytes), Entry>,
+    blobs: HashMap<Hash, Bytes>,
+    authors: HashMap<AuthorId, Author>,
+    /// entries written since the last [`MemStore::take_inserts`]
+    inserts: Vec<Entry>,
+    /// entry timestamps, strictly increasing
+    clock: u64,
+}
+
+impl MemStore {
+    pub(crate) fn add_author(&self, author: Author) {
+        let mut inner = self.0.lock().unwrap();
+        inner.authors.insert(author.id(), author);
+    }
+
+    /// Entries written since this was last called, in the order they were written.
+    pub(crate) fn take_inserts(&self) -> Vec<Entry> {
+        std::mem::take(&mut self.0.lock().unwrap().inserts)
+    }
+}
+
+impl JobStore for MemStore {
+    async fn entries(&self, author: Option<AuthorId>, prefix: &str) -> Result<Vec<Entry>> {
+        let inner = self.0.lock().unwrap();
+        let entries = inner
+            .entries
+            .iter()
+            .filter(|((a, key), _)| {
+                author.map_or(true, |author| *a == author) && key.starts_with(prefix.as_bytes())
+            })
+            .map(|(_, entry)| entry.clone())
+            .collect();
+        Ok(entries)
+    }
+
+    async fn get_exact(
+        &self,
+        author: AuthorId,
+        key: &[u8],
+        include_empty: bool,
+    ) -> Result<Option<Entry>> {
+        let inner = self.0.lock().unwrap();
+        let entry = inner
+            .entries
+            .get(&(author, Bytes::copy_from_slice(key)))
+            .filter(|entry| include_empty || entry.content_len() > 0)
+            .cloned();
+        Ok(entry)
+    }
+
+    async fn set_hash(&self, author: AuthorId, key: Bytes, hash: Hash, len: u64) -> Result<()> {
+        let mut inner = self.0.lock().unwrap();
+        inner.clock += 1;
+        let id = RecordIdentifier::new(NamespaceId::from(&[0; 32]), author, &key);
+        let entry: Entry = iroh::docs::Entry::new(id, Record::new(hash, len, inner.clock)).into();
+        inner.entries.insert((author, key), entry.clone());
+        inner.inserts.push(entry);
+        Ok(())
+    }
+
+    async fn set_bytes(&self, author: AuthorId, key: Bytes, data: Bytes) -> Result<()> {
+        let hash = Hash::new(&data);
+        let len = data.len() as u64;
+        self.0.lock().unwrap().blobs.insert(hash, data);
+        self.set_hash(author, key, hash, len).await
+    }
+
+    async fn read_blob(&self, hash: Hash) -> Result<Bytes> {
+        let inner = self.0.lock().unwrap();
+        inner
+            .blobs
+            .get(&hash)
+            .cloned()
+            .ok_or_else(|| anyhow!("blob not found: {}", hash))
+    }
+
+    async fn put_bytes(&self, _name: &str, data: Bytes) -> Result<(Hash, u64)> {
+        let hash = Hash::new(&data);
+        let len = data.len() as u64;
+        self.0.lock().unwrap().blobs.insert(hash, data);
+        Ok((hash, len))
+    }
+
+    async fn export_author(&self, author: AuthorId) -> Result<Option<Author>> {
+        Ok(self.0.lock().unwrap().authors.get(&author).cloned())
+    }
+}
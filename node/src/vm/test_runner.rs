@@ -0,0 +1,390 @@
+//! Running a program's tests.
+//!
+//! Tests live in a `tests/` dir next to the program's `program.json`, one TOML file per
+//! case:
+//!
+//! ```toml
+//! environment = { org = "n0-computer" }
+//!
+//! [[tables]]
+//! schema = "schemas/stargazers.json" # relative to the tests dir
+//! rows = [{ login = "b5" }]
+//!
+//! [expect]
+//! status = "ok"
+//! output_contains = ["done"]
+//!
+//! [[expect.tables]]
+//! title = "stargazers"
+//! rows = 2
+//! contains = [{ login = "b5" }]
+//! ```
+//!
+//! Every case runs against its own ephemeral space, so cases can't see each other's writes
+//! & no test data ends up in the node's spaces.
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use iroh::docs::Author;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use uuid::Uuid;
+
+use super::flow::TaskOutput;
+use super::job::{JobOutput, JobPriority, JobResultStatus};
+use super::VM;
+use crate::space::Space;
+
+const TESTS_DIR: &str = "tests";
+
+/// One test case, read from a file in the tests dir.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct TestCase {
+    /// name of the case, defaults to the file name
+    pub name: Option<String>,
+    pub environment: HashMap<String, String>,
+    /// tables to create before the program runs
+    pub tables: Vec<Fixture>,
+    pub expect: Expectations,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Fixture {
+    /// path to a JSON schema, relative to the tests dir
+    pub schema: PathBuf,
+    #[serde(default)]
+    pub rows: Vec<Value>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Expectations {
+    pub status: ExpectedStatus,
+    /// exact program output
+    pub output: Option<String>,
+    /// strings the program output must contain
+    pub output_contains: Vec<String>,
+    pub tables: Vec<TableExpectations>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExpectedStatus {
+    #[default]
+    Ok,
+    Err,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TableExpectations {
+    pub title: String,
+    /// exact number of rows, counting each row id once
+    pub rows: Option<usize>,
+    /// rows that must exist. Each matches any row that has at least these fields
+    #[serde(default)]
+    pub contains: Vec<Value>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TestCaseResult {
+    pub name: String,
+    /// why the case failed, empty if it passed
+    pub failures: Vec<String>,
+    /// None if the program never ran, eg: because fixtures couldn't be loaded
+    pub output: Option<TaskOutput>,
+}
+
+impl TestCaseResult {
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TestReport {
+    pub program: String,
+    pub cases: Vec<TestCaseResult>,
+}
+
+impl TestReport {
+    pub fn passed(&self) -> bool {
+        self.cases.iter().all(TestCaseResult::passed)
+    }
+}
+
+impl VM {
+    /// Run every test case of the program at `path`. Failing cases are reported, not returned
+    /// as errors, errors are for programs that can't be tested at all.
+    pub async fn test_program(&self, path: impl AsRef<Path>) -> Result<TestReport> {
+        let path = path.as_ref();
+        let cases = read_cases(&path.join(TESTS_DIR)).await?;
+        anyhow::ensure!(!cases.is_empty(), "{} has no tests", path.display());
+
+        // programs run as an author the node can sign for, use a throwaway one
+        let author = Author::new(&mut rand::thread_rng());
+        self.router.authors().import(author.clone()).await?;
+        let report = self.run_cases(author.clone(), path, cases).await;
+        // removed whether or not the cases ran, nothing else signs as it
+        self.router.authors().delete(author.id()).await?;
+        report
+    }
+
+    async fn run_cases(
+        &self,
+        author: Author,
+        path: &Path,
+        cases: Vec<(String, TestCase)>,
+    ) -> Result<TestReport> {
+        let mut program = None;
+        let mut results = Vec::new();
+        for (name, case) in cases {
+            let name = case.name.clone().unwrap_or(name);
+            let space_name = format!("test-{}", Uuid::new_v4());
+            let space = self
+                .spaces
+                .create_ephemeral(&self.router, author.clone(), &space_name)
+                .await?;
+            let result = self.run_case(&space, author.clone(), path, &case).await;
+            self.spaces.remove_ephemeral(&space.id).await;
+
+            let (program_name, result) = match result {
+                Ok((program_name, output, failures)) => (
+                    Some(program_name),
+                    TestCaseResult {
+                        name,
+                        failures,
+                        output: Some(output),
+                    },
+                ),
+                Err(err) => (
+                    None,
+                    TestCaseResult {
+                        name,
+                        failures: vec![format!("{err:#}")],
+                        output: None,
+                    },
+                ),
+            };
+            program = program.or(program_name);
+            results.push(result);
+        }
+
+        Ok(TestReport {
+            program: program.unwrap_or_else(|| path.display().to_string()),
+            cases: results,
+        })
+    }
+
+    async fn run_case(
+        &self,
+        space: &Space,
+        author: Author,
+        path: &Path,
+        case: &TestCase,
+    ) -> Result<(String, TaskOutput, Vec<String>)> {
+        let tests_dir = path.join(TESTS_DIR);
+        for fixture in case.tables.iter() {
+            let schema_path = tests_dir.join(&fixture.schema);
+            let schema = tokio::fs::read(&schema_path)
+                .await
+                .with_context(|| format!("reading {}", schema_path.display()))?;
+            let table = space
+                .tables()
+                .create(author.clone(), Bytes::from(schema))
+                .await
+                .with_context(|| format!("creating table {}", fixture.schema.display()))?;
+            for row in fixture.rows.iter() {
+                space
+                    .rows()
+                    .create(author.clone(), table.content.hash, row.clone())
+                    .await
+                    .with_context(|| format!("creating fixture row in {}", table.title))?;
+            }
+        }
+
+        let program = space.programs().create(author.clone(), path).await?;
+        let output = self
+            .run_program_as(
+                space,
+                author,
+                program.id,
+                case.environment.clone(),
+                JobPriority::Batch,
+                false,
+            )
+            .await?;
+        let failures = check(space, &case.expect, &output).await?;
+        Ok((program.manifest.name, output, failures))
+    }
+}
+
+/// Test cases in a tests dir, sorted by file name.
+async fn read_cases(dir: &Path) -> Result<Vec<(String, TestCase)>> {
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut cases = Vec::new();
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some("toml") {
+            continue;
+        }
+        let src = tokio::fs::read_to_string(&path).await?;
+        let case: TestCase =
+            toml::from_str(&src).with_context(|| format!("reading {}", path.display()))?;
+        let name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default();
+        cases.push((name, case));
+    }
+    cases.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(cases)
+}
+
+async fn check(space: &Space, expect: &Expectations, output: &TaskOutput) -> Result<Vec<String>> {
+    let mut failures = Vec::new();
+    match (&output.result.status, expect.status) {
+        (JobResultStatus::Ok(_), ExpectedStatus::Ok) => {}
+        (JobResultStatus::Err(_), ExpectedStatus::Err) => {}
+        (status, expected) => {
+            failures.push(format!("expected status {expected:?}, got {status:?}"));
+        }
+    }
+
+    let text = match &output.result.status {
+        JobResultStatus::Ok(JobOutput::Wasm { output }) => output.as_str(),
+        JobResultStatus::Ok(JobOutput::Docker { stdout, .. }) => stdout.as_str(),
+        JobResultStatus::Err(err) => err.as_str(),
+        _ => "",
+    };
+    if let Some(expected) = &expect.output {
+        if text != expected {
+            failures.push(format!("expected output {expected:?}, got {text:?}"));
+        }
+    }
+    for expected in expect.output_contains.iter() {
+        if !text.contains(expected.as_str()) {
+            failures.push(format!("output doesn't contain {expected:?}"));
+        }
+    }
+
+    for table in expect.tables.iter() {
+        let rows = match latest_rows(space, &table.title).await {
+            Ok(rows) => rows,
+            Err(err) => {
+                failures.push(format!("table {}: {err:#}", table.title));
+                continue;
+            }
+        };
+        if let Some(count) = table.rows {
+            if rows.len() != count {
+                failures.push(format!(
+                    "table {}: expected {count} rows, found {}",
+                    table.title,
+                    rows.len()
+                ));
+            }
+        }
+        for expected in table.contains.iter() {
+            if !rows.iter().any(|row| json_contains(row, expected)) {
+                failures.push(format!("table {}: no row matches {expected}", table.title));
+            }
+        }
+    }
+    Ok(failures)
+}
+
+/// The latest version of every row in a table.
+async fn latest_rows(space: &Space, title: &str) -> Result<Vec<Value>> {
+    let table = space.tables().get_by_title(title).await?;
+    let rows = space
+        .rows()
+        .query(table.content.hash, String::new(), 0, -1)
+        .await?;
+    let mut latest = HashMap::new();
+    for row in rows {
+        match latest.get(&row.id) {
+            Some((created_at, _)) if *created_at > row.created_at => {}
+            _ => {
+                latest.insert(row.id, (row.created_at, row.content));
+            }
+        }
+    }
+    let mut rows = Vec::new();
+    for (_, (_, mut content)) in latest {
+        rows.push(content.resolve(space.router()).await?);
+    }
+    Ok(rows)
+}
+
+/// Does `actual` have everything in `expected`? Objects may have extra fields, everything
+/// else has to be equal.
+fn json_contains(actual: &Value, expected: &Value) -> bool {
+    match (actual, expected) {
+        (Value::Object(actual), Value::Object(expected)) => {
+            expected.iter().all(|(key, expected)| {
+                actual
+                    .get(key)
+                    .map_or(false, |actual| json_contains(actual, expected))
+            })
+        }
+        (actual, expected) => actual == expected,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn parses_test_case() -> Result<()> {
+        let case: TestCase = toml::from_str(
+            r#"
+            environment = { org = "n0-computer" }
+
+            [[tables]]
+            schema = "schemas/stargazers.json"
+            rows = [{ login = "b5", stars = 3 }]
+
+            [expect]
+            status = "err"
+            output_contains = ["rate limited"]
+
+            [[expect.tables]]
+            title = "stargazers"
+            rows = 1
+            "#,
+        )?;
+        assert_eq!(case.environment["org"], "n0-computer");
+        assert_eq!(
+            case.tables[0].rows,
+            vec![json!({ "login": "b5", "stars": 3 })]
+        );
+        assert_eq!(case.expect.status, ExpectedStatus::Err);
+        assert_eq!(case.expect.tables[0].rows, Some(1));
+        assert!(case.expect.tables[0].contains.is_empty());
+
+        let empty: TestCase = toml::from_str("")?;
+        assert_eq!(empty.expect.status, ExpectedStatus::Ok);
+        Ok(())
+    }
+
+    #[test]
+    fn matches_partial_rows() {
+        let row = json!({ "login": "b5", "stars": 3, "meta": { "org": "n0", "admin": true } });
+        assert!(json_contains(&row, &json!({ "login": "b5" })));
+        assert!(json_contains(&row, &json!({ "meta": { "org": "n0" } })));
+        assert!(!json_contains(&row, &json!({ "login": "dig" })));
+        assert!(!json_contains(&row, &json!({ "missing": 1 })));
+        assert!(!json_contains(&json!([1, 2]), &json!([1])));
+    }
+}
@@ -0,0 +1,220 @@
+//! Generates thumbnails of images & videos attached to rows.
+//!
+//! Every few seconds each space is scanned for rows written since the last scan, and every
+//! attachment without a thumbnail gets a docker job that renders a small preview with ffmpeg.
+//! Thumbnails are written to the blob store & linked to their original in the space, see
+//! [`crate::space::attachments`]. The gateway serves them to API callers who can read the
+//! space under `/api/spaces/:space/thumbnails/:hash`. Off unless enabled in the node config.
+use std::collections::{BTreeSet, HashMap};
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinHandle;
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+use crate::router::RouterClient;
+use crate::space::attachments::{Attachment, Thumbnail};
+use crate::space::{Space, Spaces};
+
+use super::blobs::Blobs;
+use super::flow::Task;
+use super::job::{
+    Artifact, Artifacts, DockerNetwork, JobDescription, JobDetails, JobOutput, JobPriority,
+    JobResultStatus,
+};
+use super::scheduler::Scheduler;
+
+const ORIGINAL_PATH: &str = "original";
+const THUMBNAIL_PATH: &str = "thumbnail.jpg";
+const THUMBNAIL_MIME_TYPE: &str = "image/jpeg";
+const THUMBNAIL_TIMEOUT: time::Duration = time::Duration::minutes(5);
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ThumbnailConfig {
+    /// off by default, every new attachment costs a docker job
+    pub enabled: bool,
+    /// Docker image with ffmpeg as its entrypoint
+    pub image: String,
+    /// longest side of a thumbnail, in pixels
+    pub size: u32,
+    /// how often spaces are scanned for new attachments
+    pub interval_secs: u64,
+}
+
+impl Default for ThumbnailConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            image: "linuxserver/ffmpeg".to_string(),
+            size: 256,
+            interval_secs: 30,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Thumbnailer {
+    config: ThumbnailConfig,
+    spaces: Spaces,
+    router: RouterClient,
+    blobs: Blobs,
+    scheduler: Scheduler,
+    author: String,
+}
+
+impl Thumbnailer {
+    pub(crate) fn new(
+        config: ThumbnailConfig,
+        spaces: Spaces,
+        router: RouterClient,
+        blobs: Blobs,
+        scheduler: Scheduler,
+        author: String,
+    ) -> Self {
+        Self {
+            config,
+            spaces,
+            router,
+            blobs,
+            scheduler,
+            author,
+        }
+    }
+
+    /// Scan spaces for new attachments in the background.
+    pub(crate) fn spawn(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            // how far into each space's rows we've scanned
+            let mut cursors = HashMap::new();
+            let interval = Duration::from_secs(self.config.interval_secs.max(1));
+            loop {
+                if let Err(err) = self.scan(&mut cursors).await {
+                    warn!("scanning for attachments: {:?}", err);
+                }
+                tokio::time::sleep(interval).await;
+            }
+        })
+    }
+
+    async fn scan(&self, cursors: &mut HashMap<Uuid, i64>) -> Result<()> {
+        for details in self.spaces.list(0, -1).await? {
+            let Some(space) = self.spaces.get(&details.id).await else {
+                continue;
+            };
+            let since = cursors.get(&space.id).copied().unwrap_or_default();
+            let pending = space.attachments().pending(since, self.config.size).await?;
+            for attachment in pending.attachments {
+                let size = self.config.size;
+                match self.generate(&space, &attachment).await {
+                    Ok(thumbnail) => {
+                        debug!("thumbnailed {} as {}", attachment.hash, thumbnail.hash);
+                        space.attachments().record_thumbnail(&thumbnail).await?;
+                    }
+                    Err(err) => {
+                        warn!("thumbnailing {}: {:?}", attachment.hash, err);
+                        let error = format!("{err:#}");
+                        space
+                            .attachments()
+                            .record_failure(attachment.hash, size, &error)
+                            .await?;
+                    }
+                }
+            }
+            cursors.insert(space.id, pending.next);
+        }
+        Ok(())
+    }
+
+    /// Run the thumbnail job for one attachment.
+    async fn generate(&self, space: &Space, attachment: &Attachment) -> Result<Thumbnail> {
+        let scope = Uuid::new_v4();
+        let original = format!("{}/{ORIGINAL_PATH}", scope.as_simple());
+        let len = self.router.blobs().read(attachment.hash).await?.size();
+        self.blobs
+            .put_object(&original, attachment.hash, len)
+            .await?;
+
+        let task = Task {
+            tasks: vec![],
+            description: self.description(space, attachment),
+        };
+        let output = task
+            .run(
+                scope,
+                self.scheduler.clone(),
+                self.blobs.clone(),
+                Uuid::new_v4(),
+            )
+            .await
+            .into_iter()
+            .next()
+            .context("thumbnail job has no output")?;
+        match output.result.status {
+            JobResultStatus::Ok(JobOutput::Docker { code: 0, .. }) => {}
+            JobResultStatus::Ok(JobOutput::Docker { code, stderr, .. }) => {
+                bail!("ffmpeg exited with {code}: {}", stderr.trim())
+            }
+            status => bail!("thumbnail job failed: {:?}", status),
+        }
+
+        let name = format!("{}/{THUMBNAIL_PATH}", scope.as_simple());
+        let entry = self.blobs.get_object_info(&name).await?;
+        Ok(Thumbnail {
+            original: attachment.hash,
+            size: self.config.size,
+            hash: entry.content_hash(),
+            mime_type: THUMBNAIL_MIME_TYPE.to_string(),
+            created_at: chrono::Utc::now().timestamp(),
+        })
+    }
+
+    fn description(&self, space: &Space, attachment: &Attachment) -> JobDescription {
+        let size = self.config.size;
+        // fit within a size x size box, picking a representative frame of videos
+        let filter = format!("thumbnail,scale={size}:{size}:force_original_aspect_ratio=decrease");
+        let command = vec![
+            "-y".to_string(),
+            "-i".to_string(),
+            format!("/downloads/{ORIGINAL_PATH}"),
+            "-vf".to_string(),
+            filter,
+            "-frames:v".to_string(),
+            "1".to_string(),
+            format!("/uploads/{THUMBNAIL_PATH}"),
+        ];
+        JobDescription {
+            space: space.name.clone(),
+            program_id: Uuid::nil(),
+            name: format!("thumbnail {}", attachment.hash),
+            author: self.author.clone(),
+            environment: Default::default(),
+            details: JobDetails::Docker {
+                image: self.config.image.clone(),
+                command,
+                network: DockerNetwork::None,
+            },
+            artifacts: Artifacts {
+                downloads: BTreeSet::from([Artifact {
+                    name: format!("{{scope}}/{ORIGINAL_PATH}"),
+                    path: ORIGINAL_PATH.to_string(),
+                    executable: false,
+                }]),
+                uploads: BTreeSet::from([Artifact {
+                    name: format!("{{scope}}/{THUMBNAIL_PATH}"),
+                    path: THUMBNAIL_PATH.to_string(),
+                    executable: false,
+                }]),
+            },
+            timeout: THUMBNAIL_TIMEOUT,
+            priority: JobPriority::Batch,
+            preemptible: true,
+            deadline: None,
+            requires: Vec::new(),
+            profile: false,
+            spaces: Vec::new(),
+        }
+    }
+}
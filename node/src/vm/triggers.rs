@@ -0,0 +1,217 @@
+//! Fires the triggers of a space as rows are written, see [`crate::space::triggers`].
+//!
+//! A row hook hands every row write to a task that looks up the triggers on the row's table,
+//! checks their filters & queues a batch run of each matching trigger's program. The run gets
+//! the row as JSON in `TRIGGER_ROW`, along with `TRIGGER_ID`, `TRIGGER_TABLE` &
+//! `TRIGGER_ROW_ID`. Runs are made as the trigger's author, nodes without that author's key
+//! leave the trigger to a node that has it.
+//!
+//! A program that writes to the table it's triggered by would trigger itself forever. Writes
+//! to a row while a run the row triggered is still going don't fire the same trigger again,
+//! and a trigger fires at most [`MAX_ROW_FIRINGS`] times per row & [`MAX_FIRINGS`] times
+//! overall within [`LOOP_WINDOW`]. Firings past that are dropped with a warning.
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use iroh::blobs::Hash;
+use iroh::docs::AuthorId;
+use tokio::task::JoinHandle;
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+use crate::space::events::HashLink;
+use crate::space::hooks::{Hooks, RowWrite};
+use crate::space::{sensitive, Space};
+
+use super::job::JobPriority;
+use super::VM;
+
+/// How far back firings are counted to spot loops.
+const LOOP_WINDOW: Duration = Duration::from_secs(60);
+/// Firings of a trigger by one row within the window.
+const MAX_ROW_FIRINGS: usize = 5;
+/// Firings of a trigger by any row within the window.
+const MAX_FIRINGS: usize = 120;
+
+/// A row write waiting to be checked against triggers.
+struct Written {
+    space: Space,
+    table: Hash,
+    row: Uuid,
+    content: HashLink,
+}
+
+/// Fire triggers as rows are written, for as long as the returned task runs.
+pub(crate) fn spawn(vm: Arc<VM>, hooks: &Hooks) -> JoinHandle<()> {
+    let (tx, rx) = flume::unbounded();
+    // hooks run inline with the write, do the work elsewhere
+    hooks.on_row_changed(move |space, write| {
+        if let RowWrite::Mutate { table, id, content } = write {
+            let _ = tx.send(Written {
+                space: space.clone(),
+                table: *table,
+                row: *id,
+                content: (*content).clone(),
+            });
+        }
+    });
+    tokio::spawn(async move {
+        let guard = LoopGuard::default();
+        while let Ok(written) = rx.recv_async().await {
+            if let Err(err) = fire(&vm, &guard, written).await {
+                warn!("firing triggers: {:?}", err);
+            }
+        }
+    })
+}
+
+async fn fire(vm: &Arc<VM>, guard: &LoopGuard, written: Written) -> Result<()> {
+    let Written {
+        space,
+        table,
+        row,
+        mut content,
+    } = written;
+    let triggers = space.triggers().for_table(table).await?;
+    if triggers.is_empty() {
+        return Ok(());
+    }
+    let mut data = content.resolve(space.router()).await?;
+    sensitive::open_fields(&space.field_keys(), &mut data)?;
+
+    for trigger in triggers {
+        match trigger.matches(&data) {
+            Ok(true) => {}
+            Ok(false) => continue,
+            Err(err) => {
+                warn!("trigger {} has a bad filter: {:?}", trigger.id, err);
+                continue;
+            }
+        }
+        let author_id = AuthorId::from(trigger.author.as_bytes());
+        let Some(author) = space.router().authors().export(author_id).await? else {
+            debug!("no key for the author of trigger {}", trigger.id);
+            continue;
+        };
+        let key = (space.id, trigger.id);
+        if !guard.admit(key, row) {
+            warn!(
+                "trigger {} fired too often for row {}, looks like a loop. skipping",
+                trigger.id, row
+            );
+            continue;
+        }
+
+        let environment = HashMap::from([
+            ("TRIGGER_ID".to_string(), trigger.id.to_string()),
+            ("TRIGGER_TABLE".to_string(), table.to_string()),
+            ("TRIGGER_ROW_ID".to_string(), row.to_string()),
+            ("TRIGGER_ROW".to_string(), data.to_string()),
+        ]);
+        let vm = vm.clone();
+        let space = space.clone();
+        let guard = guard.clone();
+        tokio::spawn(async move {
+            let res = vm
+                .run_program_as(
+                    &space,
+                    author,
+                    trigger.program_id,
+                    environment,
+                    JobPriority::Batch,
+                    false,
+                )
+                .await;
+            if let Err(err) = res {
+                warn!("running program of trigger {}: {:?}", trigger.id, err);
+            }
+            guard.finish(key, row);
+        });
+    }
+    Ok(())
+}
+
+/// Counts recent & running firings of each trigger, by space & trigger id.
+#[derive(Debug, Clone, Default)]
+struct LoopGuard(Arc<Mutex<HashMap<(Uuid, Uuid), Firings>>>);
+
+#[derive(Debug, Default)]
+struct Firings {
+    recent: VecDeque<(Instant, Uuid)>,
+    /// rows with a run in progress
+    running: HashSet<Uuid>,
+}
+
+impl LoopGuard {
+    /// Whether `row` may fire a trigger now, counting the firing if it can.
+    fn admit(&self, key: (Uuid, Uuid), row: Uuid) -> bool {
+        self.admit_at(key, row, Instant::now())
+    }
+
+    fn admit_at(&self, key: (Uuid, Uuid), row: Uuid, now: Instant) -> bool {
+        let mut triggers = self.0.lock().expect("poisoned");
+        triggers.retain(|_, firings| {
+            while firings
+                .recent
+                .front()
+                .is_some_and(|(at, _)| now.duration_since(*at) > LOOP_WINDOW)
+            {
+                firings.recent.pop_front();
+            }
+            !firings.recent.is_empty() || !firings.running.is_empty()
+        });
+        let firings = triggers.entry(key).or_default();
+        let by_row = firings.recent.iter().filter(|(_, r)| *r == row).count();
+        if firings.running.contains(&row)
+            || by_row >= MAX_ROW_FIRINGS
+            || firings.recent.len() >= MAX_FIRINGS
+        {
+            return false;
+        }
+        firings.recent.push_back((now, row));
+        firings.running.insert(row);
+        true
+    }
+
+    fn finish(&self, key: (Uuid, Uuid), row: Uuid) {
+        let mut triggers = self.0.lock().expect("poisoned");
+        if let Some(firings) = triggers.get_mut(&key) {
+            firings.running.remove(&row);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loops_are_cut_off() {
+        let guard = LoopGuard::default();
+        let key = (Uuid::new_v4(), Uuid::new_v4());
+        let row = Uuid::new_v4();
+        let start = Instant::now();
+
+        assert!(guard.admit_at(key, row, start));
+        assert!(
+            !guard.admit_at(key, row, start),
+            "a run's own writes don't fire it again"
+        );
+        assert!(guard.admit_at(key, Uuid::new_v4(), start), "other rows do");
+        guard.finish(key, row);
+
+        for _ in 1..MAX_ROW_FIRINGS {
+            assert!(guard.admit_at(key, row, start));
+            guard.finish(key, row);
+        }
+        assert!(
+            !guard.admit_at(key, row, start),
+            "too many firings by one row"
+        );
+
+        let later = start + LOOP_WINDOW + Duration::from_secs(1);
+        assert!(guard.admit_at(key, row, later), "the window moves on");
+    }
+}
@@ -1,13 +1,13 @@
 #![allow(clippy::too_many_arguments)]
 
-use std::collections::HashSet;
-use std::path::Path;
+use std::collections::{BTreeSet, HashSet};
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use anyhow::{anyhow, Context, Result};
 use bytes::Bytes;
-use futures::StreamExt;
 use futures_buffered::try_join_all;
 use iroh::blobs::Hash;
 use iroh::client::docs::Entry;
@@ -22,55 +22,173 @@ use uuid::Uuid;
 use crate::router::RouterClient;
 use crate::space::Spaces;
 
+use super::admission::ScannerCommand;
 use super::blobs::Blobs;
+use super::chaos::Chaos;
 use super::doc::{DocEventHandler, Event, EventData};
 use super::job::{
     JobContext, JobDetails, JobNameContext, JobOutput, JobResult, JobResultStatus, JobStatus,
-    JobType, ScheduledJob, JOBS_PREFIX,
+    JobType, JobUsage, ScheduledJob, JOBS_PREFIX,
 };
+use super::logs::{self, LogSink};
 use super::metrics::Metrics;
 use super::scheduler::{parse_status, SchedulerEvent};
+use super::store::{JobStore, NodeStore, Store};
 
+pub use self::capabilities::WorkerCapabilities;
+
+use self::capabilities::{capabilities_key, CAPABILITIES_KEY};
+use self::egress::EgressConfig;
 use self::executor::Executors;
+use self::pickup::{Pickup, PickupConfig};
+use self::quota::DiskQuotaConfig;
+use self::slots::{Slots, SlotsConfig};
 
 pub(crate) const WORKER_PREFIX: &str = "worker";
+/// how often [`Worker::drain`] checks for running jobs
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(100);
 
+pub mod capabilities;
+mod dns;
+pub mod egress;
 mod executor;
+pub mod pickup;
+pub mod quota;
+pub mod slots;
 
 #[derive(Clone, Debug)]
 pub struct Worker {
     author_id: AuthorId,
-    executors: Executors,
-    doc: Doc,
-    blobs: Blobs,
-    router: RouterClient,
+    store: Store,
+    runner: Runner,
     current_jobs: Arc<Mutex<HashSet<Uuid>>>,
+    slots: Slots,
+    /// when to ask for jobs, see [`pickup`]
+    pickup: Pickup,
+    /// where jobs keep their files, its disk is checked against the high watermark
+    root: PathBuf,
+    disk: DiskQuotaConfig,
+    /// whether the disk was past the high watermark when last checked
+    disk_full: Arc<AtomicBool>,
     /// If this worker will accept work.
     enabled: Arc<AtomicBool>,
+    /// Running without spaces, see [`crate::vm::headless`]
+    headless: bool,
+    /// advertised with the worker's capabilities
+    labels: BTreeSet<String>,
+    chaos: Chaos,
+}
+
+/// What runs the jobs a worker is assigned.
+#[derive(Clone, Debug)]
+enum Runner {
+    Executors {
+        executors: Executors,
+        /// where job output goes & artifacts come from
+        blobs: Blobs,
+    },
+    /// finishes every job right away without output, for simulations
+    #[cfg(test)]
+    Instant,
 }
 
 impl Worker {
     pub async fn new(
-        spaces: Spaces,
+        spaces: Option<Spaces>,
         router: RouterClient,
         author_id: AuthorId,
         doc: Doc,
         blobs: Blobs,
         root: impl AsRef<Path>,
+        egress: &EgressConfig,
+        slots: &SlotsConfig,
+        pickup: PickupConfig,
+        disk: DiskQuotaConfig,
+        labels: BTreeSet<String>,
+        admission_scanner: Option<ScannerCommand>,
+        chaos: Chaos,
     ) -> Result<Self> {
-        let executors = Executors::new(spaces.clone(), router.clone(), blobs.clone(), root).await?;
+        let headless = spaces.is_none();
+        let root = root.as_ref().to_path_buf();
+        let executors = Executors::new(
+            spaces,
+            router.clone(),
+            blobs.clone(),
+            &root,
+            egress,
+            admission_scanner,
+        )
+        .await?;
         let w = Self {
-            router,
             author_id,
-            executors,
-            doc,
-            blobs,
+            store: Store::Node(NodeStore::new(doc, blobs.clone(), router)),
+            runner: Runner::Executors { executors, blobs },
             current_jobs: Default::default(),
+            slots: Slots::new(slots.clone()),
+            pickup: Pickup::new(pickup, slots),
+            root,
+            disk,
+            disk_full: Default::default(),
             enabled: Arc::new(AtomicBool::new(true)),
+            headless,
+            labels,
+            chaos,
         };
         Ok(w)
     }
 
+    /// A worker coordinating through `store` that finishes its jobs right away.
+    #[cfg(test)]
+    pub(crate) fn simulated(author_id: AuthorId, store: Store, pickup: PickupConfig) -> Self {
+        let slots = SlotsConfig::default();
+        Self {
+            author_id,
+            store,
+            runner: Runner::Instant,
+            current_jobs: Default::default(),
+            slots: Slots::new(slots.clone()),
+            pickup: Pickup::new(pickup, &slots),
+            root: PathBuf::new(),
+            disk: DiskQuotaConfig {
+                job_quota: None,
+                high_watermark: None,
+            },
+            disk_full: Default::default(),
+            enabled: Arc::new(AtomicBool::new(true)),
+            headless: true,
+            labels: Default::default(),
+            chaos: Default::default(),
+        }
+    }
+
+    pub fn capabilities(&self) -> WorkerCapabilities {
+        let job_types = [JobType::Docker, JobType::Wasm]
+            .into_iter()
+            .filter(|t| self.supports_job_type(t))
+            .collect();
+        WorkerCapabilities::detect(job_types, self.headless, self.labels.clone())
+    }
+
+    /// Write this worker's capabilities to the workspace doc, replacing what it advertised
+    /// before.
+    pub async fn advertise(&self) -> Result<()> {
+        let capabilities = self.capabilities();
+        let data = serde_json::to_vec(&capabilities)?;
+        self.store
+            .set_bytes(
+                self.author_id,
+                capabilities_key(self.author_id).into(),
+                data.into(),
+            )
+            .await?;
+        debug!(
+            "{} advertised {:?}",
+            self.author_id.fmt_short(),
+            capabilities
+        );
+        Ok(())
+    }
+
     /// Enable this worker to accept work.
     pub fn enable(&self) {
         self.enabled.store(true, Ordering::Relaxed);
@@ -86,18 +204,60 @@ impl Worker {
         self.enabled.load(Ordering::Relaxed)
     }
 
+    /// Whether the disk jobs keep their files on has room for another job, see
+    /// [`quota`]. Errors checking leave room, the job fails later if there isn't any.
+    fn has_disk_room(&self) -> bool {
+        let Some(watermark) = self.disk.high_watermark else {
+            return true;
+        };
+        let full = match quota::over_watermark(&self.root, watermark) {
+            Ok(full) => full,
+            Err(err) => {
+                warn!("checking worker disk space: {:?}", err);
+                false
+            }
+        };
+        match (self.disk_full.swap(full, Ordering::Relaxed), full) {
+            (false, true) => warn!(
+                "worker disk is over {}% full, not taking new jobs",
+                watermark
+            ),
+            (true, false) => info!("worker disk has room again, taking new jobs"),
+            _ => {}
+        }
+        !full
+    }
+
+    /// Stop accepting work & wait up to `timeout` for the jobs already running to finish.
+    /// Returns the jobs still running when time ran out. The worker stays disabled.
+    pub async fn drain(&self, timeout: Duration) -> Vec<Uuid> {
+        self.disable();
+        let deadline = Instant::now() + timeout;
+        loop {
+            let running = self
+                .current_jobs
+                .lock()
+                .await
+                .iter()
+                .copied()
+                .collect::<Vec<_>>();
+            if running.is_empty() || Instant::now() >= deadline {
+                return running;
+            }
+            tokio::time::sleep(DRAIN_POLL_INTERVAL).await;
+        }
+    }
+
     /// Get the current scheduling status of a job on this node by id.
     pub async fn read_job_status(&self, job_id: Uuid) -> Result<JobStatus> {
         let job_id = job_id.as_u128();
         let mut status: Option<JobStatus> = None;
 
         // query from all authors
-        let q = iroh::docs::store::Query::all()
-            .key_prefix(format!("{}/status/{}/", JOBS_PREFIX, job_id));
-        let mut entries = self.doc.get_many(q).await?;
+        let prefix = format!("{}/status/{}/", JOBS_PREFIX, job_id);
+        let entries = self.store.entries(None, &prefix).await?;
 
-        while let Some(entry) = entries.next().await {
-            let entry = entry?;
+        for entry in entries {
             let key = std::str::from_utf8(entry.key())?;
 
             debug!("checking status: {}", key);
@@ -131,56 +291,74 @@ impl Worker {
             .await
     }
 
-    async fn execute_job(&self, job_id: Uuid, scheduled_job: ScheduledJob) -> Result<JobOutput> {
-        info!("executing job {}", job_id);
-
+    async fn job_context(&self, job_id: Uuid, scheduled_job: &ScheduledJob) -> Result<JobContext> {
         let author = self
-            .router
-            .authors()
-            .export(scheduled_job.author)
+            .store
+            .export_author(scheduled_job.author)
             .await?
             .ok_or_else(|| anyhow!("author not found: {}", scheduled_job.author))?;
 
-        let job_ctx = JobContext {
-            space: scheduled_job.description.space,
+        Ok(JobContext {
+            space: scheduled_job.description.space.clone(),
             author,
             id: job_id,
-            program_id: scheduled_job.description.program_id.clone(),
+            program_id: scheduled_job.description.program_id,
             environment: scheduled_job.description.environment.clone(),
             name: scheduled_job.description.name.clone(),
             name_context: JobNameContext {
                 scope: scheduled_job.scope,
             },
             artifacts: scheduled_job.description.artifacts.clone(),
-        };
-
-        self.ensure_artifact_downloads(&job_ctx).await?;
+            profile: scheduled_job.description.profile,
+            spaces: scheduled_job.description.spaces.clone(),
+            disk_quota: self.disk.job_quota,
+        })
+    }
 
-        match &scheduled_job.description.details {
-            JobDetails::Docker { image, command } => {
+    async fn execute_job(
+        executors: &Executors,
+        blobs: &Blobs,
+        job_ctx: &JobContext,
+        details: &JobDetails,
+        logs: &LogSink,
+    ) -> Result<(JobOutput, JobUsage)> {
+        info!("executing job {}", job_ctx.id);
+
+        Self::ensure_artifact_downloads(blobs, job_ctx).await?;
+
+        match details {
+            JobDetails::Docker {
+                image,
+                command,
+                network,
+            } => {
                 let job = executor::docker::Job {
                     image: image.clone(),
                     command: command.clone(),
+                    network: *network,
+                    logs: logs.clone(),
                 };
-                let res = self.executors.execute_docker(&job_ctx, job).await?;
-                Ok(JobOutput::Docker {
+                let res = executors.execute_docker(job_ctx, job).await?;
+                let output = JobOutput::Docker {
                     code: res.code,
                     stderr: res.stderr,
                     stdout: res.stdout,
-                })
+                };
+                Ok((output, res.usage))
             }
             JobDetails::Wasm { module } => {
                 let job = executor::wasm::Job {
                     module: module.clone(),
+                    logs: logs.clone(),
                 };
-                let res = self.executors.execute_wasm(&job_ctx, job).await?;
-                Ok(JobOutput::Wasm { output: res.output })
+                let res = executors.execute_wasm(job_ctx, job).await?;
+                Ok((JobOutput::Wasm { output: res.output }, res.usage))
             }
         }
     }
 
     /// Ensures all required download artifcats are available locally.
-    async fn ensure_artifact_downloads(&self, ctx: &JobContext) -> Result<()> {
+    async fn ensure_artifact_downloads(blobs: &Blobs, ctx: &JobContext) -> Result<()> {
         // Fetch required downloads
 
         let mut futures = Vec::new();
@@ -188,7 +366,7 @@ impl Worker {
             futures.push(async move {
                 debug!("fetching {:?}", artifact);
                 let name = ctx.name_context.render(&artifact.name)?;
-                self.blobs.fetch_object(&name).await?;
+                blobs.fetch_object(&name).await?;
                 anyhow::Ok(())
             });
         }
@@ -224,36 +402,32 @@ impl Worker {
 
     pub async fn get_execution_status(&self, job_id: Uuid) -> Result<ExecutionStatus> {
         let mut status = ExecutionStatus::Unknown;
-        let q = iroh::docs::store::Query::author(self.author_id)
-            .key_prefix(Self::execution_status_prefix(job_id));
-        let mut entries = self.doc.get_many(q).await?;
-        while let Some(entry) = entries.next().await {
-            let entry = entry?;
+        let prefix = Self::execution_status_prefix(job_id);
+        let entries = self.store.entries(Some(self.author_id), &prefix).await?;
+        for entry in entries {
             let key = String::from_utf8(entry.key().to_vec())
                 .map_err(|_| anyhow::anyhow!("Invalid UTF-8"))?;
 
             let read_status = Self::parse_execution_status(key)?;
-            status = match (status, read_status) {
-                (ExecutionStatus::Unknown, _) => read_status,
-                (ExecutionStatus::Requested, ExecutionStatus::Running) => read_status,
-                (ExecutionStatus::Requested, ExecutionStatus::Skipped) => read_status,
-                (ExecutionStatus::Running, ExecutionStatus::Completed) => read_status,
-                _ => status,
-            }
+            status = status.advance(read_status);
         }
 
         Ok(status)
     }
 
     fn supports_job_type(&self, t: &JobType) -> bool {
-        self.executors.supports_job_type(t)
+        match &self.runner {
+            Runner::Executors { executors, .. } => executors.supports_job_type(t),
+            #[cfg(test)]
+            Runner::Instant => true,
+        }
     }
 
     fn execution_status_prefix(id: Uuid) -> String {
         format!("{}/status/{}/", WORKER_PREFIX, id.as_u128())
     }
 
-    fn execution_status_key(id: Uuid, status: ExecutionStatus) -> String {
+    pub(super) fn execution_status_key(id: Uuid, status: ExecutionStatus) -> String {
         format!("{}/status/{}/{}", WORKER_PREFIX, id.as_u128(), status,)
     }
 
@@ -277,15 +451,47 @@ impl Worker {
         let scheduled_job = self.get_scheduled_job(job_hash).await?;
         debug!("{} job: {:?}", self.author_id.fmt_short(), scheduled_job);
 
-        if self.is_enabled() && self.supports_job_type(&scheduled_job.job_type()) {
-            self.request_job(job_id, job_hash, job_len).await?;
+        let requires = &scheduled_job.description.requires;
+        if self.is_enabled()
+            && self.supports_job_type(&scheduled_job.job_type())
+            && (requires.is_empty() || self.capabilities().satisfies(requires))
+            && self.has_disk_room()
+        {
+            let running = self.current_jobs.lock().await.len();
+            let Some(delay) = self.pickup.begin(job_id, running) else {
+                debug!("not requesting job {}, holding too many", job_id);
+                iroh_metrics::inc!(Metrics, worker_jobs_throttled);
+                return Ok(());
+            };
+            let res = self.request_after(delay, job_id, job_hash, job_len).await;
+            if !matches!(res, Ok(true)) {
+                self.pickup.settle(job_id);
+            }
+            res?;
         }
         Ok(())
     }
 
+    /// Request a job after `delay`, unless it was awarded meanwhile. Returns whether the
+    /// job was requested.
+    async fn request_after(
+        &self,
+        delay: Duration,
+        job_id: Uuid,
+        job_hash: Hash,
+        job_len: u64,
+    ) -> Result<bool> {
+        tokio::time::sleep(delay).await;
+        if !matches!(self.read_job_status(job_id).await?, JobStatus::Scheduling) {
+            debug!("not requesting job {}, no longer scheduling", job_id);
+            return Ok(false);
+        }
+        self.request_job(job_id, job_hash, job_len).await?;
+        Ok(true)
+    }
+
     async fn get_scheduled_job(&self, job_hash: Hash) -> Result<ScheduledJob> {
-        self.blobs.fetch_blob(job_hash).await?;
-        let data = self.router.blobs().read_to_bytes(job_hash).await?;
+        let data = self.store.read_blob(job_hash).await?;
         let jd = ScheduledJob::try_from(data)?;
         Ok(jd)
     }
@@ -302,7 +508,7 @@ impl Worker {
 
         let data = scheduled_job.to_bytes()?;
         let key = format!("{}/{}.json", JOBS_PREFIX, job_id.as_u128());
-        let (new_hash, new_size) = self.blobs.put_bytes(key.as_str(), data).await?;
+        let (new_hash, new_size) = self.store.put_bytes(key.as_str(), data).await?;
 
         self.mark_job_completed(job_id, new_hash, new_size).await?;
 
@@ -322,12 +528,14 @@ impl Worker {
             from, job_id, worker,
         );
 
+        self.pickup.settle(job_id);
         let is_our_job = worker == self.author_id;
         let status = self.get_execution_status(job_id).await?;
+        let action = assignment_action(is_our_job, status);
 
         if !is_our_job {
             debug!("skipping job {}, not assigned to us", job_id);
-            if status == ExecutionStatus::Requested {
+            if action == AssignmentAction::Skip {
                 // no work for us :(
                 self.skip_job(job_id, job_hash, job_len).await?;
             }
@@ -354,28 +562,66 @@ impl Worker {
         debug!("job guard: {} locked", job_id);
 
         // only execute job if we're in the requesting phase
-        if is_our_job && status == ExecutionStatus::Requested {
+        if action == AssignmentAction::Execute {
             let self2 = self.clone();
-            let node2 = self.router.clone();
 
             iroh_metrics::inc!(Metrics, worker_jobs_running);
+            let started = Instant::now();
+            let mut usage = JobUsage::default();
+            let mut preemptions = 0;
             let res = async {
                 self.set_execution_state(job_id, ExecutionStatus::Running, job_hash, job_len)
                     .await?;
 
-                let data = node2.blobs().read_to_bytes(job_hash).await?;
+                let data = self2.store.read_blob(job_hash).await?;
                 let scheduled_job = ScheduledJob::try_from(data)?;
                 let timeout: std::time::Duration = scheduled_job
                     .description
                     .timeout
                     .try_into()
                     .map_err(|_| anyhow::anyhow!("invalid timeout"))?;
-
-                let res =
-                    tokio::time::timeout(timeout, self2.execute_job(job_id, scheduled_job)).await;
+                let job_ctx = self2.job_context(job_id, &scheduled_job).await?;
+                let description = &scheduled_job.description;
+                let (executors, blobs) = match &self2.runner {
+                    Runner::Executors { executors, blobs } => (executors, blobs),
+                    #[cfg(test)]
+                    Runner::Instant => {
+                        let output = JobOutput::Wasm {
+                            output: String::new(),
+                        };
+                        return Ok(JobResultStatus::Ok(output));
+                    }
+                };
+                // one set of logs across attempts, preempted attempts' output stays in it
+                let (logs, log_writer) = logs::start(blobs.clone(), job_id);
+
+                let res = loop {
+                    let mut slot = self2
+                        .slots
+                        .acquire(job_id, description.priority, description.preemptible)
+                        .await;
+                    let execute =
+                        Self::execute_job(executors, blobs, &job_ctx, &description.details, &logs);
+                    tokio::select! {
+                        res = tokio::time::timeout(timeout, execute) => break res,
+                        _ = slot.preempted() => {
+                            warn!("job {} preempted, running it again later", job_id);
+                            iroh_metrics::inc!(Metrics, worker_jobs_preempted);
+                            preemptions += 1;
+                            executors.stop(&job_ctx, &description.details).await;
+                        }
+                    }
+                };
+                drop(logs);
+                log_writer.finish().await;
+                // uploads are stored by now, or the job failed
+                executors.remove_files(&job_ctx, &description.details).await;
 
                 match res {
-                    Ok(Ok(output)) => anyhow::Ok(JobResultStatus::Ok(output)),
+                    Ok(Ok((output, job_usage))) => {
+                        usage = job_usage;
+                        anyhow::Ok(JobResultStatus::Ok(output))
+                    }
                     Ok(Err(err)) => {
                         error!("failed to execute job: {}", err);
                         Ok(JobResultStatus::Err(format!("{:#?}", err)))
@@ -386,13 +632,26 @@ impl Worker {
                     }
                 }
             };
-            let res = match res.await {
+            let res = match self2.chaos.kill_job_after() {
+                None => res.await,
+                Some(after) => match tokio::time::timeout(after, res).await {
+                    Ok(res) => res,
+                    Err(_) => {
+                        // simulate a crash: the job stays running & no result is ever written
+                        warn!("chaos: killing worker mid-job {}", job_id);
+                        return Ok(());
+                    }
+                },
+            };
+            let res = match res {
                 Ok(res) => res,
                 Err(err) => {
                     error!("failed to execute job: {}", err);
                     JobResultStatus::Err(err.to_string())
                 }
             };
+            usage.wall_time_ms = started.elapsed().as_millis() as u64;
+            usage.preemptions = preemptions;
 
             if let Err(err) = self2
                 .set_scheduled_job_result(
@@ -401,13 +660,14 @@ impl Worker {
                     JobResult {
                         worker: Some(self2.author_id),
                         status: res,
+                        usage: Some(usage),
                     },
                 )
                 .await
             {
                 error!("unable to update job result: {:?}: {}", err, job_hash);
             }
-        } else if is_our_job {
+        } else {
             error!(
                 "worker {} ignoring job {} assigned to worker {}. we're in the {:?} phase, need to be in the requesting phase",
                 self.author_id, job_id, worker, status,
@@ -420,7 +680,7 @@ impl Worker {
     /// Returns `true` if an actual update has occured.
     async fn set_hash_iff_new(&self, key: impl Into<Bytes>, hash: Hash, size: u64) -> Result<bool> {
         let key: Bytes = key.into();
-        match self.doc.get_exact(self.author_id, &key, true).await? {
+        match self.store.get_exact(self.author_id, &key, true).await? {
             Some(entry) => {
                 if entry.content_hash() == hash {
                     return Ok(false);
@@ -430,7 +690,7 @@ impl Worker {
                 // No entry, lets set it
             }
         }
-        self.doc.set_hash(self.author_id, key, hash, size).await?;
+        self.store.set_hash(self.author_id, key, hash, size).await?;
         Ok(true)
     }
 }
@@ -508,6 +768,36 @@ pub enum ExecutionStatus {
     Completed,
 }
 
+impl ExecutionStatus {
+    /// Fold another status entry of the same job into this one.
+    pub(super) fn advance(self, next: ExecutionStatus) -> Self {
+        match (self, next) {
+            (ExecutionStatus::Unknown, _) => next,
+            (ExecutionStatus::Requested, ExecutionStatus::Running) => next,
+            (ExecutionStatus::Requested, ExecutionStatus::Skipped) => next,
+            (ExecutionStatus::Running, ExecutionStatus::Completed) => next,
+            _ => self,
+        }
+    }
+}
+
+/// What a worker does when a job it requested is assigned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum AssignmentAction {
+    Execute,
+    /// assigned to someone else, withdraw our request
+    Skip,
+    Ignore,
+}
+
+pub(super) fn assignment_action(is_our_job: bool, status: ExecutionStatus) -> AssignmentAction {
+    match (is_our_job, status) {
+        (true, ExecutionStatus::Requested) => AssignmentAction::Execute,
+        (false, ExecutionStatus::Requested) => AssignmentAction::Skip,
+        _ => AssignmentAction::Ignore,
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub(crate) enum WorkerEvent {
     ExecutionStatusChanged {
@@ -520,6 +810,10 @@ pub(crate) enum WorkerEvent {
 }
 
 pub(crate) fn parse_worker_event(key: &str, from: &NodeId, entry: &Entry) -> Option<EventData> {
+    // capabilities are read when scheduling, not followed
+    if key.starts_with(&format!("{}/{}/", WORKER_PREFIX, CAPABILITIES_KEY)) {
+        return None;
+    }
     match event_components(key) {
         Ok((job_id, status)) => Some(EventData::Worker(WorkerEvent::ExecutionStatusChanged {
             worker: AuthorId::from(from.as_bytes()),
@@ -535,7 +829,7 @@ pub(crate) fn parse_worker_event(key: &str, from: &NodeId, entry: &Entry) -> Opt
     }
 }
 
-fn event_components(key: &str) -> Result<(Uuid, ExecutionStatus)> {
+pub(super) fn event_components(key: &str) -> Result<(Uuid, ExecutionStatus)> {
     let mut parts = key.splitn(4, '/').skip(2);
 
     let job_id = parts
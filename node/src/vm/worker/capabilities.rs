@@ -0,0 +1,235 @@
+//! What a worker can run, so jobs only go to workers able to run them.
+//!
+//! Workers advertise their [`WorkerCapabilities`] in the workspace doc under
+//! `worker/capabilities/<author>`. Jobs list what they need in
+//! [`JobDescription::requires`](crate::vm::job::JobDescription::requires), eg:
+//! `requires = ["docker", "gpu", "memory>=4GiB"]`. Each entry is one of:
+//!
+//! - `docker` or `wasm`: the worker runs jobs of that type
+//! - `spaces`: the worker isn't headless, see [`crate::vm::headless`]
+//! - `cpus>=N`: the worker's machine has at least `N` cpus
+//! - `memory>=N`: at least `N` MiB of memory, or `N` with a `MiB` or `GiB` suffix
+//! - anything else is a label the worker's operator has to give it, see
+//!   [`crate::vm::config::NodeConfig::worker_labels`]
+use std::collections::BTreeSet;
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Context, Result};
+use iroh::docs::AuthorId;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::vm::job::JobType;
+use crate::vm::store::{JobStore, Store};
+
+use super::WORKER_PREFIX;
+
+pub(super) const CAPABILITIES_KEY: &str = "capabilities";
+
+/// What a worker can run, advertised to schedulers in the workspace doc.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct WorkerCapabilities {
+    pub job_types: Vec<JobType>,
+    /// no spaces on this worker, jobs that read or write them can't run here
+    pub headless: bool,
+    /// whether a docker daemon answered when the worker started
+    #[serde(default)]
+    pub docker: bool,
+    /// cpus of the worker's machine, 0 when it's unknown
+    #[serde(default)]
+    pub cpus: usize,
+    /// total memory of the worker's machine in MiB, if known
+    #[serde(default)]
+    pub memory_mib: Option<u64>,
+    /// set by the worker's operator, eg: `gpu`
+    #[serde(default)]
+    pub labels: BTreeSet<String>,
+}
+
+impl WorkerCapabilities {
+    /// Describe the machine the worker runs on.
+    pub(super) fn detect(
+        job_types: Vec<JobType>,
+        headless: bool,
+        labels: BTreeSet<String>,
+    ) -> Self {
+        let docker = job_types.contains(&JobType::Docker);
+        WorkerCapabilities {
+            job_types,
+            headless,
+            docker,
+            cpus: num_cpus::get(),
+            memory_mib: total_memory_mib(),
+            labels,
+        }
+    }
+
+    /// Whether a job requiring `requires` can run on this worker. Requirements that don't
+    /// parse are never met.
+    pub fn satisfies(&self, requires: &[String]) -> bool {
+        requires
+            .iter()
+            .all(|requirement| match Requirement::from_str(requirement) {
+                Ok(requirement) => requirement.is_met_by(self),
+                Err(err) => {
+                    warn!("{:?}", err);
+                    false
+                }
+            })
+    }
+}
+
+/// One entry of a job's requirements.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Requirement {
+    JobType(JobType),
+    Spaces,
+    Cpus(usize),
+    MemoryMib(u64),
+    Label(String),
+}
+
+impl Requirement {
+    pub fn is_met_by(&self, capabilities: &WorkerCapabilities) -> bool {
+        match self {
+            Requirement::JobType(t) => capabilities.job_types.contains(t),
+            Requirement::Spaces => !capabilities.headless,
+            Requirement::Cpus(cpus) => capabilities.cpus >= *cpus,
+            Requirement::MemoryMib(mib) => capabilities.memory_mib.is_some_and(|m| m >= *mib),
+            Requirement::Label(label) => capabilities.labels.contains(label),
+        }
+    }
+}
+
+impl FromStr for Requirement {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        if let Some(cpus) = s.strip_prefix("cpus>=") {
+            let cpus = cpus
+                .trim()
+                .parse()
+                .with_context(|| format!("bad cpu requirement {s:?}"))?;
+            return Ok(Requirement::Cpus(cpus));
+        }
+        if let Some(memory) = s.strip_prefix("memory>=") {
+            let memory = memory.trim();
+            let (number, scale) = if let Some(gib) = memory.strip_suffix("GiB") {
+                (gib, 1024)
+            } else {
+                (memory.strip_suffix("MiB").unwrap_or(memory), 1)
+            };
+            let mib: u64 = number
+                .trim()
+                .parse()
+                .with_context(|| format!("bad memory requirement {s:?}"))?;
+            return Ok(Requirement::MemoryMib(mib * scale));
+        }
+        match s {
+            "" => Err(anyhow!("empty requirement")),
+            "docker" => Ok(Requirement::JobType(JobType::Docker)),
+            "wasm" => Ok(Requirement::JobType(JobType::Wasm)),
+            "spaces" => Ok(Requirement::Spaces),
+            label if label.contains(|c: char| c.is_whitespace() || "<>=".contains(c)) => {
+                Err(anyhow!("unknown requirement {s:?}"))
+            }
+            label => Ok(Requirement::Label(label.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for Requirement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Requirement::JobType(JobType::Docker) => write!(f, "docker"),
+            Requirement::JobType(JobType::Wasm) => write!(f, "wasm"),
+            Requirement::Spaces => write!(f, "spaces"),
+            Requirement::Cpus(cpus) => write!(f, "cpus>={cpus}"),
+            Requirement::MemoryMib(mib) => write!(f, "memory>={mib}MiB"),
+            Requirement::Label(label) => write!(f, "{label}"),
+        }
+    }
+}
+
+pub(crate) fn capabilities_key(author_id: AuthorId) -> String {
+    format!("{}/{}/{}", WORKER_PREFIX, CAPABILITIES_KEY, author_id)
+}
+
+/// What `author_id` last advertised, `None` if the worker never did.
+pub(crate) async fn read(store: &Store, author_id: AuthorId) -> Result<Option<WorkerCapabilities>> {
+    let key = capabilities_key(author_id);
+    let Some(entry) = store.get_exact(author_id, key.as_bytes(), false).await? else {
+        return Ok(None);
+    };
+    let data = store.read_blob(entry.content_hash()).await?;
+    Ok(Some(serde_json::from_slice(&data)?))
+}
+
+#[cfg(target_os = "linux")]
+fn total_memory_mib() -> Option<u64> {
+    let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+    let line = meminfo.lines().find(|line| line.starts_with("MemTotal:"))?;
+    let kib: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kib / 1024)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn total_memory_mib() -> Option<u64> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_requirements() -> Result<()> {
+        for (s, expected) in [
+            ("docker", Requirement::JobType(JobType::Docker)),
+            (" wasm ", Requirement::JobType(JobType::Wasm)),
+            ("spaces", Requirement::Spaces),
+            ("cpus>=4", Requirement::Cpus(4)),
+            ("memory>=512", Requirement::MemoryMib(512)),
+            ("memory>=2GiB", Requirement::MemoryMib(2048)),
+            ("gpu", Requirement::Label("gpu".to_string())),
+        ] {
+            let requirement = Requirement::from_str(s)?;
+            assert_eq!(requirement, expected, "{s}");
+            assert_eq!(Requirement::from_str(&requirement.to_string())?, expected);
+        }
+        assert!(Requirement::from_str("").is_err());
+        assert!(Requirement::from_str("cpus>=many").is_err());
+        assert!(Requirement::from_str("memory<=4GiB").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn match_requirements() {
+        let capabilities = WorkerCapabilities {
+            job_types: vec![JobType::Docker],
+            headless: true,
+            docker: true,
+            cpus: 8,
+            memory_mib: Some(16 * 1024),
+            labels: BTreeSet::from(["gpu".to_string()]),
+        };
+        let requires = |r: &[&str]| r.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+
+        assert!(capabilities.satisfies(&[]));
+        assert!(capabilities.satisfies(&requires(&["docker", "gpu", "cpus>=8"])));
+        assert!(capabilities.satisfies(&requires(&["memory>=16GiB"])));
+        assert!(!capabilities.satisfies(&requires(&["memory>=17GiB"])));
+        assert!(!capabilities.satisfies(&requires(&["docker", "tpu"])));
+        assert!(!capabilities.satisfies(&requires(&["wasm"])));
+        assert!(!capabilities.satisfies(&requires(&["spaces"])));
+        assert!(!capabilities.satisfies(&requires(&["cpus>=lots"])));
+
+        let unknown = WorkerCapabilities {
+            memory_mib: None,
+            ..capabilities
+        };
+        assert!(!unknown.satisfies(&requires(&["memory>=1"])));
+    }
+}
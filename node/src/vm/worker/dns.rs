@@ -0,0 +1,333 @@
+//! Records the DNS lookups docker jobs make.
+//!
+//! Jobs on the egress network get a `resolv.conf` pointing at this resolver, which listens on
+//! the network's gateway. It never answers a lookup, the proxy resolves hosts for jobs that
+//! are let out, but every name a job asks for is written to the job's log & recorded against
+//! the job. Lookups are matched to jobs by the container's address on the network, which
+//! docker hands out again once a container is gone, so a job only gets the lookups from its
+//! address made after its container was created.
+use std::collections::{BTreeMap, HashMap};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use anyhow::{Context, Result};
+use tokio::net::UdpSocket;
+use tracing::{debug, warn};
+
+use crate::vm::job::DnsRecord;
+use crate::vm::logs::{LogSink, LogStream};
+use crate::vm::worker::egress::AbortOnDrop;
+
+/// port resolvers listen on, `resolv.conf` has no way to pick another
+pub const DNS_PORT: u16 = 53;
+/// largest DNS message over UDP
+const MAX_PACKET_BYTES: usize = 512;
+const HEADER_BYTES: usize = 12;
+/// lookups kept for an address no job has claimed yet
+const MAX_UNCLAIMED: usize = 256;
+const RCODE_REFUSED: u16 = 5;
+
+type Lookups = BTreeMap<(String, String), DnsRecord>;
+
+/// The lookups of the job whose container has an address.
+#[derive(Debug)]
+struct Claim {
+    lookups: Lookups,
+    logs: LogSink,
+}
+
+impl Claim {
+    fn add(&mut self, name: String, record_type: String) {
+        self.logs
+            .write(LogStream::Stderr, format!("dns: {record_type} {name}\n"));
+        self.lookups
+            .entry((name.clone(), record_type.clone()))
+            .or_insert_with(|| DnsRecord {
+                name,
+                record_type,
+                lookups: 0,
+            })
+            .lookups += 1;
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// lookups from addresses no job has claimed yet, with when they were made
+    unclaimed: HashMap<IpAddr, Vec<(Instant, String, String)>>,
+    claims: HashMap<IpAddr, Claim>,
+}
+
+#[derive(Debug, Clone)]
+pub struct DnsRecorder {
+    addr: SocketAddr,
+    state: Arc<Mutex<State>>,
+    _serve: Arc<AbortOnDrop>,
+}
+
+impl DnsRecorder {
+    pub async fn bind(addr: SocketAddr) -> Result<Self> {
+        let socket = UdpSocket::bind(addr)
+            .await
+            .with_context(|| format!("binding dns recorder to {addr}"))?;
+        let addr = socket.local_addr()?;
+        let state: Arc<Mutex<State>> = Default::default();
+        let state2 = state.clone();
+        let serve = tokio::spawn(async move {
+            let mut buf = [0u8; MAX_PACKET_BYTES];
+            loop {
+                let (len, peer) = match socket.recv_from(&mut buf).await {
+                    Ok(received) => received,
+                    Err(err) => {
+                        warn!("dns recorder receive: {:?}", err);
+                        continue;
+                    }
+                };
+                let Some(query) = Query::parse(&buf[..len]) else {
+                    debug!("ignoring malformed dns message from {}", peer);
+                    continue;
+                };
+                record(&state2, peer.ip(), &query);
+                if let Err(err) = socket.send_to(&query.refuse(&buf[..len]), peer).await {
+                    debug!("dns reply to {}: {:?}", peer, err);
+                }
+            }
+        });
+        debug!("dns recorder listening on {}", addr);
+        Ok(DnsRecorder {
+            addr,
+            state,
+            _serve: Arc::new(AbortOnDrop(serve)),
+        })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Attribute lookups from `ip` to a job until the returned lease is finished or dropped,
+    /// writing each to `logs`. Lookups made since `created`, when the job's container was
+    /// created, count even if they came before the job was registered.
+    pub fn register(&self, ip: IpAddr, created: Instant, logs: LogSink) -> DnsLease {
+        let mut state = self.state.lock().unwrap();
+        let mut claim = Claim {
+            lookups: Lookups::new(),
+            logs,
+        };
+        // anything older was looked up by whichever container had the address before
+        for (at, name, record_type) in state.unclaimed.remove(&ip).unwrap_or_default() {
+            if at >= created {
+                claim.add(name, record_type);
+            }
+        }
+        state.claims.insert(ip, claim);
+        DnsLease {
+            state: self.state.clone(),
+            ip,
+        }
+    }
+}
+
+/// A job's claim on the lookups from its container's address.
+#[derive(Debug)]
+pub struct DnsLease {
+    state: Arc<Mutex<State>>,
+    ip: IpAddr,
+}
+
+impl DnsLease {
+    /// Stop recording, returning every name the job looked up.
+    pub fn finish(self) -> Vec<DnsRecord> {
+        let claim = self.state.lock().unwrap().claims.remove(&self.ip);
+        claim
+            .map(|claim| claim.lookups.into_values().collect())
+            .unwrap_or_default()
+    }
+}
+
+impl Drop for DnsLease {
+    fn drop(&mut self) {
+        self.state.lock().unwrap().claims.remove(&self.ip);
+    }
+}
+
+fn record(state: &Mutex<State>, ip: IpAddr, query: &Query) {
+    let mut state = state.lock().unwrap();
+    let record_type = record_type(query.qtype);
+    if let Some(claim) = state.claims.get_mut(&ip) {
+        claim.add(query.name.clone(), record_type);
+        return;
+    }
+    let unclaimed = state.unclaimed.entry(ip).or_default();
+    if unclaimed.len() < MAX_UNCLAIMED {
+        unclaimed.push((Instant::now(), query.name.clone(), record_type));
+    }
+}
+
+fn record_type(qtype: u16) -> String {
+    match qtype {
+        1 => "A".to_string(),
+        2 => "NS".to_string(),
+        5 => "CNAME".to_string(),
+        12 => "PTR".to_string(),
+        15 => "MX".to_string(),
+        16 => "TXT".to_string(),
+        28 => "AAAA".to_string(),
+        33 => "SRV".to_string(),
+        65 => "HTTPS".to_string(),
+        other => format!("TYPE{other}"),
+    }
+}
+
+/// The first question of a DNS query.
+#[derive(Debug, PartialEq, Eq)]
+struct Query {
+    name: String,
+    qtype: u16,
+    /// where the question ends in the message
+    end: usize,
+}
+
+impl Query {
+    fn parse(msg: &[u8]) -> Option<Self> {
+        if msg.len() < HEADER_BYTES {
+            return None;
+        }
+        let flags = u16::from_be_bytes([msg[2], msg[3]]);
+        let questions = u16::from_be_bytes([msg[4], msg[5]]);
+        // answers & empty queries aren't lookups
+        if flags & 0x8000 != 0 || questions == 0 {
+            return None;
+        }
+
+        let mut labels = Vec::new();
+        let mut pos = HEADER_BYTES;
+        loop {
+            let len = *msg.get(pos)? as usize;
+            pos += 1;
+            if len == 0 {
+                break;
+            }
+            // questions aren't compressed
+            if len & 0xc0 != 0 {
+                return None;
+            }
+            let label = msg.get(pos..pos + len)?;
+            labels.push(String::from_utf8_lossy(label).to_lowercase());
+            pos += len;
+        }
+        let qtype = u16::from_be_bytes([*msg.get(pos)?, *msg.get(pos + 1)?]);
+        // skip the class
+        msg.get(pos + 2..pos + 4)?;
+        Some(Query {
+            name: labels.join("."),
+            qtype,
+            end: pos + 4,
+        })
+    }
+
+    /// A reply to `msg` refusing to answer.
+    fn refuse(&self, msg: &[u8]) -> Vec<u8> {
+        let request_flags = u16::from_be_bytes([msg[2], msg[3]]);
+        // keep the opcode & recursion desired bits
+        let flags = 0x8000 | (request_flags & 0x7900) | RCODE_REFUSED;
+        let mut reply = Vec::with_capacity(self.end);
+        reply.extend_from_slice(&msg[0..2]);
+        reply.extend_from_slice(&flags.to_be_bytes());
+        reply.extend_from_slice(&1u16.to_be_bytes());
+        reply.extend_from_slice(&[0; 6]);
+        reply.extend_from_slice(&msg[HEADER_BYTES..self.end]);
+        reply
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(id: u16, name: &str, qtype: u16) -> Vec<u8> {
+        let mut msg = id.to_be_bytes().to_vec();
+        // recursion desired, one question
+        msg.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+        for label in name.split('.') {
+            msg.push(label.len() as u8);
+            msg.extend_from_slice(label.as_bytes());
+        }
+        msg.push(0);
+        msg.extend_from_slice(&qtype.to_be_bytes());
+        msg.extend_from_slice(&1u16.to_be_bytes());
+        msg
+    }
+
+    #[test]
+    fn parses_and_refuses_queries() {
+        let msg = query(0xbeef, "API.github.com", 28);
+        let parsed = Query::parse(&msg).unwrap();
+        assert_eq!(parsed.name, "api.github.com");
+        assert_eq!(record_type(parsed.qtype), "AAAA");
+        assert_eq!(parsed.end, msg.len());
+
+        let reply = parsed.refuse(&msg);
+        assert_eq!(&reply[0..2], &[0xbe, 0xef]);
+        assert_eq!(u16::from_be_bytes([reply[2], reply[3]]), 0x8105);
+        assert_eq!(&reply[HEADER_BYTES..], &msg[HEADER_BYTES..]);
+
+        assert!(Query::parse(&msg[..msg.len() - 1]).is_none());
+        assert!(Query::parse(&reply).is_none());
+    }
+
+    #[tokio::test]
+    async fn records_lookups_per_address() -> Result<()> {
+        let recorder = DnsRecorder::bind("127.0.0.1:0".parse()?).await?;
+        let socket = UdpSocket::bind("127.0.0.1:0").await?;
+        let mut buf = [0u8; MAX_PACKET_BYTES];
+
+        // left over from a container that had the address before
+        socket
+            .send_to(&query(1, "stale.example.org", 1), recorder.addr())
+            .await?;
+        socket.recv_from(&mut buf).await?;
+        let created = Instant::now();
+        // sent before the job is registered
+        socket
+            .send_to(&query(1, "example.com", 1), recorder.addr())
+            .await?;
+        socket.recv_from(&mut buf).await?;
+        let (logs, output) = crate::vm::logs::capture();
+        let lease = recorder.register(socket.local_addr()?.ip(), created, logs);
+        for id in 2..4 {
+            socket
+                .send_to(&query(id, "example.com", 1), recorder.addr())
+                .await?;
+            let (len, _) = socket.recv_from(&mut buf).await?;
+            assert_eq!(buf[3] & 0x0f, RCODE_REFUSED as u8, "{:?}", &buf[..len]);
+        }
+        socket
+            .send_to(&query(4, "tracker.example.net", 28), recorder.addr())
+            .await?;
+        socket.recv_from(&mut buf).await?;
+
+        let records = lease.finish();
+        assert_eq!(
+            records,
+            vec![
+                DnsRecord {
+                    name: "example.com".to_string(),
+                    record_type: "A".to_string(),
+                    lookups: 3,
+                },
+                DnsRecord {
+                    name: "tracker.example.net".to_string(),
+                    record_type: "AAAA".to_string(),
+                    lookups: 1,
+                },
+            ]
+        );
+        assert!(recorder.state.lock().unwrap().claims.is_empty());
+        let logged: Vec<_> = output.drain().map(|(_, line)| line).collect();
+        assert_eq!(logged.len(), 4);
+        assert_eq!(logged[3], "dns: AAAA tracker.example.net\n");
+        Ok(())
+    }
+}
@@ -0,0 +1,689 @@
+//! Outbound HTTP proxy for docker jobs.
+//!
+//! Docker jobs run on an internal network without a route out, their only way out is this
+//! proxy. Each job gets a token, passed as the proxy username in `HTTP_PROXY` &
+//! `HTTPS_PROXY`, that ties its requests to an allowlist of hosts & a rate limit configured
+//! from the network capabilities of the job's program. Every destination a job tries to
+//! reach is recorded, whether it was let through or not.
+//!
+//! Allowlisted names are resolved by the proxy, which only connects to the addresses it
+//! checked. Loopback, private & link-local addresses are refused unless the config allows
+//! them, so an allowlisted name can't be pointed at the node's LAN or a cloud metadata
+//! service. The rate limit is per job, every connection of the job draws from it. A `CONNECT`
+//! tunnel counts as one request, however many requests travel through it.
+use std::collections::{BTreeMap, HashMap};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use anyhow::{anyhow, bail, Context, Result};
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+use crate::vm::job::EgressRecord;
+
+/// largest request head the proxy reads before giving up on a connection
+const MAX_HEAD_BYTES: usize = 16 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EgressConfig {
+    /// Route docker jobs through the proxy. Disabled, jobs have unrestricted network.
+    pub enabled: bool,
+    /// Address the proxy listens on. Defaults to the gateway of `network` with a random
+    /// port, so only containers attached to the network can reach it.
+    pub listen: Option<String>,
+    /// internal docker network jobs are attached to, created if missing
+    pub network: String,
+    /// requests a job may make per minute, unless its program asks for fewer
+    pub requests_per_minute: u32,
+    /// Let jobs reach loopback, private & link-local addresses, eg: services on the LAN.
+    pub allow_private: bool,
+    /// Record the names jobs look up, see [`super::dns`]. Needs permission to listen on port
+    /// 53 of the network's gateway.
+    pub capture_dns: bool,
+}
+
+impl Default for EgressConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            listen: None,
+            network: "squiggle-egress".to_string(),
+            requests_per_minute: 120,
+            allow_private: false,
+            capture_dns: true,
+        }
+    }
+}
+
+/// What a single job may reach.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EgressPolicy {
+    /// Hosts the job may connect to. `*.example.com` matches subdomains of example.com, a
+    /// port limits an entry to that port.
+    pub allow: Vec<String>,
+    pub requests_per_minute: u32,
+}
+
+impl EgressPolicy {
+    pub fn allows(&self, host: &str, port: u16) -> bool {
+        let host = host.trim_end_matches('.').to_lowercase();
+        self.allow.iter().any(|entry| {
+            let (pattern, entry_port) = match entry.rsplit_once(':') {
+                Some((pattern, p)) => match p.parse::<u16>() {
+                    Ok(p) => (pattern, Some(p)),
+                    Err(_) => (entry.as_str(), None),
+                },
+                None => (entry.as_str(), None),
+            };
+            if entry_port.is_some_and(|p| p != port) {
+                return false;
+            }
+            match pattern.strip_prefix("*.") {
+                Some(domain) => host.ends_with(&format!(".{domain}")),
+                None => host == pattern,
+            }
+        })
+    }
+}
+
+/// Token bucket refilled continuously, holding at most a minute's worth of requests.
+#[derive(Debug)]
+struct RateLimiter {
+    capacity: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    fn new(per_minute: u32) -> Self {
+        let capacity = per_minute as f64;
+        RateLimiter {
+            capacity,
+            tokens: capacity,
+            updated: Instant::now(),
+        }
+    }
+
+    fn try_acquire(&mut self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.capacity / 60.0).min(self.capacity);
+        self.updated = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[derive(Debug)]
+struct JobEgress {
+    job: Uuid,
+    policy: EgressPolicy,
+    destinations: BTreeMap<String, EgressRecord>,
+}
+
+/// Leases by token & the rate limiters of the jobs holding them.
+#[derive(Debug, Default)]
+struct State {
+    leases: HashMap<String, JobEgress>,
+    /// by job, shared by every lease of the job & dropped with its last one
+    limiters: HashMap<Uuid, RateLimiter>,
+}
+
+impl State {
+    fn remove(&mut self, token: &str) -> Option<JobEgress> {
+        let lease = self.leases.remove(token)?;
+        if !self.leases.values().any(|other| other.job == lease.job) {
+            self.limiters.remove(&lease.job);
+        }
+        Some(lease)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Decision {
+    Allow,
+    Denied,
+    RateLimited,
+    UnknownJob,
+}
+
+type Jobs = Arc<Mutex<State>>;
+
+#[derive(Debug, Clone)]
+pub struct EgressProxy {
+    addr: SocketAddr,
+    jobs: Jobs,
+    _accept: Arc<AbortOnDrop>,
+}
+
+#[derive(Debug)]
+pub(crate) struct AbortOnDrop(pub(crate) JoinHandle<()>);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+impl EgressProxy {
+    /// Listen on `addr`. Destinations that aren't public are refused unless `allow_private`.
+    pub async fn bind(addr: &str, allow_private: bool) -> Result<Self> {
+        let listener = TcpListener::bind(addr)
+            .await
+            .with_context(|| format!("binding egress proxy to {addr}"))?;
+        let addr = listener.local_addr()?;
+        let jobs: Jobs = Default::default();
+        let jobs2 = jobs.clone();
+        let accept = tokio::spawn(async move {
+            loop {
+                let (conn, peer) = match listener.accept().await {
+                    Ok(conn) => conn,
+                    Err(err) => {
+                        warn!("egress proxy accept: {:?}", err);
+                        continue;
+                    }
+                };
+                let jobs = jobs2.clone();
+                tokio::spawn(async move {
+                    if let Err(err) = handle(jobs, conn, allow_private).await {
+                        debug!("egress proxy connection from {}: {:#}", peer, err);
+                    }
+                });
+            }
+        });
+        debug!("egress proxy listening on {}", addr);
+        Ok(EgressProxy {
+            addr,
+            jobs,
+            _accept: Arc::new(AbortOnDrop(accept)),
+        })
+    }
+
+    pub fn port(&self) -> u16 {
+        self.addr.port()
+    }
+
+    /// Let `job` use the proxy until the returned lease is finished or dropped.
+    pub fn register(&self, job: Uuid, policy: EgressPolicy) -> EgressLease {
+        let token = hex::encode(rand::random::<[u8; 16]>());
+        let mut state = self.jobs.lock().unwrap();
+        state
+            .limiters
+            .entry(job)
+            .or_insert_with(|| RateLimiter::new(policy.requests_per_minute));
+        let lease = JobEgress {
+            job,
+            policy,
+            destinations: BTreeMap::new(),
+        };
+        state.leases.insert(token.clone(), lease);
+        drop(state);
+        EgressLease {
+            jobs: self.jobs.clone(),
+            token,
+        }
+    }
+}
+
+/// A job's access to the proxy.
+#[derive(Debug)]
+pub struct EgressLease {
+    jobs: Jobs,
+    token: String,
+}
+
+impl EgressLease {
+    /// Proxy url for the job, `host` is the proxy's address as seen from the job.
+    pub fn proxy_url(&self, host: &str, port: u16) -> String {
+        format!("http://{}@{host}:{port}", self.token)
+    }
+
+    /// Revoke access, returning everywhere the job tried to go.
+    pub fn finish(self) -> Vec<EgressRecord> {
+        let job = self.jobs.lock().unwrap().remove(&self.token);
+        job.map(|job| job.destinations.into_values().collect())
+            .unwrap_or_default()
+    }
+}
+
+impl Drop for EgressLease {
+    fn drop(&mut self) {
+        self.jobs.lock().unwrap().remove(&self.token);
+    }
+}
+
+/// Whether jobs may connect to `ip` without `allow_private`: no loopback, private,
+/// link-local or otherwise non-routable addresses.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                // "this network" & carrier-grade NAT
+                || a == 0
+                || (a == 100 && (64..128).contains(&b)))
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public(IpAddr::V4(ip)),
+            None => {
+                let first = ip.segments()[0];
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    // unique local fc00::/7 & link-local fe80::/10
+                    || (first & 0xfe00) == 0xfc00
+                    || (first & 0xffc0) == 0xfe80)
+            }
+        },
+    }
+}
+
+/// Check a request for `host`, which resolved to `addrs`, against its job's policy & record
+/// it.
+fn authorize(
+    jobs: &Jobs,
+    token: &str,
+    host: &str,
+    port: u16,
+    addrs: &[SocketAddr],
+    allow_private: bool,
+) -> Decision {
+    let mut state = jobs.lock().unwrap();
+    let State { leases, limiters } = &mut *state;
+    let Some(job) = leases.get_mut(token) else {
+        return Decision::UnknownJob;
+    };
+    let reachable = allow_private || addrs.iter().all(|addr| is_public(addr.ip()));
+    let decision = if !job.policy.allows(host, port) || !reachable {
+        Decision::Denied
+    } else if !limiters
+        .get_mut(&job.job)
+        .is_some_and(|limiter| limiter.try_acquire(Instant::now()))
+    {
+        Decision::RateLimited
+    } else {
+        Decision::Allow
+    };
+    let destination = format!("{}:{port}", host.to_lowercase());
+    let record = job
+        .destinations
+        .entry(destination.clone())
+        .or_insert_with(|| EgressRecord {
+            destination,
+            ..Default::default()
+        });
+    match decision {
+        Decision::Allow => record.requests += 1,
+        _ => record.denied += 1,
+    }
+    decision
+}
+
+fn record_bytes(jobs: &Jobs, token: &str, host: &str, port: u16, sent: u64, received: u64) {
+    let mut state = jobs.lock().unwrap();
+    let destination = format!("{}:{port}", host.to_lowercase());
+    if let Some(record) = state
+        .leases
+        .get_mut(token)
+        .and_then(|job| job.destinations.get_mut(&destination))
+    {
+        record.bytes_sent += sent;
+        record.bytes_received += received;
+    }
+}
+
+async fn handle(jobs: Jobs, mut client: TcpStream, allow_private: bool) -> Result<()> {
+    let (head, rest) = read_head(&mut client).await?;
+    let request = RequestHead::parse(&head)?;
+    let Some(token) = request.token() else {
+        return respond(&mut client, "407 Proxy Authentication Required").await;
+    };
+    if !jobs.lock().unwrap().leases.contains_key(&token) {
+        return respond(&mut client, "407 Proxy Authentication Required").await;
+    }
+    let (host, port) = request.destination()?;
+    // connect to the addresses that were checked, a second lookup could answer differently
+    let addrs: Vec<SocketAddr> = match tokio::net::lookup_host((host.as_str(), port)).await {
+        Ok(addrs) => addrs.collect(),
+        Err(err) => {
+            debug!("egress to {}:{} failed to resolve: {:?}", host, port, err);
+            return respond(&mut client, "502 Bad Gateway").await;
+        }
+    };
+    match authorize(&jobs, &token, &host, port, &addrs, allow_private) {
+        Decision::Allow => {}
+        Decision::Denied => {
+            debug!("egress to {}:{} denied", host, port);
+            return respond(&mut client, "403 Forbidden").await;
+        }
+        Decision::RateLimited => return respond(&mut client, "429 Too Many Requests").await,
+        Decision::UnknownJob => {
+            return respond(&mut client, "407 Proxy Authentication Required").await
+        }
+    }
+
+    let mut upstream = match TcpStream::connect(addrs.as_slice()).await {
+        Ok(upstream) => upstream,
+        Err(err) => {
+            debug!("egress to {}:{} failed: {:?}", host, port, err);
+            return respond(&mut client, "502 Bad Gateway").await;
+        }
+    };
+    if request.method == "CONNECT" {
+        client
+            .write_all(b"HTTP/1.1 200 Connection Established\r\n\r\n")
+            .await?;
+    } else {
+        upstream
+            .write_all(request.origin_form()?.as_bytes())
+            .await?;
+    }
+    upstream.write_all(&rest).await?;
+    let (sent, received) = tokio::io::copy_bidirectional(&mut client, &mut upstream).await?;
+    let sent = sent + rest.len() as u64;
+    record_bytes(&jobs, &token, &host, port, sent, received);
+    Ok(())
+}
+
+async fn respond(client: &mut TcpStream, status: &str) -> Result<()> {
+    let res = format!("HTTP/1.1 {status}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+    client.write_all(res.as_bytes()).await?;
+    Ok(())
+}
+
+/// Read up to the end of the request head. Returns the head & anything read past it.
+async fn read_head(client: &mut TcpStream) -> Result<(Vec<u8>, Vec<u8>)> {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 2048];
+    loop {
+        if let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            let rest = buf.split_off(end + 4);
+            return Ok((buf, rest));
+        }
+        if buf.len() > MAX_HEAD_BYTES {
+            bail!("request head too large");
+        }
+        let n = client.read(&mut chunk).await?;
+        if n == 0 {
+            bail!("connection closed before request head");
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    }
+}
+
+#[derive(Debug)]
+struct RequestHead {
+    method: String,
+    target: String,
+    version: String,
+    headers: Vec<(String, String)>,
+}
+
+impl RequestHead {
+    fn parse(head: &[u8]) -> Result<Self> {
+        let head = std::str::from_utf8(head).context("request head isn't utf-8")?;
+        let mut lines = head.split("\r\n");
+        let mut request_line = lines.next().unwrap_or_default().split(' ');
+        let (Some(method), Some(target), Some(version)) = (
+            request_line.next(),
+            request_line.next(),
+            request_line.next(),
+        ) else {
+            bail!("malformed request line");
+        };
+        let headers = lines
+            .filter(|line| !line.is_empty())
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+            .collect();
+        Ok(RequestHead {
+            method: method.to_string(),
+            target: target.to_string(),
+            version: version.to_string(),
+            headers,
+        })
+    }
+
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The job token, the username of `Proxy-Authorization: Basic ...`
+    fn token(&self) -> Option<String> {
+        let credentials = self.header("proxy-authorization")?.strip_prefix("Basic ")?;
+        let decoded = base64::engine::general_purpose::STANDARD
+            .decode(credentials.trim())
+            .ok()?;
+        let decoded = String::from_utf8(decoded).ok()?;
+        let user = decoded.split(':').next()?;
+        (!user.is_empty()).then(|| user.to_string())
+    }
+
+    fn destination(&self) -> Result<(String, u16)> {
+        if self.method == "CONNECT" {
+            let (host, port) = self
+                .target
+                .rsplit_once(':')
+                .ok_or_else(|| anyhow!("CONNECT target without a port"))?;
+            let host = host.trim_start_matches('[').trim_end_matches(']');
+            return Ok((host.to_string(), port.parse()?));
+        }
+        let url = url::Url::parse(&self.target).context("proxy requests need absolute urls")?;
+        anyhow::ensure!(
+            url.scheme() == "http",
+            "unsupported scheme {}",
+            url.scheme()
+        );
+        let host = url.host_str().context("url without a host")?.to_string();
+        Ok((host, url.port_or_known_default().unwrap_or(80)))
+    }
+
+    /// The head to send upstream for plain HTTP. Proxy headers are dropped & the connection
+    /// is closed after one request, so every request passes the policy check.
+    fn origin_form(&self) -> Result<String> {
+        let url = url::Url::parse(&self.target)?;
+        let mut path = url.path().to_string();
+        if let Some(query) = url.query() {
+            path = format!("{path}?{query}");
+        }
+        let mut head = format!("{} {path} {}\r\n", self.method, self.version);
+        for (name, value) in self.headers.iter() {
+            let name_lower = name.to_lowercase();
+            if name_lower.starts_with("proxy-") || name_lower == "connection" {
+                continue;
+            }
+            head.push_str(&format!("{name}: {value}\r\n"));
+        }
+        if self.header("host").is_none() {
+            head.push_str(&format!("Host: {}\r\n", url.authority()));
+        }
+        head.push_str("Connection: close\r\n\r\n");
+        Ok(head)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn policy_matches_hosts() {
+        let policy = EgressPolicy {
+            allow: vec![
+                "api.github.com".to_string(),
+                "*.example.com".to_string(),
+                "localhost:8080".to_string(),
+            ],
+            requests_per_minute: 10,
+        };
+        assert!(policy.allows("api.github.com", 443));
+        assert!(policy.allows("API.github.com.", 80));
+        assert!(!policy.allows("github.com", 443));
+        assert!(policy.allows("cdn.example.com", 443));
+        assert!(!policy.allows("example.com", 443));
+        assert!(policy.allows("localhost", 8080));
+        assert!(!policy.allows("localhost", 22));
+    }
+
+    #[test]
+    fn private_addresses() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fd00:ec2::254",
+            "fe80::1",
+            "::ffff:10.0.0.1",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{ip}");
+        }
+        for ip in ["1.1.1.1", "140.82.112.3", "2606:4700::1111"] {
+            assert!(is_public(ip.parse().unwrap()), "{ip}");
+        }
+    }
+
+    #[test]
+    fn rate_limiter_refills() {
+        let start = Instant::now();
+        let mut limiter = RateLimiter::new(60);
+        limiter.updated = start;
+        for _ in 0..60 {
+            assert!(limiter.try_acquire(start));
+        }
+        assert!(!limiter.try_acquire(start));
+        assert!(limiter.try_acquire(start + Duration::from_secs(1)));
+        assert!(!limiter.try_acquire(start + Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn parses_proxy_requests() -> Result<()> {
+        let auth = base64::engine::general_purpose::STANDARD.encode("token123:");
+        let head = format!(
+            "GET http://example.com/a?b=c HTTP/1.1\r\n\
+             Host: example.com\r\n\
+             Proxy-Authorization: Basic {auth}\r\n\
+             Proxy-Connection: keep-alive\r\n\r\n"
+        );
+        let request = RequestHead::parse(head.as_bytes())?;
+        assert_eq!(request.token().as_deref(), Some("token123"));
+        assert_eq!(request.destination()?, ("example.com".to_string(), 80));
+        assert_eq!(
+            request.origin_form()?,
+            "GET /a?b=c HTTP/1.1\r\nHost: example.com\r\nConnection: close\r\n\r\n"
+        );
+
+        let connect = RequestHead::parse(b"CONNECT api.github.com:443 HTTP/1.1\r\n\r\n")?;
+        assert_eq!(connect.destination()?, ("api.github.com".to_string(), 443));
+        assert_eq!(connect.token(), None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn proxies_allowed_destinations() -> Result<()> {
+        let upstream = TcpListener::bind("127.0.0.1:0").await?;
+        let upstream_port = upstream.local_addr()?.port();
+        tokio::spawn(async move {
+            while let Ok((mut conn, _)) = upstream.accept().await {
+                let _ = read_head(&mut conn).await;
+                let _ = conn
+                    .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok")
+                    .await;
+            }
+        });
+
+        // the upstream is on loopback
+        let proxy = EgressProxy::bind("127.0.0.1:0", true).await?;
+        let policy = EgressPolicy {
+            allow: vec![format!("127.0.0.1:{upstream_port}")],
+            requests_per_minute: 1,
+        };
+        let job = Uuid::new_v4();
+        let lease = proxy.register(job, policy.clone());
+        let request = |lease: &EgressLease, target: String| {
+            let auth =
+                base64::engine::general_purpose::STANDARD.encode(format!("{}:", lease.token));
+            let port = proxy.port();
+            async move {
+                let mut conn = TcpStream::connect(("127.0.0.1", port)).await?;
+                let head =
+                    format!("GET {target} HTTP/1.1\r\nProxy-Authorization: Basic {auth}\r\n\r\n");
+                conn.write_all(head.as_bytes()).await?;
+                let mut res = String::new();
+                conn.read_to_string(&mut res).await?;
+                anyhow::Ok(res)
+            }
+        };
+
+        let ok = request(&lease, format!("http://127.0.0.1:{upstream_port}/")).await?;
+        assert!(ok.starts_with("HTTP/1.1 200"), "{ok}");
+        let limited = request(&lease, format!("http://127.0.0.1:{upstream_port}/")).await?;
+        assert!(limited.starts_with("HTTP/1.1 429"), "{limited}");
+        let denied = request(&lease, "http://127.0.0.2:9/".to_string()).await?;
+        assert!(denied.starts_with("HTTP/1.1 403"), "{denied}");
+        // another lease of the same job shares its limit
+        let second = proxy.register(job, policy.clone());
+        let limited = request(&second, format!("http://127.0.0.1:{upstream_port}/")).await?;
+        assert!(limited.starts_with("HTTP/1.1 429"), "{limited}");
+        drop(second);
+
+        // private addresses are refused unless the proxy allows them
+        let strict = EgressProxy::bind("127.0.0.1:0", false).await?;
+        let strict_lease = strict.register(Uuid::new_v4(), policy);
+        let auth =
+            base64::engine::general_purpose::STANDARD.encode(format!("{}:", strict_lease.token));
+        let mut conn = TcpStream::connect(("127.0.0.1", strict.port())).await?;
+        let head = format!(
+            "GET http://127.0.0.1:{upstream_port}/ HTTP/1.1\r\nProxy-Authorization: Basic {auth}\r\n\r\n"
+        );
+        conn.write_all(head.as_bytes()).await?;
+        let mut res = String::new();
+        conn.read_to_string(&mut res).await?;
+        assert!(res.starts_with("HTTP/1.1 403"), "{res}");
+
+        // bytes are recorded once both sides of a connection are closed
+        for _ in 0..100 {
+            let recorded = proxy.jobs.lock().unwrap().leases[&lease.token]
+                .destinations
+                .values()
+                .any(|record| record.bytes_received > 0);
+            if recorded {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let records = lease.finish();
+        assert_eq!(records.len(), 2);
+        let upstream = &records[0];
+        assert_eq!(upstream.destination, format!("127.0.0.1:{upstream_port}"));
+        assert_eq!((upstream.requests, upstream.denied), (1, 1));
+        assert!(upstream.bytes_received > 0);
+        assert_eq!((records[1].requests, records[1].denied), (0, 1));
+        Ok(())
+    }
+}
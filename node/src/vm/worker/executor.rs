@@ -1,12 +1,14 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use anyhow::{bail, Result};
 use tracing::{debug, warn};
 
 use crate::router::RouterClient;
 use crate::space::Spaces;
+use crate::vm::admission::ScannerCommand;
 use crate::vm::blobs::Blobs;
-use crate::vm::job::{JobContext, JobType};
+use crate::vm::job::{JobContext, JobDetails, JobType};
+use crate::vm::worker::egress::EgressConfig;
 
 use self::{docker::Docker, wasm::WasmExecutor};
 
@@ -25,37 +27,56 @@ pub trait Executor {
 
 #[derive(Debug, Clone)]
 pub struct Executors {
+    /// holds a directory for each executor's jobs
+    root: PathBuf,
     docker: Option<Docker>,
-    wasm: WasmExecutor,
+    /// wasm jobs read & write their space, workers without spaces can't run them
+    wasm: Option<WasmExecutor>,
 }
 
 impl Executors {
     pub async fn new(
-        spaces: Spaces,
+        spaces: Option<Spaces>,
         router: RouterClient,
         blobs: Blobs,
         root: impl AsRef<Path>,
+        egress: &EgressConfig,
+        admission_scanner: Option<ScannerCommand>,
     ) -> Result<Self> {
-        let docker_root = root.as_ref().join("docker");
-        let docker =
-            match Docker::new(spaces.clone(), router.clone(), blobs.clone(), docker_root).await {
-                Ok(docker) => Some(docker),
-                Err(err) => {
-                    debug!("docker error: {:?}", err);
-                    warn!("Docker is not available, worker capability will not be started");
-                    None
-                }
-            };
-        let wasm_root = root.as_ref().join("wasm");
-        let wasm = WasmExecutor::new(spaces, router, blobs, wasm_root).await?;
-
-        Ok(Self { docker, wasm })
+        let root = root.as_ref().to_path_buf();
+        let docker_root = job_root(&root, JobType::Docker);
+        let docker = Docker::new(
+            spaces.clone(),
+            router.clone(),
+            blobs.clone(),
+            docker_root,
+            egress,
+            admission_scanner.clone(),
+        )
+        .await;
+        let docker = match docker {
+            Ok(docker) => Some(docker),
+            Err(err) => {
+                debug!("docker error: {:?}", err);
+                warn!("Docker is not available, worker capability will not be started");
+                None
+            }
+        };
+        let wasm = match spaces {
+            Some(spaces) => {
+                let wasm_root = job_root(&root, JobType::Wasm);
+                Some(WasmExecutor::new(spaces, router, blobs, wasm_root, admission_scanner).await?)
+            }
+            None => None,
+        };
+
+        Ok(Self { root, docker, wasm })
     }
 
     pub fn supports_job_type(&self, t: &JobType) -> bool {
         match t {
             JobType::Docker => self.docker.is_some(),
-            JobType::Wasm => true,
+            JobType::Wasm => self.wasm.is_some(),
         }
     }
 
@@ -72,6 +93,40 @@ impl Executors {
     }
 
     pub async fn execute_wasm(&self, ctx: &JobContext, job: wasm::Job) -> Result<wasm::Report> {
-        self.wasm.execute(ctx, job).await
+        let Some(ref wasm) = self.wasm else {
+            bail!("no wasm executor available");
+        };
+
+        wasm.execute(ctx, job).await
+    }
+
+    /// Clean up after a job that was stopped before it finished. Wasm jobs run to completion
+    /// once started, so there's nothing to stop.
+    pub async fn stop(&self, ctx: &JobContext, details: &JobDetails) {
+        if let (JobDetails::Docker { .. }, Some(docker)) = (details, &self.docker) {
+            docker.stop(ctx).await;
+        }
+    }
+
+    /// Delete the files of a job that's done, and its run's directory once it's empty.
+    pub async fn remove_files(&self, ctx: &JobContext, details: &JobDetails) {
+        let path = ctx.job_path(job_root(&self.root, details.typ()));
+        if let Err(err) = tokio::fs::remove_dir_all(&path).await {
+            if err.kind() != std::io::ErrorKind::NotFound {
+                warn!("removing files of job {}: {:?}", ctx.id, err);
+            }
+        }
+        if let Some(scope) = path.parent() {
+            // fails while other jobs of the run still have files
+            let _ = tokio::fs::remove_dir(scope).await;
+        }
+    }
+}
+
+/// Where an executor keeps the files of its jobs.
+fn job_root(root: &Path, job_type: JobType) -> PathBuf {
+    match job_type {
+        JobType::Docker => root.join("docker"),
+        JobType::Wasm => root.join("wasm"),
     }
 }
@@ -1,48 +1,200 @@
+use std::net::{IpAddr, SocketAddr};
 use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use anyhow::{Context, Result};
-use bollard::container::LogOutput;
+use bollard::container::{LogOutput, StatsOptions, TopOptions};
+use bollard::network::{CreateNetworkOptions, InspectNetworkOptions};
 use futures::StreamExt;
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
 
 use crate::router::RouterClient;
+use crate::space::programs::CapabilityKind;
 use crate::space::Spaces;
 
 use crate::vm::{
+    admission::{self, ScannerCommand},
     blobs::Blobs,
     docker::{delete_container, get_docker, pull_docker_image, stop_container},
-    job::JobContext,
+    job::{DockerNetwork, JobContext, JobUsage},
+    logs::{LogSink, LogStream},
+    profile::{add_process_sample, ProcessSample, Profile},
+    worker::dns::{DnsRecorder, DNS_PORT},
+    worker::egress::{EgressConfig, EgressPolicy, EgressProxy},
 };
 
 use super::Executor;
 
 #[derive(Debug, Clone)]
 pub struct Docker {
-    // spaces: Spaces,
+    spaces: Option<Spaces>,
     router: RouterClient,
     docker: bollard::Docker,
     blobs: Blobs,
     /// Root folder to store shared files in
     root: PathBuf,
+    /// None if jobs have unrestricted network
+    egress: Option<Egress>,
+    /// run on every artifact before it's uploaded
+    scanner: Option<ScannerCommand>,
+}
+
+/// The proxy docker jobs reach the outside world through.
+#[derive(Debug, Clone)]
+struct Egress {
+    proxy: EgressProxy,
+    /// internal network jobs are attached to
+    network: String,
+    /// the proxy's address as seen from the network
+    gateway: String,
+    requests_per_minute: u32,
+    /// None if lookups aren't recorded
+    dns: Option<DnsRecorder>,
 }
 
 impl Docker {
     pub async fn new(
-        _spaces: Spaces,
+        spaces: Option<Spaces>,
         router: RouterClient,
         blobs: Blobs,
         root: PathBuf,
+        egress: &EgressConfig,
+        scanner: Option<ScannerCommand>,
     ) -> Result<Self> {
         let docker = get_docker().await?;
         tokio::fs::create_dir_all(&root).await?;
         let root = root.canonicalize()?;
+        let egress = match egress.enabled {
+            true => Some(Egress::open(&docker, egress).await?),
+            false => None,
+        };
 
         Ok(Self {
-            // spaces,
+            spaces,
             router,
             docker,
             blobs,
             root,
+            egress,
+            scanner,
+        })
+    }
+
+    /// Stop & remove the container of a job that was interrupted, so it can be run again.
+    pub async fn stop(&self, ctx: &JobContext) {
+        let container_name = ctx.job_scope("docker");
+        if let Err(err) = stop_container(&self.docker, &container_name).await {
+            warn!("stopping container {}: {:?}", container_name, err);
+        }
+        if let Err(err) = delete_container(&self.docker, &container_name).await {
+            warn!("removing container {}: {:?}", container_name, err);
+        }
+    }
+
+    /// Egress policy from the network capabilities the job's program declares. Jobs that
+    /// don't belong to a program can't reach anything, nor can jobs on workers without spaces
+    /// to read programs from.
+    async fn egress_policy(&self, ctx: &JobContext, requests_per_minute: u32) -> EgressPolicy {
+        let mut policy = EgressPolicy {
+            allow: Vec::new(),
+            requests_per_minute,
+        };
+        let Some(ref spaces) = self.spaces else {
+            warn!("worker has no spaces, job {} has no network", ctx.id);
+            return policy;
+        };
+        let Some(space) = spaces.get_by_name(&ctx.space).await else {
+            warn!(
+                "space {} not found, job {} has no network",
+                ctx.space, ctx.id
+            );
+            return policy;
+        };
+        let program = match space.programs().get_by_id(ctx.program_id).await {
+            Ok(program) => program,
+            Err(err) => {
+                warn!(
+                    "job {} has no network, loading its program: {:?}",
+                    ctx.id, err
+                );
+                return policy;
+            }
+        };
+        policy.allow = program
+            .manifest
+            .capabilities()
+            .into_iter()
+            .filter(|cap| cap.kind == CapabilityKind::Network)
+            .map(|cap| cap.target)
+            .collect();
+        let declared = program
+            .manifest
+            .permissions
+            .as_ref()
+            .and_then(|permissions| permissions.network_rate_limit);
+        if let Some(declared) = declared {
+            policy.requests_per_minute = policy.requests_per_minute.min(declared);
+        }
+        policy
+    }
+}
+
+impl Egress {
+    async fn open(docker: &bollard::Docker, config: &EgressConfig) -> Result<Self> {
+        let inspect =
+            || docker.inspect_network(&config.network, None::<InspectNetworkOptions<String>>);
+        let network = match inspect().await {
+            Ok(network) => network,
+            Err(_) => {
+                debug!("creating egress network {}", config.network);
+                docker
+                    .create_network(CreateNetworkOptions {
+                        name: config.network.as_str(),
+                        driver: "bridge",
+                        internal: true,
+                        ..Default::default()
+                    })
+                    .await
+                    .context("create egress network")?;
+                inspect().await.context("inspect egress network")?
+            }
+        };
+        let gateway = network
+            .ipam
+            .and_then(|ipam| ipam.config)
+            .into_iter()
+            .flatten()
+            .find_map(|config| config.gateway)
+            .context("egress network has no gateway")?;
+        // the gateway is the host's address on the network, only its containers reach it
+        let listen = match &config.listen {
+            Some(listen) => listen.clone(),
+            None => {
+                SocketAddr::new(gateway.parse().context("egress network gateway")?, 0).to_string()
+            }
+        };
+        let proxy = EgressProxy::bind(&listen, config.allow_private).await?;
+        let dns = match config.capture_dns {
+            true => {
+                let ip: IpAddr = gateway.parse().context("egress network gateway")?;
+                match DnsRecorder::bind(SocketAddr::new(ip, DNS_PORT)).await {
+                    Ok(dns) => Some(dns),
+                    Err(err) => {
+                        warn!("dns lookups of jobs won't be recorded: {:#}", err);
+                        None
+                    }
+                }
+            }
+            false => None,
+        };
+
+        Ok(Egress {
+            proxy,
+            network: config.network.clone(),
+            gateway,
+            requests_per_minute: config.requests_per_minute,
+            dns,
         })
     }
 }
@@ -52,6 +204,11 @@ impl Executor for Docker {
     type Report = Report;
 
     async fn execute(&self, ctx: &JobContext, job: Self::Job) -> Result<Self::Report> {
+        // containers only reach the node through artifacts, which belong to the job's own space
+        anyhow::ensure!(
+            ctx.spaces.is_empty(),
+            "docker jobs can't use other spaces, only wasm jobs can"
+        );
         let downloads_path = ctx.downloads_path(&self.root);
         let uploads_path = ctx.uploads_path(&self.root);
 
@@ -74,16 +231,64 @@ impl Executor for Docker {
             format!("{}:/uploads", uploads_path.to_string_lossy()),
         ];
 
-        let host_config = bollard::models::HostConfig {
+        let mut host_config = bollard::models::HostConfig {
             binds: Some(binds),
             ..Default::default()
         };
 
+        // without a route out of the network, the proxy is the only way out
+        let mut env = Vec::new();
+        let mut lease = None;
+        let mut dns = None;
+        match (job.network, &self.egress) {
+            (DockerNetwork::None, _) => host_config.network_mode = Some("none".to_string()),
+            (DockerNetwork::Internal, Some(egress)) => {
+                host_config.network_mode = Some(egress.network.clone());
+                dns = egress.dns.as_ref();
+            }
+            (DockerNetwork::Internal, None) => {
+                warn!(
+                    "job {} asked for the internal network, egress is disabled so it gets none",
+                    ctx.id
+                );
+                host_config.network_mode = Some("none".to_string());
+            }
+            (DockerNetwork::Allowlist, Some(egress)) => {
+                let policy = self.egress_policy(ctx, egress.requests_per_minute).await;
+                debug!("job {} may reach {:?}", ctx.id, policy.allow);
+                let job_lease = egress.proxy.register(ctx.id, policy);
+                let url = job_lease.proxy_url(&egress.gateway, egress.proxy.port());
+                for var in ["HTTP_PROXY", "HTTPS_PROXY", "http_proxy", "https_proxy"] {
+                    env.push(format!("{var}={url}"));
+                }
+                host_config.network_mode = Some(egress.network.clone());
+                lease = Some(job_lease);
+                dns = egress.dns.as_ref();
+            }
+            (DockerNetwork::Allowlist, None) => {
+                warn!(
+                    "job {} asked for the allowlist network, egress is disabled so it gets none",
+                    ctx.id
+                );
+                host_config.network_mode = Some("none".to_string());
+            }
+        }
+        // docker's own resolver would answer for the job, point it at the recorder instead
+        if let Some(dns) = dns {
+            let resolv_conf = downloads_path.with_file_name("resolv.conf");
+            tokio::fs::write(&resolv_conf, format!("nameserver {}\n", dns.addr().ip())).await?;
+            host_config.binds.get_or_insert_with(Vec::new).push(format!(
+                "{}:/etc/resolv.conf:ro",
+                resolv_conf.to_string_lossy()
+            ));
+        }
+
         let config = bollard::container::Config {
             image: Some(job.image.clone()),
             tty: Some(false),
             host_config: Some(host_config),
             cmd: Some(job.command.clone()),
+            env: Some(env),
             ..Default::default()
         };
 
@@ -92,6 +297,7 @@ impl Executor for Docker {
             platform: None,
         };
 
+        let created = Instant::now();
         let id = self
             .docker
             .create_container(Some(container_options), config)
@@ -102,6 +308,26 @@ impl Executor for Docker {
             .await
             .context("start container")?;
 
+        let mut dns_lease = None;
+        if let (Some(dns), Some(egress)) = (dns, &self.egress) {
+            match container_ip(&self.docker, &id, &egress.network).await {
+                Ok(ip) => dns_lease = Some(dns.register(ip, created, job.logs.clone())),
+                Err(err) => warn!("job {} lookups won't be recorded: {:#}", ctx.id, err),
+            }
+        }
+
+        let usage = Arc::new(Mutex::new(JobUsage::default()));
+        let profile = ctx
+            .profile
+            .then(|| Arc::new(Mutex::new(Profile::default())));
+        let stats = tokio::spawn(watch_stats(
+            self.docker.clone(),
+            id.clone(),
+            usage.clone(),
+            profile.clone(),
+        ));
+        let output = tokio::spawn(follow_logs(self.docker.clone(), id.clone(), job.logs));
+
         let mut wait_result = self.docker.wait_container(
             &id,
             Some(bollard::container::WaitContainerOptions {
@@ -124,44 +350,42 @@ impl Executor for Docker {
             }
         }
 
-        debug!("collecting logs");
-        let mut logs = self.docker.logs(
-            &id,
-            Some(bollard::container::LogsOptions::<String> {
-                stdout: true,
-                stderr: true,
-                ..Default::default()
-            }),
-        );
-
-        let mut stdout = String::new();
-        let mut stderr = String::new();
-
-        while let Some(Ok(msg)) = logs.next().await {
-            match msg {
-                LogOutput::StdErr { message } => {
-                    let message = String::from_utf8_lossy(&message);
-                    info!("[docker:stderr] {}", message);
-                    stderr.push_str(&message);
-                }
-                LogOutput::StdOut { message } => {
-                    let message = String::from_utf8_lossy(&message);
-                    info!("[docker:stdout] {}", message);
-                    stdout.push_str(&message);
-                }
-                LogOutput::Console { message } => {
-                    info!("[docker:console] {}", String::from_utf8_lossy(&message));
-                }
-                LogOutput::StdIn { message } => {
-                    info!("[docker:stdin] {}", String::from_utf8_lossy(&message));
+        stats.abort();
+        let mut usage = usage.lock().unwrap().clone();
+        if let Some(lease) = lease {
+            usage.egress = lease.finish();
+        }
+        if let Some(dns_lease) = dns_lease {
+            usage.dns = dns_lease.finish();
+        }
+        if let Some(profile) = profile {
+            let profile = profile.lock().unwrap().clone();
+            let title = format!("{} ({})", ctx.name, job.image);
+            match profile
+                .upload(ctx, &self.blobs, &title, "microseconds")
+                .await
+            {
+                Ok(Some(artifact)) => {
+                    usage.profile = Some(artifact.name.clone());
+                    usage.artifacts.push(artifact);
                 }
+                Ok(None) => {}
+                Err(err) => warn!("job {} profile not stored: {:?}", ctx.id, err),
             }
         }
 
+        debug!("collecting logs");
+        let (stdout, stderr) = output.await?;
+
         debug!("uploading artifacts from {}", uploads_path.display());
         // TODO: parallelize the with container stopping
-        ctx.read_uploads(&uploads_path, &self.blobs, &self.router)
+        let policy = admission::load(&self.blobs, &self.router, self.scanner.as_ref()).await?;
+        let (artifact_bytes, artifacts, rejected_artifacts) = ctx
+            .read_uploads(&uploads_path, &self.blobs, &policy)
             .await?;
+        usage.artifact_bytes = artifact_bytes;
+        usage.artifacts.extend(artifacts);
+        usage.rejected_artifacts = rejected_artifacts;
 
         debug!("stopping container");
         stop_container(&self.docker, &container_name).await?;
@@ -171,14 +395,130 @@ impl Executor for Docker {
             code,
             stdout,
             stderr,
+            usage,
         })
     }
 }
 
+/// Address of a container on a network.
+async fn container_ip(docker: &bollard::Docker, id: &str, network: &str) -> Result<IpAddr> {
+    let container = docker.inspect_container(id, None).await?;
+    let ip = container
+        .network_settings
+        .and_then(|settings| settings.networks)
+        .and_then(|mut networks| networks.remove(network))
+        .and_then(|endpoint| endpoint.ip_address)
+        .filter(|ip| !ip.is_empty())
+        .with_context(|| format!("container has no address on {network}"))?;
+    Ok(ip.parse()?)
+}
+
+/// Pass a container's output on to `logs` as it's written, until the container stops.
+/// Returns everything written to stdout & stderr.
+async fn follow_logs(docker: bollard::Docker, id: String, logs: LogSink) -> (String, String) {
+    let mut output = docker.logs(
+        &id,
+        Some(bollard::container::LogsOptions::<String> {
+            follow: true,
+            stdout: true,
+            stderr: true,
+            ..Default::default()
+        }),
+    );
+
+    let mut stdout = String::new();
+    let mut stderr = String::new();
+
+    while let Some(Ok(msg)) = output.next().await {
+        match msg {
+            LogOutput::StdErr { message } => {
+                let message = String::from_utf8_lossy(&message);
+                info!("[docker:stderr] {}", message);
+                logs.write(LogStream::Stderr, message.to_string());
+                stderr.push_str(&message);
+            }
+            LogOutput::StdOut { message } => {
+                let message = String::from_utf8_lossy(&message);
+                info!("[docker:stdout] {}", message);
+                logs.write(LogStream::Stdout, message.to_string());
+                stdout.push_str(&message);
+            }
+            LogOutput::Console { message } => {
+                info!("[docker:console] {}", String::from_utf8_lossy(&message));
+            }
+            LogOutput::StdIn { message } => {
+                info!("[docker:stdin] {}", String::from_utf8_lossy(&message));
+            }
+        }
+    }
+    (stdout, stderr)
+}
+
+/// Track cpu time & peak memory of a running container until aborted. With a `profile`, the
+/// cpu time used between stats is attributed to the processes running in the container.
+async fn watch_stats(
+    docker: bollard::Docker,
+    id: String,
+    usage: Arc<Mutex<JobUsage>>,
+    profile: Option<Arc<Mutex<Profile>>>,
+) {
+    let mut stats = docker.stats(
+        &id,
+        Some(StatsOptions {
+            stream: true,
+            one_shot: false,
+        }),
+    );
+    let mut last_total = 0;
+    while let Some(Ok(stats)) = stats.next().await {
+        // total_usage is cumulative, in nanoseconds
+        let total = stats.cpu_stats.cpu_usage.total_usage;
+        {
+            let mut usage = usage.lock().unwrap();
+            let cpu_time_ms = total / 1_000_000;
+            usage.cpu_time_ms = Some(usage.cpu_time_ms.unwrap_or_default().max(cpu_time_ms));
+            let memory = stats
+                .memory_stats
+                .max_usage
+                .or(stats.memory_stats.usage)
+                .unwrap_or_default();
+            usage.peak_memory_bytes = Some(usage.peak_memory_bytes.unwrap_or_default().max(memory));
+        }
+
+        let Some(ref profile) = profile else {
+            continue;
+        };
+        let cpu_time = Duration::from_nanos(total.saturating_sub(last_total));
+        last_total = last_total.max(total);
+        let top = docker
+            .top_processes(
+                &id,
+                Some(TopOptions {
+                    ps_args: ProcessSample::PS_ARGS,
+                }),
+            )
+            .await;
+        match top {
+            Ok(top) => {
+                let processes = ProcessSample::parse(
+                    &top.titles.unwrap_or_default(),
+                    &top.processes.unwrap_or_default(),
+                );
+                add_process_sample(&mut profile.lock().unwrap(), &processes, cpu_time);
+            }
+            // the container may have exited since the stats were taken
+            Err(err) => debug!("sampling processes of {}: {:?}", id, err),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Job {
     pub image: String,
     pub command: Vec<String>,
+    pub network: DockerNetwork,
+    /// receives the job's output as it runs
+    pub(crate) logs: LogSink,
 }
 
 #[derive(Debug)]
@@ -186,4 +526,6 @@ pub struct Report {
     pub code: i64,
     pub stdout: String,
     pub stderr: String,
+    /// everything but wall time, which the worker measures
+    pub usage: JobUsage,
 }
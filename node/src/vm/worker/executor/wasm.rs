@@ -1,17 +1,24 @@
+use std::collections::HashMap;
 use std::path::PathBuf;
 use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
 use anyhow::{anyhow, Context, Ok, Result};
 use extism::*;
 use iroh::blobs::Hash;
 use iroh::docs::Author;
-use tracing::debug;
+use tracing::{debug, warn};
 use uuid::Uuid;
 
 use crate::router::RouterClient;
+use crate::space::grants::{self, Access};
 use crate::space::{Space, Spaces};
+use crate::vm::admission::{self, ScannerCommand};
 use crate::vm::blobs::Blobs;
-use crate::vm::job::Source;
+use crate::vm::job::{JobUsage, Source};
+use crate::vm::logs::{LogSink, LogStream};
+use crate::vm::profile::Profile;
 
 use super::Executor;
 
@@ -24,6 +31,8 @@ pub struct WasmExecutor {
     blobs: Blobs,
     /// Root folder to store shared files in
     root: PathBuf,
+    /// run on every artifact before it's uploaded
+    scanner: Option<ScannerCommand>,
 }
 
 impl WasmExecutor {
@@ -32,12 +41,14 @@ impl WasmExecutor {
         router: RouterClient,
         blobs: Blobs,
         root: PathBuf,
+        scanner: Option<ScannerCommand>,
     ) -> Result<Self> {
         Ok(WasmExecutor {
             spaces,
             router,
             blobs,
             root,
+            scanner,
         })
     }
 }
@@ -95,50 +106,167 @@ impl Executor for WasmExecutor {
             .with_allowed_host("*")
             .with_config(environment.into_iter());
 
+        // grants are checked again here, one may have been taken back since the run started
+        let linked = grants::linked_spaces(&self.spaces, space.id, ctx.program_id, &ctx.spaces)
+            .await
+            .context("opening spaces the job uses")?
+            .into_iter()
+            .map(|(space, access)| (space.id, (space, access)))
+            .collect();
+
         let wasm_context = UserData::new(WasmContext {
             author: ctx.author.clone(),
+            program_id: ctx.program_id,
             rt: tokio::runtime::Handle::current(),
             space: space.clone(),
+            linked,
             output: String::new(),
+            logs: job.logs,
         });
+        // wall time spent in host functions, when profiling
+        let calls = ctx
+            .profile
+            .then(|| Arc::new(Mutex::new(Profile::default())));
         let mut plugin = PluginBuilder::new(manifest)
             .with_wasi(true)
-            .with_function("print", [PTR], [], wasm_context.clone(), print)
-            .with_function("sleep", [ValType::I64], [], wasm_context.clone(), sleep)
+            .with_function(
+                "print",
+                [PTR],
+                [],
+                wasm_context.clone(),
+                timed("print", &calls, print),
+            )
+            .with_function(
+                "sleep",
+                [ValType::I64],
+                [],
+                wasm_context.clone(),
+                timed("sleep", &calls, sleep),
+            )
             .with_function(
                 "schema_load_or_create",
                 [PTR],
                 [PTR],
                 wasm_context.clone(),
-                schema_load_or_create,
+                timed("schema_load_or_create", &calls, schema_load_or_create),
             )
             .with_function(
                 "event_create",
                 [PTR, PTR],
                 [PTR],
                 wasm_context.clone(),
-                event_create,
+                timed("event_create", &calls, event_create),
             )
             .with_function(
                 "event_mutate",
                 [PTR, PTR, PTR],
                 [PTR],
                 wasm_context.clone(),
-                event_mutate,
+                timed("event_mutate", &calls, event_mutate),
+            )
+            .with_function(
+                "event_query",
+                [PTR, PTR],
+                [PTR],
+                wasm_context.clone(),
+                timed("event_query", &calls, event_query),
+            )
+            .with_function(
+                "rows_create",
+                [PTR, PTR],
+                [PTR],
+                wasm_context.clone(),
+                timed("rows_create", &calls, event_create),
+            )
+            .with_function(
+                "rows_query",
+                [PTR, PTR],
+                [PTR],
+                wasm_context.clone(),
+                timed("rows_query", &calls, event_query),
+            )
+            .with_function(
+                "space_rows_create",
+                [PTR, PTR, PTR],
+                [PTR],
+                wasm_context.clone(),
+                timed("space_rows_create", &calls, space_rows_create),
+            )
+            .with_function(
+                "space_rows_query",
+                [PTR, PTR, PTR],
+                [PTR],
+                wasm_context.clone(),
+                timed("space_rows_query", &calls, space_rows_query),
+            )
+            .with_function(
+                "tables_get",
+                [PTR],
+                [PTR],
+                wasm_context.clone(),
+                timed("tables_get", &calls, tables_get),
+            )
+            .with_function(
+                "secrets_get",
+                [PTR],
+                [PTR],
+                wasm_context.clone(),
+                timed("secrets_get", &calls, secrets_get),
+            )
+            .with_function(
+                "blob_put",
+                [PTR],
+                [PTR],
+                wasm_context.clone(),
+                timed("blob_put", &calls, blob_put),
+            )
+            .with_function(
+                "blob_get",
+                [PTR],
+                [PTR],
+                wasm_context,
+                timed("blob_get", &calls, blob_get),
             )
-            .with_function("event_query", [PTR, PTR], [PTR], wasm_context, event_query)
             .build()?;
 
-        let output = plugin.call::<_, &str>(MAIN_FUNC_NAME, ())?;
+        let started = Instant::now();
+        let output = plugin.call::<_, &str>(MAIN_FUNC_NAME, ())?.to_string();
+        let elapsed = started.elapsed();
 
         debug!("uploading artifacts from {}", uploads_path.display());
-        ctx.read_uploads(&uploads_path, &self.blobs, &self.router)
+        let policy = admission::load(&self.blobs, &self.router, self.scanner.as_ref()).await?;
+        let (artifact_bytes, artifacts, rejected_artifacts) = ctx
+            .read_uploads(&uploads_path, &self.blobs, &policy)
             .await
             .context("read uploads")?;
 
-        Ok(Report {
-            output: output.to_string(),
-        })
+        // cpu time & memory stay None: extism can meter fuel but has no way to read back what
+        // a call consumed, so there is nothing to report besides wall time
+        let mut usage = JobUsage {
+            artifact_bytes,
+            artifacts,
+            rejected_artifacts,
+            ..Default::default()
+        };
+        if let Some(calls) = calls {
+            // the module's own share is the time it didn't spend in the host
+            let mut profile = calls.lock().unwrap().clone();
+            let main_micros = (elapsed.as_micros() as u64).saturating_sub(profile.total());
+            profile.add(&[MAIN_FUNC_NAME], main_micros);
+            match profile
+                .upload(ctx, &self.blobs, &ctx.name, "microseconds")
+                .await
+            {
+                Result::Ok(Some(artifact)) => {
+                    usage.profile = Some(artifact.name.clone());
+                    usage.artifacts.push(artifact);
+                }
+                Result::Ok(None) => {}
+                Err(err) => warn!("job {} profile not stored: {:?}", ctx.id, err),
+            }
+        }
+
+        Ok(Report { output, usage })
     }
 }
 
@@ -146,24 +274,86 @@ impl Executor for WasmExecutor {
 pub struct Job {
     /// Module file path
     pub module: Source,
+    /// receives what the module prints as it runs
+    pub(crate) logs: LogSink,
 }
 
 #[derive(Debug)]
 pub struct Report {
     pub output: String,
+    /// everything but wall time, which the worker measures
+    pub usage: JobUsage,
 }
 
+/// What host functions act with. Modules call back into the node through these imports:
+///
+/// - `rows_create(table, json) -> row`, also imported as `event_create`
+/// - `rows_query(table, query) -> [row]` with a [`crate::space::rows::query::RowQuery`]
+///   string, also imported as `event_query`
+/// - `event_mutate(table, id, json) -> row`
+/// - `tables_get(table) -> table` & `schema_load_or_create(schema) -> table`
+/// - `secrets_get(key) -> value`, from the secrets stored for the running program
+/// - `blob_put(bytes) -> hash` & `blob_get(hash) -> bytes`
+/// - `space_rows_query(space, table, query) -> [row]` & `space_rows_create(space, table, json)
+///   -> row`, for the other spaces the job was given, see [`crate::space::grants`]
+///
+/// Tables are referred to by schema hash. Rows & tables are passed as JSON.
 struct WasmContext {
     rt: tokio::runtime::Handle,
     author: Author,
+    /// the program the module belongs to
+    program_id: Uuid,
     space: Space,
+    /// other spaces the job may use, by id
+    linked: HashMap<Uuid, (Space, Access)>,
     output: String,
+    logs: LogSink,
+}
+
+impl WasmContext {
+    /// One of the other spaces the job was given, if it was given at least `access`.
+    fn linked(&self, id: &str, access: Access) -> Result<Space> {
+        let id = Uuid::parse_str(id).context("invalid space id")?;
+        match self.linked.get(&id) {
+            Some((space, granted)) if *granted >= access => Ok(space.clone()),
+            Some(_) => Err(anyhow!("job has no {:?} access to space {}", access, id)),
+            None => Err(anyhow!("job wasn't given space {}", id)),
+        }
+    }
+}
+
+/// A host function as extism calls it.
+type HostFn = dyn Fn(&mut CurrentPlugin, &[Val], &mut [Val], UserData<WasmContext>) -> Result<(), Error>
+    + Send
+    + Sync;
+
+/// Record the wall time calls to a host function take into `calls`, as a `main;<name>` stack.
+fn timed<F>(name: &'static str, calls: &Option<Arc<Mutex<Profile>>>, f: F) -> Box<HostFn>
+where
+    F: Fn(&mut CurrentPlugin, &[Val], &mut [Val], UserData<WasmContext>) -> Result<(), Error>
+        + Send
+        + Sync
+        + 'static,
+{
+    let Some(calls) = calls.clone() else {
+        return Box::new(f);
+    };
+    Box::new(move |plugin, inputs, outputs, user_data| {
+        let started = Instant::now();
+        let result = f(plugin, inputs, outputs, user_data);
+        calls
+            .lock()
+            .unwrap()
+            .add_duration(&[MAIN_FUNC_NAME, name], started.elapsed());
+        result
+    })
 }
 
 host_fn!(print(ctx: WasmContext; msg: String) -> () {
     let ctx = ctx.get()?;
     let mut ctx = ctx.lock().unwrap();
     ctx.output = ctx.output.to_owned() + &msg;
+    ctx.logs.write(LogStream::Stdout, msg.clone());
     println!("{}", msg);
     Ok(())
 });
@@ -242,6 +432,96 @@ host_fn!(event_query(ctx: WasmContext; schema: String, query: String) -> Vec<u8>
     })
 });
 
+host_fn!(space_rows_create(ctx: WasmContext; space: String, schema: String, data: String) -> Vec<u8> {
+    let ctx = ctx.get()?;
+    let ctx = ctx.lock().unwrap();
+    let space = ctx.linked(&space, Access::Write)?;
+    let schema_hash = Hash::from_str(schema.as_str()).context("invalid schema hash")?;
+    let author = ctx.author.clone();
+    let parsed = serde_json::from_str::<serde_json::Value>(&data).context("parsing JSON")?;
+
+    tokio::task::block_in_place(|| {
+        ctx.rt.block_on(async move {
+            let mut schema = space.tables().get_by_hash(schema_hash).await.context("loading schema")?;
+            let row = schema.create_row(&space, author, parsed).await.context("failed to created row")?;
+            serde_json::to_vec(&row).context("failed to serialize event")
+        })
+    })
+});
+
+host_fn!(space_rows_query(ctx: WasmContext; space: String, schema: String, query: String) -> Vec<u8> {
+    let ctx = ctx.get()?;
+    let ctx = ctx.lock().unwrap();
+    let space = ctx.linked(&space, Access::Read)?;
+    let schema = Hash::from_str(schema.as_str()).map_err(|_| anyhow!("invalid schema hash"))?;
+    let rows = space.rows();
+
+    tokio::task::block_in_place(|| {
+        ctx.rt.block_on(async move {
+            let res = rows.query(schema, query, 0, -1).await?;
+            let data = serde_json::to_vec(&res).map_err(|e| anyhow!("failed to serialize events: {}", e))?;
+            data.to_bytes()
+        })
+    })
+});
+
+host_fn!(tables_get(ctx: WasmContext; schema: String) -> Vec<u8> {
+    let ctx = ctx.get()?;
+    let ctx = ctx.lock().unwrap();
+    let schema = Hash::from_str(schema.as_str()).context("invalid schema hash")?;
+    let tables = ctx.space.tables();
+
+    tokio::task::block_in_place(|| {
+        ctx.rt.block_on(async move {
+            let table = tables.get_by_hash(schema).await.context("loading table")?;
+            serde_json::to_vec(&table).context("failed to serialize table")
+        })
+    })
+});
+
+host_fn!(secrets_get(ctx: WasmContext; key: String) -> String {
+    let ctx = ctx.get()?;
+    let ctx = ctx.lock().unwrap();
+    let secrets = ctx.space.secrets();
+    let program_id = ctx.program_id;
+
+    tokio::task::block_in_place(|| {
+        ctx.rt.block_on(async move {
+            let secrets = secrets.for_program_id(program_id).await?;
+            secrets
+                .and_then(|secrets| secrets.config.get(&key).cloned())
+                .ok_or_else(|| anyhow!("no secret named {}", key))
+        })
+    })
+});
+
+host_fn!(blob_put(ctx: WasmContext; data: Vec<u8>) -> String {
+    let ctx = ctx.get()?;
+    let ctx = ctx.lock().unwrap();
+    let router = ctx.space.router().clone();
+
+    tokio::task::block_in_place(|| {
+        ctx.rt.block_on(async move {
+            let outcome = router.blobs().add_bytes(data).await.context("adding blob")?;
+            Ok(outcome.hash.to_string())
+        })
+    })
+});
+
+host_fn!(blob_get(ctx: WasmContext; hash: String) -> Vec<u8> {
+    let ctx = ctx.get()?;
+    let ctx = ctx.lock().unwrap();
+    let hash = Hash::from_str(hash.as_str()).context("invalid blob hash")?;
+    let router = ctx.space.router().clone();
+
+    tokio::task::block_in_place(|| {
+        ctx.rt.block_on(async move {
+            let data = router.blobs().read_to_bytes(hash).await.context("reading blob")?;
+            Ok(data.to_vec())
+        })
+    })
+});
+
 // host_fn!(iroh_blob_get_ticket(_user_data: WasmContext; _ticket: &str) -> Vec<u8> {
 //     // let ctx = user_data.get()?;
 //     // let ctx = ctx.lock().unwrap();
@@ -0,0 +1,142 @@
+//! Decides whether & when a worker asks for a job.
+//!
+//! Every worker that can run a job hears about it at about the same time, & the scheduler
+//! awards the first request. So instead of asking right away a worker waits a random jitter,
+//! plus a backoff for each job it already holds, which lets idle workers ask first. A worker
+//! holding `max_concurrent_jobs`, counting jobs it asked for but wasn't awarded yet, doesn't
+//! ask at all.
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::slots::SlotsConfig;
+
+/// How long a request counts against the worker when it never hears who got the job.
+const PENDING_TTL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PickupConfig {
+    /// Most jobs the worker holds before it stops asking for more. Falls back to the
+    /// worker's slots when unset.
+    pub max_concurrent_jobs: Option<usize>,
+    /// longest random wait before asking for a job, in milliseconds
+    pub jitter_ms: u64,
+    /// added wait for each job the worker already holds, in milliseconds
+    pub backoff_ms: u64,
+    /// cap on the added wait, in milliseconds
+    pub max_backoff_ms: u64,
+}
+
+impl Default for PickupConfig {
+    fn default() -> Self {
+        Self {
+            max_concurrent_jobs: None,
+            jitter_ms: 250,
+            backoff_ms: 100,
+            max_backoff_ms: 2000,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Pickup {
+    config: PickupConfig,
+    limit: usize,
+    /// jobs asked for that nobody was awarded yet, by when they were asked for
+    pending: Arc<Mutex<HashMap<Uuid, Instant>>>,
+}
+
+impl Pickup {
+    pub fn new(config: PickupConfig, slots: &SlotsConfig) -> Self {
+        let limit = config.max_concurrent_jobs.unwrap_or(slots.slots);
+        Self {
+            config,
+            limit,
+            pending: Default::default(),
+        }
+    }
+
+    /// How long to wait before asking for `job_id` while running `running` jobs, or None
+    /// to not ask. The job counts as held until [`Self::settle`].
+    pub fn begin(&self, job_id: Uuid, running: usize) -> Option<Duration> {
+        let mut pending = self.pending.lock().expect("poisoned");
+        let now = Instant::now();
+        pending.retain(|_, asked| now.duration_since(*asked) < PENDING_TTL);
+        let held = running + pending.len();
+        if held >= self.limit || pending.contains_key(&job_id) {
+            return None;
+        }
+        pending.insert(job_id, now);
+        Some(self.delay(held))
+    }
+
+    /// Stop counting `job_id` as asked for, once it's awarded or no longer wanted.
+    pub fn settle(&self, job_id: Uuid) {
+        self.pending.lock().expect("poisoned").remove(&job_id);
+    }
+
+    fn delay(&self, held: usize) -> Duration {
+        let backoff = self
+            .config
+            .backoff_ms
+            .saturating_mul(held as u64)
+            .min(self.config.max_backoff_ms);
+        let jitter = rand::thread_rng().gen_range(0..=self.config.jitter_ms);
+        Duration::from_millis(backoff + jitter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn throttles_at_limit() {
+        let pickup = Pickup::new(
+            PickupConfig {
+                max_concurrent_jobs: Some(2),
+                jitter_ms: 0,
+                backoff_ms: 100,
+                max_backoff_ms: 150,
+            },
+            &SlotsConfig::default(),
+        );
+        let (a, b, c) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        assert_eq!(pickup.begin(a, 0), Some(Duration::ZERO));
+        // asking twice for the same job doesn't count
+        assert_eq!(pickup.begin(a, 0), None);
+        // the pending request counts as held
+        assert_eq!(pickup.begin(b, 0), Some(Duration::from_millis(100)));
+        assert_eq!(pickup.begin(c, 0), None);
+
+        pickup.settle(a);
+        pickup.settle(b);
+        assert_eq!(pickup.begin(c, 1), Some(Duration::from_millis(100)));
+        assert_eq!(pickup.begin(Uuid::new_v4(), 0), None);
+    }
+
+    #[test]
+    fn caps_backoff() {
+        let pickup = Pickup::new(
+            PickupConfig {
+                max_concurrent_jobs: None,
+                jitter_ms: 50,
+                backoff_ms: 100,
+                max_backoff_ms: 150,
+            },
+            &SlotsConfig {
+                slots: 8,
+                preemption: true,
+            },
+        );
+        let delay = pickup.begin(Uuid::new_v4(), 5).unwrap();
+        assert!(delay >= Duration::from_millis(150));
+        assert!(delay <= Duration::from_millis(200));
+        assert_eq!(pickup.begin(Uuid::new_v4(), 7), None);
+    }
+}
@@ -0,0 +1,90 @@
+//! Keeps jobs from filling the disk the worker keeps their files on.
+//!
+//! Each job gets a directory under the worker root, holding its downloads & uploads. With a
+//! quota, writing downloads stops as soon as they'd take more than the quota, and a job whose
+//! directory outgrew it by the time its uploads are read fails instead of uploading. The
+//! directory is deleted once the job is done, whatever the outcome. While more of the disk
+//! than the high watermark is in use, the worker doesn't request new jobs.
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+/// Percentage of the disk in use past which workers stop taking jobs, unless configured.
+pub const DEFAULT_HIGH_WATERMARK: u8 = 95;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DiskQuotaConfig {
+    /// most bytes a job's directory may hold, unlimited when unset
+    pub job_quota: Option<u64>,
+    /// percentage of the disk in use, 1 - 100, past which the worker takes no new jobs.
+    /// Unset to take jobs until the disk is full.
+    pub high_watermark: Option<u8>,
+}
+
+impl Default for DiskQuotaConfig {
+    fn default() -> Self {
+        Self {
+            job_quota: None,
+            high_watermark: Some(DEFAULT_HIGH_WATERMARK),
+        }
+    }
+}
+
+/// Whether more than `watermark` percent of the disk holding `root` is in use.
+pub(crate) fn over_watermark(root: &Path, watermark: u8) -> Result<bool> {
+    let available = fs2::available_space(root)?;
+    let total = fs2::total_space(root)?;
+    Ok(exceeds(available, total, watermark))
+}
+
+fn exceeds(available: u64, total: u64, watermark: u8) -> bool {
+    let used = total.saturating_sub(available) as u128;
+    used * 100 > watermark as u128 * total as u128
+}
+
+/// Bytes taken by the files under `path`, 0 if it doesn't exist.
+pub(crate) async fn dir_size(path: impl Into<PathBuf>) -> Result<u64> {
+    let path = path.into();
+    tokio::task::spawn_blocking(move || {
+        if !path.exists() {
+            return Ok(0);
+        }
+        let mut size = 0;
+        for entry in walkdir::WalkDir::new(path) {
+            let entry = entry?;
+            if entry.file_type().is_file() {
+                size += entry.metadata()?.len();
+            }
+        }
+        Ok(size)
+    })
+    .await?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn watermark() {
+        assert!(!exceeds(50, 100, 50));
+        assert!(exceeds(49, 100, 50));
+        assert!(!exceeds(0, 100, 100));
+        assert!(!exceeds(0, 0, 10));
+        // no overflow on large disks
+        assert!(exceeds(0, u64::MAX, 99));
+    }
+
+    #[tokio::test]
+    async fn sizes_directories() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        assert_eq!(dir_size(dir.path().join("missing")).await?, 0);
+        tokio::fs::create_dir_all(dir.path().join("a/b")).await?;
+        tokio::fs::write(dir.path().join("a/one"), [0; 10]).await?;
+        tokio::fs::write(dir.path().join("a/b/two"), [0; 5]).await?;
+        assert_eq!(dir_size(dir.path()).await?, 15);
+        Ok(())
+    }
+}
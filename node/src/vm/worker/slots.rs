@@ -0,0 +1,261 @@
+//! Limits how many jobs a worker runs at once.
+//!
+//! Jobs wait for a slot in priority order, interactive jobs ahead of batch jobs. When an
+//! interactive job is waiting & every slot is taken, the most recently started preemptible
+//! batch job is told to stop. The worker runs it again from the start once a slot frees up.
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::{oneshot, Notify};
+use tracing::info;
+use uuid::Uuid;
+
+use crate::vm::job::JobPriority;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SlotsConfig {
+    /// jobs the worker runs at once
+    pub slots: usize,
+    /// Stop preemptible batch jobs to make room for interactive ones.
+    pub preemption: bool,
+}
+
+impl Default for SlotsConfig {
+    fn default() -> Self {
+        Self {
+            slots: 4,
+            preemption: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Slots {
+    config: SlotsConfig,
+    state: Arc<Mutex<State>>,
+    freed: Arc<Notify>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    running: HashMap<Uuid, Running>,
+    waiting: Vec<Waiting>,
+    /// orders waiting jobs of the same priority
+    seq: u64,
+}
+
+#[derive(Debug)]
+struct Running {
+    priority: JobPriority,
+    preemptible: bool,
+    started: Instant,
+    /// None once the job was asked to stop
+    preempt: Option<oneshot::Sender<()>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Waiting {
+    job_id: Uuid,
+    priority: JobPriority,
+    seq: u64,
+}
+
+/// A running job's slot, freed on drop.
+#[derive(Debug)]
+pub(crate) struct Slot {
+    slots: Slots,
+    job_id: Uuid,
+    preempted: Option<oneshot::Receiver<()>>,
+}
+
+impl Slots {
+    pub(crate) fn new(config: SlotsConfig) -> Self {
+        Self {
+            config,
+            state: Default::default(),
+            freed: Default::default(),
+        }
+    }
+
+    /// Wait for a slot to run `job_id` in.
+    pub(crate) async fn acquire(
+        &self,
+        job_id: Uuid,
+        priority: JobPriority,
+        preemptible: bool,
+    ) -> Slot {
+        {
+            let mut state = self.state.lock().unwrap();
+            state.seq += 1;
+            let seq = state.seq;
+            state.waiting.push(Waiting {
+                job_id,
+                priority,
+                seq,
+            });
+        }
+        let _queued = Queued(self, job_id);
+        loop {
+            let freed = self.freed.notified();
+            if let Some(slot) = self.try_start(job_id, preemptible) {
+                return slot;
+            }
+            freed.await;
+        }
+    }
+
+    fn try_start(&self, job_id: Uuid, preemptible: bool) -> Option<Slot> {
+        let mut state = self.state.lock().unwrap();
+        let next = state
+            .waiting
+            .iter()
+            .copied()
+            .max_by(|a, b| a.priority.cmp(&b.priority).then(b.seq.cmp(&a.seq)))?;
+        let full = state.running.len() >= self.config.slots.max(1);
+        if next.job_id != job_id || full {
+            if full && next.priority == JobPriority::Interactive && self.config.preemption {
+                preempt_one(&mut state);
+            }
+            return None;
+        }
+
+        state.waiting.retain(|waiting| waiting.job_id != job_id);
+        let (tx, rx) = oneshot::channel();
+        state.running.insert(
+            job_id,
+            Running {
+                priority: next.priority,
+                preemptible,
+                started: Instant::now(),
+                preempt: Some(tx),
+            },
+        );
+        Some(Slot {
+            slots: self.clone(),
+            job_id,
+            preempted: Some(rx),
+        })
+    }
+}
+
+/// Takes a job out of the queue if it stops waiting for a slot, eg: its run was canceled.
+struct Queued<'a>(&'a Slots, Uuid);
+
+impl Drop for Queued<'_> {
+    fn drop(&mut self) {
+        let mut state = self.0.state.lock().unwrap();
+        state.waiting.retain(|waiting| waiting.job_id != self.1);
+        drop(state);
+        self.0.freed.notify_waiters();
+    }
+}
+
+/// Ask the most recently started preemptible batch job to stop, unless one already was.
+fn preempt_one(state: &mut State) {
+    let stopping = state
+        .running
+        .values()
+        .any(|running| running.preempt.is_none());
+    if stopping {
+        return;
+    }
+    let victim = state
+        .running
+        .iter_mut()
+        .filter(|(_, running)| running.preemptible && running.priority == JobPriority::Batch)
+        .max_by_key(|(_, running)| running.started);
+    if let Some((job_id, running)) = victim {
+        info!("preempting job {}", job_id);
+        if let Some(tx) = running.preempt.take() {
+            tx.send(()).ok();
+        }
+    }
+}
+
+impl Slot {
+    /// Resolves when the job should stop to make room for an interactive job.
+    pub(crate) async fn preempted(&mut self) {
+        if let Some(rx) = self.preempted.as_mut() {
+            let res = rx.await;
+            self.preempted = None;
+            if res.is_ok() {
+                return;
+            }
+        }
+        std::future::pending().await
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        let mut state = self.slots.state.lock().unwrap();
+        state.running.remove(&self.job_id);
+        drop(state);
+        self.slots.freed.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn interactive_preempts_batch() {
+        let slots = Slots::new(SlotsConfig {
+            slots: 1,
+            preemption: true,
+        });
+        let batch = Uuid::new_v4();
+        let mut slot = slots.acquire(batch, JobPriority::Batch, true).await;
+        assert_eq!(slots.state.lock().unwrap().running.len(), 1);
+
+        let slots2 = slots.clone();
+        let interactive = tokio::spawn(async move {
+            slots2
+                .acquire(Uuid::new_v4(), JobPriority::Interactive, false)
+                .await
+        });
+        tokio::time::timeout(Duration::from_secs(1), slot.preempted())
+            .await
+            .expect("batch job preempted");
+
+        // the batch job waits behind the interactive job it made room for
+        drop(slot);
+        let slot = interactive.await.unwrap();
+        let slots2 = slots.clone();
+        let rerun = tokio::spawn(async move {
+            slots2.acquire(batch, JobPriority::Batch, true).await;
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!rerun.is_finished());
+        drop(slot);
+        tokio::time::timeout(Duration::from_secs(1), rerun)
+            .await
+            .expect("batch job restarted")
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn no_preemption_without_preemptible_jobs() {
+        let slots = Slots::new(SlotsConfig {
+            slots: 1,
+            preemption: true,
+        });
+        let mut slot = slots
+            .acquire(Uuid::new_v4(), JobPriority::Batch, false)
+            .await;
+        let slots2 = slots.clone();
+        let _waiting = tokio::spawn(async move {
+            slots2
+                .acquire(Uuid::new_v4(), JobPriority::Interactive, false)
+                .await
+        });
+        let res = tokio::time::timeout(Duration::from_millis(100), slot.preempted()).await;
+        assert!(res.is_err());
+    }
+}
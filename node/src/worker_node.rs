@@ -0,0 +1,118 @@
+use std::path::PathBuf;
+
+use anyhow::Result;
+use iroh::docs::DocTicket;
+use iroh::util::path::IrohPaths;
+use tokio::task::JoinHandle;
+
+use crate::blob_store::BlobStore;
+use crate::disk::{DiskMonitor, DiskThresholds};
+use crate::peers::PeerBook;
+use crate::router::Router;
+use crate::vm::config::LiveConfig;
+use crate::vm::headless::{HeadlessWorker, WorkerConfig};
+
+/// A node that only runs jobs for one workspace, see [`crate::vm::headless`]. Unlike
+/// [`crate::node::Node`] it never opens spaces.
+pub struct WorkerNode {
+    router: Router,
+    worker: HeadlessWorker,
+    config: LiveConfig,
+    _disk_monitor_handle: JoinHandle<()>,
+    _config_handle: JoinHandle<()>,
+}
+
+impl WorkerNode {
+    pub async fn open(path: impl Into<PathBuf>, ticket: DocTicket) -> Result<Self> {
+        let repo_path = path.into();
+        let peers = PeerBook::load(&repo_path).await?;
+        let router = crate::router::router(&repo_path, peers.local_discovery().await).await?;
+
+        // workers write job status as the node author, same as a full node
+        let secret_key =
+            iroh::util::fs::load_secret_key(IrohPaths::SecretKey.with_root(&repo_path)).await?;
+        let author = iroh::docs::Author::from_bytes(&secret_key.to_bytes());
+        router.authors().import(author).await?;
+
+        let disk = DiskMonitor::new(&repo_path, DiskThresholds::default());
+        let disk_monitor_handle = disk.spawn();
+        let config = LiveConfig::open(&repo_path).await?;
+        let runtime = config.runtime();
+        let (disk_quota, blob_store, egress, slots, pickup, labels, admission_scanner) =
+            match config.file() {
+                Some(config) => (
+                    config.worker_disk_quota(),
+                    config.blob_store,
+                    config.egress,
+                    config.slots,
+                    config.worker_pickup,
+                    config.worker_labels,
+                    config.admission_scanner,
+                ),
+                None => Default::default(),
+            };
+        let blob_store = BlobStore::open(&blob_store, router.client().clone())?;
+        let worker = HeadlessWorker::join(
+            router.client(),
+            ticket,
+            WorkerConfig {
+                autofetch: runtime.autofetch,
+                worker_root: repo_path,
+                peers,
+                disk,
+                blob_store,
+                egress,
+                slots,
+                pickup,
+                disk_quota,
+                labels,
+                admission_scanner,
+                chaos: Default::default(),
+            },
+        )
+        .await?;
+
+        if !runtime.worker_enabled {
+            worker.worker().disable();
+        }
+        let config_handle = follow_config(&config, &worker);
+
+        Ok(WorkerNode {
+            router,
+            worker,
+            config,
+            _disk_monitor_handle: disk_monitor_handle,
+            _config_handle: config_handle,
+        })
+    }
+
+    pub fn router(&self) -> &Router {
+        &self.router
+    }
+
+    pub fn worker(&self) -> &HeadlessWorker {
+        &self.worker
+    }
+
+    /// The node's config, with runtime overrides & reloading.
+    pub fn config(&self) -> &LiveConfig {
+        &self.config
+    }
+}
+
+/// Apply runtime config changes to the worker, see `node::follow_config`.
+fn follow_config(config: &LiveConfig, worker: &HeadlessWorker) -> JoinHandle<()> {
+    let mut changes = config.subscribe();
+    let blobs = worker.blobs().clone();
+    let worker = worker.worker().clone();
+    tokio::spawn(async move {
+        while changes.changed().await.is_ok() {
+            let runtime = changes.borrow_and_update().clone();
+            blobs.set_autofetch(runtime.autofetch);
+            match runtime.worker_enabled {
+                true => worker.enable(),
+                false => worker.disable(),
+            }
+        }
+    })
+}
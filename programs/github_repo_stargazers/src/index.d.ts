@@ -10,5 +10,11 @@ declare module 'extism:host' {
     event_create(ptr: I64, ptr: I64): I64;
     event_mutate(ptr: I64, ptr: I64, ptr: I64): I64;
     event_query(ptr: I64, ptr: I64): I64;
+    rows_create(ptr: I64, ptr: I64): I64;
+    rows_query(ptr: I64, ptr: I64): I64;
+    tables_get(ptr: I64): I64;
+    secrets_get(ptr: I64): I64;
+    blob_put(ptr: I64): I64;
+    blob_get(ptr: I64): I64;
   }
 }
\ No newline at end of file
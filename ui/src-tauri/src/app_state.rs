@@ -1,4 +1,5 @@
 use std::path::PathBuf;
+use std::sync::RwLock;
 
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
@@ -10,7 +11,8 @@ const APP_STATE_FILENAME: &str = "app_state.json";
 #[derive(Debug, Serialize, Deserialize)]
 pub(crate) struct AppState {
     write_path: PathBuf,
-    pub current_space_id: Uuid,
+    /// nil until onboarding creates a space
+    current_space_id: RwLock<Uuid>,
 }
 
 impl AppState {
@@ -36,18 +38,27 @@ impl AppState {
     async fn create(path: impl Into<PathBuf>, node: &Node) -> Result<Self> {
         let path = path.into();
 
-        // default to the first space we find
+        // default to the first space we find, a fresh install has none until onboarding
         let spaces = node.spaces().list(0, 1).await?;
-        let space = spaces.first().expect("no spaces found");
+        let space_id = spaces.first().map(|space| space.id).unwrap_or_default();
 
         let state = Self {
             write_path: path.clone(),
-            current_space_id: space.id,
+            current_space_id: RwLock::new(space_id),
         };
         state.write_to_file().await?;
         Ok(state)
     }
 
+    pub fn current_space_id(&self) -> Uuid {
+        *self.current_space_id.read().expect("poisoned")
+    }
+
+    pub async fn set_current_space_id(&self, id: Uuid) -> Result<()> {
+        *self.current_space_id.write().expect("poisoned") = id;
+        self.write_to_file().await
+    }
+
     async fn write_to_file(&self) -> Result<()> {
         let state = serde_json::to_string(self)?;
         tokio::fs::write(&self.write_path, state).await?;
@@ -1,23 +1,54 @@
 use std::collections::HashMap;
+use std::net::SocketAddr;
 use std::str::FromStr;
 use std::sync::Arc;
 
+#[cfg(debug_assertions)]
+use squiggle_node::aliases::{Alias, Aliased};
+use squiggle_node::bootstrap::Bootstrap;
+use squiggle_node::disk::DiskStatus;
+use squiggle_node::ipfs::{Cid, Multihash};
 use squiggle_node::node::Node;
-use squiggle_node::space::events::Event;
-use squiggle_node::space::programs::Program;
-use squiggle_node::space::rows::Row;
+use squiggle_node::peers::PeerInfo;
+use squiggle_node::space::archive::ArchiveSummary;
+use squiggle_node::space::capabilities::{Action, Object};
+use squiggle_node::space::event_rates::{AuthorRate, RateLimits};
+use squiggle_node::space::external_sources::{ExternalRow, ExternalSource, SourceMapping};
+use squiggle_node::space::fork::ForkOptions;
+use squiggle_node::space::grants::{Access, Grant, SpaceHandle};
+use squiggle_node::space::join::{JoinProgress, SpaceJoin};
+use squiggle_node::space::outbox::{OutboxEntry, OutboxStatus};
+use squiggle_node::space::programs::{Capability, Program, ProgramUsage, UsagePeriod};
+use squiggle_node::space::render::Render;
+use squiggle_node::space::rows::query::RowQuery;
+use squiggle_node::space::rows::{
+    AggregateRow, Aggregation, Bucket, RangeResult, Row, RowChanges, RowFilter,
+    DEFAULT_HYDRATE_BUDGET,
+};
+use squiggle_node::space::runs::Run;
+use squiggle_node::space::schedules::{Cadence, Schedule};
+use squiggle_node::space::search::SearchMatch;
 use squiggle_node::space::secrets::Secret;
 use squiggle_node::space::tables::Table;
-use squiggle_node::space::users::User;
-use squiggle_node::space::SpaceDetails;
-use squiggle_node::vm::flow::TaskOutput;
-use squiggle_node::Hash;
+use squiggle_node::space::users::{Profile, User};
+use squiggle_node::space::{Page, SearchResult, SpaceDetails};
+use squiggle_node::storage::StorageReport;
+use squiggle_node::vm::flow::{Flow, TaskOutput};
+use squiggle_node::vm::lint::Diagnostic;
+use squiggle_node::vm::{JobPriority, RestartCheckpoint, RunOptions, SlaStats};
+use squiggle_node::{DocTicket, Error, Hash, NodeAddr, NodeId, PublicKey, RelayUrl};
+use tauri::Emitter;
+use tracing::warn;
 use uuid::Uuid;
 
 mod app_state;
 
 use crate::app_state::AppState;
 
+/// generated typescript types & invoke wrappers for every command, see `tests::export_bindings`
+#[cfg(test)]
+const BINDINGS_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../src/bindings.ts");
+
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     let path = squiggle_node::node::data_root().unwrap();
@@ -39,92 +70,192 @@ pub fn run() {
         (node, state)
     });
 
+    let mut rate_alerts = node.spaces().event_rates().subscribe();
+
+    let commands = commands();
+
     tauri::Builder::default()
+        .setup(move |app| {
+            let handle = app.handle().clone();
+            tauri::async_runtime::spawn(async move {
+                while let Ok(alert) = rate_alerts.recv().await {
+                    if let Err(err) = handle.emit("event-rate-alert", alert) {
+                        warn!("failed to emit event rate alert: {err}");
+                    }
+                }
+            });
+            Ok(())
+        })
         .plugin(tauri_plugin_shell::init())
         .manage(Arc::new(state))
         .manage(Arc::new(node))
-        .invoke_handler(tauri::generate_handler![
-            spaces_list,
-            current_space,
-            current_space_set,
-            events_search,
-            users_list,
-            programs_list,
-            program_run,
-            program_get,
-            secrets_get,
-            secrets_set,
-            tables_list,
-            table_get,
-            rows_query
-        ])
+        .manage(Arc::new(Joins::default()))
+        .invoke_handler(commands.invoke_handler())
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
 
+/// Every command the app can invoke, with typescript bindings generated from their signatures.
+fn commands() -> tauri_specta::Builder<tauri::Wry> {
+    tauri_specta::Builder::<tauri::Wry>::new().commands(tauri_specta::collect_commands![
+        bootstrap,
+        spaces_list,
+        current_space,
+        current_space_set,
+        events_search,
+        events_search_all,
+        users_list,
+        programs_list,
+        program_run,
+        runs_list,
+        run_render,
+        schedules_list,
+        schedules_create,
+        schedules_delete,
+        grants_list,
+        grants_create,
+        grants_delete,
+        program_get,
+        program_import,
+        program_capabilities,
+        sandbox_token,
+        secrets_get,
+        secrets_set,
+        tables_list,
+        table_get,
+        rows_query,
+        rows_aggregate,
+        rows_query_range,
+        rows_changes,
+        outbox_status,
+        outbox_pending,
+        outbox_conflicts,
+        outbox_clear_conflicts,
+        capabilities_can_i,
+        external_sources_list,
+        external_source_attach,
+        external_source_rows,
+        blob_add,
+        blob_cid,
+        blob_import_cid,
+        storage_report,
+        disk_status,
+        sync_resume,
+        prepare_restart,
+        jobs_sla_stats,
+        flow_lint,
+        peers_list,
+        peer_add,
+        peer_forget,
+        peers_local,
+        local_discovery_set,
+        relays_list,
+        relay_add,
+        relay_remove,
+        event_rates,
+        event_rate_limits,
+        event_rate_limits_set,
+        event_rate_unthrottle,
+        program_usage,
+        aliases_list,
+        alias_set,
+        alias_remove,
+        aliases_resolve,
+        space_fork,
+        space_export_archive,
+        space_import_archive,
+        space_join,
+        space_join_cancel,
+        program_run_task,
+    ])
+}
+
+/// First-run onboarding: create an account, a default space & sample data, and switch to it.
+#[tauri::command]
+#[specta::specta]
+async fn bootstrap(
+    state: tauri::State<'_, Arc<AppState>>,
+    node: tauri::State<'_, Arc<Node>>,
+    profile: Profile,
+) -> Result<Bootstrap, Error> {
+    let state = state.clone();
+    let node = node.clone();
+    tokio::task::block_in_place(|| {
+        tauri::async_runtime::block_on(async move {
+            let bootstrap = node.bootstrap(profile).await.map_err(Error::from)?;
+            state
+                .set_current_space_id(bootstrap.space_id)
+                .await
+                .map_err(Error::from)?;
+            Ok(bootstrap)
+        })
+    })
+}
+
 #[tauri::command]
+#[specta::specta]
 async fn users_list(
     node: tauri::State<'_, Arc<Node>>,
     space_id: Uuid,
     offset: i64,
     limit: i64,
-) -> Result<Vec<User>, String> {
+) -> Result<Page<User>, Error> {
     let spaces = node.spaces().clone();
     tokio::task::block_in_place(|| {
         tauri::async_runtime::block_on(async move {
-            let space = spaces.get(&space_id).await.ok_or("space not found")?;
-            space
-                .users()
-                .list(offset, limit)
+            let space = spaces
+                .get(&space_id)
                 .await
-                .map_err(|e| e.to_string())
+                .ok_or(Error::SpaceNotFound { id: space_id })?;
+            space.users().page(offset, limit).await.map_err(Error::from)
         })
     })
 }
 
 #[tauri::command]
+#[specta::specta]
 async fn spaces_list(
     node: tauri::State<'_, Arc<Node>>,
     offset: i64,
     limit: i64,
-) -> Result<Vec<SpaceDetails>, String> {
+) -> Result<Vec<SpaceDetails>, Error> {
     let node = node.clone();
     tokio::task::block_in_place(|| {
         tauri::async_runtime::block_on(async move {
-            node.spaces()
-                .list(offset, limit)
-                .await
-                .map_err(|e| e.to_string())
+            node.spaces().list(offset, limit).await.map_err(Error::from)
         })
     })
 }
 
 #[tauri::command]
+#[specta::specta]
 async fn current_space(
     state: tauri::State<'_, Arc<AppState>>,
     node: tauri::State<'_, Arc<Node>>,
-) -> Result<SpaceDetails, String> {
+) -> Result<SpaceDetails, Error> {
     let state = state.clone();
     let node = node.clone();
     tokio::task::block_in_place(|| {
         tauri::async_runtime::block_on(async move {
+            let space_id = state.current_space_id();
             let space = node
                 .spaces()
-                .get(&state.current_space_id)
+                .get(&space_id)
                 .await
-                .ok_or("space not found")?;
+                .ok_or(Error::SpaceNotFound { id: space_id })?;
             Ok(space.details())
         })
     })
 }
 
 #[tauri::command]
+#[specta::specta]
 async fn current_space_set(
     state: tauri::State<'_, Arc<AppState>>,
     node: tauri::State<'_, Arc<Node>>,
     space_id: Uuid,
-) -> Result<SpaceDetails, String> {
-    let _state = state.clone();
+) -> Result<SpaceDetails, Error> {
+    let state = state.clone();
     let node = node.clone();
 
     tokio::task::block_in_place(|| {
@@ -133,21 +264,25 @@ async fn current_space_set(
                 .spaces()
                 .get(&space_id)
                 .await
-                .ok_or("space not found")?;
-            // state.current_space_id = space_id;
+                .ok_or(Error::SpaceNotFound { id: space_id })?;
+            state
+                .set_current_space_id(space_id)
+                .await
+                .map_err(Error::from)?;
             Ok(space.details())
         })
     })
 }
 
 #[tauri::command]
+#[specta::specta]
 async fn events_search(
     node: tauri::State<'_, Arc<Node>>,
     space_id: Uuid,
     query: &str,
     offset: i64,
     limit: i64,
-) -> Result<Vec<Event>, String> {
+) -> Result<Vec<Aliased<SearchMatch>>, Error> {
     let node = node.clone();
     tokio::task::block_in_place(|| {
         tauri::async_runtime::block_on(async move {
@@ -155,69 +290,160 @@ async fn events_search(
                 .spaces()
                 .get(&space_id)
                 .await
-                .ok_or("space not found")?;
-            space
+                .ok_or(Error::SpaceNotFound { id: space_id })?;
+            let matches = space
                 .search(query, offset, limit)
                 .await
-                .map_err(|e| e.to_string())
+                .map_err(Error::from)?;
+            Ok(node.aliases().hydrate(matches, |m| m.event.pubkey).await)
+        })
+    })
+}
+
+/// Search events in every space, for the global search bar.
+#[tauri::command]
+#[specta::specta]
+async fn events_search_all(
+    node: tauri::State<'_, Arc<Node>>,
+    query: &str,
+    limit: i64,
+) -> Result<Vec<Aliased<SearchResult>>, Error> {
+    let node = node.clone();
+    tokio::task::block_in_place(|| {
+        tauri::async_runtime::block_on(async move {
+            let results = node.search_all(query, limit).await.map_err(Error::from)?;
+            Ok(node.aliases().hydrate(results, |r| r.event.pubkey).await)
         })
     })
 }
 
 #[tauri::command]
+#[specta::specta]
 async fn programs_list(
     node: tauri::State<'_, Arc<Node>>,
     space_id: Uuid,
     offset: i64,
     limit: i64,
-) -> Result<Vec<Program>, String> {
+) -> Result<Page<Program>, Error> {
     let spaces = node.spaces().clone();
     tokio::task::block_in_place(|| {
         tauri::async_runtime::block_on(async move {
-            let space = spaces.get(&space_id).await.ok_or("space not found")?;
+            let space = spaces
+                .get(&space_id)
+                .await
+                .ok_or(Error::SpaceNotFound { id: space_id })?;
             space
                 .programs()
-                .list(offset, limit)
+                .page(offset, limit)
                 .await
-                .map_err(|e| e.to_string())
+                .map_err(Error::from)
         })
     })
 }
 
 #[tauri::command]
+#[specta::specta]
 async fn program_get(
     node: tauri::State<'_, Arc<Node>>,
     space_id: Uuid,
     program_id: Uuid,
-) -> Result<Program, String> {
+) -> Result<Program, Error> {
     let spaces = node.spaces().clone();
     tokio::task::block_in_place(|| {
         tauri::async_runtime::block_on(async move {
-            let space = spaces.get(&space_id).await.ok_or("space not found")?;
+            let space = spaces
+                .get(&space_id)
+                .await
+                .ok_or(Error::SpaceNotFound { id: space_id })?;
             space
                 .programs()
                 .get_by_id(program_id)
                 .await
-                .map_err(|e| e.to_string())
+                .map_err(Error::from)
+        })
+    })
+}
+
+/// Install a program from a ticket someone shared it with.
+#[tauri::command]
+#[specta::specta]
+async fn program_import(
+    node: tauri::State<'_, Arc<Node>>,
+    space_id: Uuid,
+    ticket: String,
+) -> Result<Program, Error> {
+    let spaces = node.spaces().clone();
+    tokio::task::block_in_place(|| {
+        tauri::async_runtime::block_on(async move {
+            let author_id = node
+                .accounts()
+                .await
+                .map_err(Error::from)?
+                .pop()
+                .ok_or(Error::AuthorMissing)?;
+            let author = node
+                .router()
+                .authors()
+                .export(author_id)
+                .await
+                .map_err(Error::from)?
+                .ok_or(Error::AuthorMissing)?;
+
+            let space = spaces
+                .get(&space_id)
+                .await
+                .ok_or(Error::SpaceNotFound { id: space_id })?;
+            space
+                .programs()
+                .import(author, &ticket)
+                .await
+                .map_err(Error::from)
+        })
+    })
+}
+
+#[tauri::command]
+#[specta::specta]
+async fn program_capabilities(
+    node: tauri::State<'_, Arc<Node>>,
+    space_id: Uuid,
+    program_id: Uuid,
+) -> Result<Vec<Capability>, Error> {
+    let spaces = node.spaces().clone();
+    tokio::task::block_in_place(|| {
+        tauri::async_runtime::block_on(async move {
+            let space = spaces
+                .get(&space_id)
+                .await
+                .ok_or(Error::SpaceNotFound { id: space_id })?;
+            space
+                .programs()
+                .required_capabilities(program_id)
+                .await
+                .map_err(Error::from)
         })
     })
 }
 
 #[tauri::command]
+#[specta::specta]
 async fn secrets_get(
     node: tauri::State<'_, Arc<Node>>,
     space_id: Uuid,
     program_id: Uuid,
-) -> Result<HashMap<String, String>, String> {
+) -> Result<HashMap<String, String>, Error> {
     let spaces = node.spaces().clone();
     tokio::task::block_in_place(|| {
         tauri::async_runtime::block_on(async move {
-            let space = spaces.get(&space_id).await.ok_or("space not found")?;
+            let space = spaces
+                .get(&space_id)
+                .await
+                .ok_or(Error::SpaceNotFound { id: space_id })?;
             let secrets = space
                 .secrets()
                 .for_program_id(program_id)
                 .await
-                .map_err(|e| e.to_string())?
+                .map_err(Error::from)?
                 .map(|s| s.config)
                 .unwrap_or_default()
                 .into_keys()
@@ -229,129 +455,1258 @@ async fn secrets_get(
 }
 
 #[tauri::command]
+#[specta::specta]
 async fn secrets_set(
     node: tauri::State<'_, Arc<Node>>,
     space_id: Uuid,
     program_id: Uuid,
     secrets: HashMap<String, String>,
-) -> Result<Secret, String> {
+) -> Result<Secret, Error> {
     let spaces = node.spaces().clone();
     tokio::task::block_in_place(|| {
         tauri::async_runtime::block_on(async move {
             let author_id = node
                 .accounts()
                 .await
-                .map_err(|e| e.to_string())?
+                .map_err(Error::from)?
                 .pop()
-                .ok_or("no author")
-                .map_err(|e| e.to_string())?;
+                .ok_or(Error::AuthorMissing)?;
             let author = node
                 .router()
                 .authors()
                 .export(author_id)
                 .await
-                .map_err(|e| e.to_string())?
-                .ok_or("no author")
-                .map_err(|e| e.to_string())?;
+                .map_err(Error::from)?
+                .ok_or(Error::AuthorMissing)?;
 
-            let space = spaces.get(&space_id).await.ok_or("space not found")?;
+            let space = spaces
+                .get(&space_id)
+                .await
+                .ok_or(Error::SpaceNotFound { id: space_id })?;
             space
                 .secrets()
                 .set_for_program_id(author, program_id, secrets)
                 .await
-                .map_err(|e| e.to_string())
+                .map_err(Error::from)
         })
     })
 }
 
 #[tauri::command]
+#[specta::specta]
 async fn program_run(
     node: tauri::State<'_, Arc<Node>>,
     space_id: Uuid,
     _author: &str,
     program_id: Uuid,
     environment: HashMap<String, String>,
-) -> Result<TaskOutput, String> {
+    idempotency_key: Option<String>,
+    profile: Option<bool>,
+    spaces: Option<Vec<SpaceHandle>>,
+) -> Result<TaskOutput, Error> {
     let spaces = node.spaces().clone();
     let node = node.clone();
     tokio::task::block_in_place(|| {
         tauri::async_runtime::block_on(async move {
-            let space = spaces.get(&space_id).await.ok_or("space not found")?;
+            let space = spaces
+                .get(&space_id)
+                .await
+                .ok_or(Error::SpaceNotFound { id: space_id })?;
             let author_id = node
                 .accounts()
                 .await
-                .map_err(|e| e.to_string())?
+                .map_err(Error::from)?
                 .pop()
-                .ok_or("no author")
-                .map_err(|e| e.to_string())?;
+                .ok_or(Error::AuthorMissing)?;
             let author = node
                 .router()
                 .authors()
                 .export(author_id)
                 .await
-                .map_err(|e| e.to_string())?
-                .ok_or("no author")
-                .map_err(|e| e.to_string())?;
-            node.vm()
-                .run_program(&space, author, program_id, environment)
+                .map_err(Error::from)?
+                .ok_or(Error::AuthorMissing)?;
+            let spaces = spaces.unwrap_or_default();
+            match idempotency_key {
+                Some(_) if !spaces.is_empty() => {
+                    return Err(Error::invalid(
+                        "runs with an idempotency key can't use other spaces",
+                    ))
+                }
+                Some(key) => {
+                    node.vm()
+                        .run_program_once(&space, author, program_id, environment, &key)
+                        .await
+                }
+                None => {
+                    let options = RunOptions {
+                        priority: JobPriority::Interactive,
+                        profile: profile.unwrap_or_default(),
+                        spaces,
+                    };
+                    node.vm()
+                        .run_program_with(&space, author, program_id, environment, options)
+                        .await
+                }
+            }
+            .map_err(Error::from)
+        })
+    })
+}
+
+/// Previous runs of a program, most recent first.
+#[tauri::command]
+#[specta::specta]
+async fn runs_list(
+    node: tauri::State<'_, Arc<Node>>,
+    space_id: Uuid,
+    program_id: Uuid,
+    offset: i64,
+    limit: i64,
+) -> Result<Vec<Run>, Error> {
+    let spaces = node.spaces().clone();
+    tokio::task::block_in_place(|| {
+        tauri::async_runtime::block_on(async move {
+            let space = spaces
+                .get(&space_id)
+                .await
+                .ok_or(Error::SpaceNotFound { id: space_id })?;
+            space
+                .runs()
+                .list_for_program(program_id, offset, limit)
                 .await
-                .map_err(|e| e.to_string())
+                .map_err(Error::from)
         })
     })
 }
 
+/// What a run asked to be displayed as, none for plain output.
 #[tauri::command]
-async fn tables_list(
+#[specta::specta]
+async fn run_render(
     node: tauri::State<'_, Arc<Node>>,
     space_id: Uuid,
-) -> Result<Vec<Table>, String> {
+    run_id: Uuid,
+) -> Result<Option<Render>, Error> {
     let spaces = node.spaces().clone();
     tokio::task::block_in_place(|| {
         tauri::async_runtime::block_on(async move {
-            let space = spaces.get(&space_id).await.ok_or("space not found")?;
-            space.tables().list(0, -1).await.map_err(|e| e.to_string())
+            let space = spaces
+                .get(&space_id)
+                .await
+                .ok_or(Error::SpaceNotFound { id: space_id })?;
+            space.runs().render(run_id).await.map_err(Error::from)
         })
     })
 }
 
+/// Programs the space runs on a timer.
 #[tauri::command]
-async fn table_get(
+#[specta::specta]
+async fn schedules_list(
     node: tauri::State<'_, Arc<Node>>,
     space_id: Uuid,
-    table: &str,
-) -> Result<Table, String> {
+) -> Result<Vec<Schedule>, Error> {
+    let spaces = node.spaces().clone();
+    tokio::task::block_in_place(|| {
+        tauri::async_runtime::block_on(async move {
+            let space = spaces
+                .get(&space_id)
+                .await
+                .ok_or(Error::SpaceNotFound { id: space_id })?;
+            space.schedules().list().await.map_err(Error::from)
+        })
+    })
+}
+
+/// Run a program on `cadence` from now on.
+#[tauri::command]
+#[specta::specta]
+async fn schedules_create(
+    node: tauri::State<'_, Arc<Node>>,
+    space_id: Uuid,
+    program_id: Uuid,
+    cadence: Cadence,
+) -> Result<Schedule, Error> {
+    let spaces = node.spaces().clone();
+    tokio::task::block_in_place(|| {
+        tauri::async_runtime::block_on(async move {
+            let author_id = node
+                .accounts()
+                .await
+                .map_err(Error::from)?
+                .pop()
+                .ok_or(Error::AuthorMissing)?;
+            let author = node
+                .router()
+                .authors()
+                .export(author_id)
+                .await
+                .map_err(Error::from)?
+                .ok_or(Error::AuthorMissing)?;
+
+            let space = spaces
+                .get(&space_id)
+                .await
+                .ok_or(Error::SpaceNotFound { id: space_id })?;
+            space
+                .schedules()
+                .create(author, program_id, cadence)
+                .await
+                .map_err(Error::from)
+        })
+    })
+}
+
+#[tauri::command]
+#[specta::specta]
+async fn schedules_delete(
+    node: tauri::State<'_, Arc<Node>>,
+    space_id: Uuid,
+    schedule_id: Uuid,
+) -> Result<(), Error> {
     let spaces = node.spaces().clone();
-    let table_hash = Hash::from_str(table).map_err(|e| e.to_string())?;
     tokio::task::block_in_place(|| {
         tauri::async_runtime::block_on(async move {
-            let space = spaces.get(&space_id).await.ok_or("space not found")?;
+            let author_id = node
+                .accounts()
+                .await
+                .map_err(Error::from)?
+                .pop()
+                .ok_or(Error::AuthorMissing)?;
+            let author = node
+                .router()
+                .authors()
+                .export(author_id)
+                .await
+                .map_err(Error::from)?
+                .ok_or(Error::AuthorMissing)?;
+
+            let space = spaces
+                .get(&space_id)
+                .await
+                .ok_or(Error::SpaceNotFound { id: space_id })?;
             space
-                .tables()
-                .get_by_hash(table_hash)
+                .schedules()
+                .delete(author, schedule_id)
                 .await
-                .map_err(|e| e.to_string())
+                .map_err(Error::from)
         })
     })
 }
 
+/// Programs of other spaces allowed to use the space.
 #[tauri::command]
-async fn rows_query(
+#[specta::specta]
+async fn grants_list(
+    node: tauri::State<'_, Arc<Node>>,
+    space_id: Uuid,
+) -> Result<Vec<Grant>, Error> {
+    let spaces = node.spaces().clone();
+    tokio::task::block_in_place(|| {
+        tauri::async_runtime::block_on(async move {
+            let space = spaces
+                .get(&space_id)
+                .await
+                .ok_or(Error::SpaceNotFound { id: space_id })?;
+            space.grants().list().await.map_err(Error::from)
+        })
+    })
+}
+
+/// Let `program_id` of `from_space_id` use the space with `access`.
+#[tauri::command]
+#[specta::specta]
+async fn grants_create(
+    node: tauri::State<'_, Arc<Node>>,
+    space_id: Uuid,
+    from_space_id: Uuid,
+    program_id: Uuid,
+    access: Access,
+) -> Result<Grant, Error> {
+    let spaces = node.spaces().clone();
+    tokio::task::block_in_place(|| {
+        tauri::async_runtime::block_on(async move {
+            let author_id = node
+                .accounts()
+                .await
+                .map_err(Error::from)?
+                .pop()
+                .ok_or(Error::AuthorMissing)?;
+            let author = node
+                .router()
+                .authors()
+                .export(author_id)
+                .await
+                .map_err(Error::from)?
+                .ok_or(Error::AuthorMissing)?;
+
+            let space = spaces
+                .get(&space_id)
+                .await
+                .ok_or(Error::SpaceNotFound { id: space_id })?;
+            space
+                .grants()
+                .create(author, from_space_id, program_id, access)
+                .await
+                .map_err(Error::from)
+        })
+    })
+}
+
+#[tauri::command]
+#[specta::specta]
+async fn grants_delete(
+    node: tauri::State<'_, Arc<Node>>,
+    space_id: Uuid,
+    grant_id: Uuid,
+) -> Result<(), Error> {
+    let spaces = node.spaces().clone();
+    tokio::task::block_in_place(|| {
+        tauri::async_runtime::block_on(async move {
+            let author_id = node
+                .accounts()
+                .await
+                .map_err(Error::from)?
+                .pop()
+                .ok_or(Error::AuthorMissing)?;
+            let author = node
+                .router()
+                .authors()
+                .export(author_id)
+                .await
+                .map_err(Error::from)?
+                .ok_or(Error::AuthorMissing)?;
+
+            let space = spaces
+                .get(&space_id)
+                .await
+                .ok_or(Error::SpaceNotFound { id: space_id })?;
+            space
+                .grants()
+                .delete(author, grant_id)
+                .await
+                .map_err(Error::from)
+        })
+    })
+}
+
+#[tauri::command]
+#[specta::specta]
+async fn tables_list(
     node: tauri::State<'_, Arc<Node>>,
     space_id: Uuid,
-    table: &str,
     offset: i64,
     limit: i64,
-) -> Result<Vec<Row>, String> {
+) -> Result<Page<Table>, Error> {
     let spaces = node.spaces().clone();
-    let table_hash = Hash::from_str(table).map_err(|e| e.to_string())?;
     tokio::task::block_in_place(|| {
         tauri::async_runtime::block_on(async move {
-            let space = spaces.get(&space_id).await.ok_or("space not found")?;
+            let space = spaces
+                .get(&space_id)
+                .await
+                .ok_or(Error::SpaceNotFound { id: space_id })?;
             space
-                .rows()
-                .query(table_hash, String::from(""), offset, limit)
+                .tables()
+                .page(offset, limit)
+                .await
+                .map_err(Error::from)
+        })
+    })
+}
+
+/// A token the program's sandbox hands it for the gateway API, see `ProgramSandbox`.
+#[tauri::command]
+#[specta::specta]
+async fn sandbox_token(
+    node: tauri::State<'_, Arc<Node>>,
+    space_id: Uuid,
+    program: &str,
+) -> Result<Option<String>, Error> {
+    let program = Hash::from_str(program).map_err(Error::invalid)?;
+    tokio::task::block_in_place(|| {
+        tauri::async_runtime::block_on(async move {
+            node.sandbox_token(space_id, program)
+                .await
+                .map_err(Error::from)
+        })
+    })
+}
+
+#[tauri::command]
+#[specta::specta]
+async fn table_get(
+    node: tauri::State<'_, Arc<Node>>,
+    space_id: Uuid,
+    table: &str,
+) -> Result<Table, Error> {
+    let spaces = node.spaces().clone();
+    let table_hash = Hash::from_str(table).map_err(Error::invalid)?;
+    tokio::task::block_in_place(|| {
+        tauri::async_runtime::block_on(async move {
+            let space = spaces
+                .get(&space_id)
+                .await
+                .ok_or(Error::SpaceNotFound { id: space_id })?;
+            space
+                .tables()
+                .get_by_hash(table_hash)
                 .await
-                .map_err(|e| e.to_string())
+                .map_err(Error::from)
         })
     })
 }
+
+#[tauri::command]
+#[specta::specta]
+async fn rows_query(
+    node: tauri::State<'_, Arc<Node>>,
+    space_id: Uuid,
+    table: &str,
+    query: Option<RowQuery>,
+    offset: i64,
+    limit: i64,
+    hydrate_budget: Option<u64>,
+) -> Result<Page<Aliased<Row>>, Error> {
+    let node = node.clone();
+    let table_hash = Hash::from_str(table).map_err(Error::invalid)?;
+    let query = query.unwrap_or_default();
+    let budget = hydrate_budget.unwrap_or(DEFAULT_HYDRATE_BUDGET);
+    tokio::task::block_in_place(|| {
+        tauri::async_runtime::block_on(async move {
+            let space = node
+                .spaces()
+                .get(&space_id)
+                .await
+                .ok_or(Error::SpaceNotFound { id: space_id })?;
+            let rows = space.rows();
+            let found = rows
+                .query_hydrated(table_hash, &query, offset, limit, budget)
+                .await
+                .map_err(Error::from)?;
+            let total = rows.count(table_hash, &query).await.map_err(Error::from)?;
+            let found = node.aliases().hydrate(found, |row| row.author).await;
+            Ok(Page::new(found, total, offset, limit))
+        })
+    })
+}
+
+#[tauri::command]
+#[specta::specta]
+async fn rows_aggregate(
+    node: tauri::State<'_, Arc<Node>>,
+    space_id: Uuid,
+    table: &str,
+    group_by: Vec<String>,
+    aggregations: Vec<Aggregation>,
+    filter: Option<RowFilter>,
+) -> Result<Vec<AggregateRow>, Error> {
+    let spaces = node.spaces().clone();
+    let table_hash = Hash::from_str(table).map_err(Error::invalid)?;
+    tokio::task::block_in_place(|| {
+        tauri::async_runtime::block_on(async move {
+            let space = spaces
+                .get(&space_id)
+                .await
+                .ok_or(Error::SpaceNotFound { id: space_id })?;
+            space
+                .rows()
+                .aggregate(table_hash, group_by, aggregations, filter)
+                .await
+                .map_err(Error::from)
+        })
+    })
+}
+
+#[tauri::command]
+#[specta::specta]
+async fn rows_query_range(
+    node: tauri::State<'_, Arc<Node>>,
+    space_id: Uuid,
+    table: &str,
+    field: String,
+    from: i64,
+    to: i64,
+    bucket: Option<Bucket>,
+) -> Result<RangeResult, Error> {
+    let spaces = node.spaces().clone();
+    let table_hash = Hash::from_str(table).map_err(Error::invalid)?;
+    tokio::task::block_in_place(|| {
+        tauri::async_runtime::block_on(async move {
+            let space = spaces
+                .get(&space_id)
+                .await
+                .ok_or(Error::SpaceNotFound { id: space_id })?;
+            space
+                .rows()
+                .query_range(table_hash, &field, from, to, bucket)
+                .await
+                .map_err(Error::from)
+        })
+    })
+}
+
+/// Inserts, updates & deletes in a table since `since`, for UIs that keep a copy of the rows.
+#[tauri::command]
+#[specta::specta]
+async fn rows_changes(
+    node: tauri::State<'_, Arc<Node>>,
+    space_id: Uuid,
+    table: &str,
+    since: i64,
+) -> Result<RowChanges, Error> {
+    let spaces = node.spaces().clone();
+    let table_hash = Hash::from_str(table).map_err(Error::invalid)?;
+    tokio::task::block_in_place(|| {
+        tauri::async_runtime::block_on(async move {
+            let space = spaces
+                .get(&space_id)
+                .await
+                .ok_or(Error::SpaceNotFound { id: space_id })?;
+            space
+                .rows()
+                .changes(table_hash, since)
+                .await
+                .map_err(Error::from)
+        })
+    })
+}
+
+/// Counts of events this node wrote that no peer has acknowledged yet.
+#[tauri::command]
+#[specta::specta]
+async fn outbox_status(
+    node: tauri::State<'_, Arc<Node>>,
+    space_id: Uuid,
+) -> Result<OutboxStatus, Error> {
+    let spaces = node.spaces().clone();
+    tokio::task::block_in_place(|| {
+        tauri::async_runtime::block_on(async move {
+            let space = spaces
+                .get(&space_id)
+                .await
+                .ok_or(Error::SpaceNotFound { id: space_id })?;
+            space.outbox().status().await.map_err(Error::from)
+        })
+    })
+}
+
+#[tauri::command]
+#[specta::specta]
+async fn outbox_pending(
+    node: tauri::State<'_, Arc<Node>>,
+    space_id: Uuid,
+    offset: i64,
+    limit: i64,
+) -> Result<Vec<OutboxEntry>, Error> {
+    let spaces = node.spaces().clone();
+    tokio::task::block_in_place(|| {
+        tauri::async_runtime::block_on(async move {
+            let space = spaces
+                .get(&space_id)
+                .await
+                .ok_or(Error::SpaceNotFound { id: space_id })?;
+            space
+                .outbox()
+                .pending(offset, limit)
+                .await
+                .map_err(Error::from)
+        })
+    })
+}
+
+/// Acknowledged events that raced another member's edit to the same object.
+#[tauri::command]
+#[specta::specta]
+async fn outbox_conflicts(
+    node: tauri::State<'_, Arc<Node>>,
+    space_id: Uuid,
+    offset: i64,
+    limit: i64,
+) -> Result<Vec<OutboxEntry>, Error> {
+    let spaces = node.spaces().clone();
+    tokio::task::block_in_place(|| {
+        tauri::async_runtime::block_on(async move {
+            let space = spaces
+                .get(&space_id)
+                .await
+                .ok_or(Error::SpaceNotFound { id: space_id })?;
+            space
+                .outbox()
+                .conflicts(offset, limit)
+                .await
+                .map_err(Error::from)
+        })
+    })
+}
+
+#[tauri::command]
+#[specta::specta]
+async fn outbox_clear_conflicts(
+    node: tauri::State<'_, Arc<Node>>,
+    space_id: Uuid,
+) -> Result<(), Error> {
+    let spaces = node.spaces().clone();
+    tokio::task::block_in_place(|| {
+        tauri::async_runtime::block_on(async move {
+            let space = spaces
+                .get(&space_id)
+                .await
+                .ok_or(Error::SpaceNotFound { id: space_id })?;
+            space.outbox().clear_conflicts().await.map_err(Error::from)
+        })
+    })
+}
+
+#[tauri::command]
+#[specta::specta]
+async fn capabilities_can_i(
+    node: tauri::State<'_, Arc<Node>>,
+    space_id: Uuid,
+    action: Action,
+    object: Object,
+) -> Result<bool, Error> {
+    let spaces = node.spaces().clone();
+    tokio::task::block_in_place(|| {
+        tauri::async_runtime::block_on(async move {
+            let author_id = node
+                .accounts()
+                .await
+                .map_err(Error::from)?
+                .pop()
+                .ok_or(Error::AuthorMissing)?;
+            let me = PublicKey::from_bytes(author_id.as_bytes()).map_err(Error::invalid)?;
+            let space = spaces
+                .get(&space_id)
+                .await
+                .ok_or(Error::SpaceNotFound { id: space_id })?;
+            space
+                .capabilities()
+                .can(&me, action, &object)
+                .await
+                .map_err(Error::from)
+        })
+    })
+}
+
+#[tauri::command]
+#[specta::specta]
+async fn blob_add(
+    node: tauri::State<'_, Arc<Node>>,
+    path: Option<String>,
+    bytes: Option<Vec<u8>>,
+) -> Result<String, Error> {
+    let node = node.clone();
+    tokio::task::block_in_place(|| {
+        tauri::async_runtime::block_on(async move {
+            match (path, bytes) {
+                (Some(path), None) => node.blob_add_path(path).await,
+                (None, Some(bytes)) => node.blob_add_bytes(bytes).await,
+                _ => return Err(Error::invalid("provide exactly one of path or bytes")),
+            }
+            .map(|hash| hash.to_string())
+            .map_err(Error::from)
+        })
+    })
+}
+
+#[tauri::command]
+#[specta::specta]
+async fn peers_list(node: tauri::State<'_, Arc<Node>>) -> Result<Vec<PeerInfo>, Error> {
+    let node = node.clone();
+    tokio::task::block_in_place(|| {
+        tauri::async_runtime::block_on(async move { node.peers().await.map_err(Error::from) })
+    })
+}
+
+#[tauri::command]
+#[specta::specta]
+async fn peers_local(node: tauri::State<'_, Arc<Node>>) -> Result<Vec<PeerInfo>, Error> {
+    let node = node.clone();
+    tokio::task::block_in_place(|| {
+        tauri::async_runtime::block_on(async move { node.local_peers().await.map_err(Error::from) })
+    })
+}
+
+#[tauri::command]
+#[specta::specta]
+async fn local_discovery_set(
+    node: tauri::State<'_, Arc<Node>>,
+    enabled: bool,
+) -> Result<(), Error> {
+    let node = node.clone();
+    tokio::task::block_in_place(|| {
+        tauri::async_runtime::block_on(async move {
+            node.set_local_discovery(enabled).await.map_err(Error::from)
+        })
+    })
+}
+
+#[tauri::command]
+#[specta::specta]
+async fn peer_add(
+    node: tauri::State<'_, Arc<Node>>,
+    node_id: &str,
+    relay_url: Option<String>,
+    addrs: Vec<String>,
+) -> Result<(), Error> {
+    let node = node.clone();
+    let node_id = NodeId::from_str(node_id).map_err(Error::invalid)?;
+    let relay_url = relay_url
+        .map(|url| RelayUrl::from_str(&url))
+        .transpose()
+        .map_err(Error::invalid)?;
+    let addrs = addrs
+        .iter()
+        .map(|addr| SocketAddr::from_str(addr))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(Error::invalid)?;
+    let addr = NodeAddr::from_parts(node_id, relay_url, addrs);
+    tokio::task::block_in_place(|| {
+        tauri::async_runtime::block_on(
+            async move { node.add_peer(addr).await.map_err(Error::from) },
+        )
+    })
+}
+
+#[tauri::command]
+#[specta::specta]
+async fn peer_forget(node: tauri::State<'_, Arc<Node>>, node_id: &str) -> Result<(), Error> {
+    let node = node.clone();
+    let node_id = NodeId::from_str(node_id).map_err(Error::invalid)?;
+    tokio::task::block_in_place(|| {
+        tauri::async_runtime::block_on(async move {
+            node.forget_peer(node_id).await.map_err(Error::from)
+        })
+    })
+}
+
+#[tauri::command]
+#[specta::specta]
+async fn relays_list(node: tauri::State<'_, Arc<Node>>) -> Result<Vec<String>, Error> {
+    let node = node.clone();
+    tokio::task::block_in_place(|| {
+        tauri::async_runtime::block_on(async move {
+            let relays = node.relays().await;
+            Ok(relays.into_iter().map(|r| r.url.to_string()).collect())
+        })
+    })
+}
+
+/// Add a relay, the running node rebinds to it.
+#[tauri::command]
+#[specta::specta]
+async fn relay_add(node: tauri::State<'_, Arc<Node>>, url: &str) -> Result<(), Error> {
+    let node = node.inner().clone();
+    let url = RelayUrl::from_str(url).map_err(Error::invalid)?;
+    tokio::task::block_in_place(|| {
+        tauri::async_runtime::block_on(async move {
+            node.add_relay(url).await?;
+            Ok(())
+        })
+    })
+}
+
+/// Remove a relay, rebinding like [`relay_add`].
+#[tauri::command]
+#[specta::specta]
+async fn relay_remove(node: tauri::State<'_, Arc<Node>>, url: &str) -> Result<(), Error> {
+    let node = node.inner().clone();
+    let url = RelayUrl::from_str(url).map_err(Error::invalid)?;
+    tokio::task::block_in_place(|| {
+        tauri::async_runtime::block_on(async move {
+            node.remove_relay(&url).await?;
+            Ok(())
+        })
+    })
+}
+
+#[tauri::command]
+#[specta::specta]
+async fn external_sources_list(
+    node: tauri::State<'_, Arc<Node>>,
+    space_id: Uuid,
+) -> Result<Vec<ExternalSource>, Error> {
+    let spaces = node.spaces().clone();
+    tokio::task::block_in_place(|| {
+        tauri::async_runtime::block_on(async move {
+            let space = spaces
+                .get(&space_id)
+                .await
+                .ok_or(Error::SpaceNotFound { id: space_id })?;
+            space.external_sources().list().await.map_err(Error::from)
+        })
+    })
+}
+
+#[tauri::command]
+#[specta::specta]
+async fn external_source_attach(
+    node: tauri::State<'_, Arc<Node>>,
+    space_id: Uuid,
+    ticket: &str,
+    mapping: SourceMapping,
+) -> Result<ExternalSource, Error> {
+    let spaces = node.spaces().clone();
+    let ticket = DocTicket::from_str(ticket).map_err(Error::invalid)?;
+    tokio::task::block_in_place(|| {
+        tauri::async_runtime::block_on(async move {
+            let space = spaces
+                .get(&space_id)
+                .await
+                .ok_or(Error::SpaceNotFound { id: space_id })?;
+            space
+                .external_sources()
+                .attach(ticket, mapping)
+                .await
+                .map_err(Error::from)
+        })
+    })
+}
+
+#[tauri::command]
+#[specta::specta]
+async fn external_source_rows(
+    node: tauri::State<'_, Arc<Node>>,
+    space_id: Uuid,
+    source_id: Uuid,
+    offset: i64,
+    limit: i64,
+) -> Result<Vec<ExternalRow>, Error> {
+    let spaces = node.spaces().clone();
+    tokio::task::block_in_place(|| {
+        tauri::async_runtime::block_on(async move {
+            let space = spaces
+                .get(&space_id)
+                .await
+                .ok_or(Error::SpaceNotFound { id: space_id })?;
+            space
+                .external_sources()
+                .rows(source_id, offset, limit)
+                .await
+                .map_err(Error::from)
+        })
+    })
+}
+
+#[tauri::command]
+#[specta::specta]
+async fn blob_cid(
+    node: tauri::State<'_, Arc<Node>>,
+    hash: &str,
+    multihash: Multihash,
+) -> Result<Cid, Error> {
+    let node = node.clone();
+    let hash = Hash::from_str(hash).map_err(Error::invalid)?;
+    tokio::task::block_in_place(|| {
+        tauri::async_runtime::block_on(async move {
+            node.blob_cid(hash, multihash).await.map_err(Error::from)
+        })
+    })
+}
+
+#[tauri::command]
+#[specta::specta]
+async fn blob_import_cid(
+    node: tauri::State<'_, Arc<Node>>,
+    cid: &str,
+    gateway: Option<String>,
+) -> Result<String, Error> {
+    let node = node.clone();
+    let cid = Cid::from_str(cid).map_err(Error::invalid)?;
+    tokio::task::block_in_place(|| {
+        tauri::async_runtime::block_on(async move {
+            node.blob_import_cid(&cid, gateway.as_deref())
+                .await
+                .map(|hash| hash.to_string())
+                .map_err(Error::from)
+        })
+    })
+}
+
+#[tauri::command]
+#[specta::specta]
+async fn storage_report(node: tauri::State<'_, Arc<Node>>) -> Result<StorageReport, Error> {
+    let node = node.clone();
+    tokio::task::block_in_place(|| {
+        tauri::async_runtime::block_on(
+            async move { node.storage_report().await.map_err(Error::from) },
+        )
+    })
+}
+
+#[tauri::command]
+#[specta::specta]
+async fn disk_status(node: tauri::State<'_, Arc<Node>>) -> Result<DiskStatus, Error> {
+    let node = node.clone();
+    tokio::task::block_in_place(|| {
+        tauri::async_runtime::block_on(async move { node.disk_status().await.map_err(Error::from) })
+    })
+}
+
+/// Drain the node before the app restarts for an update. Resolves once it's safe to restart.
+#[tauri::command]
+#[specta::specta]
+async fn prepare_restart(
+    node: tauri::State<'_, Arc<Node>>,
+    timeout_secs: u64,
+) -> Result<RestartCheckpoint, Error> {
+    let node = node.clone();
+    tokio::task::block_in_place(|| {
+        tauri::async_runtime::block_on(async move {
+            node.prepare_restart(std::time::Duration::from_secs(timeout_secs))
+                .await
+                .map_err(Error::from)
+        })
+    })
+}
+
+#[tauri::command]
+#[specta::specta]
+async fn sync_resume(node: tauri::State<'_, Arc<Node>>) -> Result<DiskStatus, Error> {
+    let node = node.clone();
+    tokio::task::block_in_place(|| {
+        tauri::async_runtime::block_on(async move { node.resume_sync().await.map_err(Error::from) })
+    })
+}
+
+#[tauri::command]
+#[specta::specta]
+fn flow_lint(source: String) -> Vec<Diagnostic> {
+    Flow::lint(&source)
+}
+
+#[tauri::command]
+#[specta::specta]
+async fn event_rates(
+    node: tauri::State<'_, Arc<Node>>,
+    space_id: Uuid,
+) -> Result<Vec<AuthorRate>, Error> {
+    let spaces = node.spaces().clone();
+    tokio::task::block_in_place(|| {
+        tauri::async_runtime::block_on(async move {
+            let space = spaces
+                .get(&space_id)
+                .await
+                .ok_or(Error::SpaceNotFound { id: space_id })?;
+            Ok(space.event_rates())
+        })
+    })
+}
+
+#[tauri::command]
+#[specta::specta]
+fn jobs_sla_stats(node: tauri::State<'_, Arc<Node>>) -> SlaStats {
+    node.vm().scheduler().sla_stats()
+}
+
+#[tauri::command]
+#[specta::specta]
+fn event_rate_limits(node: tauri::State<'_, Arc<Node>>) -> RateLimits {
+    node.spaces().event_rates().limits()
+}
+
+#[tauri::command]
+#[specta::specta]
+fn event_rate_limits_set(
+    node: tauri::State<'_, Arc<Node>>,
+    limits: RateLimits,
+) -> Result<RateLimits, Error> {
+    let rates = node.spaces().event_rates();
+    rates.set_limits(limits).map_err(Error::from)?;
+    Ok(rates.limits())
+}
+
+#[tauri::command]
+#[specta::specta]
+fn event_rate_unthrottle(node: tauri::State<'_, Arc<Node>>, space_id: Uuid, author: String) {
+    node.spaces().event_rates().unthrottle(space_id, &author);
+}
+
+#[tauri::command]
+#[specta::specta]
+async fn program_usage(
+    node: tauri::State<'_, Arc<Node>>,
+    space_id: Uuid,
+    program_id: Uuid,
+    period: UsagePeriod,
+) -> Result<ProgramUsage, Error> {
+    let spaces = node.spaces().clone();
+    tokio::task::block_in_place(|| {
+        tauri::async_runtime::block_on(async move {
+            let space = spaces
+                .get(&space_id)
+                .await
+                .ok_or(Error::SpaceNotFound { id: space_id })?;
+            space
+                .programs()
+                .usage(program_id, period)
+                .await
+                .map_err(Error::from)
+        })
+    })
+}
+
+#[tauri::command]
+#[specta::specta]
+async fn aliases_list(node: tauri::State<'_, Arc<Node>>) -> Result<Vec<Alias>, Error> {
+    let node = node.clone();
+    tokio::task::block_in_place(|| {
+        tauri::async_runtime::block_on(async move {
+            node.aliases()
+                .refresh(node.spaces())
+                .await
+                .map_err(Error::from)?;
+            Ok(node.aliases().list().await)
+        })
+    })
+}
+
+#[tauri::command]
+#[specta::specta]
+async fn alias_set(
+    node: tauri::State<'_, Arc<Node>>,
+    pubkey: &str,
+    name: &str,
+) -> Result<Alias, Error> {
+    let node = node.clone();
+    let pubkey = PublicKey::from_str(pubkey).map_err(Error::invalid)?;
+    tokio::task::block_in_place(|| {
+        tauri::async_runtime::block_on(async move {
+            node.aliases().set(pubkey, name).await.map_err(Error::from)
+        })
+    })
+}
+
+#[tauri::command]
+#[specta::specta]
+async fn alias_remove(node: tauri::State<'_, Arc<Node>>, pubkey: &str) -> Result<(), Error> {
+    let node = node.clone();
+    let pubkey = PublicKey::from_str(pubkey).map_err(Error::invalid)?;
+    tokio::task::block_in_place(|| {
+        tauri::async_runtime::block_on(async move {
+            node.aliases().remove(&pubkey).await.map_err(Error::from)
+        })
+    })
+}
+
+#[tauri::command]
+#[specta::specta]
+async fn aliases_resolve(
+    node: tauri::State<'_, Arc<Node>>,
+    pubkeys: Vec<String>,
+) -> Result<HashMap<String, String>, Error> {
+    let node = node.clone();
+    let pubkeys = pubkeys
+        .iter()
+        .map(|key| PublicKey::from_str(key))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(Error::invalid)?;
+    tokio::task::block_in_place(|| {
+        tauri::async_runtime::block_on(async move { Ok(node.aliases().resolve_all(pubkeys).await) })
+    })
+}
+
+#[tauri::command]
+#[specta::specta]
+async fn space_fork(
+    node: tauri::State<'_, Arc<Node>>,
+    space_id: Uuid,
+    name: &str,
+    options: ForkOptions,
+) -> Result<SpaceDetails, Error> {
+    let mut spaces = node.spaces().clone();
+    tokio::task::block_in_place(|| {
+        tauri::async_runtime::block_on(async move {
+            let author_id = node
+                .accounts()
+                .await
+                .map_err(Error::from)?
+                .pop()
+                .ok_or(Error::AuthorMissing)?;
+            let author = node
+                .router()
+                .authors()
+                .export(author_id)
+                .await
+                .map_err(Error::from)?
+                .ok_or(Error::AuthorMissing)?;
+
+            let space = spaces
+                .fork(author, &space_id, name, options)
+                .await
+                .map_err(Error::from)?;
+            Ok(space.details())
+        })
+    })
+}
+
+/// Write a space & everything it links to into an archive file at `path`.
+#[tauri::command]
+#[specta::specta]
+async fn space_export_archive(
+    node: tauri::State<'_, Arc<Node>>,
+    space_id: Uuid,
+    path: String,
+) -> Result<ArchiveSummary, Error> {
+    let spaces = node.spaces().clone();
+    tokio::task::block_in_place(|| {
+        tauri::async_runtime::block_on(async move {
+            let space = spaces
+                .get(&space_id)
+                .await
+                .ok_or(Error::SpaceNotFound { id: space_id })?;
+            space.export_archive(path).await.map_err(Error::from)
+        })
+    })
+}
+
+/// Open the space in an archive file at `path` on this node.
+#[tauri::command]
+#[specta::specta]
+async fn space_import_archive(
+    node: tauri::State<'_, Arc<Node>>,
+    path: String,
+) -> Result<SpaceDetails, Error> {
+    let node = node.clone();
+    tokio::task::block_in_place(|| {
+        tauri::async_runtime::block_on(async move {
+            let space = node
+                .spaces()
+                .import_archive(node.router(), path)
+                .await
+                .map_err(Error::from)?;
+            Ok(space.details())
+        })
+    })
+}
+
+/// Joins running in the background, by the id [`space_join`] handed out for them.
+#[derive(Default)]
+struct Joins(std::sync::Mutex<HashMap<Uuid, SpaceJoin>>);
+
+/// A progress update for a join, emitted as `space-join-progress` events.
+#[derive(Clone, serde::Serialize, specta::Type)]
+#[serde(rename_all = "camelCase")]
+struct SpaceJoinUpdate {
+    join_id: Uuid,
+    progress: JoinProgress,
+}
+
+/// Start joining the space a write ticket was shared for. Returns right away with an id to
+/// cancel the join with, progress arrives as `space-join-progress` events until the phase is
+/// done, failed or canceled.
+#[tauri::command]
+#[specta::specta]
+async fn space_join(
+    app: tauri::AppHandle,
+    node: tauri::State<'_, Arc<Node>>,
+    joins: tauri::State<'_, Arc<Joins>>,
+    ticket: &str,
+    name: &str,
+) -> Result<Uuid, Error> {
+    let ticket = DocTicket::from_str(ticket).map_err(Error::invalid)?;
+    let node = node.inner().clone();
+    let joins = joins.inner().clone();
+    tokio::task::block_in_place(|| {
+        tauri::async_runtime::block_on(async move {
+            let join = node
+                .spaces()
+                .join(node.router(), ticket, name)
+                .await
+                .map_err(Error::from)?;
+            let join_id = Uuid::new_v4();
+            joins
+                .0
+                .lock()
+                .expect("poisoned")
+                .insert(join_id, join.clone());
+
+            let mut updates = join.subscribe();
+            tauri::async_runtime::spawn(async move {
+                loop {
+                    let progress = updates.borrow_and_update().clone();
+                    let finished = progress.phase.is_finished();
+                    let update = SpaceJoinUpdate { join_id, progress };
+                    if let Err(err) = app.emit("space-join-progress", update) {
+                        warn!("failed to emit space join progress: {err}");
+                    }
+                    if finished || updates.changed().await.is_err() {
+                        break;
+                    }
+                }
+                joins.0.lock().expect("poisoned").remove(&join_id);
+            });
+            Ok(join_id)
+        })
+    })
+}
+
+#[tauri::command]
+#[specta::specta]
+async fn space_join_cancel(
+    joins: tauri::State<'_, Arc<Joins>>,
+    join_id: Uuid,
+) -> Result<(), Error> {
+    let joins = joins.0.lock().expect("poisoned");
+    let join = joins
+        .get(&join_id)
+        .ok_or_else(|| Error::invalid("join not found"))?;
+    join.cancel();
+    Ok(())
+}
+
+#[tauri::command]
+#[specta::specta]
+async fn program_run_task(
+    node: tauri::State<'_, Arc<Node>>,
+    space_id: Uuid,
+    program_id: Uuid,
+    task_name: &str,
+    environment: HashMap<String, String>,
+) -> Result<TaskOutput, Error> {
+    let spaces = node.spaces().clone();
+    let node = node.clone();
+    tokio::task::block_in_place(|| {
+        tauri::async_runtime::block_on(async move {
+            let space = spaces
+                .get(&space_id)
+                .await
+                .ok_or(Error::SpaceNotFound { id: space_id })?;
+            let author_id = node
+                .accounts()
+                .await
+                .map_err(Error::from)?
+                .pop()
+                .ok_or(Error::AuthorMissing)?;
+            let author = node
+                .router()
+                .authors()
+                .export(author_id)
+                .await
+                .map_err(Error::from)?
+                .ok_or(Error::AuthorMissing)?;
+            node.vm()
+                .run_task(&space, author, program_id, task_name, environment)
+                .await
+                .map_err(Error::from)
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use specta_typescript::{BigIntExportBehavior, Typescript};
+
+    use super::*;
+
+    /// Writes src/bindings.ts, run by `npm run bindings` before every build.
+    #[test]
+    fn export_bindings() {
+        commands()
+            .export(
+                Typescript::default().bigint(BigIntExportBehavior::Number),
+                BINDINGS_PATH,
+            )
+            .unwrap();
+    }
+}
@@ -1,7 +1,7 @@
 import { useEffect, useState } from "react";
 import { invoke, InvokeArgs } from "@tauri-apps/api/core";
 
-import { User, Program, Table, Row, SpaceDetails, Event, Uuid } from "@/types";
+import { User, Program, Table, Row, SpaceDetails, SearchMatch, SearchResult, Uuid, Capability, Run, Render, RowQuery, Cadence, Schedule, Access, Grant, SpaceHandle, NodeError, Page, ArchiveSummary } from "@/types";
 import { string } from "zod";
 
 export interface SpaceParam {
@@ -16,6 +16,7 @@ export interface Pagination {
 export interface ApiEnvelope<O> {
   isLoading: boolean;
   data?: O;
+  error?: NodeError;
 }
 
 function ApiQueryFactory<I, O>(method_name: string): ((i: I) => ApiEnvelope<O>) {
@@ -28,6 +29,8 @@ function ApiQueryFactory<I, O>(method_name: string): ((i: I) => ApiEnvelope<O>)
       console.log(method_name, input);
       invoke<O>(method_name, input as InvokeArgs).then((res) => {
         setEnvelope({ isLoading: false, data: res });
+      }).catch((error: NodeError) => {
+        setEnvelope({ isLoading: false, error });
       });
     }, []);
 
@@ -43,8 +46,8 @@ function ApiMutationFactory<I, O>(method_name: string): (() => (i: I) => Promise
   }
 }
 
-export const useEventSearch = (spaceId: Uuid, query: string, offset: number, limit: number): ApiEnvelope<Event[]> => {
-  const [envelope, setEnvelope] = useState<ApiEnvelope<Event[]>>({
+export const useEventSearch = (spaceId: Uuid, query: string, offset: number, limit: number): ApiEnvelope<SearchMatch[]> => {
+  const [envelope, setEnvelope] = useState<ApiEnvelope<SearchMatch[]>>({
     isLoading: true,
   });
 
@@ -55,21 +58,53 @@ export const useEventSearch = (spaceId: Uuid, query: string, offset: number, lim
     }
 
     invoke("events_search", { spaceId, query, limit, offset }).then((res) => {
-      setEnvelope({ isLoading: false, data: res as Event[] });
+      setEnvelope({ isLoading: false, data: res as SearchMatch[] });
     });
   }, [spaceId, query, limit, offset]);
 
   return envelope;
 }
 
+export const useEventSearchAll = (query: string, limit: number): ApiEnvelope<SearchResult[]> => {
+  const [envelope, setEnvelope] = useState<ApiEnvelope<SearchResult[]>>({
+    isLoading: true,
+  });
+
+  useEffect(() => {
+    if (!query) {
+      setEnvelope({ isLoading: false, data: [] });
+      return
+    }
+
+    invoke("events_search_all", { query, limit }).then((res) => {
+      setEnvelope({ isLoading: false, data: res as SearchResult[] });
+    });
+  }, [query, limit]);
+
+  return envelope;
+}
+
 export const useQuerySpace = ApiQueryFactory<SpaceParam, SpaceDetails>("current_space");
+export const useMutationExportSpace = ApiMutationFactory<SpaceParam & { path: string }, ArchiveSummary>("space_export_archive");
+export const useMutationImportSpace = ApiMutationFactory<{ path: string }, SpaceDetails>("space_import_archive");
 export const useQueryListSpaces = ApiQueryFactory<Pagination, [SpaceDetails]>("spaces_list");
-export const useQueryUsers = ApiQueryFactory<SpaceParam & Pagination, [User]>("users_list");
-export const useQueryPrograms = ApiQueryFactory<SpaceParam & Pagination, [Program]>("programs_list");
+export const useQueryUsers = ApiQueryFactory<SpaceParam & Pagination, Page<User>>("users_list");
+export const useQueryPrograms = ApiQueryFactory<SpaceParam & Pagination, Page<Program>>("programs_list");
 export const useQueryProgram = ApiQueryFactory<SpaceParam & { programId: Uuid }, Program>("program_get");
+export const useMutationImportProgram = ApiMutationFactory<SpaceParam & { ticket: string }, Program>("program_import");
+export const useQueryProgramCapabilities = ApiQueryFactory<SpaceParam & { programId: Uuid }, [Capability]>("program_capabilities");
 export const useQuerySecrets = ApiQueryFactory<SpaceParam & { programId: Uuid }, Record<string,string>>("secrets_get");
 export const useMutationSetSecrets = ApiMutationFactory<SpaceParam & { programId: Uuid, secrets: Record<string, string> }, {}>("secrets_set");
-export const useMutationRunProgram = ApiMutationFactory<SpaceParam & { author: string, programId: string, environment: Record<string,string> }, {}>("program_run");
-export const useQueryTables = ApiQueryFactory<SpaceParam & Pagination, [Table]>("tables_list");
+export const useMutationRunProgram = ApiMutationFactory<SpaceParam & { author: string, programId: string, environment: Record<string,string>, idempotencyKey?: string, profile?: boolean, spaces?: SpaceHandle[] }, {}>("program_run");
+export const useQueryRuns = ApiQueryFactory<SpaceParam & { programId: Uuid } & Pagination, [Run]>("runs_list");
+export const useQueryRunRender = ApiQueryFactory<SpaceParam & { runId: Uuid }, Render | null>("run_render");
+export const useQuerySchedules = ApiQueryFactory<SpaceParam, [Schedule]>("schedules_list");
+export const useMutationCreateSchedule = ApiMutationFactory<SpaceParam & { programId: Uuid, cadence: Cadence }, Schedule>("schedules_create");
+export const useMutationDeleteSchedule = ApiMutationFactory<SpaceParam & { scheduleId: Uuid }, {}>("schedules_delete");
+export const useQueryGrants = ApiQueryFactory<SpaceParam, [Grant]>("grants_list");
+export const useMutationCreateGrant = ApiMutationFactory<SpaceParam & { fromSpaceId: Uuid, programId: Uuid, access: Access }, Grant>("grants_create");
+export const useMutationDeleteGrant = ApiMutationFactory<SpaceParam & { grantId: Uuid }, {}>("grants_delete");
+export const useQueryTables = ApiQueryFactory<SpaceParam & Pagination, Page<Table>>("tables_list");
 export const useQueryTable = ApiQueryFactory<SpaceParam & { table: string }, Table>("table_get");
-export const useQueryRows = ApiQueryFactory<SpaceParam & { table: string } & Pagination, [Row]>("rows_query");
+export const useQueryRows = ApiQueryFactory<SpaceParam & { table: string, query?: RowQuery, hydrateBudget?: number } & Pagination, Page<Row>>("rows_query");
+export const useMutationAddBlob = ApiMutationFactory<{ path?: string, bytes?: number[] }, string>("blob_add");
@@ -8,6 +8,7 @@ import { Avatar, AvatarFallback } from "@/components/ui/avatar"
 import { Separator } from "@/components/ui/separator"
 import { useQueryProgram, useMutationRunProgram, useQuerySecrets} from "@/api";
 import SecretsDialog from "@/components/secrets-dialog";
+import ProgramSandbox from "@/components/program-sandbox";
 import { Loading } from "@/components/ui/loading";
 import { Uuid } from "@/types";
 import { Program } from "@/types";
@@ -31,6 +32,11 @@ export function Component() {
           {!isLoadingSecrets && <SecretsDialog spaceId={spaceId} programId={programId} secrets={secrets} />}
           <Button onClick={() => run({ spaceId, programId, author: "me", environment: {} })}>Run</Button>
         </div>
+        {data?.html_index && (
+          <div className="max-w-3xl mx-auto mt-8">
+            <ProgramSandbox spaceId={spaceId} program={data} />
+          </div>
+        )}
       </div>
     </div>
   )
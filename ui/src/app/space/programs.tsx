@@ -16,7 +16,7 @@ export function Component() {
   return (
     <div className="p-4">
       <h1>Programs</h1>
-      {data?.map((program, i) => (
+      {data?.items.map((program, i) => (
         <Link key={i} to={`/spaces/${spaceId}/programs/${program.id}`} className="p-2 border-b block">
           <h3>{program.manifest.name}</h3>
         </Link>
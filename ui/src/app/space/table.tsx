@@ -29,7 +29,7 @@ export function Component() {
     <div className="p-4">
       <h1>Data</h1>
       {schema && 
-        <DataTable data={data?.map(mapper) || []} columns={arrayRowColumns(schema)} />
+        <DataTable data={data?.items.map(mapper) || []} columns={arrayRowColumns(schema)} />
       }
     </div>
   )
@@ -19,7 +19,7 @@ export function Component() {
         <h1 className="text-xl font-bold">Local Data</h1>
         <p className="text-sm">data stored on your local device</p>
       </div>
-      {data?.map((schema, i) => {
+      {data?.items.map((schema, i) => {
         return (
           <div key={i} className="p-2 border-b">
             <Link to={`/spaces/${spaceId}/tables/${schema.content.hash}`} className="cursor-pointer">{schema.title}</Link>
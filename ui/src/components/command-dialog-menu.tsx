@@ -13,7 +13,7 @@ import {
   CommandItem,
   CommandList,
 } from "@/components/ui/command"
-import { useEventSearch } from "@/api"
+import { useEventSearchAll } from "@/api"
 import { schemaId, Uuid } from "@/types"
 import { Loading } from "./ui/loading"
 
@@ -22,7 +22,7 @@ export function CommandDialogMenu() {
   const navigate = useNavigate()
   const [open, setOpen] = React.useState(false)
   const [query, setQuery] = React.useState("")
-  const { isLoading, data } = useEventSearch(spaceId, query, 0, 1000);
+  const { isLoading, data } = useEventSearchAll(query, 1000);
 
   React.useEffect(() => {
     const down = (e: KeyboardEvent) => {
@@ -50,11 +50,12 @@ export function CommandDialogMenu() {
           {data?.map((event) => {
             const schId = schemaId(event)
             return (
-            <CommandItem key={event.id} value={`/spaces/${spaceId}/tables/${schId}#${event.id}`} forceMount onSelect={(path) => {
+            <CommandItem key={event.id} value={`/spaces/${event.spaceId}/tables/${schId}#${event.id}`} forceMount onSelect={(path) => {
               console.log(path)
               navigate(path)
             }}>
               <span>{event.id}</span>
+              <span>{event.spaceName}</span>
               <span>{event.createdAt}</span>
             </CommandItem>
           )})}
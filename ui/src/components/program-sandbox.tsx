@@ -0,0 +1,79 @@
+import { useEffect, useRef, useState } from "react";
+import { invoke } from "@tauri-apps/api/core";
+
+import { Program, Uuid } from "@/types";
+
+const GATEWAY_ORIGIN = "http://127.0.0.1:8080";
+
+// must match the gateway's approved sandbox methods. All read-only.
+const APPROVED_METHODS = new Set([
+  "tables_list",
+  "table_get",
+  "rows_query",
+  "rows_aggregate",
+  "rows_query_range",
+  "rows_changes",
+]);
+
+interface SandboxRequest {
+  squiggle: "sandbox-request";
+  program: string;
+  id: unknown;
+  method: string;
+  params: Record<string, unknown>;
+}
+
+// Renders a program's UI inside the gateway sandbox, answering approved API requests for the
+// current space only. The node issues a token for the gateway's /api routes, scoped to the
+// space, which the sandbox hands to the program.
+export default function ProgramSandbox({ spaceId, program }: { spaceId: Uuid, program: Program }) {
+  const frame = useRef<HTMLIFrameElement>(null);
+  const hash = program.content.hash;
+  // undefined until the node answers, null when the program runs without a token
+  const [token, setToken] = useState<string | null>();
+  const parent = encodeURIComponent(window.location.origin);
+  const src = `${GATEWAY_ORIGIN}/sandbox/${hash}?parent=${parent}${token ? `&token=${token}` : ""}`;
+
+  useEffect(() => {
+    invoke<string | null>("sandbox_token", { spaceId, program: hash })
+      .then(setToken)
+      .catch(() => setToken(null));
+  }, [spaceId, hash]);
+
+  useEffect(() => {
+    const onMessage = async (event: MessageEvent) => {
+      if (event.origin !== GATEWAY_ORIGIN || event.source !== frame.current?.contentWindow) return;
+      const msg = event.data as SandboxRequest;
+      if (msg?.squiggle !== "sandbox-request" || msg.program !== hash) return;
+
+      const respond = (body: { result?: unknown, error?: string }) =>
+        frame.current?.contentWindow?.postMessage({ squiggle: "sandbox-response", id: msg.id, ...body }, GATEWAY_ORIGIN);
+
+      if (!APPROVED_METHODS.has(msg.method)) {
+        respond({ error: "method not allowed" });
+        return;
+      }
+      try {
+        // the space is always ours to pick, never the program's
+        const result = await invoke(msg.method, { ...msg.params, spaceId });
+        respond({ result });
+      } catch (error) {
+        respond({ error: String(error) });
+      }
+    };
+    window.addEventListener("message", onMessage);
+    return () => window.removeEventListener("message", onMessage);
+  }, [spaceId, hash]);
+
+  if (token === undefined) return null;
+
+  return (
+    <iframe
+      ref={frame}
+      src={src}
+      title={program.manifest.name}
+      referrerPolicy="no-referrer"
+      className="w-full h-[600px] border rounded"
+    />
+  );
+}
@@ -88,7 +88,7 @@ export function SpaceSidebar() {
                 </SidebarMenuButton>
               </SidebarMenuItem>
               {isLoadingPrograms && <LoadingSpinner />}
-              {programs?.map((program) => (
+              {programs?.items.map((program) => (
                 <SidebarMenuItem key={program.id}>
                   <SidebarMenuButton asChild>
                     <Link to={`/spaces/${spaceId}/programs/${program.id}`}>
@@ -107,7 +107,7 @@ export function SpaceSidebar() {
           <SidebarGroupContent>
             <SidebarMenu>
               {isLoadingTables && <LoadingSpinner />}
-              {tables?.map((table) => (
+              {tables?.items.map((table) => (
                 <SidebarMenuItem key={table.content.hash}>
                   <SidebarMenuButton asChild>
                     <Link to={`/spaces/${spaceId}/tables/${table.content.hash}`}>
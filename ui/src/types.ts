@@ -1,3 +1,7 @@
+// types generated from the node, see src/bindings.ts. What's declared here is either UI-only or
+// shaped differently from its rust namesake
+export * from "./bindings";
+import type { Access, HashLink, JoinProgress, ProgramPermissions, SnippetPart } from "./bindings";
 
 export type Uuid = string;
 
@@ -6,8 +10,6 @@ export interface SpaceDetails {
   name: string;
 }
 
-export interface User {
-
 }
 
 export interface ProgramManifest {
@@ -18,6 +20,14 @@ export interface ProgramManifest {
   repository?: string,
   license?: string,
   main?: string,
+  permissions?: ProgramPermissions,
+}
+
+export interface Capability {
+  kind: "network" | "writeTable" | "secret",
+  target: string,
+  required: boolean,
+  description: string,
 }
 
 export interface Program {
@@ -30,9 +40,15 @@ export interface Program {
   program_entry?: string,
 }
 
-export interface HashLink {
-  hash: string;
-  value?: any;
+export interface Grant {
+  id: Uuid;
+  createdAt: number;
+  author: string;
+  content: HashLink;
+  // the space the program belongs to
+  space: Uuid;
+  programId: Uuid;
+  access: Access;
 }
 
 export interface Table {
@@ -43,6 +59,18 @@ export interface Table {
 
 export interface Row {
   content: HashLink;
+  authorName?: string;
+}
+
+export type CapabilityAction = "createRow" | "updateRow" | "deleteRow" | "manageTable";
+
+export type CapabilityObject =
+  | { type: "table"; table: string }
+  | { type: "row"; table: string; id: Uuid };
+
+export interface SpaceJoinUpdate {
+  joinId: string;
+  progress: JoinProgress;
 }
 
 export type Tag = [string, string, string?];
@@ -67,6 +95,50 @@ export interface Event {
   kind: EventKind,
   tags: [Tag],
   content: HashLink,
+  authorName?: string,
+}
+
+export interface SearchMatch extends Event {
+  score: number,
+  snippet: SnippetPart[],
+}
+
+export interface SearchResult extends SearchMatch {
+  spaceId: Uuid,
+  spaceName: string,
+}
+
+// one page of a list, `limit` is -1 when the page holds everything from `offset` on
+export interface Page<T> {
+  items: T[],
+  total: number,
+  offset: number,
+  limit: number,
+}
+
+// errors commands reject with, tagged by kind
+export type NodeError =
+  | { kind: "spaceNotFound", id: Uuid }
+  | { kind: "programNotFound", program: string }
+  | { kind: "authorMissing" }
+  | { kind: "invalid", message: string }
+  | { kind: "io", message: string }
+  | { kind: "sync", message: string }
+  | { kind: "internal", message: string };
+
+export function errorMessage(err: NodeError): string {
+  switch (err.kind) {
+    case "spaceNotFound":
+      return `space ${err.id} not found`;
+    case "programNotFound":
+      return `program ${err.program} not found`;
+    case "authorMissing":
+      return "no author";
+    case "sync":
+      return `sync failed: ${err.message}`;
+    default:
+      return err.message;
+  }
 }
 
 export function schemaId(event: Event): string | undefined {
@@ -0,0 +1,194 @@
+//! Read-only space client for the browser.
+//!
+//! Fetches every event of a space from a gateway running in replica mode & answers table &
+//! row queries locally, so web apps can render space data without a node. The node crate
+//! can't be built for wasm32, so this crate speaks the gateway's JSON instead. Build with
+//! `wasm-pack build --target web`.
+//!
+//! ```js
+//! import init, { SpaceClient } from "squiggle_web";
+//! await init();
+//! const space = await SpaceClient.fetch("https://gateway.example", spaceId);
+//! for (const table of space.tables()) {
+//!   console.log(table.title, space.rows(table.hash, 0, 50));
+//! }
+//! ```
+use js_sys::{Promise, Uint8Array};
+use serde::Serialize;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{Request, RequestInit, Response, Window, WorkerGlobalScope};
+
+pub mod snapshot;
+
+use snapshot::{Event, Snapshot};
+
+/// events requested per page while fetching a snapshot
+const PAGE_SIZE: usize = 1000;
+
+/// How current the replica was when the snapshot was fetched, from its `x-replica-*` headers.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Staleness {
+    pub stale: bool,
+    pub origin_online: bool,
+    /// unix timestamp of the replica's last sync with the origin
+    pub synced_at: Option<i64>,
+    /// seconds since the last sync, as of the fetch
+    pub age: Option<i64>,
+}
+
+#[wasm_bindgen]
+pub struct SpaceClient {
+    gateway: String,
+    space: String,
+    snapshot: Snapshot,
+    staleness: Staleness,
+}
+
+#[wasm_bindgen]
+impl SpaceClient {
+    /// Fetch every event of `space` from the gateway at `gateway`.
+    pub async fn fetch(gateway: String, space: String) -> Result<SpaceClient, JsError> {
+        let gateway = gateway.trim_end_matches('/').to_string();
+        let mut snapshot = Snapshot::default();
+        let mut staleness = Staleness::default();
+        // pages by cursor, events that sync in while fetching don't shift later pages
+        let mut cursor: Option<String> = None;
+        loop {
+            let mut url = format!("{gateway}/replica/spaces/{space}/events?limit={PAGE_SIZE}");
+            if let Some(cursor) = &cursor {
+                url.push_str(&format!("&before={cursor}"));
+            }
+            let res = get(&url).await?;
+            if cursor.is_none() {
+                staleness = read_staleness(&res);
+            }
+            cursor = res.headers().get("x-replica-cursor").ok().flatten();
+            let text = JsFuture::from(res.text().map_err(js_error)?)
+                .await
+                .map_err(js_error)?
+                .as_string()
+                .unwrap_or_default();
+            let events: Vec<Event> = serde_json::from_str(&text)?;
+            let done = events.len() < PAGE_SIZE || cursor.is_none();
+            snapshot.extend(events);
+            if done {
+                break;
+            }
+        }
+        Ok(SpaceClient {
+            gateway,
+            space,
+            snapshot,
+            staleness,
+        })
+    }
+
+    /// Load a snapshot saved by the app, eg: a JSON array of events bundled with the page.
+    /// Blobs are still fetched from `gateway`.
+    #[wasm_bindgen(js_name = fromSnapshot)]
+    pub fn from_snapshot(
+        gateway: String,
+        space: String,
+        events_json: &str,
+    ) -> Result<SpaceClient, JsError> {
+        Ok(SpaceClient {
+            gateway: gateway.trim_end_matches('/').to_string(),
+            space,
+            snapshot: Snapshot::from_json(events_json)?,
+            staleness: Staleness::default(),
+        })
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn space(&self) -> String {
+        self.space.clone()
+    }
+
+    #[wasm_bindgen(getter, js_name = eventCount)]
+    pub fn event_count(&self) -> usize {
+        self.snapshot.len()
+    }
+
+    pub fn staleness(&self) -> Result<JsValue, JsError> {
+        to_js(&self.staleness)
+    }
+
+    /// The latest version of every table, sorted by title.
+    pub fn tables(&self) -> Result<JsValue, JsError> {
+        to_js(&self.snapshot.tables())
+    }
+
+    /// A page of the latest rows in the table with schema hash `table`, newest first. Rows
+    /// with `data: null` weren't inlined, fetch them with `fetchBlob(row.contentHash)`.
+    pub fn rows(&self, table: &str, offset: usize, limit: usize) -> Result<JsValue, JsError> {
+        to_js(&self.snapshot.rows(table, offset, limit))
+    }
+
+    /// URL of a blob on the gateway, for `<img src>` & friends.
+    #[wasm_bindgen(js_name = blobUrl)]
+    pub fn blob_url(&self, hash: &str) -> String {
+        format!(
+            "{}/replica/spaces/{}/blobs/{hash}",
+            self.gateway, self.space
+        )
+    }
+
+    #[wasm_bindgen(js_name = fetchBlob)]
+    pub async fn fetch_blob(&self, hash: String) -> Result<Uint8Array, JsError> {
+        let res = get(&self.blob_url(&hash)).await?;
+        let buf = JsFuture::from(res.array_buffer().map_err(js_error)?)
+            .await
+            .map_err(js_error)?;
+        Ok(Uint8Array::new(&buf))
+    }
+}
+
+async fn get(url: &str) -> Result<Response, JsError> {
+    let opts = RequestInit::new();
+    opts.set_method("GET");
+    let req = Request::new_with_str_and_init(url, &opts).map_err(js_error)?;
+    let res: Response = JsFuture::from(fetch(&req)?)
+        .await
+        .map_err(js_error)?
+        .dyn_into()
+        .map_err(js_error)?;
+    if !res.ok() {
+        return Err(JsError::new(&format!("GET {url}: {}", res.status())));
+    }
+    Ok(res)
+}
+
+/// `fetch` from either a page or a web worker.
+fn fetch(req: &Request) -> Result<Promise, JsError> {
+    let global = js_sys::global();
+    if let Some(window) = global.dyn_ref::<Window>() {
+        return Ok(window.fetch_with_request(req));
+    }
+    if let Some(worker) = global.dyn_ref::<WorkerGlobalScope>() {
+        return Ok(worker.fetch_with_request(req));
+    }
+    Err(JsError::new("fetch is not available in this context"))
+}
+
+fn read_staleness(res: &Response) -> Staleness {
+    let headers = res.headers();
+    let header = |name: &str| headers.get(name).ok().flatten();
+    Staleness {
+        stale: header("x-replica-stale").as_deref() == Some("true"),
+        origin_online: header("x-replica-origin").as_deref() == Some("online"),
+        synced_at: header("x-replica-synced-at").and_then(|v| v.parse().ok()),
+        age: header("age").and_then(|v| v.parse().ok()),
+    }
+}
+
+fn to_js<T: Serialize>(value: &T) -> Result<JsValue, JsError> {
+    let serializer = serde_wasm_bindgen::Serializer::json_compatible();
+    Ok(value.serialize(&serializer)?)
+}
+
+fn js_error(value: JsValue) -> JsError {
+    JsError::new(&value.as_string().unwrap_or_else(|| format!("{value:?}")))
+}
@@ -0,0 +1,264 @@
+//! Space events & the tables and rows they add up to.
+//!
+//! Mirrors the event format written by `squiggle_node::space::events`, without depending on
+//! the node crate, which can't be built for wasm32.
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+pub const KIND_MUTATE_TABLE: u32 = 100008;
+pub const KIND_DELETE_TABLE: u32 = 100009;
+pub const KIND_MUTATE_ROW: u32 = 100010;
+pub const KIND_DELETE_ROW: u32 = 100011;
+
+const SCHEMA_TAG: &str = "sch";
+const ID_TAG: &str = "id";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Event {
+    pub id: String,
+    pub pubkey: String,
+    #[serde(rename = "createdAt")]
+    pub created_at: i64,
+    pub kind: u32,
+    pub tags: Vec<(String, String, Option<String>)>,
+    #[serde(default)]
+    pub sig: Option<Value>,
+    pub content: Content,
+}
+
+impl Event {
+    fn tag(&self, name: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|tag| tag.0 == name)
+            .map(|tag| tag.1.as_str())
+    }
+
+    pub fn data_id(&self) -> Option<&str> {
+        self.tag(ID_TAG)
+    }
+
+    pub fn schema(&self) -> Option<&str> {
+        self.tag(SCHEMA_TAG)
+    }
+}
+
+/// Event content is either a bare hash, or a hash with the value inlined.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Content {
+    Hash(String),
+    Inline { hash: String, value: Option<Value> },
+}
+
+impl Content {
+    pub fn hash(&self) -> &str {
+        match self {
+            Content::Hash(hash) => hash,
+            Content::Inline { hash, .. } => hash,
+        }
+    }
+
+    pub fn value(&self) -> Option<&Value> {
+        match self {
+            Content::Hash(_) => None,
+            Content::Inline { value, .. } => value.as_ref(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Table {
+    pub id: String,
+    /// None when the schema isn't inlined & has to be fetched by hash
+    pub title: Option<String>,
+    pub hash: String,
+    pub author: String,
+    pub created_at: i64,
+    pub schema: Option<Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Row {
+    pub id: String,
+    /// schema hash of the row's table
+    pub table: String,
+    pub author: String,
+    pub created_at: i64,
+    pub content_hash: String,
+    /// None when the row data isn't inlined & has to be fetched by hash
+    pub data: Option<Value>,
+}
+
+/// Every event of a space, as served by a gateway replica.
+#[derive(Debug, Clone, Default)]
+pub struct Snapshot {
+    events: Vec<Event>,
+}
+
+impl Snapshot {
+    pub fn new(events: Vec<Event>) -> Self {
+        Snapshot { events }
+    }
+
+    pub fn from_json(json: &str) -> serde_json::Result<Self> {
+        Ok(Snapshot::new(serde_json::from_str(json)?))
+    }
+
+    pub fn extend(&mut self, events: Vec<Event>) {
+        self.events.extend(events);
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// The latest version of every table that hasn't been deleted, sorted by title.
+    pub fn tables(&self) -> Vec<Table> {
+        let mut tables: Vec<Table> = latest(&self.events, KIND_MUTATE_TABLE, KIND_DELETE_TABLE)
+            .into_iter()
+            .map(|(id, event)| {
+                let schema = event.content.value().cloned();
+                Table {
+                    id: id.to_string(),
+                    title: schema
+                        .as_ref()
+                        .and_then(|schema| schema.get("title"))
+                        .and_then(Value::as_str)
+                        .map(str::to_string),
+                    hash: event.content.hash().to_string(),
+                    author: event.pubkey.clone(),
+                    created_at: event.created_at,
+                    schema,
+                }
+            })
+            .collect();
+        tables.sort_by(|a, b| a.title.cmp(&b.title));
+        tables
+    }
+
+    /// The latest version of every row in the table with schema hash `table`, newest first.
+    pub fn rows(&self, table: &str, offset: usize, limit: usize) -> Vec<Row> {
+        let events: Vec<Event> = self
+            .events
+            .iter()
+            .filter(|event| event.schema() == Some(table))
+            .cloned()
+            .collect();
+        let mut rows: Vec<Row> = latest(&events, KIND_MUTATE_ROW, KIND_DELETE_ROW)
+            .into_iter()
+            .map(|(id, event)| Row {
+                id: id.to_string(),
+                table: table.to_string(),
+                author: event.pubkey.clone(),
+                created_at: event.created_at,
+                content_hash: event.content.hash().to_string(),
+                data: event.content.value().cloned(),
+            })
+            .collect();
+        rows.sort_by(|a, b| b.created_at.cmp(&a.created_at).then(a.id.cmp(&b.id)));
+        rows.into_iter().skip(offset).take(limit).collect()
+    }
+}
+
+/// The newest mutation per data id, dropping ids whose newest event is a deletion.
+fn latest(events: &[Event], mutate: u32, delete: u32) -> Vec<(&str, &Event)> {
+    let mut newest: HashMap<&str, &Event> = HashMap::new();
+    for event in events {
+        if event.kind != mutate && event.kind != delete {
+            continue;
+        }
+        let Some(id) = event.data_id() else {
+            continue;
+        };
+        match newest.get(id) {
+            Some(prev) if prev.created_at > event.created_at => {}
+            _ => {
+                newest.insert(id, event);
+            }
+        }
+    }
+    newest
+        .into_iter()
+        .filter(|(_, event)| event.kind == mutate)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn event(kind: u32, id: &str, created_at: i64, schema: Option<&str>, content: Value) -> Value {
+        let mut tags = vec![json!([ID_TAG, id, null])];
+        if let Some(schema) = schema {
+            tags.push(json!([SCHEMA_TAG, schema, null]));
+        }
+        json!({
+            "id": format!("{id}-{created_at}"),
+            "pubkey": "author",
+            "createdAt": created_at,
+            "kind": kind,
+            "tags": tags,
+            "sig": null,
+            "content": content,
+        })
+    }
+
+    #[test]
+    fn rows_from_events() {
+        let events = json!([
+            event(
+                KIND_MUTATE_TABLE,
+                "t1",
+                1,
+                None,
+                json!({ "hash": "schema", "value": { "title": "things" } })
+            ),
+            event(
+                KIND_MUTATE_ROW,
+                "r1",
+                2,
+                Some("schema"),
+                json!({ "hash": "a", "value": 1 })
+            ),
+            event(
+                KIND_MUTATE_ROW,
+                "r1",
+                3,
+                Some("schema"),
+                json!({ "hash": "b", "value": 2 })
+            ),
+            event(KIND_MUTATE_ROW, "r2", 4, Some("schema"), json!("c")),
+            event(KIND_MUTATE_ROW, "r3", 5, Some("schema"), json!("d")),
+            event(KIND_DELETE_ROW, "r3", 6, Some("schema"), json!("e")),
+            event(KIND_MUTATE_ROW, "r4", 7, Some("other"), json!("f")),
+        ]);
+        let snapshot = Snapshot::from_json(&events.to_string()).unwrap();
+        assert_eq!(snapshot.len(), 7);
+
+        let tables = snapshot.tables();
+        assert_eq!(tables.len(), 1);
+        assert_eq!(tables[0].title.as_deref(), Some("things"));
+        assert_eq!(tables[0].hash, "schema");
+
+        let rows = snapshot.rows("schema", 0, usize::MAX);
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].id, "r2");
+        assert_eq!(rows[0].data, None);
+        assert_eq!(rows[0].content_hash, "c");
+        assert_eq!(rows[1].id, "r1");
+        assert_eq!(rows[1].data, Some(json!(2)));
+
+        assert_eq!(snapshot.rows("schema", 1, 1)[0].id, "r1");
+    }
+}